@@ -0,0 +1,110 @@
+//! Benchmarks for keep-alive overhead on a pipelined connection.
+//!
+//! Each iteration pushes `MESSAGES` messages through an echo server and waits for every
+//! echo, once without keep-alive and once with pings queued every `PING_INTERVAL` through
+//! the pipeline's writer task. A counting global allocator tracks heap allocations, and the
+//! allocations per message of both runs are printed after the timings so any allocation
+//! pressure added by keep-alive shows up next to the throughput numbers.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::Mutex;
+use websocket_toolkit::controller::WebSocketController;
+use websocket_toolkit::keep_alive::KeepAlive;
+use websocket_toolkit::pipeline::{PipelineConfig, PipelineReceiver, PipelineSender};
+use websocket_toolkit::testing::EchoServer;
+
+/// The number of messages per iteration.
+const MESSAGES: usize = 1000;
+/// How often the keep-alive run pings; far more often than any real deployment.
+const PING_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Counts every allocation made by the process.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Sends `MESSAGES` messages and waits for all of their echoes.
+async fn round_trip(sender: &PipelineSender, receiver: &mut PipelineReceiver, payload: &[u8]) {
+    for _ in 0..MESSAGES {
+        sender.send_binary(payload.to_vec()).await.unwrap();
+    }
+    for _ in 0..MESSAGES {
+        receiver.recv().await.unwrap();
+    }
+}
+
+/// Benchmarks pipelined round trips with and without keep-alive pings.
+fn bench_keep_alive(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("Failed to create Tokio runtime");
+    let server = runtime.block_on(EchoServer::start()).expect("Failed to start echo server");
+    let payload = vec![0xABu8; 256];
+
+    let mut group = c.benchmark_group("keep_alive");
+    group.throughput(Throughput::Elements(MESSAGES as u64));
+    group.sample_size(10);
+
+    let mut allocations = Vec::new();
+    for (name, keep_alive) in [("without_pings", false), ("with_pings", true)] {
+        let (sender, receiver, tasks) = runtime
+            .block_on(async {
+                WebSocketController::new(server.url(), 1, None)
+                    .connect_pipeline(PipelineConfig::default())
+                    .await
+            })
+            .expect("Failed to connect to echo server");
+        let pinger = keep_alive.then(|| {
+            let keep_alive = KeepAlive::new(PING_INTERVAL);
+            let sender = sender.clone();
+            runtime.spawn(async move { keep_alive.run(&sender).await })
+        });
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        group.bench_function(BenchmarkId::new("round_trip", name), |b| {
+            b.to_async(&runtime).iter(|| async {
+                round_trip(&sender, &mut *receiver.lock().await, &payload).await
+            })
+        });
+
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        runtime.block_on(async { round_trip(&sender, &mut *receiver.lock().await, &payload).await });
+        let per_message = (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / MESSAGES as f64;
+        allocations.push((name, per_message));
+
+        if let Some(pinger) = pinger {
+            pinger.abort();
+        }
+        tasks.abort();
+    }
+
+    group.finish();
+    for (name, per_message) in allocations {
+        println!("keep_alive/{}: {:.2} allocations per message", name, per_message);
+    }
+}
+
+criterion_group!(benches, bench_keep_alive);
+criterion_main!(benches);
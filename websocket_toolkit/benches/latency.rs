@@ -0,0 +1,140 @@
+//! Benchmarks for round-trip tail latency under the default and low-latency profiles.
+//!
+//! Each profile connects to an echo server through `WebSocketController::from_config` and a
+//! pipeline built from `Config::pipeline_config`. A background task sends bursts of
+//! `BURST` messages every `BURST_INTERVAL` on the same connection while the benchmark
+//! measures the round trip of single probe messages, modelling an order sent while market
+//! data streams in. A drain task reads every echo and reports when each probe's echo
+//! arrives. Criterion reports the mean; afterwards `SAMPLES` probes are timed
+//! individually and their p50, p99, p99.9 and maximum are printed, since batching and
+//! queueing show up in the tail long before they move the mean.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tokio::sync::{mpsc, Mutex};
+use tokio_tungstenite::tungstenite::Message;
+use websocket_toolkit::config::Config;
+use websocket_toolkit::controller::WebSocketController;
+use websocket_toolkit::pipeline::PipelineSender;
+use websocket_toolkit::testing::EchoServer;
+
+/// The number of individually timed probes per profile.
+const SAMPLES: usize = 10_000;
+/// The number of messages in each background burst.
+const BURST: usize = 32;
+/// How often the background task sends a burst.
+const BURST_INTERVAL: Duration = Duration::from_millis(1);
+/// How long to wait for a probe's echo before counting it as lost.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The first payload byte of probes; burst messages start with 0.
+const PROBE: u8 = 1;
+
+/// Sends one probe and waits for the drain task to report its echo.
+///
+/// Returns the round-trip time, or `None` if the probe's echo was dropped.
+async fn probe(sender: &PipelineSender, echoes: &mut mpsc::UnboundedReceiver<Instant>, payload: &[u8]) -> Option<Duration> {
+    let started = Instant::now();
+    sender.send_binary(payload.to_vec()).await.unwrap();
+    match tokio::time::timeout(PROBE_TIMEOUT, echoes.recv()).await {
+        Ok(Some(arrived)) => Some(arrived - started),
+        Ok(None) => panic!("Connection closed"),
+        Err(_) => None,
+    }
+}
+
+/// Returns the `quantile` of sorted `samples`.
+fn percentile(samples: &[Duration], quantile: f64) -> Duration {
+    samples[((samples.len() - 1) as f64 * quantile).round() as usize]
+}
+
+/// Benchmarks probe round trips under background bursts for both profiles.
+fn bench_latency(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("Failed to create Tokio runtime");
+    let server = runtime.block_on(EchoServer::start()).expect("Failed to start echo server");
+    let mut probe_payload = vec![0u8; 64];
+    probe_payload[0] = PROBE;
+
+    let mut group = c.benchmark_group("latency");
+    group.sample_size(50);
+
+    let mut summaries = Vec::new();
+    for (name, profile) in [("default", Config::default()), ("low_latency", Config::low_latency())] {
+        let config = Config {
+            urls: vec![server.url().to_string()],
+            ..profile
+        };
+        let controller = WebSocketController::from_config(&config).expect("Invalid config");
+        let (sender, mut receiver, tasks) = runtime
+            .block_on(controller.connect_pipeline(controller.pipeline_config()))
+            .expect("Failed to connect to echo server");
+        let bursts = {
+            let sender = sender.clone();
+            runtime.spawn(async move {
+                let mut ticker = tokio::time::interval(BURST_INTERVAL);
+                loop {
+                    ticker.tick().await;
+                    for _ in 0..BURST {
+                        if sender.send_binary(vec![0u8; 256]).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            })
+        };
+        let (probe_echoes, echoes) = mpsc::unbounded_channel();
+        let drain = runtime.spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                if matches!(&message, Message::Binary(data) if data.first() == Some(&PROBE)) {
+                    let _ = probe_echoes.send(Instant::now());
+                }
+            }
+        });
+        // Keep-alive pings feed the RTT statistics of profiles that track them.
+        runtime.block_on(async { controller.maintain_pipeline(sender.clone()) });
+        let echoes = Arc::new(Mutex::new(echoes));
+
+        group.bench_function(BenchmarkId::new("probe_under_bursts", name), |b| {
+            b.to_async(&runtime)
+                .iter(|| async { probe(&sender, &mut *echoes.lock().await, &probe_payload).await })
+        });
+
+        let (mut samples, lost) = runtime.block_on(async {
+            let mut echoes = echoes.lock().await;
+            let mut samples = Vec::with_capacity(SAMPLES);
+            let mut lost = 0;
+            for _ in 0..SAMPLES {
+                match probe(&sender, &mut echoes, &probe_payload).await {
+                    Some(sample) => samples.push(sample),
+                    None => lost += 1,
+                }
+            }
+            (samples, lost)
+        });
+        samples.sort();
+        summaries.push((name, samples, lost, sender.rtt()));
+
+        bursts.abort();
+        drain.abort();
+        tasks.abort();
+    }
+
+    group.finish();
+    for (name, samples, lost, rtt) in summaries {
+        println!(
+            "latency/{}: p50 {:?}, p99 {:?}, p99.9 {:?}, max {:?}, {} probes lost, ping RTT {:?}",
+            name,
+            percentile(&samples, 0.5),
+            percentile(&samples, 0.99),
+            percentile(&samples, 0.999),
+            samples.last().unwrap(),
+            lost,
+            rtt.map(|rtt| rtt.smoothed),
+        );
+    }
+}
+
+criterion_group!(benches, bench_latency);
+criterion_main!(benches);
@@ -0,0 +1,138 @@
+//! Benchmarks for concurrent senders sharing one connection.
+//!
+//! Compares four tasks sharing an `Arc<Mutex<WebSocketStream>>` (each holds the lock for a
+//! send and the echoed receive) against the same tasks sending through a `pipeline` with
+//! dedicated reader and writer tasks. Each iteration pushes `MESSAGES` messages through an
+//! echo server and waits for every echo. The server either echoes immediately (loopback) or
+//! after `SERVER_LATENCY`, modelling a server that takes time to answer; the latter is where
+//! a single in-flight operation caps throughput.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures_util::{SinkExt, StreamExt};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio::sync::{mpsc, Mutex};
+use tokio_tungstenite::accept_async;
+use websocket_toolkit::controller::WebSocketController;
+use websocket_toolkit::pipeline::PipelineConfig;
+use websocket_toolkit::testing::EchoServer;
+
+/// The number of concurrent sending tasks.
+const SENDERS: usize = 4;
+/// The total number of messages per iteration.
+const MESSAGES: usize = 400;
+/// How long the delayed echo server waits before answering each message.
+const SERVER_LATENCY: Duration = Duration::from_millis(1);
+
+/// Starts a server that echoes every message after `latency`, answering messages concurrently.
+///
+/// Returns the server's `ws://` URL.
+async fn start_delayed_echo_server(latency: Duration) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            stream.set_nodelay(true).unwrap();
+            tokio::spawn(async move {
+                let (mut sink, mut stream) = accept_async(stream).await.unwrap().split();
+                let (replies, mut pending) = mpsc::unbounded_channel();
+                tokio::spawn(async move {
+                    while let Some(reply) = pending.recv().await {
+                        if sink.send(reply).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+                while let Some(Ok(message)) = stream.next().await {
+                    if message.is_binary() || message.is_text() {
+                        let replies = replies.clone();
+                        tokio::spawn(async move {
+                            tokio::time::sleep(latency).await;
+                            let _ = replies.send(message);
+                        });
+                    }
+                }
+            });
+        }
+    });
+    url
+}
+
+/// Benchmarks shared-mutex access against the reader/writer pipeline.
+fn bench_concurrent_senders(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("Failed to create Tokio runtime");
+    let echo_server = runtime.block_on(EchoServer::start()).expect("Failed to start echo server");
+    let delayed_url = runtime.block_on(start_delayed_echo_server(SERVER_LATENCY));
+    let payload = vec![0xABu8; 256];
+
+    let mut group = c.benchmark_group("concurrent_senders");
+    group.throughput(Throughput::Elements(MESSAGES as u64));
+    group.sample_size(10);
+
+    for (server, url) in [("loopback", echo_server.url().to_string()), ("1ms_server", delayed_url)] {
+        let controller = Arc::new(Mutex::new(WebSocketController::new(&url, 1, None)));
+        let ws_stream = Arc::new(Mutex::new(
+            runtime
+                .block_on(async { controller.lock().await.connect().await })
+                .expect("Failed to connect to echo server"),
+        ));
+        group.bench_function(BenchmarkId::new("mutex", server), |b| {
+            b.to_async(&runtime).iter(|| async {
+                let mut tasks = Vec::with_capacity(SENDERS);
+                for _ in 0..SENDERS {
+                    let controller = controller.clone();
+                    let ws_stream = ws_stream.clone();
+                    let payload = payload.clone();
+                    tasks.push(tokio::spawn(async move {
+                        for _ in 0..MESSAGES / SENDERS {
+                            let mut ws_stream = ws_stream.lock().await;
+                            let mut controller = controller.lock().await;
+                            controller.send_message(&mut ws_stream, &payload).await.unwrap();
+                            controller.receive_message(&mut ws_stream).await.unwrap();
+                        }
+                    }));
+                }
+                for task in tasks {
+                    task.await.unwrap();
+                }
+            })
+        });
+
+        let (sender, receiver, _tasks) = runtime
+            .block_on(async {
+                WebSocketController::new(&url, 1, None)
+                    .connect_pipeline(PipelineConfig::default())
+                    .await
+            })
+            .expect("Failed to connect to echo server");
+        let receiver = Arc::new(Mutex::new(receiver));
+        group.bench_function(BenchmarkId::new("pipeline", server), |b| {
+            b.to_async(&runtime).iter(|| async {
+                let mut tasks = Vec::with_capacity(SENDERS);
+                for _ in 0..SENDERS {
+                    let sender = sender.clone();
+                    let payload = payload.clone();
+                    tasks.push(tokio::spawn(async move {
+                        for _ in 0..MESSAGES / SENDERS {
+                            sender.send_binary(payload.clone()).await.unwrap();
+                        }
+                    }));
+                }
+                let mut receiver = receiver.lock().await;
+                for _ in 0..MESSAGES {
+                    receiver.recv().await.unwrap();
+                }
+                for task in tasks {
+                    task.await.unwrap();
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_concurrent_senders);
+criterion_main!(benches);
@@ -0,0 +1,51 @@
+//! Benchmarks for reconnection overhead.
+//!
+//! Measures the bookkeeping cost of `ReconnectStrategy` when the first attempt
+//! succeeds, and the cost of a full WebSocket handshake against a local server.
+
+use async_trait::async_trait;
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+use tokio_tungstenite::tungstenite::Error;
+use websocket_toolkit::connection::WebSocketClient;
+use websocket_toolkit::reconnection::{Connectable, ReconnectStrategy};
+use websocket_toolkit::testing::EchoServer;
+
+/// A client that always connects successfully, isolating the strategy's own overhead.
+struct InstantClient;
+
+#[async_trait]
+impl Connectable for InstantClient {
+    async fn connect(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Benchmarks `ReconnectStrategy::reconnect` when the first attempt succeeds.
+fn bench_strategy_overhead(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("Failed to create Tokio runtime");
+    let strategy = ReconnectStrategy::new(3, 1);
+    let client: Arc<dyn Connectable> = Arc::new(InstantClient);
+
+    c.bench_function("reconnect_strategy_first_attempt", |b| {
+        b.to_async(&runtime).iter(|| strategy.reconnect(client.clone()))
+    });
+}
+
+/// Benchmarks a complete TCP connect + WebSocket handshake + close against a local server.
+fn bench_handshake(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("Failed to create Tokio runtime");
+    let server = runtime.block_on(EchoServer::start()).expect("Failed to start echo server");
+    let client = WebSocketClient::new(server.url(), 1);
+
+    c.bench_function("reconnect_handshake", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let mut ws_stream = client.connect().await.unwrap();
+            ws_stream.close(None).await.unwrap();
+        })
+    });
+}
+
+criterion_group!(benches, bench_strategy_overhead, bench_handshake);
+criterion_main!(benches);
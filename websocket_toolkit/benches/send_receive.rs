@@ -0,0 +1,83 @@
+//! Benchmarks for the controller send/receive hot path.
+//!
+//! Each iteration sends a binary message through `WebSocketController::send_message`
+//! to a local echo server and waits for the echo via `receive_message`. The batch group
+//! compares sending a burst with `send_message` against `feed_message` plus one `flush`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::runtime::Runtime;
+use tokio::sync::Mutex;
+use websocket_toolkit::controller::WebSocketController;
+use websocket_toolkit::flush::FlushPolicy;
+use websocket_toolkit::testing::EchoServer;
+
+/// Benchmarks a full send + echoed receive round trip for several payload sizes.
+fn bench_round_trip(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("Failed to create Tokio runtime");
+    let server = runtime.block_on(EchoServer::start()).expect("Failed to start echo server");
+    let controller = Mutex::new(WebSocketController::new(server.url(), 1, None));
+    let ws_stream = Mutex::new(
+        runtime
+            .block_on(async { controller.lock().await.connect().await })
+            .expect("Failed to connect to echo server"),
+    );
+
+    let mut group = c.benchmark_group("round_trip");
+    for size in [16usize, 1024, 64 * 1024] {
+        let payload = vec![0xABu8; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &payload, |b, payload| {
+            b.to_async(&runtime).iter(|| async {
+                let mut controller = controller.lock().await;
+                let mut ws_stream = ws_stream.lock().await;
+                controller.send_message(&mut ws_stream, payload).await.unwrap();
+                controller.receive_message(&mut ws_stream).await.unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// The number of messages in each burst of the batch benchmark.
+const BATCH_SIZE: usize = 32;
+
+/// Benchmarks a burst of messages sent one flush per message versus one flush per burst.
+fn bench_batch(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("Failed to create Tokio runtime");
+    let server = runtime.block_on(EchoServer::start()).expect("Failed to start echo server");
+    let controller = Mutex::new(WebSocketController::new(server.url(), 1, None));
+    let ws_stream = Mutex::new(
+        runtime
+            .block_on(async { controller.lock().await.connect().await })
+            .expect("Failed to connect to echo server"),
+    );
+    let payload = vec![0xABu8; 256];
+
+    let mut group = c.benchmark_group("batch");
+    group.throughput(Throughput::Elements(BATCH_SIZE as u64));
+    for batched in [false, true] {
+        let name = if batched { "feed_flush" } else { "send" };
+        group.bench_function(name, |b| {
+            b.to_async(&runtime).iter(|| async {
+                let mut controller = controller.lock().await;
+                let mut ws_stream = ws_stream.lock().await;
+                controller.set_flush_policy(FlushPolicy::Manual);
+                for _ in 0..BATCH_SIZE {
+                    if batched {
+                        controller.feed_message(&mut ws_stream, &payload).await.unwrap();
+                    } else {
+                        controller.send_message(&mut ws_stream, &payload).await.unwrap();
+                    }
+                }
+                controller.flush(&mut ws_stream).await.unwrap();
+                for _ in 0..BATCH_SIZE {
+                    controller.receive_message(&mut ws_stream).await.unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_round_trip, bench_batch);
+criterion_main!(benches);
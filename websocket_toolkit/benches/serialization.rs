@@ -0,0 +1,99 @@
+//! Benchmarks for message serialization and deserialization.
+//!
+//! Compares the JSON and CBOR code paths of `MessageHandler` on a small and a large
+//! structured message, and against serializing into buffers from a `BufferPool`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use serde::{Deserialize, Serialize};
+use websocket_toolkit::messages::{MessageFormat, MessageHandler};
+use websocket_toolkit::pool::BufferPool;
+
+/// A representative structured message used by the benchmarks.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct BenchMessage {
+    /// The type of the message.
+    #[serde(rename = "type")]
+    msg_type: String,
+    /// A monotonically increasing sequence number.
+    sequence: u64,
+    /// The message body.
+    content: String,
+    /// A list of numeric samples.
+    samples: Vec<f64>,
+}
+
+impl BenchMessage {
+    /// Builds a message whose body and sample list grow with `size`.
+    fn with_size(size: usize) -> Self {
+        BenchMessage {
+            msg_type: "update".to_string(),
+            sequence: 42,
+            content: "x".repeat(size),
+            samples: (0..size / 8).map(|i| i as f64 * 0.5).collect(),
+        }
+    }
+}
+
+/// Benchmarks `MessageHandler::serialize` for each format and message size.
+fn bench_serialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialize");
+    for size in [64usize, 4096] {
+        let message = BenchMessage::with_size(size);
+        for format in [MessageFormat::Json, MessageFormat::Cbor] {
+            let encoded_len = MessageHandler::serialize(&message, format).unwrap().len();
+            group.throughput(Throughput::Bytes(encoded_len as u64));
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", format), size),
+                &message,
+                |b, message| b.iter(|| MessageHandler::serialize(black_box(message), format).unwrap()),
+            );
+        }
+    }
+    group.finish();
+}
+
+/// Benchmarks `BufferPool::serialize` for each format and message size.
+fn bench_serialize_pooled(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialize_pooled");
+    let pool = BufferPool::default();
+    for size in [64usize, 4096] {
+        let message = BenchMessage::with_size(size);
+        for format in [MessageFormat::Json, MessageFormat::Cbor] {
+            let encoded_len = pool.serialize(&message, format).unwrap().len();
+            group.throughput(Throughput::Bytes(encoded_len as u64));
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", format), size),
+                &message,
+                |b, message| b.iter(|| pool.serialize(black_box(message), format).unwrap()),
+            );
+        }
+    }
+    group.finish();
+}
+
+/// Benchmarks `MessageHandler::deserialize` for each format and message size.
+fn bench_deserialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("deserialize");
+    for size in [64usize, 4096] {
+        let message = BenchMessage::with_size(size);
+        for format in [MessageFormat::Json, MessageFormat::Cbor] {
+            let encoded = MessageHandler::serialize(&message, format).unwrap();
+            group.throughput(Throughput::Bytes(encoded.len() as u64));
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", format), size),
+                &encoded,
+                |b, encoded| {
+                    b.iter(|| {
+                        let decoded: Option<BenchMessage> =
+                            MessageHandler::deserialize(black_box(encoded), format).unwrap();
+                        decoded
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_serialize, bench_serialize_pooled, bench_deserialize);
+criterion_main!(benches);
@@ -0,0 +1,47 @@
+//! Throughput and latency benchmarks built on the `loadgen` harness.
+//!
+//! Each benchmark runs `N` connections × `M` messages per second against a local echo
+//! server and prints the achieved throughput and latency percentiles alongside
+//! Criterion's timing.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use websocket_toolkit::loadgen::{self, LoadConfig};
+use websocket_toolkit::testing::EchoServer;
+
+/// Benchmarks fixed load profiles and reports throughput and tail latency.
+fn bench_load_profiles(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("Failed to create Tokio runtime");
+    let server = runtime.block_on(EchoServer::start()).expect("Failed to start echo server");
+
+    let mut group = c.benchmark_group("load");
+    group.sample_size(10);
+    for (connections, messages_per_sec) in [(1usize, 1000u32), (10, 100), (50, 100)] {
+        let config = LoadConfig {
+            connections,
+            messages_per_sec,
+            duration: Duration::from_millis(500),
+            payload_size: 128,
+        };
+        let id = BenchmarkId::from_parameter(format!("{}x{}", connections, messages_per_sec));
+        group.bench_with_input(id, &config, |b, config| {
+            b.to_async(&runtime).iter(|| async {
+                let report = loadgen::run(server.url(), config).await;
+                println!(
+                    "{}x{}: {:.0} msg/s, p50={:?} p99={:?}",
+                    config.connections,
+                    config.messages_per_sec,
+                    report.throughput(),
+                    report.latency_percentile(50.0),
+                    report.latency_percentile(99.0)
+                );
+                report
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_load_profiles);
+criterion_main!(benches);
@@ -0,0 +1,31 @@
+//! Build script: generates the C header for the `ffi` feature with cbindgen, into
+//! `OUT_DIR/include/wstk.h`, and the uniffi scaffolding for the `mobile` feature.
+
+fn main() {
+    #[cfg(feature = "ffi")]
+    {
+        let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is set by cargo");
+        // cbindgen reads only these two files: the header covers `src/ffi.rs` alone, so
+        // public items of other modules are not exported to C.
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        let generated = cbindgen::Config::from_file("cbindgen.toml")
+            .map_err(|e| e.to_string())
+            .and_then(|config| {
+                cbindgen::Builder::new()
+                    .with_config(config)
+                    .with_src("src/ffi.rs")
+                    .generate()
+                    .map_err(|e| e.to_string())
+            });
+        match generated {
+            Ok(bindings) => {
+                bindings.write_to_file(format!("{}/include/wstk.h", out_dir));
+            }
+            Err(e) => println!("cargo:warning=Failed to generate wstk.h: {}", e),
+        }
+    }
+
+    #[cfg(feature = "mobile")]
+    uniffi::generate_scaffolding("src/websocket_toolkit.udl").expect("Failed to generate uniffi scaffolding");
+}
@@ -0,0 +1,854 @@
+//! # `websocket_toolkit_core`: message and envelope core of `websocket_toolkit`
+//!
+//! This crate holds the pure serialization and envelope logic of `websocket_toolkit`:
+//! `MessageFormat`, `MessageHandler`, `Envelope` and `LazyEnvelope`. It is `no_std` + `alloc`
+//! compatible so firmware that shares message definitions with a gateway can reuse the exact
+//! same types and codecs. `websocket_toolkit` re-exports everything from its `messages` module.
+//!
+//! # Features
+//!
+//! * `json` (default) - the JSON codec, via `serde_json` with `alloc` only.
+//! * `cbor` (default) - the CBOR codec, via `serde_cbor` with `alloc` only.
+//! * `arbitrary-precision` - decodes JSON numbers exactly, via `serde_json`'s
+//!   `arbitrary_precision`; implies `json`.
+//! * `raw-value` - `serde_json::value::RawValue` fields pass JSON through untouched, via
+//!   `serde_json`'s `raw_value`; implies `json`.
+//! * `std` - enables the `std` support of serde and the codecs.
+//! * `fuzzing` - `arbitrary` implementations for fuzz targets; implies `std`.
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use serde::{Serialize, Deserialize};
+use log::error;
+#[cfg(feature = "fuzzing")]
+use arbitrary::Arbitrary;
+
+/// Implementation of the `Arbitrary` trait for `MessageFormat`.
+///
+/// This allows `MessageFormat` to be used in fuzz testing by generating random values.
+/// Available with the `fuzzing` feature.
+#[cfg(feature = "fuzzing")]
+impl<'a> Arbitrary<'a> for MessageFormat {
+    /// Generates a random `MessageFormat`.
+    ///
+    /// # Arguments
+    ///
+    /// * `u` - The `Unstructured` data used to generate random values.
+    ///
+    /// # Returns
+    ///
+    /// A random instance of `MessageFormat`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if random generation fails.
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let choice = u.int_in_range(0..=1)?;
+        match choice {
+            0 => Ok(MessageFormat::Json),
+            1 => Ok(MessageFormat::Cbor),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Enum representing the supported message formats for serialization and deserialization.
+///
+/// This enum is used to specify whether messages should be serialized or deserialized
+/// in JSON or CBOR formats. Each format is backed by a cargo feature (`json`, `cbor`);
+/// using a format whose feature is disabled returns an error.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+    /// JSON format.
+    #[serde(alias = "json")]
+    Json,
+    /// CBOR format.
+    #[serde(alias = "cbor")]
+    Cbor,
+}
+
+/// How JSON numbers that do not fit a native number are decoded.
+///
+/// Without the `arbitrary-precision` feature, serde_json rounds decimals to the nearest `f64`
+/// and integers beyond `i64`/`u64` to a float, so a price of `12345678901234567.89` silently
+/// arrives as `12345678901234568`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum JsonNumbers {
+    /// serde_json's behavior: numbers are rounded to a native number if necessary.
+    #[default]
+    Native,
+    /// Numbers must decode without losing digits. With the `arbitrary-precision` feature they
+    /// do; without it, a message with a number that would be rounded fails to decode.
+    Exact,
+}
+
+/// A handler for serializing and deserializing messages.
+///
+/// Provides utility functions to handle message encoding and decoding in JSON and CBOR formats.
+pub struct MessageHandler;
+
+impl MessageHandler {
+    /// Serializes the given data into the specified format.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The data to serialize.
+    /// * `format` - The format to serialize the data into (`MessageFormat::Json` or `MessageFormat::Cbor`).
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the serialized data as a `Vec<u8>` on success, or an error message as a `String` on failure.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit_core::{MessageHandler, MessageFormat};
+    ///
+    /// let message = "Hello, WebSocket!";
+    /// let serialized = MessageHandler::serialize(&message, MessageFormat::Json).unwrap();
+    /// assert!(!serialized.is_empty());
+    /// ```
+    pub fn serialize<T: Serialize>(data: &T, format: MessageFormat) -> Result<Vec<u8>, String> {
+        match format {
+            MessageFormat::Json => Self::private_serialize_json(data),
+            MessageFormat::Cbor => Self::private_serialize_cbor(data),
+        }
+    }
+
+    /// Deserializes the given byte slice into the specified type.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The byte slice containing the serialized data.
+    /// * `format` - The format of the serialized data (`MessageFormat::Json` or `MessageFormat::Cbor`).
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the deserialized data as an `Option<T>` on success, or an error message as a `String` on failure.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit_core::{MessageHandler, MessageFormat};
+    ///
+    /// let serialized = b"\"Hello, WebSocket!\"";
+    /// let deserialized: Option<String> = MessageHandler::deserialize(serialized, MessageFormat::Json).unwrap();
+    /// assert_eq!(deserialized, Some("Hello, WebSocket!".to_string()));
+    /// ```
+    pub fn deserialize<'a, T: Deserialize<'a>>(data: &'a [u8], format: MessageFormat) -> Result<Option<T>, String> {
+        match format {
+            MessageFormat::Json => Self::private_deserialize_json(data),
+            MessageFormat::Cbor => Self::private_deserialize_cbor(data),
+        }
+    }
+
+    /// Deserializes the given byte slice like `deserialize`, handling JSON numbers as `numbers`
+    /// says.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The byte slice containing the serialized data.
+    /// * `format` - The format of the serialized data.
+    /// * `numbers` - How JSON numbers that do not fit a native number are handled.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the deserialized data as an `Option<T>` on success, or an error
+    /// message on failure, including a number that `JsonNumbers::Exact` would have to round.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit_core::{JsonNumbers, MessageFormat, MessageHandler};
+    ///
+    /// let tenth: Option<f64> = MessageHandler::deserialize_with(b"0.1", MessageFormat::Json, JsonNumbers::Exact).unwrap();
+    /// assert_eq!(tenth, Some(0.1));
+    ///
+    /// let price = b"12345678901234567.89";
+    /// # #[cfg(not(feature = "arbitrary-precision"))]
+    /// assert!(MessageHandler::deserialize_with::<f64>(price, MessageFormat::Json, JsonNumbers::Exact).is_err());
+    /// ```
+    pub fn deserialize_with<'a, T: Deserialize<'a>>(
+        data: &'a [u8],
+        format: MessageFormat,
+        numbers: JsonNumbers,
+    ) -> Result<Option<T>, String> {
+        if format == MessageFormat::Json && numbers == JsonNumbers::Exact {
+            Self::private_check_exact_numbers(data)?;
+        }
+        Self::deserialize(data, format)
+    }
+
+    /// Checks that every number in a JSON document decodes without rounding; with the
+    /// `arbitrary-precision` feature, every number does.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The JSON document.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())`, or an error message naming the first number that would be rounded.
+    #[cfg(feature = "arbitrary-precision")]
+    fn private_check_exact_numbers(_data: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Checks that every number in a JSON document decodes without rounding.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The JSON document.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())`, or an error message naming the first number that would be rounded.
+    #[cfg(not(feature = "arbitrary-precision"))]
+    fn private_check_exact_numbers(data: &[u8]) -> Result<(), String> {
+        let mut in_string = false;
+        let mut escaped = false;
+        let mut number = None;
+        for (i, &byte) in data.iter().enumerate() {
+            if in_string {
+                match byte {
+                    _ if escaped => escaped = false,
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            let numeric = matches!(byte, b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E');
+            match number {
+                Some(_) if numeric => continue,
+                Some(start) => {
+                    Self::private_check_exact_number(&data[start..i])?;
+                    number = None;
+                }
+                None => {}
+            }
+            match byte {
+                b'"' => in_string = true,
+                b'0'..=b'9' | b'-' => number = Some(i),
+                _ => {}
+            }
+        }
+        match number {
+            Some(start) => Self::private_check_exact_number(&data[start..]),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that one JSON number fits an `i64`, a `u64` or, with all its digits, an `f64`.
+    ///
+    /// # Arguments
+    ///
+    /// * `number` - The number as written in the document.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())`, or an error message if the number would be rounded.
+    #[cfg(not(feature = "arbitrary-precision"))]
+    fn private_check_exact_number(number: &[u8]) -> Result<(), String> {
+        let text = core::str::from_utf8(number).unwrap_or_default();
+        let exact = text.parse::<i64>().is_ok()
+            || text.parse::<u64>().is_ok()
+            || text.parse::<f64>().ok().filter(|value| value.is_finite()).is_some_and(|value| {
+                let written = Self::private_normalize_decimal(text);
+                written.is_some() && written == Self::private_normalize_decimal(&format!("{:e}", value))
+            });
+        if exact {
+            Ok(())
+        } else {
+            error!("JSON number {} would lose precision", text);
+            Err(format!(
+                "JSON number {} would lose precision; enable the `arbitrary-precision` feature",
+                text
+            ))
+        }
+    }
+
+    /// Reduces a decimal number to its sign, significant digits and exponent, so equal values
+    /// compare equal however they are written.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The number, such as `-1.50e3`.
+    ///
+    /// # Returns
+    ///
+    /// The sign, the digits without leading or trailing zeros and the position of the decimal
+    /// point relative to the first digit, or `None` if the exponent is out of range.
+    #[cfg(not(feature = "arbitrary-precision"))]
+    fn private_normalize_decimal(text: &str) -> Option<(bool, String, i64)> {
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let (mantissa, exponent) = match text.find(['e', 'E']) {
+            Some(at) => (&text[..at], text[at + 1..].parse::<i64>().ok()?),
+            None => (text, 0),
+        };
+        let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let digits: String = integer.chars().chain(fraction.chars()).collect();
+        let significant = digits.trim_start_matches('0');
+        let leading = digits.len() - significant.len();
+        let significant = significant.trim_end_matches('0');
+        if significant.is_empty() {
+            return Some((false, String::new(), 0));
+        }
+        Some((negative, significant.to_string(), integer.len() as i64 - leading as i64 + exponent))
+    }
+
+    /// Serializes the data to JSON format.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The data to serialize.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the serialized JSON as a `Vec<u8>` on success, or an error message on failure.
+    fn private_serialize_json<T: Serialize>(data: &T) -> Result<Vec<u8>, String> {
+        #[cfg(feature = "json")]
+        {
+            serde_json::to_vec(data).map_err(|e| {
+                error!("Failed to serialize JSON: {}", e);
+                format!("Failed to serialize JSON: {}", e)
+            })
+        }
+        #[cfg(not(feature = "json"))]
+        {
+            let _ = data;
+            Err(Self::private_codec_disabled("JSON", "json"))
+        }
+    }
+
+    /// Serializes the data to CBOR format.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The data to serialize.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the serialized CBOR as a `Vec<u8>` on success, or an error message on failure.
+    fn private_serialize_cbor<T: Serialize>(data: &T) -> Result<Vec<u8>, String> {
+        #[cfg(feature = "cbor")]
+        {
+            serde_cbor::to_vec(data).map_err(|e| {
+                error!("Failed to serialize CBOR: {}", e);
+                format!("Failed to serialize CBOR: {}", e)
+            })
+        }
+        #[cfg(not(feature = "cbor"))]
+        {
+            let _ = data;
+            Err(Self::private_codec_disabled("CBOR", "cbor"))
+        }
+    }
+
+    /// Deserializes data from JSON format.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The byte slice containing the serialized JSON data.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the deserialized data as an `Option<T>` on success, or an error message on failure.
+    fn private_deserialize_json<'a, T: Deserialize<'a>>(data: &'a [u8]) -> Result<Option<T>, String> {
+        #[cfg(feature = "json")]
+        {
+            serde_json::from_slice(data).map(|v| Some(v)).map_err(|e| {
+                error!("Failed to deserialize JSON: {}", e);
+                format!("Failed to deserialize JSON: {}", e)
+            })
+        }
+        #[cfg(not(feature = "json"))]
+        {
+            let _ = data;
+            Err(Self::private_codec_disabled("JSON", "json"))
+        }
+    }
+
+    /// Deserializes data from CBOR format.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The byte slice containing the serialized CBOR data.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the deserialized data as an `Option<T>` on success, or an error message on failure.
+    fn private_deserialize_cbor<'a, T: Deserialize<'a>>(data: &'a [u8]) -> Result<Option<T>, String> {
+        #[cfg(feature = "cbor")]
+        {
+            serde_cbor::from_slice(data).map(|v| Some(v)).map_err(|e| {
+                error!("Failed to deserialize CBOR: {}", e);
+                format!("Failed to deserialize CBOR: {}", e)
+            })
+        }
+        #[cfg(not(feature = "cbor"))]
+        {
+            let _ = data;
+            Err(Self::private_codec_disabled("CBOR", "cbor"))
+        }
+    }
+
+    /// Builds the error returned when a codec's cargo feature is disabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The format name, such as `JSON`.
+    /// * `feature` - The cargo feature that enables the format.
+    ///
+    /// # Returns
+    ///
+    /// An error message naming the missing feature.
+    #[cfg(not(all(feature = "json", feature = "cbor")))]
+    fn private_codec_disabled(name: &str, feature: &str) -> String {
+        error!("{} support is disabled", name);
+        format!("{} support is disabled; enable the `{}` feature", name, feature)
+    }
+}
+
+/// A message envelope carrying a type tag, an optional correlation id and an opaque payload.
+///
+/// Envelopes let peers route messages on `kind` (and match replies on `id`) without decoding
+/// the payload, which is usually itself a JSON or CBOR document. An optional `version` tells
+/// the receiver which schema the payload follows, so it can be migrated before decoding, and
+/// an optional `timestamp` and `nonce` let the receiver reject replayed messages. `headers`
+/// carry string metadata such as a W3C `traceparent` alongside the payload.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit_core::{Envelope, MessageFormat};
+///
+/// let envelope = Envelope::new("chat", b"hello".to_vec()).with_id("42");
+/// let encoded = envelope.encode(MessageFormat::Cbor).unwrap();
+/// assert_eq!(Envelope::decode(&encoded, MessageFormat::Cbor).unwrap(), envelope);
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    /// The message type used for routing.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// An optional id correlating requests and replies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// The schema version of the payload, or `None` for unversioned envelopes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+    /// When the envelope was sent, in milliseconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// A value the sender never reuses, identifying the envelope for replay protection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+    /// Metadata such as trace context, left out of the encoding when empty.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// The encoded message body.
+    #[serde(default)]
+    pub payload: Vec<u8>,
+}
+
+impl Envelope {
+    /// Creates an envelope with the given type and payload and no id.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The message type.
+    /// * `payload` - The encoded message body.
+    ///
+    /// # Returns
+    ///
+    /// A new `Envelope`.
+    pub fn new(kind: impl Into<String>, payload: Vec<u8>) -> Self {
+        Envelope {
+            kind: kind.into(),
+            id: None,
+            version: None,
+            timestamp: None,
+            nonce: None,
+            headers: BTreeMap::new(),
+            payload,
+        }
+    }
+
+    /// Creates an envelope whose payload is `value` serialized in `format`.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The message type.
+    /// * `value` - The message body to serialize.
+    /// * `format` - The format used for the payload.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the envelope, or an error message if serialization fails.
+    pub fn from_value<T: Serialize>(kind: impl Into<String>, value: &T, format: MessageFormat) -> Result<Self, String> {
+        Ok(Self::new(kind, MessageHandler::serialize(value, format)?))
+    }
+
+    /// Sets the correlation id.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The correlation id.
+    ///
+    /// # Returns
+    ///
+    /// The envelope with the id set.
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Sets the schema version of the payload.
+    ///
+    /// # Arguments
+    ///
+    /// * `version` - The schema version.
+    ///
+    /// # Returns
+    ///
+    /// The envelope with the version set.
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// Sets the send time and nonce checked by replay protection.
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - The send time in milliseconds since the Unix epoch.
+    /// * `nonce` - A value not used for any other envelope from the same sender.
+    ///
+    /// # Returns
+    ///
+    /// The envelope with the timestamp and nonce set.
+    pub fn with_stamp(mut self, timestamp: u64, nonce: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self.nonce = Some(nonce);
+        self
+    }
+
+    /// Sets a header.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The header name.
+    /// * `value` - The header value, replacing any earlier one.
+    ///
+    /// # Returns
+    ///
+    /// The envelope with the header set.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+
+    /// Returns the value of the header `name`, if set.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+
+    /// Deserializes the payload as a `T` encoded in `format`.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format of the payload.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the decoded payload, or an error message on failure.
+    pub fn payload_as<'a, T: Deserialize<'a>>(&'a self, format: MessageFormat) -> Result<T, String> {
+        MessageHandler::deserialize(&self.payload, format)?
+            .ok_or_else(|| "Envelope payload is empty".to_string())
+    }
+
+    /// Serializes the whole envelope in `format`.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The wire format.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the encoded envelope, or an error message on failure.
+    pub fn encode(&self, format: MessageFormat) -> Result<Vec<u8>, String> {
+        MessageHandler::serialize(self, format)
+    }
+
+    /// Deserializes an envelope encoded in `format`.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The encoded envelope.
+    /// * `format` - The wire format.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the envelope, or an error message on failure.
+    pub fn decode(data: &[u8], format: MessageFormat) -> Result<Self, String> {
+        MessageHandler::deserialize(data, format)?.ok_or_else(|| "Empty envelope".to_string())
+    }
+}
+
+/// An envelope whose header is parsed and whose payload is left encoded.
+///
+/// Routers and filters usually look at the `type` and `id` only, yet `Envelope::decode`
+/// builds the whole payload, element by element. `LazyEnvelope::parse` skips the payload
+/// instead, borrowing `kind` from the encoded bytes unless it contains escapes, and
+/// decodes it only when `payload` or `payload_as` is called. A message that is dropped or
+/// forwarded elsewhere (see `raw`) never pays for its payload.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit_core::{Envelope, LazyEnvelope, MessageFormat};
+///
+/// let encoded = Envelope::from_value("quote", &[1.5, 2.5], MessageFormat::Json).unwrap().encode(MessageFormat::Json).unwrap();
+/// let lazy = LazyEnvelope::parse(&encoded, MessageFormat::Json).unwrap();
+/// assert_eq!(lazy.kind, "quote");
+/// assert_eq!(lazy.payload_as::<Vec<f64>>().unwrap(), vec![1.5, 2.5]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LazyEnvelope<'a> {
+    /// The message type used for routing.
+    pub kind: Cow<'a, str>,
+    /// An optional id correlating requests and replies.
+    pub id: Option<Cow<'a, str>>,
+    /// The schema version of the payload, or `None` for unversioned envelopes.
+    pub version: Option<u32>,
+    /// When the envelope was sent, in milliseconds since the Unix epoch.
+    pub timestamp: Option<u64>,
+    /// A value the sender never reuses, identifying the envelope for replay protection.
+    pub nonce: Option<u64>,
+    /// Metadata such as trace context.
+    pub headers: BTreeMap<String, String>,
+    raw: &'a [u8],
+    format: MessageFormat,
+}
+
+/// The fields of `Envelope` except `payload`, which the deserializer skips as unknown.
+#[derive(Deserialize)]
+struct EnvelopeHeader<'a> {
+    #[serde(rename = "type", borrow)]
+    kind: Cow<'a, str>,
+    #[serde(default, borrow)]
+    id: Option<Cow<'a, str>>,
+    #[serde(default)]
+    version: Option<u32>,
+    #[serde(default)]
+    timestamp: Option<u64>,
+    #[serde(default)]
+    nonce: Option<u64>,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+}
+
+/// The `payload` of `Envelope`, with every other field skipped.
+#[derive(Deserialize)]
+struct EnvelopePayload {
+    #[serde(default)]
+    payload: Vec<u8>,
+}
+
+impl<'a> LazyEnvelope<'a> {
+    /// Parses the header of an envelope encoded in `format`, skipping its payload.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The encoded envelope.
+    /// * `format` - The wire format.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the envelope, or an error message if the data is not an
+    /// envelope. The payload is only checked for well-formedness, not decoded.
+    pub fn parse(data: &'a [u8], format: MessageFormat) -> Result<Self, String> {
+        let header: EnvelopeHeader<'a> =
+            MessageHandler::deserialize(data, format)?.ok_or_else(|| "Empty envelope".to_string())?;
+        Ok(LazyEnvelope {
+            kind: header.kind,
+            id: header.id,
+            version: header.version,
+            timestamp: header.timestamp,
+            nonce: header.nonce,
+            headers: header.headers,
+            raw: data,
+            format,
+        })
+    }
+
+    /// Returns the value of the header `name`, if set.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+
+    /// Returns the encoded envelope, e.g. to forward it without encoding it again.
+    pub fn raw(&self) -> &'a [u8] {
+        self.raw
+    }
+
+    /// Returns the wire format of the envelope.
+    pub fn format(&self) -> MessageFormat {
+        self.format
+    }
+
+    /// Decodes the payload.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the encoded message body, or an error message on failure.
+    pub fn payload(&self) -> Result<Vec<u8>, String> {
+        let decoded: Option<EnvelopePayload> = MessageHandler::deserialize(self.raw, self.format)?;
+        Ok(decoded.map(|envelope| envelope.payload).unwrap_or_default())
+    }
+
+    /// Decodes the payload and deserializes it as a `T` encoded in `format`.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format of the payload, usually the envelope's own.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the decoded payload, or an error message on failure.
+    pub fn payload_as<T: serde::de::DeserializeOwned>(&self) -> Result<T, String> {
+        MessageHandler::deserialize(&self.payload()?, self.format)?
+            .ok_or_else(|| "Envelope payload is empty".to_string())
+    }
+
+    /// Decodes the payload and returns the complete envelope.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the envelope, or an error message if the payload fails to
+    /// decode.
+    pub fn into_envelope(self) -> Result<Envelope, String> {
+        Ok(Envelope {
+            payload: self.payload()?,
+            kind: self.kind.into_owned(),
+            id: self.id.map(Cow::into_owned),
+            version: self.version,
+            timestamp: self.timestamp,
+            nonce: self.nonce,
+            headers: self.headers,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests JSON serialization and deserialization.
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json_serialization() {
+        let message = "Hello, WebSocket!";
+        let serialized = MessageHandler::serialize(&message, MessageFormat::Json);
+        assert!(serialized.is_ok(), "Expected successful JSON serialization");
+        let serialized = serialized.unwrap();
+        assert!(!serialized.is_empty(), "Expected non-empty JSON serialized data");
+
+        let deserialized: Result<Option<String>, String> = MessageHandler::deserialize(&serialized, MessageFormat::Json);
+        assert!(deserialized.is_ok(), "Expected successful JSON deserialization");
+        assert_eq!(deserialized.unwrap(), Some(message.to_string()), "Expected deserialized JSON to match original message");
+    }
+
+    /// Tests CBOR serialization and deserialization.
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn test_cbor_serialization() {
+        let message = "Hello, WebSocket!";
+        let serialized = MessageHandler::serialize(&message, MessageFormat::Cbor);
+        assert!(serialized.is_ok(), "Expected successful CBOR serialization");
+        let serialized = serialized.unwrap();
+        assert!(!serialized.is_empty(), "Expected non-empty CBOR serialized data");
+
+        let deserialized: Result<Option<String>, String> = MessageHandler::deserialize(&serialized, MessageFormat::Cbor);
+        assert!(deserialized.is_ok(), "Expected successful CBOR deserialization");
+        assert_eq!(deserialized.unwrap(), Some(message.to_string()), "Expected deserialized CBOR to match original message");
+    }
+
+    /// Tests that an envelope and its typed payload round-trip in both formats.
+    #[cfg(all(feature = "json", feature = "cbor"))]
+    #[test]
+    fn test_envelope_round_trip() {
+        for format in [MessageFormat::Json, MessageFormat::Cbor] {
+            let envelope = Envelope::from_value("greeting", &"hi", format).unwrap().with_id("1");
+            let decoded = Envelope::decode(&envelope.encode(format).unwrap(), format).unwrap();
+            assert_eq!(decoded, envelope);
+            assert_eq!(decoded.payload_as::<String>(format).unwrap(), "hi");
+        }
+    }
+
+    /// Tests that a lazy envelope reads the header, borrowing it where possible, and decodes
+    /// the payload only on demand.
+    #[cfg(all(feature = "json", feature = "cbor"))]
+    #[test]
+    fn test_lazy_envelope() {
+        for format in [MessageFormat::Json, MessageFormat::Cbor] {
+            let envelope = Envelope::from_value("quote", &"ACME 12.5", format)
+                .unwrap()
+                .with_id("7")
+                .with_version(2)
+                .with_header("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+            let encoded = envelope.encode(format).unwrap();
+            let lazy = LazyEnvelope::parse(&encoded, format).unwrap();
+            assert!(matches!(lazy.kind, Cow::Borrowed("quote")), "Expected the type to be borrowed");
+            assert_eq!(lazy.id.as_deref(), Some("7"));
+            assert_eq!(lazy.version, Some(2));
+            assert_eq!(lazy.header("traceparent"), envelope.header("traceparent"));
+            assert_eq!(lazy.raw(), &encoded[..]);
+            assert_eq!(lazy.payload_as::<String>().unwrap(), "ACME 12.5");
+            assert_eq!(lazy.into_envelope().unwrap(), envelope);
+        }
+        assert!(LazyEnvelope::parse(br#"{"id": "1"}"#, MessageFormat::Json).is_err());
+        let lazy = LazyEnvelope::parse(br#"{"type": "ping"}"#, MessageFormat::Json).unwrap();
+        assert!(lazy.payload().unwrap().is_empty());
+    }
+
+    /// Tests that `JsonNumbers::Exact` accepts numbers that decode exactly, however they are
+    /// written, and rejects those that would be rounded, ignoring digits inside strings.
+    #[cfg(all(feature = "json", not(feature = "arbitrary-precision")))]
+    #[test]
+    fn test_exact_json_numbers() {
+        let exact = |json: &str| {
+            MessageHandler::deserialize_with::<serde_json::Value>(json.as_bytes(), MessageFormat::Json, JsonNumbers::Exact)
+        };
+        for json in ["0.1", "1.10", "-2.5e3", "1E-7", "-0.0", "18446744073709551615", "-9223372036854775808", "[1, true, 2e2]"] {
+            assert!(exact(json).is_ok(), "Expected {} to be exact", json);
+        }
+        assert!(exact(r#"{"note": "12345678901234567890.5 \" 0.12345678901234567890", "qty": 3}"#).is_ok());
+        for json in ["12345678901234567.89", "18446744073709551616", "[0.1, 0.12345678901234567890]", "1e999"] {
+            let error = exact(json).unwrap_err();
+            assert!(error.contains("lose precision"), "Unexpected error for {}: {}", json, error);
+        }
+        let native = MessageHandler::deserialize_with::<f64>(b"12345678901234567.89", MessageFormat::Json, JsonNumbers::Native);
+        assert_eq!(native.unwrap(), Some(12345678901234568.0));
+    }
+
+    /// Tests that using a format whose feature is disabled reports the missing feature.
+    #[cfg(not(feature = "json"))]
+    #[test]
+    fn test_disabled_codec_reports_feature() {
+        let error = MessageHandler::serialize(&"hello", MessageFormat::Json).unwrap_err();
+        assert!(error.contains("`json` feature"), "Unexpected error: {}", error);
+    }
+}
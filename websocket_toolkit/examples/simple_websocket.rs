@@ -12,9 +12,7 @@
 use websocket_toolkit::controller::WebSocketController;
 use tokio::time::{timeout, Duration, sleep};
 use log::{info, error};
-use env_logger;
 use serde::{Deserialize, Serialize};
-use serde_cbor;
 use tokio::sync::Mutex;
 use std::sync::Arc;
 
@@ -133,7 +131,7 @@ async fn simulate_keep_alive_and_reconnect(
 
     for _ in 0..3 {
         let mut stream = ws_stream.lock().await;
-        match controller.receive_message(&mut *stream).await {
+        match controller.receive_message(&mut stream).await {
             Ok(Some(msg)) => {
                 if let Ok(json_msg) = serde_json::from_slice::<Message>(&msg) {
                     info!("Received JSON: {:?}", json_msg);
@@ -0,0 +1,91 @@
+#![no_main]
+
+//! Fuzz target for close handling.
+//!
+//! This fuzz target interleaves client operations (send, ping, receive, close) with
+//! server-side close frames carrying arbitrary status codes and reasons, to make sure the
+//! controller and the in-memory transport never panic during the closing handshake or
+//! when the connection is used after it has been closed.
+
+use arbitrary::{Arbitrary, Unstructured};
+use futures_util::{SinkExt, StreamExt};
+use libfuzzer_sys::fuzz_target;
+use std::borrow::Cow;
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Message;
+use websocket_toolkit::controller::WebSocketController;
+use websocket_toolkit::testing::memory_pair;
+
+/// A single step of the close-handling scenario.
+#[derive(Arbitrary, Debug)]
+enum Step {
+    /// The client sends a binary message through the controller.
+    ClientSend(Vec<u8>),
+    /// The client sends a ping through the controller.
+    ClientPing,
+    /// The client reads one message through the controller.
+    ClientReceive,
+    /// The client initiates the closing handshake.
+    ClientClose(Option<(u16, String)>),
+    /// The server sends a close frame.
+    ServerClose(Option<(u16, String)>),
+    /// The server sends a text frame.
+    ServerText(String),
+    /// The server reads one message.
+    ServerReceive,
+}
+
+/// Builds a close frame from a fuzzed status code and reason.
+fn close_frame(close: Option<(u16, String)>) -> Option<CloseFrame<'static>> {
+    close.map(|(code, reason)| CloseFrame {
+        code: CloseCode::from(code),
+        reason: Cow::Owned(reason),
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut unstructured = Unstructured::new(data);
+    let steps = match Vec::<Step>::arbitrary(&mut unstructured) {
+        Ok(steps) if steps.len() <= 64 => steps,
+        _ => return,
+    };
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to create Tokio runtime");
+
+    runtime.block_on(async {
+        let mut controller = WebSocketController::new("ws://127.0.0.1:9001", 1, None);
+        let (mut client, mut server) = memory_pair(None).await;
+        let step_timeout = Duration::from_millis(10);
+
+        for step in steps {
+            match step {
+                Step::ClientSend(payload) => {
+                    let _ = controller.send_message(&mut client, &payload).await;
+                }
+                Step::ClientPing => {
+                    let _ = controller.send_ping(&mut client).await;
+                }
+                Step::ClientReceive => {
+                    let _ = timeout(step_timeout, controller.receive_message(&mut client)).await;
+                }
+                Step::ClientClose(close) => {
+                    let _ = client.close(close_frame(close)).await;
+                }
+                Step::ServerClose(close) => {
+                    let _ = server.close(close_frame(close)).await;
+                }
+                Step::ServerText(text) => {
+                    let _ = server.send(Message::Text(text)).await;
+                }
+                Step::ServerReceive => {
+                    let _ = timeout(step_timeout, server.next()).await;
+                }
+            }
+        }
+    });
+});
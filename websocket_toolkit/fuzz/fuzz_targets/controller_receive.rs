@@ -0,0 +1,133 @@
+#![no_main]
+
+//! Fuzz target for the controller receive path.
+//!
+//! This fuzz target feeds arbitrary frame sequences through the in-memory transport into
+//! `WebSocketController::receive_message` and hands every received payload to the
+//! JSON/CBOR decoding path used for dispatching inbound messages. Two input shapes are used:
+//! 1. Well-formed frames of every type sent by a real server-side stream.
+//! 2. Raw bytes written directly to the client's socket, exercising the frame parser.
+
+use arbitrary::{Arbitrary, Unstructured};
+use futures_util::SinkExt;
+use libfuzzer_sys::fuzz_target;
+use std::borrow::Cow;
+use std::future::{self, Future};
+use tokio::io::AsyncWriteExt;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Message;
+use websocket_toolkit::controller::WebSocketController;
+use websocket_toolkit::messages::{MessageFormat, MessageHandler};
+use websocket_toolkit::testing::{memory_pair, raw_memory_pair, MemoryStream};
+
+/// The maximum number of messages read from the client before giving up.
+const MAX_RECEIVES: usize = 256;
+
+/// A single frame sent by the fuzzed server.
+#[derive(Arbitrary, Debug)]
+enum FuzzFrame {
+    /// A text frame.
+    Text(String),
+    /// A binary frame.
+    Binary(Vec<u8>),
+    /// A ping frame with an arbitrary payload.
+    Ping(Vec<u8>),
+    /// A pong frame with an arbitrary payload.
+    Pong(Vec<u8>),
+    /// A close frame with an optional status code and reason.
+    Close(Option<(u16, String)>),
+}
+
+impl From<FuzzFrame> for Message {
+    fn from(frame: FuzzFrame) -> Self {
+        match frame {
+            FuzzFrame::Text(text) => Message::Text(text),
+            FuzzFrame::Binary(data) => Message::Binary(data),
+            FuzzFrame::Ping(data) => Message::Ping(data),
+            FuzzFrame::Pong(data) => Message::Pong(data),
+            FuzzFrame::Close(close) => Message::Close(close.map(|(code, reason)| CloseFrame {
+                code: CloseCode::from(code),
+                reason: Cow::Owned(reason),
+            })),
+        }
+    }
+}
+
+/// The shape of a single fuzz input.
+#[derive(Arbitrary, Debug)]
+enum FuzzInput {
+    /// A sequence of well-formed frames sent by a server-side stream.
+    Frames(Vec<FuzzFrame>),
+    /// Raw bytes written directly to the client's socket.
+    Raw(Vec<u8>),
+}
+
+/// Reads from the client until the controller reports an error, decoding every payload.
+async fn drain(controller: &mut WebSocketController, client: &mut MemoryStream) {
+    for _ in 0..MAX_RECEIVES {
+        match controller.receive_message(client).await {
+            Ok(Some(payload)) => {
+                let _ = MessageHandler::deserialize::<serde_json::Value>(&payload, MessageFormat::Json);
+                let _ = MessageHandler::deserialize::<serde_json::Value>(&payload, MessageFormat::Cbor);
+            }
+            Ok(None) => continue,
+            Err(_) => break,
+        }
+    }
+}
+
+/// Drains the client while `writer` feeds it, dropping the writer once the client stops
+/// reading: a write larger than the in-memory buffer would otherwise block forever.
+async fn drain_while<W>(controller: &mut WebSocketController, client: &mut MemoryStream, writer: W)
+where
+    W: Future<Output = ()>,
+{
+    let writer = async move {
+        writer.await;
+        // The peer is dropped with the finished writer; keep draining until the client errors.
+        future::pending::<()>().await
+    };
+    tokio::select! {
+        () = drain(controller, client) => {}
+        () = writer => {}
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut unstructured = Unstructured::new(data);
+    let input = match FuzzInput::arbitrary(&mut unstructured) {
+        Ok(input) => input,
+        Err(_) => return,
+    };
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to create Tokio runtime");
+
+    runtime.block_on(async {
+        let mut controller = WebSocketController::new("ws://127.0.0.1:9001", 1, None);
+
+        match input {
+            FuzzInput::Frames(frames) => {
+                let (mut client, mut server) = memory_pair(None).await;
+                let writer = async move {
+                    for frame in frames {
+                        if server.send(Message::from(frame)).await.is_err() {
+                            break;
+                        }
+                    }
+                };
+                drain_while(&mut controller, &mut client, writer).await;
+            }
+            FuzzInput::Raw(bytes) => {
+                let (mut client, mut raw_peer) = raw_memory_pair(None).await;
+                let writer = async move {
+                    let _ = raw_peer.write_all(&bytes).await;
+                };
+                drain_while(&mut controller, &mut client, writer).await;
+            }
+        }
+    });
+});
@@ -0,0 +1,116 @@
+#ifndef WSTK_H
+#define WSTK_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The call succeeded.
+#define WSTK_OK 0
+
+// A pointer argument was null or a string was not valid UTF-8.
+#define WSTK_ERR_INVALID_ARGUMENT -1
+
+// The connection is closed and can no longer send.
+#define WSTK_ERR_CLOSED -2
+
+// Writing the message to the connection failed.
+#define WSTK_ERR_SEND_FAILED -3
+
+// The function was called from an `on_message` callback.
+#define WSTK_ERR_REENTRANT -4
+
+// The function panicked; the panic was caught and logged.
+#define WSTK_ERR_PANIC -5
+
+// An open connection created by `wstk_connect`. Opaque to C callers.
+typedef struct WstkClient WstkClient;
+
+// Called with every text or binary message received on a connection.
+//
+// `data` points to `len` bytes that are only valid for the duration of the call. The
+// callback runs on the connection's runtime thread and receives the `user_data` pointer
+// given to `wstk_connect`. It must not call `wstk_connect`, `wstk_send` or `wstk_close`;
+// those calls fail with `WSTK_ERR_REENTRANT`, or a null handle.
+typedef void (*WstkMessageCallback)(void *user_data, const uint8_t *data, uintptr_t len);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Connects to a WebSocket server.
+//
+// Blocks until the initial connection is established.
+//
+// # Arguments
+//
+// * `url` - A NUL-terminated UTF-8 WebSocket URL.
+// * `retries` - The number of reconnection attempts after the connection drops.
+// * `ping_interval_secs` - The keep-alive ping interval in seconds, or `0` to disable pings.
+// * `on_message` - Called with every received message; may be null.
+// * `user_data` - Passed unchanged to `on_message`.
+//
+// # Returns
+//
+// A handle to pass to `wstk_send` and `wstk_close`, or null if the arguments are invalid,
+// the connection fails, the function panics or it is called from an `on_message` callback.
+//
+// # Safety
+//
+// `url` must be null or point to a NUL-terminated string. `user_data` must remain valid,
+// and usable from another thread, until `wstk_close` returns.
+struct WstkClient *wstk_connect(const char *url,
+                                uint32_t retries,
+                                uint64_t ping_interval_secs,
+                                WstkMessageCallback on_message,
+                                void *user_data);
+
+// Sends a binary message.
+//
+// Blocks until the message has been written to the connection or the write failed. While
+// the connection is being reopened, that includes waiting for the reconnection.
+//
+// # Arguments
+//
+// * `client` - A handle returned by `wstk_connect`.
+// * `data` - The message bytes; may be null when `len` is `0`.
+// * `len` - The number of bytes at `data`.
+//
+// # Returns
+//
+// `WSTK_OK`, `WSTK_ERR_INVALID_ARGUMENT`, `WSTK_ERR_CLOSED`, `WSTK_ERR_SEND_FAILED`,
+// `WSTK_ERR_REENTRANT` or `WSTK_ERR_PANIC`.
+//
+// # Safety
+//
+// `client` must be a live handle and `data` must point to `len` readable bytes.
+int wstk_send(struct WstkClient *client, const uint8_t *data, uintptr_t len);
+
+// Closes the connection and frees the handle.
+//
+// Waits up to five seconds for the close handshake, then aborts the connection task and
+// waits for it to stop. The callback is not invoked after this function returns.
+//
+// # Arguments
+//
+// * `client` - A handle returned by `wstk_connect`, or null.
+//
+// # Returns
+//
+// `WSTK_OK`, `WSTK_ERR_REENTRANT`, in which case the handle is left open, or
+// `WSTK_ERR_PANIC`.
+//
+// # Safety
+//
+// `client` must be null or a live handle; it must not be used after this call unless it
+// returned `WSTK_ERR_REENTRANT`.
+int wstk_close(struct WstkClient *client);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* WSTK_H */
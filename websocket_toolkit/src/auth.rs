@@ -0,0 +1,233 @@
+//! # `auth.rs`: First-frame authentication
+//!
+//! Many servers authenticate a connection with its first message rather than with handshake
+//! headers: the client must send e.g. `{"type":"auth","token":"..."}` within a few seconds of
+//! connecting, or the server drops it. `AuthMessage` describes that frame and its deadline.
+//! Set with `WebSocketController::set_auth_message`, the controller sends it as part of every
+//! connect, so it goes out before anything the application sends or a pipeline has queued.
+//!
+//! With `with_reply`, the controller also waits for the server's answer within the deadline.
+//! A reply the check refuses, or a close instead of a reply, fails the connect with
+//! `AuthRejected`. Retrying with the same credentials cannot succeed, so reconnect loops
+//! (`reconnect_if_needed`, `FleetConnector`) stop on it instead of retrying.
+
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, warn};
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/// Builds the auth frame; called on every connect, so it can pick up a refreshed token.
+pub type AuthProvider = Arc<dyn Fn() -> Result<Message, String> + Send + Sync>;
+
+/// Checks the server's reply to the auth frame; an `Err` rejects the connection.
+pub type AuthCheck = Arc<dyn Fn(&Message) -> Result<(), String> + Send + Sync>;
+
+/// The server refused the connection's credentials.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthRejected {
+    /// Why the connection was rejected: the check's error or the server's close.
+    pub reason: String,
+}
+
+impl fmt::Display for AuthRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Authentication rejected: {}", self.reason)
+    }
+}
+
+impl StdError for AuthRejected {}
+
+/// Returns whether `error` is an `AuthRejected`, which must not be retried.
+pub fn is_auth_rejected(error: &(dyn StdError + 'static)) -> bool {
+    error.downcast_ref::<AuthRejected>().is_some()
+}
+
+/// The auth frame sent first on every connection.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use tokio_tungstenite::tungstenite::Message;
+/// use websocket_toolkit::auth::AuthMessage;
+/// use websocket_toolkit::controller::WebSocketController;
+///
+/// let mut controller = WebSocketController::new("ws://example.com", 3, None);
+/// let auth = AuthMessage::new(|| Ok(Message::Text(r#"{"type":"auth","token":"secret"}"#.into())), Duration::from_secs(5))
+///     .with_reply(|reply| match reply {
+///         Message::Text(text) if text.contains("\"ok\"") => Ok(()),
+///         other => Err(format!("unexpected reply {:?}", other)),
+///     });
+/// controller.set_auth_message(Some(auth));
+/// ```
+#[derive(Clone)]
+pub struct AuthMessage {
+    provider: AuthProvider,
+    deadline: Duration,
+    check: Option<AuthCheck>,
+}
+
+impl AuthMessage {
+    /// Creates an auth message.
+    ///
+    /// # Arguments
+    ///
+    /// * `provider` - Builds the auth frame for each connection.
+    /// * `deadline` - How long after the handshake the frame must be sent, and its reply
+    ///   received if a check is set.
+    ///
+    /// # Returns
+    ///
+    /// A new `AuthMessage` that does not wait for a reply.
+    pub fn new<F>(provider: F, deadline: Duration) -> Self
+    where
+        F: Fn() -> Result<Message, String> + Send + Sync + 'static,
+    {
+        AuthMessage { provider: Arc::new(provider), deadline, check: None }
+    }
+
+    /// Waits for the server's first data message after the auth frame and checks it.
+    ///
+    /// The reply is consumed and not handed to the application.
+    ///
+    /// # Arguments
+    ///
+    /// * `check` - Accepts the reply with `Ok`, or rejects the connection with `Err`.
+    pub fn with_reply<F>(mut self, check: F) -> Self
+    where
+        F: Fn(&Message) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.check = Some(Arc::new(check));
+        self
+    }
+
+    /// Returns the deadline.
+    pub fn deadline(&self) -> Duration {
+        self.deadline
+    }
+
+    /// Sends the auth frame on a fresh connection and, with a check, awaits its reply.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - The connection, before anything else was sent on it.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an `AuthRejected` error if the server refused the
+    /// credentials, or another error if the provider failed, the deadline passed or the
+    /// connection broke.
+    pub async fn authenticate<S>(&self, ws_stream: &mut WebSocketStream<S>) -> Result<(), Box<dyn StdError>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let frame = (self.provider)().map_err(|e| format!("Failed to build auth message: {}", e))?;
+        let deadline = Instant::now() + self.deadline;
+        tokio::time::timeout_at(deadline, ws_stream.send(frame))
+            .await
+            .map_err(|_| format!("Failed to send auth message within {:?}", self.deadline))??;
+        let check = match &self.check {
+            Some(check) => check,
+            None => return Ok(()),
+        };
+        loop {
+            let reply = tokio::time::timeout_at(deadline, ws_stream.next())
+                .await
+                .map_err(|_| format!("No auth reply within {:?}", self.deadline))?;
+            match reply {
+                Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => continue,
+                Some(Ok(Message::Close(frame))) => {
+                    let reason = match frame {
+                        Some(frame) => format!("server closed the connection ({}: {})", u16::from(frame.code), frame.reason),
+                        None => "server closed the connection".to_string(),
+                    };
+                    warn!("Authentication rejected: {}", reason);
+                    return Err(AuthRejected { reason }.into());
+                }
+                Some(Ok(reply)) => {
+                    return match check(&reply) {
+                        Ok(()) => {
+                            debug!("Authenticated");
+                            Ok(())
+                        }
+                        Err(reason) => {
+                            warn!("Authentication rejected: {}", reason);
+                            Err(AuthRejected { reason }.into())
+                        }
+                    };
+                }
+                Some(Err(e)) => return Err(format!("Failed to read auth reply: {}", e).into()),
+                None => return Err("Connection closed before the auth reply".into()),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for AuthMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthMessage")
+            .field("deadline", &self.deadline)
+            .field("check", &self.check.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::memory_pair;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+    use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+
+    fn auth() -> AuthMessage {
+        AuthMessage::new(|| Ok(Message::Text("auth secret".into())), Duration::from_millis(200)).with_reply(|reply| match reply {
+            Message::Text(text) if text == "ok" => Ok(()),
+            other => Err(format!("unexpected reply {:?}", other)),
+        })
+    }
+
+    /// Tests that the auth frame goes out first and an accepting reply is consumed.
+    #[tokio::test]
+    async fn test_authenticate_accepted() {
+        let (mut client, mut server) = memory_pair(None).await;
+        let peer = tokio::spawn(async move {
+            let first = server.next().await.unwrap().unwrap();
+            server.send(Message::Text("ok".into())).await.unwrap();
+            first
+        });
+        auth().authenticate(&mut client).await.unwrap();
+        assert_eq!(peer.await.unwrap(), Message::Text("auth secret".into()));
+    }
+
+    /// Tests that a refused reply or a close is an `AuthRejected`, and silence a timeout.
+    #[tokio::test]
+    async fn test_authenticate_rejected() {
+        let (mut client, mut server) = memory_pair(None).await;
+        tokio::spawn(async move {
+            server.next().await;
+            server.send(Message::Text("denied".into())).await.unwrap();
+        });
+        let error = auth().authenticate(&mut client).await.unwrap_err();
+        assert!(is_auth_rejected(error.as_ref()));
+
+        let (mut client, mut server) = memory_pair(None).await;
+        tokio::spawn(async move {
+            server.next().await;
+            let frame = CloseFrame { code: CloseCode::Policy, reason: "bad token".into() };
+            server.send(Message::Close(Some(frame))).await.unwrap();
+        });
+        let error = auth().authenticate(&mut client).await.unwrap_err();
+        assert!(error.to_string().contains("1008: bad token"), "{}", error);
+
+        let (mut client, _server) = memory_pair(None).await;
+        let error = auth().authenticate(&mut client).await.unwrap_err();
+        assert!(!is_auth_rejected(error.as_ref()));
+        assert!(error.to_string().contains("No auth reply"));
+    }
+}
@@ -0,0 +1,358 @@
+//! # `avro.rs`: Avro messages and the Confluent schema registry
+//!
+//! Data platforms that feed WebSocket traffic into Kafka-compatible pipelines expect Avro
+//! payloads in the Confluent wire format: a zero magic byte, the writer schema's registry ID
+//! as a big-endian `u32`, then the Avro-encoded datum. `AvroHandler` (re-exported from
+//! `messages`) encodes and decodes serde types against an Avro schema, bare or in that wire
+//! format, so the bytes of a binary frame can be produced to Kafka unchanged.
+//!
+//! Avro needs a schema for every value, so it does not fit `MessageFormat` and the
+//! schema-free `MessageHandler` of the `no_std` core; it is enabled by the `avro` feature.
+//!
+//! `SchemaRegistry` (feature `schema-registry`) is a client for a Confluent-compatible
+//! registry's REST API. It registers schemas under a subject, fetches writer schemas by ID
+//! and caches both, so `SchemaRegistry::decode` can read a message from any producer
+//! without knowing its schema in advance.
+
+use apache_avro::Schema;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// The first byte of a message in the Confluent wire format.
+pub const CONFLUENT_MAGIC: u8 = 0;
+
+/// Encodes and decodes serde types as Avro.
+///
+/// # Examples
+///
+/// ```rust
+/// use apache_avro::Schema;
+/// use serde::{Deserialize, Serialize};
+/// use websocket_toolkit::messages::AvroHandler;
+///
+/// #[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// struct Trade {
+///     symbol: String,
+///     price: f64,
+/// }
+///
+/// let schema = Schema::parse_str(
+///     r#"{"type":"record","name":"Trade","fields":[{"name":"symbol","type":"string"},{"name":"price","type":"double"}]}"#,
+/// )
+/// .unwrap();
+/// let trade = Trade { symbol: "BTC".into(), price: 64000.0 };
+/// let framed = AvroHandler::serialize_confluent(&trade, 42, &schema).unwrap();
+/// assert_eq!(AvroHandler::schema_id(&framed), Ok(42));
+/// assert_eq!(AvroHandler::deserialize_confluent::<Trade>(&framed, &schema).unwrap(), trade);
+/// ```
+pub struct AvroHandler;
+
+impl AvroHandler {
+    /// Encodes `value` as an Avro datum of `schema`.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The value to encode.
+    /// * `schema` - The writer schema.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the datum, or an error message if `value` does not match
+    /// `schema`.
+    pub fn serialize<T: Serialize>(value: &T, schema: &Schema) -> Result<Vec<u8>, String> {
+        let value = apache_avro::to_value(value)
+            .and_then(|value| value.resolve(schema))
+            .map_err(|e| format!("Failed to serialize Avro: {}", e))?;
+        apache_avro::to_avro_datum(schema, value).map_err(|e| format!("Failed to serialize Avro: {}", e))
+    }
+
+    /// Decodes an Avro datum written with `schema`.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The datum.
+    /// * `schema` - The schema it was written with.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the value, or an error message if `data` is not a datum of
+    /// `schema` or not a valid `T`.
+    pub fn deserialize<T: DeserializeOwned>(mut data: &[u8], schema: &Schema) -> Result<T, String> {
+        let value = apache_avro::from_avro_datum(schema, &mut data, None)
+            .map_err(|e| format!("Failed to deserialize Avro: {}", e))?;
+        apache_avro::from_value(&value).map_err(|e| format!("Failed to deserialize Avro: {}", e))
+    }
+
+    /// Encodes `value` in the Confluent wire format.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The value to encode.
+    /// * `schema_id` - The registry ID of `schema`.
+    /// * `schema` - The writer schema.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the framed datum, or an error message if `value` does not match
+    /// `schema`.
+    pub fn serialize_confluent<T: Serialize>(value: &T, schema_id: u32, schema: &Schema) -> Result<Vec<u8>, String> {
+        let datum = Self::serialize(value, schema)?;
+        let mut framed = Vec::with_capacity(5 + datum.len());
+        framed.push(CONFLUENT_MAGIC);
+        framed.extend_from_slice(&schema_id.to_be_bytes());
+        framed.extend_from_slice(&datum);
+        Ok(framed)
+    }
+
+    /// Decodes a message in the Confluent wire format.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The framed datum.
+    /// * `schema` - The schema of the ID in the prefix.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the value, or an error message if the prefix is missing or the
+    /// datum cannot be decoded.
+    pub fn deserialize_confluent<T: DeserializeOwned>(data: &[u8], schema: &Schema) -> Result<T, String> {
+        Self::schema_id(data)?;
+        Self::deserialize(&data[5..], schema)
+    }
+
+    /// Reads the schema ID from a message in the Confluent wire format.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The framed datum.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the schema ID, or an error message if the message is shorter
+    /// than the prefix or does not start with the magic byte.
+    pub fn schema_id(data: &[u8]) -> Result<u32, String> {
+        match data {
+            [CONFLUENT_MAGIC, a, b, c, d, ..] => Ok(u32::from_be_bytes([*a, *b, *c, *d])),
+            [magic, _, _, _, _, ..] => Err(format!("Failed to read Confluent message: unknown magic byte {}", magic)),
+            _ => Err(format!("Failed to read Confluent message: {} bytes is shorter than the prefix", data.len())),
+        }
+    }
+}
+
+/// A client for a Confluent-compatible schema registry, caching the schemas it has seen.
+///
+/// Enabled by the `schema-registry` feature.
+#[cfg(feature = "schema-registry")]
+#[derive(Debug)]
+pub struct SchemaRegistry {
+    url: url::Url,
+    http: reqwest::Client,
+    /// Schemas by ID.
+    schemas: std::sync::Mutex<std::collections::HashMap<u32, std::sync::Arc<Schema>>>,
+    /// IDs by subject and canonical schema.
+    ids: std::sync::Mutex<std::collections::HashMap<(String, String), u32>>,
+}
+
+#[cfg(feature = "schema-registry")]
+impl SchemaRegistry {
+    /// Creates a client for the registry at `url`.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The registry's base URL, e.g. `http://localhost:8081`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the client, or an error message if `url` is not a valid URL.
+    pub fn new(url: &str) -> Result<Self, String> {
+        let url = url::Url::parse(url).map_err(|e| format!("Invalid schema registry URL {}: {}", url, e))?;
+        Ok(SchemaRegistry {
+            url,
+            http: reqwest::Client::new(),
+            schemas: Default::default(),
+            ids: Default::default(),
+        })
+    }
+
+    /// Registers `schema` under `subject`, or looks up its ID if it is already registered.
+    ///
+    /// # Arguments
+    ///
+    /// * `subject` - The subject, e.g. `trades-value` for the values of the `trades` topic.
+    /// * `schema` - The schema to register.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the schema's ID, or an error message if the registry rejects it
+    /// or cannot be reached.
+    pub async fn register(&self, subject: &str, schema: &Schema) -> Result<u32, String> {
+        let key = (subject.to_string(), schema.canonical_form());
+        let registered = self.ids.lock().unwrap().get(&key).copied();
+        if let Some(id) = registered {
+            return Ok(id);
+        }
+        #[derive(serde::Deserialize)]
+        struct Registered {
+            id: u32,
+        }
+        let url = self.endpoint(&["subjects", subject, "versions"])?;
+        let response = self
+            .http
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/vnd.schemaregistry.v1+json")
+            .json(&serde_json::json!({ "schema": key.1 }))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("Failed to register schema for {}: {}", subject, e))?;
+        let registered: Registered = response
+            .json()
+            .await
+            .map_err(|e| format!("Failed to register schema for {}: {}", subject, e))?;
+        self.schemas.lock().unwrap().insert(registered.id, std::sync::Arc::new(schema.clone()));
+        self.ids.lock().unwrap().insert(key, registered.id);
+        Ok(registered.id)
+    }
+
+    /// Returns the schema with the ID `id`, fetching it on first use.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The schema's registry ID.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the schema, or an error message if the registry does not know
+    /// it, cannot be reached or returns an invalid schema.
+    pub async fn schema(&self, id: u32) -> Result<std::sync::Arc<Schema>, String> {
+        let cached = self.schemas.lock().unwrap().get(&id).cloned();
+        if let Some(schema) = cached {
+            return Ok(schema);
+        }
+        #[derive(serde::Deserialize)]
+        struct Fetched {
+            schema: String,
+        }
+        let url = self.endpoint(&["schemas", "ids", &id.to_string()])?;
+        let fetched: Fetched = async { self.http.get(url).send().await?.error_for_status()?.json().await }
+            .await
+            .map_err(|e| format!("Failed to fetch schema {}: {}", id, e))?;
+        let schema = Schema::parse_str(&fetched.schema).map_err(|e| format!("Invalid schema {}: {}", id, e))?;
+        let schema = std::sync::Arc::new(schema);
+        self.schemas.lock().unwrap().insert(id, schema.clone());
+        Ok(schema)
+    }
+
+    /// Encodes `value` in the Confluent wire format with the schema registered under
+    /// `subject`, registering it first if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `subject` - The subject the schema is registered under.
+    /// * `schema` - The writer schema.
+    /// * `value` - The value to encode.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the framed datum, or an error message if registration or
+    /// encoding fails.
+    pub async fn encode<T: Serialize>(&self, subject: &str, schema: &Schema, value: &T) -> Result<Vec<u8>, String> {
+        let id = self.register(subject, schema).await?;
+        AvroHandler::serialize_confluent(value, id, schema)
+    }
+
+    /// Decodes a message in the Confluent wire format with the writer schema named by its
+    /// prefix.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The framed datum, e.g. a binary frame's payload.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the value, or an error message if the schema cannot be fetched
+    /// or the datum cannot be decoded.
+    pub async fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, String> {
+        let schema = self.schema(AvroHandler::schema_id(data)?).await?;
+        AvroHandler::deserialize_confluent(data, &schema)
+    }
+
+    /// Returns the URL of the API path made of `segments`.
+    fn endpoint(&self, segments: &[&str]) -> Result<url::Url, String> {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .map_err(|_| format!("Invalid schema registry URL {}", self.url))?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Order {
+        id: i64,
+        side: String,
+        quantity: Option<i32>,
+    }
+
+    const ORDER_SCHEMA: &str = r#"{"type":"record","name":"Order","fields":[
+        {"name":"id","type":"long"},
+        {"name":"side","type":"string"},
+        {"name":"quantity","type":["null","int"],"default":null}
+    ]}"#;
+
+    /// Tests bare and Confluent-framed round trips and malformed prefixes.
+    #[test]
+    fn test_avro_handler() {
+        let schema = Schema::parse_str(ORDER_SCHEMA).unwrap();
+        let order = Order { id: 7, side: "buy".into(), quantity: Some(3) };
+        let datum = AvroHandler::serialize(&order, &schema).unwrap();
+        assert_eq!(AvroHandler::deserialize::<Order>(&datum, &schema).unwrap(), order);
+
+        let framed = AvroHandler::serialize_confluent(&order, 258, &schema).unwrap();
+        assert_eq!(&framed[..5], &[0, 0, 0, 1, 2]);
+        assert_eq!(&framed[5..], datum.as_slice());
+        assert_eq!(AvroHandler::deserialize_confluent::<Order>(&framed, &schema).unwrap(), order);
+
+        assert!(AvroHandler::schema_id(&[0, 0, 1]).unwrap_err().contains("shorter than the prefix"));
+        assert!(AvroHandler::schema_id(&[1, 0, 0, 0, 1]).unwrap_err().contains("magic byte 1"));
+        assert!(AvroHandler::serialize(&"not an order", &schema).is_err());
+    }
+
+    /// Tests that the registry fetches a writer schema once and decodes with it.
+    #[cfg(feature = "schema-registry")]
+    #[tokio::test]
+    async fn test_schema_registry() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let registry = SchemaRegistry::new(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let n = socket.read(&mut request).await.unwrap();
+            let request = String::from_utf8_lossy(&request[..n]).into_owned();
+            let body = serde_json::json!({ "schema": ORDER_SCHEMA }).to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            request
+        });
+
+        let schema = Schema::parse_str(ORDER_SCHEMA).unwrap();
+        let order = Order { id: 1, side: "sell".into(), quantity: None };
+        let framed = AvroHandler::serialize_confluent(&order, 5, &schema).unwrap();
+        assert_eq!(registry.decode::<Order>(&framed).await.unwrap(), order);
+        assert!(server.await.unwrap().starts_with("GET /schemas/ids/5 "));
+        // Served from the cache; the server is gone.
+        assert_eq!(registry.decode::<Order>(&framed).await.unwrap(), order);
+    }
+}
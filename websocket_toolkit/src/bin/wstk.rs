@@ -0,0 +1,207 @@
+//! # `wstk`: a websocat-style command-line client built on `websocket_toolkit`
+//!
+//! `wstk` connects to a WebSocket server, sends every line read from stdin as a text,
+//! JSON or CBOR message, pretty-prints inbound messages, optionally reconnects when the
+//! connection drops, and can record the whole session to a JSON-lines file.
+//!
+//! Build and run it with the `cli` feature enabled:
+//!
+//! ```bash
+//! cargo run --features cli --bin wstk -- ws://127.0.0.1:9001 --format json --reconnect
+//! ```
+
+use clap::{Parser, ValueEnum};
+use futures_util::SinkExt;
+use log::{error, info};
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::time::{interval, Duration};
+use tokio_tungstenite::tungstenite::Message;
+use websocket_toolkit::connection::WebSocketClient;
+use websocket_toolkit::controller::WebSocketController;
+use websocket_toolkit::messages::{MessageFormat, MessageHandler};
+
+/// The encoding applied to lines read from stdin before they are sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutboundFormat {
+    /// Send each line verbatim as a text frame.
+    Text,
+    /// Parse each line as JSON (or wrap it as a JSON string) and send it as a text frame.
+    Json,
+    /// Parse each line as JSON (or wrap it as a string) and send it CBOR-encoded as a binary frame.
+    Cbor,
+}
+
+/// Command-line arguments of `wstk`.
+#[derive(Debug, Parser)]
+#[command(name = "wstk", version, about = "A WebSocket command-line client built on websocket_toolkit")]
+struct Args {
+    /// The WebSocket server URL, e.g. ws://127.0.0.1:9001.
+    url: String,
+
+    /// The encoding applied to lines read from stdin.
+    #[arg(short, long, value_enum, default_value_t = OutboundFormat::Text)]
+    format: OutboundFormat,
+
+    /// Reconnect automatically when the connection drops.
+    #[arg(short, long)]
+    reconnect: bool,
+
+    /// The number of reconnection attempts before giving up.
+    #[arg(long, default_value_t = 5)]
+    retries: u32,
+
+    /// Send a keep-alive ping every given number of seconds.
+    #[arg(long, value_name = "SECS")]
+    ping: Option<u64>,
+
+    /// Record every sent and received message to this JSON-lines file.
+    #[arg(long, value_name = "FILE")]
+    record: Option<String>,
+}
+
+/// Writes a JSON-lines transcript of a session.
+struct Recorder {
+    writer: BufWriter<File>,
+    started: Instant,
+}
+
+impl Recorder {
+    /// Creates a recorder writing to the given path, truncating any existing file.
+    fn create(path: &str) -> std::io::Result<Self> {
+        Ok(Recorder {
+            writer: BufWriter::new(File::create(path)?),
+            started: Instant::now(),
+        })
+    }
+
+    /// Appends one event to the transcript.
+    fn record(&mut self, direction: &str, payload: &[u8]) {
+        let entry = json!({
+            "elapsed_ms": self.started.elapsed().as_millis() as u64,
+            "direction": direction,
+            "text": std::str::from_utf8(payload).ok(),
+            "bytes": if std::str::from_utf8(payload).is_ok() { None } else { Some(payload) },
+        });
+        if let Err(e) = writeln!(self.writer, "{}", entry).and_then(|_| self.writer.flush()) {
+            error!("Failed to record session event: {}", e);
+        }
+    }
+}
+
+/// Encodes a line read from stdin into the frame sent to the server.
+fn encode_line(line: &str, format: OutboundFormat) -> Result<Message, String> {
+    let value = || serde_json::from_str::<Value>(line).unwrap_or_else(|_| Value::String(line.to_string()));
+    match format {
+        OutboundFormat::Text => Ok(Message::Text(line.to_string())),
+        OutboundFormat::Json => {
+            let bytes = MessageHandler::serialize(&value(), MessageFormat::Json)?;
+            String::from_utf8(bytes).map(Message::Text).map_err(|e| e.to_string())
+        }
+        OutboundFormat::Cbor => MessageHandler::serialize(&value(), MessageFormat::Cbor).map(Message::Binary),
+    }
+}
+
+/// Renders an inbound payload for display, decoding JSON and CBOR when possible.
+///
+/// The codecs are probed directly rather than through `MessageHandler` so that payloads
+/// in other formats do not log deserialization errors.
+fn pretty_print(payload: &[u8]) -> String {
+    if let Ok(value) = serde_json::from_slice::<Value>(payload) {
+        return serde_json::to_string_pretty(&value).unwrap_or_default();
+    }
+    if let Ok(text) = std::str::from_utf8(payload) {
+        return text.to_string();
+    }
+    if let Ok(value) = serde_cbor::from_slice::<Value>(payload) {
+        return format!("(cbor) {}", serde_json::to_string_pretty(&value).unwrap_or_default());
+    }
+    format!("(binary, {} bytes) {:02x?}", payload.len(), payload)
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let args = Args::parse();
+
+    let mut recorder = match args.record.as_deref().map(Recorder::create).transpose() {
+        Ok(recorder) => recorder,
+        Err(e) => {
+            eprintln!("Failed to open record file: {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let client = WebSocketClient::new(&args.url, args.retries);
+    let mut controller = WebSocketController::new(&args.url, args.retries, args.ping);
+    let mut ws_stream = match controller.connect().await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            eprintln!("Failed to connect to {}: {}", args.url, e);
+            std::process::exit(1);
+        }
+    };
+    eprintln!("Connected to {}", args.url);
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdin_open = true;
+    let mut ticker = interval(Duration::from_secs(args.ping.unwrap_or(60).max(1)));
+    ticker.tick().await;
+
+    loop {
+        tokio::select! {
+            line = lines.next_line(), if stdin_open => match line {
+                Ok(Some(line)) => match encode_line(&line, args.format) {
+                    Ok(message) => {
+                        let payload = message.clone().into_data();
+                        if let Err(e) = ws_stream.send(message).await {
+                            eprintln!("Failed to send message: {}", e);
+                        } else if let Some(recorder) = recorder.as_mut() {
+                            recorder.record("out", &payload);
+                        }
+                    }
+                    Err(e) => eprintln!("Failed to encode line: {}", e),
+                },
+                Ok(None) | Err(_) => {
+                    stdin_open = false;
+                    info!("Stdin closed; closing the connection");
+                    let _ = ws_stream.close(None).await;
+                }
+            },
+            _ = ticker.tick(), if args.ping.is_some() => {
+                if let Err(e) = controller.send_ping(&mut ws_stream).await {
+                    error!("Ping failed: {}", e);
+                }
+            },
+            received = controller.receive_message(&mut ws_stream) => match received {
+                Ok(Some(payload)) => {
+                    println!("{}", pretty_print(&payload));
+                    if let Some(recorder) = recorder.as_mut() {
+                        recorder.record("in", &payload);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    eprintln!("Connection lost: {}", e);
+                    if !args.reconnect || !stdin_open {
+                        break;
+                    }
+                    eprintln!("Reconnecting to {} (up to {} attempts)...", args.url, args.retries);
+                    match client.reconnect().await {
+                        Ok(new_stream) => {
+                            ws_stream = new_stream;
+                            eprintln!("Reconnected to {}", args.url);
+                        }
+                        Err(e) => {
+                            eprintln!("Giving up: {}", e);
+                            break;
+                        }
+                    }
+                }
+            },
+        }
+    }
+}
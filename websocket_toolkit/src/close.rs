@@ -0,0 +1,173 @@
+//! # `close.rs`: Interpreting server-initiated closes
+//!
+//! Not every close frame means the connection is finished. RFC 6455's registry defines two
+//! codes that ask the client to come back:
+//!
+//! - `1012` (Service Restart): the server is restarting; reconnect after a short backoff.
+//! - `1013` (Try Again Later): the server is overloaded; reconnect later, ideally after the
+//!   delay it suggests. Servers commonly put that delay in the reason, e.g. `retry after 30`.
+//!
+//! `ServerClosed` is the error the controller returns when the server closes a connection; it
+//! carries the code and reason so callers can tell these apart from a generic failure.
+//! `ClosePolicy` turns a close into a `CloseAction`, reconnecting after 1012 and 1013 by
+//! default. `WebSocketController::receive_or_reconnect` applies it automatically, and
+//! `WebSocketController::set_close_policy` replaces it with an application's own hook.
+
+use std::fmt;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+
+/// The close code for a server restart.
+pub const SERVICE_RESTART: u16 = 1012;
+/// The close code asking the client to try again later.
+pub const TRY_AGAIN_LATER: u16 = 1013;
+
+/// The server closed the connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerClosed {
+    /// The close code, or `None` if the close frame carried none.
+    pub code: Option<u16>,
+    /// The close reason, possibly empty.
+    pub reason: String,
+}
+
+impl ServerClosed {
+    /// Describes a received close frame.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - The frame's payload, if any.
+    ///
+    /// # Returns
+    ///
+    /// A new `ServerClosed`.
+    pub fn new(frame: Option<&CloseFrame<'_>>) -> Self {
+        ServerClosed {
+            code: frame.map(|frame| u16::from(frame.code)),
+            reason: frame.map(|frame| frame.reason.to_string()).unwrap_or_default(),
+        }
+    }
+
+    /// Returns whether the server asked the client to reconnect (1012 or 1013).
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.code, Some(SERVICE_RESTART) | Some(TRY_AGAIN_LATER))
+    }
+
+    /// Returns the delay suggested in the reason: the first whole number in it, in seconds,
+    /// such as the `30` in `retry after 30`.
+    pub fn suggested_delay(&self) -> Option<Duration> {
+        let digits: String = self
+            .reason
+            .chars()
+            .skip_while(|c| !c.is_ascii_digit())
+            .take_while(char::is_ascii_digit)
+            .collect();
+        digits.parse().ok().map(Duration::from_secs)
+    }
+}
+
+impl fmt::Display for ServerClosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "Connection closed by server ({}: {})", code, self.reason),
+            None => write!(f, "Connection closed by server"),
+        }
+    }
+}
+
+impl std::error::Error for ServerClosed {}
+
+/// What to do after the server closed the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseAction {
+    /// Reconnect after the given delay.
+    Reconnect(Duration),
+    /// Return the `ServerClosed` error to the caller.
+    Surface,
+}
+
+/// The default response to server closes: reconnect after 1012 and 1013, surface the rest.
+///
+/// After 1012 the delay doubles from `base_delay` with each consecutive close. After 1013 the
+/// delay suggested in the reason is used if there is one, the same backoff otherwise. Delays
+/// are capped at `max_delay`, and after `max_reconnects` consecutive closes the close is
+/// surfaced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClosePolicy {
+    /// The delay before the first reconnect.
+    pub base_delay: Duration,
+    /// The longest delay, including suggested ones.
+    pub max_delay: Duration,
+    /// The number of consecutive closes answered with a reconnect.
+    pub max_reconnects: u32,
+}
+
+impl Default for ClosePolicy {
+    /// Starts at 1 second, caps at 5 minutes and reconnects up to 10 times in a row.
+    fn default() -> Self {
+        ClosePolicy {
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(300),
+            max_reconnects: 10,
+        }
+    }
+}
+
+impl ClosePolicy {
+    /// Decides how to respond to a close.
+    ///
+    /// # Arguments
+    ///
+    /// * `closed` - The close received.
+    /// * `attempt` - The number of consecutive closes already answered with a reconnect.
+    ///
+    /// # Returns
+    ///
+    /// The `CloseAction` to take.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use websocket_toolkit::close::{CloseAction, ClosePolicy, ServerClosed};
+    ///
+    /// let policy = ClosePolicy::default();
+    /// let overloaded = ServerClosed { code: Some(1013), reason: "retry after 30".into() };
+    /// assert_eq!(policy.decide(&overloaded, 0), CloseAction::Reconnect(Duration::from_secs(30)));
+    /// let normal = ServerClosed { code: Some(1000), reason: String::new() };
+    /// assert_eq!(policy.decide(&normal, 0), CloseAction::Surface);
+    /// ```
+    pub fn decide(&self, closed: &ServerClosed, attempt: u32) -> CloseAction {
+        if !closed.is_retryable() || attempt >= self.max_reconnects {
+            return CloseAction::Surface;
+        }
+        let backoff = self.base_delay.saturating_mul(2_u32.saturating_pow(attempt));
+        let delay = match closed.code {
+            Some(TRY_AGAIN_LATER) => closed.suggested_delay().unwrap_or(backoff),
+            _ => backoff,
+        };
+        CloseAction::Reconnect(delay.min(self.max_delay))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests the default decisions for restarts, overloads and exhausted attempts.
+    #[test]
+    fn test_close_policy() {
+        let policy = ClosePolicy::default();
+        let restart = ServerClosed { code: Some(SERVICE_RESTART), reason: "deploying".to_string() };
+        assert_eq!(policy.decide(&restart, 0), CloseAction::Reconnect(Duration::from_secs(1)));
+        assert_eq!(policy.decide(&restart, 3), CloseAction::Reconnect(Duration::from_secs(8)));
+        assert_eq!(policy.decide(&restart, 20), CloseAction::Surface);
+
+        let busy = ServerClosed { code: Some(TRY_AGAIN_LATER), reason: "busy".to_string() };
+        assert_eq!(policy.decide(&busy, 1), CloseAction::Reconnect(Duration::from_secs(2)));
+        let later = ServerClosed { code: Some(TRY_AGAIN_LATER), reason: "retry after 9000".to_string() };
+        assert_eq!(policy.decide(&later, 0), CloseAction::Reconnect(Duration::from_secs(300)));
+
+        assert_eq!(policy.decide(&ServerClosed::new(None), 0), CloseAction::Surface);
+    }
+}
@@ -0,0 +1,486 @@
+//! # `cluster.rs`: Sharing rooms and presence across server nodes
+//!
+//! A server scaled out behind a load balancer holds each client on one node, but a room's
+//! members are spread over all of them: a message posted on node A must reach the members
+//! connected to node B, and "who is in this room" must count both. `ClusterHub` shares that
+//! state through a `ClusterBus`, a minimal publish/subscribe interface, so every node sees
+//! every room message and every join and leave.
+//!
+//! Two buses are provided:
+//!
+//! - `MemoryBus` connects hubs within one process, for tests and single-node deployments.
+//! - `RedisBus` uses Redis pub/sub. Enabled by the `cluster-redis` feature.
+//!
+//! Other brokers, such as NATS or Postgres `LISTEN`/`NOTIFY`, plug in by implementing
+//! `ClusterBus`.
+//!
+//! A room message posted on any node, including this one, is delivered to the hub's local
+//! subscribers of the room (`ClusterHub::subscribe`) once it comes back from the bus, so all
+//! nodes see a room's messages in the bus's order. When a hub starts it asks the other nodes
+//! to announce their members again, so a node that joins late knows the existing presence.
+//! Pub/sub buses do not store messages: a member of a node that dies without leaving stays
+//! listed until `ClusterHub::forget_node` is called for it, e.g. by a health check.
+
+use crate::messages::{Envelope, MessageFormat, MessageHandler};
+use crate::tasks::spawn_named;
+use async_trait::async_trait;
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+/// The bus channel the hubs of a cluster share.
+pub const CLUSTER_CHANNEL: &str = "websocket_toolkit:cluster";
+
+/// The number of messages buffered for each lagging subscriber.
+const ROOM_CAPACITY: usize = 1024;
+
+/// A publish/subscribe channel between the nodes of a cluster.
+///
+/// Every payload published on a channel must reach every subscription to that channel,
+/// including those of the publishing node.
+#[async_trait]
+pub trait ClusterBus: Send + Sync {
+    /// Publishes `payload` on `channel`.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The channel name.
+    /// * `payload` - The bytes to publish.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message on failure.
+    async fn publish(&self, channel: &str, payload: Vec<u8>) -> Result<(), String>;
+
+    /// Subscribes to `channel`.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The channel name.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the stream of payloads published from now on, ending when the
+    /// bus disconnects, or an error message on failure.
+    async fn subscribe(&self, channel: &str) -> Result<BoxStream<'static, Vec<u8>>, String>;
+}
+
+/// A `ClusterBus` connecting the hubs of one process.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBus {
+    channels: Arc<Mutex<HashMap<String, broadcast::Sender<Vec<u8>>>>>,
+}
+
+impl MemoryBus {
+    /// Creates a bus with no channels.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn channel(&self, channel: &str) -> broadcast::Sender<Vec<u8>> {
+        let mut channels = self.channels.lock().unwrap();
+        channels.entry(channel.to_string()).or_insert_with(|| broadcast::channel(ROOM_CAPACITY).0).clone()
+    }
+}
+
+#[async_trait]
+impl ClusterBus for MemoryBus {
+    async fn publish(&self, channel: &str, payload: Vec<u8>) -> Result<(), String> {
+        // No subscriber is not an error, as with Redis.
+        let _ = self.channel(channel).send(payload);
+        Ok(())
+    }
+
+    async fn subscribe(&self, channel: &str) -> Result<BoxStream<'static, Vec<u8>>, String> {
+        let receiver = self.channel(channel).subscribe();
+        Ok(Box::pin(futures_util::stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(payload) => return Some((payload, receiver)),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => warn!("Cluster bus subscriber missed {} messages", missed),
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })))
+    }
+}
+
+/// A `ClusterBus` over Redis pub/sub.
+///
+/// Enabled by the `cluster-redis` feature.
+#[cfg(feature = "cluster-redis")]
+#[derive(Clone)]
+pub struct RedisBus {
+    client: redis::Client,
+    /// The connection used for publishing, opened on first use.
+    publisher: Arc<tokio::sync::Mutex<Option<redis::aio::MultiplexedConnection>>>,
+}
+
+#[cfg(feature = "cluster-redis")]
+impl RedisBus {
+    /// Creates a bus on the Redis server at `url`.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The server URL, e.g. `redis://127.0.0.1:6379`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the bus, or an error message if the URL is invalid.
+    pub fn open(url: &str) -> Result<Self, String> {
+        let client = redis::Client::open(url).map_err(|e| format!("Failed to open Redis client: {}", e))?;
+        Ok(RedisBus {
+            client,
+            publisher: Default::default(),
+        })
+    }
+}
+
+#[cfg(feature = "cluster-redis")]
+#[async_trait]
+impl ClusterBus for RedisBus {
+    async fn publish(&self, channel: &str, payload: Vec<u8>) -> Result<(), String> {
+        use redis::AsyncCommands;
+
+        let mut publisher = self.publisher.lock().await;
+        if publisher.is_none() {
+            let connection = self
+                .client
+                .get_multiplexed_tokio_connection()
+                .await
+                .map_err(|e| format!("Failed to connect to Redis: {}", e))?;
+            *publisher = Some(connection);
+        }
+        let connection = publisher.as_mut().expect("connected above");
+        let published: Result<(), _> = connection.publish(channel, payload).await;
+        if let Err(e) = published {
+            // Reconnect on the next publish.
+            *publisher = None;
+            return Err(format!("Failed to publish to Redis: {}", e));
+        }
+        Ok(())
+    }
+
+    async fn subscribe(&self, channel: &str) -> Result<BoxStream<'static, Vec<u8>>, String> {
+        let mut pubsub = self
+            .client
+            .get_async_pubsub()
+            .await
+            .map_err(|e| format!("Failed to connect to Redis: {}", e))?;
+        pubsub
+            .subscribe(channel)
+            .await
+            .map_err(|e| format!("Failed to subscribe to Redis channel {}: {}", channel, e))?;
+        Ok(Box::pin(pubsub.into_on_message().map(|message| message.get_payload_bytes().to_vec())))
+    }
+}
+
+/// What the hubs of a cluster tell each other.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum ClusterEvent {
+    /// A message posted to a room.
+    Message { room: String, envelope: Envelope },
+    /// A member joined a room on the sending node.
+    Joined { room: String, member: String },
+    /// A member left a room on the sending node.
+    Left { room: String, member: String },
+    /// A node started and asks the others to announce their members.
+    Hello,
+}
+
+/// A `ClusterEvent` with the node that sent it.
+#[derive(Debug, Serialize, Deserialize)]
+struct ClusterFrame {
+    node: String,
+    event: ClusterEvent,
+}
+
+/// The hub state shared with the bus task.
+#[derive(Debug, Default)]
+struct HubState {
+    /// The members of every room, with the node each is connected to.
+    presence: HashMap<String, HashMap<String, String>>,
+    /// The members connected to this node, re-announced to nodes that start later.
+    local: HashMap<String, HashSet<String>>,
+    /// The local subscribers of every room.
+    rooms: HashMap<String, broadcast::Sender<Envelope>>,
+}
+
+/// One node's view of the rooms and presence shared across a cluster.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use websocket_toolkit::cluster::{ClusterHub, MemoryBus};
+/// use websocket_toolkit::messages::{Envelope, MessageFormat};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let bus = Arc::new(MemoryBus::new());
+/// let a = ClusterHub::start(bus.clone(), "node-a", MessageFormat::Cbor).await.unwrap();
+/// let b = ClusterHub::start(bus, "node-b", MessageFormat::Cbor).await.unwrap();
+///
+/// let mut lobby = b.subscribe("lobby");
+/// a.join("lobby", "ada").await.unwrap();
+/// a.broadcast("lobby", Envelope::new("chat", b"hi".to_vec())).await.unwrap();
+/// assert_eq!(lobby.recv().await.unwrap().payload, b"hi");
+/// assert_eq!(b.presence("lobby"), vec!["ada".to_string()]);
+/// # }
+/// ```
+pub struct ClusterHub {
+    node: String,
+    bus: Arc<dyn ClusterBus>,
+    format: MessageFormat,
+    state: Arc<Mutex<HubState>>,
+    task: JoinHandle<()>,
+}
+
+impl ClusterHub {
+    /// Subscribes to the cluster channel and announces this node.
+    ///
+    /// Must be called within a tokio runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `bus` - The bus shared by the cluster.
+    /// * `node` - This node's id, unique within the cluster.
+    /// * `format` - The format events travel in; the same on every node.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the running hub, or an error message if the bus fails.
+    pub async fn start(bus: Arc<dyn ClusterBus>, node: impl Into<String>, format: MessageFormat) -> Result<Self, String> {
+        let node = node.into();
+        let events = bus.subscribe(CLUSTER_CHANNEL).await?;
+        let state = Arc::new(Mutex::new(HubState::default()));
+        let task = spawn_named(
+            "websocket_toolkit::cluster",
+            receive(events, bus.clone(), node.clone(), format, state.clone()),
+        );
+        let hub = ClusterHub { node, bus, format, state, task };
+        hub.publish(ClusterEvent::Hello).await?;
+        Ok(hub)
+    }
+
+    /// Returns this node's id.
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+
+    /// Posts `envelope` to `room` on every node.
+    ///
+    /// # Arguments
+    ///
+    /// * `room` - The room.
+    /// * `envelope` - The message.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if the bus fails.
+    pub async fn broadcast(&self, room: &str, envelope: Envelope) -> Result<(), String> {
+        self.publish(ClusterEvent::Message { room: room.to_string(), envelope }).await
+    }
+
+    /// Subscribes to the messages posted to `room` on any node from now on.
+    ///
+    /// # Arguments
+    ///
+    /// * `room` - The room.
+    ///
+    /// # Returns
+    ///
+    /// A `broadcast::Receiver`, e.g. for each local member's connection task.
+    pub fn subscribe(&self, room: &str) -> broadcast::Receiver<Envelope> {
+        let mut state = self.state.lock().unwrap();
+        state.rooms.entry(room.to_string()).or_insert_with(|| broadcast::channel(ROOM_CAPACITY).0).subscribe()
+    }
+
+    /// Records that `member`, connected to this node, joined `room`, and tells the cluster.
+    ///
+    /// # Arguments
+    ///
+    /// * `room` - The room.
+    /// * `member` - The member's id.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if the bus fails.
+    pub async fn join(&self, room: &str, member: &str) -> Result<(), String> {
+        self.state.lock().unwrap().local.entry(room.to_string()).or_default().insert(member.to_string());
+        self.publish(ClusterEvent::Joined { room: room.to_string(), member: member.to_string() }).await
+    }
+
+    /// Records that `member` left `room`, and tells the cluster.
+    ///
+    /// # Arguments
+    ///
+    /// * `room` - The room.
+    /// * `member` - The member's id.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if the bus fails.
+    pub async fn leave(&self, room: &str, member: &str) -> Result<(), String> {
+        if let Some(members) = self.state.lock().unwrap().local.get_mut(room) {
+            members.remove(member);
+        }
+        self.publish(ClusterEvent::Left { room: room.to_string(), member: member.to_string() }).await
+    }
+
+    /// Returns the members of `room` on every node, sorted.
+    ///
+    /// # Arguments
+    ///
+    /// * `room` - The room.
+    pub fn presence(&self, room: &str) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        let members: BTreeSet<&String> = state.presence.get(room).map(|members| members.keys().collect()).unwrap_or_default();
+        members.into_iter().cloned().collect()
+    }
+
+    /// Removes every member of `node` from the presence, e.g. after it crashed.
+    ///
+    /// # Arguments
+    ///
+    /// * `node` - The id of the node that is gone.
+    pub fn forget_node(&self, node: &str) {
+        let mut state = self.state.lock().unwrap();
+        for members in state.presence.values_mut() {
+            members.retain(|_, member_node| member_node != node);
+        }
+        state.presence.retain(|_, members| !members.is_empty());
+    }
+
+    async fn publish(&self, event: ClusterEvent) -> Result<(), String> {
+        publish(self.bus.as_ref(), &self.node, self.format, event).await
+    }
+}
+
+impl fmt::Debug for ClusterHub {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClusterHub")
+            .field("node", &self.node)
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+impl Drop for ClusterHub {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Encodes and publishes an event from `node`.
+async fn publish(bus: &dyn ClusterBus, node: &str, format: MessageFormat, event: ClusterEvent) -> Result<(), String> {
+    let frame = ClusterFrame { node: node.to_string(), event };
+    let payload = MessageHandler::serialize(&frame, format)?;
+    bus.publish(CLUSTER_CHANNEL, payload).await
+}
+
+/// Applies the cluster's events to the hub state until the bus disconnects.
+async fn receive(
+    mut events: BoxStream<'static, Vec<u8>>,
+    bus: Arc<dyn ClusterBus>,
+    node: String,
+    format: MessageFormat,
+    state: Arc<Mutex<HubState>>,
+) {
+    while let Some(payload) = events.next().await {
+        let frame = match MessageHandler::deserialize::<ClusterFrame>(&payload, format) {
+            Ok(Some(frame)) => frame,
+            _ => {
+                warn!("Ignoring an undecodable cluster event");
+                continue;
+            }
+        };
+        let announce = {
+            let mut state = state.lock().unwrap();
+            match frame.event {
+                ClusterEvent::Message { room, envelope } => {
+                    if let Some(room) = state.rooms.get(&room) {
+                        // No local subscriber is not an error.
+                        let _ = room.send(envelope);
+                    }
+                    Vec::new()
+                }
+                ClusterEvent::Joined { room, member } => {
+                    state.presence.entry(room).or_default().insert(member, frame.node);
+                    Vec::new()
+                }
+                ClusterEvent::Left { room, member } => {
+                    if let Some(members) = state.presence.get_mut(&room) {
+                        members.remove(&member);
+                        if members.is_empty() {
+                            state.presence.remove(&room);
+                        }
+                    }
+                    Vec::new()
+                }
+                ClusterEvent::Hello if frame.node != node => {
+                    debug!("Node {} joined the cluster", frame.node);
+                    state
+                        .local
+                        .iter()
+                        .flat_map(|(room, members)| members.iter().map(move |member| (room.clone(), member.clone())))
+                        .collect()
+                }
+                ClusterEvent::Hello => Vec::new(),
+            }
+        };
+        for (room, member) in announce {
+            if let Err(e) = publish(bus.as_ref(), &node, format, ClusterEvent::Joined { room, member }).await {
+                warn!("{}", e);
+            }
+        }
+    }
+    warn!("Cluster bus disconnected");
+}
+
+#[cfg(all(test, feature = "cbor"))]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Tests that messages and presence cross nodes, a late node learns the existing members
+    /// and a forgotten node's members disappear.
+    #[tokio::test]
+    async fn test_cluster_hub() {
+        let bus = Arc::new(MemoryBus::new());
+        let a = ClusterHub::start(bus.clone(), "a", MessageFormat::Cbor).await.unwrap();
+        let b = ClusterHub::start(bus.clone(), "b", MessageFormat::Cbor).await.unwrap();
+        let mut on_a = a.subscribe("lobby");
+        let mut on_b = b.subscribe("lobby");
+
+        b.broadcast("lobby", Envelope::new("chat", b"hi".to_vec())).await.unwrap();
+        assert_eq!(on_a.recv().await.unwrap().payload, b"hi");
+        assert_eq!(on_b.recv().await.unwrap().payload, b"hi");
+
+        a.join("lobby", "ada").await.unwrap();
+        b.join("lobby", "bob").await.unwrap();
+        b.join("lobby", "eve").await.unwrap();
+        b.leave("lobby", "eve").await.unwrap();
+        // Presence is applied by the bus task; a round trip of this node's own event orders it.
+        b.broadcast("lobby", Envelope::new("sync", Vec::new())).await.unwrap();
+        on_a.recv().await.unwrap();
+        assert_eq!(a.presence("lobby"), ["ada", "bob"]);
+
+        let c = ClusterHub::start(bus, "c", MessageFormat::Cbor).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while c.presence("lobby").len() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Expected the late node to learn the members");
+        c.forget_node("b");
+        assert_eq!(c.presence("lobby"), ["ada"]);
+    }
+}
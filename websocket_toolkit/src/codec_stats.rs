@@ -0,0 +1,281 @@
+//! # `codec_stats.rs`: Codec and compression statistics
+//!
+//! Whether CBOR beats JSON, or deflate pays for its CPU time, depends on the messages an
+//! application actually sends. `CodecStats` measures it on live traffic: every envelope the
+//! controller encodes or decodes (`encode_envelope`, `decode_envelope` and the methods built
+//! on them) records how long serialization took per format, how much compression shrank the
+//! payload and how long that took, and the payload's size on the wire.
+//!
+//! `WebSocketController::codec_stats` returns a `CodecSnapshot` of the current connection;
+//! the statistics restart with every connection. Size percentiles are computed over the
+//! last `SIZE_WINDOW` messages in each direction, so they follow changes in traffic.
+
+use crate::messages::MessageFormat;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The number of recent messages per direction that size percentiles are computed over.
+pub const SIZE_WINDOW: usize = 1024;
+
+/// Serialization counts and times of one format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FormatStats {
+    /// The number of envelopes encoded.
+    pub encoded: u64,
+    /// The total time spent encoding.
+    pub encode_time: Duration,
+    /// The number of envelopes decoded.
+    pub decoded: u64,
+    /// The total time spent decoding.
+    pub decode_time: Duration,
+}
+
+impl FormatStats {
+    /// Returns the average time to encode an envelope, or `None` before the first.
+    pub fn average_encode(&self) -> Option<Duration> {
+        average(self.encode_time, self.encoded)
+    }
+
+    /// Returns the average time to decode an envelope, or `None` before the first.
+    pub fn average_decode(&self) -> Option<Duration> {
+        average(self.decode_time, self.decoded)
+    }
+}
+
+/// How much compression saved in one direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompressionStats {
+    /// The number of payloads compressed or decompressed.
+    pub messages: u64,
+    /// The serialized size of those payloads before compression.
+    pub raw_bytes: u64,
+    /// Their size on the wire.
+    pub wire_bytes: u64,
+    /// The total time spent compressing or decompressing.
+    pub time: Duration,
+}
+
+impl CompressionStats {
+    /// Returns the wire size as a fraction of the raw size: 0.25 means compression saved
+    /// 75%, above 1.0 means it cost bytes. `None` before the first payload.
+    pub fn ratio(&self) -> Option<f64> {
+        (self.raw_bytes > 0).then(|| self.wire_bytes as f64 / self.raw_bytes as f64)
+    }
+}
+
+/// Percentiles of recent wire sizes, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SizePercentiles {
+    /// The number of messages the percentiles cover.
+    pub samples: usize,
+    /// The median size.
+    pub p50: usize,
+    /// The 90th percentile.
+    pub p90: usize,
+    /// The 99th percentile.
+    pub p99: usize,
+    /// The largest size.
+    pub max: usize,
+}
+
+impl SizePercentiles {
+    fn of(sizes: &VecDeque<usize>) -> Self {
+        let mut sorted: Vec<usize> = sizes.iter().copied().collect();
+        sorted.sort_unstable();
+        let at = |percentile: usize| match sorted.len() {
+            0 => 0,
+            len => sorted[(len * percentile / 100).min(len - 1)],
+        };
+        SizePercentiles {
+            samples: sorted.len(),
+            p50: at(50),
+            p90: at(90),
+            p99: at(99),
+            max: sorted.last().copied().unwrap_or(0),
+        }
+    }
+}
+
+/// The codec statistics of a connection at one moment.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CodecSnapshot {
+    /// The JSON serialization statistics.
+    pub json: FormatStats,
+    /// The CBOR serialization statistics.
+    pub cbor: FormatStats,
+    /// Compression of sent payloads.
+    pub compressed: CompressionStats,
+    /// Decompression of received payloads.
+    pub decompressed: CompressionStats,
+    /// The wire sizes of recently sent payloads.
+    pub sent_sizes: SizePercentiles,
+    /// The wire sizes of recently received payloads.
+    pub received_sizes: SizePercentiles,
+}
+
+impl CodecSnapshot {
+    /// Returns the statistics of `format`.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The message format.
+    pub fn format(&self, format: MessageFormat) -> &FormatStats {
+        match format {
+            MessageFormat::Json => &self.json,
+            MessageFormat::Cbor => &self.cbor,
+        }
+    }
+}
+
+/// Collected statistics, behind the lock.
+#[derive(Debug, Default)]
+struct Collected {
+    json: FormatStats,
+    cbor: FormatStats,
+    compressed: CompressionStats,
+    decompressed: CompressionStats,
+    sent_sizes: VecDeque<usize>,
+    received_sizes: VecDeque<usize>,
+}
+
+impl Collected {
+    fn format(&mut self, format: MessageFormat) -> &mut FormatStats {
+        match format {
+            MessageFormat::Json => &mut self.json,
+            MessageFormat::Cbor => &mut self.cbor,
+        }
+    }
+}
+
+/// The timing and size of one encoded or decoded payload.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CodecSample {
+    pub format: MessageFormat,
+    /// The time spent serializing or deserializing.
+    pub codec_time: Duration,
+    /// The serialized size, and the time spent compressing or decompressing, if compression
+    /// applied.
+    pub compression: Option<(usize, Duration)>,
+    /// The size on the wire.
+    pub wire_bytes: usize,
+}
+
+/// Codec statistics collected by a controller.
+#[derive(Debug, Default)]
+pub struct CodecStats {
+    collected: Mutex<Collected>,
+}
+
+impl CodecStats {
+    /// Records an encoded envelope.
+    pub(crate) fn record_encode(&self, sample: CodecSample) {
+        let mut collected = self.collected.lock().unwrap();
+        let stats = collected.format(sample.format);
+        stats.encoded += 1;
+        stats.encode_time += sample.codec_time;
+        if let Some((raw_bytes, time)) = sample.compression {
+            add_compression(&mut collected.compressed, raw_bytes, sample.wire_bytes, time);
+        }
+        push_size(&mut collected.sent_sizes, sample.wire_bytes);
+    }
+
+    /// Records a decoded envelope.
+    pub(crate) fn record_decode(&self, sample: CodecSample) {
+        let mut collected = self.collected.lock().unwrap();
+        let stats = collected.format(sample.format);
+        stats.decoded += 1;
+        stats.decode_time += sample.codec_time;
+        if let Some((raw_bytes, time)) = sample.compression {
+            add_compression(&mut collected.decompressed, raw_bytes, sample.wire_bytes, time);
+        }
+        push_size(&mut collected.received_sizes, sample.wire_bytes);
+    }
+
+    /// Forgets everything recorded, e.g. when a new connection starts.
+    pub fn reset(&self) {
+        *self.collected.lock().unwrap() = Collected::default();
+    }
+
+    /// Returns the statistics recorded so far.
+    pub fn snapshot(&self) -> CodecSnapshot {
+        let collected = self.collected.lock().unwrap();
+        CodecSnapshot {
+            json: collected.json,
+            cbor: collected.cbor,
+            compressed: collected.compressed,
+            decompressed: collected.decompressed,
+            sent_sizes: SizePercentiles::of(&collected.sent_sizes),
+            received_sizes: SizePercentiles::of(&collected.received_sizes),
+        }
+    }
+}
+
+fn add_compression(stats: &mut CompressionStats, raw_bytes: usize, wire_bytes: usize, time: Duration) {
+    stats.messages += 1;
+    stats.raw_bytes += raw_bytes as u64;
+    stats.wire_bytes += wire_bytes as u64;
+    stats.time += time;
+}
+
+fn push_size(sizes: &mut VecDeque<usize>, size: usize) {
+    if sizes.len() == SIZE_WINDOW {
+        sizes.pop_front();
+    }
+    sizes.push_back(size);
+}
+
+fn average(total: Duration, count: u64) -> Option<Duration> {
+    (count > 0).then(|| total / u32::try_from(count).unwrap_or(u32::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests averages, the compression ratio and size percentiles over the window.
+    #[test]
+    fn test_codec_stats() {
+        let stats = CodecStats::default();
+        let millis = Duration::from_millis;
+        for size in 1..=100 {
+            stats.record_encode(CodecSample {
+                format: MessageFormat::Cbor,
+                codec_time: millis(2),
+                compression: Some((size * 4, millis(1))),
+                wire_bytes: size,
+            });
+        }
+        stats.record_decode(CodecSample {
+            format: MessageFormat::Json,
+            codec_time: millis(3),
+            compression: None,
+            wire_bytes: 10,
+        });
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.format(MessageFormat::Cbor).average_encode(), Some(millis(2)));
+        assert_eq!(snapshot.json.average_decode(), Some(millis(3)));
+        assert_eq!(snapshot.json.average_encode(), None);
+        assert_eq!(snapshot.compressed.ratio(), Some(0.25));
+        assert_eq!(snapshot.compressed.time, millis(100));
+        assert_eq!(snapshot.decompressed.ratio(), None);
+        assert_eq!(
+            snapshot.sent_sizes,
+            SizePercentiles { samples: 100, p50: 51, p90: 91, p99: 100, max: 100 }
+        );
+        assert_eq!(snapshot.received_sizes.max, 10);
+
+        for _ in 0..SIZE_WINDOW {
+            stats.record_encode(CodecSample {
+                format: MessageFormat::Cbor,
+                codec_time: Duration::ZERO,
+                compression: None,
+                wire_bytes: 7,
+            });
+        }
+        assert_eq!(stats.snapshot().sent_sizes.max, 7);
+        stats.reset();
+        assert_eq!(stats.snapshot(), CodecSnapshot::default());
+    }
+}
@@ -0,0 +1,437 @@
+//! # `compression.rs`: Application-level payload compression
+//!
+//! On metered or narrow links (cellular, NB-IoT) every byte counts, and many servers do not
+//! negotiate `permessage-deflate`. `Compression` compresses encoded payloads before they are
+//! sent and decompresses them after they are received, independently of the WebSocket
+//! extensions in use. Deflate is provided by the `compression` feature; using it without the
+//! feature returns an error naming the missing feature, like the message codecs do.
+//!
+//! Behind a load balancer, some servers of a fleet may negotiate `permessage-deflate` and
+//! others not. `Encoding::negotiate` picks one encoding per connection: the extension when the
+//! server accepted it, since the transport then compresses every frame and compressing again
+//! would only cost CPU, and application-level compression otherwise. `Encoding::annotate`
+//! prefixes each payload with a one-byte tag naming the encoding applied, and
+//! `Encoding::read_annotated` undoes whatever the tag says, so peers decode correctly no
+//! matter which server or client encoded a message. A payload that does not start with a
+//! known tag is taken as sent untagged by a peer without compression; JSON and CBOR envelopes
+//! never start with a tag byte.
+//!
+//! Decompression is bounded by `DecompressionLimits`: an absolute cap on the decompressed
+//! size (16 MiB by default) and optionally a maximum expansion ratio, so a 1 KB frame cannot
+//! expand into gigabytes. Decoding stops as soon as a limit is exceeded, with
+//! `DecompressionError::LimitExceeded`. `permessage-deflate` is decompressed by the
+//! transport, not here; its frames are bounded by the socket's maximum message size.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The default cap on a decompressed payload: 16 MiB.
+pub const DEFAULT_MAX_DECOMPRESSED_BYTES: usize = 16 * 1024 * 1024;
+
+/// Bounds on how far a received payload may expand when decompressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DecompressionLimits {
+    /// The most bytes a payload may decompress to, or `None` for no cap.
+    pub max_bytes: Option<usize>,
+    /// The most a payload may expand relative to its compressed size, e.g. `100` for 100:1,
+    /// or `None` for no ratio limit.
+    pub max_ratio: Option<u32>,
+}
+
+impl Default for DecompressionLimits {
+    /// A 16 MiB cap and no ratio limit.
+    fn default() -> Self {
+        DecompressionLimits { max_bytes: Some(DEFAULT_MAX_DECOMPRESSED_BYTES), max_ratio: None }
+    }
+}
+
+impl DecompressionLimits {
+    /// Returns limits that allow any expansion.
+    pub fn unlimited() -> Self {
+        DecompressionLimits { max_bytes: None, max_ratio: None }
+    }
+
+    /// Returns the most bytes a payload of `compressed` bytes may decompress to.
+    pub fn limit_for(&self, compressed: usize) -> Option<usize> {
+        let by_ratio = self.max_ratio.map(|ratio| compressed.saturating_mul(ratio as usize));
+        match (self.max_bytes, by_ratio) {
+            (Some(max), Some(by_ratio)) => Some(max.min(by_ratio)),
+            (max, by_ratio) => max.or(by_ratio),
+        }
+    }
+}
+
+/// Why a payload could not be decompressed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecompressionError {
+    /// The payload expanded beyond the limits; decoding stopped at the limit.
+    LimitExceeded {
+        /// The compressed size.
+        compressed: usize,
+        /// The limit that was exceeded.
+        limit: usize,
+    },
+    /// The payload is not valid compressed data.
+    Corrupt(String),
+    /// The algorithm's feature is disabled.
+    Unsupported(String),
+}
+
+impl fmt::Display for DecompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecompressionError::LimitExceeded { compressed, limit } => write!(
+                f,
+                "Failed to decompress payload: {} compressed bytes expand beyond the limit of {} bytes",
+                compressed, limit
+            ),
+            DecompressionError::Corrupt(e) => write!(f, "Failed to decompress payload: {}", e),
+            DecompressionError::Unsupported(e) => f.write_str(e),
+        }
+    }
+}
+
+impl std::error::Error for DecompressionError {}
+
+impl From<DecompressionError> for String {
+    fn from(error: DecompressionError) -> Self {
+        error.to_string()
+    }
+}
+
+/// A payload compression algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// Payloads are sent as they are.
+    #[default]
+    None,
+    /// Raw deflate (RFC 1951), the algorithm behind `permessage-deflate`.
+    Deflate,
+}
+
+impl Compression {
+    /// Compresses `payload`.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The encoded message.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the compressed bytes, or an error message if compression fails
+    /// or the algorithm's feature is disabled.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit::compression::Compression;
+    ///
+    /// # #[cfg(feature = "compression")]
+    /// # {
+    /// let payload = b"temperature=21.5;temperature=21.5;temperature=21.5".to_vec();
+    /// let compressed = Compression::Deflate.compress(&payload).unwrap();
+    /// assert!(compressed.len() < payload.len());
+    /// assert_eq!(Compression::Deflate.decompress(&compressed).unwrap(), payload);
+    /// # }
+    /// ```
+    pub fn compress(self, payload: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Compression::None => Ok(payload.to_vec()),
+            #[cfg(feature = "compression")]
+            Compression::Deflate => {
+                use std::io::Write;
+                let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder
+                    .write_all(payload)
+                    .and_then(|_| encoder.finish())
+                    .map_err(|e| format!("Failed to compress payload: {}", e))
+            }
+            #[cfg(not(feature = "compression"))]
+            Compression::Deflate => Err("Deflate compression requires the `compression` feature".to_string()),
+        }
+    }
+
+    /// Decompresses `payload` within the default `DecompressionLimits`.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The received bytes.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the decompressed bytes, or an error message if the payload is
+    /// corrupt, expands beyond 16 MiB or the algorithm's feature is disabled.
+    pub fn decompress(self, payload: &[u8]) -> Result<Vec<u8>, String> {
+        Ok(self.decompress_limited(payload, DecompressionLimits::default())?)
+    }
+
+    /// Decompresses `payload`, stopping as soon as it expands beyond `limits`.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The received bytes.
+    /// * `limits` - The cap and ratio the decompressed size must stay within.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the decompressed bytes, or a `DecompressionError`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit::compression::{Compression, DecompressionError, DecompressionLimits};
+    ///
+    /// # #[cfg(feature = "compression")]
+    /// # {
+    /// let bomb = Compression::Deflate.compress(&vec![0; 1024 * 1024]).unwrap();
+    /// let limits = DecompressionLimits { max_bytes: Some(64 * 1024), max_ratio: Some(100) };
+    /// assert!(matches!(
+    ///     Compression::Deflate.decompress_limited(&bomb, limits),
+    ///     Err(DecompressionError::LimitExceeded { .. })
+    /// ));
+    /// # }
+    /// ```
+    pub fn decompress_limited(self, payload: &[u8], limits: DecompressionLimits) -> Result<Vec<u8>, DecompressionError> {
+        match self {
+            Compression::None => Ok(payload.to_vec()),
+            #[cfg(feature = "compression")]
+            Compression::Deflate => {
+                use std::io::Read;
+                let limit = limits.limit_for(payload.len());
+                let mut decompressed = Vec::new();
+                // One byte past the limit tells an exact fit from an overflow.
+                let cap = limit.map_or(u64::MAX, |limit| (limit as u64).saturating_add(1));
+                flate2::read::DeflateDecoder::new(payload)
+                    .take(cap)
+                    .read_to_end(&mut decompressed)
+                    .map_err(|e| DecompressionError::Corrupt(e.to_string()))?;
+                match limit {
+                    Some(limit) if decompressed.len() > limit => {
+                        Err(DecompressionError::LimitExceeded { compressed: payload.len(), limit })
+                    }
+                    _ => Ok(decompressed),
+                }
+            }
+            #[cfg(not(feature = "compression"))]
+            Compression::Deflate => Err(DecompressionError::Unsupported(
+                "Deflate compression requires the `compression` feature".to_string(),
+            )),
+        }
+    }
+}
+
+/// The encoding applied to a message on one connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    /// Not compressed.
+    Identity,
+    /// Compressed by the negotiated `permessage-deflate` extension; the payload itself is
+    /// sent uncompressed.
+    PermessageDeflate,
+    /// Compressed by the application with the given algorithm.
+    Application(Compression),
+}
+
+impl Encoding {
+    /// Chooses the encoding for a connection.
+    ///
+    /// | Preferred | `permessage-deflate` negotiated | Encoding |
+    /// |-----------|---------------------------------|----------|
+    /// | `None` | either | `Identity` |
+    /// | `Deflate` | yes | `PermessageDeflate` |
+    /// | `Deflate` | no | `Application(Deflate)` |
+    ///
+    /// # Arguments
+    ///
+    /// * `preferred` - The configured compression.
+    /// * `extensions` - The extensions the server accepted, e.g. from `ConnectionInfo`.
+    ///
+    /// # Returns
+    ///
+    /// The encoding to use.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit::compression::{Compression, Encoding};
+    ///
+    /// let negotiated = vec!["permessage-deflate; client_no_context_takeover".to_string()];
+    /// assert_eq!(Encoding::negotiate(Compression::Deflate, &negotiated), Encoding::PermessageDeflate);
+    /// assert_eq!(Encoding::negotiate(Compression::Deflate, &[]), Encoding::Application(Compression::Deflate));
+    /// ```
+    pub fn negotiate(preferred: Compression, extensions: &[String]) -> Encoding {
+        let deflate_negotiated = extensions.iter().flat_map(|header| header.split(',')).any(|extension| {
+            let name = extension.split(';').next().unwrap_or_default().trim();
+            name.eq_ignore_ascii_case("permessage-deflate")
+        });
+        match preferred {
+            Compression::None => Encoding::Identity,
+            _ if deflate_negotiated => Encoding::PermessageDeflate,
+            algorithm => Encoding::Application(algorithm),
+        }
+    }
+
+    /// Returns the tag byte identifying this encoding in annotated payloads.
+    fn tag(self) -> u8 {
+        match self {
+            Encoding::Identity | Encoding::Application(Compression::None) => b'i',
+            Encoding::PermessageDeflate => b'p',
+            Encoding::Application(Compression::Deflate) => b'd',
+        }
+    }
+
+    /// Applies the encoding to `payload` and prefixes the result with the encoding's tag.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The encoded message.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the annotated payload, or an error message if compression fails.
+    pub fn annotate(self, payload: &[u8]) -> Result<Vec<u8>, String> {
+        let body = match self {
+            Encoding::Application(algorithm) => algorithm.compress(payload)?,
+            Encoding::Identity | Encoding::PermessageDeflate => payload.to_vec(),
+        };
+        let mut annotated = Vec::with_capacity(body.len() + 1);
+        annotated.push(self.tag());
+        annotated.extend_from_slice(&body);
+        Ok(annotated)
+    }
+
+    /// Reads the tag of an annotated payload and reverses the encoding it names.
+    ///
+    /// A payload that does not start with a known tag was sent untagged, by a peer without
+    /// compression, and is returned as it is with `Encoding::Identity`.
+    ///
+    /// # Arguments
+    ///
+    /// * `annotated` - A payload produced by `annotate`, or an untagged payload.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the encoding applied by the sender and the original payload, or
+    /// an error message if decompression fails.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit::compression::Encoding;
+    ///
+    /// let annotated = Encoding::PermessageDeflate.annotate(b"{}").unwrap();
+    /// assert_eq!(Encoding::read_annotated(&annotated).unwrap(), (Encoding::PermessageDeflate, b"{}".to_vec()));
+    /// assert_eq!(Encoding::read_annotated(b"{}").unwrap(), (Encoding::Identity, b"{}".to_vec()));
+    /// ```
+    pub fn read_annotated(annotated: &[u8]) -> Result<(Encoding, Vec<u8>), String> {
+        Encoding::read_annotated_limited(annotated, DecompressionLimits::default())
+    }
+
+    /// Like `read_annotated`, decompressing within `limits`.
+    ///
+    /// # Arguments
+    ///
+    /// * `annotated` - A payload produced by `annotate`, or an untagged payload.
+    /// * `limits` - The cap and ratio the decompressed size must stay within.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the encoding applied by the sender and the original payload, or
+    /// an error message if decompression fails or a limit is exceeded.
+    pub fn read_annotated_limited(annotated: &[u8], limits: DecompressionLimits) -> Result<(Encoding, Vec<u8>), String> {
+        match annotated.split_first() {
+            Some((b'i', body)) => Ok((Encoding::Identity, body.to_vec())),
+            Some((b'p', body)) => Ok((Encoding::PermessageDeflate, body.to_vec())),
+            Some((b'd', body)) => Ok((
+                Encoding::Application(Compression::Deflate),
+                Compression::Deflate.decompress_limited(body, limits)?,
+            )),
+            _ => Ok((Encoding::Identity, annotated.to_vec())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests the negotiation matrix, annotated round trips without compression and untagged
+    /// payloads.
+    #[test]
+    fn test_encoding_negotiation() {
+        let negotiated = vec!["x-custom, PerMessage-Deflate; server_max_window_bits=10".to_string()];
+        let other = vec!["x-custom".to_string()];
+        assert_eq!(Encoding::negotiate(Compression::None, &negotiated), Encoding::Identity);
+        assert_eq!(Encoding::negotiate(Compression::None, &other), Encoding::Identity);
+        assert_eq!(Encoding::negotiate(Compression::Deflate, &negotiated), Encoding::PermessageDeflate);
+        assert_eq!(Encoding::negotiate(Compression::Deflate, &other), Encoding::Application(Compression::Deflate));
+
+        for encoding in [Encoding::Identity, Encoding::PermessageDeflate] {
+            let annotated = encoding.annotate(b"payload").unwrap();
+            assert_eq!(annotated.len(), 8);
+            assert_eq!(Encoding::read_annotated(&annotated).unwrap(), (encoding, b"payload".to_vec()));
+        }
+        assert_eq!(Encoding::read_annotated(b"").unwrap(), (Encoding::Identity, Vec::new()));
+        assert_eq!(Encoding::read_annotated(b"{\"kind\":1}").unwrap(), (Encoding::Identity, b"{\"kind\":1}".to_vec()));
+    }
+
+    /// Tests round trips and rejection of corrupt input.
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_deflate_round_trip() {
+        let payload: Vec<u8> = (0..4096u32).map(|i| (i % 7) as u8).collect();
+        let compressed = Compression::Deflate.compress(&payload).unwrap();
+        assert!(compressed.len() < payload.len() / 10);
+        assert_eq!(Compression::Deflate.decompress(&compressed).unwrap(), payload);
+        assert_eq!(Compression::None.compress(&payload).unwrap(), payload);
+        assert!(Compression::Deflate.decompress(&[0xff; 16]).is_err());
+
+        let annotated = Encoding::Application(Compression::Deflate).annotate(&payload).unwrap();
+        assert_eq!(annotated[0], b'd');
+        assert!(Encoding::read_annotated_limited(&annotated, DecompressionLimits { max_bytes: Some(4095), max_ratio: None }).is_err());
+        assert_eq!(
+            Encoding::read_annotated(&annotated).unwrap(),
+            (Encoding::Application(Compression::Deflate), payload)
+        );
+    }
+
+    /// Tests that expansion stops at the cap and the ratio, and exact fits pass.
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_decompression_limits() {
+        let bomb = Compression::Deflate.compress(&vec![0; 4 * 1024 * 1024]).unwrap();
+        assert!(bomb.len() < 8 * 1024);
+        let capped = DecompressionLimits { max_bytes: Some(1024 * 1024), max_ratio: None };
+        assert_eq!(
+            Compression::Deflate.decompress_limited(&bomb, capped),
+            Err(DecompressionError::LimitExceeded { compressed: bomb.len(), limit: 1024 * 1024 })
+        );
+        let ratio = DecompressionLimits { max_bytes: None, max_ratio: Some(10) };
+        assert_eq!(
+            Compression::Deflate.decompress_limited(&bomb, ratio),
+            Err(DecompressionError::LimitExceeded { compressed: bomb.len(), limit: bomb.len() * 10 })
+        );
+        assert!(Compression::Deflate.decompress(&bomb).is_ok());
+
+        let exact = DecompressionLimits { max_bytes: Some(4 * 1024 * 1024), max_ratio: None };
+        assert_eq!(Compression::Deflate.decompress_limited(&bomb, exact).unwrap().len(), 4 * 1024 * 1024);
+        assert!(Compression::Deflate.decompress_limited(&bomb, DecompressionLimits::unlimited()).is_ok());
+        assert!(matches!(
+            Compression::Deflate.decompress_limited(&[0xff; 16], capped),
+            Err(DecompressionError::Corrupt(_))
+        ));
+    }
+
+    /// Tests how the cap and the ratio combine.
+    #[test]
+    fn test_limit_for() {
+        let limits = DecompressionLimits { max_bytes: Some(1000), max_ratio: Some(20) };
+        assert_eq!(limits.limit_for(10), Some(200));
+        assert_eq!(limits.limit_for(100), Some(1000));
+        assert_eq!(DecompressionLimits::default().limit_for(10), Some(DEFAULT_MAX_DECOMPRESSED_BYTES));
+        assert_eq!(DecompressionLimits::unlimited().limit_for(10), None);
+    }
+}
@@ -0,0 +1,1004 @@
+//! # `config.rs`: File and environment configuration
+//!
+//! `Config` gathers the settings a deployment usually tunes: server URLs, retries and
+//! backoff, timeouts, keep-alive, the message format and TLS file paths. It loads from a TOML
+//! file (with the `toml` feature) or a YAML file (with the `yaml` feature), and `WSTK_*`
+//! environment variables override individual settings, so the same file can serve several
+//! environments:
+//!
+//! | Variable | Setting |
+//! |----------|---------|
+//! | `WSTK_URL` | `urls`, comma-separated |
+//! | `WSTK_FAILOVER` | `failover` (`priority` or `round_robin`) |
+//! | `WSTK_RETRIES` | `retries` |
+//! | `WSTK_BACKOFF` | `backoff` (`linear`, `exponential`, `fibonacci`, `decorrelated_jitter` or `constant`) |
+//! | `WSTK_BACKOFF_SECS` | `backoff_secs` |
+//! | `WSTK_BACKOFF_MAX_SECS` | `backoff_max_secs` |
+//! | `WSTK_HANDSHAKE_RETRIES` | `handshake_retries` |
+//! | `WSTK_CONNECT_TIMEOUT_MS` | `connect_timeout_ms` |
+//! | `WSTK_PING_INTERVAL_SECS` | `ping_interval_secs` |
+//! | `WSTK_PING_JITTER` | `ping_jitter` |
+//! | `WSTK_FORMAT` | `format` (`json` or `cbor`) |
+//! | `WSTK_COMPRESSION` | `compression` (`none` or `deflate`) |
+//! | `WSTK_MAX_DECOMPRESSED_BYTES` | `max_decompressed_bytes` |
+//! | `WSTK_MAX_DECOMPRESSION_RATIO` | `max_decompression_ratio` |
+//! | `WSTK_TEXT_MODE` | `text_mode` (`preserve`, `validated`, `lossy` or `raw`) |
+//! | `WSTK_JSON_NUMBERS` | `json_numbers` (`native` or `exact`) |
+//! | `WSTK_FRAME_KIND` | `frame_kind` (`text` or `binary`) |
+//! | `WSTK_IDLE_TIMEOUT_SECS` | `idle_timeout_secs` |
+//! | `WSTK_OUTBOUND_CAPACITY` | `outbound_capacity` |
+//! | `WSTK_INBOUND_CAPACITY` | `inbound_capacity` |
+//! | `WSTK_INBOUND_POLICY` | `inbound_policy` (`backpressure` or `drop_oldest`) |
+//! | `WSTK_FLUSH_IMMEDIATELY` | `flush_immediately` |
+//! | `WSTK_TCP_NODELAY` | `tcp_nodelay` |
+//! | `WSTK_PREALLOCATED_BUFFERS` | `preallocated_buffers` |
+//! | `WSTK_TRACK_RTT` | `track_rtt` |
+//! | `WSTK_TRACK_LINK_QUALITY` | `track_link_quality` |
+//! | `WSTK_FRAGMENT_THRESHOLD` | `fragment_threshold` |
+//! | `WSTK_FRAGMENT_SIZE` | `fragment_size` |
+//! | `WSTK_SUBSCRIPTION_SYNC_TIMEOUT_MS` | `subscription_sync_timeout_ms` |
+//! | `WSTK_REPLAY_WINDOW_SECS` | `replay_window_secs` |
+//! | `WSTK_REPLAY_MAX_SKEW_MS` | `replay_max_skew_ms` |
+//! | `WSTK_TRACE_FIELD` | `trace_field` |
+//! | `WSTK_TRACE_SAMPLE_RATE` | `trace_sample_rate` |
+//! | `WSTK_TIMESTAMPS` | `timestamps` |
+//! | `WSTK_INBOUND_MESSAGES_PER_SEC` | `inbound_messages_per_sec` |
+//! | `WSTK_INBOUND_BYTES_PER_SEC` | `inbound_bytes_per_sec` |
+//! | `WSTK_LOG_LEVEL` | `log_level` (`off`, `error`, `warn`, `info`, `debug` or `trace`) |
+//! | `WSTK_CLOSE_TIMEOUT_MS` | `close_timeout_ms` |
+//! | `WSTK_CLOSE_LINGER` | `close_linger` |
+//! | `WSTK_CLOSE_SUMMARY` | `close_summary` |
+//! | `WSTK_TLS_CA_CERT` | `tls.ca_cert` |
+//! | `WSTK_TLS_CLIENT_CERT` | `tls.client_cert` |
+//! | `WSTK_TLS_CLIENT_KEY` | `tls.client_key` |
+//! | `WSTK_TLS_ROOTS` | `tls.roots` (`bundled` or `native`) |
+//!
+//! `WebSocketController::from_config` builds a controller from a loaded `Config`, and
+//! `WebSocketController::reconfigure` applies the URLs, ping and inbound rate settings of a
+//! reloaded one without dropping the connection.
+//!
+//! Presets tune every setting for a kind of deployment at once; start from one and override
+//! individual fields:
+//!
+//! - `Config::low_bandwidth()` for cellular and NB-IoT devices: CBOR, deflate compression,
+//!   long jittered pings, small buffers and an aggressive idle timeout.
+//! - `Config::low_latency()` for trading and other tail-latency-sensitive clients:
+//!   `TCP_NODELAY`, a flush per message, drop-oldest inbound buffering, pre-allocated buffers
+//!   and RTT tracking.
+
+use crate::compression::{Compression, DecompressionLimits, DEFAULT_MAX_DECOMPRESSED_BYTES};
+use crate::connection::{Failover, HandshakeRetryPolicy};
+use crate::flush::FlushPolicy;
+use crate::fragment::Fragmentation;
+use crate::handle::CloseHandshake;
+use crate::messages::{FrameKind, JsonNumbers, MessageFormat, TextMode};
+use crate::pipeline::{InboundPolicy, PipelineConfig};
+use crate::quality::QualityThresholds;
+use crate::ratelimit::InboundRateLimit;
+#[cfg(feature = "reconnection")]
+use crate::reconnection::{Constant, DecorrelatedJitter, Exponential, Fibonacci, Linear, ReconnectStrategy};
+use crate::replay::ReplayPolicy;
+use crate::trace_context::TracePropagation;
+use log::LevelFilter;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use url::Url;
+
+/// Where the root certificates that verify `wss://` servers come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrustRoots {
+    /// The Mozilla root set bundled with the `webpki-roots` crate.
+    #[default]
+    Bundled,
+    /// The operating system's certificate store, including roots an administrator installed,
+    /// such as an enterprise CA. Requires the `native-roots` feature.
+    Native,
+}
+
+/// How the delay between reconnection attempts grows; see the `reconnection` module's
+/// `BackoffStrategy` implementations of the same names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backoff {
+    /// `backoff_secs * attempt`.
+    #[default]
+    Linear,
+    /// Doubles from `backoff_secs` up to `backoff_max_secs`.
+    Exponential,
+    /// Grows along the Fibonacci sequence from `backoff_secs` up to `backoff_max_secs`.
+    Fibonacci,
+    /// A random delay between `backoff_secs` and three times the previous one, up to
+    /// `backoff_max_secs`.
+    DecorrelatedJitter,
+    /// Always `backoff_secs`.
+    Constant,
+}
+
+/// Paths of the TLS files used for `wss://` connections.
+///
+/// With the `rustls-tls` feature, `WebSocketController::from_config` builds a connector that
+/// trusts `roots` plus `ca_cert` (see `tls::client_config`). The client certificate paths are
+/// for applications that build their own TLS connector.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// The trusted root certificates.
+    pub roots: TrustRoots,
+    /// A PEM file of additional trusted root certificates.
+    pub ca_cert: Option<PathBuf>,
+    /// A PEM client certificate for mutual TLS.
+    pub client_cert: Option<PathBuf>,
+    /// The PEM private key of `client_cert`.
+    pub client_key: Option<PathBuf>,
+}
+
+/// Connection settings loaded from a file and the environment.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::config::Config;
+/// use websocket_toolkit::messages::MessageFormat;
+///
+/// // With the `toml` feature: `let mut config = Config::from_file("wstk.toml")?;`
+/// let mut config = Config {
+///     urls: vec!["wss://primary.example.com/ws".to_string()],
+///     ..Config::default()
+/// };
+/// config.apply_overrides(|name| (name == "WSTK_FORMAT").then(|| "cbor".to_string())).unwrap();
+/// assert_eq!(config.primary_url(), Some("wss://primary.example.com/ws"));
+/// assert_eq!(config.format, MessageFormat::Cbor);
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The server URLs, in order of preference.
+    pub urls: Vec<String>,
+    /// The order in which the URLs are tried when connecting.
+    pub failover: Failover,
+    /// The maximum number of reconnection attempts.
+    pub retries: u32,
+    /// How the delay between reconnection attempts grows.
+    pub backoff: Backoff,
+    /// The base delay in seconds of the reconnection backoff.
+    pub backoff_secs: u64,
+    /// The longest delay in seconds between reconnection attempts, for the backoffs that
+    /// grow towards a cap.
+    pub backoff_max_secs: u64,
+    /// How often an upgrade rejected with 429, 502, 503 or 504 is retried within one
+    /// connection attempt, honoring `Retry-After`.
+    pub handshake_retries: u32,
+    /// How long a connection attempt may take, or `None` for no limit.
+    pub connect_timeout_ms: Option<u64>,
+    /// The keep-alive ping interval in seconds, or `None` for the default of 5 seconds.
+    pub ping_interval_secs: Option<u64>,
+    /// The maximum relative deviation of each ping interval, from 0 (fixed) to 1.
+    pub ping_jitter: f64,
+    /// The format messages are encoded in.
+    pub format: MessageFormat,
+    /// The compression applied to encoded payloads.
+    pub compression: Compression,
+    /// The most bytes a received payload may decompress to, or `None` for no cap.
+    pub max_decompressed_bytes: Option<usize>,
+    /// The most a received payload may expand when decompressed, e.g. `100` for 100:1, or
+    /// `None` for no ratio limit.
+    pub max_decompression_ratio: Option<u32>,
+    /// How received messages are handed to the application.
+    pub text_mode: TextMode,
+    /// How JSON numbers that do not fit a native number are decoded.
+    pub json_numbers: JsonNumbers,
+    /// The frame type envelopes are sent in, or `None` for the format's convention (text for
+    /// JSON, binary for CBOR).
+    pub frame_kind: Option<FrameKind>,
+    /// Closes pipelined connections that received no data message for this many seconds.
+    pub idle_timeout_secs: Option<u64>,
+    /// The number of outbound messages a pipeline queues before senders wait.
+    pub outbound_capacity: usize,
+    /// The number of inbound messages a pipeline buffers before it applies `inbound_policy`.
+    pub inbound_capacity: usize,
+    /// What a pipeline does when its inbound buffer is full.
+    pub inbound_policy: InboundPolicy,
+    /// Flushes every message as soon as it is written instead of batching bursts.
+    pub flush_immediately: bool,
+    /// Disables Nagle's algorithm so small frames are not delayed.
+    pub tcp_nodelay: bool,
+    /// The number of payload buffers the controller's `BufferPool` allocates up front.
+    pub preallocated_buffers: usize,
+    /// Times keep-alive pings against their pongs.
+    pub track_rtt: bool,
+    /// Estimates link quality on pipelines with the default `QualityThresholds`.
+    pub track_link_quality: bool,
+    /// Sends messages with a payload above this many bytes in fragments, or `None` to send
+    /// every message as a single frame.
+    pub fragment_threshold: Option<usize>,
+    /// The most payload bytes per fragment.
+    pub fragment_size: usize,
+    /// How long in milliseconds to wait for the server's subscription list on a new
+    /// connection, or `None` to replay every typed stream subscription without asking.
+    pub subscription_sync_timeout_ms: Option<u64>,
+    /// Rejects received envelopes older than this many seconds or replayed within them, or
+    /// `None` to accept envelopes without replay checks.
+    pub replay_window_secs: Option<u64>,
+    /// How far in milliseconds the server's clock may differ from the local one when
+    /// replay protection is on.
+    pub replay_max_skew_ms: u64,
+    /// The envelope header carrying W3C trace context (usually `traceparent`), or `None` to
+    /// send and receive envelopes without trace propagation.
+    pub trace_field: Option<String>,
+    /// The fraction of traces started locally that are sampled, from 0 to 1.
+    pub trace_sample_rate: f64,
+    /// Stamps outbound envelopes with their send time and inbound ones with their receive
+    /// time.
+    pub timestamps: bool,
+    /// The messages per second the server may send, or `None` for no limit.
+    pub inbound_messages_per_sec: Option<f64>,
+    /// The payload bytes per second the server may send, or `None` for no limit.
+    pub inbound_bytes_per_sec: Option<f64>,
+    /// The maximum log level, or `None` to leave it as the application set it. The toolkit
+    /// never sets it; the application applies `log_level()` to its logger.
+    pub log_level: Option<String>,
+    /// How long in milliseconds closing a connection waits for the server's close reply.
+    pub close_timeout_ms: u64,
+    /// Keeps the messages that arrive while closing a connection instead of discarding them.
+    pub close_linger: bool,
+    /// Sends a close summary envelope, in `format`, before the close frame.
+    pub close_summary: bool,
+    /// TLS file paths.
+    pub tls: TlsConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            urls: Vec::new(),
+            failover: Failover::Priority,
+            retries: 3,
+            backoff: Backoff::Linear,
+            backoff_secs: 1,
+            backoff_max_secs: 30,
+            handshake_retries: 0,
+            connect_timeout_ms: None,
+            ping_interval_secs: None,
+            ping_jitter: 0.0,
+            format: MessageFormat::Json,
+            compression: Compression::None,
+            max_decompressed_bytes: Some(DEFAULT_MAX_DECOMPRESSED_BYTES),
+            max_decompression_ratio: None,
+            text_mode: TextMode::Preserve,
+            json_numbers: JsonNumbers::Native,
+            frame_kind: None,
+            idle_timeout_secs: None,
+            outbound_capacity: 1024,
+            inbound_capacity: 1024,
+            inbound_policy: InboundPolicy::Backpressure,
+            flush_immediately: false,
+            tcp_nodelay: true,
+            preallocated_buffers: 0,
+            track_rtt: false,
+            track_link_quality: false,
+            fragment_threshold: None,
+            fragment_size: 16 * 1024,
+            subscription_sync_timeout_ms: None,
+            replay_window_secs: None,
+            replay_max_skew_ms: 5_000,
+            trace_field: None,
+            trace_sample_rate: 1.0,
+            timestamps: false,
+            inbound_messages_per_sec: None,
+            inbound_bytes_per_sec: None,
+            log_level: None,
+            close_timeout_ms: 5_000,
+            close_linger: false,
+            close_summary: false,
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Returns settings tuned for low-bandwidth links such as cellular and NB-IoT.
+    ///
+    /// Messages are CBOR-encoded and deflate-compressed (the `compression` feature is needed
+    /// to send them), pings go out every 4 minutes ±20% so a fleet does not wake its radios in
+    /// lockstep, buffers hold 32 messages, connections that carry no data for 2 minutes are
+    /// closed so the modem can sleep, and slow cellular handshakes get 30 seconds. No URL is
+    /// set.
+    ///
+    /// # Returns
+    ///
+    /// The preset `Config`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit::config::Config;
+    ///
+    /// let config = Config {
+    ///     urls: vec!["wss://telemetry.example.com/ws".to_string()],
+    ///     idle_timeout_secs: Some(600),
+    ///     ..Config::low_bandwidth()
+    /// };
+    /// assert!(config.validate().is_ok());
+    /// ```
+    pub fn low_bandwidth() -> Self {
+        Config {
+            backoff_secs: 5,
+            connect_timeout_ms: Some(30_000),
+            ping_interval_secs: Some(240),
+            ping_jitter: 0.2,
+            format: MessageFormat::Cbor,
+            compression: Compression::Deflate,
+            idle_timeout_secs: Some(120),
+            outbound_capacity: 32,
+            inbound_capacity: 32,
+            ..Config::default()
+        }
+    }
+
+    /// Returns settings tuned for tail latency, such as trading clients.
+    ///
+    /// `TCP_NODELAY` is on and every message is flushed as soon as it is written, so nothing
+    /// waits for a batch. When the consumer falls behind, the oldest inbound messages are
+    /// dropped rather than letting the socket back up, so what it does read is current. 256
+    /// payload buffers of 4 KiB are allocated up front so the first messages do not pay for
+    /// allocation, a ping every second keeps the RTT statistics fresh, and connection attempts
+    /// give up after 2 seconds so a failover starts promptly. No URL is set.
+    ///
+    /// # Returns
+    ///
+    /// The preset `Config`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit::config::Config;
+    ///
+    /// let config = Config {
+    ///     urls: vec!["wss://md.example.com/ws".to_string()],
+    ///     inbound_capacity: 4096,
+    ///     ..Config::low_latency()
+    /// };
+    /// assert!(config.pipeline_config().track_rtt);
+    /// ```
+    pub fn low_latency() -> Self {
+        Config {
+            connect_timeout_ms: Some(2_000),
+            ping_interval_secs: Some(1),
+            inbound_policy: InboundPolicy::DropOldest,
+            flush_immediately: true,
+            tcp_nodelay: true,
+            preallocated_buffers: 256,
+            track_rtt: true,
+            ..Config::default()
+        }
+    }
+
+    /// Parses a configuration from a TOML document.
+    ///
+    /// # Arguments
+    ///
+    /// * `document` - The TOML text.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the configuration, or an error message if it cannot be parsed.
+    #[cfg(feature = "toml")]
+    pub fn from_toml_str(document: &str) -> Result<Self, String> {
+        toml::from_str(document).map_err(|e| format!("Failed to parse config: {}", e))
+    }
+
+    /// Parses a configuration from a YAML document.
+    ///
+    /// # Arguments
+    ///
+    /// * `document` - The YAML text.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the configuration, or an error message if it cannot be parsed.
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml_str(document: &str) -> Result<Self, String> {
+        serde_yaml::from_str(document).map_err(|e| format!("Failed to parse config: {}", e))
+    }
+
+    /// Reads a configuration file, choosing the parser by extension (`.toml`, `.yaml` or `.yml`).
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The configuration file.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the configuration, or an error message if the file cannot be read
+    /// or parsed, or its format's feature is disabled.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let document = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+        match path.extension().and_then(|extension| extension.to_str()).unwrap_or_default() {
+            "toml" => {
+                #[cfg(feature = "toml")]
+                return Self::from_toml_str(&document);
+                #[cfg(not(feature = "toml"))]
+                return Err("Failed to parse config: the `toml` feature is disabled".to_string());
+            }
+            "yaml" | "yml" => {
+                #[cfg(feature = "yaml")]
+                return Self::from_yaml_str(&document);
+                #[cfg(not(feature = "yaml"))]
+                return Err("Failed to parse config: the `yaml` feature is disabled".to_string());
+            }
+            _ => Err(format!("Failed to parse config: unknown format of {}", path.display())),
+        }
+    }
+
+    /// Reads a configuration file, applies the `WSTK_*` environment overrides and validates it.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The configuration file.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the configuration, or an error message on failure.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let mut config = Self::from_file(path)?;
+        config.apply_env_overrides()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Builds a configuration from defaults and the `WSTK_*` environment variables alone.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the configuration, or an error message if a variable is invalid
+    /// or `WSTK_URL` is missing.
+    pub fn from_env() -> Result<Self, String> {
+        let mut config = Self::default();
+        config.apply_env_overrides()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Overrides settings with the `WSTK_*` environment variables that are set.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if a variable is invalid.
+    pub fn apply_env_overrides(&mut self) -> Result<(), String> {
+        self.apply_overrides(|name| std::env::var(name).ok())
+    }
+
+    /// Overrides settings with the variables returned by `lookup`, named as in the module docs.
+    ///
+    /// # Arguments
+    ///
+    /// * `lookup` - Returns the value of a variable, or `None` if it is unset.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if a variable is invalid.
+    pub fn apply_overrides(&mut self, lookup: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+        if let Some(urls) = lookup("WSTK_URL") {
+            self.urls = urls
+                .split(',')
+                .map(str::trim)
+                .filter(|url| !url.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some(failover) = lookup("WSTK_FAILOVER") {
+            self.failover = match failover.to_ascii_lowercase().as_str() {
+                "priority" => Failover::Priority,
+                "round_robin" => Failover::RoundRobin,
+                _ => return Err(format!("Invalid WSTK_FAILOVER: {}", failover)),
+            };
+        }
+        if let Some(retries) = lookup("WSTK_RETRIES") {
+            self.retries = parse_variable("WSTK_RETRIES", &retries)?;
+        }
+        if let Some(backoff) = lookup("WSTK_BACKOFF") {
+            self.backoff = match backoff.to_ascii_lowercase().as_str() {
+                "linear" => Backoff::Linear,
+                "exponential" => Backoff::Exponential,
+                "fibonacci" => Backoff::Fibonacci,
+                "decorrelated_jitter" => Backoff::DecorrelatedJitter,
+                "constant" => Backoff::Constant,
+                _ => return Err(format!("Invalid WSTK_BACKOFF: {}", backoff)),
+            };
+        }
+        if let Some(backoff) = lookup("WSTK_BACKOFF_SECS") {
+            self.backoff_secs = parse_variable("WSTK_BACKOFF_SECS", &backoff)?;
+        }
+        if let Some(backoff) = lookup("WSTK_BACKOFF_MAX_SECS") {
+            self.backoff_max_secs = parse_variable("WSTK_BACKOFF_MAX_SECS", &backoff)?;
+        }
+        if let Some(retries) = lookup("WSTK_HANDSHAKE_RETRIES") {
+            self.handshake_retries = parse_variable("WSTK_HANDSHAKE_RETRIES", &retries)?;
+        }
+        if let Some(timeout) = lookup("WSTK_CONNECT_TIMEOUT_MS") {
+            self.connect_timeout_ms = Some(parse_variable("WSTK_CONNECT_TIMEOUT_MS", &timeout)?);
+        }
+        if let Some(interval) = lookup("WSTK_PING_INTERVAL_SECS") {
+            self.ping_interval_secs = Some(parse_variable("WSTK_PING_INTERVAL_SECS", &interval)?);
+        }
+        if let Some(jitter) = lookup("WSTK_PING_JITTER") {
+            self.ping_jitter = parse_variable("WSTK_PING_JITTER", &jitter)?;
+        }
+        if let Some(format) = lookup("WSTK_FORMAT") {
+            self.format = match format.to_ascii_lowercase().as_str() {
+                "json" => MessageFormat::Json,
+                "cbor" => MessageFormat::Cbor,
+                _ => return Err(format!("Invalid WSTK_FORMAT: {}", format)),
+            };
+        }
+        if let Some(compression) = lookup("WSTK_COMPRESSION") {
+            self.compression = match compression.to_ascii_lowercase().as_str() {
+                "none" => Compression::None,
+                "deflate" => Compression::Deflate,
+                _ => return Err(format!("Invalid WSTK_COMPRESSION: {}", compression)),
+            };
+        }
+        if let Some(bytes) = lookup("WSTK_MAX_DECOMPRESSED_BYTES") {
+            self.max_decompressed_bytes = Some(parse_variable("WSTK_MAX_DECOMPRESSED_BYTES", &bytes)?);
+        }
+        if let Some(ratio) = lookup("WSTK_MAX_DECOMPRESSION_RATIO") {
+            self.max_decompression_ratio = Some(parse_variable("WSTK_MAX_DECOMPRESSION_RATIO", &ratio)?);
+        }
+        if let Some(mode) = lookup("WSTK_TEXT_MODE") {
+            self.text_mode = match mode.to_ascii_lowercase().as_str() {
+                "preserve" => TextMode::Preserve,
+                "validated" => TextMode::Validated,
+                "lossy" => TextMode::Lossy,
+                "raw" => TextMode::Raw,
+                _ => return Err(format!("Invalid WSTK_TEXT_MODE: {}", mode)),
+            };
+        }
+        if let Some(numbers) = lookup("WSTK_JSON_NUMBERS") {
+            self.json_numbers = match numbers.to_ascii_lowercase().as_str() {
+                "native" => JsonNumbers::Native,
+                "exact" => JsonNumbers::Exact,
+                _ => return Err(format!("Invalid WSTK_JSON_NUMBERS: {}", numbers)),
+            };
+        }
+        if let Some(kind) = lookup("WSTK_FRAME_KIND") {
+            self.frame_kind = match kind.to_ascii_lowercase().as_str() {
+                "text" => Some(FrameKind::Text),
+                "binary" => Some(FrameKind::Binary),
+                _ => return Err(format!("Invalid WSTK_FRAME_KIND: {}", kind)),
+            };
+        }
+        if let Some(timeout) = lookup("WSTK_IDLE_TIMEOUT_SECS") {
+            self.idle_timeout_secs = Some(parse_variable("WSTK_IDLE_TIMEOUT_SECS", &timeout)?);
+        }
+        if let Some(capacity) = lookup("WSTK_OUTBOUND_CAPACITY") {
+            self.outbound_capacity = parse_variable("WSTK_OUTBOUND_CAPACITY", &capacity)?;
+        }
+        if let Some(capacity) = lookup("WSTK_INBOUND_CAPACITY") {
+            self.inbound_capacity = parse_variable("WSTK_INBOUND_CAPACITY", &capacity)?;
+        }
+        if let Some(policy) = lookup("WSTK_INBOUND_POLICY") {
+            self.inbound_policy = match policy.to_ascii_lowercase().as_str() {
+                "backpressure" => InboundPolicy::Backpressure,
+                "drop_oldest" => InboundPolicy::DropOldest,
+                _ => return Err(format!("Invalid WSTK_INBOUND_POLICY: {}", policy)),
+            };
+        }
+        if let Some(flush) = lookup("WSTK_FLUSH_IMMEDIATELY") {
+            self.flush_immediately = parse_variable("WSTK_FLUSH_IMMEDIATELY", &flush)?;
+        }
+        if let Some(nodelay) = lookup("WSTK_TCP_NODELAY") {
+            self.tcp_nodelay = parse_variable("WSTK_TCP_NODELAY", &nodelay)?;
+        }
+        if let Some(buffers) = lookup("WSTK_PREALLOCATED_BUFFERS") {
+            self.preallocated_buffers = parse_variable("WSTK_PREALLOCATED_BUFFERS", &buffers)?;
+        }
+        if let Some(track) = lookup("WSTK_TRACK_RTT") {
+            self.track_rtt = parse_variable("WSTK_TRACK_RTT", &track)?;
+        }
+        if let Some(track) = lookup("WSTK_TRACK_LINK_QUALITY") {
+            self.track_link_quality = parse_variable("WSTK_TRACK_LINK_QUALITY", &track)?;
+        }
+        if let Some(threshold) = lookup("WSTK_FRAGMENT_THRESHOLD") {
+            self.fragment_threshold = Some(parse_variable("WSTK_FRAGMENT_THRESHOLD", &threshold)?);
+        }
+        if let Some(size) = lookup("WSTK_FRAGMENT_SIZE") {
+            self.fragment_size = parse_variable("WSTK_FRAGMENT_SIZE", &size)?;
+        }
+        if let Some(timeout) = lookup("WSTK_SUBSCRIPTION_SYNC_TIMEOUT_MS") {
+            self.subscription_sync_timeout_ms = Some(parse_variable("WSTK_SUBSCRIPTION_SYNC_TIMEOUT_MS", &timeout)?);
+        }
+        if let Some(window) = lookup("WSTK_REPLAY_WINDOW_SECS") {
+            self.replay_window_secs = Some(parse_variable("WSTK_REPLAY_WINDOW_SECS", &window)?);
+        }
+        if let Some(skew) = lookup("WSTK_REPLAY_MAX_SKEW_MS") {
+            self.replay_max_skew_ms = parse_variable("WSTK_REPLAY_MAX_SKEW_MS", &skew)?;
+        }
+        if let Some(field) = lookup("WSTK_TRACE_FIELD") {
+            self.trace_field = Some(field.trim().to_string()).filter(|field| !field.is_empty());
+        }
+        if let Some(rate) = lookup("WSTK_TRACE_SAMPLE_RATE") {
+            self.trace_sample_rate = parse_variable("WSTK_TRACE_SAMPLE_RATE", &rate)?;
+        }
+        if let Some(timestamps) = lookup("WSTK_TIMESTAMPS") {
+            self.timestamps = parse_variable("WSTK_TIMESTAMPS", &timestamps)?;
+        }
+        if let Some(rate) = lookup("WSTK_INBOUND_MESSAGES_PER_SEC") {
+            self.inbound_messages_per_sec = Some(parse_variable("WSTK_INBOUND_MESSAGES_PER_SEC", &rate)?);
+        }
+        if let Some(rate) = lookup("WSTK_INBOUND_BYTES_PER_SEC") {
+            self.inbound_bytes_per_sec = Some(parse_variable("WSTK_INBOUND_BYTES_PER_SEC", &rate)?);
+        }
+        if let Some(level) = lookup("WSTK_LOG_LEVEL") {
+            self.log_level = Some(level.trim().to_string()).filter(|level| !level.is_empty());
+        }
+        if let Some(timeout) = lookup("WSTK_CLOSE_TIMEOUT_MS") {
+            self.close_timeout_ms = parse_variable("WSTK_CLOSE_TIMEOUT_MS", &timeout)?;
+        }
+        if let Some(linger) = lookup("WSTK_CLOSE_LINGER") {
+            self.close_linger = parse_variable("WSTK_CLOSE_LINGER", &linger)?;
+        }
+        if let Some(summary) = lookup("WSTK_CLOSE_SUMMARY") {
+            self.close_summary = parse_variable("WSTK_CLOSE_SUMMARY", &summary)?;
+        }
+        if let Some(path) = lookup("WSTK_TLS_CA_CERT") {
+            self.tls.ca_cert = Some(PathBuf::from(path));
+        }
+        if let Some(path) = lookup("WSTK_TLS_CLIENT_CERT") {
+            self.tls.client_cert = Some(PathBuf::from(path));
+        }
+        if let Some(path) = lookup("WSTK_TLS_CLIENT_KEY") {
+            self.tls.client_key = Some(PathBuf::from(path));
+        }
+        if let Some(roots) = lookup("WSTK_TLS_ROOTS") {
+            self.tls.roots = match roots.to_ascii_lowercase().as_str() {
+                "bundled" => TrustRoots::Bundled,
+                "native" => TrustRoots::Native,
+                _ => return Err(format!("Invalid WSTK_TLS_ROOTS: {}", roots)),
+            };
+        }
+        Ok(())
+    }
+
+    /// Checks that at least one URL is set, every URL is a `ws://` or `wss://` URL, the ping
+    /// jitter is a fraction, a client certificate comes with its key, and native roots are
+    /// only requested with the `native-roots` feature.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message describing the first problem.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.urls.is_empty() {
+            return Err("Invalid config: no URL set".to_string());
+        }
+        for url in &self.urls {
+            let parsed = Url::parse(url).map_err(|e| format!("Invalid config URL {}: {}", url, e))?;
+            if !matches!(parsed.scheme(), "ws" | "wss") {
+                return Err(format!("Invalid config URL {}: expected ws:// or wss://", url));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.ping_jitter) {
+            return Err(format!("Invalid config: ping_jitter must be between 0 and 1, got {}", self.ping_jitter));
+        }
+        if !(0.0..=1.0).contains(&self.trace_sample_rate) {
+            return Err(format!(
+                "Invalid config: trace_sample_rate must be between 0 and 1, got {}",
+                self.trace_sample_rate
+            ));
+        }
+        if self.fragment_threshold.is_some() && self.fragment_size == 0 {
+            return Err("Invalid config: fragment_size must be positive".to_string());
+        }
+        self.log_level()?;
+        if self.tls.client_cert.is_some() != self.tls.client_key.is_some() {
+            return Err("Invalid config: tls.client_cert and tls.client_key must be set together".to_string());
+        }
+        if self.tls.roots == TrustRoots::Native && !cfg!(feature = "native-roots") {
+            return Err("Invalid config: tls.roots = \"native\" requires the native-roots feature".to_string());
+        }
+        Ok(())
+    }
+
+    /// Returns the preferred server URL.
+    pub fn primary_url(&self) -> Option<&str> {
+        self.urls.first().map(String::as_str)
+    }
+
+    /// Returns the reconnection strategy described by `retries`, `backoff`, `backoff_secs`
+    /// and `backoff_max_secs`.
+    ///
+    /// Available with the `reconnection` feature.
+    #[cfg(feature = "reconnection")]
+    pub fn reconnect_strategy(&self) -> ReconnectStrategy {
+        let base = Duration::from_secs(self.backoff_secs);
+        let max = Duration::from_secs(self.backoff_max_secs);
+        match self.backoff {
+            Backoff::Linear => ReconnectStrategy::new_with_backoff(self.retries, Linear { base }),
+            Backoff::Exponential => ReconnectStrategy::new_with_backoff(self.retries, Exponential { base, max }),
+            Backoff::Fibonacci => ReconnectStrategy::new_with_backoff(self.retries, Fibonacci { base, max }),
+            Backoff::DecorrelatedJitter => ReconnectStrategy::new_with_backoff(self.retries, DecorrelatedJitter { base, max }),
+            Backoff::Constant => ReconnectStrategy::new_with_backoff(self.retries, Constant(base)),
+        }
+    }
+
+    /// Returns the handshake retry policy, or `None` if rejected handshakes are not retried.
+    pub fn handshake_retry(&self) -> Option<HandshakeRetryPolicy> {
+        (self.handshake_retries > 0).then(|| HandshakeRetryPolicy {
+            max_attempts: self.handshake_retries.saturating_add(1),
+            ..HandshakeRetryPolicy::default()
+        })
+    }
+
+    /// Returns the connection timeout.
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout_ms.map(Duration::from_millis)
+    }
+
+    /// Returns how long to wait for the server's subscription list, or `None` if
+    /// subscriptions are replayed without asking.
+    pub fn subscription_sync_timeout(&self) -> Option<Duration> {
+        self.subscription_sync_timeout_ms.map(Duration::from_millis)
+    }
+
+    /// Returns the replay protection policy, or `None` if replay protection is off.
+    pub fn replay_policy(&self) -> Option<ReplayPolicy> {
+        self.replay_window_secs.map(|window| ReplayPolicy {
+            window: Duration::from_secs(window),
+            max_skew: Duration::from_millis(self.replay_max_skew_ms),
+            ..ReplayPolicy::default()
+        })
+    }
+
+    /// Returns the trace propagation settings, or `None` if trace propagation is off.
+    pub fn trace_propagation(&self) -> Option<TracePropagation> {
+        self.trace_field.as_ref().map(|field| TracePropagation {
+            field: field.clone(),
+            sample_rate: self.trace_sample_rate,
+        })
+    }
+
+    /// Returns the inbound rate limit, or `None` if neither rate is set.
+    pub fn inbound_rate_limit(&self) -> Option<InboundRateLimit> {
+        if self.inbound_messages_per_sec.is_none() && self.inbound_bytes_per_sec.is_none() {
+            return None;
+        }
+        Some(InboundRateLimit {
+            messages_per_sec: self.inbound_messages_per_sec,
+            bytes_per_sec: self.inbound_bytes_per_sec,
+            ..InboundRateLimit::default()
+        })
+    }
+
+    /// Returns the maximum log level.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the level, or `None` if not set, or an error message if
+    /// `log_level` is not a level name.
+    pub fn log_level(&self) -> Result<Option<LevelFilter>, String> {
+        self.log_level
+            .as_deref()
+            .map(|level| level.parse().map_err(|_| format!("Invalid config: unknown log_level {}", level)))
+            .transpose()
+    }
+
+    /// Returns how connections are closed: the reply timeout, lingering and the summary.
+    pub fn close_handshake(&self) -> CloseHandshake {
+        CloseHandshake {
+            reply_timeout: Duration::from_millis(self.close_timeout_ms),
+            linger: self.close_linger,
+            summary: self.close_summary.then_some(self.format),
+        }
+    }
+
+    /// Returns when outbound messages are fragmented, or `None` without a
+    /// `fragment_threshold`.
+    pub fn fragmentation(&self) -> Option<Fragmentation> {
+        self.fragment_threshold.map(|threshold| Fragmentation { threshold, fragment_size: self.fragment_size })
+    }
+
+    /// Returns the bounds on how far received payloads may expand when decompressed.
+    pub fn decompression_limits(&self) -> DecompressionLimits {
+        DecompressionLimits { max_bytes: self.max_decompressed_bytes, max_ratio: self.max_decompression_ratio }
+    }
+
+    /// Returns the pipeline settings: buffer sizes and policies, flushing, idle timeout, RTT
+    /// and link quality tracking and fragmentation.
+    pub fn pipeline_config(&self) -> PipelineConfig {
+        PipelineConfig {
+            outbound_capacity: self.outbound_capacity,
+            inbound_capacity: self.inbound_capacity,
+            flush_policy: if self.flush_immediately {
+                FlushPolicy::Immediate
+            } else {
+                FlushPolicy::WhenIdle
+            },
+            idle_timeout: self.idle_timeout_secs.map(Duration::from_secs),
+            inbound_policy: self.inbound_policy,
+            track_rtt: self.track_rtt,
+            fragmentation: self.fragmentation(),
+            link_quality: self.track_link_quality.then(QualityThresholds::default),
+        }
+    }
+}
+
+/// Parses the value of the environment variable `name`.
+fn parse_variable<T: FromStr>(name: &str, value: &str) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid {}: {}: {}", name, value, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Tests that variables override file settings and invalid values are rejected.
+    #[test]
+    fn test_overrides() {
+        let mut config = Config {
+            urls: vec!["ws://file.example.com".to_string()],
+            ..Config::default()
+        };
+        let variables: HashMap<&str, &str> = [
+            ("WSTK_URL", "wss://a.example.com, wss://b.example.com"),
+            ("WSTK_RETRIES", "7"),
+            ("WSTK_BACKOFF", "Exponential"),
+            ("WSTK_BACKOFF_MAX_SECS", "8"),
+            ("WSTK_FAILOVER", "round_robin"),
+            ("WSTK_FORMAT", "CBOR"),
+            ("WSTK_CONNECT_TIMEOUT_MS", "2500"),
+            ("WSTK_TEXT_MODE", "Lossy"),
+            ("WSTK_JSON_NUMBERS", "exact"),
+            ("WSTK_FRAME_KIND", "binary"),
+            ("WSTK_REPLAY_WINDOW_SECS", "60"),
+            ("WSTK_MAX_DECOMPRESSION_RATIO", "50"),
+            ("WSTK_TRACE_FIELD", "x-trace"),
+            ("WSTK_TRACE_SAMPLE_RATE", "0.25"),
+            ("WSTK_TIMESTAMPS", "true"),
+            ("WSTK_INBOUND_MESSAGES_PER_SEC", "50"),
+            ("WSTK_LOG_LEVEL", "debug"),
+            ("WSTK_CLOSE_TIMEOUT_MS", "750"),
+            ("WSTK_CLOSE_SUMMARY", "true"),
+            ("WSTK_SUBSCRIPTION_SYNC_TIMEOUT_MS", "1500"),
+        ]
+        .into_iter()
+        .collect();
+        config
+            .apply_overrides(|name| variables.get(name).map(|value| value.to_string()))
+            .unwrap();
+        assert_eq!(config.urls, vec!["wss://a.example.com", "wss://b.example.com"]);
+        assert_eq!(config.retries, 7);
+        assert_eq!(config.failover, Failover::RoundRobin);
+        assert_eq!(config.format, MessageFormat::Cbor);
+        assert_eq!(config.connect_timeout(), Some(Duration::from_millis(2500)));
+        assert_eq!(config.backoff_secs, 1);
+        assert_eq!((config.backoff, config.backoff_max_secs), (Backoff::Exponential, 8));
+        #[cfg(feature = "reconnection")]
+        {
+            let strategy = config.reconnect_strategy();
+            assert_eq!(strategy.get_retries(), 7);
+            assert_eq!(strategy.delay(3, Duration::ZERO), Duration::from_secs(4));
+            assert_eq!(strategy.delay(5, Duration::ZERO), Duration::from_secs(8));
+        }
+        assert_eq!(config.text_mode, TextMode::Lossy);
+        assert_eq!(config.json_numbers, JsonNumbers::Exact);
+        assert_eq!(config.frame_kind, Some(FrameKind::Binary));
+        let replay = config.replay_policy().unwrap();
+        assert_eq!((replay.window, replay.max_skew), (Duration::from_secs(60), Duration::from_secs(5)));
+        let trace = config.trace_propagation().unwrap();
+        assert_eq!((trace.field.as_str(), trace.sample_rate), ("x-trace", 0.25));
+        assert!(config.timestamps);
+        assert_eq!(config.subscription_sync_timeout(), Some(Duration::from_millis(1500)));
+        let rate_limit = config.inbound_rate_limit().unwrap();
+        assert_eq!((rate_limit.messages_per_sec, rate_limit.bytes_per_sec), (Some(50.0), None));
+        assert_eq!(config.log_level(), Ok(Some(LevelFilter::Debug)));
+        assert_eq!(
+            config.close_handshake(),
+            CloseHandshake { reply_timeout: Duration::from_millis(750), linger: false, summary: Some(MessageFormat::Cbor) }
+        );
+        assert_eq!(
+            config.decompression_limits(),
+            DecompressionLimits { max_bytes: Some(DEFAULT_MAX_DECOMPRESSED_BYTES), max_ratio: Some(50) }
+        );
+        assert!(config.validate().is_ok());
+
+        let invalid = config.apply_overrides(|name| (name == "WSTK_RETRIES").then(|| "many".to_string()));
+        assert!(invalid.unwrap_err().contains("WSTK_RETRIES"));
+        config.log_level = Some("loud".to_string());
+        assert!(config.validate().unwrap_err().contains("log_level"));
+    }
+
+    /// Tests that the low-bandwidth preset keeps its tuning when individual knobs are overridden.
+    #[test]
+    fn test_low_bandwidth_preset() {
+        let mut config = Config::low_bandwidth();
+        config
+            .apply_overrides(|name| match name {
+                "WSTK_URL" => Some("wss://example.com".to_string()),
+                "WSTK_COMPRESSION" => Some("none".to_string()),
+                _ => None,
+            })
+            .unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.compression, Compression::None);
+        assert_eq!(config.format, MessageFormat::Cbor);
+        let pipeline = config.pipeline_config();
+        assert_eq!(pipeline.outbound_capacity, 32);
+        assert_eq!(pipeline.idle_timeout, Some(Duration::from_secs(120)));
+    }
+
+    /// Tests the low-latency preset and its pipeline settings.
+    #[test]
+    fn test_low_latency_preset() {
+        let mut config = Config::low_latency();
+        config
+            .apply_overrides(|name| match name {
+                "WSTK_URL" => Some("wss://example.com".to_string()),
+                "WSTK_INBOUND_POLICY" => Some("backpressure".to_string()),
+                "WSTK_TRACK_RTT" => Some("false".to_string()),
+                "WSTK_TRACK_LINK_QUALITY" => Some("true".to_string()),
+                "WSTK_FRAGMENT_THRESHOLD" => Some("65536".to_string()),
+                _ => None,
+            })
+            .unwrap();
+        assert!(config.validate().is_ok());
+        assert!(config.tcp_nodelay);
+        let pipeline = config.pipeline_config();
+        assert_eq!(pipeline.flush_policy, FlushPolicy::Immediate);
+        assert_eq!(pipeline.inbound_policy, InboundPolicy::Backpressure);
+        assert!(!pipeline.track_rtt);
+        assert_eq!(pipeline.link_quality, Some(QualityThresholds::default()));
+        assert_eq!(pipeline.fragmentation, Some(Fragmentation { threshold: 65_536, fragment_size: 16 * 1024 }));
+        assert_eq!(Config::low_latency().pipeline_config().inbound_policy, InboundPolicy::DropOldest);
+    }
+
+    /// Tests validation of URLs and TLS settings.
+    #[test]
+    fn test_validate() {
+        assert!(Config::default().validate().is_err());
+        let mut config = Config {
+            urls: vec!["http://example.com".to_string()],
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+        config.urls = vec!["wss://example.com".to_string()];
+        config.tls.client_cert = Some(PathBuf::from("client.pem"));
+        assert!(config.validate().is_err());
+        config.tls.client_key = Some(PathBuf::from("client.key"));
+        assert!(config.validate().is_ok());
+        config.tls.roots = TrustRoots::Native;
+        assert_eq!(config.validate().is_ok(), cfg!(feature = "native-roots"));
+        config.apply_overrides(|name| (name == "WSTK_TLS_ROOTS").then(|| "Bundled".to_string())).unwrap();
+        assert_eq!(config.tls.roots, TrustRoots::Bundled);
+        config.fragment_threshold = Some(65_536);
+        config.fragment_size = 0;
+        assert!(config.validate().is_err());
+    }
+
+    /// Tests loading a YAML file.
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_from_yaml_file() {
+        let path = std::env::temp_dir().join(format!("wstk-config-test-{}.yaml", std::process::id()));
+        std::fs::write(
+            &path,
+            "urls:\n  - wss://example.com/ws\nping_interval_secs: 15\ntls:\n  ca_cert: /etc/wstk/ca.pem\n",
+        )
+        .unwrap();
+        let config = Config::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(config.primary_url(), Some("wss://example.com/ws"));
+        assert_eq!(config.ping_interval_secs, Some(15));
+        assert_eq!(config.tls.ca_cert, Some(PathBuf::from("/etc/wstk/ca.pem")));
+    }
+}
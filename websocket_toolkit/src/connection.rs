@@ -4,20 +4,306 @@
 //! It provides functionality for connection setup, message sending, receiving, and reconnection logic.
 
 #![allow(unused_imports)]
-use log::{info, error};
+use log::{info, error, warn};
 use tokio_tungstenite::{connect_async, WebSocketStream, MaybeTlsStream};
 use tokio_tungstenite::tungstenite::{Error, Message};
 use tokio::net::TcpStream;
 use url::Url;
 use futures_util::{sink::SinkExt, StreamExt}; 
 use crate::messages::{MessageHandler, MessageFormat};
+use crate::runtime::{DefaultRuntime, Runtime};
+use bytes::Bytes;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::client::Response;
+use tokio_tungstenite::tungstenite::http::{self, HeaderName, HeaderValue};
+#[cfg(feature = "rustls-tls")]
+use tokio_tungstenite::{client_async_tls_with_config, Connector};
+
+/// Details of an established connection, for logging and debugging.
+///
+/// # Fields
+/// - `url` - The URL that was connected to.
+/// - `peer_addr` - The resolved address of the server.
+/// - `local_addr` - The local address of the connection.
+/// - `tls` - The negotiated TLS parameters, or `None` for a plain `ws://` connection.
+/// - `subprotocol` - The subprotocol selected by the server (`Sec-WebSocket-Protocol`).
+/// - `extensions` - The extensions accepted by the server (`Sec-WebSocket-Extensions`).
+/// - `set_cookies` - The `Set-Cookie` header values of the handshake response.
+/// - `handshake_duration` - The time from starting to connect until the handshake completed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// The URL that was connected to.
+    pub url: String,
+    /// The resolved address of the server.
+    pub peer_addr: Option<SocketAddr>,
+    /// The local address of the connection.
+    pub local_addr: Option<SocketAddr>,
+    /// The negotiated TLS parameters, or `None` for a plain connection.
+    pub tls: Option<TlsInfo>,
+    /// The subprotocol selected by the server.
+    pub subprotocol: Option<String>,
+    /// The extensions accepted by the server, such as `permessage-deflate`.
+    pub extensions: Vec<String>,
+    /// The cookies set by the server, one `Set-Cookie` header value each.
+    pub set_cookies: Vec<String>,
+    /// The time taken to connect, including DNS resolution and the handshake.
+    pub handshake_duration: Duration,
+}
+
+/// The parameters negotiated for a TLS connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsInfo {
+    /// The protocol version, such as `TLSv1.3`.
+    pub protocol: String,
+    /// The cipher suite, such as `TLS13_AES_128_GCM_SHA256`.
+    pub cipher: String,
+}
+
+impl ConnectionInfo {
+    /// Collects the details of a connection from its stream and handshake response.
+    fn new(url: &str, ws_stream: &WebSocketStream<MaybeTlsStream<TcpStream>>, response: &Response, started: Instant) -> Self {
+        let (peer_addr, local_addr, tls) = match ws_stream.get_ref() {
+            MaybeTlsStream::Plain(tcp) => (tcp.peer_addr().ok(), tcp.local_addr().ok(), None),
+            #[cfg(feature = "rustls-tls")]
+            MaybeTlsStream::Rustls(stream) => {
+                use rustls::Session;
+                let (tcp, session) = stream.get_ref();
+                let tls = TlsInfo {
+                    protocol: session
+                        .get_protocol_version()
+                        .map(|version| format!("{:?}", version).replace('_', "."))
+                        .unwrap_or_default(),
+                    cipher: session
+                        .get_negotiated_ciphersuite()
+                        .map(|suite| format!("{:?}", suite.suite))
+                        .unwrap_or_default(),
+                };
+                (tcp.peer_addr().ok(), tcp.local_addr().ok(), Some(tls))
+            }
+            // Other TLS streams are only available when tokio-tungstenite is built with their
+            // backend.
+            #[allow(unreachable_patterns)]
+            _ => (None, None, None),
+        };
+        let header_values = |name: &str| -> Vec<String> {
+            response
+                .headers()
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+                .collect()
+        };
+        ConnectionInfo {
+            url: url.to_string(),
+            peer_addr,
+            local_addr,
+            tls,
+            subprotocol: header_values("Sec-WebSocket-Protocol").into_iter().next(),
+            extensions: header_values("Sec-WebSocket-Extensions"),
+            // Cookie attributes such as `Expires` contain commas, so these are not split.
+            set_cookies: response
+                .headers()
+                .get_all("Set-Cookie")
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .map(str::to_string)
+                .collect(),
+            handshake_duration: started.elapsed(),
+        }
+    }
+}
+
+/// How `WebSocketClient` retries upgrades the server rejected with an HTTP status.
+///
+/// A load balancer answering `503 Service Unavailable` during a deploy is not the same failure
+/// as a refused TCP connection: the server is reachable and often says when to come back.
+/// Rejections with a status in `retry_statuses` are retried within `connect` itself, waiting
+/// for the response's `Retry-After` (capped at `max_delay`) or `default_delay` when it has
+/// none. Network errors are returned straight away, so they follow the caller's normal
+/// reconnection backoff.
+///
+/// # Fields
+/// - `max_attempts` - The number of handshakes attempted in total, including the first.
+/// - `retry_statuses` - The status codes worth retrying.
+/// - `default_delay` - The wait when the response has no usable `Retry-After`.
+/// - `max_delay` - The longest wait honored, whatever `Retry-After` says.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandshakeRetryPolicy {
+    /// The number of handshakes attempted in total, including the first.
+    pub max_attempts: u32,
+    /// The status codes worth retrying.
+    pub retry_statuses: Vec<u16>,
+    /// The wait when the response has no usable `Retry-After`.
+    pub default_delay: Duration,
+    /// The longest wait honored.
+    pub max_delay: Duration,
+}
+
+impl Default for HandshakeRetryPolicy {
+    /// Three attempts for 429, 502, 503 and 504, waiting 1 second unless told otherwise and
+    /// at most 60 seconds.
+    fn default() -> Self {
+        HandshakeRetryPolicy {
+            max_attempts: 3,
+            retry_statuses: vec![429, 502, 503, 504],
+            default_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl HandshakeRetryPolicy {
+    /// Decides whether a failed handshake should be retried, and after how long.
+    ///
+    /// # Arguments
+    /// - `error` - The error returned by the handshake.
+    /// - `attempt` - The number of handshakes attempted so far.
+    ///
+    /// # Returns
+    /// The delay before the next attempt, or `None` if `error` is not a retryable rejection
+    /// or the attempts are used up.
+    pub fn retry_delay(&self, error: &Error, attempt: u32) -> Option<Duration> {
+        let response = match error {
+            Error::Http(response) => response,
+            _ => return None,
+        };
+        if attempt >= self.max_attempts || !self.retry_statuses.contains(&response.status().as_u16()) {
+            return None;
+        }
+        let delay = response
+            .headers()
+            .get("Retry-After")
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after)
+            .unwrap_or(self.default_delay);
+        Some(delay.min(self.max_delay))
+    }
+}
+
+/// Parses a `Retry-After` header value: delay-seconds or an HTTP-date (IMF-fixdate, such as
+/// `Wed, 21 Oct 2015 07:28:00 GMT`).
+///
+/// # Arguments
+/// - `value` - The header value.
+///
+/// # Returns
+/// The time to wait (zero for a date in the past), or `None` if the value cannot be parsed.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    let (day, month, year, time) = match fields.as_slice() {
+        [_, day, month, year, time, "GMT"] => (day, month, year, time),
+        _ => return None,
+    };
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let month = MONTHS.iter().position(|name| name == month)? as i64 + 1;
+    let (day, year): (i64, i64) = (day.parse().ok()?, year.parse().ok()?);
+    let mut clock = time.split(':').map(|part| part.parse::<i64>().ok());
+    let (hour, minute, second) = (clock.next()??, clock.next()??, clock.next()??);
+    // Days since the Unix epoch of a proleptic Gregorian date (Howard Hinnant's algorithm).
+    let shifted_year = if month <= 2 { year - 1 } else { year };
+    let era = shifted_year.div_euclid(400);
+    let year_of_era = shifted_year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    let timestamp = u64::try_from(days * 86_400 + hour * 3_600 + minute * 60 + second).ok()?;
+    let at = std::time::UNIX_EPOCH + Duration::from_secs(timestamp);
+    Some(at.duration_since(std::time::SystemTime::now()).unwrap_or(Duration::ZERO))
+}
+
+/// Server URLs accepted by `WebSocketClient::new`: a single URL or a list in order of
+/// preference.
+pub trait IntoServerUrls {
+    /// Converts the value into the list of URLs.
+    fn into_server_urls(self) -> Vec<String>;
+}
+
+impl IntoServerUrls for &str {
+    fn into_server_urls(self) -> Vec<String> {
+        vec![self.to_string()]
+    }
+}
+
+impl IntoServerUrls for &String {
+    fn into_server_urls(self) -> Vec<String> {
+        vec![self.clone()]
+    }
+}
+
+impl IntoServerUrls for String {
+    fn into_server_urls(self) -> Vec<String> {
+        vec![self]
+    }
+}
+
+impl IntoServerUrls for Vec<String> {
+    fn into_server_urls(self) -> Vec<String> {
+        self
+    }
+}
+
+impl IntoServerUrls for &Vec<String> {
+    fn into_server_urls(self) -> Vec<String> {
+        self.clone()
+    }
+}
+
+impl IntoServerUrls for &[String] {
+    fn into_server_urls(self) -> Vec<String> {
+        self.to_vec()
+    }
+}
+
+impl IntoServerUrls for Vec<&str> {
+    fn into_server_urls(self) -> Vec<String> {
+        self.into_iter().map(str::to_string).collect()
+    }
+}
+
+impl IntoServerUrls for &[&str] {
+    fn into_server_urls(self) -> Vec<String> {
+        self.iter().map(|url| url.to_string()).collect()
+    }
+}
+
+impl<const N: usize> IntoServerUrls for [&str; N] {
+    fn into_server_urls(self) -> Vec<String> {
+        self.iter().map(|url| url.to_string()).collect()
+    }
+}
+
+/// The order in which a client with several server URLs tries them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Failover {
+    /// Every connection tries the URLs in order, so the primary is used whenever it is up.
+    #[default]
+    Priority,
+    /// Each connection starts with the URL after the one the previous connection started
+    /// with, spreading connections over all servers.
+    RoundRobin,
+}
 
 /// `WebSocketClient` is responsible for managing WebSocket connections, including connection setup, 
 /// message sending, and reconnection logic. It provides methods to establish a connection, 
 /// send and receive messages, and gracefully disconnect.
 ///
+/// With several server URLs, `connect` fails over to the next URL when one is unreachable or
+/// rejects the handshake, in the order chosen with `with_failover`.
+///
 /// # Fields
-/// - `url` - The URL of the WebSocket server.
+/// - `url` - The URL of the WebSocket server; the primary one if there are several.
 /// - `retries` - The number of reconnection attempts allowed.
 ///
 /// # Examples
@@ -29,25 +315,43 @@ use crate::messages::{MessageHandler, MessageFormat};
 /// assert_eq!(client.url, "wss://example.com/socket");
 /// assert_eq!(client.get_retries(), 3);
 /// ```
-
 #[derive(Clone)]
 pub struct WebSocketClient {
     /// The URL of the WebSocket server.
     pub url: String,
+    /// The URLs tried after `url`, in order of preference.
+    fallback_urls: Vec<String>,
+    /// The order in which the URLs are tried.
+    failover: Failover,
+    /// The URL the next round-robin connection starts with, shared by clones.
+    next_url: Arc<AtomicUsize>,
     /// Number of retries allowed for reconnection attempts.
     retries: u32,
+    /// Whether Nagle's algorithm is disabled on plain TCP connections.
+    nodelay: bool,
+    /// Extra headers sent with the handshake request.
+    headers: Vec<(String, String)>,
+    /// How rejected handshakes are retried, if at all.
+    handshake_retry: Option<HandshakeRetryPolicy>,
+    /// The rustls configuration of `wss://` connections, or `None` for tokio-tungstenite's
+    /// default connector.
+    #[cfg(feature = "rustls-tls")]
+    tls: Option<Arc<rustls::ClientConfig>>,
 }
 
 impl WebSocketClient {
     /// Creates a new `WebSocketClient` with a specified URL and retry limit.
     ///
     /// # Arguments
-    /// - `url` - The WebSocket server URL as a string.
+    /// - `urls` - The WebSocket server URL, or several in order of preference.
     /// - `retries` - The number of reconnection attempts allowed.
     ///
     /// # Returns
     /// A new instance of `WebSocketClient`.
     ///
+    /// # Panics
+    /// If `urls` is an empty list.
+    ///
     /// # Examples
     /// ```rust
     /// use websocket_toolkit::connection::WebSocketClient;
@@ -55,15 +359,123 @@ impl WebSocketClient {
     /// let client = WebSocketClient::new("wss://example.com/socket", 3);
     /// assert_eq!(client.url, "wss://example.com/socket");
     /// assert_eq!(client.get_retries(), 3);
+    ///
+    /// // Fails over to the secondary cluster while the primary is down.
+    /// let client = WebSocketClient::new(["wss://eu.example.com/socket", "wss://us.example.com/socket"], 3);
+    /// assert_eq!(client.urls(), vec!["wss://eu.example.com/socket", "wss://us.example.com/socket"]);
     /// ```
-    
-    pub fn new(url: &str, retries: u32) -> Self {
+    pub fn new(urls: impl IntoServerUrls, retries: u32) -> Self {
+        let mut urls = urls.into_server_urls().into_iter();
         WebSocketClient {
-            url: url.to_string(),
+            url: urls.next().expect("WebSocketClient needs at least one URL"),
+            fallback_urls: urls.collect(),
+            failover: Failover::Priority,
+            next_url: Arc::new(AtomicUsize::new(0)),
             retries,
+            nodelay: true,
+            headers: Vec::new(),
+            handshake_retry: None,
+            #[cfg(feature = "rustls-tls")]
+            tls: None,
         }
     }
 
+    /// Sets the order in which the server URLs are tried; `Failover::Priority` by default.
+    ///
+    /// # Arguments
+    /// - `failover` - The order.
+    ///
+    /// # Returns
+    /// The updated `WebSocketClient`.
+    pub fn with_failover(mut self, failover: Failover) -> Self {
+        self.failover = failover;
+        self
+    }
+
+    /// Returns the server URLs in order of preference, starting with `url`.
+    pub fn urls(&self) -> Vec<&str> {
+        std::iter::once(&self.url).chain(&self.fallback_urls).map(String::as_str).collect()
+    }
+
+    /// Returns the order in which the server URLs are tried.
+    pub fn failover(&self) -> Failover {
+        self.failover
+    }
+
+    /// Retries handshakes the server rejects with a retryable HTTP status, such as `503`,
+    /// honoring `Retry-After`. By default rejections are returned like any other error.
+    ///
+    /// # Arguments
+    /// - `policy` - Which statuses to retry, how often and how long to wait.
+    ///
+    /// # Returns
+    /// The updated `WebSocketClient`.
+    pub fn with_handshake_retry(mut self, policy: HandshakeRetryPolicy) -> Self {
+        self.handshake_retry = Some(policy);
+        self
+    }
+
+    /// Adds a header to the handshake request, such as `Authorization` or `Cookie`.
+    ///
+    /// # Arguments
+    /// - `name` - The header name.
+    /// - `value` - The header value.
+    ///
+    /// # Returns
+    /// The updated `WebSocketClient`. An invalid name or value makes `connect` fail.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sets whether `TCP_NODELAY` is enabled on new connections (the default).
+    ///
+    /// Disabling it lets the kernel coalesce small frames into fewer packets at the cost of
+    /// latency, which can help on links billed per packet.
+    ///
+    /// # Arguments
+    /// - `nodelay` - `true` to send every frame immediately.
+    ///
+    /// # Returns
+    /// The updated `WebSocketClient`.
+    pub fn with_nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Sets the rustls configuration of `wss://` connections, such as one from
+    /// `tls::client_config` that trusts the operating system's certificate store.
+    ///
+    /// # Arguments
+    /// - `config` - The TLS configuration.
+    ///
+    /// # Returns
+    /// The updated `WebSocketClient`.
+    #[cfg(feature = "rustls-tls")]
+    pub fn with_tls_config(mut self, config: Arc<rustls::ClientConfig>) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// Replaces the server URLs, keeping the other settings. Round-robin failover starts
+    /// over with the first URL.
+    ///
+    /// # Arguments
+    /// - `urls` - The WebSocket server URL, or several in order of preference.
+    ///
+    /// # Returns
+    /// The updated `WebSocketClient`.
+    ///
+    /// # Panics
+    /// If `urls` is an empty list.
+    pub fn with_urls(mut self, urls: impl IntoServerUrls) -> Self {
+        let mut urls = urls.into_server_urls().into_iter();
+        self.url = urls.next().expect("WebSocketClient needs at least one URL");
+        self.fallback_urls = urls.collect();
+        self.next_url = Arc::new(AtomicUsize::new(0));
+        self
+    }
+
     /// Receives a message from the WebSocket server.
     ///
     /// # Returns
@@ -81,7 +493,6 @@ impl WebSocketClient {
     ///     }
     /// });
     /// ```
-    
     pub async fn receive_message(&self) -> Option<Vec<u8>> {
         let mut ws_stream = self.connect().await.ok()?;
 
@@ -114,22 +525,136 @@ impl WebSocketClient {
     ///     }
     /// });
     /// ```
-    
     pub async fn connect(&self) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, Error> {
-        let url = Url::parse(&self.url).expect("Invalid WebSocket URL");
-        info!("Attempting to connect to WebSocket server at {}", self.url);
-        let (ws_stream, _) = connect_async(url).await?;
-        info!("Connected to WebSocket server at {}", self.url);
-        Ok(ws_stream)
+        self.connect_with_info().await.map(|(ws_stream, _)| ws_stream)
+    }
+
+    /// Establishes a WebSocket connection and reports the details of the handshake.
+    ///
+    /// With `with_handshake_retry`, handshakes rejected with a retryable HTTP status are
+    /// retried here; network errors are always returned immediately. With several URLs, each
+    /// is tried in turn until one connects; `ConnectionInfo::url` tells which one did.
+    ///
+    /// # Returns
+    /// A `Result` containing the WebSocket stream and its `ConnectionInfo` on success, or an `Error` on failure.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use websocket_toolkit::connection::WebSocketClient;
+    ///
+    /// let runtime = tokio::runtime::Runtime::new().unwrap();
+    /// runtime.block_on(async {
+    ///     let client = WebSocketClient::new("wss://example.com/socket", 3);
+    ///     if let Ok((_, info)) = client.connect_with_info().await {
+    ///         println!("Connected to {:?} in {:?}", info.peer_addr, info.handshake_duration);
+    ///     }
+    /// });
+    /// ```
+    pub async fn connect_with_info(&self) -> Result<(WebSocketStream<MaybeTlsStream<TcpStream>>, ConnectionInfo), Error> {
+        let urls = self.urls();
+        let start = match self.failover {
+            Failover::Priority => 0,
+            Failover::RoundRobin => self.next_url.fetch_add(1, Ordering::Relaxed) % urls.len(),
+        };
+        let mut failed = None;
+        for offset in 0..urls.len() {
+            let url = urls[(start + offset) % urls.len()];
+            match self.connect_to(url).await {
+                Ok(connected) => return Ok(connected),
+                Err(e) if urls.len() > 1 => {
+                    warn!("Failed to connect to {}: {}", url, e);
+                    failed = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        error!("Failed to connect to any of {} servers", urls.len());
+        Err(failed.expect("WebSocketClient has at least one URL"))
+    }
+
+    /// Connects to `url`, retrying rejected handshakes as the handshake retry policy says.
+    async fn connect_to(&self, url: &str) -> Result<(WebSocketStream<MaybeTlsStream<TcpStream>>, ConnectionInfo), Error> {
+        let mut attempt = 1;
+        loop {
+            let error = match self.handshake(url).await {
+                Ok(connected) => return Ok(connected),
+                Err(e) => e,
+            };
+            let delay = match self.handshake_retry.as_ref().and_then(|policy| policy.retry_delay(&error, attempt)) {
+                Some(delay) => delay,
+                None => return Err(error),
+            };
+            warn!("Handshake with {} rejected ({}); retrying in {:?}", url, error, delay);
+            DefaultRuntime::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Connects to `url` and performs one WebSocket handshake.
+    async fn handshake(&self, url: &str) -> Result<(WebSocketStream<MaybeTlsStream<TcpStream>>, ConnectionInfo), Error> {
+        let parsed = Url::parse(url).expect("Invalid WebSocket URL");
+        info!("Attempting to connect to WebSocket server at {}", url);
+        let started = Instant::now();
+        let mut request = parsed.into_client_request()?;
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(http::Error::from)?;
+            let value = HeaderValue::from_str(value).map_err(http::Error::from)?;
+            request.headers_mut().append(name, value);
+        }
+        // The TLS handshake state is large; boxing it keeps it off the stack of every future
+        // awaiting a connection.
+        #[cfg(feature = "rustls-tls")]
+        let (ws_stream, response) = match &self.tls {
+            Some(config) => {
+                let tcp = Self::connect_tcp(&request).await?;
+                Box::pin(client_async_tls_with_config(request, tcp, None, Some(Connector::Rustls(config.clone())))).await?
+            }
+            None => Box::pin(connect_async(request)).await?,
+        };
+        #[cfg(not(feature = "rustls-tls"))]
+        let (ws_stream, response) = connect_async(request).await?;
+        // Small frames are latency-sensitive; unless configured otherwise, don't let Nagle's
+        // algorithm hold them back waiting for the peer's delayed ACK.
+        if let MaybeTlsStream::Plain(tcp) = ws_stream.get_ref() {
+            tcp.set_nodelay(self.nodelay)?;
+        }
+        let connection_info = ConnectionInfo::new(url, &ws_stream, &response, started);
+        info!(
+            "Connected to WebSocket server at {} ({:?}) in {:?}",
+            url, connection_info.peer_addr, connection_info.handshake_duration
+        );
+        Ok((ws_stream, connection_info))
+    }
+
+    /// Opens the TCP connection a handshake `request` will be sent over, defaulting the port
+    /// from the URL scheme.
+    #[cfg(feature = "rustls-tls")]
+    async fn connect_tcp(request: &http::Request<()>) -> Result<TcpStream, Error> {
+        use tokio_tungstenite::tungstenite::error::UrlError;
+        let uri = request.uri();
+        let host = uri.host().ok_or(Error::Url(UrlError::NoHostName))?;
+        let port = uri
+            .port_u16()
+            .or_else(|| match uri.scheme_str() {
+                Some("wss") => Some(443),
+                Some("ws") => Some(80),
+                _ => None,
+            })
+            .ok_or(Error::Url(UrlError::UnsupportedUrlScheme))?;
+        // IPv6 literals keep their brackets in the URI, which `connect` doesn't accept.
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        Ok(TcpStream::connect((host, port)).await?)
     }
 
     /// Sends a message over an active WebSocket connection. The message is serialized using JSON format by default.
     ///
+    /// Serializing allocates a new buffer per call; use `send_text` or `send_bytes` to send
+    /// already-serialized data without copying.
+    ///
     /// # Arguments
     /// - `ws_stream` - The WebSocket stream to send the message over.
     /// - `message` - The message to send as a string.
     ///
-
     pub async fn send_message(&self, ws_stream: &mut WebSocketStream<MaybeTlsStream<TcpStream>>, message: &str) {
         let serialized = MessageHandler::serialize(&message, MessageFormat::Json);
 
@@ -144,6 +669,41 @@ impl WebSocketClient {
         }
     }
 
+    /// Sends already-serialized text as a text message, without re-serializing or copying it.
+    ///
+    /// # Arguments
+    /// - `ws_stream` - The WebSocket stream to send the message over.
+    /// - `text` - The text payload, such as a JSON document.
+    ///
+    /// # Returns
+    /// A `Result` indicating success, or the `Error` from the stream.
+    pub async fn send_text(
+        &self,
+        ws_stream: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+        text: impl Into<String>,
+    ) -> Result<(), Error> {
+        ws_stream.send(Message::Text(text.into())).await
+    }
+
+    /// Sends an owned payload as a binary message without copying it.
+    ///
+    /// Uniquely owned allocations (`Vec<u8>`, `BytesMut`, or `Bytes` created from either) are
+    /// moved into the frame as-is; shared or static `Bytes` are copied once.
+    ///
+    /// # Arguments
+    /// - `ws_stream` - The WebSocket stream to send the message over.
+    /// - `payload` - The already-serialized payload.
+    ///
+    /// # Returns
+    /// A `Result` indicating success, or the `Error` from the stream.
+    pub async fn send_bytes(
+        &self,
+        ws_stream: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+        payload: impl Into<Bytes>,
+    ) -> Result<(), Error> {
+        ws_stream.send(Message::Binary(Vec::from(payload.into()))).await
+    }
+
     /// Disconnects the WebSocket connection gracefully.
     ///
     /// # Examples
@@ -153,7 +713,6 @@ impl WebSocketClient {
     /// let client = WebSocketClient::new("wss://example.com/socket", 3);
     /// client.disconnect();
     /// ```
-    
     pub fn disconnect(&self) {
         self.private_disconnect();
     }
@@ -170,7 +729,6 @@ impl WebSocketClient {
     /// let client = WebSocketClient::new("wss://example.com/socket", 3);
     /// assert_eq!(client.get_retries(), 3);
     /// ```
-    
     pub fn get_retries(&self) -> u32 {
         self.retries
     }
@@ -179,12 +737,14 @@ impl WebSocketClient {
     ///
     /// # Examples
     /// This method is used internally by the `disconnect` method.
-    
     fn private_disconnect(&self) {
         info!("Disconnected from WebSocket server at {}", self.url);
     }
 
-    /// Attempts to reconnect to the WebSocket server if the connection fails.
+    /// Attempts to reconnect to the WebSocket server if the connection fails. Each retry
+    /// tries every server URL.
+    ///
+    /// Available with the `reconnection` feature.
     ///
     /// # Returns
     /// A `Result` containing the WebSocket stream on successful reconnection, or an `Error` if all retries fail.
@@ -202,7 +762,7 @@ impl WebSocketClient {
     ///     }
     /// });
     /// ```
-    
+    #[cfg(feature = "reconnection")]
     pub async fn reconnect(&self) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, Error> {
         let mut retries_left = self.retries;
         while retries_left > 0 {
@@ -214,7 +774,7 @@ impl WebSocketClient {
                 Err(e) => {
                     error!("Failed to reconnect: {}", e);
                     retries_left -= 1;
-                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    DefaultRuntime::sleep(std::time::Duration::from_secs(1)).await;
                 }
             }
         }
@@ -238,6 +798,139 @@ mod tests {
         assert_eq!(client.get_retries(), 3);
     }
 
+    /// Tests parsing of both `Retry-After` forms.
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT"), Some(Duration::ZERO));
+        let future = parse_retry_after("Fri, 01 Jan 2100 00:00:00 GMT").unwrap();
+        let expected = std::time::UNIX_EPOCH + Duration::from_secs(4_102_444_800);
+        let now_to_expected = expected.duration_since(std::time::SystemTime::now()).unwrap();
+        assert!(now_to_expected.as_secs().abs_diff(future.as_secs()) <= 1);
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    /// Tests that a 503 rejection is retried after its `Retry-After` while refused TCP
+    /// connections are not retried.
+    #[tokio::test]
+    #[allow(clippy::result_large_err)] // The handshake callback's error type is tungstenite's.
+    async fn test_handshake_retry() {
+        use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response as ServerResponse};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for attempt in 0.. {
+                let (stream, _) = listener.accept().await.unwrap();
+                let reject_first = move |_: &Request, response: ServerResponse| -> Result<ServerResponse, ErrorResponse> {
+                    // The first two handshakes are rejected.
+                    if attempt < 2 {
+                        let rejection = http::Response::builder()
+                            .status(503)
+                            .header("Retry-After", "0")
+                            .body(None)
+                            .unwrap();
+                        return Err(rejection);
+                    }
+                    Ok(response)
+                };
+                if let Ok(mut ws_stream) = tokio_tungstenite::accept_hdr_async(stream, reject_first).await {
+                    tokio::spawn(async move { while ws_stream.next().await.is_some() {} });
+                }
+            }
+        });
+
+        let url = format!("ws://{}", addr);
+        let error = WebSocketClient::new(&url, 0).connect().await.err();
+        assert!(matches!(error, Some(Error::Http(ref response)) if response.status() == 503));
+        let client = WebSocketClient::new(&url, 0).with_handshake_retry(HandshakeRetryPolicy::default());
+        assert!(client.connect().await.is_ok());
+
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let client = WebSocketClient::new(format!("ws://{}", closed), 0).with_handshake_retry(HandshakeRetryPolicy::default());
+        assert!(matches!(client.connect().await, Err(Error::Io(_))));
+    }
+
+    /// Tests that `connect_with_info` reports the peer address and negotiated subprotocol.
+    #[tokio::test]
+    #[allow(clippy::result_large_err)] // The handshake callback's error type is tungstenite's.
+    async fn test_connect_with_info() {
+        use tokio_tungstenite::tungstenite::handshake::server::{Request, Response as ServerResponse};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let select_protocol = |request: &Request, mut response: ServerResponse| {
+                assert_eq!(request.headers()["Authorization"], "Bearer secret");
+                response.headers_mut().insert("Sec-WebSocket-Protocol", "wstk.v1".parse().unwrap());
+                response.headers_mut().append("Set-Cookie", "sid=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT".parse().unwrap());
+                Ok(response)
+            };
+            let mut ws_stream = tokio_tungstenite::accept_hdr_async(stream, select_protocol).await.unwrap();
+            while ws_stream.next().await.is_some() {}
+        });
+
+        let client = WebSocketClient::new(format!("ws://{}", addr), 0).with_header("Authorization", "Bearer secret");
+        let (_ws_stream, info) = client.connect_with_info().await.unwrap();
+        assert_eq!(info.peer_addr, Some(addr));
+        assert!(info.local_addr.is_some());
+        assert_eq!(info.tls, None);
+        assert_eq!(info.subprotocol.as_deref(), Some("wstk.v1"));
+        assert!(info.extensions.is_empty());
+        assert_eq!(info.set_cookies, vec!["sid=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT".to_string()]);
+        assert!(info.handshake_duration > Duration::ZERO);
+    }
+
+    /// Tests that `connect_with_info` reports the negotiated TLS parameters of a `wss://`
+    /// connection verified against a custom CA.
+    #[cfg(feature = "rustls-tls")]
+    #[tokio::test]
+    async fn test_connect_with_info_tls() {
+        use crate::config::TlsConfig;
+        use rustls::internal::pemfile::{certs, pkcs8_private_keys};
+        use rustls::{NoClientAuth, ServerConfig};
+        use std::io::BufReader;
+        use std::path::Path;
+        use tokio_rustls::TlsAcceptor;
+
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let read = |name: &str| BufReader::new(std::fs::File::open(fixtures.join(name)).unwrap());
+        let mut server_config = ServerConfig::new(NoClientAuth::new());
+        let chain = certs(&mut read("localhost.pem")).unwrap();
+        let key = pkcs8_private_keys(&mut read("localhost.key")).unwrap().remove(0);
+        server_config.set_single_cert(chain, key).unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    if let Ok(stream) = acceptor.accept(stream).await {
+                        let mut ws_stream = accept_async(stream).await.unwrap();
+                        while ws_stream.next().await.is_some() {}
+                    }
+                });
+            }
+        });
+
+        let tls = TlsConfig { ca_cert: Some(fixtures.join("ca.pem")), ..TlsConfig::default() };
+        let client = WebSocketClient::new(format!("wss://localhost:{}", addr.port()), 0)
+            .with_tls_config(crate::tls::client_config(&tls).unwrap());
+        let (_ws_stream, info) = client.connect_with_info().await.unwrap();
+        assert_eq!(info.peer_addr, Some(addr));
+        let tls = info.tls.expect("Expected TLS details for a wss:// connection");
+        assert!(tls.protocol.starts_with("TLSv1"), "{}", tls.protocol);
+        assert!(tls.cipher.starts_with("TLS"), "{}", tls.cipher);
+
+        // Without the CA, the server's certificate doesn't verify.
+        let client = WebSocketClient::new(format!("wss://localhost:{}", addr.port()), 0)
+            .with_tls_config(crate::tls::client_config(&TlsConfig::default()).unwrap());
+        assert!(matches!(client.connect_with_info().await, Err(Error::Tls(_)) | Err(Error::Io(_))));
+    }
+
     /// Tests the ability of `WebSocketClient` to connect to a mock WebSocket server.
     #[tokio::test]
     async fn test_websocket_client_connection() {
@@ -317,5 +1010,42 @@ mod tests {
         let _ = server_handle.await; // Ensure the server task is complete
         println!("Test complete.");
     }
-}
 
+    /// Tests failing over past an unreachable server, and round-robin over live ones.
+    #[tokio::test]
+    async fn test_failover_urls() {
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let down = format!("ws://{}", closed);
+        let first = crate::testing::EchoServer::start().await.expect("Failed to start echo server");
+        let second = crate::testing::EchoServer::start().await.expect("Failed to start echo server");
+
+        let client = WebSocketClient::new([down.as_str(), first.url(), second.url()], 0);
+        for _ in 0..2 {
+            let (_, info) = client.connect_with_info().await.expect("Failed to fail over");
+            assert_eq!(info.url, first.url());
+        }
+        assert!(matches!(WebSocketClient::new(vec![down.clone(), down.clone()], 0).connect().await, Err(Error::Io(_))));
+
+        let client = WebSocketClient::new(vec![first.url(), second.url(), down.as_str()], 0).with_failover(Failover::RoundRobin);
+        let mut connected = Vec::new();
+        for _ in 0..3 {
+            let (_, info) = client.clone().connect_with_info().await.expect("Failed to connect");
+            connected.push(info.url);
+        }
+        assert_eq!(connected, vec![first.url(), second.url(), first.url()]);
+    }
+
+    /// Tests that pre-serialized text and owned bytes are sent unchanged.
+    #[tokio::test]
+    async fn test_send_text_and_bytes() {
+        let mut server = crate::testing::MockServer::start().await.expect("Failed to start mock server");
+        let client = WebSocketClient::new(server.url(), 0);
+        let mut ws_stream = client.connect().await.expect("Failed to connect");
+        let mut connection = server.accept().await;
+
+        client.send_text(&mut ws_stream, r#"{"type":"ping"}"#).await.unwrap();
+        client.send_bytes(&mut ws_stream, vec![1, 2, 3]).await.unwrap();
+        connection.assert_next_message_eq(Message::Text(r#"{"type":"ping"}"#.to_string())).await;
+        connection.assert_next_message_eq(Message::Binary(vec![1, 2, 3])).await;
+    }
+}
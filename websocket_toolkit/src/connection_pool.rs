@@ -0,0 +1,437 @@
+//! # `connection_pool.rs`: Parallel connections to one endpoint
+//!
+//! One connection writes its frames one after another, so a publisher that pushes high
+//! message volumes is eventually limited by a single socket: one TCP window, one writer task,
+//! one server-side reader. `ConnectionPool` opens several connections to the controller's
+//! endpoint and spreads sends over them, either round-robin or to the connection with the
+//! fewest queued messages.
+//!
+//! Messages sent through the pool may arrive out of order, since they travel different
+//! connections; use `shard::ShardedPool` when messages for the same key must stay in order.
+//! Received messages from every connection are merged into one stream for `recv_inbound`.
+//!
+//! When a connection closes, the pool stops handing it out and opens a replacement in the
+//! background with `ReconnectStrategy::retry`: with the controller's reconnection strategy if
+//! it has one, so its retries, jitter and time budget apply, and otherwise with exponential
+//! backoff (from `REPLACE_DELAY` up to `MAX_REPLACE_DELAY`) until the endpoint accepts it
+//! again.
+
+use crate::controller::WebSocketController;
+use crate::handle::ConnectionHandle;
+use crate::messages::{Envelope, FrameKind, InboundMessage, MessageFormat};
+use crate::pipeline::PipelineConfig;
+use crate::reconnection::{Exponential, ReconnectStrategy, RetryError};
+use crate::tasks::spawn_named;
+use log::{error, info, warn};
+use std::error::Error as StdError;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock, Weak};
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio_tungstenite::tungstenite::Message;
+
+/// The delay before retrying a failed replacement when the controller has no reconnection
+/// strategy; doubled after every failure.
+pub const REPLACE_DELAY: Duration = Duration::from_millis(500);
+
+/// The longest delay between attempts to replace a connection.
+pub const MAX_REPLACE_DELAY: Duration = Duration::from_secs(30);
+
+/// How a `ConnectionPool` picks the connection for a send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PoolStrategy {
+    /// Each send takes the next open connection in turn.
+    #[default]
+    RoundRobin,
+    /// Each send takes the open connection with the fewest queued outbound messages, so a
+    /// connection slowed down by its network receives less.
+    LeastLoaded,
+}
+
+/// A pool of connections to the same endpoint that share the send load.
+///
+/// Clones share the same connections.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use websocket_toolkit::connection_pool::{ConnectionPool, PoolStrategy};
+/// use websocket_toolkit::controller::WebSocketController;
+/// use websocket_toolkit::pipeline::PipelineConfig;
+/// use websocket_toolkit::testing::EchoServer;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let server = EchoServer::start().await.unwrap();
+/// let controller = Arc::new(WebSocketController::new(server.url(), 0, None));
+/// let pool = ConnectionPool::connect(controller, 4, PoolStrategy::LeastLoaded, PipelineConfig::default())
+///     .await
+///     .unwrap();
+///
+/// pool.send_text("tick").await.unwrap();
+/// assert_eq!(pool.recv_inbound().await.unwrap().as_bytes(), b"tick");
+/// pool.shutdown().await.unwrap();
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ConnectionPool {
+    shared: Arc<Shared>,
+}
+
+/// State shared by all clones, the forwarders and the replacement tasks.
+struct Shared {
+    controller: Arc<WebSocketController>,
+    config: PipelineConfig,
+    strategy: PoolStrategy,
+    /// The connections; a closed one stays in its place until its replacement is open.
+    members: RwLock<Vec<ConnectionHandle>>,
+    /// The connection the next pick starts with.
+    next: AtomicUsize,
+    /// Feeds the merged inbound stream; taken by `shutdown`.
+    inbound_tx: std::sync::Mutex<Option<mpsc::Sender<InboundMessage>>>,
+    inbound: Mutex<mpsc::Receiver<InboundMessage>>,
+    replacements: AtomicU64,
+    closed: AtomicBool,
+}
+
+impl ConnectionPool {
+    /// Opens `size` connections with `controller`.
+    ///
+    /// Must be called within a tokio runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `controller` - Opens every connection and replacement, with its URL, headers,
+    ///   session, auth and limits.
+    /// * `size` - The number of connections.
+    /// * `strategy` - How sends are spread over the connections.
+    /// * `config` - The pipeline settings of every connection.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the pool, or an error if `size` is 0 or a connection fails.
+    /// Connections already opened are shut down on failure.
+    pub async fn connect(
+        controller: Arc<WebSocketController>,
+        size: usize,
+        strategy: PoolStrategy,
+        config: PipelineConfig,
+    ) -> Result<Self, Box<dyn StdError>> {
+        if size == 0 {
+            return Err("Failed to create connection pool: no connections".into());
+        }
+        let mut members = Vec::with_capacity(size);
+        for index in 0..size {
+            let opened = controller.connect_handle(config).await.map_err(|e| e.to_string());
+            match opened {
+                Ok(handle) => members.push(handle),
+                Err(e) => {
+                    for handle in &members {
+                        let _ = handle.shutdown().await;
+                    }
+                    return Err(format!("Failed to open pool connection {}: {}", index, e).into());
+                }
+            }
+        }
+        let (inbound_tx, inbound) = mpsc::channel(config.inbound_capacity.max(1));
+        let shared = Arc::new(Shared {
+            controller,
+            config,
+            strategy,
+            members: RwLock::new(members.clone()),
+            next: AtomicUsize::new(0),
+            inbound_tx: std::sync::Mutex::new(Some(inbound_tx)),
+            inbound: Mutex::new(inbound),
+            replacements: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+        });
+        for (index, handle) in members.into_iter().enumerate() {
+            forward(&shared, index, handle);
+        }
+        info!("Opened a pool of {} connections", size);
+        Ok(ConnectionPool { shared })
+    }
+
+    /// Returns the number of connections, open or being replaced.
+    pub fn len(&self) -> usize {
+        self.shared.members.read().unwrap().len()
+    }
+
+    /// Returns whether the pool has no connections; always `false` for a constructed pool.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of connections currently open.
+    pub fn open_connections(&self) -> usize {
+        self.shared.members.read().unwrap().iter().filter(|handle| !handle.is_closed()).count()
+    }
+
+    /// Returns the number of closed connections that have been replaced.
+    pub fn replacements(&self) -> u64 {
+        self.shared.replacements.load(Ordering::Relaxed)
+    }
+
+    /// Returns every connection, including closed ones awaiting replacement.
+    pub fn handles(&self) -> Vec<ConnectionHandle> {
+        self.shared.members.read().unwrap().clone()
+    }
+
+    /// Hands out an open connection as the strategy picks it, e.g. for a request whose reply
+    /// must be awaited on the same connection.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the connection, or an error message if none is open.
+    pub fn handle(&self) -> Result<ConnectionHandle, String> {
+        let members = self.shared.members.read().unwrap();
+        let start = self.shared.next.fetch_add(1, Ordering::Relaxed) % members.len();
+        let mut open = (0..members.len())
+            .map(|offset| &members[(start + offset) % members.len()])
+            .filter(|handle| !handle.is_closed());
+        let picked = match self.shared.strategy {
+            PoolStrategy::RoundRobin => open.next(),
+            // Ties go to the first connection after the previous pick, spreading idle sends.
+            PoolStrategy::LeastLoaded => open.min_by_key(|handle| handle.sender().queued()),
+        };
+        picked.cloned().ok_or_else(|| "Failed to pick a pool connection: none is open".to_string())
+    }
+
+    /// Queues a message on a connection picked by the strategy. If that connection closed
+    /// in the meantime, the message is queued on another one.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The frame to send.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if no connection is open.
+    pub async fn send(&self, mut message: Message) -> Result<(), String> {
+        for _ in 0..self.len() {
+            let handle = self.handle()?;
+            match handle.sender().send(message).await {
+                Ok(()) => return Ok(()),
+                Err(returned) => message = returned.0,
+            }
+        }
+        Err("Failed to send message: no pool connection is open".to_string())
+    }
+
+    /// Queues a text message; see `send`.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The message text.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if no connection is open.
+    pub async fn send_text(&self, text: impl Into<String>) -> Result<(), String> {
+        self.send(Message::Text(text.into())).await
+    }
+
+    /// Queues a binary message; see `send`.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The message payload.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if no connection is open.
+    pub async fn send_binary(&self, payload: Vec<u8>) -> Result<(), String> {
+        self.send(Message::Binary(payload)).await
+    }
+
+    /// Encodes `envelope` in `format` and queues it in the format's conventional frame type;
+    /// see `send`.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - The message to send.
+    /// * `format` - The wire format.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if encoding fails or no connection
+    /// is open.
+    pub async fn send_envelope(&self, envelope: &Envelope, format: MessageFormat) -> Result<(), String> {
+        self.send(FrameKind::for_format(format).frame(envelope.encode(format)?)?).await
+    }
+
+    /// Waits for the next data message from any connection.
+    ///
+    /// # Returns
+    ///
+    /// The next text or binary message, or `None` once the pool has been shut down and every
+    /// connection has ended.
+    pub async fn recv_inbound(&self) -> Option<InboundMessage> {
+        self.shared.inbound.lock().await.recv().await
+    }
+
+    /// Stops replacing connections and shuts every connection down.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or the first shutdown error; every connection is shut
+    /// down regardless.
+    pub async fn shutdown(&self) -> Result<(), String> {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.inbound_tx.lock().unwrap().take();
+        let mut result = Ok(());
+        for handle in self.handles() {
+            let shutdown = handle.shutdown().await;
+            if result.is_ok() {
+                result = shutdown;
+            }
+        }
+        result
+    }
+}
+
+impl std::fmt::Debug for ConnectionPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectionPool")
+            .field("strategy", &self.shared.strategy)
+            .field("len", &self.len())
+            .field("open", &self.open_connections())
+            .field("replacements", &self.replacements())
+            .finish()
+    }
+}
+
+/// Forwards the data messages of connection `index` into the merged stream until it ends,
+/// then starts replacing it.
+fn forward(shared: &Arc<Shared>, index: usize, handle: ConnectionHandle) {
+    let inbound = shared.inbound_tx.lock().unwrap().clone();
+    let shared = Arc::downgrade(shared);
+    spawn_named("websocket_toolkit::pool_forward", async move {
+        while let Some(message) = handle.recv_inbound().await {
+            match &inbound {
+                Some(inbound) if inbound.send(message).await.is_ok() => {}
+                _ => break,
+            }
+        }
+        if let Some(shared) = shared.upgrade() {
+            if !shared.closed.load(Ordering::Acquire) {
+                warn!("Pool connection {} ended; replacing it", index);
+                replace(Arc::downgrade(&shared), index);
+            }
+        }
+    });
+}
+
+/// Opens a replacement for connection `index` with the controller's reconnection strategy,
+/// or by default until it succeeds, stopping if the pool is shut down or dropped.
+fn replace(shared: Weak<Shared>, index: usize) {
+    spawn_named("websocket_toolkit::pool_replace", async move {
+        // The controller, unlike the pool, is kept alive while retrying.
+        let controller = match shared.upgrade() {
+            Some(pool) => pool.controller.clone(),
+            None => return,
+        };
+        let fallback;
+        let strategy = match controller.reconnect_strategy() {
+            Some(strategy) => strategy,
+            None => {
+                fallback = ReconnectStrategy::new_with_backoff(u32::MAX, Exponential { base: REPLACE_DELAY, max: MAX_REPLACE_DELAY });
+                &fallback
+            }
+        };
+        let is_fatal = |e: &AttemptError| matches!(e, AttemptError::Closed);
+        match strategy.retry(|| try_replace(&shared, index), is_fatal).await {
+            Ok(()) | Err(RetryError::Fatal(_)) => {}
+            Err(RetryError::GaveUp(e)) => error!("Gave up replacing pool connection {}: {}", index, e),
+        }
+    });
+}
+
+/// Makes one attempt to replace connection `index`.
+async fn try_replace(shared: &Weak<Shared>, index: usize) -> Result<(), AttemptError> {
+    let pool = match shared.upgrade() {
+        Some(pool) if !pool.closed.load(Ordering::Acquire) => pool,
+        _ => return Err(AttemptError::Closed),
+    };
+    let handle = match pool.controller.connect_handle(pool.config).await {
+        Ok(handle) => handle,
+        Err(e) => {
+            warn!("Failed to replace pool connection {}: {}", index, e);
+            return Err(AttemptError::Failed(e.to_string()));
+        }
+    };
+    if pool.closed.load(Ordering::Acquire) {
+        let _ = handle.shutdown().await;
+        return Err(AttemptError::Closed);
+    }
+    pool.members.write().unwrap()[index] = handle.clone();
+    pool.replacements.fetch_add(1, Ordering::Relaxed);
+    info!("Replaced pool connection {}", index);
+    forward(&pool, index, handle);
+    Ok(())
+}
+
+/// Why an attempt to replace a pool connection failed.
+enum AttemptError {
+    /// The pool was shut down or dropped.
+    Closed,
+    /// The connection failed.
+    Failed(String),
+}
+
+impl std::fmt::Display for AttemptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttemptError::Closed => write!(f, "The pool was shut down"),
+            AttemptError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::EchoServer;
+
+    /// Tests that sends are spread over every connection and that a closed connection is
+    /// skipped, then replaced.
+    #[tokio::test]
+    async fn test_connection_pool() {
+        let server = EchoServer::start().await.unwrap();
+        let controller = Arc::new(WebSocketController::new(server.url(), 3, None));
+        let pool = ConnectionPool::connect(controller, 3, PoolStrategy::RoundRobin, PipelineConfig::default())
+            .await
+            .unwrap();
+        let first = pool.handles();
+        let picked: Vec<_> = (0..3).map(|_| pool.handle().unwrap().sender().clone()).collect();
+        for (handle, sender) in first.iter().zip(&picked) {
+            assert!(handle.sender().same_channel(sender), "Expected round-robin order");
+        }
+
+        first[1].abort();
+        while !first[1].is_closed() {
+            tokio::task::yield_now().await;
+        }
+        for n in 0..6 {
+            pool.send_text(n.to_string()).await.unwrap();
+        }
+        let mut received: Vec<String> = Vec::new();
+        for _ in 0..6 {
+            let message = pool.recv_inbound().await.unwrap();
+            received.push(String::from_utf8(message.into_bytes()).unwrap());
+        }
+        received.sort();
+        assert_eq!(received, vec!["0", "1", "2", "3", "4", "5"]);
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while pool.replacements() == 0 || pool.open_connections() < 3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Expected the closed connection to be replaced");
+        assert!(!pool.handles()[1].sender().same_channel(first[1].sender()));
+        pool.shutdown().await.unwrap();
+    }
+}
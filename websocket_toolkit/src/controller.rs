@@ -9,27 +9,133 @@
 //! establishment, reconnections with exponential backoff, keep-alive mechanisms,
 //! and sending/receiving messages.
 
-use crate::connection::WebSocketClient;
-use crate::messages::{MessageHandler, MessageFormat};
-use crate::reconnection::ReconnectStrategy;
+use crate::auth::{is_auth_rejected, AuthMessage};
+use crate::subscriptions::SubscriptionManager;
+use crate::close::{CloseAction, ClosePolicy, ServerClosed};
+use crate::codec_stats::{CodecSample, CodecSnapshot, CodecStats};
+use crate::compression::{Compression, DecompressionLimits, Encoding};
+use crate::config::Config;
+use crate::events::{EventDriver, EventHandlers, PanicPolicy, ReconnectScheduled};
+use crate::fanout::{Fanout, LagPolicy, Subscription};
+use crate::connection::{ConnectionInfo, WebSocketClient};
+use crate::handle::{CloseHandshake, ConnectionHandle};
+use crate::history::{ConnectionHistory, HistoryEvent};
+use crate::tasks::spawn_named;
+use crate::transform::{apply_envelope_maps, apply_payload_maps, MessageMap};
+use crate::jitter::jittered;
+use crate::latency::{now_micros, Latency, Timestamps};
+use crate::limits::{ConnectionLimits, Limits};
+use crate::messages::{Envelope, FrameKind, InboundMessage, JsonNumbers, MessageHandler, MessageFormat, TextMode};
+use crate::pool::{BufferPool, PooledBuffer};
+use crate::ratelimit::InboundRateLimit;
+use crate::replay::{ReplayGuard, ReplayPolicy};
+use crate::schema::SchemaMigrations;
+use crate::trace_context::{TraceContext, TracePropagation};
+use crate::topics::Topic;
+use crate::typed_stream::{acknowledged_topics, SubscriptionDiff, Topics, TypedStream};
+use crate::violation::{Direction, ProtocolViolation};
+use crate::wake::WakeProbe;
+use crate::maintenance::{MaintenanceNotice, MaintenancePolicy};
+use crate::flush::{FlushPolicy, FlushState};
+use crate::fragment::{send_fragmented, send_stream, Fragmentation, StreamOptions};
+use crate::pipeline::{self, PipelineConfig, PipelineReceiver, PipelineSender, PipelineTasks, PING_PAYLOAD};
+#[cfg(feature = "session")]
+use crate::session::SessionStore;
+use bytes::Bytes;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+#[cfg(feature = "reconnection")]
+use crate::reconnection::{CircuitBreaker, CircuitState, Exponential, ReconnectStrategy, RetryError};
+#[cfg(feature = "keep-alive")]
 use crate::keep_alive::KeepAlive;
 use log::{info, error, debug, warn};
 use tokio_tungstenite::{WebSocketStream, MaybeTlsStream};
 use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_tungstenite::tungstenite::Message;
 use futures_util::{sink::SinkExt, StreamExt};
 use tokio::time::{sleep, Duration};
 use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use std::collections::BTreeSet;
+use std::future::Future;
 use std::sync::Arc;
 use std::error::Error as StdError;
 
+/// Decides how `receive_or_reconnect` answers a server close, given the number of
+/// consecutive closes already answered with a reconnect. Set with `set_close_policy`.
+pub type ClosePolicyFn = Arc<dyn Fn(&ServerClosed, u32) -> CloseAction + Send + Sync>;
+
+/// Called with every protocol violation before its error is returned. Set with
+/// `set_violation_hook`.
+pub type ViolationHook = Arc<dyn Fn(&ProtocolViolation) + Send + Sync>;
+
+/// The connection a keep-alive task pings.
+#[cfg(feature = "keep-alive")]
+#[derive(Clone)]
+enum KeepAliveTarget {
+    Stream(Arc<Mutex<WebSocketStream<MaybeTlsStream<TcpStream>>>>),
+    Pipeline(PipelineSender),
+}
+
 /// The `WebSocketController` struct is responsible for managing WebSocket connections,
 /// handling reconnections, maintaining keep-alive functionality, and sending/receiving messages.
 pub struct WebSocketController {
     client: Arc<WebSocketClient>,
+    #[cfg(feature = "reconnection")]
     reconnect_strategy: Option<ReconnectStrategy>,
+    #[cfg(feature = "reconnection")]
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
     ping_interval: Duration,
+    ping_jitter: f64,
     retries: u32,
+    backoff_base: Duration,
+    connect_timeout: Option<Duration>,
+    format: MessageFormat,
+    compression: Compression,
+    decompression_limits: DecompressionLimits,
+    text_mode: TextMode,
+    json_numbers: JsonNumbers,
+    frame_kind: Option<FrameKind>,
+    migrations: Option<Arc<SchemaMigrations>>,
+    replay_guard: Option<Arc<ReplayGuard>>,
+    trace_propagation: Option<TracePropagation>,
+    timestamps: Option<Timestamps>,
+    limits: Option<Arc<Limits>>,
+    auth: Option<AuthMessage>,
+    outbound_maps: Vec<MessageMap>,
+    inbound_maps: Vec<MessageMap>,
+    /// The limits slot of the last connection opened by `connect`.
+    raw_connection: std::sync::Mutex<Option<Arc<ConnectionLimits>>>,
+    pipeline_config: PipelineConfig,
+    close_handshake: CloseHandshake,
+    fragmentation: Option<Fragmentation>,
+    buffer_pool: BufferPool,
+    fanout: Fanout,
+    topics: Topics,
+    subscription_sync: Option<Duration>,
+    subscription_manager: Option<Arc<SubscriptionManager>>,
+    close_policy: ClosePolicyFn,
+    close_reconnects: u32,
+    violation_hook: Option<ViolationHook>,
+    history: Option<Arc<ConnectionHistory>>,
+    events: EventHandlers,
+    wake_probe: Option<WakeProbe>,
+    maintenance_policy: Option<MaintenancePolicy>,
+    connection_info: std::sync::Mutex<Option<ConnectionInfo>>,
+    #[cfg(feature = "session")]
+    session: Option<Arc<SessionStore>>,
+    #[cfg(feature = "keep-alive")]
+    keep_alive_task: std::sync::Mutex<Option<JoinHandle<()>>>,
+    /// What the keep-alive task pings, so `reconfigure` can restart it.
+    #[cfg(feature = "keep-alive")]
+    keep_alive_target: std::sync::Mutex<Option<KeepAliveTarget>>,
+    inbound_rate_limit: tokio::sync::watch::Sender<Option<InboundRateLimit>>,
+    /// Set once the first connection is open, authenticated and subscribed.
+    connected: tokio::sync::watch::Sender<bool>,
+    flush_policy: FlushPolicy,
+    flush_state: FlushState,
+    codec_stats: CodecStats,
 }
 
 impl WebSocketController {
@@ -55,151 +161,2094 @@ impl WebSocketController {
     pub fn new(url: &str, retries: u32, ping_interval: Option<u64>) -> Self {
         Self {
             client: Arc::new(WebSocketClient::new(url, retries)),
+            #[cfg(feature = "reconnection")]
             reconnect_strategy: Some(ReconnectStrategy::new(retries, 2)),
+            #[cfg(feature = "reconnection")]
+            circuit_breaker: None,
             ping_interval: Duration::from_secs(ping_interval.unwrap_or(5)),
+            ping_jitter: 0.0,
             retries,
+            backoff_base: Duration::from_secs(1),
+            connect_timeout: None,
+            format: MessageFormat::Json,
+            compression: Compression::None,
+            decompression_limits: DecompressionLimits::default(),
+            text_mode: TextMode::Preserve,
+            json_numbers: JsonNumbers::Native,
+            frame_kind: None,
+            migrations: None,
+            replay_guard: None,
+            trace_propagation: None,
+            timestamps: None,
+            limits: None,
+            auth: None,
+            outbound_maps: Vec::new(),
+            inbound_maps: Vec::new(),
+            raw_connection: std::sync::Mutex::new(None),
+            pipeline_config: PipelineConfig::default(),
+            close_handshake: CloseHandshake::default(),
+            fragmentation: None,
+            buffer_pool: BufferPool::default(),
+            fanout: Fanout::default(),
+            topics: Topics::default(),
+            subscription_sync: None,
+            subscription_manager: None,
+            close_policy: Arc::new(|closed, attempt| ClosePolicy::default().decide(closed, attempt)),
+            close_reconnects: 0,
+            violation_hook: None,
+            history: None,
+            events: EventHandlers::default(),
+            wake_probe: None,
+            maintenance_policy: None,
+            connection_info: std::sync::Mutex::new(None),
+            #[cfg(feature = "session")]
+            session: None,
+            #[cfg(feature = "keep-alive")]
+            keep_alive_task: std::sync::Mutex::new(None),
+            #[cfg(feature = "keep-alive")]
+            keep_alive_target: std::sync::Mutex::new(None),
+            inbound_rate_limit: tokio::sync::watch::channel(None).0,
+            connected: tokio::sync::watch::channel(false).0,
+            flush_policy: FlushPolicy::default(),
+            flush_state: FlushState::new(),
+            codec_stats: CodecStats::default(),
+        }
+    }
+
+    /// Creates a `WebSocketController` from a loaded `Config`.
+    ///
+    /// The controller connects to the config's primary URL and applies:
+    ///
+    /// - `retries` and the reconnect strategy built from `backoff`, `backoff_secs` and
+    ///   `backoff_max_secs`;
+    /// - `urls` and `failover`, handshake retries, the connection timeout and `TCP_NODELAY`;
+    /// - the ping interval and jitter;
+    /// - the message format, compression and decompression limits, text mode and frame kind;
+    /// - replay protection, trace propagation and timestamps;
+    /// - the pipeline settings, inbound rate limit, close handshake, fragmentation and
+    ///   subscription sync timeout;
+    /// - buffer pre-allocation.
+    ///
+    /// The global logger is left alone: `log_level` is for the application to apply, e.g.
+    /// with `log::set_max_level`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration, usually from `Config::load`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the controller, or an error message if the config is invalid.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit::config::Config;
+    /// use websocket_toolkit::controller::WebSocketController;
+    ///
+    /// let config = Config {
+    ///     urls: vec!["ws://example.com".to_string()],
+    ///     retries: 5,
+    ///     ..Config::default()
+    /// };
+    /// let controller = WebSocketController::from_config(&config).unwrap();
+    /// ```
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        config.validate()?;
+        let url = config.primary_url().ok_or("Invalid config: no URL set")?;
+        let mut controller = Self::new(url, config.retries, config.ping_interval_secs);
+        let mut client = WebSocketClient::new(&config.urls, config.retries)
+            .with_failover(config.failover)
+            .with_nodelay(config.tcp_nodelay);
+        if let Some(policy) = config.handshake_retry() {
+            client = client.with_handshake_retry(policy);
+        }
+        #[cfg(feature = "rustls-tls")]
+        {
+            client = client.with_tls_config(crate::tls::client_config(&config.tls)?);
+        }
+        controller.client = Arc::new(client);
+        #[cfg(feature = "reconnection")]
+        {
+            controller.reconnect_strategy = Some(config.reconnect_strategy());
+        }
+        controller.backoff_base = Duration::from_secs(config.backoff_secs);
+        controller.connect_timeout = config.connect_timeout();
+        controller.format = config.format;
+        controller.ping_jitter = config.ping_jitter;
+        controller.compression = config.compression;
+        controller.decompression_limits = config.decompression_limits();
+        controller.text_mode = config.text_mode;
+        controller.json_numbers = config.json_numbers;
+        controller.frame_kind = config.frame_kind;
+        controller.replay_guard = config.replay_policy().map(|policy| Arc::new(ReplayGuard::new(policy)));
+        controller.trace_propagation = config.trace_propagation();
+        controller.timestamps = config.timestamps.then(Timestamps::new);
+        controller.pipeline_config = config.pipeline_config();
+        controller.inbound_rate_limit.send_replace(config.inbound_rate_limit());
+        controller.close_handshake = config.close_handshake();
+        controller.fragmentation = config.fragmentation();
+        controller.subscription_sync = config.subscription_sync_timeout();
+        if config.preallocated_buffers > 0 {
+            controller.buffer_pool = BufferPool::preallocated(4096, config.preallocated_buffers);
+        }
+        Ok(controller)
+    }
+
+    /// Applies the settings of `config` that can change while connected, without dropping
+    /// any connection:
+    ///
+    /// - `urls` and `failover` take effect on the next connection attempt; the current
+    ///   connection stays on its server.
+    /// - `ping_interval_secs` and `ping_jitter` restart the keep-alive task, if one is
+    ///   running, on the same connection.
+    /// - `inbound_messages_per_sec` and `inbound_bytes_per_sec` are published to the
+    ///   limiters that follow `watch_inbound_rate_limit`.
+    ///
+    /// Other settings, including `log_level`, are ignored; build a new controller with `from_config` to change them.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The updated configuration.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if `config` is invalid, in which
+    /// case nothing is changed.
+    pub fn reconfigure(&mut self, config: &Config) -> Result<(), String> {
+        config.validate()?;
+        if self.client.urls() != config.urls || self.client.failover() != config.failover {
+            info!("Reconfigured server URLs: {:?}", config.urls);
+            let client = (*self.client).clone().with_urls(&config.urls).with_failover(config.failover);
+            self.client = Arc::new(client);
+        }
+        let ping_interval = Duration::from_secs(config.ping_interval_secs.unwrap_or(5));
+        if (ping_interval, config.ping_jitter) != (self.ping_interval, self.ping_jitter) {
+            info!("Reconfigured ping interval: {:?}", ping_interval);
+            self.ping_interval = ping_interval;
+            self.ping_jitter = config.ping_jitter;
+            #[cfg(feature = "keep-alive")]
+            self.restart_keep_alive();
+        }
+        let rate_limit = config.inbound_rate_limit();
+        self.inbound_rate_limit.send_if_modified(|current| {
+            let changed = *current != rate_limit;
+            *current = rate_limit;
+            changed
+        });
+        Ok(())
+    }
+
+    /// Sets the inbound rate limit and publishes it to the limiters that follow
+    /// `watch_inbound_rate_limit`.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The rates, or `None` for no limit (the default).
+    pub fn set_inbound_rate_limit(&self, limit: Option<InboundRateLimit>) {
+        self.inbound_rate_limit.send_replace(limit);
+    }
+
+    /// Returns the inbound rate limit, if set.
+    pub fn inbound_rate_limit(&self) -> Option<InboundRateLimit> {
+        *self.inbound_rate_limit.borrow()
+    }
+
+    /// Returns a receiver of inbound rate limit changes, for `InboundRateLimiter::follow`.
+    pub fn watch_inbound_rate_limit(&self) -> tokio::sync::watch::Receiver<Option<InboundRateLimit>> {
+        self.inbound_rate_limit.subscribe()
+    }
+
+    /// Returns the message format configured for this controller; `MessageFormat::Json` unless
+    /// set by `from_config`.
+    pub fn format(&self) -> MessageFormat {
+        self.format
+    }
+
+    /// Returns the payload compression configured for this controller; `Compression::None`
+    /// unless set by `from_config`.
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Sets how far received payloads may expand when decompressed; decoding a payload that
+    /// expands beyond them fails without decompressing the rest.
+    ///
+    /// # Arguments
+    ///
+    /// * `limits` - The new limits; a 16 MiB cap and no ratio limit by default.
+    pub fn set_decompression_limits(&mut self, limits: DecompressionLimits) {
+        self.decompression_limits = limits;
+    }
+
+    /// Returns the current decompression limits.
+    pub fn decompression_limits(&self) -> DecompressionLimits {
+        self.decompression_limits
+    }
+
+    /// Sets how received messages are handed to the application by `receive_inbound` and the
+    /// methods built on it.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - The new text mode; `TextMode::Preserve` by default.
+    pub fn set_text_mode(&mut self, mode: TextMode) {
+        self.text_mode = mode;
+    }
+
+    /// Returns the current text mode.
+    pub fn text_mode(&self) -> TextMode {
+        self.text_mode
+    }
+
+    /// Sets how `receive_decoded` handles JSON numbers that do not fit a native number, e.g.
+    /// `JsonNumbers::Exact` for a feed of prices whose digits must all survive.
+    ///
+    /// # Arguments
+    ///
+    /// * `numbers` - The new mode; `JsonNumbers::Native` by default.
+    pub fn set_json_numbers(&mut self, numbers: JsonNumbers) {
+        self.json_numbers = numbers;
+    }
+
+    /// Returns how JSON numbers are handled.
+    pub fn json_numbers(&self) -> JsonNumbers {
+        self.json_numbers
+    }
+
+    /// Overrides the frame type `send_envelope` uses.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The frame type, or `None` to follow the message format.
+    pub fn set_frame_kind(&mut self, kind: Option<FrameKind>) {
+        self.frame_kind = kind;
+    }
+
+    /// Returns the frame type `send_envelope` uses: the override if set, binary for compressed
+    /// payloads, and otherwise the format's convention (text for JSON, binary for CBOR).
+    pub fn frame_kind(&self) -> FrameKind {
+        match (self.frame_kind, self.compression) {
+            (Some(kind), _) => kind,
+            (None, Compression::None) => FrameKind::for_format(self.format),
+            (None, _) => FrameKind::Binary,
+        }
+    }
+
+    /// Sets the schema migrations applied by `encode_envelope` and `decode_envelope`.
+    ///
+    /// Outgoing envelopes are stamped with the local schema version and downgraded for an
+    /// older peer; received envelopes are upgraded to the local version. See
+    /// `SchemaMigrations`.
+    ///
+    /// # Arguments
+    ///
+    /// * `migrations` - The migrations, or `None` to send and receive envelopes unchanged.
+    pub fn set_migrations(&mut self, migrations: Option<Arc<SchemaMigrations>>) {
+        self.migrations = migrations;
+    }
+
+    /// Returns the schema migrations, if set.
+    pub fn migrations(&self) -> Option<Arc<SchemaMigrations>> {
+        self.migrations.clone()
+    }
+
+    /// Turns on replay protection for `encode_envelope` and `decode_envelope`.
+    ///
+    /// Outgoing envelopes are stamped with a monotonic timestamp and a nonce; received
+    /// envelopes that are unstamped, outside the acceptance window or replayed are rejected.
+    /// See `ReplayGuard`.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The acceptance window and skew tolerance, or `None` to turn replay
+    ///   protection off.
+    pub fn set_replay_policy(&mut self, policy: Option<ReplayPolicy>) {
+        self.replay_guard = policy.map(|policy| Arc::new(ReplayGuard::new(policy)));
+    }
+
+    /// Returns the replay guard, if replay protection is on.
+    pub fn replay_guard(&self) -> Option<Arc<ReplayGuard>> {
+        self.replay_guard.clone()
+    }
+
+    /// Turns on W3C trace context propagation for `encode_envelope`.
+    ///
+    /// Outgoing envelopes without trace context get a child of the context in scope (see
+    /// `TraceContext::scope`), or a new trace sampled at `sample_rate`. See the
+    /// `trace_context` module.
+    ///
+    /// # Arguments
+    ///
+    /// * `propagation` - The header and sample rate, or `None` to turn propagation off.
+    pub fn set_trace_propagation(&mut self, propagation: Option<TracePropagation>) {
+        self.trace_propagation = propagation;
+    }
+
+    /// Returns the trace propagation settings, if propagation is on.
+    pub fn trace_propagation(&self) -> Option<&TracePropagation> {
+        self.trace_propagation.as_ref()
+    }
+
+    /// Returns the context to handle a received envelope in: the sender's trace, or a new
+    /// sampled or unsampled one.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - The received envelope.
+    ///
+    /// # Returns
+    ///
+    /// The `TraceContext`, or `None` if trace propagation is off.
+    pub fn trace_context(&self, envelope: &Envelope) -> Option<TraceContext> {
+        self.trace_propagation.as_ref().map(|propagation| propagation.inbound(envelope))
+    }
+
+    /// Turns on send and receive timestamps: `encode_envelope` stamps outbound envelopes
+    /// with their send time and `decode_envelope` annotates inbound ones with their receive
+    /// time. See the `latency` module.
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamps` - The timestamps, with the server clock estimate if any, or `None` to
+    ///   turn them off.
+    pub fn set_timestamps(&mut self, timestamps: Option<Timestamps>) {
+        self.timestamps = timestamps;
+    }
+
+    /// Returns the timestamp settings, if timestamps are on.
+    pub fn timestamps(&self) -> Option<&Timestamps> {
+        self.timestamps.as_ref()
+    }
+
+    /// Returns the send and receive times of a decoded envelope, and its one-way latency if
+    /// the server's clock is estimated.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - An envelope returned by `decode_envelope`.
+    ///
+    /// # Returns
+    ///
+    /// The `Latency`, or `None` if timestamps are off or the sender did not stamp it.
+    pub fn latency(&self, envelope: &Envelope) -> Option<Latency> {
+        self.timestamps.as_ref()?.latency(envelope)
+    }
+
+    /// Registers the controller against shared resource limits.
+    ///
+    /// Every connection then takes a connection slot (failing if none is free) and waits for
+    /// a handshake slot, and pipelined connections reserve their queued bytes. See `Limits`.
+    ///
+    /// # Arguments
+    ///
+    /// * `limits` - The limits, usually shared with other controllers, or `None` for no
+    ///   limits.
+    pub fn set_limits(&mut self, limits: Option<Arc<Limits>>) {
+        self.limits = limits;
+    }
+
+    /// Returns the limits the controller is registered against, if any.
+    pub fn limits(&self) -> Option<Arc<Limits>> {
+        self.limits.clone()
+    }
+
+    /// Adds a rewrite applied to every envelope `encode_envelope` encodes, after the maps
+    /// added before it; see the `transform` module.
+    ///
+    /// # Arguments
+    ///
+    /// * `map` - The rewrite, at the envelope or the serialized level.
+    pub fn map_outbound(&mut self, map: MessageMap) {
+        self.outbound_maps.push(map);
+    }
+
+    /// Adds a rewrite applied to every envelope `decode_envelope` decodes, after the maps
+    /// added before it; see the `transform` module.
+    ///
+    /// # Arguments
+    ///
+    /// * `map` - The rewrite, at the envelope or the serialized level.
+    pub fn map_inbound(&mut self, map: MessageMap) {
+        self.inbound_maps.push(map);
+    }
+
+    /// Sets the auth frame sent first on every connection.
+    ///
+    /// The frame is sent as part of `connect`, `connect_pipeline` and `connect_handle`, before
+    /// the stream or pipeline is handed out, so no application message can precede it. A
+    /// rejected auth fails the connect with `auth::AuthRejected`, which reconnect loops do not
+    /// retry.
+    ///
+    /// # Arguments
+    ///
+    /// * `auth` - The auth frame and its deadline, or `None` to send none.
+    pub fn set_auth_message(&mut self, auth: Option<AuthMessage>) {
+        self.auth = auth;
+    }
+
+    /// Returns the auth frame sent first on every connection, if any.
+    pub fn auth_message(&self) -> Option<&AuthMessage> {
+        self.auth.as_ref()
+    }
+
+    /// Replays the subscriptions recorded in `manager` on every connection, right after the
+    /// auth frame; see the `subscriptions` module.
+    ///
+    /// # Arguments
+    ///
+    /// * `manager` - The recorded subscriptions, or `None` to replay none.
+    pub fn set_subscription_manager(&mut self, manager: Option<Arc<SubscriptionManager>>) {
+        self.subscription_manager = manager;
+    }
+
+    /// Returns the subscriptions replayed on every connection, if any.
+    pub fn subscription_manager(&self) -> Option<Arc<SubscriptionManager>> {
+        self.subscription_manager.clone()
+    }
+
+    /// Records the controller's connects, disconnects, errors and directly sent and received
+    /// messages in `history`; see the `history` module.
+    ///
+    /// # Arguments
+    ///
+    /// * `history` - The ring to record in, or `None` to stop recording.
+    pub fn set_history(&mut self, history: Option<Arc<ConnectionHistory>>) {
+        self.history = history;
+    }
+
+    /// Returns the ring the controller records in, if any.
+    pub fn history(&self) -> Option<Arc<ConnectionHistory>> {
+        self.history.clone()
+    }
+
+    /// Records the event built by `event` if a history is set, dumping the history to the
+    /// error log for errors if it asks for that.
+    fn record(&self, event: impl FnOnce() -> HistoryEvent) {
+        if let Some(history) = &self.history {
+            let event = event();
+            let is_error = event.is_error();
+            history.record(event);
+            if is_error && history.dumps_on_error() {
+                error!("Recent WebSocket history:\n{}", history.dump());
+            }
+        }
+    }
+
+    /// Sets a hook called with a structured report of every protocol violation the
+    /// controller's receive and send methods run into, e.g. to count violations per rule.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook` - Called with each `ProtocolViolation`, before the error is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit::controller::WebSocketController;
+    /// use log::warn;
+    ///
+    /// let mut controller = WebSocketController::new("ws://example.com", 3, None);
+    /// controller.set_violation_hook(|violation| warn!("{}", violation));
+    /// ```
+    pub fn set_violation_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&ProtocolViolation) + Send + Sync + 'static,
+    {
+        self.violation_hook = Some(Arc::new(hook));
+    }
+
+    /// Passes `violation` to the violation hook, if any.
+    fn report_violation(&self, violation: ProtocolViolation) {
+        debug!("{}", violation);
+        if let Some(hook) = &self.violation_hook {
+            hook(&violation);
+        }
+    }
+
+    /// Reports `error` to the violation hook if it is a protocol violation.
+    fn report_error(&self, error: &tokio_tungstenite::tungstenite::Error, direction: Direction) {
+        if let Some(violation) = ProtocolViolation::from_error(error, direction) {
+            self.report_violation(violation);
+        }
+    }
+
+    /// Sets the handler `EventDriver` calls with the handle of every new connection, e.g. to
+    /// subscribe or to keep a clone for sending.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - Called on the driver task after every connect.
+    pub fn on_open<F>(&mut self, handler: F)
+    where
+        F: Fn(&ConnectionHandle) + Send + Sync + 'static,
+    {
+        self.events.open = Some(Arc::new(handler));
+    }
+
+    /// Sets the handler `EventDriver` calls with every text and binary message.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - Called on the driver task with each message, in order.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit::controller::WebSocketController;
+    ///
+    /// let mut controller = WebSocketController::new("ws://example.com", 3, None);
+    /// controller.on_message(|message| println!("{:?}", message));
+    /// ```
+    pub fn on_message<F>(&mut self, handler: F)
+    where
+        F: Fn(InboundMessage) + Send + Sync + 'static,
+    {
+        self.events.message = Some(Arc::new(handler));
+    }
+
+    /// Sets the handler `EventDriver` calls when a connection ends.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - Called with the server's close frame, or `None` if the connection was
+    ///   dropped without one or stopped locally.
+    pub fn on_close<F>(&mut self, handler: F)
+    where
+        F: Fn(Option<&ServerClosed>) + Send + Sync + 'static,
+    {
+        self.events.close = Some(Arc::new(handler));
+    }
+
+    /// Sets the handler `EventDriver` calls with every failed connection attempt.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - Called with the attempt's error; downcast it to inspect it.
+    pub fn on_error<F>(&mut self, handler: F)
+    where
+        F: Fn(&(dyn StdError + 'static)) + Send + Sync + 'static,
+    {
+        self.events.error = Some(Arc::new(handler));
+    }
+
+    /// Sets the handler `EventDriver` calls before every wait for a reconnect, e.g. to show
+    /// "reconnecting in 12s (attempt 3/10)" with a cancel button.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - Called with the attempt, the retry limit, the delay and when it ends;
+    ///   `ReconnectScheduled::cancel` stops the driver instead of reconnecting.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit::controller::WebSocketController;
+    ///
+    /// let mut controller = WebSocketController::new("ws://example.com", 10, None);
+    /// controller.on_reconnect_scheduled(|scheduled| println!("{}", scheduled));
+    /// ```
+    pub fn on_reconnect_scheduled<F>(&mut self, handler: F)
+    where
+        F: Fn(&ReconnectScheduled) + Send + Sync + 'static,
+    {
+        self.events.reconnect_scheduled = Some(Arc::new(handler));
+    }
+
+    /// Sets what `EventDriver` does when `on_open`, `on_message` or `on_close` panics. The
+    /// panic is always reported to `on_error` as an `events::HandlerPanic`.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - Whether to keep calling the handler (`PanicPolicy::Restart`, the
+    ///   default), stop calling it, or end the driver.
+    pub fn set_handler_panic_policy(&mut self, policy: PanicPolicy) {
+        self.events.panic_policy = policy;
+    }
+
+    /// Returns what `EventDriver` does when a handler panics.
+    pub fn handler_panic_policy(&self) -> PanicPolicy {
+        self.events.panic_policy
+    }
+
+    /// Starts a task that connects and calls the `on_open`, `on_message`, `on_close` and
+    /// `on_error` handlers, reconnecting as the close policy and retries allow; see the
+    /// `events` module.
+    ///
+    /// Must be called within a tokio runtime.
+    ///
+    /// # Returns
+    ///
+    /// The running `EventDriver`, which sends on and stops the connection.
+    pub fn run_events(self) -> EventDriver {
+        EventDriver::start(Arc::new(self))
+    }
+
+    /// Returns the registered event handlers.
+    pub(crate) fn event_handlers(&self) -> EventHandlers {
+        self.events.clone()
+    }
+
+    /// Returns the number of connection attempts retried after a failure.
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// Replaces the strategy that `reconnect_if_needed` and `ManagedConnection` reconnect
+    /// with, e.g. to use another `BackoffStrategy`, jitter or a time budget.
+    ///
+    /// Available with the `reconnection` feature.
+    ///
+    /// # Arguments
+    ///
+    /// * `strategy` - The strategy, or `None` to fall back to the controller's retries and
+    ///   exponential backoff.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use websocket_toolkit::controller::WebSocketController;
+    /// use websocket_toolkit::reconnection::{Exponential, Jitter, ReconnectStrategy};
+    ///
+    /// let mut controller = WebSocketController::new("ws://example.com", 5, None);
+    /// controller.set_reconnect_strategy(Some(
+    ///     ReconnectStrategy::new_with_backoff(5, Exponential { base: Duration::from_secs(1), max: Duration::from_secs(30) })
+    ///         .with_jitter(Jitter::Full),
+    /// ));
+    /// ```
+    #[cfg(feature = "reconnection")]
+    pub fn set_reconnect_strategy(&mut self, strategy: Option<ReconnectStrategy>) {
+        self.reconnect_strategy = strategy;
+    }
+
+    /// Returns the configured reconnection strategy, if any.
+    #[cfg(feature = "reconnection")]
+    pub fn reconnect_strategy(&self) -> Option<&ReconnectStrategy> {
+        self.reconnect_strategy.as_ref()
+    }
+
+    /// Returns the strategy used without one set: the controller's retries with exponential
+    /// backoff from its base delay.
+    #[cfg(feature = "reconnection")]
+    pub(crate) fn default_reconnect_strategy(&self) -> ReconnectStrategy {
+        ReconnectStrategy::new_with_backoff(self.retries, Exponential { base: self.backoff_base, max: Duration::MAX })
+    }
+
+    /// Applies the close policy to a close after `attempt` consecutive reconnects.
+    pub(crate) fn close_action(&self, closed: &ServerClosed, attempt: u32) -> CloseAction {
+        (self.close_policy)(closed, attempt)
+    }
+
+    /// Returns the pipeline settings from the controller's config, for `connect_pipeline` and
+    /// `connect_handle`.
+    pub fn pipeline_config(&self) -> PipelineConfig {
+        self.pipeline_config
+    }
+
+    /// Sets how the handles opened by `connect_handle` and `connect_handle_at` close their
+    /// connection; see `ConnectionHandle::set_close_handshake`.
+    ///
+    /// # Arguments
+    ///
+    /// * `handshake` - The reply timeout, linger and summary settings.
+    pub fn set_close_handshake(&mut self, handshake: CloseHandshake) {
+        self.close_handshake = handshake;
+    }
+
+    /// Returns how the handles the controller opens close their connection.
+    pub fn close_handshake(&self) -> CloseHandshake {
+        self.close_handshake
+    }
+
+    /// Sends messages above a size in fragments; see the `fragment` module.
+    ///
+    /// Applies to `send_message`, `send_bytes`, `send_envelope`, `send_pooled` and `send_raw`,
+    /// and to the pipelines the controller opens whose `PipelineConfig` sets no fragmentation
+    /// of its own. Messages queued with `feed_message` are not fragmented.
+    ///
+    /// # Arguments
+    ///
+    /// * `fragmentation` - The threshold and fragment size, or `None` to send every message
+    ///   as a single frame (the default).
+    pub fn set_fragmentation(&mut self, fragmentation: Option<Fragmentation>) {
+        self.fragmentation = fragmentation;
+    }
+
+    /// Returns the fragmentation settings, if set.
+    pub fn fragmentation(&self) -> Option<Fragmentation> {
+        self.fragmentation
+    }
+
+    /// Returns the controller's payload buffer pool, for `send_pooled` and `receive_pooled`.
+    ///
+    /// Clones share the same buffers. With `Config::preallocated_buffers`, the pool starts
+    /// out full.
+    pub fn buffer_pool(&self) -> BufferPool {
+        self.buffer_pool.clone()
+    }
+
+    /// Returns the encoding applied by `encode_envelope` on the current connection.
+    ///
+    /// With compression configured, this is `Encoding::PermessageDeflate` if the server of
+    /// the last connection accepted `permessage-deflate`, and application-level compression
+    /// otherwise (including before the first connection). See `Encoding::negotiate`.
+    pub fn encoding(&self) -> Encoding {
+        let connection_info = self.connection_info.lock().unwrap();
+        let extensions = connection_info.as_ref().map(|info| info.extensions.as_slice()).unwrap_or_default();
+        Encoding::negotiate(self.compression, extensions)
+    }
+
+    /// Encodes `envelope` in the configured format and applies the connection's encoding.
+    ///
+    /// With compression configured, the payload is annotated with the encoding applied (see
+    /// `Encoding::annotate`), so peers can decode it whichever encoding was negotiated.
+    /// Without compression, the encoded envelope is sent as it is. With schema migrations set,
+    /// the envelope is first migrated to the peer's schema version. With replay protection
+    /// on, it is stamped with a timestamp and nonce. With trace propagation on, it carries
+    /// trace context. With timestamps on, it carries its send time. Outbound maps (see
+    /// `map_outbound`) run before migration and, for serialized maps, after encoding. The
+    /// time taken, the compression ratio and the payload size are recorded in `codec_stats`.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - The message to encode.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the payload to send, or an error message on failure.
+    pub fn encode_envelope(&self, envelope: &Envelope) -> Result<Vec<u8>, String> {
+        let started = std::time::Instant::now();
+        let encoded = if self.migrations.is_none()
+            && self.replay_guard.is_none()
+            && self.trace_propagation.is_none()
+            && self.timestamps.is_none()
+            && self.outbound_maps.is_empty()
+        {
+            envelope.encode(self.format)?
+        } else {
+            let mut envelope = apply_envelope_maps(&self.outbound_maps, envelope.clone())?;
+            if let Some(propagation) = &self.trace_propagation {
+                envelope = propagation.inject(envelope);
+            }
+            if let Some(timestamps) = &self.timestamps {
+                envelope = timestamps.stamp(envelope);
+            }
+            if let Some(migrations) = &self.migrations {
+                envelope = migrations.prepare_outgoing(envelope)?;
+            }
+            if let Some(guard) = &self.replay_guard {
+                envelope = guard.stamp(envelope);
+            }
+            apply_payload_maps(&self.outbound_maps, envelope.encode(self.format)?)?
+        };
+        let codec_time = started.elapsed();
+        let (payload, compression) = match self.compression {
+            Compression::None => (encoded, None),
+            _ => {
+                let started = std::time::Instant::now();
+                let payload = self.encoding().annotate(&encoded)?;
+                (payload, Some((encoded.len(), started.elapsed())))
+            }
+        };
+        self.codec_stats.record_encode(CodecSample {
+            format: self.format,
+            codec_time,
+            compression,
+            wire_bytes: payload.len(),
+        });
+        Ok(payload)
+    }
+
+    /// Reverses the encoding `payload` is annotated with and decodes it in the configured
+    /// format; the inverse of `encode_envelope`. With replay protection on, replayed and
+    /// stale envelopes are rejected. With schema migrations set, the envelope is then
+    /// upgraded to the local schema version. Inbound maps (see `map_inbound`) run on the
+    /// serialized envelope before decoding and on the envelope last. With timestamps on, the
+    /// envelope is annotated with the time this call started. Like encoding, decoding is
+    /// recorded in `codec_stats`.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The received payload.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the envelope, or an error message on failure.
+    pub fn decode_envelope(&self, payload: &[u8]) -> Result<Envelope, String> {
+        self.decode_envelope_with_encoding(payload).map(|(envelope, _)| envelope)
+    }
+
+    /// Like `decode_envelope`, but also returns the encoding the sender applied.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The received payload.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the envelope and its encoding (`Encoding::Identity` for
+    /// untagged payloads), or an error message on failure.
+    pub fn decode_envelope_with_encoding(&self, payload: &[u8]) -> Result<(Envelope, Encoding), String> {
+        let received_at = self.timestamps.as_ref().map(|_| now_micros());
+        let started = std::time::Instant::now();
+        // Peers tag payloads only with compression configured, so whatever is configured
+        // here, tagged payloads are unwrapped and untagged ones are taken as they are.
+        let (encoding, encoded) = Encoding::read_annotated_limited(payload, self.decompression_limits)?;
+        let compression = match encoding {
+            Encoding::Application(_) => Some((encoded.len(), started.elapsed())),
+            _ => None,
+        };
+        let started = std::time::Instant::now();
+        let encoded = apply_payload_maps(&self.inbound_maps, encoded)?;
+        let envelope = Envelope::decode(&encoded, self.format)?;
+        self.codec_stats.record_decode(CodecSample {
+            format: self.format,
+            codec_time: started.elapsed(),
+            compression,
+            wire_bytes: payload.len(),
+        });
+        if let Some(guard) = &self.replay_guard {
+            guard
+                .verify(&envelope)
+                .map_err(|e| format!("Rejected {} envelope: {}", envelope.kind, e))?;
+        }
+        let envelope = match &self.migrations {
+            Some(migrations) => migrations.upgrade(envelope)?,
+            None => envelope,
+        };
+        let envelope = apply_envelope_maps(&self.inbound_maps, envelope)?;
+        let envelope = match (&self.timestamps, received_at) {
+            (Some(timestamps), Some(received_at)) => timestamps.annotate_at(envelope, received_at),
+            _ => envelope,
+        };
+        Ok((envelope, encoding))
+    }
+
+    /// Establishes a WebSocket connection.
+    ///
+    /// With `Limits` set, the connection holds a connection slot until the controller
+    /// connects again or is dropped, and waits for a handshake slot before the configured
+    /// timeout starts.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a `WebSocketStream` if the connection is successful,
+    /// or a boxed error if the connection fails, exceeds the configured timeout or no
+    /// connection slot is free.
+    pub async fn connect(
+        &self,
+    ) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, Box<dyn StdError>> {
+        // The new connection replaces the previous one, and with it its slot.
+        self.raw_connection.lock().unwrap().take();
+        let (ws_stream, slot) = self.connect_limited(None).await?;
+        *self.raw_connection.lock().unwrap() = slot;
+        Ok(ws_stream)
+    }
+
+    /// Takes a connection slot and a handshake slot if `Limits` are set, and connects to
+    /// `url` (the controller's URL if `None`), recording the attempt and its outcome in the
+    /// history.
+    async fn connect_limited(
+        &self,
+        url: Option<&str>,
+    ) -> Result<(WebSocketStream<MaybeTlsStream<TcpStream>>, Option<Arc<ConnectionLimits>>), Box<dyn StdError>> {
+        let target = url.unwrap_or(&self.client.url);
+        // Dropping the attempt unreported, if this future is cancelled, frees a half-open probe.
+        #[cfg(feature = "reconnection")]
+        let attempt = match &self.circuit_breaker {
+            Some(breaker) => Some(breaker.acquire(target)?),
+            None => None,
+        };
+        self.record(|| HistoryEvent::Connecting { url: target.to_string() });
+        let result = self.open_connection(url).await;
+        match &result {
+            Ok(_) => {
+                self.codec_stats.reset();
+                self.record(|| HistoryEvent::Connected)
+            }
+            Err(e) => self.record(|| HistoryEvent::Error(format!("Failed to connect: {}", e))),
+        }
+        #[cfg(feature = "reconnection")]
+        if let Some(attempt) = attempt {
+            match &result {
+                Ok(_) => attempt.succeeded(),
+                Err(_) => attempt.failed(),
+            }
+        }
+        result
+    }
+
+    /// Returns the codec statistics of the current connection: encode and decode times per
+    /// format, the compression ratio in each direction and percentiles of recent message
+    /// sizes, to judge whether CBOR or compression pay off for the actual traffic.
+    ///
+    /// Only envelopes passing through `encode_envelope` and `decode_envelope` are counted;
+    /// the statistics restart when a new connection opens.
+    ///
+    /// # Returns
+    ///
+    /// A `CodecSnapshot` of the statistics recorded so far.
+    pub fn codec_stats(&self) -> CodecSnapshot {
+        self.codec_stats.snapshot()
+    }
+
+    /// Guards connection attempts with `breaker`: once the controller's URL has failed too
+    /// often in a row, attempts fail at once with a `reconnection::CircuitOpen` error until
+    /// the breaker's cooldown ends.
+    ///
+    /// The breaker may be shared with other controllers; its circuits are per URL.
+    ///
+    /// Available with the `reconnection` feature.
+    ///
+    /// # Arguments
+    ///
+    /// * `breaker` - The circuit breaker, or `None` to attempt every connection (the default).
+    #[cfg(feature = "reconnection")]
+    pub fn set_circuit_breaker(&mut self, breaker: Option<Arc<CircuitBreaker>>) {
+        self.circuit_breaker = breaker;
+    }
+
+    /// Returns the state of the circuit for the controller's URL, e.g. to show that the
+    /// server is considered down.
+    ///
+    /// Available with the `reconnection` feature.
+    ///
+    /// # Returns
+    ///
+    /// The circuit state, or `None` if no circuit breaker is set.
+    #[cfg(feature = "reconnection")]
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.circuit_breaker.as_ref().map(|breaker| breaker.state(&self.client.url))
+    }
+
+    /// Takes the limits' slots and connects; see `connect_limited`.
+    async fn open_connection(
+        &self,
+        url: Option<&str>,
+    ) -> Result<(WebSocketStream<MaybeTlsStream<TcpStream>>, Option<Arc<ConnectionLimits>>), Box<dyn StdError>> {
+        let slot = match &self.limits {
+            Some(limits) => Some(limits.admit()?),
+            None => None,
+        };
+        let _handshake = match &self.limits {
+            Some(limits) => Some(limits.handshake().await),
+            None => None,
+        };
+        let redirected = url.map(|url| {
+            let mut client = (*self.client).clone();
+            client.url = url.to_string();
+            client
+        });
+        let client = redirected.as_ref().unwrap_or(&*self.client);
+        #[cfg(feature = "session")]
+        let session_client = self.session.as_ref().map(|store| {
+            store
+                .session()
+                .headers()
+                .into_iter()
+                .fold(client.clone(), |client, (name, value)| client.with_header(name, value))
+        });
+        #[cfg(feature = "session")]
+        let client = session_client.as_ref().unwrap_or(client);
+
+        let connect = client.connect_with_info();
+        let result = match self.connect_timeout {
+            Some(limit) => tokio::time::timeout(limit, connect)
+                .await
+                .map_err(|_| format!("Connection timed out after {:?}", limit))?,
+            None => connect.await,
+        };
+        let (mut ws_stream, connection_info) = result.map_err(|e| Box::new(e) as Box<dyn StdError>)?;
+        if let Some(auth) = &self.auth {
+            auth.authenticate(&mut ws_stream).await?;
+        }
+        let desired = self.topics.topics().into_iter().collect();
+        let acknowledged = match self.subscription_sync {
+            Some(timeout) => acknowledged_topics(self, &mut ws_stream, timeout).await?.unwrap_or_default(),
+            None => Default::default(),
+        };
+        let managed: BTreeSet<String> = match &self.subscription_manager {
+            Some(manager) => manager.keys().into_iter().collect(),
+            None => BTreeSet::new(),
+        };
+        let mut diff = SubscriptionDiff::new(&desired, &acknowledged);
+        // The manager's subscriptions are replayed with its own frames, not unsubscribed.
+        diff.unsubscribe.retain(|topic| !managed.contains(topic));
+        if !acknowledged.is_empty() {
+            debug!(
+                "Server has {} subscriptions; sending {} subscribes and {} unsubscribes",
+                acknowledged.len(),
+                diff.subscribe.len(),
+                diff.unsubscribe.len()
+            );
+        }
+        for envelope in diff.envelopes() {
+            ws_stream.send(self.subscription_frame(&envelope)?).await?;
+        }
+        if let Some(manager) = &self.subscription_manager {
+            for frame in manager.replay_missing(&acknowledged)? {
+                ws_stream.send(frame).await?;
+            }
+        }
+        #[cfg(feature = "session")]
+        if let Some(store) = &self.session {
+            let saved = store.update(|session| {
+                session.url = Some(connection_info.url.clone());
+                for cookie in &connection_info.set_cookies {
+                    session.apply_set_cookie(cookie);
+                }
+            });
+            if let Err(e) = saved {
+                warn!("{}", e);
+            }
+        }
+        *self.connection_info.lock().unwrap() = Some(connection_info);
+        self.connected.send_replace(true);
+        Ok((ws_stream, slot))
+    }
+
+    /// Persists the controller's session in `store` and restores it.
+    ///
+    /// If the store holds a URL from an earlier run, the controller connects there instead of
+    /// its configured URL. Every handshake then sends the session's auth token and cookies,
+    /// and every successful connection records its URL and the cookies the server set.
+    ///
+    /// Available with the `session` feature.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - The opened session store.
+    #[cfg(feature = "session")]
+    pub fn set_session(&mut self, store: Arc<SessionStore>) {
+        if let Some(url) = store.session().url {
+            info!("Restoring session for {}", url);
+            let mut client = (*self.client).clone();
+            client.url = url;
+            self.client = Arc::new(client);
+        }
+        self.session = Some(store);
+    }
+
+    /// Returns the session store set by `set_session`, e.g. to update the auth token or
+    /// resume state.
+    ///
+    /// Available with the `session` feature.
+    #[cfg(feature = "session")]
+    pub fn session(&self) -> Option<Arc<SessionStore>> {
+        self.session.clone()
+    }
+
+    /// Returns the details of the most recent successful connection.
+    ///
+    /// # Returns
+    ///
+    /// The peer address, TLS parameters, negotiated subprotocol and extensions and handshake
+    /// duration of the last connection made by `connect`, or `None` before the first one.
+    pub fn connection_info(&self) -> Option<ConnectionInfo> {
+        self.connection_info.lock().unwrap().clone()
+    }
+
+    /// Returns whether a connection has been opened, with its auth and subscriptions sent.
+    pub fn has_connected(&self) -> bool {
+        *self.connected.borrow()
+    }
+
+    /// Returns a future that resolves once the controller has opened its first connection
+    /// (by any of `connect`, `connect_pipeline` or `connect_handle`) and sent its auth
+    /// message and typed stream subscriptions. It resolves at once if that already happened.
+    ///
+    /// The future does not borrow the controller, so a service can hand it to the components
+    /// that must wait for the connection before starting.
+    ///
+    /// # Returns
+    ///
+    /// A future of `Ok(())` once connected, or an error message if the controller is dropped
+    /// first.
+    pub fn readiness(&self) -> impl Future<Output = Result<(), String>> + Send + 'static {
+        let mut connected = self.connected.subscribe();
+        async move {
+            while !*connected.borrow_and_update() {
+                connected
+                    .changed()
+                    .await
+                    .map_err(|_| "Controller dropped before connecting".to_string())?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Waits until the controller has opened its first connection; see `readiness`.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - How long to wait.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the controller is connected, or an error message if it did not
+    /// connect within `timeout`.
+    pub async fn wait_connected(&self, timeout: Duration) -> Result<(), String> {
+        tokio::time::timeout(timeout, self.readiness())
+            .await
+            .map_err(|_| format!("Not connected within {:?}", timeout))?
+    }
+
+    /// Subscribes to every data message the controller receives from now on.
+    ///
+    /// Messages received with `receive_inbound` (and the methods built on it) are published
+    /// to all subscribers; for a pipelined connection, pass its receiver to `fanout().forward`.
+    /// Up to 1024 messages are retained for subscribers that fall behind.
+    ///
+    /// # Returns
+    ///
+    /// A `broadcast::Receiver`; its `recv` reports lag as `RecvError::Lagged`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit::controller::WebSocketController;
+    /// use websocket_toolkit::testing::EchoServer;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let server = EchoServer::start().await.unwrap();
+    /// let mut controller = WebSocketController::new(server.url(), 0, None);
+    /// let mut metrics = controller.subscribe();
+    ///
+    /// let mut ws_stream = controller.connect().await.unwrap();
+    /// controller.send_message(&mut ws_stream, b"hello").await.unwrap();
+    /// controller.receive_inbound(&mut ws_stream).await.unwrap();
+    /// assert_eq!(metrics.recv().await.unwrap().as_bytes(), b"hello");
+    /// # }
+    /// ```
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<InboundMessage> {
+        self.fanout.subscribe()
+    }
+
+    /// Subscribes like `subscribe`, handling lag with `policy`.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - Whether a lagging subscriber skips ahead or is closed.
+    ///
+    /// # Returns
+    ///
+    /// A new `Subscription`.
+    pub fn subscribe_with(&self, policy: LagPolicy) -> Subscription {
+        self.fanout.subscribe_with(policy)
+    }
+
+    /// Returns the controller's fan-out, e.g. to `forward` a pipeline's messages to the
+    /// subscribers.
+    pub fn fanout(&self) -> Fanout {
+        self.fanout.clone()
+    }
+
+    /// Returns a stream of the envelopes of kind `topic` on the fan-out, with their payload
+    /// decoded as `T` in the controller's format.
+    ///
+    /// While a stream of the topic is open, the controller subscribes to it on every
+    /// connection it opens, and on its latest pipeline right away; see the `typed_stream`
+    /// module.
+    ///
+    /// # Arguments
+    ///
+    /// * `topic` - The envelope kind to yield, and the topic to subscribe to.
+    ///
+    /// # Returns
+    ///
+    /// A new `TypedStream`.
+    pub fn typed_stream<T: DeserializeOwned + Send + 'static>(self: &Arc<Self>, topic: &str) -> TypedStream<T> {
+        TypedStream::new(self.clone(), topic)
+    }
+
+    /// Returns a typed stream of the topic `T` is mapped to by `ws_topics!`; see
+    /// `typed_stream`.
+    ///
+    /// # Returns
+    ///
+    /// A new `TypedStream` of `T::TOPIC`.
+    pub fn topic_stream<T: Topic + DeserializeOwned + Send + 'static>(self: &Arc<Self>) -> TypedStream<T> {
+        self.typed_stream(T::TOPIC)
+    }
+
+    /// Asks the server which topics it has already acknowledged before subscribing on a new
+    /// connection, and sends only the missing subscriptions and the stale ones'
+    /// unsubscriptions; see the `typed_stream` module. The subscription manager's recorded
+    /// frames are only replayed for keys the server lacks.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - How long to wait for the server's list before replaying every
+    ///   subscription, or `None` to always replay them (the default).
+    pub fn set_subscription_sync(&mut self, timeout: Option<Duration>) {
+        self.subscription_sync = timeout;
+    }
+
+    /// Returns how long the controller waits for the server's subscription list, if it asks
+    /// for one.
+    pub fn subscription_sync(&self) -> Option<Duration> {
+        self.subscription_sync
+    }
+
+    /// Returns the topics of the open typed streams.
+    pub(crate) fn topics(&self) -> &Topics {
+        &self.topics
+    }
+
+    /// Encodes `envelope` and frames it for sending.
+    pub(crate) fn subscription_frame(&self, envelope: &Envelope) -> Result<Message, String> {
+        self.frame_kind().frame(self.encode_envelope(envelope)?)
+    }
+
+    /// Establishes a WebSocket connection and hands it to dedicated reader and writer tasks.
+    ///
+    /// Use this instead of sharing the stream behind a `Mutex` when several tasks send or
+    /// receive concurrently; see the `pipeline` module.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The pipeline's channel sizes.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the pipeline's sender, receiver and task handles, or a boxed
+    /// error if the connection fails.
+    pub async fn connect_pipeline(
+        &self,
+        config: PipelineConfig,
+    ) -> Result<(PipelineSender, PipelineReceiver, PipelineTasks), Box<dyn StdError>> {
+        let (ws_stream, slot) = self.connect_limited(None).await?;
+        let config = PipelineConfig { fragmentation: config.fragmentation.or(self.fragmentation), ..config };
+        let (sender, receiver, tasks) = pipeline::spawn_limited(ws_stream, config, slot);
+        self.topics.set_live(sender.clone());
+        Ok((sender, receiver, tasks))
+    }
+
+    /// Establishes a pipelined connection and returns a cloneable handle to it.
+    ///
+    /// Clones of the handle can be moved into other tasks, so one task can send while another
+    /// awaits responses without sharing the controller or the stream; see the `handle` module.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The pipeline's channel sizes.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the handle, or a boxed error if the connection fails.
+    pub async fn connect_handle(&self, config: PipelineConfig) -> Result<ConnectionHandle, Box<dyn StdError>> {
+        let (sender, receiver, tasks) = self.connect_pipeline(config).await?;
+        let handle = ConnectionHandle::new(sender, receiver, tasks);
+        handle.set_close_handshake(self.close_handshake);
+        Ok(handle)
+    }
+
+    /// Like `connect_handle`, but connects to `url` instead of the controller's URL, e.g. to
+    /// the alternate endpoint of a maintenance notice. Everything else (headers, session,
+    /// auth, limits) is the same.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The endpoint to connect to.
+    /// * `config` - The pipeline's channel sizes.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the handle, or a boxed error if the connection fails.
+    pub async fn connect_handle_at(&self, url: &str, config: PipelineConfig) -> Result<ConnectionHandle, Box<dyn StdError>> {
+        let (ws_stream, slot) = self.connect_limited(Some(url)).await?;
+        let config = PipelineConfig { fragmentation: config.fragmentation.or(self.fragmentation), ..config };
+        let (sender, receiver, tasks) = pipeline::spawn_limited(ws_stream, config, slot);
+        self.topics.set_live(sender.clone());
+        let handle = ConnectionHandle::new(sender, receiver, tasks);
+        handle.set_close_handshake(self.close_handshake);
+        Ok(handle)
+    }
+
+    /// Makes the controller's `EventDriver` move its connection when the server announces
+    /// maintenance; see the `maintenance` module.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - How early and over how long to move, or `None` to ignore maintenance
+    ///   notices (the default).
+    pub fn set_maintenance_policy(&mut self, policy: Option<MaintenancePolicy>) {
+        self.maintenance_policy = policy;
+    }
+
+    /// Returns the maintenance policy, if set.
+    pub fn maintenance_policy(&self) -> Option<MaintenancePolicy> {
+        self.maintenance_policy
+    }
+
+    /// Reads a maintenance notice from a received message.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - A received data message.
+    ///
+    /// # Returns
+    ///
+    /// The notice, or `None` if the message is not a maintenance notice in the controller's
+    /// format.
+    pub fn maintenance_notice(&self, message: &InboundMessage) -> Option<MaintenanceNotice> {
+        let envelope = self.decode_envelope(message.as_bytes()).ok()?;
+        MaintenanceNotice::from_envelope(&envelope, self.format)
+    }
+
+    /// Connects to the WebSocket server and sends a message.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to send as a byte slice.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn connect_and_send_message(
+        &mut self,
+        message: &[u8],
+    ) -> Result<(), Box<dyn StdError>> {
+        let mut ws_stream = self.connect().await?;
+        self.send_message(&mut ws_stream, message).await?;
+        Ok(())
+    }
+
+    /// Disconnects from the WebSocket server gracefully.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn disconnect(&self) -> Result<(), Box<dyn StdError>> {
+        #[cfg(feature = "keep-alive")]
+        if let Some(task) = self.take_keep_alive_task() {
+            task.abort();
+        }
+        self.client.disconnect();
+        self.record(|| HistoryEvent::Disconnected);
+        Ok(())
+    }
+
+    /// Receives a message from the WebSocket server.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the received message as a `Vec<u8>` or an error.
+    pub async fn receive_message<S>(
+        &mut self,
+        ws_stream: &mut WebSocketStream<S>,
+    ) -> Result<Option<Vec<u8>>, Box<dyn StdError>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        Ok(self.receive_inbound(ws_stream).await?.map(InboundMessage::into_bytes))
+    }
+
+    /// Receives a data message from the WebSocket server, keeping its text or binary kind.
+    ///
+    /// The message is also published to the controller's subscribers (see `subscribe`).
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the received `InboundMessage`, `None` for a ping or pong, or an
+    /// error if the server closed the connection; that error downcasts to `ServerClosed`.
+    pub async fn receive_inbound<S>(
+        &mut self,
+        ws_stream: &mut WebSocketStream<S>,
+    ) -> Result<Option<InboundMessage>, Box<dyn StdError>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match self.receive_raw(ws_stream).await? {
+            Some(msg) => match InboundMessage::try_from(msg) {
+                Ok(inbound) => {
+                    let inbound = match (self.text_mode, inbound) {
+                        (TextMode::Validated, InboundMessage::Binary(data)) => match String::from_utf8(data) {
+                            Ok(text) => InboundMessage::Text(text),
+                            Err(e) => {
+                                let valid_up_to = e.utf8_error().valid_up_to();
+                                self.report_violation(ProtocolViolation::invalid_utf8(e.as_bytes(), valid_up_to, Direction::Inbound));
+                                return Err(format!("Failed to read message as text: {}", e).into());
+                            }
+                        },
+                        (mode, inbound) => mode.apply(inbound)?,
+                    };
+                    if self.fanout.subscriber_count() > 0 {
+                        self.fanout.publish(inbound.clone());
+                    }
+                    Ok(Some(inbound))
+                }
+                Err(Message::Close(frame)) => {
+                    let closed = ServerClosed::new(frame.as_ref());
+                    info!("Received Close message: {}", closed);
+                    Err(closed.into())
+                }
+                Err(_) => {
+                    info!("Received control message: Ping/Pong");
+                    Ok(None)
+                }
+            },
+            None => Err("No message received".into()),
+        }
+    }
+
+    /// Receives the next frame from the WebSocket server as a raw tungstenite `Message`.
+    ///
+    /// Unlike `receive_message`, control frames are returned as-is, so callers can mix this
+    /// low-level mode with the high-level API on the same connection.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the next frame, or `None` if the stream has ended.
+    pub async fn receive_raw<S>(
+        &mut self,
+        ws_stream: &mut WebSocketStream<S>,
+    ) -> Result<Option<Message>, Box<dyn StdError>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match ws_stream.next().await {
+            Some(Ok(msg)) => {
+                self.record(|| HistoryEvent::message(Direction::Inbound, &msg));
+                Ok(Some(msg))
+            }
+            Some(Err(e)) => {
+                self.report_error(&e, Direction::Inbound);
+                self.record(|| HistoryEvent::Error(format!("Failed to receive: {}", e)));
+                Err(e.into())
+            }
+            None => {
+                self.record(|| HistoryEvent::Disconnected);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Receives the next data message into a caller-provided buffer.
+    ///
+    /// The buffer's previous contents are replaced by the frame's payload. The payload is moved
+    /// rather than copied, including for text messages, so a large text message never passes
+    /// through an intermediate `String` copy. Deserialize from the buffer with
+    /// `MessageHandler::deserialize` to borrow `&str` fields from it, or use `receive_decoded`.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    /// * `buffer` - The buffer that receives the payload.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `true` if a data message was received, `false` for a ping or pong
+    /// (leaving `buffer` untouched), or an error if the server closed the connection.
+    pub async fn receive_into<S>(
+        &mut self,
+        ws_stream: &mut WebSocketStream<S>,
+        buffer: &mut Vec<u8>,
+    ) -> Result<bool, Box<dyn StdError>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match self.receive_inbound(ws_stream).await? {
+            Some(inbound) => {
+                *buffer = inbound.into_bytes();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Receives the next data message into `buffer` and deserializes it, borrowing from the buffer.
+    ///
+    /// `T` may hold `&str` (and, for CBOR, `&[u8]`) fields that point into `buffer` instead of
+    /// owning copies. Pings and pongs are skipped. JSON numbers are handled as
+    /// `set_json_numbers` says.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    /// * `buffer` - The buffer that receives the payload and backs borrowed fields of `T`.
+    /// * `format` - The format of the payload.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the decoded value, or an error if receiving or decoding fails.
+    pub async fn receive_decoded<'b, S, T>(
+        &mut self,
+        ws_stream: &mut WebSocketStream<S>,
+        buffer: &'b mut Vec<u8>,
+        format: MessageFormat,
+    ) -> Result<T, Box<dyn StdError>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+        T: Deserialize<'b>,
+    {
+        while !self.receive_into(ws_stream, buffer).await? {}
+        let buffer: &'b [u8] = buffer;
+        MessageHandler::deserialize_with(buffer, format, self.json_numbers)?
+            .ok_or_else(|| "Message payload is empty".into())
+    }
+
+    /// Receives a data message into a buffer from `pool`.
+    ///
+    /// The payload's allocation is adopted by the pool, so once the returned buffer is dropped
+    /// it is reused for later pooled serialization instead of being freed.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    /// * `pool` - The pool the payload buffer returns to.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the payload, `None` for a ping or pong, or an error.
+    pub async fn receive_pooled<S>(
+        &mut self,
+        ws_stream: &mut WebSocketStream<S>,
+        pool: &BufferPool,
+    ) -> Result<Option<PooledBuffer>, Box<dyn StdError>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        Ok(self.receive_message(ws_stream).await?.map(|data| pool.recycle(data)))
+    }
+
+    /// Sends a message to the WebSocket server.
+    ///
+    /// The slice is copied into the outgoing frame; use `send_bytes` to hand over an owned
+    /// payload without copying.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    /// * `message` - The message to send as a byte slice.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn send_message<S>(
+        &mut self,
+        ws_stream: &mut WebSocketStream<S>,
+        message: &[u8],
+    ) -> Result<(), Box<dyn StdError>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.send_raw(ws_stream, Message::Binary(message.to_vec())).await
+    }
+
+    /// Encodes `envelope` with `encode_envelope` and sends it in a frame of type
+    /// `frame_kind()`: a text frame for JSON and a binary frame for CBOR by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    /// * `envelope` - The message to send.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error if encoding fails, a text frame is requested
+    /// for a payload that is not UTF-8, or the send fails.
+    pub async fn send_envelope<S>(
+        &mut self,
+        ws_stream: &mut WebSocketStream<S>,
+        envelope: &Envelope,
+    ) -> Result<(), Box<dyn StdError>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let frame = match (self.frame_kind(), self.encode_envelope(envelope)?) {
+            (FrameKind::Text, payload) => match String::from_utf8(payload) {
+                Ok(text) => Message::Text(text),
+                Err(e) => {
+                    let valid_up_to = e.utf8_error().valid_up_to();
+                    self.report_violation(ProtocolViolation::invalid_utf8(e.as_bytes(), valid_up_to, Direction::Outbound));
+                    return Err(format!("Failed to send payload as text: {}", e).into());
+                }
+            },
+            (kind, payload) => kind.frame(payload)?,
+        };
+        self.record(|| HistoryEvent::message(Direction::Outbound, &frame));
+        if let Err(e) = send_fragmented(ws_stream, frame, self.fragmentation).await {
+            self.report_error(&e, Direction::Outbound);
+            self.record(|| HistoryEvent::Error(format!("Failed to send: {}", e)));
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Sends an owned payload as a binary message without copying it.
+    ///
+    /// Payloads backed by a uniquely owned allocation, such as a `Vec<u8>`, a `BytesMut`, or
+    /// `Bytes` created from either, are moved into the frame as-is. Shared or static `Bytes`
+    /// are copied once.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    /// * `payload` - The already-serialized payload.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn send_bytes<S>(
+        &mut self,
+        ws_stream: &mut WebSocketStream<S>,
+        payload: impl Into<Bytes>,
+    ) -> Result<(), Box<dyn StdError>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.send_raw(ws_stream, Message::Binary(Vec::from(payload.into()))).await
+    }
+
+    /// Sends a pooled buffer, such as one filled by `BufferPool::serialize`, as a binary message.
+    ///
+    /// The message is recorded in the history and fragmented like one sent with `send_raw`.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    /// * `buffer` - The payload; it is handed to the stream without copying.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn send_pooled<S>(
+        &mut self,
+        ws_stream: &mut WebSocketStream<S>,
+        buffer: PooledBuffer,
+    ) -> Result<(), Box<dyn StdError>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.send_raw(ws_stream, Message::Binary(buffer.into_vec())).await
+    }
+
+    /// Sends the contents of `reader` as one message, reading and sending a chunk at a time;
+    /// see `fragment::send_stream`.
+    ///
+    /// The payload goes out as written by the reader, without the controller's encoding or
+    /// transformation hooks. A sent message is recorded in the history with its length but
+    /// no preview, and a failure as an error. Not cancel-safe: if this fails or is cancelled midway, the
+    /// message is left unfinished and `ws_stream` must be dropped rather than reused.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    /// * `reader` - The payload, read until end of file.
+    /// * `options` - The frame type and chunk size.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the number of payload bytes sent.
+    pub async fn send_stream<S, R>(
+        &mut self,
+        ws_stream: &mut WebSocketStream<S>,
+        reader: R,
+        options: StreamOptions,
+    ) -> Result<u64, Box<dyn StdError>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+        R: AsyncRead + Unpin,
+    {
+        let sent = match send_stream(ws_stream, reader, options).await {
+            Ok(sent) => sent,
+            Err(e) => {
+                self.report_error(&e, Direction::Outbound);
+                self.record(|| HistoryEvent::Error(format!("Failed to send: {}", e)));
+                return Err(e.into());
+            }
+        };
+        debug!("Streamed a {}-byte message", sent);
+        let frame = match options.frame_kind {
+            FrameKind::Text => "text",
+            FrameKind::Binary => "binary",
+        };
+        self.record(|| HistoryEvent::Message { direction: Direction::Outbound, frame, len: sent as usize, preview: String::new() });
+        Ok(sent)
+    }
+
+    /// Sets when messages queued with `feed_message` are flushed.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The new flush policy; `FlushPolicy::Immediate` by default.
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.flush_policy = policy;
+    }
+
+    /// Returns the current flush policy.
+    pub fn flush_policy(&self) -> FlushPolicy {
+        self.flush_policy
+    }
+
+    /// Returns the number of messages fed since the last flush.
+    pub fn pending_messages(&self) -> usize {
+        self.flush_state.pending()
+    }
+
+    /// Queues a message without flushing, then flushes if the flush policy calls for it.
+    ///
+    /// Unlike `send_message`, which flushes after every message, this lets high-frequency
+    /// senders batch many messages per flush. With `FlushPolicy::Manual` or
+    /// `FlushPolicy::Interval`, call `flush` or `flush_if_due` so the last messages are not
+    /// held back.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    /// * `message` - The message to queue as a byte slice.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn feed_message<S>(
+        &mut self,
+        ws_stream: &mut WebSocketStream<S>,
+        message: &[u8],
+    ) -> Result<(), Box<dyn StdError>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        ws_stream.feed(Message::Binary(message.to_vec())).await?;
+        self.flush_state.record_feed();
+        self.flush_if_due(ws_stream).await
+    }
+
+    /// Flushes all messages queued with `feed_message`.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn flush<S>(&mut self, ws_stream: &mut WebSocketStream<S>) -> Result<(), Box<dyn StdError>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        ws_stream.flush().await?;
+        debug!("Flushed {} queued messages", self.flush_state.pending());
+        self.flush_state.record_flush();
+        Ok(())
+    }
+
+    /// Flushes queued messages if the flush policy calls for it.
+    ///
+    /// Poll this from a timer when using `FlushPolicy::Interval`.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn flush_if_due<S>(&mut self, ws_stream: &mut WebSocketStream<S>) -> Result<(), Box<dyn StdError>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        if self.flush_state.is_due(self.flush_policy) {
+            self.flush(ws_stream).await?;
+        }
+        Ok(())
+    }
+
+    /// Sends a raw tungstenite `Message`, bypassing the controller's framing.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    /// * `message` - The frame to send, such as a text message or a custom close frame.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn send_raw<S>(
+        &mut self,
+        ws_stream: &mut WebSocketStream<S>,
+        message: Message,
+    ) -> Result<(), Box<dyn StdError>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.record(|| HistoryEvent::message(Direction::Outbound, &message));
+        if let Err(e) = send_fragmented(ws_stream, message, self.fragmentation).await {
+            self.report_error(&e, Direction::Outbound);
+            self.record(|| HistoryEvent::Error(format!("Failed to send: {}", e)));
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Maintains the WebSocket connection by periodically sending pings.
+    ///
+    /// The pings run in a task named `websocket_toolkit::keep_alive`, which replaces (and
+    /// aborts) any keep-alive task started earlier by this controller and is aborted by
+    /// `disconnect`. Use `take_keep_alive_task` to await or abort it directly. Each ping
+    /// locks the stream; for pipelined connections use `maintain_pipeline` instead.
+    ///
+    /// Available with the `keep-alive` feature.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - An `Arc`-wrapped, thread-safe `Mutex` protecting the WebSocket stream.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    #[cfg(feature = "keep-alive")]
+    pub async fn maintain_connection(
+        &self,
+        ws_stream: Arc<Mutex<WebSocketStream<MaybeTlsStream<TcpStream>>>>,
+    ) -> Result<(), Box<dyn StdError>> {
+        self.spawn_keep_alive(KeepAliveTarget::Stream(ws_stream));
+        Ok(())
+    }
+
+    /// Maintains a pipelined connection by periodically queuing pings on its writer task.
+    ///
+    /// Unlike `maintain_connection`, the pings never lock the stream and do not allocate. The
+    /// task is managed like the one started by `maintain_connection`: it replaces any earlier
+    /// keep-alive task, is aborted by `disconnect`, stops when the writer task stops, and its
+    /// handle is available from `take_keep_alive_task`.
+    ///
+    /// Available with the `keep-alive` feature.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - The sending half of the connection's pipeline.
+    #[cfg(feature = "keep-alive")]
+    pub fn maintain_pipeline(&self, sender: PipelineSender) {
+        self.spawn_keep_alive(KeepAliveTarget::Pipeline(sender));
+    }
+
+    /// Starts the keep-alive task for `target`, replacing any earlier one.
+    #[cfg(feature = "keep-alive")]
+    fn spawn_keep_alive(&self, target: KeepAliveTarget) {
+        let interval = self.ping_interval;
+        let jitter = self.ping_jitter;
+        let task = match target.clone() {
+            KeepAliveTarget::Stream(ws_stream) => spawn_named("websocket_toolkit::keep_alive", async move {
+                let mut ticker = tokio::time::interval(interval);
+                loop {
+                    if jitter > 0.0 {
+                        sleep(jittered(interval, jitter)).await;
+                    } else {
+                        ticker.tick().await;
+                    }
+                    let mut stream = ws_stream.lock().await;
+                    if let Err(e) = stream.send(Message::Ping(PING_PAYLOAD)).await {
+                        error!("Ping failed: {}", e);
+                        break;
+                    }
+                }
+            }),
+            KeepAliveTarget::Pipeline(sender) => {
+                let mut keep_alive = KeepAlive::new(interval).with_jitter(jitter);
+                if let Some(wake) = self.wake_probe {
+                    keep_alive = keep_alive.with_wake_probe(wake);
+                }
+                spawn_named("websocket_toolkit::keep_alive", async move {
+                    if let Err(e) = keep_alive.run(&sender).await {
+                        debug!("Keep-alive stopped: {}", e);
+                    }
+                })
+            }
+        };
+        *self.keep_alive_target.lock().unwrap() = Some(target);
+        let previous = self.keep_alive_task.lock().unwrap().replace(task);
+        if let Some(previous) = previous {
+            previous.abort();
         }
     }
 
-    /// Establishes a WebSocket connection.
-    ///
-    /// # Returns
-    ///
-    /// A `Result` containing a `WebSocketStream` if the connection is successful,
-    /// or a boxed error if the connection fails.
-    pub async fn connect(
-        &self,
-    ) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, Box<dyn StdError>> {
-        self.client
-            .connect()
-            .await
-            .map_err(|e| Box::new(e) as Box<dyn StdError>)
+    /// Restarts a running keep-alive task with the current ping settings.
+    #[cfg(feature = "keep-alive")]
+    fn restart_keep_alive(&self) {
+        let running = self.keep_alive_task.lock().unwrap().as_ref().is_some_and(|task| !task.is_finished());
+        let target = self.keep_alive_target.lock().unwrap().clone();
+        if let (true, Some(target)) = (running, target) {
+            self.spawn_keep_alive(target);
+        }
     }
 
-    /// Connects to the WebSocket server and sends a message.
+    /// Makes the keep-alive task of `maintain_pipeline` probe the connection as soon as the
+    /// machine resumes from sleep, closing it if the server no longer answers; see the
+    /// `wake` module.
     ///
     /// # Arguments
     ///
-    /// * `message` - The message to send as a byte slice.
+    /// * `probe` - How a wake is detected and verified, or `None` to trust the connection
+    ///   until the next ping (the default).
+    pub fn set_wake_probe(&mut self, probe: Option<WakeProbe>) {
+        self.wake_probe = probe;
+    }
+
+    /// Returns the wake detection settings, if set.
+    pub fn wake_probe(&self) -> Option<WakeProbe> {
+        self.wake_probe
+    }
+
+    /// Takes the handle of the keep-alive task started by `maintain_connection` or
+    /// `maintain_pipeline`, if any.
+    ///
+    /// Available with the `keep-alive` feature.
     ///
     /// # Returns
     ///
-    /// A `Result` indicating success or failure.
-    pub async fn connect_and_send_message(
-        &mut self,
-        message: &[u8],
-    ) -> Result<(), Box<dyn StdError>> {
-        let mut ws_stream = self.connect().await?;
-        self.send_message(&mut ws_stream, message).await?;
-        Ok(())
+    /// The task's `JoinHandle`, or `None` if no keep-alive task is running. The controller
+    /// no longer aborts the task on `disconnect` once its handle has been taken.
+    #[cfg(feature = "keep-alive")]
+    pub fn take_keep_alive_task(&self) -> Option<JoinHandle<()>> {
+        self.keep_alive_target.lock().unwrap().take();
+        self.keep_alive_task.lock().unwrap().take()
     }
 
-    /// Disconnects from the WebSocket server gracefully.
+    /// Attempts to reconnect to the WebSocket server with the controller's
+    /// `ReconnectStrategy`, or, without one, its retries and exponential backoff.
+    ///
+    /// Rejected credentials end the attempts at once. The new connection is dropped; use
+    /// `reconnect` to keep it.
+    ///
+    /// Available with the `reconnection` feature.
     ///
     /// # Returns
     ///
     /// A `Result` indicating success or failure.
-    pub async fn disconnect(&self) -> Result<(), Box<dyn StdError>> {
-        self.client.disconnect();
-        Ok(())
+    #[cfg(feature = "reconnection")]
+    pub async fn reconnect_if_needed(&self) -> Result<(), Box<dyn StdError>> {
+        self.reconnect().await.map(|_| ())
     }
 
-    /// Receives a message from the WebSocket server.
+    /// Reconnects like `reconnect_if_needed`, returning the new connection.
     ///
-    /// # Arguments
+    /// Like every connect, a successful attempt sends the auth frame and replays the
+    /// subscriptions of the subscription manager, if set, on the new connection.
     ///
-    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    /// Available with the `reconnection` feature.
     ///
     /// # Returns
     ///
-    /// A `Result` containing the received message as a `Vec<u8>` or an error.
-    pub async fn receive_message(
-        &mut self,
-        ws_stream: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
-    ) -> Result<Option<Vec<u8>>, Box<dyn StdError>> {
-        if let Some(msg) = ws_stream.next().await {
-            match msg? {
-                Message::Binary(data) => Ok(Some(data)),
-                Message::Text(text) => Ok(Some(text.into_bytes())),
-                Message::Ping(_) | Message::Pong(_) => {
-                    info!("Received control message: Ping/Pong");
-                    Ok(None)
-                }
-                Message::Close(_) => {
-                    info!("Received Close message");
-                    Err("Connection closed by server".into())
-                }
+    /// A `Result` containing the new `WebSocketStream`, which replaces the dropped one, or a
+    /// boxed error if every attempt failed.
+    #[cfg(feature = "reconnection")]
+    pub async fn reconnect(&self) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, Box<dyn StdError>> {
+        let fallback;
+        let strategy = match &self.reconnect_strategy {
+            Some(strategy) => strategy,
+            None => {
+                fallback = self.default_reconnect_strategy();
+                &fallback
             }
-        } else {
-            Err("No message received".into())
+        };
+        match strategy.retry(|| self.connect(), |e| is_auth_rejected(e.as_ref())).await {
+            Ok(ws_stream) => Ok(ws_stream),
+            Err(RetryError::Fatal(e)) => Err(e),
+            Err(RetryError::GaveUp(e)) => Err(e.into()),
         }
     }
 
-    /// Sends a message to the WebSocket server.
+    /// Replaces the policy applied by `receive_or_reconnect` when the server closes the
+    /// connection.
+    ///
+    /// By default `ClosePolicy::default()` reconnects after 1012 (Service Restart) and 1013
+    /// (Try Again Later) and surfaces every other close.
     ///
     /// # Arguments
     ///
-    /// * `ws_stream` - A mutable reference to the WebSocket stream.
-    /// * `message` - The message to send as a byte slice.
+    /// * `policy` - Called with the close and the number of consecutive closes already
+    ///   answered with a reconnect; returns the `CloseAction` to take.
     ///
-    /// # Returns
+    /// # Examples
     ///
-    /// A `Result` indicating success or failure.
-    pub async fn send_message(
-        &mut self,
-        ws_stream: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
-        message: &[u8],
-    ) -> Result<(), Box<dyn StdError>> {
-        ws_stream.send(Message::Binary(message.to_vec())).await?;
-        Ok(())
+    /// ```rust
+    /// use std::time::Duration;
+    /// use websocket_toolkit::close::{CloseAction, ClosePolicy};
+    /// use websocket_toolkit::controller::WebSocketController;
+    ///
+    /// let mut controller = WebSocketController::new("ws://example.com", 3, None);
+    /// let policy = ClosePolicy { max_delay: Duration::from_secs(30), ..ClosePolicy::default() };
+    /// controller.set_close_policy(move |closed, attempt| match closed.code {
+    ///     Some(4000) => CloseAction::Reconnect(Duration::from_secs(5)),
+    ///     _ => policy.decide(closed, attempt),
+    /// });
+    /// ```
+    pub fn set_close_policy<F>(&mut self, policy: F)
+    where
+        F: Fn(&ServerClosed, u32) -> CloseAction + Send + Sync + 'static,
+    {
+        self.close_policy = Arc::new(policy);
     }
 
-    /// Maintains the WebSocket connection by periodically sending pings.
+    /// Receives a data message like `receive_inbound`, reconnecting when the server closes
+    /// the connection and the close policy asks for it.
+    ///
+    /// After a 1012 or 1013 close, the default policy waits, reconnects like `reconnect` and
+    /// replaces `ws_stream` with the new connection, so a server restart or overload is not
+    /// surfaced as an error. The reconnect retries with the controller's `ReconnectStrategy`
+    /// and stops at once on rejected credentials; without the `reconnection` feature it is a
+    /// single connect. The count of consecutive reconnects resets with every data message.
     ///
     /// # Arguments
     ///
-    /// * `ws_stream` - An `Arc`-wrapped, thread-safe `Mutex` protecting the WebSocket stream.
+    /// * `ws_stream` - A mutable reference to the WebSocket stream; replaced on reconnect.
     ///
     /// # Returns
     ///
-    /// A `Result` indicating success or failure.
-    pub async fn maintain_connection(
-        &self,
-        ws_stream: Arc<Mutex<WebSocketStream<MaybeTlsStream<TcpStream>>>>,
-    ) -> Result<(), Box<dyn StdError>> {
-        let interval = self.ping_interval;
-        tokio::spawn(async move {
-            let mut ticker = tokio::time::interval(interval);
-            loop {
-                ticker.tick().await;
-                let mut stream = ws_stream.lock().await;
-                if let Err(e) = stream.send(Message::Ping(vec![])).await {
-                    error!("Ping failed: {}", e);
-                    break;
+    /// A `Result` containing the received `InboundMessage`, `None` for a ping, a pong or a
+    /// reconnect, or an error if the close is surfaced or reconnecting fails.
+    pub async fn receive_or_reconnect(
+        &mut self,
+        ws_stream: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+    ) -> Result<Option<InboundMessage>, Box<dyn StdError>> {
+        let error = match self.receive_inbound(ws_stream).await {
+            Ok(message) => {
+                if message.is_some() {
+                    self.close_reconnects = 0;
                 }
+                return Ok(message);
             }
-        });
-        Ok(())
-    }
-
-    /// Attempts to reconnect to the WebSocket server using exponential backoff.
-    ///
-    /// # Returns
-    ///
-    /// A `Result` indicating success or failure.
-    pub async fn reconnect_if_needed(&self) -> Result<(), Box<dyn StdError>> {
-        let mut attempts = 0;
-        while attempts < self.retries {
-            match self.connect().await {
-                Ok(_) => return Ok(()),
-                Err(e) => {
-                    error!("Reconnection attempt {} failed: {}", attempts + 1, e);
-                    tokio::time::sleep(Duration::from_secs(2_u64.pow(attempts))).await; // Exponential backoff
-                    attempts += 1;
+            Err(error) => error,
+        };
+        let closed = match error.downcast_ref::<ServerClosed>() {
+            Some(closed) => closed,
+            None => return Err(error),
+        };
+        match (self.close_policy)(closed, self.close_reconnects) {
+            CloseAction::Reconnect(delay) => {
+                self.close_reconnects += 1;
+                warn!("{}; reconnecting in {:?} (attempt {})", closed, delay, self.close_reconnects);
+                sleep(delay).await;
+                // Boxed, since the retry loop's future would otherwise make this one large
+                // enough to overflow a test thread's stack.
+                #[cfg(feature = "reconnection")]
+                {
+                    *ws_stream = Box::pin(self.reconnect()).await?;
                 }
+                #[cfg(not(feature = "reconnection"))]
+                {
+                    *ws_stream = self.connect().await?;
+                }
+                Ok(None)
             }
+            CloseAction::Surface => Err(error),
         }
-        Err("All reconnection attempts failed.".into())
     }
 
     /// Sends a ping message to the WebSocket server.
@@ -211,11 +2260,14 @@ impl WebSocketController {
     /// # Returns
     ///
     /// A `Result` indicating success or failure.
-    pub async fn send_ping(
+    pub async fn send_ping<S>(
         &self,
-        ws_stream: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
-    ) -> Result<(), Box<dyn StdError>> {
-        ws_stream.send(Message::Ping(Vec::new())).await?;
+        ws_stream: &mut WebSocketStream<S>,
+    ) -> Result<(), Box<dyn StdError>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        ws_stream.send(Message::Ping(PING_PAYLOAD)).await?;
         Ok(())
     }
 }
@@ -241,10 +2293,11 @@ mod tests {
     }
 
     /// Tests the lifecycle of a `WebSocketController`.
+    #[cfg(all(feature = "keep-alive", feature = "reconnection"))]
     #[tokio::test]
     async fn test_websocket_controller_lifecycle() -> Result<(), Box<dyn StdError>> {
         let url = "ws://node_server:9001";
-        let mut controller = WebSocketController::new(&url, 3, Some(10));
+        let mut controller = WebSocketController::new(url, 3, Some(10));
 
         // Test connection and sending a message
         let connect_result = controller.connect_and_send_message(b"Hello, WebSocket!").await;
@@ -283,7 +2336,7 @@ mod tests {
     #[tokio::test]
     async fn test_websocket_connection() -> Result<(), Box<dyn StdError>> {
         let url = start_mock_server().await;
-        let mut controller = WebSocketController::new(&url, 3, Some(5));
+        let controller = WebSocketController::new(&url, 3, Some(5));
 
         // Test connect method
         let ws_stream = controller.connect().await;
@@ -295,6 +2348,45 @@ mod tests {
         Ok(())
     }
 
+    /// Tests that readiness resolves once the first connection is open, and at once after.
+    #[tokio::test]
+    async fn test_wait_connected() {
+        let server = crate::testing::EchoServer::start().await.unwrap();
+        let controller = WebSocketController::new(server.url(), 0, None);
+        assert!(!controller.has_connected());
+        assert!(controller.wait_connected(Duration::from_millis(50)).await.is_err());
+
+        let readiness = tokio::spawn(controller.readiness());
+        let _ws_stream = controller.connect().await.unwrap();
+        readiness.await.unwrap().unwrap();
+        assert!(controller.has_connected());
+        controller.wait_connected(Duration::ZERO).await.unwrap();
+
+        let unconnected = WebSocketController::new(server.url(), 0, None);
+        let readiness = unconnected.readiness();
+        drop(unconnected);
+        assert!(readiness.await.is_err());
+    }
+
+    /// Tests that messages above the threshold are fragmented on both send paths.
+    #[tokio::test]
+    async fn test_fragmentation() {
+        let server = crate::testing::EchoServer::start().await.unwrap();
+        let mut controller = WebSocketController::new(server.url(), 0, None);
+        controller.set_fragmentation(Some(Fragmentation { threshold: 1024, fragment_size: 256 }));
+        let large = vec![7u8; 4096];
+
+        let mut ws_stream = controller.connect().await.unwrap();
+        controller.send_message(&mut ws_stream, b"small").await.unwrap();
+        controller.send_message(&mut ws_stream, &large).await.unwrap();
+        assert_eq!(controller.receive_message(&mut ws_stream).await.unwrap(), Some(b"small".to_vec()));
+        assert_eq!(controller.receive_message(&mut ws_stream).await.unwrap(), Some(large.clone()));
+
+        let handle = controller.connect_handle(PipelineConfig::default()).await.unwrap();
+        handle.send_binary(large.clone()).await.unwrap();
+        assert_eq!(handle.recv().await, Some(Message::Binary(large)));
+    }
+
     /// Tests the sending and receiving of messages using `WebSocketController`.
     #[tokio::test]
     async fn test_send_and_receive_message() -> Result<(), Box<dyn StdError>> {
@@ -320,11 +2412,626 @@ mod tests {
         Ok(())
     }
 
+    /// Tests mixing raw and high-level sends and receives on one connection.
+    #[tokio::test]
+    async fn test_raw_passthrough() -> Result<(), Box<dyn StdError>> {
+        let server = crate::testing::EchoServer::start().await?;
+        let mut controller = WebSocketController::new(server.url(), 0, None);
+        let mut ws_stream = controller.connect().await?;
+
+        let mut subscriber = controller.subscribe();
+        controller.send_raw(&mut ws_stream, Message::Text("raw".into())).await?;
+        let inbound = controller.receive_inbound(&mut ws_stream).await?;
+        assert_eq!(inbound, Some(InboundMessage::Text("raw".to_string())));
+        assert_eq!(subscriber.try_recv()?, InboundMessage::Text("raw".to_string()));
+
+        controller.send_message(&mut ws_stream, b"bytes").await?;
+        let raw = controller.receive_raw(&mut ws_stream).await?;
+        assert_eq!(raw, Some(Message::Binary(b"bytes".to_vec())));
+        Ok(())
+    }
+
+    /// Tests that the keep-alive task handle is exposed and replaced on restart.
+    #[cfg(feature = "keep-alive")]
+    #[tokio::test]
+    async fn test_keep_alive_task_handle() -> Result<(), Box<dyn StdError>> {
+        let server = crate::testing::EchoServer::start().await?;
+        let controller = WebSocketController::new(server.url(), 0, Some(1));
+        let ws_stream = Arc::new(Mutex::new(controller.connect().await?));
+
+        controller.maintain_connection(ws_stream.clone()).await?;
+        let first = controller.take_keep_alive_task().expect("Expected a keep-alive task");
+        assert!(controller.take_keep_alive_task().is_none());
+
+        controller.maintain_connection(ws_stream.clone()).await?;
+        controller.maintain_connection(ws_stream).await?;
+        controller.disconnect().await?;
+        assert!(controller.take_keep_alive_task().is_none(), "Expected disconnect to stop keep-alive");
+
+        first.abort();
+        assert!(first.await.unwrap_err().is_cancelled());
+        Ok(())
+    }
+
+    /// Tests a pooled send and receive round trip through the echo server, and that the send
+    /// is recorded in the history.
+    #[tokio::test]
+    async fn test_pooled_round_trip() -> Result<(), Box<dyn StdError>> {
+        let server = crate::testing::EchoServer::start().await?;
+        let mut controller = WebSocketController::new(server.url(), 0, None);
+        let mut ws_stream = controller.connect().await?;
+        let pool = BufferPool::new(256, 4);
+
+        let history = Arc::new(ConnectionHistory::new(4));
+        controller.set_history(Some(history.clone()));
+
+        let mut buffer = pool.get();
+        buffer.extend_from_slice(b"pooled");
+        controller.send_pooled(&mut ws_stream, buffer).await?;
+        assert_eq!(history.entries().last().unwrap().event.to_string(), "-> binary (6 bytes) \"pooled\"");
+
+        let received = controller.receive_pooled(&mut ws_stream, &pool).await?.expect("Expected a payload");
+        assert_eq!(&received[..], b"pooled");
+        drop(received);
+        assert_eq!(pool.available(), 1, "Expected the received payload to join the pool");
+        Ok(())
+    }
+
+    /// Tests that fed messages stay pending under a manual policy until flushed.
+    #[tokio::test]
+    async fn test_feed_and_flush() -> Result<(), Box<dyn StdError>> {
+        let mut server = crate::testing::MockServer::start().await?;
+        let mut controller = WebSocketController::new(server.url(), 0, None);
+        let mut ws_stream = controller.connect().await?;
+        let mut connection = server.accept().await;
+
+        controller.set_flush_policy(FlushPolicy::Manual);
+        for message in [&b"one"[..], b"two", b"three"] {
+            controller.feed_message(&mut ws_stream, message).await?;
+        }
+        assert_eq!(controller.pending_messages(), 3);
+        controller.flush_if_due(&mut ws_stream).await?;
+        assert_eq!(controller.pending_messages(), 3, "Manual policy should not flush on its own");
+
+        controller.flush(&mut ws_stream).await?;
+        assert_eq!(controller.pending_messages(), 0);
+        for expected in [&b"one"[..], b"two", b"three"] {
+            connection.assert_next_message_eq(Message::Binary(expected.to_vec())).await;
+        }
+
+        controller.set_flush_policy(FlushPolicy::Immediate);
+        controller.feed_message(&mut ws_stream, b"four").await?;
+        assert_eq!(controller.pending_messages(), 0);
+        Ok(())
+    }
+
+    /// Tests decoding a received text message with fields borrowed from the receive buffer.
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn test_receive_decoded_borrows() -> Result<(), Box<dyn StdError>> {
+        #[derive(Deserialize)]
+        struct Quote<'a> {
+            symbol: &'a str,
+            price: f64,
+        }
+
+        let server = crate::testing::EchoServer::start().await?;
+        let mut controller = WebSocketController::new(server.url(), 0, None);
+        let mut ws_stream = controller.connect().await?;
+        controller.send_raw(&mut ws_stream, Message::Text(r#"{"symbol":"ACME","price":1.5}"#.into())).await?;
+
+        let mut buffer = Vec::new();
+        let symbol_at = {
+            let quote: Quote = controller.receive_decoded(&mut ws_stream, &mut buffer, MessageFormat::Json).await?;
+            assert_eq!((quote.symbol, quote.price), ("ACME", 1.5));
+            quote.symbol.as_ptr()
+        };
+        assert!(buffer.as_ptr_range().contains(&symbol_at), "Expected symbol to borrow from the buffer");
+
+        // Exact numbers refuse a price that an `f64` would round.
+        controller.set_json_numbers(JsonNumbers::Exact);
+        controller.send_raw(&mut ws_stream, Message::Text(r#"{"symbol":"ACME","price":12345678901234567.89}"#.into())).await?;
+        let rounded = controller.receive_decoded::<_, Quote>(&mut ws_stream, &mut buffer, MessageFormat::Json).await;
+        assert_eq!(rounded.is_err(), cfg!(not(feature = "json-arbitrary-precision")));
+        Ok(())
+    }
+
+    /// Tests sending owned payloads of several types.
+    #[tokio::test]
+    async fn test_send_bytes() -> Result<(), Box<dyn StdError>> {
+        let mut server = crate::testing::MockServer::start().await?;
+        let mut controller = WebSocketController::new(server.url(), 0, None);
+        let mut ws_stream = controller.connect().await?;
+        let mut connection = server.accept().await;
+
+        controller.send_bytes(&mut ws_stream, b"vec".to_vec()).await?;
+        controller.send_bytes(&mut ws_stream, Bytes::from_static(b"static")).await?;
+        controller.send_bytes(&mut ws_stream, bytes::BytesMut::from(&b"bytes_mut"[..])).await?;
+        for expected in [&b"vec"[..], b"static", b"bytes_mut"] {
+            connection.assert_next_message_eq(Message::Binary(expected.to_vec())).await;
+        }
+        Ok(())
+    }
+
+    /// Tests that a `Vec` converted to `Bytes` and back keeps its allocation.
+    #[test]
+    fn test_owned_payload_conversion_is_zero_copy() {
+        let payload = vec![7u8; 1024];
+        let pointer = payload.as_ptr();
+        let round_tripped = Vec::from(Bytes::from(payload));
+        assert_eq!(round_tripped.as_ptr(), pointer);
+    }
+
+    /// Tests an echo round trip through a controller-created pipeline.
+    #[tokio::test]
+    async fn test_connect_pipeline() -> Result<(), Box<dyn StdError>> {
+        let server = crate::testing::EchoServer::start().await?;
+        let controller = WebSocketController::new(server.url(), 0, None);
+        assert!(controller.connection_info().is_none());
+        let (sender, mut receiver, tasks) = controller.connect_pipeline(PipelineConfig::default()).await?;
+        let info = controller.connection_info().expect("Expected connection info after connecting");
+        assert_eq!(info.url, server.url());
+        assert!(info.peer_addr.is_some());
+
+        sender.send_binary(b"piped".to_vec()).await?;
+        assert_eq!(receiver.recv().await, Some(Message::Binary(b"piped".to_vec())));
+        tasks.abort();
+        Ok(())
+    }
+
+    /// Tests that `maintain_pipeline` pings through the writer task.
+    #[cfg(feature = "keep-alive")]
+    #[tokio::test]
+    async fn test_maintain_pipeline() -> Result<(), Box<dyn StdError>> {
+        let mut server = crate::testing::MockServer::start().await?;
+        let controller = WebSocketController::new(server.url(), 0, Some(1));
+        let (sender, _receiver, tasks) = controller.connect_pipeline(PipelineConfig::default()).await?;
+        let mut connection = server.accept().await;
+
+        controller.maintain_pipeline(sender);
+        assert_eq!(connection.next_frame().await, Some(Message::Ping(Vec::new())));
+        controller.disconnect().await?;
+        assert!(controller.take_keep_alive_task().is_none());
+        tasks.abort();
+        Ok(())
+    }
+
+    /// Tests that a 1013 close reconnects after the suggested delay and that an overriding
+    /// policy surfaces the close instead.
+    #[tokio::test]
+    async fn test_receive_or_reconnect() -> Result<(), Box<dyn StdError>> {
+        let mut server = crate::testing::MockServer::start().await?;
+        let mut controller = WebSocketController::new(server.url(), 1, None);
+        let mut ws_stream = controller.connect().await?;
+        let mut connection = server.accept().await;
+
+        connection.close(crate::close::TRY_AGAIN_LATER, "retry after 0").await;
+        assert_eq!(controller.receive_or_reconnect(&mut ws_stream).await?, None);
+        let mut reconnected = server.accept().await;
+        reconnected.send(Message::Text("back".to_string())).await;
+        assert_eq!(controller.receive_or_reconnect(&mut ws_stream).await?, Some(InboundMessage::Text("back".to_string())));
+
+        controller.set_close_policy(|_, _| CloseAction::Surface);
+        reconnected.close(crate::close::SERVICE_RESTART, "deploying").await;
+        let error = controller.receive_or_reconnect(&mut ws_stream).await.unwrap_err();
+        let closed = error.downcast_ref::<ServerClosed>().expect("Expected a ServerClosed error");
+        assert_eq!(closed.code, Some(crate::close::SERVICE_RESTART));
+        assert_eq!(closed.reason, "deploying");
+        Ok(())
+    }
+
+    /// Tests that `receive_or_reconnect` stops reconnecting at once when the credentials are
+    /// rejected, although the strategy has retries left.
+    #[cfg(feature = "reconnection")]
+    #[tokio::test]
+    async fn test_receive_or_reconnect_auth_rejected() -> Result<(), Box<dyn StdError>> {
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        let mut server = crate::testing::MockServer::start().await?;
+        let mut controller = WebSocketController::new(server.url(), 3, None);
+        controller.set_close_policy(|_, _| CloseAction::Reconnect(Duration::ZERO));
+        let mut ws_stream = controller.connect().await?;
+        let mut connection = server.accept().await;
+
+        let attempts = Arc::new(AtomicU32::new(0));
+        let counted = attempts.clone();
+        let provider = move || {
+            counted.fetch_add(1, Ordering::Relaxed);
+            Ok(Message::Text("auth".into()))
+        };
+        let rejecting = AuthMessage::new(provider, Duration::from_secs(1)).with_reply(|_| Err("invalid token".into()));
+        controller.set_auth_message(Some(rejecting));
+        connection.close(crate::close::SERVICE_RESTART, "deploying").await;
+        let rejected = async {
+            let mut reconnected = server.accept().await;
+            reconnected.assert_next_message_eq(Message::Text("auth".into())).await;
+            reconnected.send(Message::Text("denied".into())).await;
+            reconnected
+        };
+        let (result, _reconnected) = tokio::join!(controller.receive_or_reconnect(&mut ws_stream), rejected);
+        let error = result.unwrap_err();
+        assert!(is_auth_rejected(error.as_ref()), "{}", error);
+        assert_eq!(attempts.load(Ordering::Relaxed), 1);
+        Ok(())
+    }
+
+    /// Tests that cookies set by the server are persisted and sent again after a restart.
+    #[cfg(feature = "session")]
+    #[tokio::test]
+    #[allow(clippy::result_large_err)] // The handshake callback's error type is tungstenite's.
+    async fn test_session_persists_cookies() {
+        use crate::session::{SessionKey, SessionStore};
+        use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (cookies_tx, mut cookies) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let cookies_tx = cookies_tx.clone();
+                let set_cookie = |request: &Request, mut response: Response| {
+                    let cookie = request.headers().get("Cookie").map(|value| value.to_str().unwrap().to_string());
+                    cookies_tx.send(cookie).unwrap();
+                    response.headers_mut().insert("Set-Cookie", "sid=abc; HttpOnly".parse().unwrap());
+                    Ok(response)
+                };
+                let _ = tokio_tungstenite::accept_hdr_async(stream, set_cookie).await;
+            }
+        });
+
+        let path = std::env::temp_dir().join(format!("wstk-controller-session-{}", std::process::id()));
+        let key = SessionKey::generate();
+        let mut controller = WebSocketController::new(&url, 0, None);
+        controller.set_session(Arc::new(SessionStore::open(&path, key.clone()).unwrap()));
+        controller.connect().await.unwrap();
+        assert_eq!(cookies.recv().await.unwrap(), None);
+
+        // A new process restores the session from the file, including the URL.
+        let mut restarted = WebSocketController::new("ws://127.0.0.1:1", 0, None);
+        restarted.set_session(Arc::new(SessionStore::open(&path, key).unwrap()));
+        restarted.connect().await.unwrap();
+        assert_eq!(cookies.recv().await.unwrap().as_deref(), Some("sid=abc"));
+        restarted.session().unwrap().clear().unwrap();
+    }
+
+    /// Tests that `from_config` applies the configured connection timeout and format.
+    #[tokio::test]
+    async fn test_from_config_connect_timeout() {
+        // The listener accepts TCP connections but never answers the WebSocket handshake.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = Config {
+            urls: vec![format!("ws://{}", listener.local_addr().unwrap())],
+            connect_timeout_ms: Some(100),
+            format: MessageFormat::Cbor,
+            preallocated_buffers: 2,
+            ..Config::default()
+        };
+        let controller = WebSocketController::from_config(&config).unwrap();
+        assert_eq!(controller.format(), MessageFormat::Cbor);
+        assert_eq!(controller.buffer_pool().available(), 2);
+        let envelope = Envelope::new("reading", vec![21, 5]);
+        let payload = controller.encode_envelope(&envelope).unwrap();
+        assert_eq!(controller.decode_envelope(&payload).unwrap(), envelope);
+
+        let error = controller.connect().await.expect_err("Expected the connection to time out");
+        assert!(error.to_string().contains("timed out"), "Unexpected error: {}", error);
+        assert!(WebSocketController::from_config(&Config::default()).is_err());
+    }
+
+    /// Tests that JSON envelopes are sent as text frames and that the text mode applies to
+    /// received messages.
+    #[tokio::test]
+    async fn test_envelope_framing_and_text_mode() -> Result<(), Box<dyn StdError>> {
+        let mut server = crate::testing::MockServer::start().await?;
+        let mut controller = WebSocketController::new(server.url(), 0, None);
+        let mut ws_stream = controller.connect().await?;
+        let mut connection = server.accept().await;
+        let envelope = Envelope::new("chat", b"hi".to_vec());
+
+        assert_eq!(controller.frame_kind(), FrameKind::Text);
+        controller.send_envelope(&mut ws_stream, &envelope).await?;
+        let expected = String::from_utf8(envelope.encode(MessageFormat::Json)?)?;
+        connection.assert_next_message_eq(Message::Text(expected)).await;
+        controller.set_frame_kind(Some(FrameKind::Binary));
+        controller.send_envelope(&mut ws_stream, &envelope).await?;
+        assert!(matches!(connection.next_frame().await, Some(Message::Binary(_))));
+
+        controller.set_text_mode(TextMode::Lossy);
+        connection.send(Message::Binary(vec![b'o', b'k', 0xff])).await;
+        assert_eq!(controller.receive_inbound(&mut ws_stream).await?, Some(InboundMessage::Text("ok\u{fffd}".to_string())));
+        controller.set_text_mode(TextMode::Raw);
+        connection.send(Message::Text("ok".to_string())).await;
+        assert_eq!(controller.receive_inbound(&mut ws_stream).await?, Some(InboundMessage::Binary(b"ok".to_vec())));
+        Ok(())
+    }
+
+    /// Tests that envelopes fall back to application-level deflate unless the connection
+    /// negotiated `permessage-deflate`, and that peers with and without compression decode
+    /// each other's envelopes.
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_encoding_fallback() {
+        let config = Config {
+            urls: vec!["ws://example.com".to_string()],
+            ..Config::low_bandwidth()
+        };
+        let mut controller = WebSocketController::from_config(&config).unwrap();
+        let envelope = Envelope::new("reading", vec![7; 256]);
+        let compressed = controller.encode_envelope(&envelope).unwrap();
+        assert_eq!(controller.encoding(), Encoding::Application(Compression::Deflate));
+
+        *controller.connection_info.lock().unwrap() = Some(ConnectionInfo {
+            url: "ws://example.com".to_string(),
+            peer_addr: None,
+            local_addr: None,
+            tls: None,
+            subprotocol: None,
+            extensions: vec!["permessage-deflate".to_string()],
+            set_cookies: Vec::new(),
+            handshake_duration: Duration::ZERO,
+        });
+        assert_eq!(controller.encoding(), Encoding::PermessageDeflate);
+        let passthrough = controller.encode_envelope(&envelope).unwrap();
+        assert!(passthrough.len() > compressed.len());
+        assert_eq!(
+            controller.decode_envelope_with_encoding(&passthrough).unwrap(),
+            (envelope.clone(), Encoding::PermessageDeflate)
+        );
+        assert_eq!(
+            controller.decode_envelope_with_encoding(&compressed).unwrap(),
+            (envelope.clone(), Encoding::Application(Compression::Deflate))
+        );
+
+        let plain = WebSocketController::from_config(&Config { compression: Compression::None, ..config.clone() }).unwrap();
+        let untagged = plain.encode_envelope(&envelope).unwrap();
+        assert_eq!(untagged, envelope.encode(plain.format).unwrap());
+        assert_eq!(controller.decode_envelope_with_encoding(&untagged).unwrap(), (envelope.clone(), Encoding::Identity));
+        assert_eq!(
+            plain.decode_envelope_with_encoding(&compressed).unwrap(),
+            (envelope.clone(), Encoding::Application(Compression::Deflate))
+        );
+        assert_eq!(plain.decode_envelope(&passthrough).unwrap(), envelope);
+
+        controller.set_decompression_limits(DecompressionLimits { max_bytes: None, max_ratio: Some(2) });
+        let error = controller.decode_envelope_with_encoding(&compressed).unwrap_err();
+        assert!(error.contains("beyond the limit"), "{}", error);
+    }
+
+    /// Tests that envelopes are upgraded on receipt and downgraded for an older peer.
+    #[test]
+    fn test_envelope_migrations() {
+        let mut controller = WebSocketController::new("ws://example.com", 1, None);
+        let migrations = SchemaMigrations::new(2)
+            .with_upgrade("greeting", 1, |envelope| Ok(Envelope::new("hello", envelope.payload).with_version(2)))
+            .with_downgrade("hello", 2, |envelope| Ok(Envelope::new("greeting", envelope.payload)));
+        controller.set_migrations(Some(Arc::new(migrations)));
+
+        let old = Envelope::new("greeting", b"hi".to_vec()).with_version(1).encode(MessageFormat::Json).unwrap();
+        let upgraded = controller.decode_envelope(&old).unwrap();
+        assert_eq!(upgraded, Envelope::new("hello", b"hi".to_vec()).with_version(2));
+
+        let reply = controller.encode_envelope(&Envelope::new("hello", b"hey".to_vec())).unwrap();
+        let downgraded = Envelope::decode(&reply, MessageFormat::Json).unwrap();
+        assert_eq!(downgraded, Envelope::new("greeting", b"hey".to_vec()).with_version(1));
+        assert_eq!(controller.migrations().unwrap().peer_version(), Some(1));
+    }
+
+    /// Tests that controllers sharing `Limits` are refused connections beyond the limit until
+    /// a slot is freed.
+    #[tokio::test]
+    async fn test_shared_connection_limit() {
+        let server = crate::testing::EchoServer::start().await.expect("Failed to start echo server");
+        let limits = Arc::new(Limits::new(crate::limits::LimitsConfig { max_connections: Some(2), ..Default::default() }));
+        let mut first = WebSocketController::new(server.url(), 0, None);
+        let mut second = WebSocketController::new(server.url(), 0, None);
+        first.set_limits(Some(limits.clone()));
+        second.set_limits(Some(limits.clone()));
+
+        let handle = first.connect_handle(PipelineConfig::default()).await.unwrap();
+        let _raw = second.connect().await.unwrap();
+        assert_eq!(limits.connections(), 2);
+        let refused = first.connect_handle(PipelineConfig::default()).await.expect_err("Expected the limit to be reached");
+        assert!(refused.to_string().contains("limit of 2 connections"), "Unexpected error: {}", refused);
+        // Reconnecting the raw connection reuses its own slot.
+        let _raw = second.connect().await.unwrap();
+
+        handle.send(Message::Binary(vec![1; 64])).await.unwrap();
+        assert_eq!(handle.recv_inbound().await, Some(InboundMessage::Binary(vec![1; 64])));
+        assert_eq!(limits.buffered_bytes(), 0);
+        handle.shutdown().await.unwrap();
+        timeout(Duration::from_secs(1), async {
+            while limits.connections() > 1 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Expected the pipeline to free its slot");
+        assert!(first.connect_handle(PipelineConfig::default()).await.is_ok());
+    }
+
+    /// Tests that connects, messages and errors are recorded in the history.
+    #[tokio::test]
+    async fn test_connection_history() -> Result<(), Box<dyn StdError>> {
+        let server = crate::testing::EchoServer::start().await?;
+        let history = Arc::new(ConnectionHistory::new(16));
+        let mut controller = WebSocketController::new(server.url(), 0, None);
+        controller.set_history(Some(history.clone()));
+
+        let mut ws_stream = controller.connect().await?;
+        controller.send_message(&mut ws_stream, b"hello").await?;
+        controller.receive_raw(&mut ws_stream).await?;
+        let events: Vec<HistoryEvent> = history.entries().into_iter().map(|entry| entry.event).collect();
+        assert!(matches!(events[0], HistoryEvent::Connecting { .. }));
+        assert_eq!(events[1], HistoryEvent::Connected);
+        assert_eq!(events[2].to_string(), "-> binary (5 bytes) \"hello\"");
+        assert_eq!(events[3].to_string(), "<- binary (5 bytes) \"hello\"");
+        controller.send_stream(&mut ws_stream, &b"streamed"[..], StreamOptions::default()).await?;
+        assert_eq!(history.entries().last().unwrap().event.to_string(), "-> binary (8 bytes) \"\"");
+
+        let unreachable = {
+            let mut controller = WebSocketController::new("ws://127.0.0.1:1", 0, None);
+            controller.set_history(Some(history.clone()));
+            controller
+        };
+        assert!(unreachable.connect().await.is_err());
+        assert!(history.entries().last().unwrap().event.is_error());
+        Ok(())
+    }
+
+    /// Tests that outbound and inbound maps rewrite envelopes at both levels.
+    #[test]
+    fn test_message_maps() {
+        let mut controller = WebSocketController::new("ws://example.com", 0, None);
+        controller.map_outbound(MessageMap::envelope(|envelope| Ok(envelope.with_id("trace-7"))));
+        controller.map_outbound(MessageMap::payload(|payload| Ok(payload.iter().rev().copied().collect())));
+        controller.map_inbound(MessageMap::payload(|payload| Ok(payload.iter().rev().copied().collect())));
+        controller.map_inbound(MessageMap::envelope(|mut envelope| {
+            envelope.kind = envelope.kind.to_uppercase();
+            Ok(envelope)
+        }));
+
+        let encoded = controller.encode_envelope(&Envelope::new("order", b"{}".to_vec())).unwrap();
+        assert!(Envelope::decode(&encoded, controller.format()).is_err(), "Expected the serialized map to run");
+        let decoded = controller.decode_envelope(&encoded).unwrap();
+        assert_eq!((decoded.kind.as_str(), decoded.id.as_deref()), ("ORDER", Some("trace-7")));
+
+        controller.map_outbound(MessageMap::envelope(|_| Err("blocked".to_string())));
+        assert_eq!(controller.encode_envelope(&Envelope::new("order", Vec::new())).unwrap_err(), "blocked");
+    }
+
+    /// Tests that the auth frame precedes queued messages and rejections fail the connect.
+    #[tokio::test]
+    async fn test_auth_message() -> Result<(), Box<dyn StdError>> {
+        let server = crate::testing::EchoServer::start().await?;
+        let mut controller = WebSocketController::new(server.url(), 0, None);
+        // The echo server answers the auth frame with itself.
+        let auth = AuthMessage::new(|| Ok(Message::Text("auth".into())), Duration::from_secs(1))
+            .with_reply(|reply| if reply == &Message::Text("auth".into()) { Ok(()) } else { Err("bad reply".into()) });
+        controller.set_auth_message(Some(auth));
+        let (sender, mut receiver, tasks) = controller.connect_pipeline(controller.pipeline_config()).await?;
+        sender.send(Message::Text("hello".into())).await?;
+        let echoed = timeout(Duration::from_secs(1), receiver.recv()).await?;
+        assert_eq!(echoed, Some(Message::Text("hello".into())));
+        tasks.abort();
+
+        let rejecting = AuthMessage::new(|| Ok(Message::Text("auth".into())), Duration::from_secs(1))
+            .with_reply(|_| Err("invalid token".into()));
+        controller.set_auth_message(Some(rejecting));
+        let error = controller.connect().await.unwrap_err();
+        assert!(is_auth_rejected(error.as_ref()), "{}", error);
+        Ok(())
+    }
+
+    /// Tests that inbound protocol violations reach the hook as structured reports.
+    #[tokio::test]
+    async fn test_violation_reports() {
+        use crate::violation::ViolationRule;
+        use tokio::io::AsyncWriteExt;
+
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut controller = WebSocketController::new("ws://example.com", 1, None);
+        controller.set_text_mode(TextMode::Validated);
+        let sink = reports.clone();
+        controller.set_violation_hook(move |violation| sink.lock().unwrap().push(violation.clone()));
+
+        let (mut client, mut raw_peer) = crate::testing::raw_memory_pair(None).await;
+        // A binary frame that is not UTF-8, read in validated text mode.
+        raw_peer.write_all(&[0x82, 0x03, b'o', b'k', 0xff]).await.unwrap();
+        assert!(controller.receive_inbound(&mut client).await.is_err());
+        // A frame with the reserved data opcode 3.
+        raw_peer.write_all(&[0x83, 0x00]).await.unwrap();
+        assert!(controller.receive_raw(&mut client).await.is_err());
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!((reports[0].rule, reports[0].offset, reports[0].excerpt.clone()), (ViolationRule::InvalidUtf8, Some(2), vec![0xff]));
+        assert_eq!((reports[1].rule, reports[1].direction), (ViolationRule::InvalidOpcode, Direction::Inbound));
+        assert_eq!(reports[1].excerpt, vec![3]);
+    }
+
+    /// Tests that stamped envelopes round-trip once and replays are rejected.
+    #[test]
+    fn test_replay_protection() {
+        let mut controller = WebSocketController::new("ws://example.com", 1, None);
+        controller.set_replay_policy(Some(ReplayPolicy::default()));
+
+        let payload = controller.encode_envelope(&Envelope::new("unlock", Vec::new())).unwrap();
+        let received = controller.decode_envelope(&payload).unwrap();
+        assert!(received.timestamp.is_some() && received.nonce.is_some());
+        let replayed = controller.decode_envelope(&payload).unwrap_err();
+        assert!(replayed.contains("Rejected unlock envelope"), "Unexpected error: {}", replayed);
+
+        let unstamped = Envelope::new("unlock", Vec::new()).encode(MessageFormat::Json).unwrap();
+        assert!(controller.decode_envelope(&unstamped).is_err());
+    }
+
+    /// Tests that sent envelopes continue the trace of the message being handled.
+    #[tokio::test]
+    async fn test_trace_propagation() {
+        let mut controller = WebSocketController::new("ws://example.com", 1, None);
+        let request = Envelope::new("quote", Vec::new());
+        assert!(controller.trace_context(&request).is_none());
+        controller.set_trace_propagation(Some(TracePropagation::default()));
+
+        let received = controller.decode_envelope(&controller.encode_envelope(&request).unwrap()).unwrap();
+        let context = controller.trace_context(&received).unwrap();
+        let reply = context
+            .scope(async { controller.encode_envelope(&Envelope::new("ack", Vec::new())).unwrap() })
+            .await;
+        let continued = controller.trace_context(&controller.decode_envelope(&reply).unwrap()).unwrap();
+        assert_eq!(continued.trace_id, context.trace_id);
+        assert_ne!(continued.parent_id, context.parent_id);
+    }
+
+    /// Tests that encoded envelopes carry their send time and decoded ones their receive time.
+    #[test]
+    fn test_timestamps() {
+        let mut controller = WebSocketController::new("ws://example.com", 1, None);
+        let plain = controller.decode_envelope(&controller.encode_envelope(&Envelope::new("quote", Vec::new())).unwrap()).unwrap();
+        assert!(plain.headers.is_empty());
+        controller.set_timestamps(Some(Timestamps::new()));
+
+        let encoded = controller.encode_envelope(&Envelope::new("quote", Vec::new())).unwrap();
+        let received = controller.decode_envelope(&encoded).unwrap();
+        let latency = controller.latency(&received).unwrap();
+        assert!(latency.received_at >= latency.sent_at);
+        assert_eq!(latency.one_way, None);
+    }
+
+    /// Tests that `reconfigure` applies URLs, ping interval and rate limits, and rejects
+    /// invalid configs without changing anything.
+    #[test]
+    fn test_reconfigure() {
+        let config = Config { urls: vec!["ws://primary.example.com".to_string()], ..Config::default() };
+        let mut controller = WebSocketController::from_config(&config).unwrap();
+        let mut updates = controller.watch_inbound_rate_limit();
+        assert_eq!(controller.inbound_rate_limit(), None);
+
+        let updated = Config {
+            urls: vec!["ws://primary.example.com".to_string(), "ws://backup.example.com".to_string()],
+            ping_interval_secs: Some(30),
+            inbound_messages_per_sec: Some(10.0),
+            ..config.clone()
+        };
+        controller.reconfigure(&updated).unwrap();
+        assert_eq!(controller.client.urls(), vec!["ws://primary.example.com", "ws://backup.example.com"]);
+        assert_eq!(controller.ping_interval, Duration::from_secs(30));
+        assert!(updates.has_changed().unwrap());
+        assert_eq!(updates.borrow_and_update().unwrap().messages_per_sec, Some(10.0));
+
+        controller.reconfigure(&updated).unwrap();
+        assert!(!updates.has_changed().unwrap());
+
+        let invalid = Config { urls: Vec::new(), ..updated };
+        assert!(controller.reconfigure(&invalid).is_err());
+        assert_eq!(controller.client.urls().len(), 2);
+    }
+
     /// Tests the ping mechanism of `WebSocketController`.
     #[tokio::test]
     async fn test_send_ping() -> Result<(), Box<dyn StdError>> {
         let url = start_mock_server().await;
-        let mut controller = WebSocketController::new(&url, 3, Some(5));
+        let controller = WebSocketController::new(&url, 3, Some(5));
         let mut ws_stream = controller.connect().await.unwrap();
 
         let ping_result = controller.send_ping(&mut ws_stream).await;
@@ -337,6 +3044,7 @@ mod tests {
     }
 
     /// Tests the reconnection logic of `WebSocketController`.
+    #[cfg(feature = "reconnection")]
     #[tokio::test]
     async fn test_reconnect_logic() -> Result<(), Box<dyn StdError>> {
         let url = start_mock_server().await;
@@ -350,5 +3058,38 @@ mod tests {
         );
         Ok(())
     }
+
+    /// Tests that `reconnect_if_needed` honors the strategy's attempt timeout and time budget
+    /// and reports its failures to the circuit breaker.
+    #[cfg(feature = "reconnection")]
+    #[tokio::test]
+    async fn test_reconnect_if_needed_strategy_limits() {
+        use crate::reconnection::{Constant, ReconnectError};
+
+        // The listener accepts TCP connections but never answers the WebSocket handshake.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut controller = WebSocketController::new(&format!("ws://{}", listener.local_addr().unwrap()), 0, None);
+        let strategy = ReconnectStrategy::new_with_backoff(1000, Constant(Duration::from_millis(10)))
+            .with_attempt_timeout(Duration::from_millis(50))
+            .with_max_duration(Duration::from_millis(300));
+        controller.set_reconnect_strategy(Some(strategy));
+        let error = timeout(Duration::from_secs(5), controller.reconnect_if_needed())
+            .await
+            .expect("Expected reconnection to give up within its budget")
+            .expect_err("Expected reconnection to fail");
+        let error = error.downcast_ref::<ReconnectError>().expect("Expected a ReconnectError");
+        assert!(matches!(error, ReconnectError::DeadlineExceeded { attempts, .. } if *attempts > 1), "{}", error);
+
+        // A port nothing listens on.
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let mut controller = WebSocketController::new(&url, 0, None);
+        controller.set_reconnect_strategy(Some(ReconnectStrategy::new_with_backoff(3, Constant(Duration::from_millis(1)))));
+        controller.set_circuit_breaker(Some(Arc::new(CircuitBreaker::new(2, Duration::from_secs(60)))));
+        let error = controller.reconnect_if_needed().await.expect_err("Expected reconnection to fail");
+        let error = error.downcast_ref::<ReconnectError>().expect("Expected a ReconnectError");
+        assert!(matches!(error, ReconnectError::RetriesExhausted { attempts: 3 }), "{}", error);
+        assert!(matches!(controller.circuit_state(), Some(CircuitState::Open { .. })));
+    }
 }
 
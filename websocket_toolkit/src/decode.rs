@@ -0,0 +1,312 @@
+//! # `decode.rs`: Offloaded decoding with ordered hand-back
+//!
+//! Deserializing a multi-megabyte CBOR document (or decompressing one first) takes long
+//! enough to stall every other task scheduled on the same reactor thread. `OrderedDecoder`
+//! runs payloads at or above a size threshold on tokio's blocking pool via `spawn_blocking`,
+//! decodes small payloads inline, and hands results back strictly in the order the payloads
+//! arrived, so consumers see the same sequence as without offloading.
+//!
+//! The decode step is pluggable with `OrderedDecoder::with_decoder`, so a decompression
+//! stage can run on the worker together with deserialization. `OrderedDecoder::spawn` wires a
+//! decoder to a pipeline's `PipelineReceiver`.
+
+use crate::messages::{MessageFormat, MessageHandler};
+use crate::pipeline::PipelineReceiver;
+use crate::tasks::spawn_named;
+use log::{debug, error};
+use serde::de::DeserializeOwned;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+
+/// A function decoding one payload.
+pub type DecodeFn<T> = Arc<dyn Fn(&[u8]) -> Result<T, String> + Send + Sync>;
+
+/// When payloads are offloaded and how many decodes may run at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeConfig {
+    /// The format used by the default decoder.
+    pub format: MessageFormat,
+    /// Payloads of at least this many bytes are decoded on the blocking pool; smaller ones
+    /// are decoded inline, where spawning would cost more than decoding.
+    pub offload_threshold: usize,
+    /// The maximum number of payloads decoded but not yet handed back. `OrderedDecoder::spawn`
+    /// stops reading from the connection while this many are in flight.
+    pub max_in_flight: usize,
+}
+
+impl DecodeConfig {
+    /// Creates a configuration for `format` that offloads payloads of 64 KiB or more and keeps
+    /// up to one in-flight decode per available CPU.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format used by the default decoder.
+    ///
+    /// # Returns
+    ///
+    /// A new `DecodeConfig`.
+    pub fn new(format: MessageFormat) -> Self {
+        DecodeConfig {
+            format,
+            offload_threshold: 64 * 1024,
+            max_in_flight: std::thread::available_parallelism().map_or(4, |n| n.get()),
+        }
+    }
+}
+
+/// A payload's decode, either finished inline or running on the blocking pool.
+enum Pending<T> {
+    /// Decoded inline.
+    Ready(Result<T, String>),
+    /// Decoding on the blocking pool.
+    Running(JoinHandle<Result<T, String>>),
+}
+
+/// Decodes payloads, offloading large ones, and returns results in submission order.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::decode::{DecodeConfig, OrderedDecoder};
+/// use websocket_toolkit::messages::MessageFormat;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let mut config = DecodeConfig::new(MessageFormat::Json);
+/// config.offload_threshold = 8;
+/// let mut decoder = OrderedDecoder::<String>::new(config);
+///
+/// decoder.push(b"\"a fairly long string\"".to_vec());
+/// decoder.push(b"\"short\"".to_vec());
+/// assert_eq!(decoder.next().await, Some(Ok("a fairly long string".to_string())));
+/// assert_eq!(decoder.next().await, Some(Ok("short".to_string())));
+/// # }
+/// ```
+pub struct OrderedDecoder<T> {
+    config: DecodeConfig,
+    decoder: DecodeFn<T>,
+    pending: VecDeque<Pending<T>>,
+}
+
+impl<T> OrderedDecoder<T>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    /// Creates a decoder that deserializes payloads with `MessageHandler` in `config.format`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The offload threshold, concurrency limit and format.
+    ///
+    /// # Returns
+    ///
+    /// A new `OrderedDecoder`.
+    pub fn new(config: DecodeConfig) -> Self {
+        let format = config.format;
+        OrderedDecoder {
+            config,
+            decoder: Arc::new(move |data: &[u8]| {
+                MessageHandler::deserialize(data, format)?.ok_or_else(|| "Message payload is empty".to_string())
+            }),
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> OrderedDecoder<T>
+where
+    T: Send + 'static,
+{
+    /// Replaces the decode step, for example to decompress payloads before deserializing them.
+    ///
+    /// # Arguments
+    ///
+    /// * `decoder` - The function run on each payload; it runs on a worker for large payloads.
+    ///
+    /// # Returns
+    ///
+    /// The decoder with the decode step replaced.
+    pub fn with_decoder<F>(mut self, decoder: F) -> Self
+    where
+        F: Fn(&[u8]) -> Result<T, String> + Send + Sync + 'static,
+    {
+        self.decoder = Arc::new(decoder);
+        self
+    }
+
+    /// Starts decoding a payload, on the blocking pool if it reaches the offload threshold.
+    ///
+    /// Must be called within a tokio runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The encoded payload.
+    pub fn push(&mut self, payload: Vec<u8>) {
+        if payload.len() >= self.config.offload_threshold {
+            debug!("Offloading decode of {} bytes", payload.len());
+            let decoder = self.decoder.clone();
+            self.pending
+                .push_back(Pending::Running(tokio::task::spawn_blocking(move || decoder(&payload))));
+        } else {
+            self.pending.push_back(Pending::Ready((self.decoder)(&payload)));
+        }
+    }
+
+    /// Waits for the oldest outstanding decode.
+    ///
+    /// Cancel-safe: if the returned future is dropped, the decode stays queued.
+    ///
+    /// # Returns
+    ///
+    /// The result of the earliest pushed payload not yet handed back, or `None` if nothing
+    /// is outstanding.
+    pub async fn next(&mut self) -> Option<Result<T, String>> {
+        let result = match self.pending.front_mut()? {
+            Pending::Ready(_) => None,
+            Pending::Running(handle) => Some(
+                handle
+                    .await
+                    .unwrap_or_else(|e| Err(format!("Decode task failed: {}", e))),
+            ),
+        };
+        match (result, self.pending.pop_front()) {
+            (Some(result), _) => Some(result),
+            (None, Some(Pending::Ready(result))) => Some(result),
+            _ => unreachable!("the front entry was just inspected"),
+        }
+    }
+
+    /// Returns the number of payloads pushed but not yet handed back.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns whether no payloads are outstanding.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Returns whether `max_in_flight` payloads are outstanding.
+    pub fn is_full(&self) -> bool {
+        self.pending.len() >= self.config.max_in_flight.max(1)
+    }
+
+    /// Decodes every data message from `receiver` in a task named `websocket_toolkit::decoder`.
+    ///
+    /// Must be called within a tokio runtime. The task stops reading while `max_in_flight`
+    /// decodes are outstanding and ends once the connection closes and every result has been
+    /// handed back, or once the returned `DecodedReceiver` is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `receiver` - The receiving half of a pipeline.
+    ///
+    /// # Returns
+    ///
+    /// A `DecodedReceiver` yielding decoded messages in arrival order.
+    pub fn spawn(mut self, mut receiver: PipelineReceiver) -> DecodedReceiver<T> {
+        let (decoded_tx, decoded) = mpsc::channel(self.config.max_in_flight.max(1));
+        let task = spawn_named("websocket_toolkit::decoder", async move {
+            let mut open = true;
+            while open || !self.is_empty() {
+                tokio::select! {
+                    message = receiver.recv(), if open && !self.is_full() => match message {
+                        Some(Message::Text(text)) => self.push(text.into_bytes()),
+                        Some(Message::Binary(data)) => self.push(data),
+                        _ => open = false,
+                    },
+                    Some(result) = self.next(), if !self.is_empty() => {
+                        if let Err(e) = &result {
+                            error!("Failed to decode message: {}", e);
+                        }
+                        if decoded_tx.send(result).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+            debug!("Decoder finished");
+        });
+        DecodedReceiver { decoded, task }
+    }
+}
+
+/// Decoded messages from a pipeline, in arrival order.
+pub struct DecodedReceiver<T> {
+    decoded: mpsc::Receiver<Result<T, String>>,
+    /// The decoder task, named `websocket_toolkit::decoder`.
+    pub task: JoinHandle<()>,
+}
+
+impl<T> DecodedReceiver<T> {
+    /// Waits for the next decoded message.
+    ///
+    /// # Returns
+    ///
+    /// The next message's decode result, or `None` once the connection has ended and every
+    /// message has been handed back.
+    pub async fn recv(&mut self) -> Option<Result<T, String>> {
+        self.decoded.recv().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::WebSocketClient;
+    use crate::pipeline::{self, PipelineConfig};
+    use crate::testing::EchoServer;
+    use std::time::Duration;
+
+    /// Tests that slow offloaded decodes are still handed back in submission order.
+    #[tokio::test]
+    async fn test_results_keep_submission_order() {
+        let config = DecodeConfig {
+            format: MessageFormat::Json,
+            offload_threshold: 4,
+            max_in_flight: 8,
+        };
+        let mut decoder = OrderedDecoder::new(config).with_decoder(|data: &[u8]| {
+            if data.len() >= 4 {
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            Ok(data.len())
+        });
+
+        for len in [10, 1, 6, 2] {
+            decoder.push(vec![0; len]);
+        }
+        assert_eq!(decoder.len(), 4);
+        let mut lengths = Vec::new();
+        while let Some(result) = decoder.next().await {
+            lengths.push(result.unwrap());
+        }
+        assert_eq!(lengths, vec![10, 1, 6, 2]);
+    }
+
+    /// Tests decoding CBOR messages received through a pipeline.
+    #[cfg(feature = "cbor")]
+    #[tokio::test]
+    async fn test_spawn_decodes_pipeline_messages() {
+        let server = EchoServer::start().await.expect("Failed to start echo server");
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let (sender, receiver, _tasks) = pipeline::spawn(ws_stream, PipelineConfig::default());
+        let config = DecodeConfig {
+            offload_threshold: 1024,
+            ..DecodeConfig::new(MessageFormat::Cbor)
+        };
+        let mut decoded = OrderedDecoder::<Vec<u32>>::new(config).spawn(receiver);
+
+        let large: Vec<u32> = (0..10_000).collect();
+        for value in [large.clone(), vec![1], large.clone()] {
+            let payload = MessageHandler::serialize(&value, MessageFormat::Cbor).unwrap();
+            sender.send_binary(payload).await.unwrap();
+        }
+        assert_eq!(decoded.recv().await, Some(Ok(large.clone())));
+        assert_eq!(decoded.recv().await, Some(Ok(vec![1])));
+        assert_eq!(decoded.recv().await, Some(Ok(large)));
+    }
+}
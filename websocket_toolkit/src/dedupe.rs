@@ -0,0 +1,232 @@
+//! # `dedupe.rs`: Idempotency-key filtering of inbound messages
+//!
+//! The durable outbox delivers at-least-once: a message whose ack was lost in a crash or a
+//! reconnect is sent again. `DedupeWindow` makes the receiving side apply each message once by
+//! remembering the idempotency keys (the `Envelope` ids) of the last `capacity` messages in a
+//! `Storage` backend (a sled database with `DedupeWindow::open`), so the window survives
+//! restarts of the receiver too. Both ends of a
+//! toolkit-to-toolkit link can run an `Outbox` for what they send and a `DedupeWindow` for what
+//! they receive.
+//!
+//! Duplicates should still be acknowledged, so the sender stops resending them:
+//!
+//! ```rust
+//! use websocket_toolkit::dedupe::DedupeWindow;
+//! use websocket_toolkit::messages::Envelope;
+//! use websocket_toolkit::outbox::ack_envelope;
+//!
+//! let window = DedupeWindow::temporary(1024).unwrap();
+//! let command = Envelope::new("open_valve", Vec::new()).with_id("17");
+//! for _ in 0..2 {
+//!     if window.accept(&command).unwrap() {
+//!         // Apply the command; runs once.
+//!     }
+//!     let _ack = ack_envelope(command.id.clone().unwrap());
+//! }
+//! ```
+//!
+//! Enabled by the `outbox` feature.
+
+use crate::messages::Envelope;
+use crate::storage::{MemoryStorage, SledStorage, Storage};
+use log::debug;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// The namespace mapping idempotency keys to their insertion sequence numbers.
+const KEYS_NAMESPACE: &str = "dedupe_keys";
+
+/// The namespace mapping big-endian insertion sequence numbers to idempotency keys, for
+/// evicting the oldest keys.
+const ORDER_NAMESPACE: &str = "dedupe_order";
+
+/// A persistent window of recently seen idempotency keys.
+#[derive(Debug, Clone)]
+pub struct DedupeWindow {
+    storage: Arc<dyn Storage>,
+    /// The next insertion sequence number; held while recording so checks are atomic.
+    next: Arc<Mutex<u64>>,
+    capacity: usize,
+}
+
+impl DedupeWindow {
+    /// Opens (or creates) the window database at `path`, keeping the keys recorded before.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory of the sled database.
+    /// * `capacity` - The number of most recent keys remembered.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the window, or an error message if the database cannot be opened.
+    pub fn open(path: impl AsRef<Path>, capacity: usize) -> Result<Self, String> {
+        let storage = SledStorage::open(path).map_err(|e| format!("Failed to open dedupe window: {}", e))?;
+        Self::with_storage(Arc::new(storage), capacity)
+    }
+
+    /// Creates a window that keeps its keys in memory only, for tests.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - The number of most recent keys remembered.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the window, or an error message on failure.
+    pub fn temporary(capacity: usize) -> Result<Self, String> {
+        Self::with_storage(Arc::new(MemoryStorage::new()), capacity)
+    }
+
+    /// Creates a window on `storage`, keeping the keys already recorded there.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - The persistence backend; it may be shared with other components.
+    /// * `capacity` - The number of most recent keys remembered.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the window, or an error message if the backend cannot be read.
+    pub fn with_storage(storage: Arc<dyn Storage>, capacity: usize) -> Result<Self, String> {
+        let next = storage
+            .iterate(ORDER_NAMESPACE)
+            .map_err(|e| format!("Failed to open dedupe window: {}", e))?
+            .last()
+            .and_then(|(sequence, _)| <[u8; 8]>::try_from(sequence.as_slice()).ok())
+            .map_or(0, |sequence| u64::from_be_bytes(sequence) + 1);
+        Ok(DedupeWindow {
+            storage,
+            next: Arc::new(Mutex::new(next)),
+            capacity: capacity.max(1),
+        })
+    }
+
+    /// Records `key`, evicting the oldest key if the window is full.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The idempotency key.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `true` if `key` had not been seen within the window, or an error
+    /// message on failure.
+    pub fn check_and_record(&self, key: &str) -> Result<bool, String> {
+        let storage_error = |e: String| format!("Failed to update dedupe window: {}", e);
+        let mut next = self.next.lock().unwrap();
+        if self.storage.get(KEYS_NAMESPACE, key.as_bytes()).map_err(storage_error)?.is_some() {
+            debug!("Dropping duplicate message {}", key);
+            return Ok(false);
+        }
+        let sequence = next.to_be_bytes();
+        *next += 1;
+        self.storage
+            .put(KEYS_NAMESPACE, key.as_bytes(), &sequence)
+            .map_err(storage_error)?;
+        self.storage
+            .put(ORDER_NAMESPACE, &sequence, key.as_bytes())
+            .map_err(storage_error)?;
+        let order = self.storage.iterate(ORDER_NAMESPACE).map_err(storage_error)?;
+        let excess = order.len().saturating_sub(self.capacity);
+        for (sequence, oldest) in order.into_iter().take(excess) {
+            self.storage.delete(ORDER_NAMESPACE, &sequence).map_err(storage_error)?;
+            self.storage.delete(KEYS_NAMESPACE, &oldest).map_err(storage_error)?;
+        }
+        self.storage.flush().map_err(storage_error)?;
+        Ok(true)
+    }
+
+    /// Returns whether `envelope` should be applied.
+    ///
+    /// Envelopes without an id carry no idempotency key and are always accepted.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - A received envelope.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `false` if `envelope` is a duplicate, or an error message on failure.
+    pub fn accept(&self, envelope: &Envelope) -> Result<bool, String> {
+        match envelope.id.as_deref() {
+            Some(key) => self.check_and_record(key),
+            None => Ok(true),
+        }
+    }
+
+    /// Returns the number of keys currently remembered, or 0 if the backend cannot be read.
+    pub fn len(&self) -> usize {
+        self.storage.len(KEYS_NAMESPACE).unwrap_or(0)
+    }
+
+    /// Returns whether no keys are remembered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests duplicate detection and eviction of the oldest keys.
+    #[test]
+    fn test_duplicates_and_eviction() {
+        let window = DedupeWindow::temporary(2).unwrap();
+        assert!(window.check_and_record("a").unwrap());
+        assert!(!window.check_and_record("a").unwrap());
+        assert!(window.check_and_record("b").unwrap());
+        assert!(window.check_and_record("c").unwrap());
+        assert_eq!(window.len(), 2);
+        assert!(window.check_and_record("a").unwrap(), "Expected `a` to have been evicted");
+        assert!(!window.check_and_record("c").unwrap());
+
+        assert!(window.accept(&Envelope::new("no_id", Vec::new())).unwrap());
+        assert!(window.accept(&Envelope::new("no_id", Vec::new())).unwrap());
+    }
+
+    /// A temporary directory, removed when dropped.
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        /// Returns a path under the system temp directory unique to this process and call.
+        fn new(prefix: &str) -> Self {
+            let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos();
+            TempDir(std::env::temp_dir().join(format!("{}-{}-{}", prefix, std::process::id(), nanos)))
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Opens the window at `path`, waiting for a previous handle's background flusher to
+    /// release sled's file lock.
+    fn reopen(path: &Path, capacity: usize) -> DedupeWindow {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            match DedupeWindow::open(path, capacity) {
+                Ok(window) => return window,
+                Err(e) if e.contains("lock") && std::time::Instant::now() < deadline => {
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+                Err(e) => panic!("{}", e),
+            }
+        }
+    }
+
+    /// Tests that the window survives reopening the database.
+    #[test]
+    fn test_window_survives_restart() {
+        let dir = TempDir::new("wstk-dedupe-test");
+        {
+            let window = DedupeWindow::open(&dir.0, 16).unwrap();
+            assert!(window.accept(&Envelope::new("cmd", Vec::new()).with_id("1")).unwrap());
+        }
+        let window = reopen(&dir.0, 16);
+        assert!(!window.accept(&Envelope::new("cmd", Vec::new()).with_id("1")).unwrap());
+    }
+}
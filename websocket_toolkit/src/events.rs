@@ -0,0 +1,799 @@
+//! # `events.rs`: Event-driven connections
+//!
+//! Reading with `receive_message` means writing the same loop in every application: receive,
+//! dispatch, notice the close, decide whether to reconnect. `EventDriver` runs that loop in a
+//! task instead, calling the handlers registered on the controller with
+//! `WebSocketController::on_open`, `on_message`, `on_close` and `on_error`:
+//!
+//! - `on_open` is called with the connection's `ConnectionHandle` after every connect, e.g.
+//!   to subscribe; clones of the handle can be kept for sending.
+//! - `on_message` is called with every text and binary message, in order.
+//! - `on_close` is called when a connection ends, with the server's close frame if it sent
+//!   one.
+//! - `on_error` is called with every failed connection attempt.
+//! - `on_reconnect_scheduled` is called with a `ReconnectScheduled` before every wait for a
+//!   reconnect: the attempt number, the retry limit, the delay and when it ends, e.g. for a
+//!   "reconnecting in 12s (attempt 3/10)" banner. `ReconnectScheduled::cancel`, or a
+//!   `ReconnectCancel` taken from it for a cancel button, stops the driver instead of
+//!   reconnecting.
+//!
+//! The driver reconnects the way the stream-based methods do: after a close frame when the
+//! controller's close policy asks for it (`set_close_policy`), and after a dropped
+//! connection or a failed attempt with the controller's reconnection strategy
+//! (`set_reconnect_strategy`), so its retries, backoff, jitter, time budget and attempt
+//! timeout apply. It stops once a close is surfaced, the strategy gives up, credentials are
+//! rejected or `EventDriver::stop` is called. Without the `reconnection` feature it only
+//! reconnects when the close policy asks for it.
+//!
+//! With a maintenance policy set (`set_maintenance_policy`), a maintenance notice from the
+//! server makes the driver move before the announced restart: it connects to the notice's
+//! alternate endpoint, calls `on_open` with the new connection and closes the old one without
+//! calling `on_close`. Messages the old server sends after the move are not delivered.
+//!
+//! Handlers run on the driver task, so a slow `on_message` holds up the next message; hand
+//! long work to another task.
+//!
+//! A panic in `on_open`, `on_message`, `on_close` or `on_reconnect_scheduled` does not
+//! take the connection down: the driver catches it and calls `on_error` with a
+//! `HandlerPanic`, then applies the controller's `PanicPolicy`
+//! (`set_handler_panic_policy`). By default it keeps calling the handler for later events;
+//! `PanicPolicy::Disable` stops calling the handler that panicked, and
+//! `PanicPolicy::FailFast` lets the panic unwind and end the driver.
+
+use crate::auth::is_auth_rejected;
+use crate::close::{CloseAction, ServerClosed};
+use crate::controller::WebSocketController;
+use crate::handle::ConnectionHandle;
+use crate::messages::InboundMessage;
+use crate::pipeline::PipelineConfig;
+use crate::tasks::spawn_named;
+use log::{debug, info, warn};
+use std::any::Any;
+use std::error::Error as StdError;
+use std::fmt;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Called with the handle of every new connection.
+pub type OnOpen = Arc<dyn Fn(&ConnectionHandle) + Send + Sync>;
+
+/// Called with every received text or binary message.
+pub type OnMessage = Arc<dyn Fn(InboundMessage) + Send + Sync>;
+
+/// Called when a connection ends, with the server's close frame if it sent one.
+pub type OnClose = Arc<dyn Fn(Option<&ServerClosed>) + Send + Sync>;
+
+/// Called with every failed connection attempt.
+pub type OnError = Arc<dyn Fn(&(dyn StdError + 'static)) + Send + Sync>;
+
+/// Called before every wait for a reconnect.
+pub type OnReconnectScheduled = Arc<dyn Fn(&ReconnectScheduled) + Send + Sync>;
+
+/// A reconnect the driver is waiting for; passed to `on_reconnect_scheduled`.
+#[derive(Debug, Clone)]
+pub struct ReconnectScheduled {
+    /// The number of the attempt about to be made, starting at 1: counted by the
+    /// reconnection strategy when backing off after a failure, or the consecutive reconnects
+    /// the close policy asked for.
+    pub attempt: u32,
+    /// The reconnection strategy's retries when backing off after a failure, or `None` when
+    /// the close policy decides whether to reconnect.
+    pub max_attempts: Option<u32>,
+    /// How long the driver waits before connecting.
+    pub delay: Duration,
+    /// When the wait ends.
+    pub at: SystemTime,
+    cancel: ReconnectCancel,
+}
+
+impl ReconnectScheduled {
+    /// Stops the driver instead of reconnecting.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Returns a handle that cancels this reconnect, e.g. for a cancel button.
+    pub fn canceller(&self) -> ReconnectCancel {
+        self.cancel.clone()
+    }
+}
+
+impl fmt::Display for ReconnectScheduled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Reconnecting in {}s (attempt {}", self.delay.as_secs_f64().ceil(), self.attempt)?;
+        match self.max_attempts {
+            Some(max_attempts) => write!(f, "/{})", max_attempts),
+            None => write!(f, ")"),
+        }
+    }
+}
+
+/// Cancels one scheduled reconnect, stopping the driver. Cancelling after the wait ended has
+/// no effect.
+#[derive(Debug, Clone)]
+pub struct ReconnectCancel(Arc<watch::Sender<bool>>);
+
+impl ReconnectCancel {
+    /// Cancels the reconnect.
+    pub fn cancel(&self) {
+        self.0.send_replace(true);
+    }
+}
+
+/// What `EventDriver` does when `on_open`, `on_message`, `on_close` or
+/// `on_reconnect_scheduled` panics. The panic is reported to `on_error` as a
+/// `HandlerPanic` first in every case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PanicPolicy {
+    /// Keeps the connection and calls the handler again for later events.
+    #[default]
+    Restart,
+    /// Keeps the connection and stops calling the handler that panicked.
+    Disable,
+    /// Lets the panic unwind, ending the driver and its connection.
+    FailFast,
+}
+
+/// A handler panicked; passed to `on_error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandlerPanic {
+    /// The handler that panicked: `on_open`, `on_message`, `on_close` or
+    /// `on_reconnect_scheduled`.
+    pub handler: &'static str,
+    /// The panic message.
+    pub message: String,
+}
+
+impl fmt::Display for HandlerPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} handler panicked: {}", self.handler, self.message)
+    }
+}
+
+impl StdError for HandlerPanic {}
+
+/// The handlers registered on a controller.
+#[derive(Clone, Default)]
+pub struct EventHandlers {
+    pub(crate) open: Option<OnOpen>,
+    pub(crate) message: Option<OnMessage>,
+    pub(crate) close: Option<OnClose>,
+    pub(crate) error: Option<OnError>,
+    pub(crate) reconnect_scheduled: Option<OnReconnectScheduled>,
+    pub(crate) panic_policy: PanicPolicy,
+}
+
+impl EventHandlers {
+    fn open(&mut self, handle: &ConnectionHandle) {
+        if let Some(open) = self.open.clone() {
+            if let Err(payload) = catch_unwind(AssertUnwindSafe(|| open(handle))) {
+                self.panicked("on_open", payload, |handlers| handlers.open = None);
+            }
+        }
+    }
+
+    fn message(&mut self, message: InboundMessage) {
+        if let Some(on_message) = self.message.clone() {
+            if let Err(payload) = catch_unwind(AssertUnwindSafe(|| on_message(message))) {
+                self.panicked("on_message", payload, |handlers| handlers.message = None);
+            }
+        }
+    }
+
+    fn close(&mut self, closed: Option<&ServerClosed>) {
+        if let Some(close) = self.close.clone() {
+            if let Err(payload) = catch_unwind(AssertUnwindSafe(|| close(closed))) {
+                self.panicked("on_close", payload, |handlers| handlers.close = None);
+            }
+        }
+    }
+
+    fn reconnect_scheduled(&mut self, scheduled: &ReconnectScheduled) {
+        if let Some(reconnect_scheduled) = self.reconnect_scheduled.clone() {
+            if let Err(payload) = catch_unwind(AssertUnwindSafe(|| reconnect_scheduled(scheduled))) {
+                self.panicked("on_reconnect_scheduled", payload, |handlers| handlers.reconnect_scheduled = None);
+            }
+        }
+    }
+
+    fn error(&self, error: &(dyn StdError + 'static)) {
+        warn!("{}", error);
+        if let Some(on_error) = &self.error {
+            if let Err(payload) = catch_unwind(AssertUnwindSafe(|| on_error(error))) {
+                warn!("on_error handler panicked: {}", panic_message(payload.as_ref()));
+            }
+        }
+    }
+
+    /// Reports a handler's panic and applies the panic policy; `disable` unregisters the
+    /// handler.
+    fn panicked(&mut self, handler: &'static str, payload: Box<dyn Any + Send>, disable: impl FnOnce(&mut Self)) {
+        self.error(&HandlerPanic { handler, message: panic_message(payload.as_ref()) });
+        match self.panic_policy {
+            PanicPolicy::Restart => {}
+            PanicPolicy::Disable => {
+                warn!("Disabling the {} handler after a panic", handler);
+                disable(self);
+            }
+            PanicPolicy::FailFast => resume_unwind(payload),
+        }
+    }
+}
+
+/// Returns the message of a panic payload.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "non-string panic payload".to_string(),
+    }
+}
+
+impl fmt::Debug for EventHandlers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventHandlers")
+            .field("open", &self.open.is_some())
+            .field("message", &self.message.is_some())
+            .field("close", &self.close.is_some())
+            .field("error", &self.error.is_some())
+            .field("reconnect_scheduled", &self.reconnect_scheduled.is_some())
+            .field("panic_policy", &self.panic_policy)
+            .finish()
+    }
+}
+
+/// A task connecting a controller and dispatching its events to the registered handlers.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # async fn example() {
+/// use websocket_toolkit::controller::WebSocketController;
+///
+/// let mut controller = WebSocketController::new("ws://example.com", 3, None);
+/// controller.on_open(|_| println!("connected"));
+/// controller.on_message(|message| println!("received {:?}", message));
+/// controller.on_close(|closed| println!("closed: {:?}", closed));
+/// controller.on_error(|error| eprintln!("{}", error));
+///
+/// let driver = controller.run_events();
+/// driver.send("hello".into()).await.unwrap();
+/// driver.stop().await;
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct EventDriver {
+    current: Arc<Mutex<Option<ConnectionHandle>>>,
+    stop: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+impl EventDriver {
+    /// Starts connecting and dispatching events.
+    ///
+    /// Must be called within a tokio runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `controller` - Opens every connection and holds the handlers.
+    ///
+    /// # Returns
+    ///
+    /// The running `EventDriver`.
+    pub fn start(controller: Arc<WebSocketController>) -> Self {
+        let current = Arc::new(Mutex::new(None));
+        let stop = Arc::new(Notify::new());
+        let task = spawn_named("websocket_toolkit::event_driver", drive(controller, current.clone(), stop.clone()));
+        EventDriver { current, stop, task }
+    }
+
+    /// Returns the open connection, if any.
+    pub fn handle(&self) -> Option<ConnectionHandle> {
+        self.current.lock().unwrap().clone()
+    }
+
+    /// Queues a message on the open connection.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The frame to send.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if no connection is open.
+    pub async fn send(&self, message: Message) -> Result<(), String> {
+        let handle = self.handle().ok_or("Failed to send message: not connected")?;
+        handle.send(message).await
+    }
+
+    /// Closes the open connection, calling `on_close`, and stops the driver.
+    pub async fn stop(self) {
+        self.stop.notify_one();
+        if let Err(e) = self.task.await {
+            warn!("Event driver failed: {}", e);
+        }
+    }
+
+    /// Waits until the driver stops on its own: after a surfaced close, once the
+    /// reconnection strategy gives up, when credentials are rejected or when a reconnect is
+    /// cancelled.
+    pub async fn join(self) {
+        if let Err(e) = self.task.await {
+            warn!("Event driver failed: {}", e);
+        }
+    }
+}
+
+/// Connects, dispatches events and reconnects until told to stop or giving up.
+async fn drive(controller: Arc<WebSocketController>, current: Arc<Mutex<Option<ConnectionHandle>>>, stop: Arc<Notify>) {
+    let mut handlers = controller.event_handlers();
+    let mut close_reconnects = 0;
+    // Whether the last connection dropped without a close frame, so the server may be gone.
+    let mut dropped = false;
+    loop {
+        let connected = tokio::select! {
+            _ = stop.notified() => return,
+            connected = connect(&controller, &mut handlers, dropped) => connected,
+        };
+        let mut handle = match connected {
+            Some(handle) => handle,
+            None => return,
+        };
+        #[cfg(feature = "keep-alive")]
+        controller.maintain_pipeline(handle.sender().clone());
+        *current.lock().unwrap() = Some(handle.clone());
+        handlers.open(&handle);
+
+        // When to move to another endpoint after a maintenance notice, and where.
+        let mut moving: Option<(Instant, Option<String>)> = None;
+        let closed = loop {
+            let move_at = moving.as_ref().map(|(at, _)| *at);
+            let message = tokio::select! {
+                _ = stop.notified() => {
+                    current.lock().unwrap().take();
+                    if let Err(e) = handle.shutdown().await {
+                        debug!("Connection did not close cleanly: {}", e);
+                    }
+                    handlers.close(None);
+                    return;
+                }
+                _ = sleep_until(move_at) => {
+                    let (_, alternate) = moving.take().unwrap();
+                    if let Some(next) = move_connection(&controller, &handlers, alternate).await {
+                        #[cfg(feature = "keep-alive")]
+                        controller.maintain_pipeline(next.sender().clone());
+                        *current.lock().unwrap() = Some(next.clone());
+                        let previous = std::mem::replace(&mut handle, next);
+                        spawn_named("websocket_toolkit::event_driver_close", async move {
+                            if let Err(e) = previous.shutdown().await {
+                                debug!("Previous connection did not close cleanly: {}", e);
+                            }
+                        });
+                        handlers.open(&handle);
+                    }
+                    continue;
+                }
+                message = handle.recv() => message,
+            };
+            match message.map(InboundMessage::try_from) {
+                Some(Ok(message)) => {
+                    close_reconnects = 0;
+                    if let Some(policy) = controller.maintenance_policy() {
+                        if let Some(notice) = controller.maintenance_notice(&message) {
+                            if let Some(delay) = notice.move_delay(&policy) {
+                                info!("Server announced maintenance; moving in {:?}", delay);
+                                moving = Some((Instant::now() + delay, notice.alternate));
+                            }
+                        }
+                    }
+                    handlers.message(message);
+                }
+                Some(Err(Message::Close(frame))) => break Some(ServerClosed::new(frame.as_ref())),
+                Some(Err(_)) => continue,
+                None => break None,
+            }
+        };
+        current.lock().unwrap().take();
+        handle.abort();
+        handlers.close(closed.as_ref());
+
+        dropped = closed.is_none();
+        if let Some(closed) = &closed {
+            let delay = match controller.close_action(closed, close_reconnects) {
+                CloseAction::Reconnect(delay) => delay,
+                CloseAction::Surface => return,
+            };
+            close_reconnects += 1;
+            debug!("Reconnecting in {:?}", delay);
+            let cancelled = schedule_reconnect(&mut handlers, close_reconnects, None, delay);
+            let waited = tokio::select! {
+                _ = stop.notified() => false,
+                waited = wait_reconnect(cancelled, delay) => waited,
+            };
+            if !waited {
+                return;
+            }
+        }
+    }
+}
+
+/// Opens the next connection. After a dropped connection, or once a first attempt fails,
+/// attempts are made with the controller's reconnection strategy, announcing every wait.
+///
+/// # Returns
+///
+/// The connection, or `None` if the strategy gave up, credentials were rejected or a
+/// reconnect was cancelled.
+async fn connect(controller: &WebSocketController, handlers: &mut EventHandlers, dropped: bool) -> Option<ConnectionHandle> {
+    let config = controller.pipeline_config();
+    if !dropped {
+        // The boxed error is not `Send`, so it must be gone before the next await.
+        let rejected = match controller.connect_handle(config).await {
+            Ok(handle) => return Some(handle),
+            Err(e) => {
+                handlers.error(e.as_ref());
+                is_auth_rejected(e.as_ref())
+            }
+        };
+        if rejected {
+            return None;
+        }
+    }
+    reconnect(controller, handlers, config).await
+}
+
+/// Reconnects with the controller's reconnection strategy, announcing every wait.
+#[cfg(feature = "reconnection")]
+async fn reconnect(controller: &WebSocketController, handlers: &mut EventHandlers, config: PipelineConfig) -> Option<ConnectionHandle> {
+    let fallback;
+    let strategy = match controller.reconnect_strategy() {
+        Some(strategy) => strategy,
+        None => {
+            fallback = controller.default_reconnect_strategy();
+            &fallback
+        }
+    };
+    let max_attempts = strategy.get_retries();
+    let handlers = Mutex::new(handlers);
+    let cancelled = AtomicBool::new(false);
+    let attempt = || async {
+        if cancelled.load(Ordering::Acquire) {
+            return Err(AttemptError::Cancelled);
+        }
+        match controller.connect_handle(config).await {
+            Ok(handle) => Ok(handle),
+            Err(e) => {
+                handlers.lock().unwrap().error(e.as_ref());
+                match is_auth_rejected(e.as_ref()) {
+                    true => Err(AttemptError::Rejected(e.to_string())),
+                    false => Err(AttemptError::Failed(e.to_string())),
+                }
+            }
+        }
+    };
+    let wait = |attempt, delay| {
+        let cancel = schedule_reconnect(&mut handlers.lock().unwrap(), attempt, Some(max_attempts), delay);
+        let cancelled = &cancelled;
+        async move {
+            if !wait_reconnect(cancel, delay).await {
+                cancelled.store(true, Ordering::Release);
+            }
+        }
+    };
+    let is_fatal = |e: &AttemptError| !matches!(e, AttemptError::Failed(_));
+    match strategy.retry_after_failure(attempt, is_fatal, wait).await {
+        Ok(handle) => Some(handle),
+        Err(e) => {
+            warn!("Event driver stopped reconnecting: {}", e);
+            None
+        }
+    }
+}
+
+/// Without the `reconnection` feature there is no strategy to reconnect with.
+#[cfg(not(feature = "reconnection"))]
+async fn reconnect(_controller: &WebSocketController, _handlers: &mut EventHandlers, _config: PipelineConfig) -> Option<ConnectionHandle> {
+    warn!("Event driver stopped: reconnecting requires the `reconnection` feature");
+    None
+}
+
+/// Why one connection attempt of the event driver failed; only `Failed` is retried.
+#[cfg(feature = "reconnection")]
+#[derive(Debug)]
+enum AttemptError {
+    /// The reconnect was cancelled with a `ReconnectCancel`.
+    Cancelled,
+    /// The server rejected the credentials.
+    Rejected(String),
+    /// The connection failed.
+    Failed(String),
+}
+
+#[cfg(feature = "reconnection")]
+impl fmt::Display for AttemptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttemptError::Cancelled => write!(f, "Reconnect cancelled"),
+            AttemptError::Rejected(e) | AttemptError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Calls `on_reconnect_scheduled` for a wait of `delay` before attempt `attempt`.
+///
+/// # Returns
+///
+/// A receiver that turns `true` if the reconnect is cancelled.
+fn schedule_reconnect(
+    handlers: &mut EventHandlers,
+    attempt: u32,
+    max_attempts: Option<u32>,
+    delay: Duration,
+) -> watch::Receiver<bool> {
+    let (cancel, cancelled) = watch::channel(false);
+    handlers.reconnect_scheduled(&ReconnectScheduled {
+        attempt,
+        max_attempts,
+        delay,
+        at: SystemTime::now() + delay,
+        cancel: ReconnectCancel(Arc::new(cancel)),
+    });
+    cancelled
+}
+
+/// Waits `delay` for a scheduled reconnect.
+///
+/// # Returns
+///
+/// `false` if the reconnect was cancelled meanwhile.
+async fn wait_reconnect(mut cancelled: watch::Receiver<bool>, delay: Duration) -> bool {
+    // Fails once every `ReconnectCancel` is dropped, which does not cancel; the branch is
+    // then disabled.
+    tokio::select! {
+        Ok(_) = cancelled.wait_for(|cancelled| *cancelled) => {
+            info!("Reconnect cancelled");
+            false
+        }
+        _ = tokio::time::sleep(delay) => true,
+    }
+}
+
+/// Sleeps until `deadline`, or forever if there is none.
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Opens the connection a maintenance notice moves to: `alternate`, or the controller's URL.
+async fn move_connection(
+    controller: &WebSocketController,
+    handlers: &EventHandlers,
+    alternate: Option<String>,
+) -> Option<ConnectionHandle> {
+    let config = controller.pipeline_config();
+    let connected = match &alternate {
+        Some(url) => controller.connect_handle_at(url, config).await,
+        None => controller.connect_handle(config).await,
+    };
+    // The boxed error is not `Send`, so it must be gone before the next await.
+    match connected {
+        Ok(handle) => Some(handle),
+        Err(e) => {
+            // Stay on the current connection and reconnect when it closes.
+            handlers.error(e.as_ref());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "reconnection")]
+    use crate::reconnection::{Constant, ReconnectStrategy};
+    use crate::testing::MockServer;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    /// Tests that events are dispatched in order, a 1012 close reconnects and a normal close
+    /// stops the driver.
+    #[tokio::test]
+    async fn test_event_driver() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let mut controller = WebSocketController::new(server.url(), 0, None);
+        controller.set_close_policy(|closed, _| match closed.code {
+            Some(1012) => CloseAction::Reconnect(Duration::from_millis(10)),
+            _ => CloseAction::Surface,
+        });
+        let (events, mut received) = mpsc::unbounded_channel();
+        let on_open = events.clone();
+        controller.on_open(move |_| on_open.send("open".to_string()).unwrap());
+        let on_message = events.clone();
+        controller.on_message(move |message| on_message.send(String::from_utf8(message.into_bytes()).unwrap()).unwrap());
+        controller.on_close(move |closed| events.send(format!("close {:?}", closed.and_then(|closed| closed.code))).unwrap());
+
+        let driver = controller.run_events();
+        let mut connection = server.accept().await;
+        assert_eq!(received.recv().await.unwrap(), "open");
+        connection.send(Message::Text("tick".to_string())).await;
+        assert_eq!(received.recv().await.unwrap(), "tick");
+        driver.send(Message::Text("hello".to_string())).await.unwrap();
+        connection.assert_next_message_eq(Message::Text("hello".to_string())).await;
+
+        connection.close(1012, "restart").await;
+        assert_eq!(received.recv().await.unwrap(), "close Some(1012)");
+        let mut connection = server.accept().await;
+        assert_eq!(received.recv().await.unwrap(), "open");
+        connection.close(1000, "bye").await;
+        assert_eq!(received.recv().await.unwrap(), "close Some(1000)");
+        tokio::time::timeout(Duration::from_secs(5), driver.join()).await.expect("Expected the driver to stop");
+    }
+
+    /// Tests that failed connection attempts are reported to `on_error` and retried until the
+    /// retries are used up.
+    #[cfg(feature = "reconnection")]
+    #[tokio::test]
+    async fn test_event_driver_connect_failures() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let mut controller = WebSocketController::new(&url, 1, None);
+        let (events, mut received) = mpsc::unbounded_channel();
+        controller.on_error(move |error| events.send(error.to_string()).unwrap());
+
+        let driver = controller.run_events();
+        tokio::time::timeout(Duration::from_secs(5), driver.join()).await.expect("Expected the driver to stop");
+        assert!(received.recv().await.is_some());
+        assert!(received.recv().await.is_some());
+        assert!(received.try_recv().is_err());
+    }
+
+    /// Tests that reconnects are announced with their attempt and delay, the reconnection
+    /// strategy's after a dropped connection, and that cancelling one stops the driver.
+    #[cfg(feature = "reconnection")]
+    #[tokio::test]
+    async fn test_reconnect_scheduled() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let mut controller = WebSocketController::new(server.url(), 3, None);
+        controller.set_reconnect_strategy(Some(ReconnectStrategy::new_with_backoff(5, Constant(Duration::from_millis(20)))));
+        controller.set_close_policy(|_, _| CloseAction::Reconnect(Duration::from_millis(10)));
+        let (events, mut received) = mpsc::unbounded_channel();
+        controller.on_reconnect_scheduled(move |scheduled| events.send(scheduled.clone()).unwrap());
+
+        let driver = controller.run_events();
+        let mut connection = server.accept().await;
+        connection.close(1012, "restart").await;
+        let scheduled = received.recv().await.unwrap();
+        assert_eq!((scheduled.attempt, scheduled.max_attempts), (1, None));
+        assert_eq!(scheduled.delay, Duration::from_millis(10));
+        assert!(scheduled.at > SystemTime::now() - Duration::from_secs(1));
+        assert_eq!(scheduled.to_string(), "Reconnecting in 1s (attempt 1)");
+
+        // Cancelling a reconnect that already happened has no effect.
+        let connection = server.accept().await;
+        scheduled.cancel();
+        drop(connection);
+        let scheduled = received.recv().await.unwrap();
+        assert_eq!((scheduled.attempt, scheduled.max_attempts), (1, Some(5)));
+        assert_eq!(scheduled.delay, Duration::from_millis(20));
+        scheduled.canceller().cancel();
+        tokio::time::timeout(Duration::from_secs(5), driver.join()).await.expect("Expected the driver to stop");
+    }
+
+    /// Tests that a panicking `on_message` is reported and restarted, disabled, or ends the
+    /// driver, as the panic policy says.
+    #[tokio::test]
+    async fn test_handler_panics() {
+        for policy in [PanicPolicy::Restart, PanicPolicy::Disable, PanicPolicy::FailFast] {
+            let mut server = MockServer::start().await.expect("Failed to start mock server");
+            let mut controller = WebSocketController::new(server.url(), 0, None);
+            controller.set_handler_panic_policy(policy);
+            let (events, mut received) = mpsc::unbounded_channel();
+            let on_message = events.clone();
+            controller.on_message(move |message| {
+                let text = String::from_utf8(message.into_bytes()).unwrap();
+                assert_ne!(text, "boom", "handler failure");
+                on_message.send(text).unwrap();
+            });
+            controller.on_error(move |error| {
+                let panic = error.downcast_ref::<HandlerPanic>().expect("Expected a handler panic");
+                events.send(format!("{} {}", panic.handler, panic.message.contains("handler failure"))).unwrap();
+            });
+
+            let driver = controller.run_events();
+            let mut connection = server.accept().await;
+            connection.send(Message::Text("boom".to_string())).await;
+            assert_eq!(received.recv().await.unwrap(), "on_message true");
+            connection.send(Message::Text("after".to_string())).await;
+            match policy {
+                PanicPolicy::Restart => assert_eq!(received.recv().await.unwrap(), "after"),
+                PanicPolicy::Disable => {
+                    let nothing = tokio::time::timeout(Duration::from_millis(100), received.recv()).await;
+                    assert!(nothing.is_err());
+                    assert!(driver.handle().is_some());
+                }
+                PanicPolicy::FailFast => {
+                    tokio::time::timeout(Duration::from_secs(5), driver.join()).await.expect("Expected the driver to stop");
+                    continue;
+                }
+            }
+            driver.stop().await;
+        }
+    }
+
+    /// Tests that a drain notice moves the connection to the alternate endpoint without
+    /// calling `on_close`.
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn test_event_driver_maintenance() {
+        use crate::maintenance::{MaintenanceNotice, MaintenancePolicy};
+        use crate::messages::MessageFormat;
+
+        let mut old = MockServer::start().await.expect("Failed to start mock server");
+        let mut new = MockServer::start().await.expect("Failed to start mock server");
+        let mut controller = WebSocketController::new(old.url(), 0, None);
+        controller.set_maintenance_policy(Some(MaintenancePolicy {
+            drain_window: Duration::from_millis(50),
+            ..Default::default()
+        }));
+        let (events, mut received) = mpsc::unbounded_channel();
+        let on_open = events.clone();
+        controller.on_open(move |_| on_open.send("open").unwrap());
+        controller.on_close(move |_| events.send("close").unwrap());
+
+        let driver = controller.run_events();
+        let mut connection = old.accept().await;
+        assert_eq!(received.recv().await, Some("open"));
+        let notice = MaintenanceNotice {
+            drain: true,
+            alternate: Some(new.url().to_string()),
+            ..Default::default()
+        };
+        let frame = notice.to_envelope(MessageFormat::Json).unwrap().encode(MessageFormat::Json).unwrap();
+        connection.send(Message::Binary(frame)).await;
+
+        let mut moved = new.accept().await;
+        assert_eq!(received.recv().await, Some("open"));
+        assert!(matches!(connection.next_message().await, Some(Message::Close(_))));
+        driver.send(Message::Text("hello".to_string())).await.unwrap();
+        moved.assert_next_message_eq(Message::Text("hello".to_string())).await;
+        driver.stop().await;
+        assert_eq!(received.recv().await, Some("close"));
+    }
+
+    /// Tests that failed attempts are reported and `stop` closes the open connection.
+    #[tokio::test]
+    async fn test_event_driver_errors_and_stop() {
+        // A port nothing listens on.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let mut controller = WebSocketController::new(&url, 0, None);
+        let (errors, mut received) = mpsc::unbounded_channel();
+        controller.on_error(move |error| errors.send(error.to_string()).unwrap());
+        let driver = controller.run_events();
+        assert!(received.recv().await.is_some());
+        tokio::time::timeout(Duration::from_secs(5), driver.join()).await.expect("Expected the driver to give up");
+
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let mut controller = WebSocketController::new(server.url(), 0, None);
+        let (closes, mut closed) = mpsc::unbounded_channel();
+        controller.on_close(move |frame| closes.send(frame.is_none()).unwrap());
+        let driver = controller.run_events();
+        let mut connection = server.accept().await;
+        while driver.handle().is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        driver.stop().await;
+        assert_eq!(closed.recv().await, Some(true));
+        assert!(matches!(connection.next_message().await, Some(Message::Close(_))));
+    }
+}
@@ -0,0 +1,777 @@
+//! # `failover.rs`: Make-before-break connection switching
+//!
+//! Mobile links rarely fail all at once. As a phone leaves Wi-Fi range or a cell gets
+//! congested, round trips grow and become erratic for seconds before the connection finally
+//! drops, and reconnecting only after the drop leaves a gap of a timeout plus a handshake.
+//! `FailoverConnection` watches those signs instead: it pings its connection every
+//! `PreemptPolicy::probe_interval`, and when the policy judges the link to be degrading it
+//! opens a new connection, moves sending over to it and only then closes the old one.
+//!
+//! The policy looks at three signals from the pongs (see `rtt::RttStats`):
+//!
+//! - a smoothed RTT well above the connection's best RTT (`max_rtt_ratio`),
+//! - jitter, the variation between consecutive round trips (`max_jitter`),
+//! - a ping that has gone unanswered for too long (`max_outstanding`).
+//!
+//! Switching does not disturb receivers: messages from every connection are merged into one
+//! stream, so whatever the old connection delivers while it closes is still received.
+//!
+//! Latency-critical feeds cannot afford even a handshake when a connection dies without
+//! warning. `enable_standby` keeps a second connection warm: connected, prepared by the hook
+//! given to `set_prepare` (to authenticate or subscribe, say) and pinged with the primary.
+//! When the primary fails, or the policy calls for a switch, the standby takes over at once
+//! and a new standby is prepared in the background.
+//!
+//! RTT trends only hint at a network change that the OS reports outright. With
+//! `follow_network`, a `network::NetworkMonitor` tells the connection when the default route
+//! moves, e.g. from Wi-Fi to cellular, and it migrates to a connection over the new network
+//! at once instead of waiting for the old one to degrade.
+
+use crate::controller::WebSocketController;
+use crate::handle::ConnectionHandle;
+use crate::messages::InboundMessage;
+use crate::network::{NetworkEvent, NetworkMonitor, NetworkPath};
+use crate::pipeline::PipelineConfig;
+use crate::rtt::RttStats;
+use crate::tasks::spawn_named;
+use futures_util::future::BoxFuture;
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use log::{debug, error, info, warn};
+use std::error::Error as StdError;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tokio_tungstenite::tungstenite::Message;
+
+/// When a link is judged to be degrading.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PreemptPolicy {
+    /// How often the connection is pinged and assessed.
+    pub probe_interval: Duration,
+    /// The number of RTT samples needed before the RTT and jitter are judged.
+    pub min_samples: u64,
+    /// Degrading once the smoothed RTT exceeds the best RTT by this factor...
+    pub max_rtt_ratio: f64,
+    /// ...and by at least this much, so fast links are not switched over noise.
+    pub min_rtt_increase: Duration,
+    /// Degrading once the jitter exceeds this.
+    pub max_jitter: Duration,
+    /// Degrading once a ping has gone unanswered this long.
+    pub max_outstanding: Duration,
+    /// Whether to switch connections automatically; otherwise `health` only reports it.
+    pub switch_automatically: bool,
+}
+
+impl Default for PreemptPolicy {
+    /// Probes every second and switches once the smoothed RTT triples (and grows by at least
+    /// 100 ms), the jitter exceeds 250 ms or a ping goes unanswered for 3 seconds.
+    fn default() -> Self {
+        PreemptPolicy {
+            probe_interval: Duration::from_secs(1),
+            min_samples: 3,
+            max_rtt_ratio: 3.0,
+            min_rtt_increase: Duration::from_millis(100),
+            max_jitter: Duration::from_millis(250),
+            max_outstanding: Duration::from_secs(3),
+            switch_automatically: true,
+        }
+    }
+}
+
+/// The assessed state of a link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkHealth {
+    /// Too few pongs have been timed to judge the link.
+    Unknown,
+    /// No sign of degradation.
+    Healthy,
+    /// The link is likely to fail soon.
+    Degrading(Degradation),
+}
+
+/// Why a link is judged to be degrading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Degradation {
+    /// The smoothed RTT rose well above the best RTT seen on the connection.
+    RisingRtt {
+        /// The smoothed RTT.
+        smoothed: Duration,
+        /// The best RTT seen.
+        baseline: Duration,
+    },
+    /// Round trips vary by this much.
+    Jitter(Duration),
+    /// A ping has been waiting for its pong this long.
+    UnansweredPing(Duration),
+}
+
+impl PreemptPolicy {
+    /// Assesses a link from its RTT statistics.
+    ///
+    /// # Arguments
+    ///
+    /// * `stats` - The connection's RTT statistics, if any pong has been timed.
+    /// * `outstanding` - How long the last ping has gone unanswered, if it has.
+    ///
+    /// # Returns
+    ///
+    /// The `LinkHealth`; an overdue ping counts even before `min_samples` pongs arrived.
+    pub fn assess(&self, stats: Option<RttStats>, outstanding: Option<Duration>) -> LinkHealth {
+        if let Some(waiting) = outstanding.filter(|waiting| *waiting > self.max_outstanding) {
+            return LinkHealth::Degrading(Degradation::UnansweredPing(waiting));
+        }
+        let stats = match stats {
+            Some(stats) if stats.samples >= self.min_samples => stats,
+            _ => return LinkHealth::Unknown,
+        };
+        if stats.jitter > self.max_jitter {
+            return LinkHealth::Degrading(Degradation::Jitter(stats.jitter));
+        }
+        let rising = stats.smoothed.as_secs_f64() > stats.min.as_secs_f64() * self.max_rtt_ratio
+            && stats.smoothed.saturating_sub(stats.min) >= self.min_rtt_increase;
+        if rising {
+            return LinkHealth::Degrading(Degradation::RisingRtt {
+                smoothed: stats.smoothed,
+                baseline: stats.min,
+            });
+        }
+        LinkHealth::Healthy
+    }
+}
+
+/// A connection that switches to a fresh one before a degrading link fails, optionally with
+/// a warm standby that takes over when the primary fails.
+///
+/// Clones share the same connections.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use websocket_toolkit::controller::WebSocketController;
+/// use websocket_toolkit::failover::{FailoverConnection, PreemptPolicy};
+/// use websocket_toolkit::pipeline::PipelineConfig;
+/// use websocket_toolkit::testing::EchoServer;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let server = EchoServer::start().await.unwrap();
+/// let controller = Arc::new(WebSocketController::new(server.url(), 0, None));
+/// let connection = FailoverConnection::connect(controller, PipelineConfig::default(), PreemptPolicy::default())
+///     .await
+///     .unwrap();
+/// connection.set_prepare(|handle| async move { handle.send_text("auth token").await });
+/// connection.enable_standby().await.unwrap();
+///
+/// connection.send("quote".into()).await.unwrap();
+/// assert_eq!(connection.recv_inbound().await.unwrap().as_bytes(), b"quote");
+/// connection.shutdown().await.unwrap();
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct FailoverConnection {
+    shared: Arc<Shared>,
+}
+
+/// Prepares a new connection before it is used.
+type Prepare = Arc<dyn Fn(ConnectionHandle) -> BoxFuture<'static, Result<(), String>> + Send + Sync>;
+
+/// A connection and the id its forwarder reports when it ends.
+#[derive(Clone)]
+struct Slot {
+    id: u64,
+    handle: ConnectionHandle,
+}
+
+/// State shared by all clones and the monitor task.
+struct Shared {
+    controller: Arc<WebSocketController>,
+    config: PipelineConfig,
+    policy: PreemptPolicy,
+    /// The connection messages are sent on.
+    current: RwLock<Slot>,
+    /// The warm standby, if one is ready.
+    standby: std::sync::Mutex<Option<Slot>>,
+    /// Whether a standby is kept warm.
+    keep_standby: AtomicBool,
+    prepare: std::sync::Mutex<Option<Prepare>>,
+    next_id: AtomicU64,
+    /// Forwarders of every connection send inbound messages here; taken on shutdown.
+    inbound_tx: std::sync::Mutex<Option<mpsc::Sender<InboundMessage>>>,
+    inbound: Mutex<mpsc::Receiver<InboundMessage>>,
+    /// Forwarders report the id of their connection here when it ends.
+    ended_tx: mpsc::UnboundedSender<u64>,
+    /// Held while switching, so switches do not overlap.
+    switching: Mutex<()>,
+    /// Held while a standby is being opened, so only one is.
+    replenishing: Mutex<()>,
+    switches: AtomicU64,
+    last_switch: std::sync::Mutex<Option<Duration>>,
+    monitor: std::sync::Mutex<Option<JoinHandle<()>>>,
+    /// Follows network changes, if `follow_network` was called.
+    roaming: std::sync::Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        if let Some(monitor) = self.monitor.get_mut().unwrap().take() {
+            monitor.abort();
+        }
+        if let Some(roaming) = self.roaming.get_mut().unwrap().take() {
+            roaming.abort();
+        }
+    }
+}
+
+impl FailoverConnection {
+    /// Connects and starts monitoring the link.
+    ///
+    /// Must be called within a tokio runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `controller` - Opens the first connection and every replacement.
+    /// * `config` - The pipeline settings of each connection; RTT tracking is always enabled.
+    /// * `policy` - When to switch.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the connection, or a boxed error if connecting fails.
+    pub async fn connect(
+        controller: Arc<WebSocketController>,
+        config: PipelineConfig,
+        policy: PreemptPolicy,
+    ) -> Result<Self, Box<dyn StdError>> {
+        let config = PipelineConfig { track_rtt: true, ..config };
+        let handle = controller.connect_handle(config).await?;
+        let (inbound_tx, inbound) = mpsc::channel(config.inbound_capacity.max(1));
+        let (ended_tx, ended) = mpsc::unbounded_channel();
+        let current = Slot { id: 0, handle };
+        forward(&current, inbound_tx.clone(), ended_tx.clone());
+        let shared = Arc::new(Shared {
+            controller,
+            config,
+            policy,
+            current: RwLock::new(current),
+            standby: std::sync::Mutex::new(None),
+            keep_standby: AtomicBool::new(false),
+            prepare: std::sync::Mutex::new(None),
+            next_id: AtomicU64::new(1),
+            inbound_tx: std::sync::Mutex::new(Some(inbound_tx)),
+            inbound: Mutex::new(inbound),
+            ended_tx,
+            switching: Mutex::new(()),
+            replenishing: Mutex::new(()),
+            switches: AtomicU64::new(0),
+            last_switch: std::sync::Mutex::new(None),
+            monitor: std::sync::Mutex::new(None),
+            roaming: std::sync::Mutex::new(None),
+        });
+        *shared.monitor.lock().unwrap() = Some(spawn_monitor(&shared, ended));
+        Ok(FailoverConnection { shared })
+    }
+
+    /// Sets a hook that prepares every connection opened from now on (standbys and
+    /// replacements) before it is used, e.g. by authenticating or resubscribing.
+    ///
+    /// # Arguments
+    ///
+    /// * `prepare` - Called with the new connection; an error discards the connection.
+    pub fn set_prepare<F, Fut>(&self, prepare: F)
+    where
+        F: Fn(ConnectionHandle) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let prepare: Prepare = Arc::new(move |handle| -> BoxFuture<'static, Result<(), String>> { Box::pin(prepare(handle)) });
+        *self.shared.prepare.lock().unwrap() = Some(prepare);
+    }
+
+    /// Opens a warm standby connection and keeps one ready from now on.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the standby is ready or being opened, or an error message if
+    /// opening or preparing it fails; another attempt is made on the next probe.
+    pub async fn enable_standby(&self) -> Result<(), String> {
+        self.shared.keep_standby.store(true, Ordering::Relaxed);
+        self.replenish_standby().await
+    }
+
+    /// Returns the connection messages are currently sent on.
+    pub fn current(&self) -> ConnectionHandle {
+        self.shared.current.read().unwrap().handle.clone()
+    }
+
+    /// Returns the warm standby connection, if one is ready.
+    pub fn standby(&self) -> Option<ConnectionHandle> {
+        self.shared.standby.lock().unwrap().as_ref().map(|slot| slot.handle.clone())
+    }
+
+    /// Queues a message on the current connection.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The frame to send.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if the connection has closed.
+    pub async fn send(&self, message: Message) -> Result<(), String> {
+        self.current().send(message).await
+    }
+
+    /// Waits for the next data message from any of the connections.
+    ///
+    /// # Returns
+    ///
+    /// The next text or binary message, or `None` after `shutdown`.
+    pub async fn recv_inbound(&self) -> Option<InboundMessage> {
+        self.shared.inbound.lock().await.recv().await
+    }
+
+    /// Assesses the current connection with the policy.
+    pub fn health(&self) -> LinkHealth {
+        let current = self.current();
+        self.shared.policy.assess(current.rtt(), current.ping_outstanding())
+    }
+
+    /// Returns the number of times the connection has been switched.
+    pub fn switches(&self) -> u64 {
+        self.shared.switches.load(Ordering::Relaxed)
+    }
+
+    /// Returns how long the last switch took, from deciding to switch until the new
+    /// connection carried the send path; near zero when a warm standby took over.
+    pub fn last_switch_duration(&self) -> Option<Duration> {
+        *self.shared.last_switch.lock().unwrap()
+    }
+
+    /// Moves the send path to the warm standby, or to a new connection if none is ready, and
+    /// closes the previous connection in the background.
+    ///
+    /// Messages already queued on the previous connection are still sent before it closes,
+    /// and its remaining inbound messages are still received.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if no connection could be opened;
+    /// the current connection is kept in that case.
+    pub async fn switch_over(&self) -> Result<(), String> {
+        let _switching = self.shared.switching.lock().await;
+        let started = Instant::now();
+        let inbound_tx = self.shared.inbound_tx.lock().unwrap().clone();
+        let inbound_tx = inbound_tx.ok_or("Failed to switch connections: shut down")?;
+        let standby = self.shared.standby.lock().unwrap().take();
+        let next = match standby {
+            Some(standby) if !standby.handle.is_closed() => standby,
+            _ => self.open().await?,
+        };
+        forward(&next, inbound_tx, self.shared.ended_tx.clone());
+        let previous = std::mem::replace(&mut *self.shared.current.write().unwrap(), next);
+        let elapsed = started.elapsed();
+        *self.shared.last_switch.lock().unwrap() = Some(elapsed);
+        let switches = self.shared.switches.fetch_add(1, Ordering::Relaxed) + 1;
+        info!("Switched to a new connection in {:?} (switch {})", elapsed, switches);
+        spawn_named("websocket_toolkit::failover_close", async move {
+            if let Err(e) = previous.handle.shutdown().await {
+                debug!("Previous connection did not close cleanly: {}", e);
+            }
+        });
+        if self.shared.keep_standby.load(Ordering::Relaxed) {
+            let connection = self.clone();
+            spawn_named("websocket_toolkit::failover_standby", async move {
+                if let Err(e) = connection.replenish_standby().await {
+                    warn!("{}", e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Moves the connection onto the current network: discards the standby, which was opened
+    /// over the previous network, and switches to a new connection.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if no connection could be opened;
+    /// the current connection is kept in that case.
+    pub async fn migrate(&self) -> Result<(), String> {
+        let standby = self.shared.standby.lock().unwrap().take();
+        if let Some(standby) = standby {
+            standby.handle.abort();
+        }
+        self.switch_over().await
+    }
+
+    /// Migrates the connection whenever `monitor` reports that the default route moved to
+    /// another interface, or that the network is back after it was lost. Replaces any monitor
+    /// followed before.
+    ///
+    /// Platforms report a change as a burst of events; the connection migrates once no
+    /// event has arrived for `settle`.
+    ///
+    /// # Arguments
+    ///
+    /// * `monitor` - Reports network changes from the operating system.
+    /// * `settle` - How long the network must be stable before migrating.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # async fn example(connection: websocket_toolkit::failover::FailoverConnection) {
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use websocket_toolkit::network::{ChannelMonitor, NetworkEvent, NetworkKind, NetworkPath};
+    ///
+    /// let monitor = ChannelMonitor::new();
+    /// connection.set_prepare(|handle| async move { handle.send_text("resume").await });
+    /// connection.follow_network(Arc::new(monitor.clone()), Duration::from_millis(500));
+    ///
+    /// // From the platform's network callback:
+    /// monitor.notify(NetworkEvent::Available(NetworkPath::new("pdp_ip0", NetworkKind::Cellular)));
+    /// # }
+    /// ```
+    pub fn follow_network(&self, monitor: Arc<dyn NetworkMonitor>, settle: Duration) {
+        let roaming = spawn_roaming(&self.shared, monitor.watch(), settle);
+        if let Some(previous) = self.shared.roaming.lock().unwrap().replace(roaming) {
+            previous.abort();
+        }
+    }
+
+    /// Stops monitoring and shuts down the current and standby connections.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating a clean close, or an error message from
+    /// `ConnectionHandle::shutdown` for the current connection.
+    pub async fn shutdown(&self) -> Result<(), String> {
+        if let Some(monitor) = self.shared.monitor.lock().unwrap().take() {
+            monitor.abort();
+        }
+        if let Some(roaming) = self.shared.roaming.lock().unwrap().take() {
+            roaming.abort();
+        }
+        let _switching = self.shared.switching.lock().await;
+        self.shared.keep_standby.store(false, Ordering::Relaxed);
+        self.shared.inbound_tx.lock().unwrap().take();
+        let standby = self.shared.standby.lock().unwrap().take();
+        if let Some(standby) = standby {
+            let _ = standby.handle.shutdown().await;
+        }
+        self.current().shutdown().await
+    }
+
+    /// Opens and prepares a connection.
+    async fn open(&self) -> Result<Slot, String> {
+        let handle = match self.shared.controller.connect_handle(self.shared.config).await {
+            Ok(handle) => handle,
+            Err(e) => return Err(format!("Failed to open replacement connection: {}", e)),
+        };
+        let prepare = self.shared.prepare.lock().unwrap().clone();
+        if let Some(prepare) = prepare {
+            if let Err(e) = prepare(handle.clone()).await {
+                handle.abort();
+                return Err(format!("Failed to prepare replacement connection: {}", e));
+            }
+        }
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        Ok(Slot { id, handle })
+    }
+
+    /// Opens a standby if one should be kept and none is ready.
+    async fn replenish_standby(&self) -> Result<(), String> {
+        let _replenishing = match self.shared.replenishing.try_lock() {
+            Ok(replenishing) => replenishing,
+            Err(_) => return Ok(()),
+        };
+        let ready = self.shared.standby.lock().unwrap().as_ref().is_some_and(|slot| !slot.handle.is_closed());
+        if ready || !self.shared.keep_standby.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let standby = self.open().await?;
+        debug!("Standby connection ready");
+        let previous = self.shared.standby.lock().unwrap().replace(standby);
+        if let Some(previous) = previous {
+            previous.handle.abort();
+        }
+        Ok(())
+    }
+}
+
+/// Forwards the data messages of `slot` into `inbound` until the connection closes, then
+/// reports its id on `ended`.
+fn forward(slot: &Slot, inbound: mpsc::Sender<InboundMessage>, ended: mpsc::UnboundedSender<u64>) {
+    let Slot { id, handle } = slot.clone();
+    spawn_named("websocket_toolkit::failover_forward", async move {
+        while let Some(message) = handle.recv_inbound().await {
+            if inbound.send(message).await.is_err() {
+                break;
+            }
+        }
+        debug!("Connection {} ended", id);
+        let _ = ended.send(id);
+    });
+}
+
+/// Pings the current and standby connections every probe interval, switches when the
+/// current one degrades and fails over as soon as it ends.
+fn spawn_monitor(shared: &Arc<Shared>, mut ended: mpsc::UnboundedReceiver<u64>) -> JoinHandle<()> {
+    let shared = Arc::downgrade(shared);
+    spawn_named("websocket_toolkit::failover_monitor", async move {
+        let mut ticker = match shared.upgrade() {
+            Some(shared) => tokio::time::interval(shared.policy.probe_interval),
+            None => return,
+        };
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            let ended_id = tokio::select! {
+                _ = ticker.tick() => None,
+                Some(id) = ended.recv() => Some(id),
+            };
+            let connection = match shared.upgrade() {
+                Some(shared) => FailoverConnection { shared },
+                None => return,
+            };
+            if let Some(id) = ended_id {
+                if id == connection.shared.current.read().unwrap().id {
+                    warn!("Connection {} failed; failing over", id);
+                    if let Err(e) = connection.switch_over().await {
+                        error!("{}", e);
+                    }
+                }
+                continue;
+            }
+            if let LinkHealth::Degrading(reason) = connection.health() {
+                if connection.shared.policy.switch_automatically {
+                    warn!("Link degrading ({:?}); switching connections", reason);
+                    if let Err(e) = connection.switch_over().await {
+                        error!("{}", e);
+                    }
+                    continue;
+                }
+            }
+            if let Err(e) = connection.replenish_standby().await {
+                warn!("{}", e);
+            }
+            // An unanswered ping is left outstanding so that its age keeps growing. Pinging
+            // the standby keeps it, and any NAT mapping on its path, alive.
+            for handle in [Some(connection.current()), connection.standby()].into_iter().flatten() {
+                if handle.ping_outstanding().is_none() {
+                    let _ = handle.ping().await;
+                }
+            }
+        }
+    })
+}
+
+/// Migrates the connection when `events` settle on a different network than the one it was
+/// opened over.
+fn spawn_roaming(shared: &Arc<Shared>, mut events: BoxStream<'static, NetworkEvent>, settle: Duration) -> JoinHandle<()> {
+    let shared = Arc::downgrade(shared);
+    spawn_named("websocket_toolkit::failover_roaming", async move {
+        // The network the current connection uses, once known, and whether it was lost since.
+        let mut path: Option<NetworkPath> = None;
+        let mut lost = false;
+        while let Some(mut event) = events.next().await {
+            loop {
+                lost |= event == NetworkEvent::Lost;
+                match tokio::time::timeout(settle, events.next()).await {
+                    Ok(Some(next)) => event = next,
+                    _ => break,
+                }
+            }
+            let next = match event {
+                NetworkEvent::Available(next) => next,
+                NetworkEvent::Lost => {
+                    debug!("Network lost; waiting for it to come back");
+                    continue;
+                }
+            };
+            let moved = lost || path.as_ref().is_some_and(|path| *path != next);
+            path = Some(next);
+            lost = false;
+            if !moved {
+                continue;
+            }
+            let connection = match shared.upgrade() {
+                Some(shared) => FailoverConnection { shared },
+                None => return,
+            };
+            info!("Network changed to {}; migrating connection", path.as_ref().unwrap());
+            if let Err(e) = connection.migrate().await {
+                error!("{}", e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{EchoServer, MockServer};
+
+    /// Returns statistics with the given smoothed RTT, best RTT and jitter in milliseconds.
+    fn stats(smoothed: u64, min: u64, jitter: u64) -> Option<RttStats> {
+        Some(RttStats {
+            latest: Duration::from_millis(smoothed),
+            smoothed: Duration::from_millis(smoothed),
+            min: Duration::from_millis(min),
+            max: Duration::from_millis(smoothed),
+            jitter: Duration::from_millis(jitter),
+            samples: 10,
+        })
+    }
+
+    /// Tests each degradation signal.
+    #[test]
+    fn test_assess() {
+        let policy = PreemptPolicy::default();
+        assert_eq!(policy.assess(None, None), LinkHealth::Unknown);
+        assert_eq!(policy.assess(stats(40, 30, 5), None), LinkHealth::Healthy);
+        // Tripled, but only by a few milliseconds.
+        assert_eq!(policy.assess(stats(4, 1, 0), None), LinkHealth::Healthy);
+        assert_eq!(
+            policy.assess(stats(400, 30, 5), None),
+            LinkHealth::Degrading(Degradation::RisingRtt {
+                smoothed: Duration::from_millis(400),
+                baseline: Duration::from_millis(30),
+            })
+        );
+        assert_eq!(
+            policy.assess(stats(40, 30, 300), None),
+            LinkHealth::Degrading(Degradation::Jitter(Duration::from_millis(300)))
+        );
+        assert_eq!(
+            policy.assess(None, Some(Duration::from_secs(4))),
+            LinkHealth::Degrading(Degradation::UnansweredPing(Duration::from_secs(4)))
+        );
+    }
+
+    /// Tests that messages flow across a manual switch and that a degrading link is switched
+    /// automatically.
+    #[tokio::test]
+    async fn test_switch_over() {
+        let server = EchoServer::start().await.expect("Failed to start echo server");
+        let controller = Arc::new(WebSocketController::new(server.url(), 0, None));
+        let manual = PreemptPolicy {
+            switch_automatically: false,
+            ..PreemptPolicy::default()
+        };
+        let connection = FailoverConnection::connect(controller.clone(), PipelineConfig::default(), manual)
+            .await
+            .unwrap();
+        connection.send("before".into()).await.unwrap();
+        assert_eq!(connection.recv_inbound().await.unwrap().as_bytes(), b"before");
+        connection.switch_over().await.unwrap();
+        connection.send("after".into()).await.unwrap();
+        assert_eq!(connection.recv_inbound().await.unwrap().as_bytes(), b"after");
+        assert_eq!(connection.switches(), 1);
+        assert_eq!(connection.shutdown().await, Ok(()));
+        assert_eq!(connection.recv_inbound().await, None);
+
+        // Judges every link with one pong as degrading.
+        let eager = PreemptPolicy {
+            probe_interval: Duration::from_millis(20),
+            min_samples: 1,
+            max_rtt_ratio: 0.0,
+            min_rtt_increase: Duration::ZERO,
+            ..PreemptPolicy::default()
+        };
+        let connection = FailoverConnection::connect(controller, PipelineConfig::default(), eager).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while connection.switches() == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Expected an automatic switch");
+        connection.shutdown().await.unwrap();
+    }
+
+    /// Tests that the prepared standby takes over when the primary is closed by the server.
+    #[tokio::test]
+    async fn test_standby_failover() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let controller = Arc::new(WebSocketController::new(server.url(), 0, None));
+        let policy = PreemptPolicy {
+            switch_automatically: false,
+            ..PreemptPolicy::default()
+        };
+        let connection = FailoverConnection::connect(controller, PipelineConfig::default(), policy).await.unwrap();
+        let mut primary = server.accept().await;
+        connection.set_prepare(|handle| async move { handle.send_text("auth").await });
+        connection.enable_standby().await.unwrap();
+        let mut standby = server.accept().await;
+        standby.assert_next_message_eq(Message::Text("auth".to_string())).await;
+
+        primary.close(1001, "going away").await;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while connection.switches() == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Expected a failover");
+        assert!(connection.last_switch_duration().unwrap() < Duration::from_secs(1));
+        connection.send("on standby".into()).await.unwrap();
+        standby.assert_next_message_eq(Message::Text("on standby".to_string())).await;
+        standby.send(Message::Text("tick".to_string())).await;
+        assert_eq!(connection.recv_inbound().await, Some(InboundMessage::Text("tick".to_string())));
+
+        // A replacement standby is prepared in the background.
+        let mut replacement = server.accept().await;
+        replacement.assert_next_message_eq(Message::Text("auth".to_string())).await;
+        connection.current().abort();
+    }
+
+    /// Tests that a move to another network, or its return after a loss, migrates the
+    /// connection and resumes it with the prepare hook, while the initial state does not.
+    #[tokio::test]
+    async fn test_follow_network() {
+        use crate::network::{ChannelMonitor, NetworkKind};
+
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let controller = Arc::new(WebSocketController::new(server.url(), 0, None));
+        let policy = PreemptPolicy {
+            switch_automatically: false,
+            ..PreemptPolicy::default()
+        };
+        let connection = FailoverConnection::connect(controller, PipelineConfig::default(), policy).await.unwrap();
+        let _primary = server.accept().await;
+        connection.set_prepare(|handle| async move { handle.send_text("resume").await });
+
+        let monitor = ChannelMonitor::new();
+        let wifi = NetworkPath::new("wlan0", NetworkKind::Wifi);
+        monitor.notify(NetworkEvent::Available(wifi.clone()));
+        connection.follow_network(Arc::new(monitor.clone()), Duration::from_millis(20));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(connection.switches(), 0);
+
+        monitor.notify(NetworkEvent::Lost);
+        monitor.notify(NetworkEvent::Available(NetworkPath::new("rmnet0", NetworkKind::Cellular)));
+        let mut migrated = server.accept().await;
+        migrated.assert_next_message_eq(Message::Text("resume".to_string())).await;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while connection.switches() == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Expected a migration");
+        connection.send("on cellular".into()).await.unwrap();
+        migrated.assert_next_message_eq(Message::Text("on cellular".to_string())).await;
+
+        monitor.notify(NetworkEvent::Lost);
+        monitor.notify(NetworkEvent::Available(wifi));
+        let mut back = server.accept().await;
+        back.assert_next_message_eq(Message::Text("resume".to_string())).await;
+        // The server keeps reading so it answers the close.
+        let (shutdown, _) = tokio::join!(connection.shutdown(), back.collect_messages_for(Duration::from_secs(5)));
+        shutdown.unwrap();
+    }
+}
@@ -0,0 +1,327 @@
+//! # `fanout.rs`: Broadcasting inbound messages to independent subscribers
+//!
+//! When several components care about the same connection (a UI, a recorder, a metrics
+//! collector), routing every message through one dispatcher couples them together. `Fanout`
+//! publishes inbound messages on a `tokio::sync::broadcast` channel instead: each component
+//! calls `subscribe` and receives every message published after that, independently of the
+//! others.
+//!
+//! The channel keeps the last `capacity` messages. A subscriber that falls further behind
+//! has lagged; what happens then is its `LagPolicy`:
+//!
+//! - `LagPolicy::Skip` logs how many messages were missed and continues with the oldest
+//!   message still retained. Suited to observers such as dashboards.
+//! - `LagPolicy::Close` ends the subscription, so a component that needs every message (a
+//!   recorder, say) notices the gap and can resynchronize instead of silently missing data.
+//!
+//! Publishing never waits for subscribers, so a slow one cannot stall the connection.
+//!
+//! With a `Scrollback` set (`set_scrollback`), published messages are also kept per topic,
+//! and `subscribe_with_history` hands a late subscriber the recent history of a topic along
+//! with its subscription.
+
+use crate::messages::InboundMessage;
+use crate::pipeline::PipelineReceiver;
+use crate::scrollback::Scrollback;
+use crate::tasks::spawn_named;
+use log::{debug, warn};
+use std::sync::{Arc, RwLock};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+
+/// What a `Subscription` does when it falls behind the channel's capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LagPolicy {
+    /// Skip the missed messages and continue with the oldest one still buffered.
+    #[default]
+    Skip,
+    /// End the subscription; `recv` returns `None` from then on.
+    Close,
+}
+
+/// Publishes inbound messages to any number of subscribers.
+///
+/// Clones publish to the same subscribers.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::fanout::{Fanout, LagPolicy};
+/// use websocket_toolkit::messages::InboundMessage;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let fanout = Fanout::new(16);
+/// let mut ui = fanout.subscribe();
+/// let mut recorder = fanout.subscribe_with(LagPolicy::Close);
+///
+/// fanout.publish(InboundMessage::Text("tick".into()));
+/// assert_eq!(ui.recv().await.unwrap(), InboundMessage::Text("tick".into()));
+/// assert_eq!(recorder.recv().await, Some(InboundMessage::Text("tick".into())));
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Fanout {
+    sender: broadcast::Sender<InboundMessage>,
+    /// Shared by clones, so setting it on one records what any of them publishes.
+    scrollback: Arc<RwLock<Option<Arc<Scrollback>>>>,
+}
+
+impl Fanout {
+    /// Creates a fan-out that buffers up to `capacity` messages for lagging subscribers.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - The number of messages retained; at least 1.
+    ///
+    /// # Returns
+    ///
+    /// A new `Fanout` with no subscribers.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        Fanout { sender, scrollback: Default::default() }
+    }
+
+    /// Subscribes to messages published from now on.
+    ///
+    /// # Returns
+    ///
+    /// A raw `broadcast::Receiver`; its `recv` reports lag as `RecvError::Lagged`.
+    pub fn subscribe(&self) -> broadcast::Receiver<InboundMessage> {
+        self.sender.subscribe()
+    }
+
+    /// Subscribes to messages published from now on, handling lag with `policy`.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - What to do when the subscriber falls behind.
+    ///
+    /// # Returns
+    ///
+    /// A new `Subscription`.
+    pub fn subscribe_with(&self, policy: LagPolicy) -> Subscription {
+        Subscription {
+            receiver: self.sender.subscribe(),
+            policy,
+            missed: 0,
+            closed: false,
+        }
+    }
+
+    /// Publishes `message` to every current subscriber.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The inbound message.
+    ///
+    /// # Returns
+    ///
+    /// The number of subscribers the message was published to.
+    pub fn publish(&self, message: InboundMessage) -> usize {
+        match self.scrollback() {
+            Some(scrollback) => scrollback.record_then(&message, || self.sender.send(message.clone()).unwrap_or(0)),
+            None => self.sender.send(message).unwrap_or(0),
+        }
+    }
+
+    /// Keeps published messages in `scrollback` from now on, or stops keeping them.
+    ///
+    /// Applies to every clone of this fan-out.
+    ///
+    /// # Arguments
+    ///
+    /// * `scrollback` - Where messages are kept, or `None` to keep none.
+    pub fn set_scrollback(&self, scrollback: Option<Arc<Scrollback>>) {
+        *self.scrollback.write().unwrap() = scrollback;
+    }
+
+    /// Returns the scrollback set by `set_scrollback`.
+    pub fn scrollback(&self) -> Option<Arc<Scrollback>> {
+        self.scrollback.read().unwrap().clone()
+    }
+
+    /// Returns the last `n` messages published under `topic`, oldest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `topic` - The topic.
+    /// * `n` - The most messages returned.
+    ///
+    /// # Returns
+    ///
+    /// The messages, or none if no scrollback is set.
+    pub fn history(&self, topic: &str, n: usize) -> Vec<InboundMessage> {
+        self.scrollback().map(|scrollback| scrollback.history(topic, n)).unwrap_or_default()
+    }
+
+    /// Subscribes like `subscribe_with`, also returning the last `n` messages of `topic`.
+    ///
+    /// The subscription starts right after the returned history: no message is in both,
+    /// and none published in between is missed.
+    ///
+    /// # Arguments
+    ///
+    /// * `topic` - The topic whose history is returned.
+    /// * `n` - The most messages returned.
+    /// * `policy` - What to do when the subscriber falls behind.
+    ///
+    /// # Returns
+    ///
+    /// The history, oldest first and empty if no scrollback is set, and a new `Subscription`
+    /// to every message.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::sync::Arc;
+    /// use websocket_toolkit::fanout::{Fanout, LagPolicy};
+    /// use websocket_toolkit::messages::InboundMessage;
+    /// use websocket_toolkit::scrollback::{Scrollback, ScrollbackLimits};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let fanout = Fanout::default();
+    /// fanout.set_scrollback(Some(Arc::new(Scrollback::new(ScrollbackLimits::default(), |_| Some("chat".to_string())))));
+    /// fanout.publish(InboundMessage::Text("earlier".into()));
+    ///
+    /// let (history, mut late) = fanout.subscribe_with_history("chat", 50, LagPolicy::Skip);
+    /// fanout.publish(InboundMessage::Text("now".into()));
+    /// assert_eq!(history, vec![InboundMessage::Text("earlier".into())]);
+    /// assert_eq!(late.recv().await, Some(InboundMessage::Text("now".into())));
+    /// # }
+    /// ```
+    pub fn subscribe_with_history(&self, topic: &str, n: usize, policy: LagPolicy) -> (Vec<InboundMessage>, Subscription) {
+        match self.scrollback() {
+            Some(scrollback) => scrollback.snapshot_then(topic, n, || self.subscribe_with(policy)),
+            None => (Vec::new(), self.subscribe_with(policy)),
+        }
+    }
+
+    /// Returns the number of subscribers.
+    pub fn subscriber_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    /// Publishes every data message from a pipeline until the connection ends.
+    ///
+    /// Must be called within a tokio runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `receiver` - The pipeline's receiver; close frames end the task.
+    ///
+    /// # Returns
+    ///
+    /// The handle of the forwarding task, named `websocket_toolkit::fanout`.
+    pub fn forward(&self, mut receiver: PipelineReceiver) -> JoinHandle<()> {
+        let fanout = self.clone();
+        spawn_named("websocket_toolkit::fanout", async move {
+            while let Some(message) = receiver.recv().await {
+                match InboundMessage::try_from(message) {
+                    Ok(inbound) => {
+                        fanout.publish(inbound);
+                    }
+                    Err(_) => break,
+                }
+            }
+            debug!("Fan-out finished");
+        })
+    }
+}
+
+impl Default for Fanout {
+    /// Creates a fan-out that retains 1024 messages.
+    fn default() -> Self {
+        Fanout::new(1024)
+    }
+}
+
+/// A subscriber that applies a `LagPolicy`.
+#[derive(Debug)]
+pub struct Subscription {
+    receiver: broadcast::Receiver<InboundMessage>,
+    policy: LagPolicy,
+    /// The number of messages missed through lag.
+    missed: u64,
+    /// Whether `LagPolicy::Close` has ended the subscription.
+    closed: bool,
+}
+
+impl Subscription {
+    /// Waits for the next message.
+    ///
+    /// # Returns
+    ///
+    /// The next message, or `None` once the publisher is gone or, with `LagPolicy::Close`,
+    /// the subscriber has lagged.
+    pub async fn recv(&mut self) -> Option<InboundMessage> {
+        while !self.closed {
+            match self.receiver.recv().await {
+                Ok(message) => return Some(message),
+                Err(RecvError::Lagged(missed)) => {
+                    self.missed += missed;
+                    warn!("Subscriber lagged and missed {} messages", missed);
+                    self.closed = self.policy == LagPolicy::Close;
+                }
+                Err(RecvError::Closed) => self.closed = true,
+            }
+        }
+        None
+    }
+
+    /// Returns the number of messages missed through lag so far.
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+
+    /// Returns the underlying `broadcast::Receiver`.
+    pub fn into_inner(self) -> broadcast::Receiver<InboundMessage> {
+        self.receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests both lag policies against a subscriber that fell behind.
+    #[tokio::test]
+    async fn test_lag_policies() {
+        let fanout = Fanout::new(2);
+        let mut skipping = fanout.subscribe_with(LagPolicy::Skip);
+        let mut closing = fanout.subscribe_with(LagPolicy::Close);
+        for i in 0..5u8 {
+            assert_eq!(fanout.publish(InboundMessage::Binary(vec![i])), 2);
+        }
+
+        assert_eq!(skipping.recv().await, Some(InboundMessage::Binary(vec![3])));
+        assert_eq!(skipping.recv().await, Some(InboundMessage::Binary(vec![4])));
+        assert_eq!(skipping.missed(), 3);
+        assert_eq!(closing.recv().await, None);
+        assert_eq!(closing.missed(), 3);
+
+        drop(fanout);
+        assert_eq!(skipping.recv().await, None);
+    }
+
+    /// Tests that a scrollback set on one clone records what others publish, and that a late
+    /// subscriber gets the history and then only newer messages.
+    #[tokio::test]
+    async fn test_subscribe_with_history() {
+        let fanout = Fanout::new(16);
+        assert!(fanout.history("ticks", 10).is_empty());
+        let publisher = fanout.clone();
+        let scrollback = Scrollback::new(Default::default(), |message| Some(format!("{}", message.as_bytes()[0] % 2)));
+        fanout.set_scrollback(Some(Arc::new(scrollback)));
+        for i in 0..5u8 {
+            publisher.publish(InboundMessage::Binary(vec![i]));
+        }
+
+        let (history, mut late) = fanout.subscribe_with_history("0", 2, LagPolicy::Skip);
+        assert_eq!(history, vec![InboundMessage::Binary(vec![2]), InboundMessage::Binary(vec![4])]);
+        assert_eq!(fanout.history("1", 10), vec![InboundMessage::Binary(vec![1]), InboundMessage::Binary(vec![3])]);
+        publisher.publish(InboundMessage::Binary(vec![5]));
+        assert_eq!(late.recv().await, Some(InboundMessage::Binary(vec![5])));
+    }
+}
@@ -0,0 +1,489 @@
+//! # `ffi.rs`: C ABI bindings
+//!
+//! This module exposes the controller to C and C++ through a small C ABI: `wstk_connect`
+//! opens a connection and registers a receive callback, `wstk_send` sends a binary message
+//! and `wstk_close` closes the connection and frees the handle. Each handle owns its own
+//! tokio runtime, which runs the keep-alive pings and reconnects with the controller's
+//! reconnection strategy when the connection drops; rejected credentials are not retried.
+//!
+//! No panic unwinds into C: a panic inside one of the functions is turned into
+//! `WSTK_ERR_PANIC`, or a null handle for `wstk_connect`. The functions block on the
+//! handle's runtime, so they cannot be called from the `on_message` callback, which runs on
+//! that runtime; such calls are rejected with `WSTK_ERR_REENTRANT`.
+//!
+//! Builds with the `ffi` feature generate the header from this file alone into
+//! `OUT_DIR/include/wstk.h`. The checked-in `include/wstk.h` is refreshed explicitly with
+//! `cbindgen --config cbindgen.toml --output include/wstk.h src/ffi.rs`.
+
+use crate::auth::is_auth_rejected;
+use crate::controller::WebSocketController;
+use crate::reconnection::RetryError;
+use crate::tasks::spawn_named_on;
+use log::{error, info, warn};
+use std::cell::Cell;
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use futures_util::SinkExt;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// The call succeeded.
+pub const WSTK_OK: c_int = 0;
+/// A pointer argument was null or a string was not valid UTF-8.
+pub const WSTK_ERR_INVALID_ARGUMENT: c_int = -1;
+/// The connection is closed and can no longer send.
+pub const WSTK_ERR_CLOSED: c_int = -2;
+/// Writing the message to the connection failed.
+pub const WSTK_ERR_SEND_FAILED: c_int = -3;
+/// The function was called from an `on_message` callback.
+pub const WSTK_ERR_REENTRANT: c_int = -4;
+/// The function panicked; the panic was caught and logged.
+pub const WSTK_ERR_PANIC: c_int = -5;
+
+thread_local! {
+    /// Whether this thread is running an `on_message` callback.
+    static IN_CALLBACK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Called with every text or binary message received on a connection.
+///
+/// `data` points to `len` bytes that are only valid for the duration of the call. The
+/// callback runs on the connection's runtime thread and receives the `user_data` pointer
+/// given to `wstk_connect`. It must not call `wstk_connect`, `wstk_send` or `wstk_close`;
+/// those calls fail with `WSTK_ERR_REENTRANT`, or a null handle.
+pub type WstkMessageCallback =
+    Option<unsafe extern "C" fn(user_data: *mut c_void, data: *const u8, len: usize)>;
+
+/// An open connection created by `wstk_connect`. Opaque to C callers.
+pub struct WstkClient {
+    runtime: Runtime,
+    outbound: UnboundedSender<Outbound>,
+    task: JoinHandle<()>,
+}
+
+/// A message passed from `wstk_send` to the connection task.
+struct Outbound {
+    payload: Vec<u8>,
+    /// Receives the result of writing `payload` to the connection.
+    sent: oneshot::Sender<Result<(), String>>,
+}
+
+/// Delivers inbound messages to the C callback.
+struct MessageSink {
+    callback: WstkMessageCallback,
+    user_data: *mut c_void,
+}
+
+// SAFETY: the caller of `wstk_connect` guarantees that `user_data` may be used from the
+// connection's runtime thread for as long as the handle is open.
+unsafe impl Send for MessageSink {}
+
+impl MessageSink {
+    /// Invokes the callback with `payload`, if a callback was registered.
+    fn deliver(&self, payload: &[u8]) {
+        if let Some(callback) = self.callback {
+            IN_CALLBACK.with(|in_callback| in_callback.set(true));
+            // SAFETY: the callback contract is documented on `WstkMessageCallback`.
+            unsafe { callback(self.user_data, payload.as_ptr(), payload.len()) };
+            IN_CALLBACK.with(|in_callback| in_callback.set(false));
+        }
+    }
+}
+
+/// Returns whether the current thread is running an `on_message` callback.
+fn in_callback() -> bool {
+    IN_CALLBACK.with(Cell::get)
+}
+
+/// Runs the body of an exported function, returning `on_panic` instead of unwinding into C
+/// if it panics.
+fn catch_panic<T>(name: &str, on_panic: T, body: impl FnOnce() -> T) -> T {
+    catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|_| {
+        error!("{} panicked", name);
+        on_panic
+    })
+}
+
+/// The next thing a connection task has to handle.
+enum Event {
+    /// A message passed by `wstk_send`, or `None` once the handle is closed.
+    Outbound(Option<Outbound>),
+    /// The keep-alive ticker fired.
+    Ping,
+    /// The result of receiving from the server.
+    Received(Result<Option<Vec<u8>>, String>),
+}
+
+/// Runs a connection until its handle is closed or reconnection gives up.
+///
+/// Outbound messages are sent as they arrive and their result is reported back to
+/// `wstk_send`, pings are sent every `ping_interval` and inbound messages are handed to
+/// `sink`. When the connection drops the controller
+/// reconnects with its reconnection strategy.
+async fn run_connection(
+    mut controller: WebSocketController,
+    ping_interval: Option<Duration>,
+    mut ws_stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    mut outbound: UnboundedReceiver<Outbound>,
+    sink: MessageSink,
+) {
+    let mut ticker = tokio::time::interval(ping_interval.unwrap_or(Duration::from_secs(60)));
+    ticker.tick().await;
+
+    loop {
+        // The select only picks the next event; it is handled afterwards so that no
+        // non-`Send` controller error is held across an await.
+        let event = tokio::select! {
+            data = outbound.recv() => Event::Outbound(data),
+            _ = ticker.tick(), if ping_interval.is_some() => Event::Ping,
+            received = controller.receive_message(&mut ws_stream) => {
+                Event::Received(received.map_err(|e| e.to_string()))
+            },
+        };
+
+        match event {
+            Event::Outbound(Some(Outbound { payload, sent })) => {
+                let result = ws_stream.send(Message::Binary(payload)).await.map_err(|e| e.to_string());
+                if let Err(e) = &result {
+                    error!("Failed to send message: {}", e);
+                }
+                let _ = sent.send(result);
+            }
+            Event::Outbound(None) => {
+                info!("Handle closed; closing the connection");
+                let _ = ws_stream.close(None).await;
+                break;
+            }
+            Event::Ping => {
+                if let Err(e) = ws_stream.send(Message::Ping(Vec::new())).await {
+                    error!("Ping failed: {}", e);
+                }
+            }
+            Event::Received(Ok(Some(payload))) => sink.deliver(&payload),
+            Event::Received(Ok(None)) => {}
+            Event::Received(Err(reason)) => {
+                warn!("Connection lost: {}; reconnecting", reason);
+                match reconnect(&controller).await {
+                    Ok(new_stream) => ws_stream = new_stream,
+                    Err(e) => {
+                        error!("Giving up after failed reconnection: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Reopens the connection with the controller's reconnection strategy, or by default its
+/// retries and backoff, stopping if the server rejects the credentials.
+async fn reconnect(controller: &WebSocketController) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, RetryError<AttemptError>> {
+    let fallback;
+    let strategy = match controller.reconnect_strategy() {
+        Some(strategy) => strategy,
+        None => {
+            fallback = controller.default_reconnect_strategy();
+            &fallback
+        }
+    };
+    let attempt = || async {
+        // The boxed error is not `Send`, so it is turned into a string right away.
+        controller.connect().await.map_err(|e| match is_auth_rejected(e.as_ref()) {
+            true => AttemptError::Rejected(e.to_string()),
+            false => AttemptError::Failed(e.to_string()),
+        })
+    };
+    let is_fatal = |e: &AttemptError| matches!(e, AttemptError::Rejected(_));
+    strategy.retry(attempt, is_fatal).await
+}
+
+/// Why an attempt to reopen a connection failed; only `Failed` is retried.
+#[derive(Debug)]
+enum AttemptError {
+    /// The server rejected the credentials.
+    Rejected(String),
+    /// The connection failed.
+    Failed(String),
+}
+
+impl std::fmt::Display for AttemptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttemptError::Rejected(e) => write!(f, "Server rejected the credentials: {}", e),
+            AttemptError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Connects to a WebSocket server.
+///
+/// Blocks until the initial connection is established.
+///
+/// # Arguments
+///
+/// * `url` - A NUL-terminated UTF-8 WebSocket URL.
+/// * `retries` - The number of reconnection attempts after the connection drops.
+/// * `ping_interval_secs` - The keep-alive ping interval in seconds, or `0` to disable pings.
+/// * `on_message` - Called with every received message; may be null.
+/// * `user_data` - Passed unchanged to `on_message`.
+///
+/// # Returns
+///
+/// A handle to pass to `wstk_send` and `wstk_close`, or null if the arguments are invalid,
+/// the connection fails, the function panics or it is called from an `on_message` callback.
+///
+/// # Safety
+///
+/// `url` must be null or point to a NUL-terminated string. `user_data` must remain valid,
+/// and usable from another thread, until `wstk_close` returns.
+#[no_mangle]
+pub unsafe extern "C" fn wstk_connect(
+    url: *const c_char,
+    retries: u32,
+    ping_interval_secs: u64,
+    on_message: WstkMessageCallback,
+    user_data: *mut c_void,
+) -> *mut WstkClient {
+    catch_panic("wstk_connect", std::ptr::null_mut(), || {
+        if in_callback() {
+            error!("wstk_connect cannot be called from an on_message callback");
+            return std::ptr::null_mut();
+        }
+        if url.is_null() {
+            return std::ptr::null_mut();
+        }
+        let url = match CStr::from_ptr(url).to_str() {
+            Ok(url) => url.to_string(),
+            Err(_) => return std::ptr::null_mut(),
+        };
+        let runtime = match Runtime::new() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                error!("Failed to start runtime: {}", e);
+                return std::ptr::null_mut();
+            }
+        };
+
+        let ping_interval = (ping_interval_secs > 0).then(|| Duration::from_secs(ping_interval_secs));
+        let controller = WebSocketController::new(&url, retries, ping_interval.map(|d| d.as_secs()));
+        let ws_stream = match runtime.block_on(controller.connect()) {
+            Ok(ws_stream) => ws_stream,
+            Err(e) => {
+                error!("Failed to connect to {}: {}", url, e);
+                return std::ptr::null_mut();
+            }
+        };
+
+        let (outbound, outbound_rx) = unbounded_channel();
+        let sink = MessageSink {
+            callback: on_message,
+            user_data,
+        };
+        let task = spawn_named_on(
+            "websocket_toolkit::ffi_connection",
+            run_connection(controller, ping_interval, ws_stream, outbound_rx, sink),
+            runtime.handle(),
+        );
+
+        Box::into_raw(Box::new(WstkClient {
+            runtime,
+            outbound,
+            task,
+        }))
+    })
+}
+
+/// Sends a binary message.
+///
+/// Blocks until the message has been written to the connection or the write failed. While
+/// the connection is being reopened, that includes waiting for the reconnection.
+///
+/// # Arguments
+///
+/// * `client` - A handle returned by `wstk_connect`.
+/// * `data` - The message bytes; may be null when `len` is `0`.
+/// * `len` - The number of bytes at `data`.
+///
+/// # Returns
+///
+/// `WSTK_OK`, `WSTK_ERR_INVALID_ARGUMENT`, `WSTK_ERR_CLOSED`, `WSTK_ERR_SEND_FAILED`,
+/// `WSTK_ERR_REENTRANT` or `WSTK_ERR_PANIC`.
+///
+/// # Safety
+///
+/// `client` must be a live handle and `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn wstk_send(client: *mut WstkClient, data: *const u8, len: usize) -> c_int {
+    catch_panic("wstk_send", WSTK_ERR_PANIC, || {
+        if in_callback() {
+            return WSTK_ERR_REENTRANT;
+        }
+        let Some(client) = client.as_ref() else {
+            return WSTK_ERR_INVALID_ARGUMENT;
+        };
+        if data.is_null() && len > 0 {
+            return WSTK_ERR_INVALID_ARGUMENT;
+        }
+        let payload = if len == 0 {
+            Vec::new()
+        } else {
+            std::slice::from_raw_parts(data, len).to_vec()
+        };
+        let (sent, result) = oneshot::channel();
+        if client.outbound.send(Outbound { payload, sent }).is_err() {
+            return WSTK_ERR_CLOSED;
+        }
+        match result.blocking_recv() {
+            Ok(Ok(())) => WSTK_OK,
+            Ok(Err(_)) => WSTK_ERR_SEND_FAILED,
+            // The connection task stopped before handling the message.
+            Err(_) => WSTK_ERR_CLOSED,
+        }
+    })
+}
+
+/// Closes the connection and frees the handle.
+///
+/// Waits up to five seconds for the close handshake, then aborts the connection task and
+/// waits for it to stop. The callback is not invoked after this function returns.
+///
+/// # Arguments
+///
+/// * `client` - A handle returned by `wstk_connect`, or null.
+///
+/// # Returns
+///
+/// `WSTK_OK`, `WSTK_ERR_REENTRANT`, in which case the handle is left open, or
+/// `WSTK_ERR_PANIC`.
+///
+/// # Safety
+///
+/// `client` must be null or a live handle; it must not be used after this call unless it
+/// returned `WSTK_ERR_REENTRANT`.
+#[no_mangle]
+pub unsafe extern "C" fn wstk_close(client: *mut WstkClient) -> c_int {
+    catch_panic("wstk_close", WSTK_ERR_PANIC, || {
+        if in_callback() {
+            return WSTK_ERR_REENTRANT;
+        }
+        if client.is_null() {
+            return WSTK_OK;
+        }
+        let WstkClient {
+            runtime,
+            outbound,
+            mut task,
+        } = *Box::from_raw(client);
+        drop(outbound);
+        let finished = runtime.block_on(async { tokio::time::timeout(Duration::from_secs(5), &mut task).await });
+        if finished.is_err() {
+            warn!("Connection did not close in time; aborting");
+            task.abort();
+            // An abort only takes effect at the task's next await; wait for it so a callback
+            // already running finishes before the caller may free `user_data`.
+            let _ = runtime.block_on(&mut task);
+        }
+        runtime.shutdown_timeout(Duration::from_secs(1));
+        WSTK_OK
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::EchoServer;
+    use std::ffi::CString;
+    use std::sync::atomic::{AtomicPtr, Ordering};
+    use std::sync::Mutex;
+
+    /// Appends each received message to the `Mutex<Vec<Vec<u8>>>` behind `user_data`.
+    unsafe extern "C" fn collect(user_data: *mut c_void, data: *const u8, len: usize) {
+        let received = &*(user_data as *const Mutex<Vec<Vec<u8>>>);
+        received.lock().unwrap().push(std::slice::from_raw_parts(data, len).to_vec());
+    }
+
+    /// Tests a connect/send/receive/close round trip through the C ABI.
+    #[test]
+    fn test_ffi_round_trip() {
+        let server_runtime = Runtime::new().unwrap();
+        let server = server_runtime.block_on(EchoServer::start()).expect("Failed to start echo server");
+        let url = CString::new(server.url()).unwrap();
+        let received: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+
+        unsafe {
+            let client = wstk_connect(url.as_ptr(), 1, 0, Some(collect), &received as *const _ as *mut c_void);
+            assert!(!client.is_null(), "Expected wstk_connect to return a handle");
+            assert_eq!(wstk_send(client, b"hello".as_ptr(), 5), WSTK_OK);
+
+            for _ in 0..50 {
+                if !received.lock().unwrap().is_empty() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            assert_eq!(wstk_close(client), WSTK_OK);
+        }
+
+        assert_eq!(received.lock().unwrap().as_slice(), &[b"hello".to_vec()]);
+    }
+
+    /// The handle a callback calls back into and the codes those calls returned.
+    struct Reentrant {
+        client: AtomicPtr<WstkClient>,
+        codes: Mutex<Vec<c_int>>,
+    }
+
+    /// Calls back into the handle behind `user_data` and records the returned codes.
+    unsafe extern "C" fn call_back_in(user_data: *mut c_void, _data: *const u8, _len: usize) {
+        let reentrant = &*(user_data as *const Reentrant);
+        let client = reentrant.client.load(Ordering::SeqCst);
+        let codes = [wstk_send(client, b"again".as_ptr(), 5), wstk_close(client)];
+        reentrant.codes.lock().unwrap().extend(codes);
+    }
+
+    /// Tests that calls from the message callback are rejected instead of panicking.
+    #[test]
+    fn test_ffi_rejects_calls_from_callback() {
+        let server_runtime = Runtime::new().unwrap();
+        let server = server_runtime.block_on(EchoServer::start()).expect("Failed to start echo server");
+        let url = CString::new(server.url()).unwrap();
+        let reentrant = Reentrant {
+            client: AtomicPtr::new(std::ptr::null_mut()),
+            codes: Mutex::new(Vec::new()),
+        };
+
+        unsafe {
+            let user_data = &reentrant as *const _ as *mut c_void;
+            let client = wstk_connect(url.as_ptr(), 1, 0, Some(call_back_in), user_data);
+            assert!(!client.is_null(), "Expected wstk_connect to return a handle");
+            reentrant.client.store(client, Ordering::SeqCst);
+            assert_eq!(wstk_send(client, b"hello".as_ptr(), 5), WSTK_OK);
+
+            for _ in 0..50 {
+                if !reentrant.codes.lock().unwrap().is_empty() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            assert_eq!(wstk_close(client), WSTK_OK);
+        }
+
+        assert_eq!(*reentrant.codes.lock().unwrap(), vec![WSTK_ERR_REENTRANT, WSTK_ERR_REENTRANT]);
+    }
+
+    /// Tests that invalid arguments are rejected without crashing.
+    #[test]
+    fn test_ffi_invalid_arguments() {
+        unsafe {
+            assert!(wstk_connect(std::ptr::null(), 0, 0, None, std::ptr::null_mut()).is_null());
+            assert_eq!(wstk_send(std::ptr::null_mut(), std::ptr::null(), 0), WSTK_ERR_INVALID_ARGUMENT);
+            assert_eq!(wstk_close(std::ptr::null_mut()), WSTK_OK);
+        }
+    }
+}
@@ -0,0 +1,287 @@
+//! # `fleet.rs`: Warming up fleets of connections
+//!
+//! Device simulators and load tests open thousands of connections at once. Started naively,
+//! every connection sends its SYN and TLS handshake in the same instant, which looks like a
+//! SYN flood to the target (and its load balancer) and measures the server's accept queue
+//! rather than anything useful. `FleetConnector` spreads the starts out instead:
+//!
+//! - Each connection gets its own slot in the ramp-up period and starts at a random point
+//!   within it, so starts are staggered without falling into lockstep.
+//! - A global limit caps the handshakes in flight; the limit is shared by every clone of the
+//!   connector, so several fleets started from one connector respect it together.
+//! - Failed handshakes are retried with jittered exponential backoff, without holding a
+//!   handshake slot while waiting.
+
+use crate::auth::is_auth_rejected;
+use crate::controller::WebSocketController;
+use crate::handle::ConnectionHandle;
+use crate::jitter::{jittered, unit_random};
+use crate::tasks::spawn_named;
+use log::{debug, error, info};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+/// How a fleet is started.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FleetConfig {
+    /// The number of connections to open.
+    pub connections: usize,
+    /// The most handshakes in flight at once, across every fleet sharing the connector.
+    pub max_concurrent_handshakes: usize,
+    /// The period over which connection starts are spread.
+    pub ramp_up: Duration,
+    /// How often a failed handshake is retried.
+    pub retries: u32,
+    /// The delay before the first retry; it doubles with each further one.
+    pub backoff: Duration,
+}
+
+impl Default for FleetConfig {
+    /// 100 connections over 10 seconds, at most 32 handshakes at a time, each retried 3 times
+    /// starting after 1 second.
+    fn default() -> Self {
+        FleetConfig {
+            connections: 100,
+            max_concurrent_handshakes: 32,
+            ramp_up: Duration::from_secs(10),
+            retries: 3,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// A connected member of a fleet.
+pub struct FleetMember {
+    /// The member's index, as passed to the controller factory.
+    pub index: usize,
+    /// The member's controller.
+    pub controller: WebSocketController,
+    /// The member's pipelined connection.
+    pub handle: ConnectionHandle,
+}
+
+/// The outcome of starting a fleet.
+pub struct Fleet {
+    /// The connected members, ordered by index.
+    pub members: Vec<FleetMember>,
+    /// The indexes of members that could not connect, with the last error.
+    pub failures: Vec<(usize, String)>,
+    /// The most handshakes that were in flight at once.
+    pub peak_handshakes: usize,
+    /// How long starting the fleet took.
+    pub elapsed: Duration,
+}
+
+impl Fleet {
+    /// Shuts down every member's connection.
+    pub async fn shutdown(&self) {
+        for member in &self.members {
+            if let Err(e) = member.handle.shutdown().await {
+                debug!("Fleet member {} did not close cleanly: {}", member.index, e);
+            }
+        }
+    }
+}
+
+/// Opens fleets of connections with staggered starts and a global handshake limit.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use websocket_toolkit::controller::WebSocketController;
+/// use websocket_toolkit::fleet::{FleetConfig, FleetConnector};
+/// use websocket_toolkit::testing::EchoServer;
+///
+/// let runtime = tokio::runtime::Runtime::new().unwrap();
+/// runtime.block_on(async {
+///     let server = EchoServer::start().await.unwrap();
+///     let url = server.url().to_string();
+///     let connector = FleetConnector::new(FleetConfig {
+///         connections: 10,
+///         max_concurrent_handshakes: 4,
+///         ramp_up: Duration::from_millis(100),
+///         ..FleetConfig::default()
+///     });
+///     let fleet = connector.connect(move |_| WebSocketController::new(&url, 0, None)).await;
+///     assert_eq!(fleet.members.len(), 10);
+///     assert!(fleet.peak_handshakes <= 4);
+///     fleet.shutdown().await;
+/// });
+/// ```
+#[derive(Clone)]
+pub struct FleetConnector {
+    config: FleetConfig,
+    handshakes: Arc<Semaphore>,
+    in_flight: Arc<AtomicUsize>,
+    peak: Arc<AtomicUsize>,
+}
+
+impl FleetConnector {
+    /// Creates a connector.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The fleet size, handshake limit, ramp-up and retries.
+    ///
+    /// # Returns
+    ///
+    /// A new `FleetConnector`.
+    pub fn new(config: FleetConfig) -> Self {
+        FleetConnector {
+            config,
+            handshakes: Arc::new(Semaphore::new(config.max_concurrent_handshakes.max(1))),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            peak: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Returns the number of handshakes in flight.
+    pub fn handshakes_in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Creates `config.connections` controllers and connects each within its slot of the
+    /// ramp-up period.
+    ///
+    /// Each controller connects with `connect_handle` and its own pipeline settings.
+    ///
+    /// # Arguments
+    ///
+    /// * `make` - Creates the controller of the member with the given index, e.g. with a
+    ///   per-device URL or headers.
+    ///
+    /// # Returns
+    ///
+    /// The `Fleet`, including the members that failed to connect.
+    pub async fn connect<F>(&self, make: F) -> Fleet
+    where
+        F: Fn(usize) -> WebSocketController,
+    {
+        let started = Instant::now();
+        let count = self.config.connections;
+        let slot = self.config.ramp_up.checked_div(count.max(1) as u32).unwrap_or_default();
+        self.peak.store(self.in_flight.load(Ordering::Relaxed), Ordering::Relaxed);
+
+        let mut tasks = Vec::with_capacity(count);
+        for index in 0..count {
+            let start = slot.saturating_mul(index as u32) + slot.mul_f64(unit_random());
+            let controller = make(index);
+            let connector = self.clone();
+            tasks.push(spawn_named("websocket_toolkit::fleet_member", async move {
+                tokio::time::sleep(start).await;
+                let result = connector.connect_member(&controller).await;
+                (index, controller, result)
+            }));
+        }
+
+        let mut fleet = Fleet {
+            members: Vec::with_capacity(count),
+            failures: Vec::new(),
+            peak_handshakes: 0,
+            elapsed: Duration::ZERO,
+        };
+        for (index, task) in tasks.into_iter().enumerate() {
+            match task.await {
+                Ok((index, controller, Ok(handle))) => fleet.members.push(FleetMember { index, controller, handle }),
+                Ok((index, _, Err(e))) => fleet.failures.push((index, e)),
+                Err(e) => fleet.failures.push((index, format!("Fleet member task failed: {}", e))),
+            }
+        }
+        fleet.peak_handshakes = self.peak.load(Ordering::Relaxed);
+        fleet.elapsed = started.elapsed();
+        info!(
+            "Fleet started: {} of {} connected in {:?}, peak {} handshakes",
+            fleet.members.len(),
+            count,
+            fleet.elapsed,
+            fleet.peak_handshakes
+        );
+        fleet
+    }
+
+    /// Connects one member, retrying with backoff and holding a handshake slot only while
+    /// connecting.
+    async fn connect_member(&self, controller: &WebSocketController) -> Result<ConnectionHandle, String> {
+        let mut attempt = 0;
+        loop {
+            let result = {
+                let _permit = self
+                    .handshakes
+                    .acquire()
+                    .await
+                    .map_err(|e| format!("Failed to acquire handshake slot: {}", e))?;
+                let in_flight = self.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+                self.peak.fetch_max(in_flight, Ordering::Relaxed);
+                // Converted here, as the boxed error is not `Send`.
+                let result = controller
+                    .connect_handle(controller.pipeline_config())
+                    .await
+                    .map_err(|e| (is_auth_rejected(e.as_ref()), e.to_string()));
+                self.in_flight.fetch_sub(1, Ordering::Relaxed);
+                result
+            };
+            match result {
+                Ok(handle) => return Ok(handle),
+                Err((true, e)) => {
+                    error!("Fleet member was rejected: {}", e);
+                    return Err(format!("Failed to connect: {}", e));
+                }
+                Err((_, e)) if attempt >= self.config.retries => {
+                    error!("Fleet member failed to connect after {} attempts: {}", attempt + 1, e);
+                    return Err(format!("Failed to connect: {}", e));
+                }
+                Err((_, e)) => {
+                    let delay = jittered(self.config.backoff.saturating_mul(2_u32.saturating_pow(attempt)), 0.5);
+                    debug!("Fleet member failed to connect ({}); retrying in {:?}", e, delay);
+                    attempt += 1;
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::EchoServer;
+
+    /// Tests that starts are spread over the ramp-up and the handshake limit holds.
+    #[tokio::test]
+    async fn test_fleet_ramp_and_limit() {
+        let server = EchoServer::start().await.expect("Failed to start echo server");
+        let url = server.url().to_string();
+        let connector = FleetConnector::new(FleetConfig {
+            connections: 20,
+            max_concurrent_handshakes: 3,
+            ramp_up: Duration::from_millis(200),
+            ..FleetConfig::default()
+        });
+        let fleet = connector.connect(move |_| WebSocketController::new(&url, 0, None)).await;
+        assert_eq!(fleet.members.len(), 20);
+        assert!(fleet.failures.is_empty());
+        assert!(fleet.peak_handshakes >= 1 && fleet.peak_handshakes <= 3, "Peak was {}", fleet.peak_handshakes);
+        assert!(fleet.elapsed >= Duration::from_millis(190), "Ramp-up took {:?}", fleet.elapsed);
+        assert!(fleet.members.iter().enumerate().all(|(i, member)| member.index == i));
+        assert_eq!(connector.handshakes_in_flight(), 0);
+        fleet.shutdown().await;
+    }
+
+    /// Tests that unreachable members are retried and reported.
+    #[tokio::test]
+    async fn test_fleet_failures() {
+        let connector = FleetConnector::new(FleetConfig {
+            connections: 2,
+            ramp_up: Duration::ZERO,
+            retries: 1,
+            backoff: Duration::from_millis(10),
+            ..FleetConfig::default()
+        });
+        let fleet = connector.connect(|_| WebSocketController::new("ws://127.0.0.1:1", 0, None)).await;
+        assert!(fleet.members.is_empty());
+        assert_eq!(fleet.failures.iter().map(|(index, _)| *index).collect::<Vec<_>>(), vec![0, 1]);
+    }
+}
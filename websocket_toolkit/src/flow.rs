@@ -0,0 +1,283 @@
+//! # `flow.rs`: Credit-based application-level flow control
+//!
+//! TCP and socket buffers can absorb megabytes of messages, so a fast producer learns that its
+//! consumer is falling behind only long after it has overrun it. With credit-based flow control
+//! the receiver decides how much may be in flight: it grants credits with `credit` envelopes,
+//! and the sender spends one credit per message and waits when it has none left.
+//!
+//! The scheme is symmetric, so the same two types serve clients and servers alike:
+//!
+//! - `ReceiveWindow` is kept by the consuming side. It issues an initial grant of `window`
+//!   credits and, as the application consumes messages, replenishes them in batches of half
+//!   the window, so grants cost one small message per `window / 2` data messages.
+//! - `SendWindow` is kept by the producing side. `acquire` waits for a credit, and
+//!   `handle_grant`/`handle_message` add the credits carried by received grants.
+//!   `FlowControlledSender` combines it with a pipeline's `PipelineSender`.
+//!
+//! A grant is an `Envelope` of kind `CREDIT_KIND` whose id is the number of credits.
+
+use crate::messages::{Envelope, MessageFormat};
+use crate::pipeline::PipelineSender;
+use log::{debug, warn};
+use std::sync::Arc;
+use tokio::sync::mpsc::error::SendError;
+use tokio::sync::Semaphore;
+use tokio_tungstenite::tungstenite::Message;
+
+/// The envelope kind of credit grants.
+pub const CREDIT_KIND: &str = "credit";
+
+/// Builds a grant of `credits` messages.
+///
+/// # Arguments
+///
+/// * `credits` - The number of additional messages the peer may send.
+///
+/// # Returns
+///
+/// An empty `Envelope` of kind `CREDIT_KIND` whose id is `credits`.
+pub fn credit_envelope(credits: u32) -> Envelope {
+    Envelope::new(CREDIT_KIND, Vec::new()).with_id(credits.to_string())
+}
+
+/// The producing side's credit balance. Clones share the same balance.
+#[derive(Debug, Clone)]
+pub struct SendWindow {
+    credits: Arc<Semaphore>,
+}
+
+impl SendWindow {
+    /// Creates a window holding `initial` credits.
+    ///
+    /// # Arguments
+    ///
+    /// * `initial` - Credits usable before the first grant arrives; usually 0.
+    ///
+    /// # Returns
+    ///
+    /// A new `SendWindow`.
+    pub fn new(initial: u32) -> Self {
+        SendWindow {
+            credits: Arc::new(Semaphore::new(initial as usize)),
+        }
+    }
+
+    /// Waits for a credit and spends it.
+    pub async fn acquire(&self) {
+        match self.credits.acquire().await {
+            Ok(permit) => permit.forget(),
+            Err(_) => unreachable!("the credit semaphore is never closed"),
+        }
+    }
+
+    /// Spends a credit if one is available.
+    ///
+    /// # Returns
+    ///
+    /// `true` if a credit was spent, `false` if the window is exhausted.
+    pub fn try_acquire(&self) -> bool {
+        match self.credits.try_acquire() {
+            Ok(permit) => {
+                permit.forget();
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Adds credits, waking senders waiting in `acquire`.
+    ///
+    /// # Arguments
+    ///
+    /// * `credits` - The number of credits granted by the peer.
+    pub fn grant(&self, credits: u32) {
+        let credits = (credits as usize).min(Semaphore::MAX_PERMITS - self.credits.available_permits());
+        self.credits.add_permits(credits);
+    }
+
+    /// Adds the credits of `envelope` if it is a grant.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - A received envelope.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `envelope` was a valid grant.
+    pub fn handle_grant(&self, envelope: &Envelope) -> bool {
+        if envelope.kind != CREDIT_KIND {
+            return false;
+        }
+        match envelope.id.as_deref().map(str::parse::<u32>) {
+            Some(Ok(credits)) => {
+                debug!("Granted {} credits", credits);
+                self.grant(credits);
+                true
+            }
+            _ => {
+                warn!("Ignoring credit grant without a valid count: {:?}", envelope.id);
+                false
+            }
+        }
+    }
+
+    /// Adds the credits of a received frame if it is a grant encoded in `format`.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - A received frame.
+    /// * `format` - The format envelopes are encoded in.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `message` was a grant; other frames should be handled by the application.
+    pub fn handle_message(&self, message: &Message, format: MessageFormat) -> bool {
+        let data: &[u8] = match message {
+            Message::Binary(data) => data,
+            Message::Text(text) => text.as_bytes(),
+            _ => return false,
+        };
+        Envelope::decode(data, format).is_ok_and(|envelope| self.handle_grant(&envelope))
+    }
+
+    /// Returns the number of credits currently available.
+    pub fn available(&self) -> usize {
+        self.credits.available_permits()
+    }
+}
+
+/// The consuming side's record of credits granted and consumed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceiveWindow {
+    window: u32,
+    consumed: u32,
+}
+
+impl ReceiveWindow {
+    /// Creates a window allowing up to `window` messages in flight.
+    ///
+    /// # Arguments
+    ///
+    /// * `window` - The maximum number of unconsumed messages the peer may send.
+    ///
+    /// # Returns
+    ///
+    /// A new `ReceiveWindow`.
+    pub fn new(window: u32) -> Self {
+        ReceiveWindow {
+            window: window.max(1),
+            consumed: 0,
+        }
+    }
+
+    /// Returns the grant to send once the connection is open.
+    pub fn initial_grant(&self) -> Envelope {
+        credit_envelope(self.window)
+    }
+
+    /// Records that the application consumed one message.
+    ///
+    /// # Returns
+    ///
+    /// A grant to send back once half the window has been consumed, otherwise `None`.
+    pub fn record_consumed(&mut self) -> Option<Envelope> {
+        self.consumed += 1;
+        if self.consumed >= (self.window / 2).max(1) {
+            let credits = std::mem::take(&mut self.consumed);
+            Some(credit_envelope(credits))
+        } else {
+            None
+        }
+    }
+}
+
+/// A pipeline sender that spends a credit from a `SendWindow` for every message.
+#[derive(Debug, Clone)]
+pub struct FlowControlledSender {
+    sender: PipelineSender,
+    window: SendWindow,
+}
+
+impl FlowControlledSender {
+    /// Wraps `sender` so each message waits for a credit from `window`.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - The sending half of a pipeline.
+    /// * `window` - The credit balance, fed by the task reading grants from the peer.
+    ///
+    /// # Returns
+    ///
+    /// A new `FlowControlledSender`.
+    pub fn new(sender: PipelineSender, window: SendWindow) -> Self {
+        FlowControlledSender { sender, window }
+    }
+
+    /// Waits for a credit, then queues `message` on the pipeline.
+    ///
+    /// Control frames and credit grants should be sent through the underlying `PipelineSender`,
+    /// which does not spend credits.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The data message to send.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or the message back if the writer task has stopped.
+    pub async fn send(&self, message: Message) -> Result<(), SendError<Message>> {
+        self.window.acquire().await;
+        self.sender.send(message).await
+    }
+
+    /// Returns the credit balance.
+    pub fn window(&self) -> &SendWindow {
+        &self.window
+    }
+}
+
+#[cfg(all(test, feature = "cbor"))]
+mod tests {
+    use super::*;
+    use crate::testing::{memory_pair, TestHandle};
+    use futures_util::{SinkExt, StreamExt};
+    use std::time::Duration;
+
+    /// Tests that grants are replenished in batches of half the window.
+    #[test]
+    fn test_receive_window_batches_grants() {
+        let mut window = ReceiveWindow::new(4);
+        assert_eq!(window.initial_grant(), credit_envelope(4));
+        assert_eq!(window.record_consumed(), None);
+        assert_eq!(window.record_consumed(), Some(credit_envelope(2)));
+        assert_eq!(window.record_consumed(), None);
+    }
+
+    /// Tests that a sender stops at the granted window until the receiver replenishes it.
+    #[tokio::test]
+    async fn test_sender_respects_window() {
+        let (mut client, server) = memory_pair(None).await;
+        let mut server = TestHandle::new(server).with_timeout(Duration::from_millis(200));
+        let format = MessageFormat::Cbor;
+        let send_window = SendWindow::new(0);
+        let mut receive_window = ReceiveWindow::new(2);
+        assert!(!send_window.try_acquire());
+
+        let grant = receive_window.initial_grant().encode(format).unwrap();
+        server.send(Message::Binary(grant)).await;
+        let frame = client.next().await.unwrap().unwrap();
+        assert!(send_window.handle_message(&frame, format));
+        assert_eq!(send_window.available(), 2);
+
+        for i in 0..2u8 {
+            send_window.acquire().await;
+            client.send(Message::Binary(vec![i])).await.unwrap();
+        }
+        assert!(tokio::time::timeout(Duration::from_millis(50), send_window.acquire()).await.is_err());
+
+        server.assert_next_message_eq(Message::Binary(vec![0])).await;
+        assert_eq!(receive_window.record_consumed(), Some(credit_envelope(1)));
+        send_window.handle_grant(&credit_envelope(1));
+        assert!(send_window.try_acquire());
+    }
+}
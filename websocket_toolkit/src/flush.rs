@@ -0,0 +1,105 @@
+//! # `flush.rs`: Flush policies for batched sends
+//!
+//! Sending with `SinkExt::send` flushes the socket after every message, which costs a
+//! syscall per message. The controller's `feed_message` instead queues frames with
+//! `SinkExt::feed` and consults a `FlushPolicy` to decide when to flush, so high-frequency
+//! senders can amortize writes over many messages. The pipeline's writer task applies the
+//! same policies, so the latency/throughput trade-off can be tuned per workload without
+//! changing the send loop.
+
+use std::time::{Duration, Instant};
+
+/// When queued messages are flushed to the socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushPolicy {
+    /// Flush after every message, like `send_message`. Lowest latency.
+    #[default]
+    Immediate,
+    /// Flush after every `n` messages. Messages short of a full batch wait for the next
+    /// message, an explicit `flush`, or the connection closing.
+    EveryN(usize),
+    /// Only flush when `flush` is called.
+    Manual,
+    /// Flush once the given time has passed since the last flush. Checked on every
+    /// `feed_message` and by `flush_if_due`, which callers should poll from a timer so the
+    /// last messages of a burst are not held back.
+    Interval(Duration),
+    /// Flush whenever the pipeline writer's outbound queue runs empty, batching bursts
+    /// without delaying isolated messages. The default for pipelines; `feed_message` has
+    /// no queue to inspect and treats it like `Immediate`.
+    WhenIdle,
+}
+
+/// Tracks unflushed messages and decides when a `FlushPolicy` calls for a flush.
+#[derive(Debug, Clone)]
+pub(crate) struct FlushState {
+    /// The number of messages fed since the last flush.
+    pending: usize,
+    /// When the socket was last flushed.
+    last_flush: Instant,
+}
+
+impl FlushState {
+    /// Creates a state with nothing pending.
+    pub(crate) fn new() -> Self {
+        FlushState {
+            pending: 0,
+            last_flush: Instant::now(),
+        }
+    }
+
+    /// Records a fed message.
+    pub(crate) fn record_feed(&mut self) {
+        self.pending += 1;
+    }
+
+    /// Records a flush.
+    pub(crate) fn record_flush(&mut self) {
+        self.pending = 0;
+        self.last_flush = Instant::now();
+    }
+
+    /// Returns the number of messages fed since the last flush.
+    pub(crate) fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Returns whether `policy` calls for flushing the pending messages now.
+    pub(crate) fn is_due(&self, policy: FlushPolicy) -> bool {
+        if self.pending == 0 {
+            return false;
+        }
+        match policy {
+            FlushPolicy::Immediate | FlushPolicy::WhenIdle => true,
+            FlushPolicy::EveryN(n) => self.pending >= n.max(1),
+            FlushPolicy::Manual => false,
+            FlushPolicy::Interval(interval) => self.last_flush.elapsed() >= interval,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests when each policy considers a flush due.
+    #[test]
+    fn test_flush_due_per_policy() {
+        let mut state = FlushState::new();
+        assert!(!state.is_due(FlushPolicy::Immediate), "Nothing pending, nothing to flush");
+
+        state.record_feed();
+        assert!(state.is_due(FlushPolicy::Immediate));
+        assert!(!state.is_due(FlushPolicy::Manual));
+        assert!(!state.is_due(FlushPolicy::Interval(Duration::from_secs(60))));
+        assert!(state.is_due(FlushPolicy::Interval(Duration::ZERO)));
+        assert!(!state.is_due(FlushPolicy::EveryN(2)));
+
+        state.record_feed();
+        assert!(state.is_due(FlushPolicy::EveryN(2)));
+
+        state.record_flush();
+        assert_eq!(state.pending(), 0);
+        assert!(!state.is_due(FlushPolicy::Immediate));
+    }
+}
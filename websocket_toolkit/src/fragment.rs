@@ -0,0 +1,361 @@
+//! # `fragment.rs`: Size-based fragmentation of outbound messages
+//!
+//! A multi-megabyte message sent as one frame occupies the connection until its last byte is
+//! written, and some proxies and servers reject frames above a size limit. With a
+//! `Fragmentation` set, messages above its `threshold` are sent as a sequence of frames of
+//! at most `fragment_size` bytes (a text or binary frame followed by continuation frames, as
+//! RFC 6455 section 5.4 allows), while smaller messages keep the single-frame path.
+//!
+//! tungstenite 0.15 only writes whole messages, so the fragments are encoded here and written
+//! to the socket directly, after any frames tungstenite has buffered. Fragments are masked
+//! as a client must mask them; do not use fragmentation on the server side of a connection.
+//!
+//! `send_fragmented` sends one message on a stream the caller owns; the controller's
+//! `send_*` methods use it. Pipelines wrap their stream in a `FragmentingStream`, which does
+//! the same for every message the writer task sends.
+//!
+//! `send_stream` sends a message whose payload is read from an `AsyncRead` as it goes out,
+//! one fragment per chunk, so the producer never holds the whole payload in memory. The
+//! reader is only read from while the socket accepts the previous chunk, so a slow
+//! connection slows the reader down. Its fragments bypass tungstenite, which does not know a
+//! message is in progress: a `send_stream` that fails or is cancelled midway leaves the peer
+//! waiting for the rest of the message, and the connection must be closed.
+
+use crate::jitter::random_u64;
+use crate::messages::FrameKind;
+use futures_util::{Sink, SinkExt, Stream};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_tungstenite::tungstenite::{Error, Message};
+use tokio_tungstenite::WebSocketStream;
+
+/// When and how outbound messages are fragmented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fragmentation {
+    /// Messages with a payload longer than this many bytes are fragmented.
+    pub threshold: usize,
+    /// The most payload bytes per fragment.
+    pub fragment_size: usize,
+}
+
+impl Default for Fragmentation {
+    /// Fragments messages above 64 KiB into 16 KiB frames.
+    fn default() -> Self {
+        Fragmentation { threshold: 64 * 1024, fragment_size: 16 * 1024 }
+    }
+}
+
+impl Fragmentation {
+    /// Encodes `message` as masked fragments, if it is a text or binary message above the
+    /// threshold.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to send.
+    ///
+    /// # Returns
+    ///
+    /// The bytes of all fragments, or `None` if the message goes out as a single frame.
+    pub fn encode(&self, message: &Message) -> Option<Vec<u8>> {
+        let (opcode, payload) = match message {
+            Message::Text(text) => (0x1, text.as_bytes()),
+            Message::Binary(data) => (0x2, data.as_slice()),
+            _ => return None,
+        };
+        if payload.len() <= self.threshold {
+            return None;
+        }
+        let fragment_size = self.fragment_size.max(1);
+        let count = payload.len().div_ceil(fragment_size);
+        let mut encoded = Vec::with_capacity(payload.len() + count * 14);
+        for (i, fragment) in payload.chunks(fragment_size).enumerate() {
+            let opcode = if i == 0 { opcode } else { 0x0 };
+            let fin = if i + 1 == count { 0x80 } else { 0x00 };
+            encode_frame(&mut encoded, fin | opcode, fragment);
+        }
+        Some(encoded)
+    }
+}
+
+/// Appends a masked frame with the given first header byte.
+fn encode_frame(output: &mut Vec<u8>, first: u8, payload: &[u8]) {
+    output.push(first);
+    match payload.len() {
+        len @ 0..=125 => output.push(0x80 | len as u8),
+        len @ 126..=0xFFFF => {
+            output.push(0x80 | 126);
+            output.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            output.push(0x80 | 127);
+            output.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    let mask = (random_u64() as u32).to_be_bytes();
+    output.extend_from_slice(&mask);
+    output.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+}
+
+/// Sends `message` on `ws_stream`, in fragments if `fragmentation` calls for it.
+///
+/// # Arguments
+///
+/// * `ws_stream` - The client side of a connection.
+/// * `message` - The message to send.
+/// * `fragmentation` - When to fragment, or `None` to always send a single frame.
+///
+/// # Returns
+///
+/// A `Result` indicating the message was written and flushed.
+pub async fn send_fragmented<S>(
+    ws_stream: &mut WebSocketStream<S>,
+    message: Message,
+    fragmentation: Option<Fragmentation>,
+) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match fragmentation.and_then(|fragmentation| fragmentation.encode(&message)) {
+        Some(fragments) => {
+            // Frames tungstenite has buffered go first.
+            ws_stream.flush().await?;
+            ws_stream.get_mut().write_all(&fragments).await?;
+            ws_stream.get_mut().flush().await?;
+            Ok(())
+        }
+        None => ws_stream.send(message).await,
+    }
+}
+
+/// How `send_stream` frames a streamed message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamOptions {
+    /// Whether the message is text or binary. For text, the whole payload must be valid
+    /// UTF-8; chunks may split a character.
+    pub frame_kind: FrameKind,
+    /// The most payload bytes read and sent per fragment.
+    pub chunk_size: usize,
+}
+
+impl Default for StreamOptions {
+    /// Sends a binary message in 16 KiB fragments.
+    fn default() -> Self {
+        StreamOptions { frame_kind: FrameKind::Binary, chunk_size: 16 * 1024 }
+    }
+}
+
+/// Sends the contents of `reader` as one message, a chunk per fragment.
+///
+/// At most two chunks are held at a time: the one being written and the next, read ahead
+/// to tell whether the current one is the last. An empty reader sends an empty message.
+///
+/// Not cancel-safe: if the returned future is dropped after the first fragment went out,
+/// the message is left unfinished, and anything sent afterwards would be taken by the peer
+/// as part of it or as a protocol violation. Close the connection instead of reusing it, for
+/// example by dropping it, since tungstenite's close frame would land mid-message too.
+///
+/// # Arguments
+///
+/// * `ws_stream` - The client side of a connection.
+/// * `reader` - The payload, read until end of file.
+/// * `options` - The frame type and chunk size.
+///
+/// # Returns
+///
+/// A `Result` containing the number of payload bytes sent. If reading or writing fails after
+/// the first fragment went out, the message is left unfinished and the connection must be
+/// closed.
+pub async fn send_stream<S, R>(ws_stream: &mut WebSocketStream<S>, mut reader: R, options: StreamOptions) -> Result<u64, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    R: AsyncRead + Unpin,
+{
+    let chunk_size = options.chunk_size.max(1);
+    let mut opcode = match options.frame_kind {
+        FrameKind::Text => 0x1,
+        FrameKind::Binary => 0x2,
+    };
+    let mut current = Vec::with_capacity(chunk_size);
+    read_chunk(&mut reader, &mut current, chunk_size).await?;
+    // Frames tungstenite has buffered go first.
+    ws_stream.flush().await?;
+    let mut frame = Vec::with_capacity(chunk_size + 14);
+    let mut sent = 0;
+    loop {
+        // A short chunk means the reader is exhausted; a full one needs a look ahead.
+        let mut next = Vec::with_capacity(chunk_size);
+        if current.len() == chunk_size {
+            read_chunk(&mut reader, &mut next, chunk_size).await?;
+        }
+        let fin = next.is_empty();
+        frame.clear();
+        encode_frame(&mut frame, if fin { 0x80 | opcode } else { opcode }, &current);
+        ws_stream.get_mut().write_all(&frame).await?;
+        sent += current.len() as u64;
+        if fin {
+            ws_stream.get_mut().flush().await?;
+            return Ok(sent);
+        }
+        opcode = 0x0;
+        current = next;
+    }
+}
+
+/// Reads from `reader` until `chunk` holds `chunk_size` bytes or the reader is exhausted.
+async fn read_chunk<R: AsyncRead + Unpin>(reader: &mut R, chunk: &mut Vec<u8>, chunk_size: usize) -> io::Result<()> {
+    while chunk.len() < chunk_size {
+        let mut limited = (&mut *reader).take((chunk_size - chunk.len()) as u64);
+        if limited.read_buf(chunk).await? == 0 {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// A connection that sends messages above the fragmentation threshold in fragments.
+///
+/// Wraps a `WebSocketStream` as a `Sink` and `Stream` of messages; with no `Fragmentation`
+/// it passes everything through. While fragments are being written, reads wait, because
+/// tungstenite answers pings while reading and its pong must not land between fragments.
+#[derive(Debug)]
+pub struct FragmentingStream<S> {
+    ws_stream: WebSocketStream<S>,
+    fragmentation: Option<Fragmentation>,
+    /// The fragments of the message being sent, and how many of their bytes were written.
+    fragments: Vec<u8>,
+    written: usize,
+}
+
+impl<S> FragmentingStream<S> {
+    /// Wraps `ws_stream`.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - The client side of a connection.
+    /// * `fragmentation` - When to fragment, or `None` to pass messages through.
+    pub fn new(ws_stream: WebSocketStream<S>, fragmentation: Option<Fragmentation>) -> Self {
+        FragmentingStream { ws_stream, fragmentation, fragments: Vec::new(), written: 0 }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> FragmentingStream<S> {
+    /// Writes the pending fragments, after the frames tungstenite has buffered.
+    fn poll_write_fragments(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        if self.fragments.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        if self.written == 0 {
+            ready!(Pin::new(&mut self.ws_stream).poll_flush(cx))?;
+        }
+        while self.written < self.fragments.len() {
+            let n = ready!(Pin::new(self.ws_stream.get_mut()).poll_write(cx, &self.fragments[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(Error::Io(io::ErrorKind::WriteZero.into())));
+            }
+            self.written += n;
+        }
+        ready!(Pin::new(self.ws_stream.get_mut()).poll_flush(cx))?;
+        self.fragments = Vec::new();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Sink<Message> for FragmentingStream<S> {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        ready!(self.poll_write_fragments(cx))?;
+        Pin::new(&mut self.ws_stream).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, message: Message) -> Result<(), Error> {
+        match self.fragmentation.and_then(|fragmentation| fragmentation.encode(&message)) {
+            // Written by the next `poll_ready` or `poll_flush`.
+            Some(fragments) => {
+                self.fragments = fragments;
+                Ok(())
+            }
+            None => Pin::new(&mut self.ws_stream).start_send(message),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        ready!(self.poll_write_fragments(cx))?;
+        Pin::new(&mut self.ws_stream).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        ready!(self.poll_write_fragments(cx))?;
+        Pin::new(&mut self.ws_stream).poll_close(cx)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Stream for FragmentingStream<S> {
+    type Item = Result<Message, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Err(e) = ready!(self.poll_write_fragments(cx)) {
+            return Poll::Ready(Some(Err(e)));
+        }
+        Pin::new(&mut self.ws_stream).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::WebSocketClient;
+    use crate::testing::EchoServer;
+    use futures_util::StreamExt;
+
+    /// Tests the frame layout of fragments, and that the peer reassembles them.
+    #[tokio::test]
+    async fn test_fragmentation() {
+        let fragmentation = Fragmentation { threshold: 4, fragment_size: 3 };
+        assert_eq!(fragmentation.encode(&Message::Binary(vec![1, 2, 3, 4])), None);
+        assert_eq!(fragmentation.encode(&Message::Ping(vec![0; 10])), None);
+        let encoded = fragmentation.encode(&Message::Text("hello".into())).unwrap();
+        // A text frame of 3 bytes, then a final continuation frame of 2, each with a mask.
+        assert_eq!((encoded[0], encoded[1]), (0x01, 0x83));
+        assert_eq!((encoded[9], encoded[10]), (0x80, 0x82));
+        assert_eq!(encoded.len(), 9 + 8);
+
+        let server = EchoServer::start().await.unwrap();
+        let mut ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let large = Message::Binary((0..200_000u32).map(|i| i as u8).collect());
+        send_fragmented(&mut ws_stream, Message::Text("first".into()), Some(Fragmentation::default())).await.unwrap();
+        send_fragmented(&mut ws_stream, large.clone(), Some(Fragmentation::default())).await.unwrap();
+        assert_eq!(ws_stream.next().await.unwrap().unwrap(), Message::Text("first".into()));
+        assert_eq!(ws_stream.next().await.unwrap().unwrap(), large);
+
+        let mut stream = FragmentingStream::new(ws_stream, Some(fragmentation));
+        stream.feed(Message::Binary(vec![1])).await.unwrap();
+        stream.send(Message::Text("fragmented".into())).await.unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap(), Message::Binary(vec![1]));
+        assert_eq!(stream.next().await.unwrap().unwrap(), Message::Text("fragmented".into()));
+    }
+
+    /// Tests that streamed payloads arrive as one message, including payloads of an exact
+    /// multiple of the chunk size and empty ones.
+    #[tokio::test]
+    async fn test_send_stream() {
+        let server = EchoServer::start().await.unwrap();
+        let mut ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let options = StreamOptions { chunk_size: 1000, ..StreamOptions::default() };
+
+        let payload: Vec<u8> = (0..123_456u32).map(|i| i as u8).collect();
+        assert_eq!(send_stream(&mut ws_stream, payload.as_slice(), options).await.unwrap(), 123_456);
+        assert_eq!(ws_stream.next().await.unwrap().unwrap(), Message::Binary(payload));
+
+        let text = "é".repeat(1000);
+        let options = StreamOptions { frame_kind: FrameKind::Text, chunk_size: 1000 };
+        assert_eq!(send_stream(&mut ws_stream, text.as_bytes(), options).await.unwrap(), 2000);
+        assert_eq!(ws_stream.next().await.unwrap().unwrap(), Message::Text(text));
+
+        send_stream(&mut ws_stream, tokio::io::empty(), StreamOptions::default()).await.unwrap();
+        assert_eq!(ws_stream.next().await.unwrap().unwrap(), Message::Binary(Vec::new()));
+    }
+}
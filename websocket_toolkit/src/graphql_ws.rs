@@ -0,0 +1,604 @@
+//! # `graphql_ws.rs`: GraphQL over WebSocket (`graphql-transport-ws`)
+//!
+//! `GraphQlClient` drives GraphQL subscriptions with the `graphql-transport-ws` subprotocol
+//! used by Apollo Server, GraphQL Yoga, Hasura and others:
+//!
+//! - `connect` requests the subprotocol in the handshake, sends `connection_init` with an
+//!   optional payload (often an auth token) and waits for `connection_ack`.
+//! - `subscribe` sends a `subscribe` message under a fresh id and returns a
+//!   `GraphQlSubscription`, a `Stream` of the operation's `next` results with their `data`
+//!   deserialized into the caller's type. A result with errors and no data, or an `error`
+//!   message, yields `GraphQlError::Errors`; the server's `complete` ends the stream, and
+//!   dropping the stream early sends `complete` to the server.
+//! - `execute` runs a query or mutation and returns its single result.
+//! - The server's `ping` is answered with `pong`, and `ping` checks that the server is alive.
+//!
+//! Protocol messages are encoded and decoded as JSON with `MessageHandler`. Other messages
+//! are logged and ignored.
+
+use crate::connection::WebSocketClient;
+use crate::handle::ConnectionHandle;
+use crate::messages::{MessageFormat, MessageHandler};
+use crate::pipeline::{self, PipelineConfig};
+use crate::tasks::spawn_named;
+use futures_util::stream::Stream;
+use log::{debug, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite::Message;
+
+/// The subprotocol name requested in the handshake.
+pub const SUBPROTOCOL: &str = "graphql-transport-ws";
+
+/// How long `connect` waits for `connection_ack` unless told otherwise.
+pub const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many results of one subscription are buffered before the reader waits.
+const SUBSCRIPTION_CAPACITY: usize = 64;
+
+/// A message of the `graphql-transport-ws` protocol.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GraphQlMessage {
+    /// Client to server: opens the session.
+    ConnectionInit {
+        /// Parameters for the server, e.g. credentials.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payload: Option<Value>,
+    },
+    /// Server to client: accepts the session.
+    ConnectionAck {
+        /// Parameters from the server.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payload: Option<Value>,
+    },
+    /// Either direction: asks for a `pong`.
+    Ping {
+        /// Optional details.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payload: Option<Value>,
+    },
+    /// Either direction: answers a `ping`, or is sent unsolicited as a heartbeat.
+    Pong {
+        /// Optional details.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payload: Option<Value>,
+    },
+    /// Client to server: starts an operation.
+    Subscribe {
+        /// The operation id, unique among the operations in flight.
+        id: String,
+        /// The operation.
+        payload: SubscribePayload,
+    },
+    /// Server to client: one result of an operation.
+    Next {
+        /// The operation id.
+        id: String,
+        /// The execution result, with `data` and/or `errors`.
+        payload: Value,
+    },
+    /// Server to client: the operation failed before it could execute.
+    Error {
+        /// The operation id.
+        id: String,
+        /// The GraphQL errors.
+        payload: Vec<Value>,
+    },
+    /// Either direction: the operation is finished, or is no longer wanted.
+    Complete {
+        /// The operation id.
+        id: String,
+    },
+}
+
+impl GraphQlMessage {
+    /// Encodes the message as a text frame.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the frame, or an error message if serialization fails.
+    pub fn to_frame(&self) -> Result<Message, String> {
+        let encoded = MessageHandler::serialize(self, MessageFormat::Json)?;
+        String::from_utf8(encoded).map(Message::Text).map_err(|e| e.to_string())
+    }
+
+    /// Decodes a received message.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The payload of a text or binary frame.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the message, or an error message if it is not a protocol
+    /// message.
+    pub fn decode(data: &[u8]) -> Result<Self, String> {
+        MessageHandler::deserialize(data, MessageFormat::Json)?.ok_or_else(|| "Empty GraphQL message".to_string())
+    }
+}
+
+/// A GraphQL operation, the payload of a `subscribe` message.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscribePayload {
+    /// The GraphQL document.
+    pub query: String,
+    /// The operation to run, if the document has several.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation_name: Option<String>,
+    /// The values of the operation's variables.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variables: Option<Value>,
+    /// Protocol extensions, e.g. persisted query hashes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+}
+
+impl SubscribePayload {
+    /// Creates an operation without variables.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The GraphQL document.
+    ///
+    /// # Returns
+    ///
+    /// A new `SubscribePayload`.
+    pub fn new(query: impl Into<String>) -> Self {
+        SubscribePayload { query: query.into(), ..SubscribePayload::default() }
+    }
+
+    /// Sets the values of the operation's variables.
+    ///
+    /// # Arguments
+    ///
+    /// * `variables` - A JSON object keyed by variable name.
+    ///
+    /// # Returns
+    ///
+    /// The operation with the variables set.
+    pub fn with_variables(mut self, variables: Value) -> Self {
+        self.variables = Some(variables);
+        self
+    }
+}
+
+/// Why an operation or the session failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GraphQlError {
+    /// The connection could not be opened.
+    Connect(String),
+    /// The server did not acknowledge `connection_init` in time.
+    NotAcknowledged(Duration),
+    /// The server answered with GraphQL errors.
+    Errors(Vec<Value>),
+    /// The connection closed.
+    Closed,
+    /// A message could not be encoded, or a result did not match the expected type.
+    Serialization(String),
+}
+
+impl fmt::Display for GraphQlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphQlError::Connect(e) => write!(f, "failed to connect: {}", e),
+            GraphQlError::NotAcknowledged(timeout) => write!(f, "connection not acknowledged within {:?}", timeout),
+            GraphQlError::Errors(errors) => write!(f, "GraphQL errors: {}", Value::Array(errors.clone())),
+            GraphQlError::Closed => write!(f, "connection closed"),
+            GraphQlError::Serialization(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl StdError for GraphQlError {}
+
+/// A `graphql-transport-ws` session over one connection. Clones share the connection and
+/// the operations in flight.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use futures_util::StreamExt;
+/// use serde::Deserialize;
+/// use websocket_toolkit::connection::WebSocketClient;
+/// use websocket_toolkit::graphql_ws::{GraphQlClient, SubscribePayload};
+/// use websocket_toolkit::pipeline::PipelineConfig;
+///
+/// #[derive(Deserialize)]
+/// struct Prices {
+///     price: f64,
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let client = WebSocketClient::new("wss://api.example.com/graphql", 3);
+/// let init = serde_json::json!({ "token": "secret" });
+/// let graphql = GraphQlClient::connect(client, PipelineConfig::default(), Some(init)).await?;
+///
+/// let mut prices = graphql
+///     .subscribe::<Prices>(SubscribePayload::new("subscription { price(symbol: \"ACME\") }"))
+///     .await?;
+/// while let Some(result) = prices.next().await {
+///     println!("price: {}", result?.price);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct GraphQlClient {
+    handle: ConnectionHandle,
+    shared: Arc<Shared>,
+}
+
+/// State shared with the reader task and the subscriptions.
+struct Shared {
+    /// The operations in flight, by id.
+    operations: Mutex<HashMap<String, mpsc::Sender<Result<Value, GraphQlError>>>>,
+    next_id: AtomicU64,
+    /// The waiter of the last `ping`.
+    pong: Mutex<Option<oneshot::Sender<()>>>,
+}
+
+impl GraphQlClient {
+    /// Opens a connection that requests the `graphql-transport-ws` subprotocol, and opens
+    /// the session on it.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The server URL and any handshake headers.
+    /// * `config` - The pipeline settings of the connection.
+    /// * `init_payload` - The `connection_init` payload, if the server expects one.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the client, or why the connection or session failed.
+    pub async fn connect(client: WebSocketClient, config: PipelineConfig, init_payload: Option<Value>) -> Result<Self, GraphQlError> {
+        let ws_stream = client
+            .with_header("Sec-WebSocket-Protocol", SUBPROTOCOL)
+            .connect()
+            .await
+            .map_err(|e| GraphQlError::Connect(e.to_string()))?;
+        let (sender, receiver, tasks) = pipeline::spawn(ws_stream, config);
+        Self::new(ConnectionHandle::new(sender, receiver, tasks), init_payload, DEFAULT_ACK_TIMEOUT).await
+    }
+
+    /// Opens the session on a connection that negotiated the subprotocol. The client takes
+    /// every message received on `handle` from now on.
+    ///
+    /// # Arguments
+    ///
+    /// * `handle` - The connection to the GraphQL server.
+    /// * `init_payload` - The `connection_init` payload, if the server expects one.
+    /// * `ack_timeout` - How long to wait for `connection_ack`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the client, or why the session failed.
+    pub async fn new(handle: ConnectionHandle, init_payload: Option<Value>, ack_timeout: Duration) -> Result<Self, GraphQlError> {
+        send(&handle, &GraphQlMessage::ConnectionInit { payload: init_payload }).await?;
+        let acknowledged = async {
+            loop {
+                let message = handle.recv_inbound().await.ok_or(GraphQlError::Closed)?;
+                match GraphQlMessage::decode(message.as_bytes()) {
+                    Ok(GraphQlMessage::ConnectionAck { .. }) => return Ok(()),
+                    Ok(GraphQlMessage::Ping { .. }) => send(&handle, &GraphQlMessage::Pong { payload: None }).await?,
+                    Ok(other) => debug!("Ignoring {:?} before connection_ack", other),
+                    Err(e) => warn!("Ignoring a message that is not graphql-transport-ws: {}", e),
+                }
+            }
+        };
+        tokio::time::timeout(ack_timeout, acknowledged)
+            .await
+            .map_err(|_| GraphQlError::NotAcknowledged(ack_timeout))??;
+
+        let shared = Arc::new(Shared {
+            operations: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            pong: Mutex::new(None),
+        });
+        spawn_named("websocket_toolkit::graphql_ws_reader", read_messages(handle.clone(), Arc::downgrade(&shared)));
+        Ok(GraphQlClient { handle, shared })
+    }
+
+    /// Starts an operation and returns its results as they arrive.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The operation.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the stream of results, or an error if the operation could not
+    /// be sent.
+    pub async fn subscribe<T: DeserializeOwned>(&self, payload: SubscribePayload) -> Result<GraphQlSubscription<T>, GraphQlError> {
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed).to_string();
+        let (results_tx, results) = mpsc::channel(SUBSCRIPTION_CAPACITY);
+        self.shared.operations.lock().unwrap().insert(id.clone(), results_tx);
+        let subscription = GraphQlSubscription {
+            id: id.clone(),
+            results,
+            handle: self.handle.clone(),
+            shared: Arc::downgrade(&self.shared),
+            result_type: PhantomData,
+        };
+        send(&self.handle, &GraphQlMessage::Subscribe { id, payload }).await?;
+        Ok(subscription)
+    }
+
+    /// Runs a query or mutation and returns its result.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The operation.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the result's data, or why there is none.
+    pub async fn execute<T: DeserializeOwned>(&self, payload: SubscribePayload) -> Result<T, GraphQlError> {
+        let mut results = self.subscribe::<T>(payload).await?;
+        futures_util::StreamExt::next(&mut results).await.unwrap_or(Err(GraphQlError::Closed))
+    }
+
+    /// Sends a `ping` and waits for the server's `pong`.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - How long to wait for the `pong`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the server answered, or an error message if it did not.
+    pub async fn ping(&self, timeout: Duration) -> Result<(), String> {
+        let (pong_tx, pong) = oneshot::channel();
+        *self.shared.pong.lock().unwrap() = Some(pong_tx);
+        send(&self.handle, &GraphQlMessage::Ping { payload: None }).await.map_err(|e| e.to_string())?;
+        match tokio::time::timeout(timeout, pong).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err("Failed to ping: connection closed".to_string()),
+            Err(_) => Err(format!("Failed to ping: no pong within {:?}", timeout)),
+        }
+    }
+
+    /// Returns the number of operations in flight.
+    pub fn active(&self) -> usize {
+        self.shared.operations.lock().unwrap().len()
+    }
+
+    /// Returns the connection the client uses.
+    pub fn handle(&self) -> &ConnectionHandle {
+        &self.handle
+    }
+}
+
+impl fmt::Debug for GraphQlClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GraphQlClient").field("active", &self.active()).finish()
+    }
+}
+
+/// The results of one operation, from `GraphQlClient::subscribe`.
+///
+/// Each item is the `data` of a `next` message deserialized as `T`, or the errors of a
+/// result without data. The stream ends when the server completes the operation or the
+/// connection closes; dropping it before then sends `complete`.
+pub struct GraphQlSubscription<T> {
+    id: String,
+    results: mpsc::Receiver<Result<Value, GraphQlError>>,
+    handle: ConnectionHandle,
+    shared: Weak<Shared>,
+    result_type: PhantomData<fn() -> T>,
+}
+
+impl<T> GraphQlSubscription<T> {
+    /// Returns the operation id.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl<T: DeserializeOwned> Stream for GraphQlSubscription<T> {
+    type Item = Result<T, GraphQlError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.results.poll_recv(cx).map(|result| result.map(|result| result.and_then(decode_result)))
+    }
+}
+
+impl<T> Drop for GraphQlSubscription<T> {
+    fn drop(&mut self) {
+        let Some(shared) = self.shared.upgrade() else { return };
+        if shared.operations.lock().unwrap().remove(&self.id).is_none() {
+            return;
+        }
+        if tokio::runtime::Handle::try_current().is_ok() {
+            let handle = self.handle.clone();
+            let complete = GraphQlMessage::Complete { id: self.id.clone() };
+            spawn_named("websocket_toolkit::graphql_ws_complete", async move {
+                if send(&handle, &complete).await.is_err() {
+                    debug!("Connection closed before complete was sent");
+                }
+            });
+        }
+    }
+}
+
+impl<T> fmt::Debug for GraphQlSubscription<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GraphQlSubscription").field("id", &self.id).finish()
+    }
+}
+
+/// Deserializes the `data` of an execution result, or returns its errors if it has none.
+fn decode_result<T: DeserializeOwned>(mut result: Value) -> Result<T, GraphQlError> {
+    let data = result.get_mut("data").map(Value::take).filter(|data| !data.is_null());
+    match (data, result.get_mut("errors").map(Value::take)) {
+        (None, Some(Value::Array(errors))) => Err(GraphQlError::Errors(errors)),
+        (data, _) => serde_json::from_value(data.unwrap_or(Value::Null))
+            .map_err(|e| GraphQlError::Serialization(format!("Failed to deserialize GraphQL data: {}", e))),
+    }
+}
+
+/// Encodes `message` and queues it on `handle`.
+async fn send(handle: &ConnectionHandle, message: &GraphQlMessage) -> Result<(), GraphQlError> {
+    let frame = message.to_frame().map_err(GraphQlError::Serialization)?;
+    handle.send(frame).await.map_err(|_| GraphQlError::Closed)
+}
+
+/// Routes results to their operations and answers pings until the connection ends.
+async fn read_messages(handle: ConnectionHandle, shared: Weak<Shared>) {
+    while let Some(message) = handle.recv_inbound().await {
+        let Some(shared) = shared.upgrade() else { return };
+        let message = match GraphQlMessage::decode(message.as_bytes()) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Ignoring a message that is not graphql-transport-ws: {}", e);
+                continue;
+            }
+        };
+        match message {
+            GraphQlMessage::Next { id, payload } => {
+                let results = shared.operations.lock().unwrap().get(&id).cloned();
+                drop(shared);
+                match results {
+                    // A subscription dropped meanwhile no longer needs the result.
+                    Some(results) => {
+                        let _ = results.send(Ok(payload)).await;
+                    }
+                    None => debug!("Dropping a result of operation {}, which is not in flight", id),
+                }
+            }
+            GraphQlMessage::Error { id, payload } => {
+                let results = shared.operations.lock().unwrap().remove(&id);
+                drop(shared);
+                if let Some(results) = results {
+                    let _ = results.send(Err(GraphQlError::Errors(payload))).await;
+                }
+            }
+            GraphQlMessage::Complete { id } => {
+                shared.operations.lock().unwrap().remove(&id);
+            }
+            GraphQlMessage::Ping { .. } => {
+                drop(shared);
+                let _ = send(&handle, &GraphQlMessage::Pong { payload: None }).await;
+            }
+            GraphQlMessage::Pong { .. } => {
+                if let Some(pong) = shared.pong.lock().unwrap().take() {
+                    let _ = pong.send(());
+                }
+            }
+            other => debug!("Ignoring unexpected {:?}", other),
+        }
+    }
+    // Dropping the senders ends the subscriptions still in flight.
+    if let Some(shared) = shared.upgrade() {
+        shared.operations.lock().unwrap().clear();
+        shared.pong.lock().unwrap().take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockServer, TestHandle};
+    use futures_util::StreamExt;
+    use serde_json::json;
+    use tokio::net::TcpStream;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Price {
+        price: f64,
+    }
+
+    /// Reads the next protocol message the server received.
+    async fn next_message(connection: &mut TestHandle<TcpStream>) -> GraphQlMessage {
+        let message = connection.next_message().await.expect("No message received");
+        GraphQlMessage::decode(&message.into_data()).unwrap()
+    }
+
+    /// Sends a protocol message from the server.
+    async fn reply(connection: &mut TestHandle<TcpStream>, message: GraphQlMessage) {
+        connection.send(message.to_frame().unwrap()).await;
+    }
+
+    /// Tests the session handshake, typed results, errors, pings both ways, and completing
+    /// from either side.
+    #[tokio::test]
+    async fn test_graphql_ws() {
+        let mut server = MockServer::start().await.unwrap();
+        let client = WebSocketClient::new(server.url(), 0);
+        let (graphql, mut connection) = tokio::join!(
+            GraphQlClient::connect(client, PipelineConfig::default(), Some(json!({ "token": "secret" }))),
+            async {
+                let mut connection = server.accept().await;
+                let init = next_message(&mut connection).await;
+                assert_eq!(init, GraphQlMessage::ConnectionInit { payload: Some(json!({ "token": "secret" })) });
+                reply(&mut connection, GraphQlMessage::ConnectionAck { payload: None }).await;
+                connection
+            }
+        );
+        let graphql = graphql.unwrap();
+
+        let query = SubscribePayload::new("subscription { price }").with_variables(json!({ "symbol": "ACME" }));
+        let mut prices = graphql.subscribe::<Price>(query.clone()).await.unwrap();
+        let GraphQlMessage::Subscribe { id, payload } = next_message(&mut connection).await else {
+            panic!("Expected a subscribe message");
+        };
+        assert_eq!((id.as_str(), &payload), (prices.id(), &query));
+
+        reply(&mut connection, GraphQlMessage::Next { id: id.clone(), payload: json!({ "data": { "price": 12.5 } }) }).await;
+        assert_eq!(prices.next().await.unwrap(), Ok(Price { price: 12.5 }));
+        reply(&mut connection, GraphQlMessage::Ping { payload: None }).await;
+        assert_eq!(next_message(&mut connection).await, GraphQlMessage::Pong { payload: None });
+        let errors = json!({ "errors": [{ "message": "halted" }] });
+        reply(&mut connection, GraphQlMessage::Next { id: id.clone(), payload: errors }).await;
+        assert!(matches!(prices.next().await.unwrap(), Err(GraphQlError::Errors(errors)) if errors.len() == 1));
+        reply(&mut connection, GraphQlMessage::Complete { id }).await;
+        assert!(prices.next().await.is_none());
+        drop(prices);
+        assert_eq!(graphql.active(), 0);
+
+        let (pinged, ()) = tokio::join!(graphql.ping(Duration::from_secs(5)), async {
+            assert_eq!(next_message(&mut connection).await, GraphQlMessage::Ping { payload: None });
+            reply(&mut connection, GraphQlMessage::Pong { payload: None }).await;
+        });
+        assert_eq!(pinged, Ok(()));
+
+        let mut rejected = graphql.subscribe::<Price>(SubscribePayload::new("subscription { nope }")).await.unwrap();
+        let GraphQlMessage::Subscribe { id, .. } = next_message(&mut connection).await else {
+            panic!("Expected a subscribe message");
+        };
+        reply(&mut connection, GraphQlMessage::Error { id, payload: vec![json!({ "message": "unknown field" })] }).await;
+        assert!(matches!(rejected.next().await, Some(Err(GraphQlError::Errors(_)))));
+        assert!(rejected.next().await.is_none());
+
+        let abandoned = graphql.subscribe::<Price>(SubscribePayload::new("subscription { price }")).await.unwrap();
+        let abandoned_id = abandoned.id().to_string();
+        next_message(&mut connection).await;
+        drop(abandoned);
+        assert_eq!(next_message(&mut connection).await, GraphQlMessage::Complete { id: abandoned_id });
+    }
+
+    /// Tests that a session the server never acknowledges fails.
+    #[tokio::test]
+    async fn test_graphql_ws_not_acknowledged() {
+        let mut server = MockServer::start().await.unwrap();
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let _connection = server.accept().await;
+        let (sender, receiver, tasks) = pipeline::spawn(ws_stream, PipelineConfig::default());
+        let handle = ConnectionHandle::new(sender, receiver, tasks);
+        let error = GraphQlClient::new(handle, None, Duration::from_millis(50)).await.unwrap_err();
+        assert_eq!(error, GraphQlError::NotAcknowledged(Duration::from_millis(50)));
+    }
+}
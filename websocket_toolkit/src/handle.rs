@@ -0,0 +1,572 @@
+//! # `handle.rs`: A cloneable handle to one connection
+//!
+//! `WebSocketController`'s methods borrow the stream mutably, so sharing one connection
+//! between tasks used to mean wrapping the stream, and often the controller, in
+//! `Arc<Mutex<_>>`. `ConnectionHandle` bundles a pipeline's sender, receiver and tasks (see
+//! the `pipeline` module) behind `Arc`s instead: it is `Clone`, `Send` and `Sync`, every clone
+//! sends through the same writer task, and `recv` hands each inbound message to exactly one
+//! waiting clone. One task can therefore send while another awaits responses:
+//!
+//! ```rust
+//! use websocket_toolkit::controller::WebSocketController;
+//! use websocket_toolkit::pipeline::PipelineConfig;
+//! use websocket_toolkit::testing::EchoServer;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let server = EchoServer::start().await.unwrap();
+//! let controller = WebSocketController::new(server.url(), 0, None);
+//! let handle = controller.connect_handle(PipelineConfig::default()).await.unwrap();
+//!
+//! let sender = handle.clone();
+//! tokio::spawn(async move { sender.send_text("hello").await.unwrap() });
+//! assert_eq!(handle.recv_inbound().await.unwrap().as_bytes(), b"hello");
+//! # }
+//! ```
+//!
+//! Call `shutdown` when done with a connection. If the last clone is dropped without it, a
+//! warning is logged and a background task sends the close frame and waits up to
+//! `CLOSE_ON_DROP_TIMEOUT` for the writer to finish before aborting the connection, so
+//! forgotten connections do not linger half-open on the server.
+//!
+//! `CloseHandshake` tunes the close sequence of `shutdown`, `shutdown_with_grace` and the
+//! drop safety net: how long to wait for the peer's close reply, whether data messages that
+//! arrive meanwhile are kept for `recv_inbound` rather than discarded, and whether a
+//! `CloseSummary` envelope precedes the close frame so the server can log how the session
+//! went. Set it per handle with `set_close_handshake`, or for every handle a controller opens
+//! with `WebSocketController::set_close_handshake`.
+
+use crate::messages::{Envelope, FrameKind, InboundMessage, MessageFormat};
+use crate::pipeline::{Delivery, DrainReport, PipelineReceiver, PipelineSender, PipelineTasks, SendPermit};
+use crate::rtt::RttStats;
+use crate::tasks::spawn_named;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio_tungstenite::tungstenite::Message;
+
+/// How long `shutdown` and the drop safety net wait for the close handshake by default.
+pub const CLOSE_ON_DROP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The envelope kind of the `CloseSummary` sent before the close frame.
+pub const CLOSE_SUMMARY_KIND: &str = "close_summary";
+
+/// How a `ConnectionHandle` closes its connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CloseHandshake {
+    /// How long to wait for the peer's close reply before aborting the connection.
+    pub reply_timeout: Duration,
+    /// Keeps the data messages that arrive while waiting for the reply, so `recv_inbound`
+    /// returns them before it ends, instead of discarding them.
+    pub linger: bool,
+    /// The format to send a `CloseSummary` envelope in just before the close frame, or
+    /// `None` to send none.
+    pub summary: Option<MessageFormat>,
+}
+
+impl Default for CloseHandshake {
+    /// Waits `CLOSE_ON_DROP_TIMEOUT` for the reply, discards what arrives meanwhile and sends
+    /// no summary.
+    fn default() -> Self {
+        CloseHandshake { reply_timeout: CLOSE_ON_DROP_TIMEOUT, linger: false, summary: None }
+    }
+}
+
+/// The payload of the final status message, counting what went through the handle's
+/// `send` and `recv` methods (and the methods built on them).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CloseSummary {
+    /// The messages queued for sending, the summary excluded.
+    pub sent: u64,
+    /// The data messages received.
+    pub received: u64,
+    /// How long the handle has existed, in milliseconds.
+    pub uptime_ms: u64,
+}
+
+/// A cloneable, thread-safe handle to one pipelined connection.
+///
+/// The connection closes when `shutdown` is called or, as a safety net, once every clone has
+/// been dropped.
+#[derive(Debug, Clone)]
+pub struct ConnectionHandle {
+    sender: PipelineSender,
+    receiver: Arc<Mutex<PipelineReceiver>>,
+    shared: Arc<Shared>,
+}
+
+/// State shared by all clones; dropped with the last one.
+#[derive(Debug)]
+struct Shared {
+    /// The pipeline's tasks, taken by `shutdown` or the drop safety net.
+    tasks: std::sync::Mutex<Option<PipelineTasks>>,
+    shut_down: AtomicBool,
+    close_handshake: std::sync::Mutex<CloseHandshake>,
+    /// Data messages kept while waiting for the close reply.
+    lingered: std::sync::Mutex<VecDeque<Message>>,
+    sent: AtomicU64,
+    received: AtomicU64,
+    opened: Instant,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        let tasks = match self.tasks.get_mut().unwrap().take() {
+            Some(tasks) => tasks,
+            None => return,
+        };
+        if self.shut_down.load(Ordering::Acquire) {
+            return;
+        }
+        warn!("Connection handle dropped without shutdown(); closing the connection in the background");
+        let reply_timeout = self.close_handshake.get_mut().unwrap().reply_timeout;
+        // The writer sends the close frame once the last sender is gone; give it a bounded
+        // amount of time in case a `PipelineSender` obtained from `sender()` is still alive.
+        if tokio::runtime::Handle::try_current().is_ok() {
+            spawn_named("websocket_toolkit::close_on_drop", async move {
+                let PipelineTasks { reader, mut writer } = tasks;
+                if tokio::time::timeout(reply_timeout, &mut writer).await.is_err() {
+                    warn!("Connection did not close within {:?}; aborting it", reply_timeout);
+                    writer.abort();
+                }
+                reader.abort();
+            });
+        } else {
+            tasks.abort();
+        }
+    }
+}
+
+impl ConnectionHandle {
+    /// Wraps the parts returned by `pipeline::spawn`.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - The pipeline's sender.
+    /// * `receiver` - The pipeline's receiver.
+    /// * `tasks` - The pipeline's reader and writer tasks.
+    ///
+    /// # Returns
+    ///
+    /// A new `ConnectionHandle`.
+    pub fn new(sender: PipelineSender, receiver: PipelineReceiver, tasks: PipelineTasks) -> Self {
+        ConnectionHandle {
+            sender,
+            receiver: Arc::new(Mutex::new(receiver)),
+            shared: Arc::new(Shared {
+                tasks: std::sync::Mutex::new(Some(tasks)),
+                shut_down: AtomicBool::new(false),
+                close_handshake: std::sync::Mutex::new(CloseHandshake::default()),
+                lingered: std::sync::Mutex::new(VecDeque::new()),
+                sent: AtomicU64::new(0),
+                received: AtomicU64::new(0),
+                opened: Instant::now(),
+            }),
+        }
+    }
+
+    /// Queues a message for sending.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The frame to send.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if the connection has closed.
+    pub async fn send(&self, message: Message) -> Result<(), String> {
+        self.sender
+            .send(message)
+            .await
+            .map_err(|_| "Failed to send message: connection closed".to_string())?;
+        self.shared.sent.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Queues a message with a delivery class, which decides its fate if the connection is
+    /// shut down with `shutdown_with_grace` while it is still queued.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The frame to send.
+    /// * `delivery` - The message's delivery class.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if the connection has closed.
+    pub async fn send_with(&self, message: Message, delivery: Delivery) -> Result<(), String> {
+        self.sender
+            .send_with(message, delivery)
+            .await
+            .map_err(|_| "Failed to send message: connection closed".to_string())?;
+        self.shared.sent.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Waits for room in the outbound queue for one message; see
+    /// `PipelineSender::acquire_send_permit`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the permit, or an error message if the connection has closed.
+    pub async fn acquire_send_permit(&self) -> Result<SendPermit, String> {
+        self.sender
+            .acquire_send_permit()
+            .await
+            .map_err(|_| "Failed to acquire send permit: connection closed".to_string())
+    }
+
+    /// Queues a text message for sending.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The message text.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if the connection has closed.
+    pub async fn send_text(&self, text: impl Into<String>) -> Result<(), String> {
+        self.send(Message::Text(text.into())).await
+    }
+
+    /// Queues a binary message for sending.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The message payload.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if the connection has closed.
+    pub async fn send_binary(&self, payload: Vec<u8>) -> Result<(), String> {
+        self.send(Message::Binary(payload)).await
+    }
+
+    /// Encodes `envelope` in `format` and queues it in the format's conventional frame type:
+    /// a text message for JSON, a binary message for CBOR.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - The message to send.
+    /// * `format` - The wire format.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if encoding fails or the connection
+    /// has closed.
+    pub async fn send_envelope(&self, envelope: &Envelope, format: MessageFormat) -> Result<(), String> {
+        self.send(FrameKind::for_format(format).frame(envelope.encode(format)?)?).await
+    }
+
+    /// Waits for the next inbound message.
+    ///
+    /// When several clones wait at once, each message goes to exactly one of them.
+    ///
+    /// # Returns
+    ///
+    /// The next text, binary or close message, or `None` once the connection has ended.
+    pub async fn recv(&self) -> Option<Message> {
+        let lingered = self.shared.lingered.lock().unwrap().pop_front();
+        let message = match lingered {
+            Some(message) => message,
+            None => self.receiver.lock().await.recv().await?,
+        };
+        if !message.is_close() {
+            self.shared.received.fetch_add(1, Ordering::Relaxed);
+        }
+        Some(message)
+    }
+
+    /// Waits for the next data message.
+    ///
+    /// # Returns
+    ///
+    /// The next text or binary message, or `None` once the connection has closed or ended.
+    pub async fn recv_inbound(&self) -> Option<InboundMessage> {
+        InboundMessage::try_from(self.recv().await?).ok()
+    }
+
+    /// Asks the writer task to send a keep-alive ping.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if the connection has closed.
+    pub async fn ping(&self) -> Result<(), String> {
+        self.sender
+            .ping()
+            .await
+            .map_err(|_| "Failed to send ping: connection closed".to_string())
+    }
+
+    /// Verifies that the server still answers by pinging it; see `PipelineSender::probe`.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - How long to wait for the pong.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the time until the pong arrived, or an error message if none
+    /// arrived in time, in which case the connection is closed for every clone.
+    pub async fn probe(&self, timeout: Duration) -> Result<Duration, String> {
+        self.sender.probe(timeout).await
+    }
+
+    /// Sends a close frame; the connection ends once the server answers it.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if the connection has already closed.
+    pub async fn close(&self) -> Result<(), String> {
+        self.send(Message::Close(None)).await
+    }
+
+    /// Sets how this connection is closed, for every clone.
+    ///
+    /// # Arguments
+    ///
+    /// * `handshake` - The reply timeout, linger and summary settings.
+    pub fn set_close_handshake(&self, handshake: CloseHandshake) {
+        *self.shared.close_handshake.lock().unwrap() = handshake;
+    }
+
+    /// Returns how this connection is closed.
+    pub fn close_handshake(&self) -> CloseHandshake {
+        *self.shared.close_handshake.lock().unwrap()
+    }
+
+    /// Returns what went through the handle so far, as sent in the close summary.
+    pub fn summary(&self) -> CloseSummary {
+        CloseSummary {
+            sent: self.shared.sent.load(Ordering::Relaxed),
+            received: self.shared.received.load(Ordering::Relaxed),
+            uptime_ms: u64::try_from(self.shared.opened.elapsed().as_millis()).unwrap_or(u64::MAX),
+        }
+    }
+
+    /// Closes the connection for every clone: sends a close frame and waits up to the close
+    /// handshake's `reply_timeout` for the server to answer it, then stops the reader and
+    /// writer.
+    ///
+    /// Messages still queued before the close frame are sent first, preceded by the close
+    /// summary if the handshake asks for one. Inbound messages that nobody receives are
+    /// discarded unless the handshake lingers.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating a clean close, or an error message if the server did not answer
+    /// in time or the connection had already been shut down.
+    pub async fn shutdown(&self) -> Result<(), String> {
+        let tasks = self.shared.tasks.lock().unwrap().take();
+        let mut tasks = tasks.ok_or("Failed to shut down: connection already shut down")?;
+        self.shared.shut_down.store(true, Ordering::Release);
+        let handshake = self.close_handshake();
+        if let Some(summary) = self.summary_message(&handshake) {
+            let _ = self.sender.send(summary).await;
+        }
+        let _ = self.close().await;
+        let answered = tokio::time::timeout(handshake.reply_timeout, self.await_close_reply(handshake.linger))
+            .await
+            .is_ok();
+        tasks.abort();
+        let _ = (&mut tasks.reader).await;
+        debug!("Connection shut down");
+        if answered {
+            Ok(())
+        } else {
+            Err(format!("Failed to shut down: no close reply within {:?}", handshake.reply_timeout))
+        }
+    }
+
+    /// Encodes the close summary, if `handshake` asks for one.
+    fn summary_message(&self, handshake: &CloseHandshake) -> Option<Message> {
+        let format = handshake.summary?;
+        let encoded = Envelope::from_value(CLOSE_SUMMARY_KIND, &self.summary(), format)
+            .and_then(|envelope| envelope.encode(format))
+            .and_then(|payload| FrameKind::for_format(format).frame(payload));
+        match encoded {
+            Ok(message) => Some(message),
+            Err(e) => {
+                warn!("Failed to encode close summary: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Reads inbound messages until the close reply or the end of the connection, keeping
+    /// data messages for `recv` if `linger` is set.
+    async fn await_close_reply(&self, linger: bool) {
+        let mut receiver = self.receiver.lock().await;
+        while let Some(message) = receiver.recv().await {
+            if message.is_close() {
+                return;
+            }
+            if linger {
+                self.shared.lingered.lock().unwrap().push_back(message);
+            }
+        }
+    }
+
+    /// Closes the connection for every clone, sending queued messages by delivery class.
+    ///
+    /// Must-deliver messages are sent first and normal ones while `grace` lasts; best-effort
+    /// messages are discarded (see `PipelineSender::drain`). The close summary, if the close
+    /// handshake asks for one, is sent as a must-deliver message ahead of them. The close
+    /// frame follows, and the server's answer is awaited until `grace` has passed, lingering
+    /// as the close handshake says.
+    ///
+    /// # Arguments
+    ///
+    /// * `grace` - How long the shutdown may take.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing what each class delivered and discarded, or an error message
+    /// if the connection had already been shut down or its writer had stopped.
+    pub async fn shutdown_with_grace(&self, grace: Duration) -> Result<DrainReport, String> {
+        let deadline = tokio::time::Instant::now() + grace;
+        let tasks = self.shared.tasks.lock().unwrap().take();
+        let mut tasks = tasks.ok_or("Failed to shut down: connection already shut down")?;
+        self.shared.shut_down.store(true, Ordering::Release);
+        let handshake = self.close_handshake();
+        if let Some(summary) = self.summary_message(&handshake) {
+            let _ = self.sender.send_with(summary, Delivery::MustDeliver).await;
+        }
+        let report = self.sender.drain(grace).await;
+        if matches!(report, Ok(DrainReport { closed: true, .. })) {
+            let _ = tokio::time::timeout_at(deadline, self.await_close_reply(handshake.linger)).await;
+        }
+        tasks.abort();
+        let _ = (&mut tasks.reader).await;
+        debug!("Connection shut down");
+        report
+    }
+
+    /// Returns whether the writer task has stopped.
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    /// Returns the round-trip times measured from pings, if the pipeline tracks them.
+    pub fn rtt(&self) -> Option<RttStats> {
+        self.sender.rtt()
+    }
+
+    /// Returns how long the last ping has gone unanswered, if the pipeline tracks RTT.
+    pub fn ping_outstanding(&self) -> Option<Duration> {
+        self.sender.ping_outstanding()
+    }
+
+    /// Returns the underlying pipeline sender, e.g. for a `FlowControlledSender`.
+    pub fn sender(&self) -> &PipelineSender {
+        &self.sender
+    }
+
+    /// Aborts the reader and writer tasks, dropping the connection without a close handshake.
+    pub fn abort(&self) {
+        self.shared.shut_down.store(true, Ordering::Release);
+        if let Some(tasks) = self.shared.tasks.lock().unwrap().as_ref() {
+            tasks.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::WebSocketClient;
+    use crate::pipeline::{self, PipelineConfig};
+    use crate::testing::{EchoServer, MockServer};
+
+    /// Tests that clones can send from several tasks while another task receives.
+    #[tokio::test]
+    async fn test_clones_share_connection() {
+        let server = EchoServer::start().await.expect("Failed to start echo server");
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let (sender, receiver, tasks) = pipeline::spawn(ws_stream, PipelineConfig::default());
+        let handle = ConnectionHandle::new(sender, receiver, tasks);
+
+        let reader = handle.clone();
+        let received = tokio::spawn(async move {
+            let mut received = Vec::new();
+            while received.len() < 20 {
+                received.push(reader.recv_inbound().await.unwrap().into_bytes());
+            }
+            received
+        });
+        let senders: Vec<_> = (0..2u8)
+            .map(|task| {
+                let handle = handle.clone();
+                tokio::spawn(async move {
+                    for i in 0..10u8 {
+                        handle.send_binary(vec![task, i]).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        for sender in senders {
+            sender.await.unwrap();
+        }
+
+        let mut received = received.await.unwrap();
+        received.sort();
+        let expected: Vec<Vec<u8>> = (0..2u8).flat_map(|t| (0..10u8).map(move |i| vec![t, i])).collect();
+        assert_eq!(received, expected);
+        assert_eq!(handle.shutdown().await, Ok(()));
+        assert!(handle.shutdown().await.is_err());
+    }
+
+    /// Tests that dropping the last clone without `shutdown` still closes the connection.
+    #[tokio::test]
+    async fn test_drop_closes_connection() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let mut connection = server.accept().await;
+        let (sender, receiver, tasks) = pipeline::spawn(ws_stream, PipelineConfig::default());
+        let handle = ConnectionHandle::new(sender, receiver, tasks);
+
+        drop(handle.clone());
+        handle.send_text("last words").await.unwrap();
+        drop(handle);
+        connection.assert_next_message_eq(Message::Text("last words".to_string())).await;
+        assert!(connection.next_message().await.is_some_and(|message| message.is_close()));
+    }
+
+    /// Tests the close summary, lingering for in-flight messages, and the reply timeout.
+    #[tokio::test]
+    async fn test_close_handshake() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let mut connection = server.accept().await;
+        let (sender, receiver, tasks) = pipeline::spawn(ws_stream, PipelineConfig::default());
+        let handle = ConnectionHandle::new(sender, receiver, tasks);
+        handle.set_close_handshake(CloseHandshake { linger: true, summary: Some(MessageFormat::Json), ..CloseHandshake::default() });
+
+        handle.send_text("hello").await.unwrap();
+        connection.assert_next_message_eq(Message::Text("hello".to_string())).await;
+        connection.send(Message::Text("in flight".to_string())).await;
+        let (closed, ()) = tokio::join!(handle.shutdown(), async {
+            let summary = connection.next_message().await.unwrap().into_data();
+            let envelope = Envelope::decode(&summary, MessageFormat::Json).unwrap();
+            assert_eq!(envelope.kind, CLOSE_SUMMARY_KIND);
+            let summary: CloseSummary = envelope.payload_as(MessageFormat::Json).unwrap();
+            assert_eq!((summary.sent, summary.received), (1, 0));
+            assert!(connection.next_message().await.is_some_and(|message| message.is_close()));
+            connection.close(1000, "").await;
+        });
+        assert_eq!(closed, Ok(()));
+        assert_eq!(handle.recv_inbound().await.unwrap().as_bytes(), b"in flight");
+        assert!(handle.recv_inbound().await.is_none());
+
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let _silent = server.accept().await;
+        let (sender, receiver, tasks) = pipeline::spawn(ws_stream, PipelineConfig::default());
+        let handle = ConnectionHandle::new(sender, receiver, tasks);
+        handle.set_close_handshake(CloseHandshake { reply_timeout: Duration::from_millis(100), ..CloseHandshake::default() });
+        assert!(handle.shutdown().await.unwrap_err().contains("100ms"));
+    }
+}
@@ -0,0 +1,247 @@
+//! # `history.rs`: Recent connection events for post-mortems
+//!
+//! When a connection fails in production, the log rarely says what led up to it: debug
+//! logging is too expensive to leave on, and by the time an error is logged the frames that
+//! caused it are gone. `ConnectionHistory` keeps the last N lifecycle events and message
+//! summaries in a bounded in-memory ring instead. Recording is cheap (a summary holds a
+//! message's type, length and first `PREVIEW_LEN` bytes, never the whole payload), and the
+//! ring can be dumped on demand with `dump`, or automatically to the error log whenever the
+//! controller records an error (`with_dump_on_error`), so a crash report carries the recent
+//! WebSocket history.
+//!
+//! Set a history with `WebSocketController::set_history`; the controller then records its
+//! connects, disconnects, errors and every message it sends or receives directly.
+//! Pipelined connections do not pass their messages through the controller and record only
+//! their connect.
+
+use crate::violation::Direction;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio_tungstenite::tungstenite::Message;
+
+/// How many payload bytes a message summary keeps.
+pub const PREVIEW_LEN: usize = 32;
+
+/// A lifecycle event or message summary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistoryEvent {
+    /// A connection attempt started.
+    Connecting {
+        /// The URL connected to.
+        url: String,
+    },
+    /// The handshake completed.
+    Connected,
+    /// The connection ended or was disconnected.
+    Disconnected,
+    /// A message was sent or received.
+    Message {
+        /// Which way the message went.
+        direction: Direction,
+        /// The frame type, e.g. `text` or `close`.
+        frame: &'static str,
+        /// The payload length in bytes.
+        len: usize,
+        /// Up to `PREVIEW_LEN` bytes of the payload, lossily decoded and escaped.
+        preview: String,
+    },
+    /// An error occurred.
+    Error(String),
+}
+
+impl HistoryEvent {
+    /// Summarizes a message without keeping its payload.
+    ///
+    /// # Arguments
+    ///
+    /// * `direction` - Whether the message was sent or received.
+    /// * `message` - The message.
+    ///
+    /// # Returns
+    ///
+    /// A `HistoryEvent::Message`.
+    pub fn message(direction: Direction, message: &Message) -> Self {
+        let (frame, payload): (&'static str, &[u8]) = match message {
+            Message::Text(text) => ("text", text.as_bytes()),
+            Message::Binary(data) => ("binary", data),
+            Message::Ping(data) => ("ping", data),
+            Message::Pong(data) => ("pong", data),
+            Message::Close(Some(close)) => ("close", close.reason.as_bytes()),
+            Message::Close(None) => ("close", &[]),
+        };
+        let preview = String::from_utf8_lossy(&payload[..payload.len().min(PREVIEW_LEN)]).escape_debug().to_string();
+        HistoryEvent::Message { direction, frame, len: payload.len(), preview }
+    }
+
+    /// Returns whether the event is an error.
+    pub fn is_error(&self) -> bool {
+        matches!(self, HistoryEvent::Error(_))
+    }
+}
+
+impl fmt::Display for HistoryEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistoryEvent::Connecting { url } => write!(f, "connecting to {}", url),
+            HistoryEvent::Connected => write!(f, "connected"),
+            HistoryEvent::Disconnected => write!(f, "disconnected"),
+            HistoryEvent::Message { direction, frame, len, preview } => {
+                let arrow = match direction {
+                    Direction::Inbound => "<-",
+                    Direction::Outbound => "->",
+                };
+                write!(f, "{} {} ({} bytes) \"{}\"", arrow, frame, len, preview)
+            }
+            HistoryEvent::Error(error) => write!(f, "error: {}", error),
+        }
+    }
+}
+
+/// A recorded event and when it happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// When the event was recorded, in milliseconds since the Unix epoch.
+    pub at_ms: u64,
+    /// The event.
+    pub event: HistoryEvent,
+}
+
+/// A bounded ring of the most recent connection events.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use websocket_toolkit::controller::WebSocketController;
+/// use websocket_toolkit::history::{ConnectionHistory, HistoryEvent};
+///
+/// let history = Arc::new(ConnectionHistory::new(256).with_dump_on_error(true));
+/// let mut controller = WebSocketController::new("ws://example.com", 3, None);
+/// controller.set_history(Some(history.clone()));
+///
+/// history.record(HistoryEvent::Connected);
+/// assert!(history.dump().contains("connected"));
+/// ```
+#[derive(Debug)]
+pub struct ConnectionHistory {
+    capacity: usize,
+    dump_on_error: bool,
+    entries: Mutex<VecDeque<HistoryEntry>>,
+}
+
+impl ConnectionHistory {
+    /// Creates an empty history.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - The number of events kept; older ones are overwritten.
+    ///
+    /// # Returns
+    ///
+    /// A new `ConnectionHistory` that does not dump on errors.
+    pub fn new(capacity: usize) -> Self {
+        ConnectionHistory {
+            capacity: capacity.max(1),
+            dump_on_error: false,
+            entries: Mutex::new(VecDeque::with_capacity(capacity.max(1))),
+        }
+    }
+
+    /// Logs the history at error level whenever an error is recorded.
+    pub fn with_dump_on_error(mut self, dump_on_error: bool) -> Self {
+        self.dump_on_error = dump_on_error;
+        self
+    }
+
+    /// Returns whether the history is dumped when an error is recorded.
+    pub fn dumps_on_error(&self) -> bool {
+        self.dump_on_error
+    }
+
+    /// Records an event, overwriting the oldest one if the ring is full.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The event.
+    pub fn record(&self, event: HistoryEvent) {
+        let entry = HistoryEntry { at_ms: now_millis(), event };
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Returns the recorded events, oldest first.
+    pub fn entries(&self) -> Vec<HistoryEntry> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Returns the number of recorded events.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Returns whether no event has been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forgets every recorded event.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Formats the recorded events, oldest first, one per line.
+    ///
+    /// # Returns
+    ///
+    /// Lines of the form `<milliseconds since the epoch> <event>`.
+    pub fn dump(&self) -> String {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|entry| format!("{} {}\n", entry.at_ms, entry.event))
+            .collect()
+    }
+}
+
+/// Returns the current time in milliseconds since the Unix epoch.
+fn now_millis() -> u64 {
+    u64::try_from(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that the ring keeps the newest events and summaries stay short.
+    #[test]
+    fn test_ring_and_summaries() {
+        let history = ConnectionHistory::new(3);
+        history.record(HistoryEvent::Connecting { url: "ws://example.com".to_string() });
+        history.record(HistoryEvent::Connected);
+        history.record(HistoryEvent::message(Direction::Outbound, &Message::Text("a\n".repeat(40))));
+        history.record(HistoryEvent::message(Direction::Inbound, &Message::Binary(vec![0xff, b'o', b'k'])));
+
+        let entries = history.entries();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].event, HistoryEvent::Connected);
+        match &entries[1].event {
+            HistoryEvent::Message { frame, len, preview, .. } => {
+                assert_eq!((*frame, *len), ("text", 80));
+                assert_eq!(preview, &"a\\n".repeat(16));
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+        let dump = history.dump();
+        assert_eq!(dump.lines().count(), 3);
+        assert!(dump.lines().last().unwrap().ends_with("<- binary (3 bytes) \"\u{fffd}ok\""), "{}", dump);
+
+        history.clear();
+        assert!(history.is_empty());
+    }
+}
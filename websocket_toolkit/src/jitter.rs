@@ -0,0 +1,117 @@
+//! # `jitter.rs`: Randomized delays
+//!
+//! Thousands of devices that ping or reconnect on the same fixed schedule end up doing so in
+//! lockstep, which shows up as load spikes on the server and, on cellular links, as radios
+//! waking at the same moment. `jittered` spreads a delay randomly around its nominal value.
+//! The randomness comes from the standard library's per-hasher random keys, so no RNG
+//! dependency is needed; it is not suitable for anything security-related. Tests that need
+//! repeatable delays use a `SeededRandom` instead.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Returns `duration` scaled by a random factor in `[1 - fraction, 1 + fraction]`.
+///
+/// # Arguments
+///
+/// * `duration` - The nominal delay.
+/// * `fraction` - The maximum relative deviation, clamped to `0.0..=1.0`; `0.0` disables jitter.
+///
+/// # Returns
+///
+/// The randomized delay.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use websocket_toolkit::jitter::jittered;
+///
+/// let delay = jittered(Duration::from_secs(10), 0.2);
+/// assert!(delay >= Duration::from_secs(8) && delay <= Duration::from_secs(12));
+/// ```
+pub fn jittered(duration: Duration, fraction: f64) -> Duration {
+    let fraction = if fraction.is_nan() { 0.0 } else { fraction.clamp(0.0, 1.0) };
+    if fraction == 0.0 {
+        return duration;
+    }
+    duration.mul_f64(1.0 - fraction + 2.0 * fraction * unit_random())
+}
+
+/// Returns a pseudo-random number in `[0, 1]`.
+pub(crate) fn unit_random() -> f64 {
+    random_u64() as f64 / u64::MAX as f64
+}
+
+/// Returns a pseudo-random `u64`.
+pub(crate) fn random_u64() -> u64 {
+    // Every `RandomState` gets fresh keys, so hashing nothing still yields a new value.
+    RandomState::new().build_hasher().finish()
+}
+
+/// A deterministic pseudo-random sequence (SplitMix64), for reproducible jitter in tests.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::jitter::SeededRandom;
+///
+/// let (mut a, mut b) = (SeededRandom::new(7), SeededRandom::new(7));
+/// assert_eq!(a.next_unit(), b.next_unit());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeededRandom {
+    state: u64,
+}
+
+impl SeededRandom {
+    /// Creates a sequence starting from `seed`.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - Any value; equal seeds yield equal sequences.
+    ///
+    /// # Returns
+    ///
+    /// A new `SeededRandom`.
+    pub fn new(seed: u64) -> Self {
+        SeededRandom { state: seed }
+    }
+
+    /// Returns the next number in `[0, 1]`.
+    pub fn next_unit(&mut self) -> f64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        (z ^ (z >> 31)) as f64 / u64::MAX as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that jittered delays stay within bounds and actually vary.
+    #[test]
+    fn test_jittered_bounds() {
+        let nominal = Duration::from_millis(1000);
+        assert_eq!(jittered(nominal, 0.0), nominal);
+        let delays: Vec<Duration> = (0..100).map(|_| jittered(nominal, 0.5)).collect();
+        assert!(delays.iter().all(|d| *d >= Duration::from_millis(500) && *d <= Duration::from_millis(1500)));
+        assert!(delays.iter().any(|d| *d != delays[0]), "Expected delays to vary");
+    }
+
+    /// Tests that seeded sequences repeat per seed and stay within bounds.
+    #[test]
+    fn test_seeded_random() {
+        let sequence = |seed| {
+            let mut random = SeededRandom::new(seed);
+            (0..100).map(|_| random.next_unit()).collect::<Vec<f64>>()
+        };
+        assert_eq!(sequence(1), sequence(1));
+        assert_ne!(sequence(1), sequence(2));
+        assert!(sequence(3).iter().all(|unit| (0.0..=1.0).contains(unit)));
+    }
+}
@@ -0,0 +1,207 @@
+//! # `journal.rs`: Crash-safe inbound cursor for resuming after a restart
+//!
+//! Durable messages carry increasing sequence numbers as their `Envelope` ids (see the
+//! `outbox` module). `InboundJournal` records, in a `Storage` backend (a sled database with
+//! `InboundJournal::open`), the highest sequence number
+//! the application has finished processing. After a restart or reconnect the client sends
+//! `InboundJournal::resume_envelope`, a `resume` envelope carrying that cursor; the sending side
+//! answers it with `Outbox::handle_resume`, which drops everything up to the cursor and resends
+//! the rest. Committing the cursor only after a message has been applied gives end-to-end
+//! at-least-once processing: a crash between applying and committing replays that message,
+//! and nothing is skipped.
+//!
+//! Enabled by the `outbox` feature.
+
+use crate::messages::Envelope;
+use crate::storage::{MemoryStorage, SledStorage, Storage};
+use log::{debug, warn};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// The envelope kind of resume requests. The id is the last processed sequence number, or
+/// absent to request everything still pending.
+pub const RESUME_KIND: &str = "resume";
+
+/// The namespace the cursor is stored in.
+const JOURNAL_NAMESPACE: &str = "journal";
+
+/// The key the cursor is stored under.
+const CURSOR_KEY: &[u8] = b"cursor";
+
+/// A persistent record of the last processed inbound sequence number.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::journal::InboundJournal;
+/// use websocket_toolkit::messages::Envelope;
+///
+/// let journal = InboundJournal::temporary().unwrap();
+/// let message = Envelope::new("reading", vec![1]).with_id("41");
+/// // ... apply the message, then:
+/// journal.commit_envelope(&message).unwrap();
+///
+/// assert_eq!(journal.resume_envelope().unwrap().id.as_deref(), Some("41"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct InboundJournal {
+    storage: Arc<dyn Storage>,
+    /// Held while committing so concurrent commits cannot move the cursor backwards.
+    commit_lock: Arc<Mutex<()>>,
+}
+
+impl InboundJournal {
+    /// Opens (or creates) the journal database at `path`, keeping the recorded cursor.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory of the sled database.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the journal, or an error message if the database cannot be opened.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let storage = SledStorage::open(path).map_err(|e| format!("Failed to open journal: {}", e))?;
+        Ok(Self::with_storage(Arc::new(storage)))
+    }
+
+    /// Creates a journal that keeps its cursor in memory only, for tests.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the journal, or an error message on failure.
+    pub fn temporary() -> Result<Self, String> {
+        Ok(Self::with_storage(Arc::new(MemoryStorage::new())))
+    }
+
+    /// Creates a journal on `storage`, keeping the cursor already recorded there.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - The persistence backend; it may be shared with other components.
+    ///
+    /// # Returns
+    ///
+    /// A new `InboundJournal`.
+    pub fn with_storage(storage: Arc<dyn Storage>) -> Self {
+        InboundJournal {
+            storage,
+            commit_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Returns the last committed sequence number.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the cursor, `None` if nothing has been committed, or an error
+    /// message on failure.
+    pub fn cursor(&self) -> Result<Option<u64>, String> {
+        let stored = self
+            .storage
+            .get(JOURNAL_NAMESPACE, CURSOR_KEY)
+            .map_err(|e| format!("Failed to read journal: {}", e))?;
+        Ok(stored.and_then(|bytes| <[u8; 8]>::try_from(bytes.as_slice()).ok()).map(u64::from_be_bytes))
+    }
+
+    /// Records that every message up to `sequence` has been processed.
+    ///
+    /// Returns only once the cursor has been flushed. A `sequence` below the current cursor
+    /// leaves it unchanged.
+    ///
+    /// # Arguments
+    ///
+    /// * `sequence` - The sequence number of the message just processed.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message on failure.
+    pub fn commit(&self, sequence: u64) -> Result<(), String> {
+        let storage_error = |e: String| format!("Failed to update journal: {}", e);
+        let _guard = self.commit_lock.lock().unwrap();
+        if self.cursor()?.is_some_and(|cursor| cursor >= sequence) {
+            return Ok(());
+        }
+        self.storage
+            .put(JOURNAL_NAMESPACE, CURSOR_KEY, &sequence.to_be_bytes())
+            .map_err(storage_error)?;
+        self.storage.flush().map_err(storage_error)?;
+        debug!("Journal cursor committed at {}", sequence);
+        Ok(())
+    }
+
+    /// Commits the sequence number carried in `envelope`'s id.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - A processed durable message.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `false` if the envelope has no numeric id, or an error message on
+    /// failure.
+    pub fn commit_envelope(&self, envelope: &Envelope) -> Result<bool, String> {
+        match envelope.id.as_deref().map(str::parse::<u64>) {
+            Some(Ok(sequence)) => self.commit(sequence).map(|_| true),
+            _ => {
+                warn!("Not journaling message without a sequence number: {:?}", envelope.id);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Returns whether the message with `sequence` was already processed before a replay.
+    ///
+    /// # Arguments
+    ///
+    /// * `sequence` - The sequence number of a received message.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `true` if `sequence` is at or below the cursor, or an error message
+    /// on failure.
+    pub fn is_processed(&self, sequence: u64) -> Result<bool, String> {
+        Ok(self.cursor()?.is_some_and(|cursor| sequence <= cursor))
+    }
+
+    /// Builds the resume request to send after connecting.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing an envelope of kind `RESUME_KIND` whose id is the cursor, or an
+    /// error message on failure.
+    pub fn resume_envelope(&self) -> Result<Envelope, String> {
+        let envelope = Envelope::new(RESUME_KIND, Vec::new());
+        Ok(match self.cursor()? {
+            Some(cursor) => envelope.with_id(cursor.to_string()),
+            None => envelope,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that the cursor only moves forward and survives reopening the database.
+    #[test]
+    fn test_cursor_is_monotonic_and_durable() {
+        let dir = std::env::temp_dir().join(format!("wstk-journal-test-{}", std::process::id()));
+        {
+            let journal = InboundJournal::open(&dir).unwrap();
+            assert_eq!(journal.cursor().unwrap(), None);
+            assert!(journal.resume_envelope().unwrap().id.is_none());
+            journal.commit(5).unwrap();
+            journal.commit(3).unwrap();
+            assert!(!journal.commit_envelope(&Envelope::new("chat", Vec::new())).unwrap());
+        }
+
+        let journal = InboundJournal::open(&dir).unwrap();
+        assert_eq!(journal.cursor().unwrap(), Some(5));
+        assert!(journal.is_processed(4).unwrap());
+        assert!(!journal.is_processed(6).unwrap());
+        assert_eq!(journal.resume_envelope().unwrap().kind, RESUME_KIND);
+        drop(journal);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,583 @@
+//! # `jsonrpc.rs`: JSON-RPC 2.0 client
+//!
+//! `JsonRpcClient` speaks JSON-RPC 2.0 over a pipelined connection:
+//!
+//! - `call` sends a request and deserializes the matching response's `result` into the
+//!   caller's type, or returns the server's error object. Any number of calls may be in
+//!   flight at once; responses are matched by id, in whatever order they arrive.
+//! - `notify` sends a notification, which the server does not answer.
+//! - `batch` collects calls and notifications into one `JsonRpcBatch`, sent as a single
+//!   array by `send_batch`, whose results come back in the order the calls were added.
+//! - Notifications from the server are dispatched to the handler registered for their
+//!   method with `on_notification`. Requests from the server are answered with the
+//!   "method not found" error, as this client exposes no methods.
+//!
+//! Requests are sent as text frames. Params must serialize to a JSON array or object, as the
+//! specification requires; `()` sends no params at all.
+
+use crate::controller::WebSocketController;
+use crate::handle::ConnectionHandle;
+use crate::pipeline::PipelineConfig;
+use crate::tasks::spawn_named;
+use log::{debug, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio_tungstenite::tungstenite::Message;
+
+/// How long `call` and `send_batch` wait for responses unless told otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The error code for a method the receiver does not implement.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+
+/// A handler for server notifications of one method, given the notification's params.
+pub type NotificationHandler = Arc<dyn Fn(Option<Value>) + Send + Sync>;
+
+/// The error object of a JSON-RPC response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonRpcError {
+    /// The error code; -32768 to -32000 are reserved for protocol errors.
+    pub code: i64,
+    /// A short description of the error.
+    pub message: String,
+    /// Additional information from the server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+/// Why a call failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RpcError {
+    /// The server answered with an error object.
+    Server(JsonRpcError),
+    /// No response arrived within the timeout.
+    Timeout {
+        /// The method called, or `"batch"`.
+        method: String,
+        /// The timeout that elapsed.
+        timeout: Duration,
+    },
+    /// The connection closed before the response arrived.
+    Closed,
+    /// The params could not be serialized, or the result did not match the expected type.
+    Serialization(String),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Server(e) => write!(f, "server error {}: {}", e.code, e.message),
+            RpcError::Timeout { method, timeout } => write!(f, "{} timed out after {:?}", method, timeout),
+            RpcError::Closed => write!(f, "connection closed before the response arrived"),
+            RpcError::Serialization(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl StdError for RpcError {}
+
+/// A JSON-RPC response: either `result` or `error` is set.
+#[derive(Debug, Deserialize)]
+struct Response {
+    #[serde(default)]
+    result: Value,
+    #[serde(default)]
+    error: Option<JsonRpcError>,
+}
+
+impl Response {
+    fn into_result(self) -> Result<Value, RpcError> {
+        match self.error {
+            Some(error) => Err(RpcError::Server(error)),
+            None => Ok(self.result),
+        }
+    }
+}
+
+/// A JSON-RPC 2.0 client over one connection. Clones share the connection, the calls in
+/// flight and the notification handlers.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use websocket_toolkit::controller::WebSocketController;
+/// use websocket_toolkit::jsonrpc::JsonRpcClient;
+/// use websocket_toolkit::pipeline::PipelineConfig;
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let controller = WebSocketController::new("wss://rpc.example.com", 3, None);
+/// let client = JsonRpcClient::connect(&controller, PipelineConfig::default()).await?;
+/// client.on_notification("newHeads", |params| println!("new block: {:?}", params));
+///
+/// let block: String = client.call("eth_blockNumber", ()).await?;
+/// client.notify("log", ["connected"]).await?;
+///
+/// let mut batch = client.batch();
+/// let first = batch.call("add", [1, 2])?;
+/// batch.call("add", [3, 4])?;
+/// let results = client.send_batch(batch).await?;
+/// let sum: u64 = serde_json::from_value(results[first].clone()?)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct JsonRpcClient {
+    handle: ConnectionHandle,
+    shared: Arc<Shared>,
+    timeout: Duration,
+}
+
+/// State shared with the reader task.
+struct Shared {
+    pending: Mutex<HashMap<u64, oneshot::Sender<Response>>>,
+    next_id: AtomicU64,
+    handlers: RwLock<HashMap<String, NotificationHandler>>,
+}
+
+impl JsonRpcClient {
+    /// Opens a connection with `controller` and starts reading responses on it.
+    ///
+    /// # Arguments
+    ///
+    /// * `controller` - Opens the connection, with its URL, headers, session, auth and limits.
+    /// * `config` - The pipeline settings of the connection.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the client, or a boxed error if the connection fails.
+    pub async fn connect(controller: &WebSocketController, config: PipelineConfig) -> Result<Self, Box<dyn StdError>> {
+        Ok(Self::new(controller.connect_handle(config).await?))
+    }
+
+    /// Starts a client on an open connection. The client takes every message received on
+    /// `handle` from now on.
+    ///
+    /// Must be called within a tokio runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `handle` - The connection to the JSON-RPC server.
+    ///
+    /// # Returns
+    ///
+    /// A new `JsonRpcClient` with the `DEFAULT_TIMEOUT`.
+    pub fn new(handle: ConnectionHandle) -> Self {
+        let shared = Arc::new(Shared {
+            pending: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            handlers: RwLock::new(HashMap::new()),
+        });
+        spawn_named("websocket_toolkit::jsonrpc_reader", read_messages(handle.clone(), Arc::downgrade(&shared)));
+        JsonRpcClient { handle, shared, timeout: DEFAULT_TIMEOUT }
+    }
+
+    /// Sets how long `call` and `send_batch` wait for responses.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The time to wait, including the time to queue the request.
+    ///
+    /// # Returns
+    ///
+    /// The client with the timeout set.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Registers the handler for server notifications of `method`, replacing any earlier one.
+    /// Notifications without a handler are logged and dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The notification method.
+    /// * `handler` - Called on the reader task with the notification's params; hand slow work
+    ///   off to another task.
+    pub fn on_notification<F>(&self, method: impl Into<String>, handler: F)
+    where
+        F: Fn(Option<Value>) + Send + Sync + 'static,
+    {
+        self.shared.handlers.write().unwrap().insert(method.into(), Arc::new(handler));
+    }
+
+    /// Calls `method` and deserializes its result.
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The method to call.
+    /// * `params` - The params, serialized to an array or object; `()` for none.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the result, or the server's error, a timeout, a closed
+    /// connection or a serialization failure.
+    pub async fn call<P: Serialize, R: DeserializeOwned>(&self, method: &str, params: P) -> Result<R, RpcError> {
+        let id = self.next_id();
+        let request = request_object(Some(id), method, params)?;
+        let mut responses = self.exchange(&[id], &request, method).await?;
+        let result = responses.remove(0)?;
+        serde_json::from_value(result)
+            .map_err(|e| RpcError::Serialization(format!("Failed to deserialize {} result: {}", method, e)))
+    }
+
+    /// Sends a notification, which the server does not answer.
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The method to notify.
+    /// * `params` - The params, serialized to an array or object; `()` for none.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the notification was queued, or why it was not.
+    pub async fn notify<P: Serialize>(&self, method: &str, params: P) -> Result<(), RpcError> {
+        self.send(&request_object(None, method, params)?).await
+    }
+
+    /// Starts a batch of calls and notifications, sent together with `send_batch`.
+    pub fn batch(&self) -> JsonRpcBatch {
+        JsonRpcBatch { requests: Vec::new(), ids: Vec::new(), shared: self.shared.clone() }
+    }
+
+    /// Sends a batch and waits for all of its responses.
+    ///
+    /// # Arguments
+    ///
+    /// * `batch` - The calls and notifications, from `batch`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing one result per call, in the order the calls were added, or an
+    /// error if the batch could not be sent or not every response arrived in time. An empty
+    /// batch or one with only notifications returns no results.
+    pub async fn send_batch(&self, batch: JsonRpcBatch) -> Result<Vec<Result<Value, RpcError>>, RpcError> {
+        if batch.requests.is_empty() {
+            return Ok(Vec::new());
+        }
+        let request = Value::Array(batch.requests);
+        if batch.ids.is_empty() {
+            self.send(&request).await?;
+            return Ok(Vec::new());
+        }
+        self.exchange(&batch.ids, &request, "batch").await
+    }
+
+    /// Returns the number of calls awaiting a response.
+    pub fn in_flight(&self) -> usize {
+        self.shared.pending.lock().unwrap().len()
+    }
+
+    /// Returns the connection the client uses.
+    pub fn handle(&self) -> &ConnectionHandle {
+        &self.handle
+    }
+
+    fn next_id(&self) -> u64 {
+        self.shared.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Sends `request` and waits for the responses to `ids`, in that order.
+    async fn exchange(&self, ids: &[u64], request: &Value, method: &str) -> Result<Vec<Result<Value, RpcError>>, RpcError> {
+        let mut receivers = Vec::with_capacity(ids.len());
+        {
+            let mut pending = self.shared.pending.lock().unwrap();
+            for &id in ids {
+                let (response_tx, response) = oneshot::channel();
+                pending.insert(id, response_tx);
+                receivers.push(response);
+            }
+        }
+        let _pending = PendingGuard { shared: &self.shared, ids };
+        let exchange = async {
+            self.send(request).await?;
+            let mut results = Vec::with_capacity(receivers.len());
+            for response in receivers {
+                results.push(response.await.map_err(|_| RpcError::Closed)?.into_result());
+            }
+            Ok(results)
+        };
+        tokio::time::timeout(self.timeout, exchange)
+            .await
+            .unwrap_or_else(|_| Err(RpcError::Timeout { method: method.to_string(), timeout: self.timeout }))
+    }
+
+    async fn send(&self, request: &Value) -> Result<(), RpcError> {
+        self.handle.send(Message::Text(request.to_string())).await.map_err(|_| RpcError::Closed)
+    }
+}
+
+impl fmt::Debug for JsonRpcClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JsonRpcClient")
+            .field("in_flight", &self.in_flight())
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+/// Calls and notifications to send as one JSON-RPC batch, from `JsonRpcClient::batch`.
+pub struct JsonRpcBatch {
+    requests: Vec<Value>,
+    ids: Vec<u64>,
+    shared: Arc<Shared>,
+}
+
+impl JsonRpcBatch {
+    /// Adds a call.
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The method to call.
+    /// * `params` - The params, serialized to an array or object; `()` for none.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the index of the call's result in `send_batch`'s output, or an
+    /// error if the params cannot be serialized.
+    pub fn call<P: Serialize>(&mut self, method: &str, params: P) -> Result<usize, RpcError> {
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        self.requests.push(request_object(Some(id), method, params)?);
+        self.ids.push(id);
+        Ok(self.ids.len() - 1)
+    }
+
+    /// Adds a notification.
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The method to notify.
+    /// * `params` - The params, serialized to an array or object; `()` for none.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error if the params cannot be serialized.
+    pub fn notify<P: Serialize>(&mut self, method: &str, params: P) -> Result<(), RpcError> {
+        self.requests.push(request_object(None, method, params)?);
+        Ok(())
+    }
+
+    /// Returns the number of calls and notifications in the batch.
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// Returns whether the batch is empty.
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+}
+
+impl fmt::Debug for JsonRpcBatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JsonRpcBatch").field("requests", &self.requests).finish()
+    }
+}
+
+/// Removes calls from the pending table when they complete, fail or are cancelled.
+struct PendingGuard<'a> {
+    shared: &'a Shared,
+    ids: &'a [u64],
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        let mut pending = self.shared.pending.lock().unwrap();
+        for id in self.ids {
+            pending.remove(id);
+        }
+    }
+}
+
+/// Builds a request, or a notification without `id`.
+fn request_object<P: Serialize>(id: Option<u64>, method: &str, params: P) -> Result<Value, RpcError> {
+    let params = serde_json::to_value(params)
+        .map_err(|e| RpcError::Serialization(format!("Failed to serialize {} params: {}", method, e)))?;
+    let mut request = json!({ "jsonrpc": "2.0", "method": method });
+    match params {
+        Value::Null => {}
+        Value::Array(_) | Value::Object(_) => {
+            request["params"] = params;
+        }
+        _ => {
+            return Err(RpcError::Serialization(format!(
+                "Failed to serialize {} params: params must be an array or object",
+                method
+            )))
+        }
+    }
+    if let Some(id) = id {
+        request["id"] = id.into();
+    }
+    Ok(request)
+}
+
+/// Dispatches responses, notifications and server requests until the connection ends.
+async fn read_messages(handle: ConnectionHandle, shared: Weak<Shared>) {
+    while let Some(message) = handle.recv_inbound().await {
+        let Some(shared) = shared.upgrade() else { return };
+        let value: Value = match serde_json::from_slice(message.as_bytes()) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Ignoring a message that is not JSON-RPC: {}", e);
+                continue;
+            }
+        };
+        let messages = match value {
+            Value::Array(messages) => messages,
+            single => vec![single],
+        };
+        for message in messages {
+            if let Some(reply) = dispatch(&shared, message) {
+                let _ = handle.send(Message::Text(reply.to_string())).await;
+            }
+        }
+    }
+    // Dropping the senders fails the calls still in flight with `RpcError::Closed`.
+    if let Some(shared) = shared.upgrade() {
+        shared.pending.lock().unwrap().clear();
+    }
+}
+
+/// Handles one received object, returning the reply to send, if any.
+fn dispatch(shared: &Shared, mut message: Value) -> Option<Value> {
+    let id = message.get_mut("id").map(Value::take).filter(|id| !id.is_null());
+    match message.get("method").and_then(Value::as_str) {
+        Some(method) => {
+            if let Some(id) = id {
+                debug!("Rejecting server request {}", method);
+                let error = JsonRpcError { code: METHOD_NOT_FOUND, message: "Method not found".to_string(), data: None };
+                return Some(json!({ "jsonrpc": "2.0", "id": id, "error": error }));
+            }
+            let handler = shared.handlers.read().unwrap().get(method).cloned();
+            match handler {
+                Some(handler) => handler(message.get_mut("params").map(Value::take)),
+                None => debug!("No handler for notification {}", method),
+            }
+        }
+        None => {
+            let waiter = id.as_ref().and_then(Value::as_u64).and_then(|id| shared.pending.lock().unwrap().remove(&id));
+            match (waiter, serde_json::from_value::<Response>(message)) {
+                (Some(waiter), Ok(response)) => {
+                    let _ = waiter.send(response);
+                }
+                (None, Ok(response)) => debug!("Dropping response {:?} to no pending call: {:?}", id, response.error),
+                (_, Err(e)) => warn!("Ignoring a malformed JSON-RPC response: {}", e),
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockServer;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Reads the next request the server received.
+    async fn next_request(connection: &mut crate::testing::TestHandle<tokio::net::TcpStream>) -> Value {
+        match connection.next_message().await {
+            Some(Message::Text(text)) => serde_json::from_str(&text).unwrap(),
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    /// Tests calls answered out of order, errors, notifications both ways, server requests
+    /// and batches.
+    #[tokio::test]
+    async fn test_jsonrpc_client() {
+        let mut server = MockServer::start().await.unwrap();
+        let controller = WebSocketController::new(server.url(), 0, None);
+        let client = JsonRpcClient::connect(&controller, PipelineConfig::default()).await.unwrap();
+        let mut connection = server.accept().await;
+        let heads = Arc::new(AtomicUsize::new(0));
+        let counter = heads.clone();
+        client.on_notification("newHeads", move |params| {
+            assert_eq!(params, Some(json!({ "number": 7 })));
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let first = tokio::spawn({
+            let client = client.clone();
+            async move { client.call::<_, u64>("add", [1, 2]).await }
+        });
+        let request = next_request(&mut connection).await;
+        assert_eq!(request["method"], "add");
+        assert_eq!(request["params"], json!([1, 2]));
+        let second = tokio::spawn({
+            let client = client.clone();
+            async move { client.call::<_, u64>("fail", ()).await }
+        });
+        let failing = next_request(&mut connection).await;
+        assert!(failing.get("params").is_none());
+
+        connection
+            .send(Message::Text(json!({ "jsonrpc": "2.0", "id": failing["id"], "error": { "code": -32000, "message": "nope" } }).to_string()))
+            .await;
+        connection.send(Message::Text(json!({ "jsonrpc": "2.0", "id": request["id"], "result": 3 }).to_string())).await;
+        assert_eq!(first.await.unwrap(), Ok(3));
+        assert!(matches!(second.await.unwrap(), Err(RpcError::Server(JsonRpcError { code: -32000, .. }))));
+
+        connection.send(Message::Text(json!({ "jsonrpc": "2.0", "method": "newHeads", "params": { "number": 7 } }).to_string())).await;
+        connection.send(Message::Text(json!({ "jsonrpc": "2.0", "id": "s1", "method": "ping" }).to_string())).await;
+        let reply = next_request(&mut connection).await;
+        assert_eq!((reply["id"].clone(), reply["error"]["code"].clone()), (json!("s1"), json!(METHOD_NOT_FOUND)));
+        assert_eq!(heads.load(Ordering::SeqCst), 1);
+
+        client.notify("log", json!({ "level": "info" })).await.unwrap();
+        let notification = next_request(&mut connection).await;
+        assert!(notification.get("id").is_none());
+        assert!(client.notify("log", 5).await.is_err(), "Expected scalar params to be rejected");
+
+        let mut batch = client.batch();
+        assert_eq!(batch.call("add", [1, 1]).unwrap(), 0);
+        batch.notify("log", ["batched"]).unwrap();
+        assert_eq!(batch.call("add", [2, 2]).unwrap(), 1);
+        let results = tokio::spawn({
+            let client = client.clone();
+            async move { client.send_batch(batch).await }
+        });
+        let requests = next_request(&mut connection).await;
+        let ids: Vec<Value> = requests.as_array().unwrap().iter().filter_map(|r| r.get("id").cloned()).collect();
+        assert_eq!(ids.len(), 2);
+        connection
+            .send(Message::Text(json!([{ "jsonrpc": "2.0", "id": ids[1], "result": 4 }, { "jsonrpc": "2.0", "id": ids[0], "result": 2 }]).to_string()))
+            .await;
+        assert_eq!(results.await.unwrap().unwrap(), vec![Ok(json!(2)), Ok(json!(4))]);
+        assert_eq!(client.in_flight(), 0);
+    }
+
+    /// Tests that calls time out and fail once the connection closes.
+    #[tokio::test]
+    async fn test_jsonrpc_timeout_and_close() {
+        let mut server = MockServer::start().await.unwrap();
+        let controller = WebSocketController::new(server.url(), 0, None);
+        let client = JsonRpcClient::connect(&controller, PipelineConfig::default())
+            .await
+            .unwrap()
+            .with_timeout(Duration::from_millis(50));
+        let mut connection = server.accept().await;
+
+        let error = client.call::<_, Value>("slow", ()).await.unwrap_err();
+        assert!(matches!(error, RpcError::Timeout { .. }), "Unexpected error: {}", error);
+        assert_eq!(client.in_flight(), 0);
+
+        let pending = tokio::spawn({
+            let client = client.clone().with_timeout(DEFAULT_TIMEOUT);
+            async move { client.call::<_, Value>("never", ()).await }
+        });
+        next_request(&mut connection).await;
+        next_request(&mut connection).await;
+        connection.close(1000, "bye").await;
+        assert_eq!(pending.await.unwrap(), Err(RpcError::Closed));
+    }
+}
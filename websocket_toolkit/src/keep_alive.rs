@@ -1,9 +1,12 @@
-use tokio::time::{interval, Duration};
-use log::{info, error};
+use tokio::time::{interval, Duration, Interval};
+use log::{debug, info, error};
 use tokio_tungstenite::{WebSocketStream, MaybeTlsStream};
 use tokio_tungstenite::tungstenite::protocol::Message;
 use tokio::net::TcpStream;
 use futures_util::sink::SinkExt;
+use crate::pipeline::{PipelineSender, PING_PAYLOAD};
+use crate::jitter::jittered;
+use crate::wake::WakeProbe;
 
 /// The `KeepAlive` struct is responsible for maintaining WebSocket connections
 /// by periodically sending ping messages to the server.
@@ -14,6 +17,10 @@ use futures_util::sink::SinkExt;
 pub struct KeepAlive {
     /// The interval at which ping messages are sent to keep the connection alive.
     ping_interval: Duration,
+    /// The maximum relative deviation of each interval; 0 for fixed intervals.
+    jitter: f64,
+    /// Probes the connection as soon as the machine resumes from sleep, if set.
+    wake: Option<WakeProbe>,
 }
 
 impl KeepAlive {
@@ -36,12 +43,66 @@ impl KeepAlive {
     /// let keep_alive = KeepAlive::new(Duration::from_secs(10));
     /// ```
     pub fn new(ping_interval: Duration) -> Self {
-        KeepAlive { ping_interval }
+        KeepAlive {
+            ping_interval,
+            jitter: 0.0,
+            wake: None,
+        }
+    }
+
+    /// Randomizes each interval by up to `fraction` of the ping interval in either direction,
+    /// so a fleet of devices does not ping in lockstep.
+    ///
+    /// # Arguments
+    ///
+    /// * `fraction` - The maximum relative deviation, clamped to `0.0..=1.0`.
+    ///
+    /// # Returns
+    ///
+    /// The `KeepAlive` with jitter enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit::keep_alive::KeepAlive;
+    /// use std::time::Duration;
+    ///
+    /// // Pings every 4 to 6 minutes.
+    /// let keep_alive = KeepAlive::new(Duration::from_secs(300)).with_jitter(0.2);
+    /// ```
+    pub fn with_jitter(mut self, fraction: f64) -> Self {
+        self.jitter = fraction;
+        self
+    }
+
+    /// Makes `run` probe the connection as soon as the machine resumes from sleep, instead
+    /// of trusting it until the next ping; see the `wake` module.
+    ///
+    /// # Arguments
+    ///
+    /// * `wake` - How a wake is detected and how long its probe waits for the pong.
+    ///
+    /// # Returns
+    ///
+    /// The `KeepAlive` with wake detection enabled.
+    pub fn with_wake_probe(mut self, wake: WakeProbe) -> Self {
+        self.wake = Some(wake);
+        self
+    }
+
+    /// Waits until the next ping is due.
+    async fn wait(&self, interval: &mut Interval) {
+        if self.jitter > 0.0 {
+            tokio::time::sleep(jittered(self.ping_interval, self.jitter)).await;
+        } else {
+            interval.tick().await;
+        }
     }
 
     /// Starts sending pings to keep the WebSocket connection alive.
     ///
-    /// This method runs indefinitely, sending ping messages at the configured interval.
+    /// This method needs exclusive access to the stream; for a connection shared between
+    /// tasks, use `run` with the connection's pipeline instead. It runs indefinitely, sending ping messages at the configured interval.
     /// If a ping fails to send, the method returns an error.
     ///
     /// # Arguments
@@ -55,14 +116,13 @@ impl KeepAlive {
     /// # Errors
     ///
     /// Returns an error if sending a ping message fails.
-    
     pub async fn start(&self, ws_stream: &mut WebSocketStream<MaybeTlsStream<TcpStream>>) -> Result<(), String> {
         let mut interval = interval(self.ping_interval);
 
         loop {
-            interval.tick().await;
+            self.wait(&mut interval).await;
 
-            match ws_stream.send(Message::Ping(vec![])).await {
+            match ws_stream.send(Message::Ping(PING_PAYLOAD)).await {
                 Ok(_) => info!("Ping sent to keep connection alive"),
                 Err(e) => {
                     error!("Failed to send ping: {}", e);
@@ -71,6 +131,56 @@ impl KeepAlive {
             }
         }
     }
+
+    /// Sends pings through a pipeline's writer task to keep the connection alive.
+    ///
+    /// Pings are queued like any other outbound message, so the stream is never locked and
+    /// each ping costs no allocation. This method runs until the writer task stops. With
+    /// `with_wake_probe`, a resume from sleep triggers an immediate probe, and a probe left
+    /// unanswered closes the connection and stops this method.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - The sending half of the connection's pipeline.
+    ///
+    /// # Returns
+    ///
+    /// A `Result<(), String>` - Returns an error message once the writer task has stopped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the ping cannot be queued because the writer task has stopped,
+    /// or if the probe after a wake went unanswered.
+    pub async fn run(&self, sender: &PipelineSender) -> Result<(), String> {
+        let mut interval = interval(self.ping_interval);
+
+        loop {
+            let slept = match &self.wake {
+                // Checked first: after a wake the ping is usually due as well.
+                Some(wake) => tokio::select! {
+                    biased;
+                    slept = wake.wait_for_wake() => Some(slept),
+                    _ = self.wait(&mut interval) => None,
+                },
+                None => {
+                    self.wait(&mut interval).await;
+                    None
+                }
+            };
+            if let (Some(slept), Some(wake)) = (slept, &self.wake) {
+                info!("Resumed after about {:?} of sleep; probing the connection", slept);
+                let rtt = sender.probe(wake.probe_timeout).await?;
+                debug!("Connection survived sleep; pong after {:?}", rtt);
+                continue;
+            }
+
+            if sender.ping().await.is_err() {
+                error!("Failed to queue ping: writer task stopped");
+                return Err("Failed to queue ping: writer task stopped".to_string());
+            }
+            debug!("Ping queued to keep connection alive");
+        }
+    }
 }
 
 #[cfg(test)]
@@ -90,4 +200,24 @@ mod tests {
         let keep_alive = KeepAlive::new(Duration::from_secs(10));
         assert_eq!(keep_alive.ping_interval, Duration::from_secs(10));
     }
+
+    /// Tests that `run` pings through the pipeline and stops with the writer task.
+    #[tokio::test]
+    async fn test_keep_alive_run_through_pipeline() {
+        let mut server = crate::testing::MockServer::start().await.expect("Failed to start mock server");
+        let ws_stream = crate::connection::WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let mut connection = server.accept().await;
+        let (sender, _receiver, tasks) = crate::pipeline::spawn(ws_stream, Default::default());
+
+        let keep_alive = KeepAlive::new(Duration::from_millis(10));
+        let pinger = sender.clone();
+        let run = tokio::spawn(async move { keep_alive.run(&pinger).await });
+        for _ in 0..2 {
+            assert_eq!(connection.next_frame().await, Some(Message::Ping(Vec::new())));
+        }
+
+        tasks.writer.abort();
+        let result = timeout(Duration::from_secs(1), run).await.expect("Expected run to stop").unwrap();
+        assert!(result.is_err());
+    }
 }
@@ -0,0 +1,168 @@
+//! # `latency.rs`: Send and receive timestamps for latency tracking
+//!
+//! Service-level objectives for feeds are usually stated as one-way latency: the time from
+//! when the sender emitted a message to when the receiver got it. With `Timestamps` set on
+//! a controller, `encode_envelope` stamps every outbound envelope with its send time in the
+//! `sent_at` header, and `decode_envelope` annotates every inbound envelope with its receive
+//! time in the `received_at` header, both in microseconds since the Unix epoch by the
+//! respective clock.
+//!
+//! `WebSocketController::latency` (or `Timestamps::latency`) reads both back as a `Latency`.
+//! The two stamps come from different clocks, so the one-way latency is only computed when a
+//! `TimeSync` estimates the server's clock; the send time is then translated to the local
+//! clock first. Without an estimate, `Latency::one_way` is `None`.
+
+use crate::messages::Envelope;
+use crate::timesync::TimeSync;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The header carrying the send time.
+pub const SENT_AT: &str = "sent_at";
+
+/// The header carrying the receive time.
+pub const RECEIVED_AT: &str = "received_at";
+
+/// Stamps outbound envelopes and annotates inbound ones.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::latency::Timestamps;
+/// use websocket_toolkit::messages::Envelope;
+///
+/// let timestamps = Timestamps::new();
+/// let sent = timestamps.stamp(Envelope::new("quote", Vec::new()));
+/// // On the receiving side:
+/// let received = timestamps.annotate(sent);
+/// let latency = timestamps.latency(&received).unwrap();
+/// assert!(latency.received_at >= latency.sent_at);
+/// assert_eq!(latency.one_way, None); // no clock estimate
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Timestamps {
+    time_sync: Option<Arc<TimeSync>>,
+}
+
+/// The timestamps of a received envelope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Latency {
+    /// When the envelope was sent, by the sender's clock, in microseconds since the Unix epoch.
+    pub sent_at: u64,
+    /// When the envelope was received, by the local clock, in microseconds since the Unix epoch.
+    pub received_at: u64,
+    /// The one-way latency, or `None` without an estimate of the sender's clock. Clock
+    /// estimation error can make short latencies come out as zero.
+    pub one_way: Option<Duration>,
+}
+
+impl Timestamps {
+    /// Creates timestamps without a clock estimate.
+    pub fn new() -> Self {
+        Timestamps::default()
+    }
+
+    /// Computes one-way latencies against the server's clock as estimated by `time_sync`.
+    ///
+    /// # Arguments
+    ///
+    /// * `time_sync` - The estimator fed with the connection's time sync exchanges.
+    ///
+    /// # Returns
+    ///
+    /// The timestamps with the estimator set.
+    pub fn with_time_sync(mut self, time_sync: Arc<TimeSync>) -> Self {
+        self.time_sync = Some(time_sync);
+        self
+    }
+
+    /// Returns the clock estimator, if set.
+    pub fn time_sync(&self) -> Option<&Arc<TimeSync>> {
+        self.time_sync.as_ref()
+    }
+
+    /// Stamps `envelope` with the current time, unless it already carries a send time.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - The envelope to send.
+    ///
+    /// # Returns
+    ///
+    /// The stamped envelope.
+    pub fn stamp(&self, envelope: Envelope) -> Envelope {
+        if envelope.header(SENT_AT).is_some() {
+            return envelope;
+        }
+        envelope.with_header(SENT_AT, now_micros().to_string())
+    }
+
+    /// Annotates `envelope` with the current time as its receive time.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - The received envelope.
+    ///
+    /// # Returns
+    ///
+    /// The annotated envelope.
+    pub fn annotate(&self, envelope: Envelope) -> Envelope {
+        self.annotate_at(envelope, now_micros())
+    }
+
+    /// Like `annotate`, with the given receive time.
+    pub(crate) fn annotate_at(&self, envelope: Envelope, received_at: u64) -> Envelope {
+        envelope.with_header(RECEIVED_AT, received_at.to_string())
+    }
+
+    /// Reads the timestamps of a received envelope.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - An envelope stamped by the sender and annotated on receipt.
+    ///
+    /// # Returns
+    ///
+    /// The `Latency`, or `None` if either timestamp is missing or malformed.
+    pub fn latency(&self, envelope: &Envelope) -> Option<Latency> {
+        let sent_at: u64 = envelope.header(SENT_AT)?.parse().ok()?;
+        let received_at: u64 = envelope.header(RECEIVED_AT)?.parse().ok()?;
+        let one_way = self.time_sync.as_ref().and_then(|sync| sync.estimate_at(received_at)).map(|estimate| {
+            // The server's clock is `offset` ahead, so the send time by the local clock is
+            // `sent_at - offset`.
+            let micros = received_at as f64 + estimate.offset_micros - sent_at as f64;
+            Duration::from_micros(micros.max(0.0) as u64)
+        });
+        Some(Latency { sent_at, received_at, one_way })
+    }
+}
+
+/// Returns the current time in microseconds since the Unix epoch.
+pub(crate) fn now_micros() -> u64 {
+    u64::try_from(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timesync::TimeSample;
+
+    /// Tests stamping, annotation and the one-way latency against an estimated clock.
+    #[test]
+    fn test_latency() {
+        let timestamps = Timestamps::new();
+        let stamped = Envelope::new("quote", Vec::new()).with_header(SENT_AT, "1000000");
+        assert_eq!(timestamps.stamp(stamped.clone()).header(SENT_AT), Some("1000000"));
+        assert!(timestamps.latency(&stamped).is_none());
+        assert!(timestamps.stamp(Envelope::new("quote", Vec::new())).header(SENT_AT).is_some());
+
+        // The server's clock runs 500 ms ahead of the local one.
+        let sync = Arc::new(TimeSync::default());
+        sync.record(TimeSample { received_at: 2_000_000, offset_micros: 500_000.0, delay_micros: 100.0 });
+        let timestamps = timestamps.with_time_sync(sync);
+        let received = timestamps.annotate_at(stamped, 520_000);
+        let latency = timestamps.latency(&received).unwrap();
+        assert_eq!((latency.sent_at, latency.received_at), (1_000_000, 520_000));
+        assert_eq!(latency.one_way, Some(Duration::from_millis(20)));
+    }
+}
@@ -2,16 +2,36 @@
 #![allow(unused_imports)]
 #![allow(unused_variables)]
 
+#[cfg(all(target_arch = "wasm32", not(feature = "wasm")))]
+compile_error!("building websocket_toolkit for wasm32 requires the `wasm` feature");
+
 /// Module for WebSocket connection handling.
 ///
 /// This module contains functionality to manage WebSocket connections,
 /// including connection establishment, message sending, and graceful disconnection.
+#[cfg(not(target_arch = "wasm32"))]
 pub mod connection;
 
+/// Module for WebSocket connection handling in the browser.
+///
+/// On `wasm32` targets this module wraps the browser's `WebSocket` object and exposes
+/// the same `WebSocketClient` API as the native transport.
+#[cfg(target_arch = "wasm32")]
+#[path = "wasm/connection.rs"]
+pub mod connection;
+
+/// Module for the async runtime abstraction.
+///
+/// This module abstracts timers, task spawning and TCP connections behind a `Runtime`
+/// trait with implementations for tokio, async-std and smol.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod runtime;
+
 /// Module for reconnection strategies.
 ///
 /// This module defines strategies for handling reconnection attempts
-/// with retry logic and exponential backoff mechanisms.
+/// with retry logic and exponential backoff mechanisms. Enabled by the `reconnection` feature.
+#[cfg(feature = "reconnection")]
 pub mod reconnection;
 
 /// Module for message handling, including serialization and deserialization.
@@ -20,29 +40,487 @@ pub mod reconnection;
 /// and CBOR, for serialization and deserialization operations.
 pub mod messages;
 
+/// Module for flush policies.
+///
+/// This module defines when messages queued with `WebSocketController::feed_message` are
+/// flushed, so high-frequency senders can batch writes.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod flush;
+
+/// Module for the reader/writer task pipeline.
+///
+/// This module splits a connection into dedicated reader and writer tasks connected by
+/// channels, so many tasks can send and receive without sharing a locked stream.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod pipeline;
+
+/// Module for fragmented sending.
+///
+/// This module sends messages above a configurable size as a sequence of fragments,
+/// keeping small messages on the single-frame path.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod fragment;
+
+/// Module for streaming inbound binary messages.
+///
+/// This module reads inbound frames past tungstenite so each binary message is surfaced as
+/// an `AsyncRead` over its payload instead of a fully buffered `Vec<u8>`.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod streaming;
+
+/// Module for offloaded decoding.
+///
+/// This module decodes large payloads on tokio's blocking pool and hands results back in
+/// arrival order, so a multi-megabyte message does not stall the reactor thread.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod decode;
+
+/// Module for buffering messages while disconnected.
+///
+/// This module queues outgoing messages during an outage, optionally spilling them to a
+/// temporary file beyond a memory limit, and replays them in order on reconnect.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod offline;
+
+/// Module for file and environment configuration.
+///
+/// This module loads connection settings from TOML or YAML files with `WSTK_*` environment
+/// overrides, for `WebSocketController::from_config`.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod config;
+
+/// Module for TLS trust configuration.
+///
+/// This module builds the rustls configuration of `wss://` connections, trusting the bundled
+/// Mozilla roots or the operating system's certificate store.
+#[cfg(all(feature = "rustls-tls", feature = "tokio", not(target_arch = "wasm32")))]
+pub mod tls;
+
+/// Module for pluggable persistence backends.
+///
+/// This module defines the namespaced key-value `Storage` trait used by the outbox, dedupe
+/// window and inbound journal, with in-memory, file and sled implementations.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod storage;
+
+/// Module for cloneable connection handles.
+///
+/// This module wraps a pipelined connection in a `Clone + Send + Sync` handle so several
+/// tasks can send and receive on one connection without external locking.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod handle;
+
+/// Module for self-healing connections.
+///
+/// This module wraps a `ConnectionHandle` so it reconnects transparently, using the
+/// controller's reconnection strategy, whenever the connection drops.
+#[cfg(all(feature = "reconnection", feature = "tokio", not(target_arch = "wasm32")))]
+pub mod managed;
+
+/// Module for request/response correlation.
+///
+/// This module stamps requests with correlation ids and matches the server's replies to
+/// them, with a timeout per request and any number of requests in flight.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod request;
+
+/// Module for replaying subscriptions.
+///
+/// This module records the subscribe frames of active subscriptions and replays them, with
+/// refreshed tokens, on every connection the controller opens.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod subscriptions;
+
+/// Module for JSON-RPC 2.0.
+///
+/// This module implements a JSON-RPC 2.0 client with typed calls, notifications, batches
+/// and handlers for server notifications. Requires the `json` feature.
+#[cfg(all(feature = "json", feature = "tokio", not(target_arch = "wasm32")))]
+pub mod jsonrpc;
+
+/// Module for GraphQL over WebSocket.
+///
+/// This module implements the `graphql-transport-ws` subprotocol, driving GraphQL
+/// subscriptions with typed results. Requires the `json` feature.
+#[cfg(all(feature = "json", feature = "tokio", not(target_arch = "wasm32")))]
+pub mod graphql_ws;
+
+/// Module for MQTT over WebSocket.
+///
+/// This module negotiates the `mqtt` subprotocol and adapts a connection to `AsyncRead` and
+/// `AsyncWrite`, sending MQTT packets in binary frames.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod mqtt_ws;
+
+/// Module for the Socket.IO client.
+///
+/// This module implements the Engine.IO handshake, Socket.IO namespaces and event
+/// emit/acknowledgement over the controller's connections, reconnecting namespaces after a
+/// drop. Requires the `json` feature.
+#[cfg(all(feature = "json", feature = "tokio", not(target_arch = "wasm32")))]
+pub mod socketio;
+
+/// Module for codec statistics.
+///
+/// This module records encode and decode times per format, compression ratios and message
+/// size percentiles per connection, reported by `WebSocketController::codec_stats`.
+pub mod codec_stats;
+
+/// Module for application-level payload compression.
+///
+/// This module defines `Compression`, which deflates encoded payloads for low-bandwidth
+/// links when the server does not negotiate `permessage-deflate`.
+pub mod compression;
+
+/// Module for timing jitter.
+///
+/// This module randomizes intervals such as keep-alive pings so that many clients started
+/// together do not act in lockstep.
+pub mod jitter;
+
+/// Module for round-trip time tracking.
+///
+/// This module times keep-alive pings against their pongs and keeps latest, smoothed,
+/// minimum and maximum round-trip times for pipelines that enable it.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod rtt;
+
+/// Module for link quality estimation.
+///
+/// This module grades a connection as good, degraded or poor from round-trip jitter, missed
+/// pongs and stalled writes, for pipelines that enable it.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod quality;
+
+/// Module for encrypted session persistence.
+///
+/// This module keeps auth tokens, cookies and resume state in an encrypted file so a
+/// restarted client reconnects where it left off. Enabled by the `session` feature.
+#[cfg(all(feature = "session", not(target_arch = "wasm32")))]
+pub mod session;
+
+/// Module for pools of parallel connections.
+///
+/// This module spreads sends over several connections to one endpoint, round-robin or to
+/// the least-loaded connection, and replaces connections that close. Enabled by the
+/// `reconnection` feature.
+#[cfg(all(feature = "reconnection", feature = "tokio", not(target_arch = "wasm32")))]
+pub mod connection_pool;
+
+/// Module for keyed connection sharding.
+///
+/// This module spreads keyed messages over a pool of connections with consistent hashing,
+/// preserving per-key ordering while scaling throughput.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod shard;
+
+/// Module for per-topic message scrollback.
+///
+/// This module keeps the most recent inbound messages of every topic within count and byte
+/// limits, so late subscribers can be handed the recent history.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod scrollback;
+
+/// Module for inbound message fan-out.
+///
+/// This module publishes inbound messages on a broadcast channel so independent components
+/// can each observe the stream, with a per-subscriber policy for lag.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod fanout;
+
+/// Module for typed topic streams.
+///
+/// This module decodes the fan-out's envelopes of one topic into a typed stream and keeps
+/// the topic subscribed across reconnects.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod typed_stream;
+
+/// Module for compile-time topic mappings.
+///
+/// This module provides the `ws_topics!` macro, which binds topic names to payload types and
+/// generates the topic constants and decode dispatch.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod topics;
+
+/// Module for topic-based publish/subscribe.
+///
+/// This module publishes to topics and routes received envelopes to per-topic streams by an
+/// envelope field.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod pubsub;
+
+/// Module for server-initiated closes.
+///
+/// This module describes close frames received from the server and decides whether a
+/// Service Restart (1012) or Try Again Later (1013) close is answered with a reconnect.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod close;
+
+/// Module for server maintenance announcements.
+///
+/// This module defines the maintenance notice a server sends before a restart or drain, and
+/// when a client should move to another endpoint in response.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod maintenance;
+
+/// Module for event-driven connections.
+///
+/// This module runs a controller's connection in a driver task that calls the registered
+/// `on_open`, `on_message`, `on_close` and `on_error` handlers.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod events;
+
+/// Module for make-before-break failover.
+///
+/// This module predicts failing links from ping round trips and switches to a new
+/// connection before the old one dies, or to a warm standby when it does.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod failover;
+
+/// Module for schema-versioned envelopes.
+///
+/// This module migrates envelopes between schema versions, so clients and servers on
+/// different releases keep understanding each other during a rollout.
+pub mod schema;
+
+/// Module for replay protection.
+///
+/// This module stamps envelopes with monotonic timestamps and nonces and rejects stale or
+/// replayed ones, for signed command channels.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod replay;
+
+/// Module for fleet warmup.
+///
+/// This module opens thousands of connections with staggered starts and a global limit on
+/// concurrent handshakes, for device simulators and load tests.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod fleet;
+
+/// Module for shared resource limits.
+///
+/// This module caps connections, handshakes in flight and buffered bytes across every
+/// controller registered against the same `Limits`.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod limits;
+
+/// Module for inbound rate limiting.
+///
+/// This module enforces per-connection message and byte rates on received messages, delaying
+/// reads, warning or closing with 1008 when a peer exceeds them.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod ratelimit;
+
+/// Module for first-frame authentication.
+///
+/// This module sends an auth frame first on every connection, within a deadline, and
+/// reports rejected credentials as a non-retryable error.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod auth;
+
+/// Module for distributed trace propagation.
+///
+/// This module carries W3C trace context in envelope headers, continuing the sender's trace
+/// in handlers and sampling traces started locally.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod trace_context;
+
+/// Module for message transformation hooks.
+///
+/// This module rewrites envelopes sent and received by the controller, at the typed or the
+/// serialized level, e.g. to inject trace ids or redact PII.
+pub mod transform;
+
+/// Module for clock synchronization.
+///
+/// This module estimates the server's clock offset and drift from NTP-like request/reply
+/// exchanges over the connection.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod timesync;
+
+/// Module for send and receive timestamps.
+///
+/// This module stamps envelopes with their send and receive times and computes one-way
+/// latency against an estimate of the server's clock.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod latency;
+
+/// Module for connection history.
+///
+/// This module keeps a bounded ring of recent connection events and message summaries that
+/// can be dumped for post-mortems.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod history;
+
+/// Module for resume-from-sleep detection.
+///
+/// This module detects that the machine slept from gaps in its clocks, so connections can
+/// be probed before they are trusted again.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod wake;
+
+/// Module for network change notifications.
+///
+/// This module defines the `NetworkMonitor` hook that reports network changes from the
+/// operating system, so connections can migrate to a new network proactively.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod network;
+
+/// Module for protocol violation reports.
+///
+/// This module classifies protocol errors into structured reports with a rule id, the
+/// direction and an excerpt of the offending bytes.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod violation;
+
+/// Module for the durable outbox.
+///
+/// This module persists outgoing messages in a sled database until the peer acknowledges
+/// them, so they survive crashes and reconnects. Enabled by the `outbox` feature.
+#[cfg(all(feature = "outbox", not(target_arch = "wasm32")))]
+pub mod outbox;
+
+/// Module for idempotency-key filtering.
+///
+/// This module remembers the ids of recently received envelopes in a sled database so
+/// messages resent after a crash or reconnect are applied once. Enabled by the `outbox` feature.
+#[cfg(all(feature = "outbox", not(target_arch = "wasm32")))]
+pub mod dedupe;
+
+/// Module for server clustering.
+///
+/// This module shares room messages and presence between the nodes of a horizontally scaled
+/// server through a pluggable publish/subscribe bus, with in-memory and Redis implementations.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod cluster;
+
+/// Module for Avro messages.
+///
+/// This module encodes and decodes Avro datums, bare or in the Confluent wire format, and
+/// provides a cached schema registry client. Requires the `avro` feature; the registry client
+/// also requires `schema-registry`.
+#[cfg(feature = "avro")]
+pub mod avro;
+
+/// Module for persistent room logs.
+///
+/// This module keeps the messages posted to each room in a storage backend and replays them
+/// to joining clients, the last few or everything after the client's resume cursor.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod rooms;
+
+/// Module for the inbound journal.
+///
+/// This module persists the last processed inbound sequence number so a restarted client can
+/// ask the sender to resume from it. Enabled by the `outbox` feature.
+#[cfg(all(feature = "outbox", not(target_arch = "wasm32")))]
+pub mod journal;
+
+/// Module for credit-based flow control.
+///
+/// This module lets a receiver grant message credits that the sender spends, so fast
+/// producers cannot overrun slow consumers regardless of socket buffer sizes.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod flow;
+
+/// Module for ordered delivery.
+///
+/// This module restores the original order of sequenced envelopes and drops duplicates after
+/// reconnects, optionally stalling delivery until gaps are filled.
+pub mod ordering;
+
+/// Module for pooled payload buffers.
+///
+/// This module recycles `BytesMut` buffers for serialized and received payloads so high
+/// message rates do not allocate a fresh `Vec<u8>` per message.
+pub mod pool;
+
 /// Module for WebSocket keep-alive mechanisms.
 ///
 /// This module provides a mechanism to maintain active WebSocket connections
-/// by sending periodic pings to the server to prevent timeouts.
+/// by sending periodic pings to the server to prevent timeouts. Enabled by the `keep-alive` feature.
+#[cfg(all(feature = "keep-alive", not(target_arch = "wasm32")))]
 pub mod keep_alive;
 
 /// Module for WebSocket controller logic, managing connections and communication.
 ///
 /// This module defines a controller that centralizes WebSocket connection
 /// management, message handling, and reconnection strategies.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod controller;
+
+/// Module for WebSocket controller logic in the browser.
+///
+/// On `wasm32` targets this module provides a `WebSocketController` with the same
+/// methods as the native controller, operating on browser WebSocket streams.
+#[cfg(target_arch = "wasm32")]
+#[path = "wasm/controller.rs"]
 pub mod controller;
 
+/// Module for named task spawning.
+///
+/// This module spawns the toolkit's background tasks under descriptive names so they show
+/// up in `tokio-console`, and returns their join handles so they can be awaited or aborted.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub mod tasks;
+
+/// Module with local WebSocket servers for tests and benchmarks.
+///
+/// This module provides an in-process echo server and an in-memory transport so tests,
+/// benchmarks, fuzz targets and the load-generation harness do not depend on an external
+/// WebSocket server. Enabled by the `testing` feature.
+#[cfg(all(feature = "testing", feature = "tokio", not(target_arch = "wasm32")))]
+pub mod testing;
+
+/// Module for load generation and throughput/latency measurement.
+///
+/// This module drives many concurrent connections at a fixed message rate and
+/// reports throughput and round-trip latency percentiles. Enabled by the `testing` feature.
+#[cfg(all(feature = "testing", feature = "tokio", not(target_arch = "wasm32")))]
+pub mod loadgen;
+
+/// Module for scripted integration-test scenarios.
+///
+/// This module lets tests describe connect/send/expect/drop/reconnect steps in code
+/// or TOML and runs them against the mock server. Enabled by the `testing` feature together
+/// with `reconnection`.
+#[cfg(all(feature = "testing", feature = "reconnection", feature = "tokio", not(target_arch = "wasm32")))]
+pub mod scenario;
+
+/// Module for C ABI bindings.
+///
+/// This module exposes `wstk_connect`, `wstk_send`, `wstk_close` and a receive callback
+/// so C and C++ applications can embed the controller. Enabled by the `ffi` feature.
+#[cfg(all(feature = "ffi", not(target_arch = "wasm32")))]
+pub mod ffi;
+
+/// Module for uniffi bindings used by iOS and Android apps.
+///
+/// This module implements the interface in `src/websocket_toolkit.udl`, covering the
+/// controller, message envelopes and reconnect configuration. Enabled by the `mobile` feature.
+#[cfg(all(feature = "mobile", not(target_arch = "wasm32")))]
+pub mod mobile;
+
+/// Module re-exporting the most commonly used types.
+///
+/// `use websocket_toolkit::prelude::*;` brings the controller, configuration, message types
+/// and the tungstenite types used in the controller's signatures into scope.
+pub mod prelude;
+
+#[cfg(all(feature = "mobile", not(target_arch = "wasm32")))]
+use crate::mobile::UniFfiTag;
+
+#[cfg(feature = "reconnection")]
 use crate::reconnection::Connectable;
-use tokio_tungstenite::tungstenite::protocol::Message;
-use futures_util::{StreamExt, SinkExt};
-use tokio::sync::Mutex;
 
 /// A mock WebSocket client for testing purposes.
 ///
 /// This struct simulates a WebSocket client that always fails to connect,
 /// which is useful for testing reconnection logic and error handling.
+#[cfg(feature = "reconnection")]
 pub struct MockWebSocketClient;
 
+#[cfg(feature = "reconnection")]
 #[async_trait::async_trait]
 impl Connectable for MockWebSocketClient {
     /// Simulates a connection failure for the mock WebSocket client.
@@ -59,6 +537,7 @@ mod tests {
     use super::*;
     use super::connection::WebSocketClient;
     use super::messages::{MessageHandler, MessageFormat};
+    #[cfg(feature = "reconnection")]
     use super::reconnection::ReconnectStrategy;
     use super::controller::WebSocketController;
     use tokio::net::TcpListener;
@@ -68,6 +547,7 @@ mod tests {
     use tokio_tungstenite::tungstenite::protocol::Message;
     use std::sync::Arc;
     use futures_util::{StreamExt, SinkExt};
+    use tokio::sync::Mutex;
 
     /// Tests the ability of `WebSocketClient` to establish a connection with a mock server.
     ///
@@ -131,6 +611,7 @@ mod tests {
     ///
     /// This test verifies that the reconnection strategy stops after the
     /// maximum number of retries if the connection cannot be re-established.
+    #[cfg(feature = "reconnection")]
     #[tokio::test]
     async fn test_reconnect_strategy_with_backoff() {
         let reconnect_strategy = ReconnectStrategy::new(3, 1);
@@ -144,6 +625,7 @@ mod tests {
     ///
     /// This test verifies the controller's ability to manage WebSocket connections,
     /// including initial connection, reconnection, and keep-alive mechanisms.
+    #[cfg(all(feature = "keep-alive", feature = "reconnection"))]
     #[tokio::test]
     async fn test_websocket_controller_lifecycle() {
         let mut controller = WebSocketController::new("ws://node_server:9001", 3, Some(5));
@@ -177,6 +659,7 @@ mod tests {
     ///
     /// This test ensures that messages are correctly serialized into both JSON and CBOR
     /// formats and can be deserialized back into their original structure.
+    #[cfg(all(feature = "json", feature = "cbor"))]
     #[test]
     fn test_message_serialization_and_deserialization() {
         let message = "Hello, WebSocket!";
@@ -0,0 +1,297 @@
+//! # `limits.rs`: Process-wide resource limits
+//!
+//! A multi-tenant service runs many controllers in one process, and each tenant's traffic
+//! must not be able to exhaust the process. `Limits` is a registry of ceilings that any
+//! number of controllers share (`WebSocketController::set_limits`):
+//!
+//! - `max_connections`: connections beyond the limit fail immediately instead of being
+//!   opened. A pipelined connection holds its slot until both of its tasks have stopped; a
+//!   connection from `WebSocketController::connect` holds it until the controller connects
+//!   again or is dropped.
+//! - `max_handshakes`: handshakes beyond the limit wait for a slot, so a reconnect storm
+//!   cannot open thousands of TCP and TLS handshakes at once.
+//! - `max_buffered_bytes`: messages waiting in pipeline queues, in either direction and
+//!   across all connections, may not exceed the limit. Senders and readers wait for room, so
+//!   the ceiling turns into backpressure instead of memory growth.
+//!   `max_connection_buffered_bytes` applies the same per connection.
+//!
+//! A single message larger than a byte limit is admitted once nothing else is buffered, so
+//! it cannot wait forever.
+
+use log::warn;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Notify, Semaphore, SemaphorePermit};
+
+/// The ceilings enforced by `Limits`; `None` leaves a resource unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LimitsConfig {
+    /// The most open connections.
+    pub max_connections: Option<usize>,
+    /// The most handshakes in flight.
+    pub max_handshakes: Option<usize>,
+    /// The most bytes buffered in pipeline queues across all connections.
+    pub max_buffered_bytes: Option<usize>,
+    /// The most bytes buffered in the pipeline queues of any one connection.
+    pub max_connection_buffered_bytes: Option<usize>,
+}
+
+/// A byte budget that reservations wait on.
+#[derive(Debug)]
+struct Budget {
+    max: Option<usize>,
+    used: Mutex<usize>,
+    /// Signalled whenever bytes are released.
+    released: Notify,
+}
+
+impl Budget {
+    fn new(max: Option<usize>) -> Self {
+        Budget { max, used: Mutex::new(0), released: Notify::new() }
+    }
+
+    /// Waits until `bytes` fit, or nothing else is reserved, and reserves them.
+    async fn reserve(&self, bytes: usize) {
+        loop {
+            // Created before checking, so a release in between is not missed.
+            let released = self.released.notified();
+            {
+                let mut used = self.used.lock().unwrap();
+                let fits = match self.max {
+                    Some(max) => *used == 0 || *used + bytes <= max,
+                    None => true,
+                };
+                if fits {
+                    *used += bytes;
+                    return;
+                }
+            }
+            released.await;
+        }
+    }
+
+    fn release(&self, bytes: usize) {
+        *self.used.lock().unwrap() -= bytes;
+        self.released.notify_waiters();
+    }
+
+    fn used(&self) -> usize {
+        *self.used.lock().unwrap()
+    }
+}
+
+/// Shared resource ceilings for any number of controllers.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use websocket_toolkit::controller::WebSocketController;
+/// use websocket_toolkit::limits::{Limits, LimitsConfig};
+///
+/// let limits = Arc::new(Limits::new(LimitsConfig {
+///     max_connections: Some(10_000),
+///     max_handshakes: Some(64),
+///     max_buffered_bytes: Some(256 * 1024 * 1024),
+///     ..LimitsConfig::default()
+/// }));
+/// for tenant in ["ws://a.example.com", "ws://b.example.com"] {
+///     let mut controller = WebSocketController::new(tenant, 3, None);
+///     controller.set_limits(Some(limits.clone()));
+/// }
+/// assert_eq!(limits.connections(), 0);
+/// ```
+#[derive(Debug)]
+pub struct Limits {
+    config: LimitsConfig,
+    connections: AtomicUsize,
+    handshake_slots: Option<Semaphore>,
+    handshakes: AtomicUsize,
+    buffered: Budget,
+}
+
+impl Limits {
+    /// Creates a registry.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The ceilings to enforce.
+    ///
+    /// # Returns
+    ///
+    /// A new `Limits` with nothing in use.
+    pub fn new(config: LimitsConfig) -> Self {
+        Limits {
+            config,
+            connections: AtomicUsize::new(0),
+            handshake_slots: config.max_handshakes.map(|max| Semaphore::new(max.max(1))),
+            handshakes: AtomicUsize::new(0),
+            buffered: Budget::new(config.max_buffered_bytes),
+        }
+    }
+
+    /// Returns the ceilings.
+    pub fn config(&self) -> LimitsConfig {
+        self.config
+    }
+
+    /// Returns the number of open connections.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of handshakes in flight.
+    pub fn handshakes_in_flight(&self) -> usize {
+        self.handshakes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes buffered across all connections.
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered.used()
+    }
+
+    /// Takes a connection slot, failing if all are in use.
+    pub(crate) fn admit(self: &Arc<Self>) -> Result<Arc<ConnectionLimits>, String> {
+        let max = self.config.max_connections.unwrap_or(usize::MAX);
+        self.connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |open| (open < max).then_some(open + 1))
+            .map_err(|open| {
+                warn!("Connection limit of {} reached", max);
+                format!("Failed to open connection: limit of {} connections reached ({} open)", max, open)
+            })?;
+        Ok(Arc::new(ConnectionLimits {
+            limits: self.clone(),
+            buffered: Budget::new(self.config.max_connection_buffered_bytes),
+        }))
+    }
+
+    /// Waits for a handshake slot, held until the returned guard is dropped.
+    pub(crate) async fn handshake(&self) -> HandshakeGuard<'_> {
+        let permit = match &self.handshake_slots {
+            Some(slots) => slots.acquire().await.ok(),
+            None => None,
+        };
+        self.handshakes.fetch_add(1, Ordering::Relaxed);
+        HandshakeGuard { limits: self, _permit: permit }
+    }
+}
+
+/// A handshake slot.
+pub(crate) struct HandshakeGuard<'a> {
+    limits: &'a Limits,
+    _permit: Option<SemaphorePermit<'a>>,
+}
+
+impl Drop for HandshakeGuard<'_> {
+    fn drop(&mut self) {
+        self.limits.handshakes.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A connection's slot and byte budget; the slot is freed when the last reference is dropped.
+#[derive(Debug)]
+pub(crate) struct ConnectionLimits {
+    limits: Arc<Limits>,
+    buffered: Budget,
+}
+
+impl ConnectionLimits {
+    /// Waits until `bytes` fit in the connection's and the global budget, and reserves them
+    /// until the returned reservation is dropped.
+    pub(crate) async fn reserve(self: &Arc<Self>, bytes: usize) -> BufferReservation {
+        self.buffered.reserve(bytes).await;
+        // Releases the connection's share if the wait below is cancelled.
+        let mut reservation = BufferReservation { connection: self.clone(), bytes, global: false };
+        self.limits.buffered.reserve(bytes).await;
+        reservation.global = true;
+        reservation
+    }
+}
+
+impl Drop for ConnectionLimits {
+    fn drop(&mut self) {
+        self.limits.connections.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Bytes reserved for a buffered message, released on drop.
+#[derive(Debug)]
+pub(crate) struct BufferReservation {
+    connection: Arc<ConnectionLimits>,
+    bytes: usize,
+    /// Whether the bytes were also reserved in the global budget.
+    global: bool,
+}
+
+impl Drop for BufferReservation {
+    fn drop(&mut self) {
+        if self.global {
+            self.connection.limits.buffered.release(self.bytes);
+        }
+        self.connection.buffered.release(self.bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    /// Tests that connection slots are limited and freed with their last reference.
+    #[test]
+    fn test_connection_slots() {
+        let limits = Arc::new(Limits::new(LimitsConfig { max_connections: Some(2), ..LimitsConfig::default() }));
+        let first = limits.admit().unwrap();
+        let second = limits.admit().unwrap();
+        assert!(limits.admit().unwrap_err().contains("limit of 2 connections"));
+        assert_eq!(limits.connections(), 2);
+        drop(first);
+        let third = limits.admit().unwrap();
+        drop((second, third));
+        assert_eq!(limits.connections(), 0);
+    }
+
+    /// Tests that byte reservations wait for room in the global and per-connection budgets.
+    #[tokio::test]
+    async fn test_buffered_bytes() {
+        let limits = Arc::new(Limits::new(LimitsConfig {
+            max_buffered_bytes: Some(100),
+            max_connection_buffered_bytes: Some(60),
+            ..LimitsConfig::default()
+        }));
+        let a = limits.admit().unwrap();
+        let b = limits.admit().unwrap();
+
+        let a1 = a.reserve(50).await;
+        assert!(timeout(Duration::from_millis(50), a.reserve(20)).await.is_err(), "Expected the connection budget to be full");
+        let b1 = b.reserve(50).await;
+        assert_eq!(limits.buffered_bytes(), 100);
+        let waiting = tokio::spawn({
+            let b = b.clone();
+            async move { b.reserve(10).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+        drop(a1);
+        let b2 = timeout(Duration::from_secs(1), waiting).await.unwrap().unwrap();
+        assert_eq!(limits.buffered_bytes(), 60);
+
+        drop((b1, b2));
+        let oversized = timeout(Duration::from_secs(1), a.reserve(500)).await.expect("Expected an oversized message alone to fit");
+        drop(oversized);
+        assert_eq!(limits.buffered_bytes(), 0);
+    }
+
+    /// Tests that handshakes beyond the limit wait.
+    #[tokio::test]
+    async fn test_handshake_slots() {
+        let limits = Limits::new(LimitsConfig { max_handshakes: Some(1), ..LimitsConfig::default() });
+        let first = limits.handshake().await;
+        assert_eq!(limits.handshakes_in_flight(), 1);
+        assert!(timeout(Duration::from_millis(50), limits.handshake()).await.is_err());
+        drop(first);
+        let _second = limits.handshake().await;
+        assert_eq!(limits.handshakes_in_flight(), 1);
+    }
+}
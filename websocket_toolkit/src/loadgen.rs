@@ -0,0 +1,250 @@
+//! # `loadgen.rs`: Load-generation harness
+//!
+//! This module drives `N` concurrent connections, each sending `M` messages per second
+//! to a WebSocket server that echoes them back, and reports throughput and round-trip
+//! latency. It is used by the `throughput` benchmark and can be pointed at any echo
+//! endpoint to measure performance regressions.
+
+use crate::connection::WebSocketClient;
+use crate::tasks::spawn_named;
+use futures_util::{SinkExt, StreamExt};
+use log::{error, info};
+use std::time::{Duration, Instant};
+use tokio::time::{interval, MissedTickBehavior};
+use tokio_tungstenite::tungstenite::Message;
+
+/// Configuration for a load-generation run.
+///
+/// # Fields
+///
+/// * `connections` - The number of concurrent connections to open.
+/// * `messages_per_sec` - The send rate of each individual connection.
+/// * `duration` - How long each connection keeps sending.
+/// * `payload_size` - The size of every message in bytes (at least 8 bytes are used for the timestamp).
+#[derive(Debug, Clone)]
+pub struct LoadConfig {
+    /// The number of concurrent connections to open.
+    pub connections: usize,
+    /// The number of messages each connection sends per second.
+    pub messages_per_sec: u32,
+    /// How long each connection keeps sending.
+    pub duration: Duration,
+    /// The size of every message in bytes.
+    pub payload_size: usize,
+}
+
+impl Default for LoadConfig {
+    fn default() -> Self {
+        LoadConfig {
+            connections: 10,
+            messages_per_sec: 100,
+            duration: Duration::from_secs(5),
+            payload_size: 64,
+        }
+    }
+}
+
+/// The result of a load-generation run.
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    /// Total number of messages sent across all connections.
+    pub sent: u64,
+    /// Total number of echoed messages received across all connections.
+    pub received: u64,
+    /// Number of connections that failed to connect or errored mid-run.
+    pub failed_connections: usize,
+    /// Wall-clock time of the whole run.
+    pub elapsed: Duration,
+    /// Round-trip latencies of all received messages, sorted ascending.
+    pub latencies: Vec<Duration>,
+}
+
+impl LoadReport {
+    /// Returns the number of received messages per second over the whole run.
+    pub fn throughput(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.received as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Returns the round-trip latency at the given percentile (`0.0..=100.0`).
+    ///
+    /// # Returns
+    ///
+    /// `None` if no messages were received.
+    pub fn latency_percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0) * (self.latencies.len() - 1) as f64;
+        Some(self.latencies[rank.round() as usize])
+    }
+}
+
+/// Runs a load-generation session against an echo server.
+///
+/// # Arguments
+///
+/// * `url` - The URL of a WebSocket server that echoes binary frames.
+/// * `config` - The load profile to apply.
+///
+/// # Returns
+///
+/// A `LoadReport` aggregating the results of all connections.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::loadgen::{run, LoadConfig};
+/// use websocket_toolkit::testing::EchoServer;
+/// use std::time::Duration;
+///
+/// let runtime = tokio::runtime::Runtime::new().unwrap();
+/// runtime.block_on(async {
+///     let server = EchoServer::start().await.unwrap();
+///     let config = LoadConfig {
+///         connections: 2,
+///         messages_per_sec: 50,
+///         duration: Duration::from_millis(200),
+///         payload_size: 32,
+///     };
+///     let report = run(server.url(), &config).await;
+///     assert_eq!(report.failed_connections, 0);
+/// });
+/// ```
+pub async fn run(url: &str, config: &LoadConfig) -> LoadReport {
+    let started = Instant::now();
+    let mut tasks = Vec::with_capacity(config.connections);
+    for _ in 0..config.connections {
+        let url = url.to_string();
+        let config = config.clone();
+        tasks.push(spawn_named("websocket_toolkit::loadgen_connection", async move {
+            run_connection(&url, &config, started).await
+        }));
+    }
+
+    let mut report = LoadReport::default();
+    for task in tasks {
+        match task.await {
+            Ok(Ok((sent, latencies))) => {
+                report.sent += sent;
+                report.received += latencies.len() as u64;
+                report.latencies.extend(latencies);
+            }
+            Ok(Err(e)) => {
+                error!("Load connection failed: {}", e);
+                report.failed_connections += 1;
+            }
+            Err(e) => {
+                error!("Load connection task panicked: {}", e);
+                report.failed_connections += 1;
+            }
+        }
+    }
+    report.elapsed = started.elapsed();
+    report.latencies.sort();
+    info!(
+        "Load run finished: sent={} received={} throughput={:.1} msg/s",
+        report.sent,
+        report.received,
+        report.throughput()
+    );
+    report
+}
+
+/// Drives a single connection, returning the number of messages sent and the measured latencies.
+async fn run_connection(
+    url: &str,
+    config: &LoadConfig,
+    started: Instant,
+) -> Result<(u64, Vec<Duration>), tokio_tungstenite::tungstenite::Error> {
+    let ws_stream = WebSocketClient::new(url, 1).connect().await?;
+    let (mut sink, mut stream) = ws_stream.split();
+
+    let period = Duration::from_secs_f64(1.0 / f64::from(config.messages_per_sec.max(1)));
+    let duration = config.duration;
+    let payload_size = config.payload_size.max(8);
+
+    let writer = async move {
+        let mut ticker = interval(period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Burst);
+        let deadline = Instant::now() + duration;
+        let mut sent = 0u64;
+        while Instant::now() < deadline {
+            ticker.tick().await;
+            let mut payload = vec![0u8; payload_size];
+            let stamp = started.elapsed().as_nanos() as u64;
+            payload[..8].copy_from_slice(&stamp.to_be_bytes());
+            sink.send(Message::Binary(payload)).await?;
+            sent += 1;
+        }
+        Ok::<_, tokio_tungstenite::tungstenite::Error>((sent, sink))
+    };
+
+    let reader = async move {
+        let mut latencies = Vec::new();
+        while let Some(Ok(message)) = stream.next().await {
+            if let Message::Binary(data) = message {
+                if data.len() >= 8 {
+                    let mut stamp = [0u8; 8];
+                    stamp.copy_from_slice(&data[..8]);
+                    let sent_at = Duration::from_nanos(u64::from_be_bytes(stamp));
+                    latencies.push(started.elapsed().saturating_sub(sent_at));
+                }
+            }
+        }
+        latencies
+    };
+
+    let reader = spawn_named("websocket_toolkit::loadgen_reader", reader);
+    let (sent, mut sink) = writer.await?;
+
+    // Give in-flight echoes a moment to arrive before closing the connection.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let _ = sink.close().await;
+
+    let latencies = match tokio::time::timeout(Duration::from_secs(1), reader).await {
+        Ok(Ok(latencies)) => latencies,
+        _ => Vec::new(),
+    };
+    Ok((sent, latencies))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::EchoServer;
+
+    /// Tests that latency percentiles are picked from the sorted sample set.
+    #[test]
+    fn test_latency_percentile() {
+        let report = LoadReport {
+            received: 3,
+            elapsed: Duration::from_secs(1),
+            latencies: vec![Duration::from_millis(1), Duration::from_millis(2), Duration::from_millis(3)],
+            ..LoadReport::default()
+        };
+        assert_eq!(report.latency_percentile(0.0), Some(Duration::from_millis(1)));
+        assert_eq!(report.latency_percentile(50.0), Some(Duration::from_millis(2)));
+        assert_eq!(report.latency_percentile(100.0), Some(Duration::from_millis(3)));
+        assert_eq!(report.throughput(), 3.0);
+    }
+
+    /// Tests a short load run against the local echo server.
+    #[tokio::test]
+    async fn test_load_run_against_echo_server() {
+        let server = EchoServer::start().await.expect("Failed to start echo server");
+        let config = LoadConfig {
+            connections: 2,
+            messages_per_sec: 100,
+            duration: Duration::from_millis(200),
+            payload_size: 16,
+        };
+
+        let report = run(server.url(), &config).await;
+        assert_eq!(report.failed_connections, 0);
+        assert!(report.sent > 0, "Expected messages to be sent");
+        assert_eq!(report.sent, report.received, "Expected every message to be echoed");
+    }
+}
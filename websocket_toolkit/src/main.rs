@@ -2,13 +2,12 @@
 #![allow(unused_imports)]
 #![allow(unused_variables)]
 
+use websocket_toolkit::config::Config;
 use websocket_toolkit::controller::WebSocketController;
 use tokio::time::{timeout, Duration, sleep};
 use log::{info, error};
-use env_logger;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use serde_cbor;
 use tokio::sync::Mutex;
 use std::sync::Arc;
 
@@ -35,13 +34,40 @@ async fn main() {
     // Initialize the logging framework for structured logs.
     env_logger::init();
 
-    // Configuration variables for the WebSocket client.
-    let url = "ws://127.0.0.1:9001";
-    let retries = 5; // Maximum number of reconnection attempts.
-    let ping_interval = Some(5); // Interval in seconds for keep-alive pings.
+    // Load the configuration from the file named by `WSTK_CONFIG`, if any, falling back to
+    // the local test server; `WSTK_*` variables override either.
+    let config = match std::env::var("WSTK_CONFIG") {
+        Ok(path) => Config::load(path),
+        Err(_) => {
+            let mut config = Config {
+                urls: vec!["ws://127.0.0.1:9001".to_string()],
+                retries: 5,
+                ping_interval_secs: Some(5),
+                ..Config::default()
+            };
+            config.apply_env_overrides().and_then(|_| config.validate()).map(|_| config)
+        }
+    };
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    if let Ok(Some(level)) = config.log_level() {
+        log::set_max_level(level);
+    }
+    let ping_interval = config.ping_interval_secs;
 
     // Instantiate a WebSocket controller to manage the connection.
-    let mut controller = WebSocketController::new(url, retries, ping_interval);
+    let mut controller = match WebSocketController::from_config(&config) {
+        Ok(controller) => controller,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
 
     // Exponential backoff for reconnection attempts.
     let mut backoff = 1;
@@ -105,7 +131,7 @@ async fn run_connection_loop(
         let mut stream = ws_stream.lock().await;
 
         // Receive a message from the WebSocket server.
-        match controller.receive_message(&mut *stream).await {
+        match controller.receive_message(&mut stream).await {
             Ok(Some(msg)) => {
                 // Attempt to deserialize as JSON message.
                 if let Ok(json_msg) = serde_json::from_slice::<Message>(&msg) {
@@ -125,7 +151,7 @@ async fn run_connection_loop(
                     msg_type: "response".to_string(),
                     content: "Acknowledged (CBOR)".to_string(),
                 })?;
-                controller.send_message(&mut *stream, &cbor_response).await?;
+                controller.send_message(&mut stream, &cbor_response).await?;
             }
             Ok(None) => info!("Control message received, ignoring."),
             Err(e) => {
@@ -136,7 +162,7 @@ async fn run_connection_loop(
 
         // Send keep-alive ping at the specified interval.
         if let Some(interval) = ping_interval {
-            if let Err(e) = controller.send_ping(&mut *stream).await {
+            if let Err(e) = controller.send_ping(&mut stream).await {
                 error!("Ping failed: {}", e);
                 break;
             }
@@ -0,0 +1,164 @@
+//! # `maintenance.rs`: Server maintenance announcements
+//!
+//! When a server restarts, every client it held reconnects at the same moment, and the
+//! burst of handshakes lands on whichever instances are left. Servers usually know about a
+//! restart in advance, so this module defines a control message that lets them say so: an
+//! `Envelope` of kind `MAINTENANCE_KIND` whose payload is a `MaintenanceNotice`, encoded in
+//! the connection's message format:
+//!
+//! - `maintenance_at`: when the server restarts, in milliseconds since the Unix epoch.
+//! - `drain`: the server is draining and clients should leave now.
+//! - `alternate`: an endpoint to move to; by default the client reconnects to its own URL,
+//!   which a load balancer routes to another instance once this one drains.
+//!
+//! With `WebSocketController::set_maintenance_policy`, the controller's `EventDriver` acts on
+//! such notices: it opens a connection to the alternate endpoint at a random moment before
+//! the restart, switches over to it and closes the old connection, so clients move one at a
+//! time instead of all at once. The notice is still passed to `on_message`, e.g. to show a
+//! banner.
+
+use crate::jitter::unit_random;
+use crate::messages::{Envelope, MessageFormat, MessageHandler};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The envelope kind of maintenance notices.
+pub const MAINTENANCE_KIND: &str = "maintenance";
+
+/// A server's announcement of upcoming maintenance.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct MaintenanceNotice {
+    /// When the server restarts, in milliseconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance_at: Option<u64>,
+    /// Whether the server is draining: clients should move now.
+    #[serde(default)]
+    pub drain: bool,
+    /// The endpoint to move to, or `None` for the client's own URL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alternate: Option<String>,
+}
+
+impl MaintenanceNotice {
+    /// Builds the envelope a server sends to announce this notice.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format of the payload.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing an envelope of kind `MAINTENANCE_KIND`, or an error message if
+    /// serialization fails.
+    pub fn to_envelope(&self, format: MessageFormat) -> Result<Envelope, String> {
+        Envelope::from_value(MAINTENANCE_KIND, self, format)
+    }
+
+    /// Reads a notice from a received envelope.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - A received envelope.
+    /// * `format` - The format of the payload.
+    ///
+    /// # Returns
+    ///
+    /// The notice, or `None` if `envelope` is not a valid maintenance notice.
+    pub fn from_envelope(envelope: &Envelope, format: MessageFormat) -> Option<Self> {
+        if envelope.kind != MAINTENANCE_KIND {
+            return None;
+        }
+        match MessageHandler::deserialize(&envelope.payload, format) {
+            Ok(Some(notice)) => Some(notice),
+            Ok(None) => Some(MaintenanceNotice::default()),
+            Err(e) => {
+                warn!("Ignoring invalid maintenance notice: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Picks when to move, spreading clients over the time left before the restart.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - How early and over how long clients move.
+    ///
+    /// # Returns
+    ///
+    /// The delay before moving, or `None` if the notice announces neither a drain nor a
+    /// restart time.
+    pub fn move_delay(&self, policy: &MaintenancePolicy) -> Option<Duration> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let window = self.move_window(policy, now)?;
+        Some(window.mul_f64(unit_random()))
+    }
+
+    /// Returns the time within which the client should move.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - How early and over how long clients move.
+    /// * `now` - The current time since the Unix epoch.
+    fn move_window(&self, policy: &MaintenancePolicy, now: Duration) -> Option<Duration> {
+        if self.drain {
+            return Some(policy.drain_window);
+        }
+        let restart = Duration::from_millis(self.maintenance_at?);
+        // Moving must be done `lead` before the restart; a late notice moves at once.
+        Some(restart.saturating_sub(policy.lead).saturating_sub(now).min(policy.max_window))
+    }
+}
+
+/// How a controller moves its connection after a maintenance notice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenancePolicy {
+    /// How long before the announced restart every client should have moved.
+    pub lead: Duration,
+    /// The longest time clients are spread over, however early the restart is announced.
+    pub max_window: Duration,
+    /// The time clients of a draining server are spread over.
+    pub drain_window: Duration,
+}
+
+impl Default for MaintenancePolicy {
+    /// Moves at least 30 seconds before the restart, spread over at most 5 minutes, and
+    /// within 10 seconds of a drain.
+    fn default() -> Self {
+        MaintenancePolicy {
+            lead: Duration::from_secs(30),
+            max_window: Duration::from_secs(300),
+            drain_window: Duration::from_secs(10),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "cbor"))]
+mod tests {
+    use super::*;
+
+    /// Tests the envelope round trip and the window clients move within.
+    #[test]
+    fn test_maintenance_notice() {
+        let notice = MaintenanceNotice {
+            maintenance_at: Some(1_000_000),
+            drain: false,
+            alternate: Some("wss://b.example.com".to_string()),
+        };
+        let envelope = notice.to_envelope(MessageFormat::Cbor).unwrap();
+        assert_eq!(MaintenanceNotice::from_envelope(&envelope, MessageFormat::Cbor), Some(notice.clone()));
+        assert_eq!(MaintenanceNotice::from_envelope(&Envelope::new("chat", Vec::new()), MessageFormat::Cbor), None);
+
+        let policy = MaintenancePolicy::default();
+        let at = |secs| Duration::from_secs(secs);
+        // 1000s restart, 30s lead: move within the 70s left at 900s, at once when late.
+        assert_eq!(notice.move_window(&policy, at(900)), Some(at(70)));
+        assert_eq!(notice.move_window(&policy, at(980)), Some(Duration::ZERO));
+        assert_eq!(notice.move_window(&policy, at(0)), Some(policy.max_window));
+        let drain = MaintenanceNotice { drain: true, ..Default::default() };
+        assert_eq!(drain.move_window(&policy, at(0)), Some(policy.drain_window));
+        assert_eq!(MaintenanceNotice::default().move_window(&policy, at(0)), None);
+        assert!(drain.move_delay(&policy).unwrap() <= policy.drain_window);
+    }
+}
@@ -0,0 +1,365 @@
+//! # `managed.rs`: Connections that reconnect transparently
+//!
+//! A `ConnectionHandle` ends for good when its connection drops: `recv` returns `None` and
+//! `send` fails, and the caller has to notice, reconnect and restore whatever state the
+//! server kept for the old connection (subscriptions, authentication, ...). A
+//! `ManagedConnection` does that itself. When the current connection drops it reconnects
+//! with the controller's `ReconnectStrategy` (or, without one, its retries and exponential
+//! backoff), runs the caller's `on_reconnect` hook against the new connection before anyone
+//! else can use it, and carries on, so `send` and `recv` behave as if the connection never
+//! went away. The strategy's time budget and attempt timeout apply as well as its retries.
+//!
+//! A server close counts as a drop and is not passed to `recv`. Messages written to a
+//! connection in the moment before its drop is noticed may be lost; resend them from the
+//! hook if the protocol needs exactly-once delivery. `recv` returns `None` and `send` fails
+//! only after `shutdown`, after a reconnect runs out of retries, or when the server rejects
+//! the credentials.
+
+use crate::auth::is_auth_rejected;
+use crate::controller::WebSocketController;
+use crate::handle::ConnectionHandle;
+use crate::pipeline::PipelineConfig;
+use futures::future::BoxFuture;
+use crate::reconnection::RetryError;
+use log::info;
+use std::error::Error as StdError;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio_tungstenite::tungstenite::Message;
+
+/// A hook run against every new connection before it is used, e.g. to resubscribe.
+pub type OnReconnect = Arc<dyn Fn(ConnectionHandle) -> BoxFuture<'static, Result<(), String>> + Send + Sync>;
+
+/// A cloneable connection that reconnects whenever the underlying connection drops.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use websocket_toolkit::controller::WebSocketController;
+/// use websocket_toolkit::managed::ManagedConnection;
+/// use websocket_toolkit::pipeline::PipelineConfig;
+/// use websocket_toolkit::testing::EchoServer;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let server = EchoServer::start().await.unwrap();
+/// let controller = Arc::new(WebSocketController::new(server.url(), 3, None));
+/// let connection = ManagedConnection::connect(controller, PipelineConfig::default())
+///     .await
+///     .unwrap()
+///     .with_on_reconnect(|handle| Box::pin(async move { handle.send_text("subscribe").await }));
+///
+/// connection.send_text("hello").await.unwrap();
+/// assert!(connection.recv().await.is_some());
+/// connection.shutdown().await.unwrap();
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ManagedConnection {
+    inner: Arc<Inner>,
+}
+
+/// State shared by all clones.
+struct Inner {
+    controller: Arc<WebSocketController>,
+    config: PipelineConfig,
+    on_reconnect: std::sync::Mutex<Option<OnReconnect>>,
+    /// The current connection and its generation, bumped on every reconnect.
+    current: std::sync::Mutex<(u64, ConnectionHandle)>,
+    /// Held while reconnecting so concurrent callers reconnect only once.
+    reconnecting: Mutex<()>,
+    reconnects: AtomicU64,
+    shut_down: AtomicBool,
+    /// Set once a reconnect has given up; later calls fail without retrying.
+    failed: AtomicBool,
+}
+
+impl ManagedConnection {
+    /// Connects through the controller's `connect_handle`.
+    ///
+    /// The first connection is not retried; only drops after it succeeds are.
+    ///
+    /// # Arguments
+    ///
+    /// * `controller` - The controller used for this and every later connection.
+    /// * `config` - The pipeline settings for every connection.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the connected `ManagedConnection`, or the connection error.
+    pub async fn connect(
+        controller: Arc<WebSocketController>,
+        config: PipelineConfig,
+    ) -> Result<Self, Box<dyn StdError>> {
+        let handle = controller.connect_handle(config).await?;
+        Ok(ManagedConnection {
+            inner: Arc::new(Inner {
+                controller,
+                config,
+                on_reconnect: std::sync::Mutex::new(None),
+                current: std::sync::Mutex::new((0, handle)),
+                reconnecting: Mutex::new(()),
+                reconnects: AtomicU64::new(0),
+                shut_down: AtomicBool::new(false),
+                failed: AtomicBool::new(false),
+            }),
+        })
+    }
+
+    /// Sets the hook run against every new connection before `send` or `recv` use it.
+    ///
+    /// A hook error counts as a failed reconnection attempt and the connection is retried.
+    ///
+    /// # Arguments
+    ///
+    /// * `on_reconnect` - Called with the new connection's handle.
+    pub fn with_on_reconnect<F>(self, on_reconnect: F) -> Self
+    where
+        F: Fn(ConnectionHandle) -> BoxFuture<'static, Result<(), String>> + Send + Sync + 'static,
+    {
+        *self.inner.on_reconnect.lock().unwrap() = Some(Arc::new(on_reconnect));
+        self
+    }
+
+    /// Sends a message, reconnecting first if the connection has dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The frame to send.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if the connection was shut down or
+    /// could not be re-established.
+    pub async fn send(&self, message: Message) -> Result<(), String> {
+        loop {
+            let (generation, handle) = self.current()?;
+            if !handle.is_closed() && handle.send(message.clone()).await.is_ok() {
+                return Ok(());
+            }
+            self.reconnect(generation).await?;
+        }
+    }
+
+    /// Sends a text message, reconnecting first if the connection has dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The message text.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if the connection was shut down or
+    /// could not be re-established.
+    pub async fn send_text(&self, text: impl Into<String>) -> Result<(), String> {
+        self.send(Message::Text(text.into())).await
+    }
+
+    /// Sends a binary message, reconnecting first if the connection has dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The message bytes.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if the connection was shut down or
+    /// could not be re-established.
+    pub async fn send_binary(&self, payload: Vec<u8>) -> Result<(), String> {
+        self.send(Message::Binary(payload)).await
+    }
+
+    /// Receives the next message, reconnecting whenever the connection drops.
+    ///
+    /// # Returns
+    ///
+    /// The next text or binary message, or `None` once the connection was shut down or could
+    /// not be re-established.
+    pub async fn recv(&self) -> Option<Message> {
+        loop {
+            let (generation, handle) = self.current().ok()?;
+            match handle.recv().await {
+                Some(Message::Close(frame)) => info!("Server closed the connection ({:?}); reconnecting", frame),
+                Some(message) => return Some(message),
+                None => {}
+            }
+            if self.inner.shut_down.load(Ordering::Acquire) {
+                return None;
+            }
+            self.reconnect(generation).await.ok()?;
+        }
+    }
+
+    /// Returns the current connection's handle, e.g. to read its round-trip times.
+    ///
+    /// The handle is replaced on reconnect; do not keep it.
+    pub fn handle(&self) -> ConnectionHandle {
+        self.inner.current.lock().unwrap().1.clone()
+    }
+
+    /// Returns how many times the connection has been re-established.
+    pub fn reconnects(&self) -> u64 {
+        self.inner.reconnects.load(Ordering::Relaxed)
+    }
+
+    /// Closes the connection for good; `recv` then returns `None` and `send` fails.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if the close handshake failed.
+    pub async fn shutdown(&self) -> Result<(), String> {
+        self.inner.shut_down.store(true, Ordering::Release);
+        let _reconnecting = self.inner.reconnecting.lock().await;
+        self.handle().shutdown().await
+    }
+
+    /// Returns the current generation and handle, or an error once the connection is over.
+    fn current(&self) -> Result<(u64, ConnectionHandle), String> {
+        if self.inner.shut_down.load(Ordering::Acquire) {
+            return Err("Connection was shut down".to_string());
+        }
+        if self.inner.failed.load(Ordering::Acquire) {
+            return Err("Connection could not be re-established".to_string());
+        }
+        Ok(self.inner.current.lock().unwrap().clone())
+    }
+
+    /// Replaces the connection of `generation`, unless another caller already has.
+    async fn reconnect(&self, generation: u64) -> Result<(), String> {
+        let _reconnecting = self.inner.reconnecting.lock().await;
+        if self.inner.current.lock().unwrap().0 != generation {
+            return Ok(());
+        }
+        let (_, stale) = self.current()?;
+        stale.abort();
+
+        let controller = &self.inner.controller;
+        let fallback;
+        let strategy = match controller.reconnect_strategy() {
+            Some(strategy) => strategy,
+            None => {
+                fallback = controller.default_reconnect_strategy();
+                &fallback
+            }
+        };
+        let is_fatal = |e: &AttemptError| !matches!(e, AttemptError::Failed(_));
+        match strategy.retry(|| self.try_reconnect(), is_fatal).await {
+            Ok(handle) => {
+                *self.inner.current.lock().unwrap() = (generation + 1, handle);
+                self.inner.reconnects.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(RetryError::Fatal(AttemptError::ShutDown)) => Err("Connection was shut down".to_string()),
+            Err(e) => {
+                self.inner.failed.store(true, Ordering::Release);
+                Err(format!("Connection could not be re-established: {}", e))
+            }
+        }
+    }
+
+    /// Connects once and runs the hook against the new connection.
+    async fn try_reconnect(&self) -> Result<ConnectionHandle, AttemptError> {
+        if self.inner.shut_down.load(Ordering::Acquire) {
+            return Err(AttemptError::ShutDown);
+        }
+        let handle = match self.inner.controller.connect_handle(self.inner.config).await {
+            Ok(handle) => handle,
+            Err(e) if is_auth_rejected(e.as_ref()) => return Err(AttemptError::Rejected(e.to_string())),
+            Err(e) => return Err(AttemptError::Failed(e.to_string())),
+        };
+        let on_reconnect = self.inner.on_reconnect.lock().unwrap().clone();
+        if let Some(on_reconnect) = on_reconnect {
+            if let Err(e) = on_reconnect(handle.clone()).await {
+                handle.abort();
+                return Err(AttemptError::Failed(format!("Reconnect hook failed: {}", e)));
+            }
+        }
+        Ok(handle)
+    }
+}
+
+/// Why one reconnection attempt failed; only `Failed` is retried.
+enum AttemptError {
+    /// `shutdown` was called.
+    ShutDown,
+    /// The server rejected the credentials.
+    Rejected(String),
+    /// The connection or the hook failed.
+    Failed(String),
+}
+
+impl std::fmt::Display for AttemptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttemptError::ShutDown => write!(f, "Connection was shut down"),
+            AttemptError::Rejected(e) => write!(f, "Server rejected the credentials: {}", e),
+            AttemptError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reconnection::{Constant, ReconnectStrategy};
+    use crate::testing::MockServer;
+    use std::time::Duration;
+
+    /// Tests that a dropped connection is replaced, the hook replayed and callers unaffected.
+    #[tokio::test]
+    async fn test_managed_reconnect() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let controller = WebSocketController::new(server.url(), 3, None);
+        let connection = ManagedConnection::connect(Arc::new(controller), PipelineConfig::default())
+            .await
+            .unwrap()
+            .with_on_reconnect(|handle| Box::pin(async move { handle.send_text("resubscribe").await }));
+
+        let mut first = server.accept().await;
+        connection.send_text("one").await.unwrap();
+        first.assert_next_message_eq(Message::Text("one".into())).await;
+        first.close(1001, "going away").await;
+
+        let receiver = connection.clone();
+        let received = tokio::spawn(async move { receiver.recv().await });
+        let mut second = server.accept().await;
+        second.assert_next_message_eq(Message::Text("resubscribe".into())).await;
+        second.send(Message::Text("two".into())).await;
+        assert_eq!(received.await.unwrap(), Some(Message::Text("two".into())));
+        assert_eq!(connection.reconnects(), 1);
+
+        connection.send_text("three").await.unwrap();
+        second.assert_next_message_eq(Message::Text("three".into())).await;
+
+        // The server keeps reading so it answers the close.
+        let (shutdown, _) = tokio::join!(connection.shutdown(), second.collect_messages_for(Duration::from_secs(5)));
+        shutdown.unwrap();
+        assert_eq!(connection.recv().await, None);
+        assert!(connection.send_text("four").await.is_err());
+    }
+
+    /// Tests that reconnecting gives up once the strategy's time budget is spent, however
+    /// many retries are left.
+    #[tokio::test]
+    async fn test_managed_reconnect_deadline() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let mut controller = WebSocketController::new(server.url(), 3, None);
+        let strategy = ReconnectStrategy::new_with_backoff(1000, Constant(Duration::from_millis(50)))
+            .with_max_duration(Duration::from_millis(300));
+        controller.set_reconnect_strategy(Some(strategy));
+        let connection = ManagedConnection::connect(Arc::new(controller), PipelineConfig::default()).await.unwrap();
+        let first = server.accept().await;
+        drop(server);
+        drop(first);
+
+        let started = std::time::Instant::now();
+        let received = tokio::time::timeout(Duration::from_secs(5), connection.recv()).await;
+        assert_eq!(received.expect("Expected reconnection to give up within its budget"), None);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        let error = connection.send_text("late").await.unwrap_err();
+        assert!(error.contains("could not be re-established"), "Unexpected error: {}", error);
+        assert_eq!(connection.reconnects(), 0);
+    }
+}
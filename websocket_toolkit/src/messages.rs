@@ -1,169 +1,373 @@
-#![allow(unused_imports)]
-use serde::{Serialize, Deserialize};
-use log::{error, info};
-use arbitrary::Arbitrary;
+//! # `messages.rs`: message formats, codecs and envelopes
+//!
+//! The message types live in the `no_std + alloc` compatible `websocket_toolkit_core` crate so
+//! firmware that shares message definitions with a gateway can reuse the exact same types and
+//! codecs. This module re-exports them under their historical paths, and adds `InboundMessage`,
+//! the data message type the controller receives, with conversions to and from tungstenite's
+//! `Message` so the high-level and raw APIs can be mixed on the same connection.
+//!
+//! Strict servers expect JSON in text frames and reject it in binary ones. `FrameKind` picks the
+//! frame type for a codec (text for JSON, binary for CBOR by default), and `TextMode` chooses
+//! how received text is handed to the application: as the frame arrived, as validated or
+//! lossily decoded `String`s, or as raw bytes.
+//!
+//! Protobuf messages are not serde types, so they do not go through `MessageFormat`;
+//! `ProtoHandler` (feature `protobuf`) encodes and decodes prost-generated types, one per
+//! binary frame or several length-delimited in one. Avro needs a schema per value, so
+//! `AvroHandler` (feature `avro`, see the `avro` module) sits beside them as well.
 
-/// Implementation of the `Arbitrary` trait for `MessageFormat`.
+pub use websocket_toolkit_core::{Envelope, JsonNumbers, LazyEnvelope, MessageFormat, MessageHandler};
+
+#[cfg(feature = "avro")]
+pub use crate::avro::AvroHandler;
+
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::tungstenite::Message;
+
+/// A data message received from the server.
+///
+/// Control frames (ping, pong and close) are handled by the controller and never surface as
+/// an `InboundMessage`; use `WebSocketController::receive_raw` to observe them.
+///
+/// # Examples
+///
+/// ```rust
+/// use tokio_tungstenite::tungstenite::Message;
+/// use websocket_toolkit::messages::InboundMessage;
 ///
-/// This allows `MessageFormat` to be used in fuzz testing by generating random values.
-impl<'a> Arbitrary<'a> for MessageFormat {
-    /// Generates a random `MessageFormat`.
+/// let inbound = InboundMessage::try_from(Message::Text("hi".into())).unwrap();
+/// assert_eq!(inbound.as_bytes(), b"hi");
+/// assert_eq!(Message::from(inbound), Message::Text("hi".into()));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InboundMessage {
+    /// A UTF-8 text message.
+    Text(String),
+    /// A binary message.
+    Binary(Vec<u8>),
+}
+
+impl InboundMessage {
+    /// Returns the message payload as bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            InboundMessage::Text(text) => text.as_bytes(),
+            InboundMessage::Binary(data) => data,
+        }
+    }
+
+    /// Deserializes the payload as a `T` encoded in `format`, borrowing from the message.
+    ///
+    /// `T` may hold `&str` (and, for CBOR, `&[u8]`) fields that point into the received
+    /// payload instead of being copied into fresh `String`s. JSON strings containing escape
+    /// sequences cannot be borrowed; use `Cow<str>` fields to accept both.
     ///
     /// # Arguments
     ///
-    /// * `u` - The `Unstructured` data used to generate random values.
+    /// * `format` - The format of the payload.
     ///
     /// # Returns
     ///
-    /// A random instance of `MessageFormat`.
+    /// A `Result` containing the decoded value, or an error message on failure.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use serde::Deserialize;
+    /// use websocket_toolkit::messages::{InboundMessage, MessageFormat};
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Chat<'a> {
+    ///     user: &'a str,
+    /// }
+    ///
+    /// let inbound = InboundMessage::Text(r#"{"user":"ada"}"#.to_string());
+    /// let chat: Chat = inbound.decode(MessageFormat::Json).unwrap();
+    /// assert_eq!(chat.user, "ada");
+    /// ```
+    pub fn decode<'a, T: Deserialize<'a>>(&'a self, format: MessageFormat) -> Result<T, String> {
+        MessageHandler::deserialize(self.as_bytes(), format)?.ok_or_else(|| "Message payload is empty".to_string())
+    }
+
+    /// Parses the message as an envelope encoded in `format`, leaving the payload encoded
+    /// until it is asked for.
     ///
-    /// # Errors
+    /// This skips the controller's compression, replay protection, migrations and inbound
+    /// maps; pass `raw()` to `WebSocketController::decode_envelope` for a message that is
+    /// kept and needs them.
     ///
-    /// Returns an error if random generation fails.
-    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
-        let choice = u.int_in_range(0..=1)?;
-        match choice {
-            0 => Ok(MessageFormat::Json),
-            1 => Ok(MessageFormat::Cbor),
-            _ => unreachable!(),
+    /// # Arguments
+    ///
+    /// * `format` - The wire format.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the envelope, or an error message if the message is not an
+    /// envelope.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit::messages::{Envelope, InboundMessage, MessageFormat};
+    ///
+    /// let encoded = Envelope::new("heartbeat", vec![0; 4096]).encode(MessageFormat::Cbor).unwrap();
+    /// let inbound = InboundMessage::Binary(encoded);
+    /// let envelope = inbound.lazy_envelope(MessageFormat::Cbor).unwrap();
+    /// assert_eq!(envelope.kind, "heartbeat");
+    /// ```
+    pub fn lazy_envelope(&self, format: MessageFormat) -> Result<LazyEnvelope<'_>, String> {
+        LazyEnvelope::parse(self.as_bytes(), format)
+    }
+
+    /// Consumes the message and returns its payload as bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            InboundMessage::Text(text) => text.into_bytes(),
+            InboundMessage::Binary(data) => data,
         }
     }
 }
 
-/// Enum representing the supported message formats for serialization and deserialization.
-///
-/// This enum is used to specify whether messages should be serialized or deserialized
-/// in JSON or CBOR formats.
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
-pub enum MessageFormat {
-    /// JSON format.
-    Json,
-    /// CBOR format.
-    Cbor,
+impl From<InboundMessage> for Message {
+    fn from(message: InboundMessage) -> Self {
+        match message {
+            InboundMessage::Text(text) => Message::Text(text),
+            InboundMessage::Binary(data) => Message::Binary(data),
+        }
+    }
 }
 
-/// A handler for serializing and deserializing messages.
-///
-/// Provides utility functions to handle message encoding and decoding in JSON and CBOR formats.
-pub struct MessageHandler;
+impl TryFrom<Message> for InboundMessage {
+    /// The control frame that could not be converted, returned unchanged.
+    type Error = Message;
+
+    /// Converts a text or binary frame; ping, pong and close frames are returned as the error.
+    fn try_from(message: Message) -> Result<Self, Self::Error> {
+        match message {
+            Message::Text(text) => Ok(InboundMessage::Text(text)),
+            Message::Binary(data) => Ok(InboundMessage::Binary(data)),
+            other => Err(other),
+        }
+    }
+}
+
+/// The frame type a payload is sent in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameKind {
+    /// A text frame; the payload must be valid UTF-8.
+    Text,
+    /// A binary frame.
+    Binary,
+}
 
-impl MessageHandler {
-    /// Serializes the given data into the specified format.
+impl FrameKind {
+    /// Returns the conventional frame type for `format`: text for JSON, binary for CBOR.
+    pub fn for_format(format: MessageFormat) -> FrameKind {
+        match format {
+            MessageFormat::Json => FrameKind::Text,
+            MessageFormat::Cbor => FrameKind::Binary,
+        }
+    }
+
+    /// Wraps `payload` in a frame of this type.
     ///
     /// # Arguments
     ///
-    /// * `data` - The data to serialize.
-    /// * `format` - The format to serialize the data into (`MessageFormat::Json` or `MessageFormat::Cbor`).
+    /// * `payload` - The encoded message.
     ///
     /// # Returns
     ///
-    /// A `Result` containing the serialized data as a `Vec<u8>` on success, or an error message as a `String` on failure.
+    /// A `Result` containing the frame, or an error message if a text frame is requested for
+    /// a payload that is not valid UTF-8.
     ///
     /// # Examples
     ///
     /// ```rust
-    /// use websocket_toolkit::messages::{MessageHandler, MessageFormat};
+    /// use tokio_tungstenite::tungstenite::Message;
+    /// use websocket_toolkit::messages::{FrameKind, MessageFormat};
     ///
-    /// let message = "Hello, WebSocket!";
-    /// let serialized = MessageHandler::serialize(&message, MessageFormat::Json).unwrap();
-    /// assert!(!serialized.is_empty());
+    /// let kind = FrameKind::for_format(MessageFormat::Json);
+    /// assert_eq!(kind.frame(b"{}".to_vec()).unwrap(), Message::Text("{}".into()));
+    /// assert!(kind.frame(vec![0xff]).is_err());
     /// ```
-    pub fn serialize<T: Serialize>(data: &T, format: MessageFormat) -> Result<Vec<u8>, String> {
-        match format {
-            MessageFormat::Json => Self::private_serialize_json(data),
-            MessageFormat::Cbor => Self::private_serialize_cbor(data),
+    pub fn frame(self, payload: Vec<u8>) -> Result<Message, String> {
+        match self {
+            FrameKind::Text => String::from_utf8(payload)
+                .map(Message::Text)
+                .map_err(|e| format!("Failed to send payload as text: {}", e)),
+            FrameKind::Binary => Ok(Message::Binary(payload)),
         }
     }
+}
 
-    /// Deserializes the given byte slice into the specified type.
+/// How received data messages are handed to the application.
+///
+/// tungstenite already rejects text frames that are not valid UTF-8, so `Validated` and
+/// `Lossy` differ only for servers that send text in binary frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextMode {
+    /// Text frames as `InboundMessage::Text`, binary frames as `InboundMessage::Binary`.
+    #[default]
+    Preserve,
+    /// Every message as `InboundMessage::Text`; a binary frame that is not valid UTF-8 is an
+    /// error.
+    Validated,
+    /// Every message as `InboundMessage::Text`, replacing invalid UTF-8 with `U+FFFD`.
+    Lossy,
+    /// Every message as `InboundMessage::Binary`, without UTF-8 handling.
+    Raw,
+}
+
+impl TextMode {
+    /// Converts a received message according to the mode.
     ///
     /// # Arguments
     ///
-    /// * `data` - The byte slice containing the serialized data.
-    /// * `format` - The format of the serialized data (`MessageFormat::Json` or `MessageFormat::Cbor`).
+    /// * `message` - The message as received.
     ///
     /// # Returns
     ///
-    /// A `Result` containing the deserialized data as an `Option<T>` on success, or an error message as a `String` on failure.
+    /// A `Result` containing the converted message, or an error message if `Validated` meets
+    /// invalid UTF-8.
     ///
     /// # Examples
     ///
     /// ```rust
-    /// use websocket_toolkit::messages::{MessageHandler, MessageFormat};
+    /// use websocket_toolkit::messages::{InboundMessage, TextMode};
     ///
-    /// let serialized = b"\"Hello, WebSocket!\"";
-    /// let deserialized: Option<String> = MessageHandler::deserialize(serialized, MessageFormat::Json).unwrap();
-    /// assert_eq!(deserialized, Some("Hello, WebSocket!".to_string()));
+    /// let invalid = InboundMessage::Binary(vec![b'h', b'i', 0xff]);
+    /// assert!(TextMode::Validated.apply(invalid.clone()).is_err());
+    /// assert_eq!(TextMode::Lossy.apply(invalid).unwrap(), InboundMessage::Text("hi\u{fffd}".into()));
     /// ```
-    pub fn deserialize<'a, T: Deserialize<'a>>(data: &'a [u8], format: MessageFormat) -> Result<Option<T>, String> {
-        match format {
-            MessageFormat::Json => Self::private_deserialize_json(data),
-            MessageFormat::Cbor => Self::private_deserialize_cbor(data),
+    pub fn apply(self, message: InboundMessage) -> Result<InboundMessage, String> {
+        match (self, message) {
+            (TextMode::Validated, InboundMessage::Binary(data)) => String::from_utf8(data)
+                .map(InboundMessage::Text)
+                .map_err(|e| format!("Failed to read message as text: {}", e)),
+            (TextMode::Lossy, InboundMessage::Binary(data)) => Ok(InboundMessage::Text(match String::from_utf8(data) {
+                Ok(text) => text,
+                Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+            })),
+            (TextMode::Raw, InboundMessage::Text(text)) => Ok(InboundMessage::Binary(text.into_bytes())),
+            (_, message) => Ok(message),
         }
     }
+}
+
+/// Encodes and decodes Protobuf messages of prost-generated types.
+///
+/// Streaming protocols often batch several messages in one binary frame, each prefixed with
+/// its length as a varint (the framing of Java's `writeDelimitedTo` and of prost's
+/// `encode_length_delimited`); `encode_delimited` and `decode_delimited` write and read that
+/// framing.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::messages::ProtoHandler;
+///
+/// #[derive(Clone, PartialEq, prost::Message)]
+/// struct Trade {
+///     #[prost(string, tag = "1")]
+///     symbol: String,
+///     #[prost(double, tag = "2")]
+///     price: f64,
+/// }
+///
+/// let trades = vec![
+///     Trade { symbol: "BTC".into(), price: 64000.0 },
+///     Trade { symbol: "ETH".into(), price: 3100.5 },
+/// ];
+/// let frame = ProtoHandler::frame_delimited(&trades);
+/// assert_eq!(ProtoHandler::decode_delimited::<Trade>(frame.into_data().as_slice()).unwrap(), trades);
+/// ```
+#[cfg(feature = "protobuf")]
+pub struct ProtoHandler;
 
-    /// Serializes the data to JSON format.
+#[cfg(feature = "protobuf")]
+impl ProtoHandler {
+    /// Encodes `message`.
     ///
     /// # Arguments
     ///
-    /// * `data` - The data to serialize.
+    /// * `message` - The message to encode.
     ///
     /// # Returns
     ///
-    /// A `Result` containing the serialized JSON as a `Vec<u8>` on success, or an error message on failure.
-    fn private_serialize_json<T: Serialize>(data: &T) -> Result<Vec<u8>, String> {
-        serde_json::to_vec(data).map_err(|e| {
-            error!("Failed to serialize JSON: {}", e);
-            format!("Failed to serialize JSON: {}", e)
-        })
+    /// The encoded message.
+    pub fn serialize<M: prost::Message>(message: &M) -> Vec<u8> {
+        message.encode_to_vec()
     }
 
-    /// Serializes the data to CBOR format.
+    /// Decodes a single message that fills `data`.
     ///
     /// # Arguments
     ///
-    /// * `data` - The data to serialize.
+    /// * `data` - The encoded message, e.g. a binary frame's payload.
     ///
     /// # Returns
     ///
-    /// A `Result` containing the serialized CBOR as a `Vec<u8>` on success, or an error message on failure.
-    fn private_serialize_cbor<T: Serialize>(data: &T) -> Result<Vec<u8>, String> {
-        serde_cbor::to_vec(data).map_err(|e| {
-            error!("Failed to serialize CBOR: {}", e);
-            format!("Failed to serialize CBOR: {}", e)
-        })
+    /// A `Result` containing the message, or an error message if `data` is not a valid `M`.
+    pub fn deserialize<M: prost::Message + Default>(data: &[u8]) -> Result<M, String> {
+        M::decode(data).map_err(|e| format!("Failed to deserialize Protobuf: {}", e))
     }
 
-    /// Deserializes data from JSON format.
+    /// Encodes `messages` one after another, each prefixed with its varint length.
     ///
     /// # Arguments
     ///
-    /// * `data` - The byte slice containing the serialized JSON data.
+    /// * `messages` - The messages to encode, in order.
     ///
     /// # Returns
     ///
-    /// A `Result` containing the deserialized data as an `Option<T>` on success, or an error message on failure.
-    fn private_deserialize_json<'a, T: Deserialize<'a>>(data: &'a [u8]) -> Result<Option<T>, String> {
-        serde_json::from_slice(data).map(|v| Some(v)).map_err(|e| {
-            error!("Failed to deserialize JSON: {}", e);
-            format!("Failed to deserialize JSON: {}", e)
-        })
+    /// The length-delimited messages.
+    pub fn encode_delimited<'a, M, I>(messages: I) -> Vec<u8>
+    where
+        M: prost::Message + 'a,
+        I: IntoIterator<Item = &'a M>,
+    {
+        let mut data = Vec::new();
+        for message in messages {
+            data.extend_from_slice(&message.encode_length_delimited_to_vec());
+        }
+        data
     }
 
-    /// Deserializes data from CBOR format.
+    /// Decodes every length-delimited message in `data`.
     ///
     /// # Arguments
     ///
-    /// * `data` - The byte slice containing the serialized CBOR data.
+    /// * `data` - Messages each prefixed with its varint length, e.g. a binary frame's
+    ///   payload.
     ///
     /// # Returns
     ///
-    /// A `Result` containing the deserialized data as an `Option<T>` on success, or an error message on failure.
-    fn private_deserialize_cbor<'a, T: Deserialize<'a>>(data: &'a [u8]) -> Result<Option<T>, String> {
-        serde_cbor::from_slice(data).map(|v| Some(v)).map_err(|e| {
-            error!("Failed to deserialize CBOR: {}", e);
-            format!("Failed to deserialize CBOR: {}", e)
-        })
+    /// A `Result` containing the messages in order, or an error message naming the first
+    /// message that is truncated or not a valid `M`.
+    pub fn decode_delimited<M: prost::Message + Default>(mut data: &[u8]) -> Result<Vec<M>, String> {
+        let mut messages = Vec::new();
+        while !data.is_empty() {
+            let message = M::decode_length_delimited(&mut data)
+                .map_err(|e| format!("Failed to deserialize Protobuf message {}: {}", messages.len(), e))?;
+            messages.push(message);
+        }
+        Ok(messages)
+    }
+
+    /// Encodes `message` in a binary frame.
+    pub fn frame<M: prost::Message>(message: &M) -> Message {
+        Message::Binary(Self::serialize(message))
+    }
+
+    /// Encodes `messages`, length-delimited, in one binary frame.
+    pub fn frame_delimited<'a, M, I>(messages: I) -> Message
+    where
+        M: prost::Message + 'a,
+        I: IntoIterator<Item = &'a M>,
+    {
+        Message::Binary(Self::encode_delimited(messages))
     }
 }
 
@@ -171,31 +375,83 @@ impl MessageHandler {
 mod tests {
     use super::*;
 
-    /// Tests JSON serialization and deserialization.
+    /// Tests that data frames convert both ways and control frames are handed back.
+    #[test]
+    fn test_inbound_message_conversions() {
+        let binary = InboundMessage::try_from(Message::Binary(vec![1, 2])).unwrap();
+        assert_eq!(binary, InboundMessage::Binary(vec![1, 2]));
+        assert_eq!(Message::from(binary.clone()), Message::Binary(vec![1, 2]));
+        assert_eq!(binary.into_bytes(), vec![1, 2]);
+
+        let ping = Message::Ping(vec![9]);
+        assert_eq!(InboundMessage::try_from(ping.clone()), Err(ping));
+        assert_eq!(InboundMessage::try_from(Message::Close(None)), Err(Message::Close(None)));
+    }
+
+    /// Tests every text mode on text, valid binary and invalid binary messages.
+    #[test]
+    fn test_text_modes() {
+        let text = InboundMessage::Text("héllo".to_string());
+        let valid = InboundMessage::Binary("héllo".as_bytes().to_vec());
+        let invalid = InboundMessage::Binary(vec![b'a', 0xc3]);
+
+        for message in [&text, &valid, &invalid] {
+            assert_eq!(TextMode::Preserve.apply(message.clone()).unwrap(), *message);
+            assert_eq!(TextMode::Raw.apply(message.clone()).unwrap().as_bytes(), message.as_bytes());
+        }
+        assert_eq!(TextMode::Raw.apply(text.clone()).unwrap(), valid);
+        assert_eq!(TextMode::Validated.apply(valid.clone()).unwrap(), text);
+        assert!(TextMode::Validated.apply(invalid.clone()).unwrap_err().contains("Failed to read message as text"));
+        assert_eq!(TextMode::Lossy.apply(valid).unwrap(), text);
+        assert_eq!(TextMode::Lossy.apply(invalid).unwrap(), InboundMessage::Text("a\u{fffd}".to_string()));
+        assert_eq!(FrameKind::for_format(MessageFormat::Cbor).frame(vec![0xff]).unwrap(), Message::Binary(vec![0xff]));
+    }
+
+    /// Tests that decoded `&str` fields point into the received payload.
+    #[cfg(all(feature = "json", feature = "cbor"))]
     #[test]
-    fn test_json_serialization() {
-        let message = "Hello, WebSocket!";
-        let serialized = MessageHandler::serialize(&message, MessageFormat::Json);
-        assert!(serialized.is_ok(), "Expected successful JSON serialization");
-        let serialized = serialized.unwrap();
-        assert!(!serialized.is_empty(), "Expected non-empty JSON serialized data");
+    fn test_decode_borrows_from_payload() {
+        #[derive(serde::Serialize, Deserialize)]
+        struct Chat<'a> {
+            user: &'a str,
+            body: &'a str,
+        }
 
-        let deserialized: Result<Option<String>, String> = MessageHandler::deserialize(&serialized, MessageFormat::Json);
-        assert!(deserialized.is_ok(), "Expected successful JSON deserialization");
-        assert_eq!(deserialized.unwrap(), Some(message.to_string()), "Expected deserialized JSON to match original message");
+        let text = InboundMessage::Text(r#"{"user":"ada","body":"hello"}"#.to_string());
+        let chat: Chat = text.decode(MessageFormat::Json).unwrap();
+        let payload = text.as_bytes().as_ptr_range();
+        assert!(payload.contains(&chat.body.as_ptr()), "Expected body to borrow from the payload");
+
+        let encoded = MessageHandler::serialize(&Chat { user: "ada", body: "hello" }, MessageFormat::Cbor).unwrap();
+        let binary = InboundMessage::Binary(encoded);
+        let chat: Chat = binary.decode(MessageFormat::Cbor).unwrap();
+        assert_eq!((chat.user, chat.body), ("ada", "hello"));
+        assert!(binary.as_bytes().as_ptr_range().contains(&chat.user.as_ptr()));
     }
 
-    /// Tests CBOR serialization and deserialization.
+    /// Tests single and length-delimited Protobuf round trips, and truncated input.
+    #[cfg(feature = "protobuf")]
     #[test]
-    fn test_cbor_serialization() {
-        let message = "Hello, WebSocket!";
-        let serialized = MessageHandler::serialize(&message, MessageFormat::Cbor);
-        assert!(serialized.is_ok(), "Expected successful CBOR serialization");
-        let serialized = serialized.unwrap();
-        assert!(!serialized.is_empty(), "Expected non-empty CBOR serialized data");
-
-        let deserialized: Result<Option<String>, String> = MessageHandler::deserialize(&serialized, MessageFormat::Cbor);
-        assert!(deserialized.is_ok(), "Expected successful CBOR deserialization");
-        assert_eq!(deserialized.unwrap(), Some(message.to_string()), "Expected deserialized CBOR to match original message");
+    fn test_protobuf() {
+        #[derive(Clone, PartialEq, prost::Message)]
+        struct Order {
+            #[prost(uint64, tag = "1")]
+            id: u64,
+            #[prost(string, tag = "2")]
+            side: String,
+        }
+
+        let order = Order { id: 7, side: "buy".into() };
+        let frame = ProtoHandler::frame(&order);
+        let inbound = InboundMessage::try_from(frame).unwrap();
+        assert_eq!(ProtoHandler::deserialize::<Order>(inbound.as_bytes()).unwrap(), order);
+
+        let orders: Vec<Order> = (0..3).map(|id| Order { id, side: "sell".into() }).collect();
+        let data = ProtoHandler::encode_delimited(&orders);
+        assert_eq!(ProtoHandler::decode_delimited::<Order>(&data).unwrap(), orders);
+        assert!(ProtoHandler::decode_delimited::<Order>(&[]).unwrap().is_empty());
+
+        let truncated = &data[..data.len() - 1];
+        assert!(ProtoHandler::decode_delimited::<Order>(truncated).unwrap_err().contains("message 2"));
     }
 }
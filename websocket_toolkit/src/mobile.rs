@@ -0,0 +1,426 @@
+//! # `mobile.rs`: uniffi bindings for iOS and Android
+//!
+//! This module implements the interface declared in `src/websocket_toolkit.udl`: a blocking
+//! `MobileController` covering connect/send/receive/reconnect, the message `Envelope`, and a
+//! `ReconnectConfig`. Swift and Kotlin bindings are generated from the compiled library with
+//! `uniffi-bindgen`; the scaffolding itself is included at the crate root. Each controller
+//! owns a tokio runtime that also drives keep-alive pings.
+//! Connections run on a `pipeline`, so a `receive` waiting for a message never holds up
+//! sends or pings from other threads.
+
+use crate::connection::WebSocketClient;
+use crate::messages::{Envelope as WireEnvelope, MessageFormat};
+use crate::pipeline::{self, PipelineConfig, PipelineReceiver, PipelineSender, PipelineTasks};
+use crate::reconnection::{ReconnectStrategy, RetryError};
+use crate::tasks::spawn_named_on;
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::Mutex;
+use tokio_tungstenite::tungstenite::Message;
+
+/// The uniffi scaffolding generated from `src/websocket_toolkit.udl` by the build script.
+#[allow(clippy::empty_line_after_doc_comments)]
+mod scaffolding {
+    use super::*;
+    uniffi::include_scaffolding!("websocket_toolkit");
+}
+
+/// The uniffi type tag, re-exported at the crate root where the scaffolding expects it.
+pub(crate) use scaffolding::UniFfiTag;
+
+/// The open connection of a `MobileController`.
+struct Link {
+    /// Queues frames and pings for the writer task.
+    sender: PipelineSender,
+    /// Messages from the reader task; `receive` holds the lock while it waits.
+    receiver: Arc<Mutex<PipelineReceiver>>,
+    /// The reader and writer tasks.
+    tasks: PipelineTasks,
+}
+
+/// A message envelope as exposed to Swift and Kotlin.
+///
+/// This mirrors `messages::Envelope`, except that the headers are a `HashMap`, the only map
+/// type uniffi can pass.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    /// The message type used for routing.
+    pub kind: String,
+    /// An optional id correlating requests and replies.
+    pub id: Option<String>,
+    /// The schema version of the payload, or `None` for unversioned envelopes.
+    pub version: Option<u32>,
+    /// When the envelope was sent, in milliseconds since the Unix epoch.
+    pub timestamp: Option<u64>,
+    /// A value the sender never reuses, identifying the envelope for replay protection.
+    pub nonce: Option<u64>,
+    /// Metadata such as trace context.
+    pub headers: HashMap<String, String>,
+    /// The encoded message body.
+    pub payload: Vec<u8>,
+}
+
+impl From<WireEnvelope> for Envelope {
+    fn from(envelope: WireEnvelope) -> Self {
+        Envelope {
+            kind: envelope.kind,
+            id: envelope.id,
+            version: envelope.version,
+            timestamp: envelope.timestamp,
+            nonce: envelope.nonce,
+            headers: envelope.headers.into_iter().collect(),
+            payload: envelope.payload,
+        }
+    }
+}
+
+impl From<Envelope> for WireEnvelope {
+    fn from(envelope: Envelope) -> Self {
+        WireEnvelope {
+            kind: envelope.kind,
+            id: envelope.id,
+            version: envelope.version,
+            timestamp: envelope.timestamp,
+            nonce: envelope.nonce,
+            headers: envelope.headers.into_iter().collect(),
+            payload: envelope.payload,
+        }
+    }
+}
+
+/// Reconnection and keep-alive settings for a `MobileController`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconnectConfig {
+    /// The maximum number of reconnection attempts.
+    pub retries: u32,
+    /// The base delay in seconds between attempts; attempt `n` waits `n` times this long.
+    pub base_delay_secs: u64,
+    /// The keep-alive ping interval in seconds, or `None` to disable pings.
+    pub ping_interval_secs: Option<u64>,
+}
+
+/// Errors reported to Swift and Kotlin callers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolkitError {
+    /// Connecting or sending failed.
+    Connection(String),
+    /// A payload could not be encoded or decoded.
+    Serialization(String),
+    /// The controller has no open connection.
+    NotConnected,
+    /// The server closed the connection.
+    Closed,
+}
+
+impl fmt::Display for ToolkitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolkitError::Connection(reason) => write!(f, "Connection error: {}", reason),
+            ToolkitError::Serialization(reason) => write!(f, "Serialization error: {}", reason),
+            ToolkitError::NotConnected => write!(f, "Not connected"),
+            ToolkitError::Closed => write!(f, "Connection closed by server"),
+        }
+    }
+}
+
+impl std::error::Error for ToolkitError {}
+
+/// Serializes an envelope in the given wire format.
+///
+/// # Arguments
+///
+/// * `envelope` - The envelope to encode.
+/// * `format` - The wire format.
+///
+/// # Returns
+///
+/// A `Result` containing the encoded bytes or a `ToolkitError::Serialization`.
+pub fn encode_envelope(envelope: Envelope, format: MessageFormat) -> Result<Vec<u8>, ToolkitError> {
+    WireEnvelope::from(envelope).encode(format).map_err(ToolkitError::Serialization)
+}
+
+/// Deserializes an envelope encoded in the given wire format.
+///
+/// # Arguments
+///
+/// * `data` - The encoded envelope.
+/// * `format` - The wire format.
+///
+/// # Returns
+///
+/// A `Result` containing the envelope or a `ToolkitError::Serialization`.
+pub fn decode_envelope(data: Vec<u8>, format: MessageFormat) -> Result<Envelope, ToolkitError> {
+    WireEnvelope::decode(&data, format)
+        .map(Envelope::from)
+        .map_err(ToolkitError::Serialization)
+}
+
+/// A blocking WebSocket controller for mobile apps.
+///
+/// Every method blocks the calling thread on the controller's runtime, so apps should call
+/// them from a background thread. Methods may be called concurrently from several threads.
+pub struct MobileController {
+    runtime: Runtime,
+    client: WebSocketClient,
+    strategy: ReconnectStrategy,
+    link: Arc<std::sync::Mutex<Option<Link>>>,
+}
+
+impl MobileController {
+    /// Creates a controller for `url`. No connection is made until `connect` is called.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The WebSocket server URL.
+    /// * `config` - Reconnection and keep-alive settings.
+    ///
+    /// # Returns
+    ///
+    /// A new `MobileController`.
+    pub fn new(url: String, config: ReconnectConfig) -> Self {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .expect("Failed to start the controller runtime");
+        let link: Arc<std::sync::Mutex<Option<Link>>> = Arc::new(std::sync::Mutex::new(None));
+
+        if let Some(secs) = config.ping_interval_secs.filter(|secs| *secs > 0) {
+            let link = link.clone();
+            let keep_alive = async move {
+                let mut ticker = tokio::time::interval(Duration::from_secs(secs));
+                ticker.tick().await;
+                loop {
+                    ticker.tick().await;
+                    let sender = link.lock().unwrap().as_ref().map(|link| link.sender.clone());
+                    if let Some(sender) = sender {
+                        if sender.ping().await.is_err() {
+                            error!("Ping failed: the connection has closed");
+                        }
+                    }
+                }
+            };
+            spawn_named_on("websocket_toolkit::mobile_keep_alive", keep_alive, runtime.handle());
+        }
+
+        MobileController {
+            runtime,
+            client: WebSocketClient::new(&url, config.retries),
+            strategy: ReconnectStrategy::new(config.retries, config.base_delay_secs),
+            link,
+        }
+    }
+
+    /// Opens the connection, replacing any existing one.
+    pub fn connect(&self) -> Result<(), ToolkitError> {
+        self.runtime.block_on(self.open())
+    }
+
+    /// Sends `data` as a binary message.
+    pub fn send(&self, data: Vec<u8>) -> Result<(), ToolkitError> {
+        self.send_frame(Message::Binary(data))
+    }
+
+    /// Encodes `envelope` in `format` and sends it.
+    pub fn send_envelope(&self, envelope: Envelope, format: MessageFormat) -> Result<(), ToolkitError> {
+        let data = encode_envelope(envelope, format)?;
+        self.send_frame(match format {
+            MessageFormat::Json => Message::Text(String::from_utf8_lossy(&data).into_owned()),
+            MessageFormat::Cbor => Message::Binary(data),
+        })
+    }
+
+    /// Waits up to `timeout_ms` for the next text or binary message.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(None)` if nothing arrived in time, the payload otherwise.
+    pub fn receive(&self, timeout_ms: u64) -> Result<Option<Vec<u8>>, ToolkitError> {
+        self.runtime.block_on(async {
+            let deadline = tokio::time::Instant::now() + Duration::from_millis(timeout_ms);
+            loop {
+                let receiver = self
+                    .link
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .map(|link| link.receiver.clone())
+                    .ok_or(ToolkitError::NotConnected)?;
+                let next = match tokio::time::timeout_at(deadline, async { receiver.lock().await.recv().await }).await {
+                    Ok(next) => next,
+                    Err(_) => return Ok(None),
+                };
+                match next {
+                    Some(Message::Text(text)) => return Ok(Some(text.into_bytes())),
+                    Some(Message::Binary(data)) => return Ok(Some(data)),
+                    Some(Message::Ping(_)) | Some(Message::Pong(_)) => continue,
+                    Some(Message::Close(_)) | None => {
+                        let mut link = self.link.lock().unwrap();
+                        // A connection replaced by `connect` or `network_changed` ends too;
+                        // keep waiting on its replacement.
+                        if !link.as_ref().is_some_and(|link| Arc::ptr_eq(&link.receiver, &receiver)) {
+                            continue;
+                        }
+                        *link = None;
+                        return Err(ToolkitError::Closed);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Waits up to `timeout_ms` for the next message and decodes it as an envelope.
+    pub fn receive_envelope(&self, format: MessageFormat, timeout_ms: u64) -> Result<Option<Envelope>, ToolkitError> {
+        self.receive(timeout_ms)?
+            .map(|data| decode_envelope(data, format))
+            .transpose()
+    }
+
+    /// Reconnects, retrying with a linearly increasing delay up to the configured retries.
+    pub fn reconnect(&self) -> Result<(), ToolkitError> {
+        self.runtime.block_on(async {
+            match self.strategy.retry(|| self.open(), |_| false).await {
+                Ok(()) => Ok(()),
+                Err(RetryError::Fatal(e)) => Err(e),
+                Err(RetryError::GaveUp(e)) => {
+                    warn!("Reconnection failed: {}", e);
+                    Err(ToolkitError::Connection(e.to_string()))
+                }
+            }
+        })
+    }
+
+    /// Moves the connection onto the current network. Call it from the platform's network
+    /// callback (`NWPathMonitor`, `ConnectivityManager.NetworkCallback`) when the default
+    /// route changes, e.g. from Wi-Fi to cellular.
+    ///
+    /// The new connection is opened before the old one is replaced, so sends keep working
+    /// throughout; the old one is closed in the background. Does nothing when not connected.
+    pub fn network_changed(&self) -> Result<(), ToolkitError> {
+        if !self.is_connected() {
+            return Ok(());
+        }
+        info!("Network changed; migrating connection");
+        self.runtime.block_on(self.open())
+    }
+
+    /// Returns whether a connection is currently open.
+    pub fn is_connected(&self) -> bool {
+        self.link.lock().unwrap().is_some()
+    }
+
+    /// Closes the connection, if any.
+    pub fn disconnect(&self) {
+        let link = self.link.lock().unwrap().take();
+        if let Some(Link { sender, tasks, .. }) = link {
+            // The writer sends the close frame once its last sender is gone.
+            drop(sender);
+            let _ = self.runtime.block_on(tasks.writer);
+        }
+        self.client.disconnect();
+    }
+
+    /// Connects and starts the connection's pipeline, replacing the current connection.
+    ///
+    /// The previous connection's writer closes it in the background once its sender is
+    /// dropped, so sends keep working until the new connection is in place.
+    async fn open(&self) -> Result<(), ToolkitError> {
+        let ws_stream = self
+            .client
+            .connect()
+            .await
+            .map_err(|e| ToolkitError::Connection(e.to_string()))?;
+        let (sender, receiver, tasks) = pipeline::spawn(ws_stream, PipelineConfig::default());
+        let link = Link { sender, receiver: Arc::new(Mutex::new(receiver)), tasks };
+        let previous = self.link.lock().unwrap().replace(link);
+        drop(previous);
+        Ok(())
+    }
+
+    /// Sends one frame on the open connection.
+    fn send_frame(&self, message: Message) -> Result<(), ToolkitError> {
+        let sender = self
+            .link
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|link| link.sender.clone())
+            .ok_or(ToolkitError::NotConnected)?;
+        self.runtime.block_on(async {
+            sender
+                .send(message)
+                .await
+                .map_err(|_| ToolkitError::Connection("The connection has closed".to_string()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::EchoServer;
+
+    /// Returns a config with a single retry, no backoff delay and no pings.
+    fn config() -> ReconnectConfig {
+        ReconnectConfig {
+            retries: 1,
+            base_delay_secs: 0,
+            ping_interval_secs: None,
+        }
+    }
+
+    /// Tests sending and receiving an envelope through the blocking controller.
+    #[test]
+    fn test_mobile_controller_envelope_round_trip() {
+        let server_runtime = Runtime::new().unwrap();
+        let server = server_runtime.block_on(EchoServer::start()).expect("Failed to start echo server");
+        let controller = MobileController::new(server.url().to_string(), config());
+
+        assert_eq!(controller.send(vec![1]), Err(ToolkitError::NotConnected));
+        controller.connect().expect("Failed to connect");
+        assert!(controller.is_connected());
+
+        let mut envelope = Envelope::from(WireEnvelope::new("chat", b"hi".to_vec()).with_id("7"));
+        envelope.headers.insert("traceparent".to_string(), "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".to_string());
+        controller.send_envelope(envelope.clone(), MessageFormat::Json).unwrap();
+        let received = controller.receive_envelope(MessageFormat::Json, 2000).unwrap();
+        assert_eq!(received, Some(envelope));
+        assert_eq!(controller.receive(50).unwrap(), None, "Expected a timeout with no traffic");
+
+        controller.disconnect();
+        assert!(!controller.is_connected());
+    }
+
+    /// Tests that a receive waiting on one thread does not hold up a send from another.
+    #[test]
+    fn test_mobile_controller_concurrent_receive_and_send() {
+        let server_runtime = Runtime::new().unwrap();
+        let server = server_runtime.block_on(EchoServer::start()).expect("Failed to start echo server");
+        let controller = Arc::new(MobileController::new(server.url().to_string(), config()));
+        controller.connect().expect("Failed to connect");
+
+        let receiving = controller.clone();
+        let receiver = std::thread::spawn(move || receiving.receive(5000));
+        std::thread::sleep(Duration::from_millis(100));
+        let started = std::time::Instant::now();
+        controller.send(vec![1, 2, 3]).unwrap();
+        assert_eq!(receiver.join().unwrap().unwrap(), Some(vec![1, 2, 3]));
+        assert!(started.elapsed() < Duration::from_secs(2), "Expected the send not to wait for the receive");
+
+        controller.network_changed().expect("Failed to migrate");
+        controller.send(vec![4]).unwrap();
+        assert_eq!(controller.receive(2000).unwrap(), Some(vec![4]));
+        controller.disconnect();
+    }
+
+    /// Tests that reconnecting to an unreachable server fails after the configured retries.
+    #[test]
+    fn test_mobile_controller_reconnect_failure() {
+        let controller = MobileController::new("ws://127.0.0.1:1".to_string(), config());
+        assert!(matches!(controller.reconnect(), Err(ToolkitError::Connection(_))));
+    }
+}
@@ -0,0 +1,270 @@
+//! # `mqtt_ws.rs`: MQTT over WebSocket
+//!
+//! Brokers such as Mosquitto, EMQX and HiveMQ accept MQTT on a WebSocket endpoint, which is
+//! the only way to reach them from networks that only let HTTP(S) through. MQTT over
+//! WebSocket carries the MQTT byte stream in binary frames and negotiates the `mqtt`
+//! subprotocol during the handshake.
+//!
+//! `MqttWsStream` adapts a WebSocket connection to `AsyncRead` and `AsyncWrite`, so MQTT
+//! clients that run over any byte stream (such as rumqttc or a hand-written codec) can use it
+//! in place of a TCP socket. Writes are split into MQTT control packets: each binary frame
+//! carries one or more whole packets, and a partial packet is held back until its remaining
+//! bytes are written. Reads yield the payloads of received binary frames in order, whatever
+//! the packet boundaries; a text frame is a protocol error.
+//!
+//! `packet_len` parses the fixed header of a packet, for code that handles packets frame by
+//! frame instead.
+
+use crate::connection::WebSocketClient;
+use futures_util::{Sink, Stream};
+use log::warn;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::{Error, Message};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// The WebSocket subprotocol of MQTT (versions 3.1.1 and 5).
+pub const SUBPROTOCOL: &str = "mqtt";
+
+/// The most bytes the remaining length of a packet is encoded in.
+const MAX_REMAINING_LENGTH_BYTES: usize = 4;
+
+/// Returns the length of the MQTT control packet at the start of `buf`.
+///
+/// # Arguments
+///
+/// * `buf` - Bytes starting at a packet's fixed header.
+///
+/// # Returns
+///
+/// A `Result` containing the length of the whole packet, including its fixed header, or
+/// `None` if `buf` is too short to tell, or an error message if the remaining length is
+/// malformed.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::mqtt_ws::packet_len;
+///
+/// // PINGREQ: a fixed header with a remaining length of zero.
+/// assert_eq!(packet_len(&[0xC0, 0x00]), Ok(Some(2)));
+/// // A PUBLISH with 200 bytes after a 3-byte fixed header.
+/// assert_eq!(packet_len(&[0x30, 0xC8, 0x01]), Ok(Some(203)));
+/// assert_eq!(packet_len(&[0x30, 0xC8]), Ok(None));
+/// ```
+pub fn packet_len(buf: &[u8]) -> Result<Option<usize>, String> {
+    let mut remaining = 0usize;
+    for (i, byte) in buf.iter().skip(1).take(MAX_REMAINING_LENGTH_BYTES).enumerate() {
+        remaining |= usize::from(byte & 0x7F) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some(1 + (i + 1) + remaining));
+        }
+    }
+    if buf.len() > MAX_REMAINING_LENGTH_BYTES {
+        return Err("Malformed MQTT packet: remaining length exceeds 4 bytes".to_string());
+    }
+    Ok(None)
+}
+
+/// Returns the length of the whole packets at the start of `buf`.
+fn complete_len(buf: &[u8]) -> Result<usize, String> {
+    let mut complete = 0;
+    while let Some(len) = packet_len(&buf[complete..])? {
+        if complete + len > buf.len() {
+            break;
+        }
+        complete += len;
+    }
+    Ok(complete)
+}
+
+/// Converts a WebSocket error to an I/O error.
+fn io_error(error: Error) -> io::Error {
+    match error {
+        Error::Io(e) => e,
+        Error::ConnectionClosed | Error::AlreadyClosed => io::Error::new(io::ErrorKind::BrokenPipe, error),
+        other => io::Error::other(other),
+    }
+}
+
+/// A WebSocket connection carrying an MQTT byte stream.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+/// use websocket_toolkit::connection::WebSocketClient;
+/// use websocket_toolkit::mqtt_ws::MqttWsStream;
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut stream = MqttWsStream::connect(WebSocketClient::new("wss://broker.example.com/mqtt", 3)).await?;
+/// // Hand `stream` to an MQTT client, or speak the protocol directly:
+/// stream.write_all(&[0xC0, 0x00]).await?; // PINGREQ
+/// stream.flush().await?;
+/// let mut pingresp = [0u8; 2];
+/// stream.read_exact(&mut pingresp).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct MqttWsStream<S> {
+    ws_stream: WebSocketStream<S>,
+    /// The payload of the last binary frame received, and how much of it was read.
+    incoming: Vec<u8>,
+    read: usize,
+    /// Written bytes not yet sent, starting with an incomplete packet.
+    outgoing: Vec<u8>,
+}
+
+impl MqttWsStream<MaybeTlsStream<TcpStream>> {
+    /// Opens a connection that requests the `mqtt` subprotocol.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The broker URL and any handshake headers.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the stream, or an error message if the connection failed or the
+    /// server selected a different subprotocol.
+    pub async fn connect(client: WebSocketClient) -> Result<Self, String> {
+        let (ws_stream, info) = client
+            .with_header("Sec-WebSocket-Protocol", SUBPROTOCOL)
+            .connect_with_info()
+            .await
+            .map_err(|e| format!("Failed to connect to MQTT broker: {}", e))?;
+        match info.subprotocol.as_deref() {
+            Some(SUBPROTOCOL) => {}
+            Some(other) => return Err(format!("MQTT broker selected subprotocol {} instead of {}", other, SUBPROTOCOL)),
+            None => warn!("MQTT broker at {} did not confirm the {} subprotocol", info.url, SUBPROTOCOL),
+        }
+        Ok(MqttWsStream::new(ws_stream))
+    }
+}
+
+impl<S> MqttWsStream<S> {
+    /// Wraps a connection that negotiated the `mqtt` subprotocol.
+    pub fn new(ws_stream: WebSocketStream<S>) -> Self {
+        MqttWsStream { ws_stream, incoming: Vec::new(), read: 0, outgoing: Vec::new() }
+    }
+
+    /// Returns the underlying connection.
+    pub fn get_ref(&self) -> &WebSocketStream<S> {
+        &self.ws_stream
+    }
+
+    /// Returns the underlying connection, discarding any unread bytes and any incomplete
+    /// packet written.
+    pub fn into_inner(self) -> WebSocketStream<S> {
+        self.ws_stream
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for MqttWsStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if this.read < this.incoming.len() {
+                let n = buf.remaining().min(this.incoming.len() - this.read);
+                buf.put_slice(&this.incoming[this.read..this.read + n]);
+                this.read += n;
+                return Poll::Ready(Ok(()));
+            }
+            match ready!(Pin::new(&mut this.ws_stream).poll_next(cx)) {
+                Some(Ok(Message::Binary(data))) => {
+                    this.incoming = data;
+                    this.read = 0;
+                }
+                Some(Ok(Message::Text(_))) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "MQTT over WebSocket received a text frame",
+                    )));
+                }
+                // End of stream.
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                // Pongs to pings are queued by the connection and sent with the next write.
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Poll::Ready(Err(io_error(e))),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for MqttWsStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        ready!(Pin::new(&mut this.ws_stream).poll_ready(cx)).map_err(io_error)?;
+        this.outgoing.extend_from_slice(buf);
+        let complete = complete_len(&this.outgoing).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        if complete > 0 {
+            let frame = this.outgoing.drain(..complete).collect();
+            Pin::new(&mut this.ws_stream).start_send(Message::Binary(frame)).map_err(io_error)?;
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    /// Sends the frames of the whole packets written; an incomplete packet stays buffered.
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.ws_stream).poll_flush(cx).map_err(io_error)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match ready!(Pin::new(&mut self.ws_stream).poll_close(cx)) {
+            Ok(()) | Err(Error::ConnectionClosed) | Err(Error::AlreadyClosed) => Poll::Ready(Ok(())),
+            Err(e) => Poll::Ready(Err(io_error(e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockServer;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Tests remaining length parsing at the encoding boundaries.
+    #[test]
+    fn test_packet_len() {
+        assert_eq!(packet_len(&[]), Ok(None));
+        assert_eq!(packet_len(&[0x10]), Ok(None));
+        assert_eq!(packet_len(&[0x10, 0x7F]), Ok(Some(129)));
+        assert_eq!(packet_len(&[0x10, 0x80, 0x01]), Ok(Some(131)));
+        assert_eq!(packet_len(&[0x10, 0xFF, 0xFF, 0xFF, 0x7F]), Ok(Some(1 + 4 + 268_435_455)));
+        assert!(packet_len(&[0x10, 0xFF, 0xFF, 0xFF, 0xFF]).is_err());
+        assert_eq!(complete_len(&[0xC0, 0x00, 0xD0, 0x00, 0x30, 0x05, 0x00]), Ok(4));
+    }
+
+    /// Tests that writes are sent as whole packets and that reads span frames.
+    #[tokio::test]
+    async fn test_mqtt_ws_stream() {
+        let mut server = MockServer::start().await.unwrap();
+        let (stream, mut connection) =
+            tokio::join!(MqttWsStream::connect(WebSocketClient::new(server.url(), 0)), server.accept());
+        let mut stream = stream.unwrap();
+
+        // A CONNECT packet written in two halves arrives in one frame, with the PINGREQ
+        // written after it.
+        let connect = [0x10, 0x0C, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0x02, 0x00, 0x3C, 0x00, 0x00];
+        stream.write_all(&connect[..5]).await.unwrap();
+        stream.write_all(&[&connect[5..], &[0xC0, 0x00][..]].concat()).await.unwrap();
+        stream.flush().await.unwrap();
+        let frame = connection.stream().next().await.unwrap().unwrap();
+        assert_eq!(frame, Message::Binary([&connect[..], &[0xC0, 0x00][..]].concat()));
+
+        // CONNACK and PINGRESP, split across frames.
+        connection.stream().send(Message::Binary(vec![0x20, 0x02, 0x00])).await.unwrap();
+        connection.stream().send(Message::Binary(vec![0x00, 0xD0, 0x00])).await.unwrap();
+        let mut received = [0u8; 6];
+        stream.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, [0x20, 0x02, 0x00, 0x00, 0xD0, 0x00]);
+
+        connection.stream().send(Message::Text("not mqtt".into())).await.unwrap();
+        let error = stream.read(&mut received).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
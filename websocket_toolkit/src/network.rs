@@ -0,0 +1,168 @@
+//! # `network.rs`: Network change notifications for socket migration
+//!
+//! When a phone moves from Wi-Fi to cellular, its sockets stay bound to the old interface.
+//! The connection does not fail at once: it hangs until a ping or a write times out, which
+//! takes seconds, and only then does reconnection start. The OS knows about the change
+//! immediately (`NWPathMonitor` on iOS, `ConnectivityManager.NetworkCallback` on Android,
+//! netlink on Linux), but the toolkit cannot ask it portably.
+//!
+//! `NetworkMonitor` is the hook for that knowledge: implement it per platform, or feed a
+//! `ChannelMonitor` from the platform's callbacks, and hand it to
+//! `FailoverConnection::follow_network`. The connection then migrates as soon as the default
+//! route moves: it opens a new connection over the new network, moves sending over to it and
+//! closes the old one. Replacement connections are prepared with the hook given to
+//! `FailoverConnection::set_prepare` and, through the controller, carry its session
+//! credentials and auth message, so the session resumes where it left off.
+
+use futures_util::stream::{self, BoxStream};
+use futures_util::StreamExt;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+/// The kind of interface a network path uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NetworkKind {
+    /// A wireless LAN.
+    Wifi,
+    /// A cellular modem.
+    Cellular,
+    /// Ethernet or another wired link.
+    Wired,
+    /// Anything else, such as a VPN or loopback.
+    Other,
+}
+
+/// The interface the default route uses.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NetworkPath {
+    /// The interface name, e.g. `en0` or `wlan0`.
+    pub interface: String,
+    /// The kind of interface.
+    pub kind: NetworkKind,
+}
+
+impl NetworkPath {
+    /// Creates a network path.
+    ///
+    /// # Arguments
+    ///
+    /// * `interface` - The interface name.
+    /// * `kind` - The kind of interface.
+    ///
+    /// # Returns
+    ///
+    /// A new `NetworkPath`.
+    pub fn new(interface: impl Into<String>, kind: NetworkKind) -> Self {
+        NetworkPath { interface: interface.into(), kind }
+    }
+}
+
+impl fmt::Display for NetworkPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:?})", self.interface, self.kind)
+    }
+}
+
+/// A change of the default route reported by a `NetworkMonitor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkEvent {
+    /// The default route uses `path`: reported when watching starts, whenever the route
+    /// moves to another interface and when the network comes back after `Lost`.
+    Available(NetworkPath),
+    /// No network is available.
+    Lost,
+}
+
+/// Reports network changes from the operating system.
+///
+/// Implementations should report the current path as soon as `watch` is called, so the
+/// first change can be told apart from the initial state.
+pub trait NetworkMonitor: Send + Sync {
+    /// Starts watching the network.
+    ///
+    /// # Returns
+    ///
+    /// A stream of changes that ends when the monitor stops.
+    fn watch(&self) -> BoxStream<'static, NetworkEvent>;
+}
+
+/// A `NetworkMonitor` fed by hand, e.g. from platform callbacks or bindings.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::network::{ChannelMonitor, NetworkEvent, NetworkKind, NetworkPath};
+///
+/// let monitor = ChannelMonitor::new();
+/// // From the platform's network callback:
+/// monitor.notify(NetworkEvent::Available(NetworkPath::new("pdp_ip0", NetworkKind::Cellular)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChannelMonitor {
+    events: broadcast::Sender<NetworkEvent>,
+    /// The last event, replayed to new watchers as the current state.
+    last: Arc<Mutex<Option<NetworkEvent>>>,
+}
+
+impl ChannelMonitor {
+    /// Creates a monitor with no known network state.
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(16);
+        ChannelMonitor { events, last: Default::default() }
+    }
+
+    /// Reports a change to every watcher.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The change.
+    pub fn notify(&self, event: NetworkEvent) {
+        *self.last.lock().unwrap() = Some(event.clone());
+        // Nobody watching is not an error; the event is kept as the current state.
+        let _ = self.events.send(event);
+    }
+}
+
+impl Default for ChannelMonitor {
+    fn default() -> Self {
+        ChannelMonitor::new()
+    }
+}
+
+impl NetworkMonitor for ChannelMonitor {
+    fn watch(&self) -> BoxStream<'static, NetworkEvent> {
+        let receiver = self.events.subscribe();
+        let current = self.last.lock().unwrap().clone();
+        let changes = stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    // Only the latest state matters to migration.
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        });
+        Box::pin(stream::iter(current).chain(changes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that watchers see the current state first and then every change.
+    #[tokio::test]
+    async fn test_channel_monitor() {
+        let monitor = ChannelMonitor::new();
+        let wifi = NetworkPath::new("wlan0", NetworkKind::Wifi);
+        monitor.notify(NetworkEvent::Available(wifi.clone()));
+
+        let mut events = monitor.watch();
+        monitor.notify(NetworkEvent::Lost);
+        assert_eq!(events.next().await, Some(NetworkEvent::Available(wifi)));
+        assert_eq!(events.next().await, Some(NetworkEvent::Lost));
+        assert_eq!(NetworkPath::new("rmnet0", NetworkKind::Cellular).to_string(), "rmnet0 (Cellular)");
+    }
+}
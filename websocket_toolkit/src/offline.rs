@@ -0,0 +1,526 @@
+//! # `offline.rs`: Buffering messages while disconnected
+//!
+//! Messages sent during an outage have to wait for the next connection. `OfflineQueue`
+//! keeps them in memory up to `OfflineConfig::memory_limit` bytes. Past that limit it
+//! either rejects new messages or, with a spill directory configured, appends them to a
+//! temporary file, so RAM stays bounded however long the outage lasts.
+//! `OfflineQueue::replay` streams everything back to a new connection in the original
+//! order, memory first and then the spilled messages; the spill file is deleted when the
+//! queue is dropped.
+//!
+//! Messages can carry a time-to-live (`push_with_ttl`, or `OfflineConfig::default_ttl`
+//! for `push`). A price quote or presence update that expired during the outage is
+//! dropped instead of being delivered uselessly late, and handed to the dead-letter hook
+//! set with `set_dead_letter_hook` so the application can log or reroute it.
+
+use futures_util::SinkExt;
+use log::{debug, info};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/// Distinguishes spill files created by the same process.
+static SPILL_FILE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Memory limit and spill location of an `OfflineQueue`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OfflineConfig {
+    /// The number of payload bytes kept in memory.
+    pub memory_limit: usize,
+    /// Where messages beyond `memory_limit` are spilled, or `None` to reject them instead.
+    pub spill_dir: Option<PathBuf>,
+    /// The time-to-live given to messages queued with `push`, or `None` to keep them until
+    /// sent.
+    pub default_ttl: Option<Duration>,
+}
+
+impl OfflineConfig {
+    /// Creates a configuration that spills to the system temp directory beyond
+    /// `memory_limit`.
+    ///
+    /// # Arguments
+    ///
+    /// * `memory_limit` - The number of payload bytes kept in memory.
+    ///
+    /// # Returns
+    ///
+    /// A new `OfflineConfig`.
+    pub fn spill_to_temp(memory_limit: usize) -> Self {
+        OfflineConfig {
+            memory_limit,
+            spill_dir: Some(std::env::temp_dir()),
+            default_ttl: None,
+        }
+    }
+}
+
+impl Default for OfflineConfig {
+    /// Keeps up to 8 MiB in memory, rejects messages beyond that and never expires messages.
+    fn default() -> Self {
+        OfflineConfig {
+            memory_limit: 8 * 1024 * 1024,
+            spill_dir: None,
+            default_ttl: None,
+        }
+    }
+}
+
+/// Why a message was handed to the dead-letter hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadLetterReason {
+    /// The message's time-to-live ran out before it could be sent.
+    Expired,
+}
+
+/// A function receiving messages the queue dropped instead of sending.
+pub type DeadLetterHook = Box<dyn FnMut(Vec<u8>, DeadLetterReason) + Send>;
+
+/// A queued payload and when it expires.
+#[derive(Debug)]
+struct Queued {
+    payload: Vec<u8>,
+    expires_at: Option<Instant>,
+}
+
+/// A temporary file of length-prefixed payloads, read back in the order they were
+/// written.
+///
+/// Each record is the payload length (`u32`), the expiry in milliseconds after `epoch`
+/// (`u64`, `u64::MAX` for none) and the payload, all little-endian.
+#[derive(Debug)]
+struct SpillFile {
+    path: PathBuf,
+    file: File,
+    epoch: Instant,
+    read_pos: u64,
+    write_pos: u64,
+    records: usize,
+}
+
+impl SpillFile {
+    /// Creates an empty spill file in `dir`.
+    fn create(dir: &Path) -> std::io::Result<Self> {
+        let path = dir.join(format!(
+            "wstk-spill-{}-{}.bin",
+            std::process::id(),
+            SPILL_FILE_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        info!("Spilling offline messages to {}", path.display());
+        Ok(SpillFile {
+            path,
+            file,
+            epoch: Instant::now(),
+            read_pos: 0,
+            write_pos: 0,
+            records: 0,
+        })
+    }
+
+    /// Appends a payload.
+    fn append(&mut self, queued: &Queued) -> std::io::Result<()> {
+        let expires_at = queued
+            .expires_at
+            .map_or(u64::MAX, |at| at.saturating_duration_since(self.epoch).as_millis() as u64);
+        self.file.seek(SeekFrom::Start(self.write_pos))?;
+        self.file.write_all(&(queued.payload.len() as u32).to_le_bytes())?;
+        self.file.write_all(&expires_at.to_le_bytes())?;
+        self.file.write_all(&queued.payload)?;
+        self.write_pos += 12 + queued.payload.len() as u64;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Reads the oldest unread payload, truncating the file once everything has been
+    /// read.
+    fn pop(&mut self) -> std::io::Result<Option<Queued>> {
+        if self.records == 0 {
+            return Ok(None);
+        }
+        self.file.seek(SeekFrom::Start(self.read_pos))?;
+        let mut len = [0u8; 4];
+        self.file.read_exact(&mut len)?;
+        let mut expires_at = [0u8; 8];
+        self.file.read_exact(&mut expires_at)?;
+        let mut payload = vec![0u8; u32::from_le_bytes(len) as usize];
+        self.file.read_exact(&mut payload)?;
+        self.read_pos += 12 + payload.len() as u64;
+        self.records -= 1;
+        if self.records == 0 {
+            self.file.set_len(0)?;
+            self.read_pos = 0;
+            self.write_pos = 0;
+        }
+        let expires_at = match u64::from_le_bytes(expires_at) {
+            u64::MAX => None,
+            millis => Some(self.epoch + Duration::from_millis(millis)),
+        };
+        Ok(Some(Queued { payload, expires_at }))
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// A FIFO of binary messages waiting for a connection, bounded in memory.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::offline::{OfflineConfig, OfflineQueue};
+///
+/// let mut queue = OfflineQueue::new(OfflineConfig::spill_to_temp(16));
+/// queue.push(b"in memory".to_vec()).unwrap();
+/// queue.push(b"spilled to disk".to_vec()).unwrap();
+/// assert_eq!(queue.spilled(), 1);
+/// assert_eq!(queue.pop().unwrap(), Some(b"in memory".to_vec()));
+/// assert_eq!(queue.pop().unwrap(), Some(b"spilled to disk".to_vec()));
+/// ```
+pub struct OfflineQueue {
+    config: OfflineConfig,
+    memory: VecDeque<Queued>,
+    memory_bytes: usize,
+    spill: Option<SpillFile>,
+    dead_letter_hook: Option<DeadLetterHook>,
+}
+
+impl fmt::Debug for OfflineQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OfflineQueue")
+            .field("config", &self.config)
+            .field("memory", &self.memory.len())
+            .field("memory_bytes", &self.memory_bytes)
+            .field("spilled", &self.spilled())
+            .finish_non_exhaustive()
+    }
+}
+
+impl OfflineQueue {
+    /// Creates an empty queue. The spill file is only created once the memory limit is
+    /// reached.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The memory limit and spill location.
+    ///
+    /// # Returns
+    ///
+    /// A new `OfflineQueue`.
+    pub fn new(config: OfflineConfig) -> Self {
+        OfflineQueue {
+            config,
+            memory: VecDeque::new(),
+            memory_bytes: 0,
+            spill: None,
+            dead_letter_hook: None,
+        }
+    }
+
+    /// Sets the function that receives expired messages instead of them being dropped
+    /// silently.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook` - Called with each dropped payload and the reason it was dropped.
+    pub fn set_dead_letter_hook(&mut self, hook: impl FnMut(Vec<u8>, DeadLetterReason) + Send + 'static) {
+        self.dead_letter_hook = Some(Box::new(hook));
+    }
+
+    /// Queues a payload with the configured default time-to-live, spilling it to disk if
+    /// memory is full.
+    ///
+    /// Once anything has been spilled, later payloads are spilled too until the spill file
+    /// has been drained, so the original order is kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The binary message to queue.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if memory is full and spilling is
+    /// disabled or fails.
+    pub fn push(&mut self, payload: Vec<u8>) -> Result<(), String> {
+        let expires_at = self.config.default_ttl.map(|ttl| Instant::now() + ttl);
+        self.push_queued(Queued { payload, expires_at })
+    }
+
+    /// Queues a payload that is dropped if it has not been sent within `ttl`.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The binary message to queue.
+    /// * `ttl` - How long the message stays worth sending.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if memory is full and spilling is
+    /// disabled or fails.
+    pub fn push_with_ttl(&mut self, payload: Vec<u8>, ttl: Duration) -> Result<(), String> {
+        self.push_queued(Queued {
+            payload,
+            expires_at: Some(Instant::now() + ttl),
+        })
+    }
+
+    /// Queues an entry in memory or in the spill file.
+    fn push_queued(&mut self, queued: Queued) -> Result<(), String> {
+        let spilling = self.spilled() > 0;
+        if !spilling && self.memory_bytes + queued.payload.len() <= self.config.memory_limit {
+            self.memory_bytes += queued.payload.len();
+            self.memory.push_back(queued);
+            return Ok(());
+        }
+        let dir = self
+            .config
+            .spill_dir
+            .as_deref()
+            .ok_or_else(|| format!("Offline queue is full ({} bytes in memory)", self.memory_bytes))?;
+        if self.spill.is_none() {
+            self.spill = Some(SpillFile::create(dir).map_err(|e| format!("Failed to create spill file: {}", e))?);
+        }
+        let spill = self.spill.as_mut().expect("spill file was just created");
+        spill.append(&queued).map_err(|e| format!("Failed to spill message: {}", e))
+    }
+
+    /// Takes the oldest queued payload that has not expired.
+    ///
+    /// Expired payloads found on the way are passed to the dead-letter hook.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the payload, `None` if the queue is empty, or an error message
+    /// if reading the spill file fails.
+    pub fn pop(&mut self) -> Result<Option<Vec<u8>>, String> {
+        Ok(self.pop_queued()?.map(|queued| queued.payload))
+    }
+
+    /// Takes the oldest queued entry that has not expired, together with its expiry.
+    fn pop_queued(&mut self) -> Result<Option<Queued>, String> {
+        loop {
+            let queued = match self.memory.pop_front() {
+                Some(queued) => {
+                    self.memory_bytes -= queued.payload.len();
+                    queued
+                }
+                None => match self.spill.as_mut() {
+                    Some(spill) => match spill.pop().map_err(|e| format!("Failed to read spilled message: {}", e))? {
+                        Some(queued) => queued,
+                        None => return Ok(None),
+                    },
+                    None => return Ok(None),
+                },
+            };
+            match queued.expires_at {
+                Some(at) if at <= Instant::now() => self.dead_letter(queued.payload, DeadLetterReason::Expired),
+                _ => return Ok(Some(queued)),
+            }
+        }
+    }
+
+    /// Drops expired payloads held in memory, passing them to the dead-letter hook.
+    ///
+    /// Spilled payloads are checked as they are read back by `pop` and `replay`.
+    ///
+    /// # Returns
+    ///
+    /// The number of payloads dropped.
+    pub fn purge_expired(&mut self) -> usize {
+        let now = Instant::now();
+        let (expired, kept): (VecDeque<Queued>, VecDeque<Queued>) = std::mem::take(&mut self.memory)
+            .into_iter()
+            .partition(|queued| queued.expires_at.is_some_and(|at| at <= now));
+        self.memory = kept;
+        let count = expired.len();
+        for queued in expired {
+            self.memory_bytes -= queued.payload.len();
+            self.dead_letter(queued.payload, DeadLetterReason::Expired);
+        }
+        count
+    }
+
+    /// Hands a dropped payload to the dead-letter hook, if any.
+    fn dead_letter(&mut self, payload: Vec<u8>, reason: DeadLetterReason) {
+        debug!("Dropping offline message of {} bytes: {:?}", payload.len(), reason);
+        if let Some(hook) = self.dead_letter_hook.as_mut() {
+            hook(payload, reason);
+        }
+    }
+
+    /// Puts a payload back at the front of the queue, for example after a failed send.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The payload to requeue; it is kept in memory even if that exceeds the
+    ///   limit.
+    /// * `ttl` - How long the payload stays worth sending, or `None` to keep it until sent.
+    pub fn push_front(&mut self, payload: Vec<u8>, ttl: Option<Duration>) {
+        self.requeue(Queued {
+            payload,
+            expires_at: ttl.map(|ttl| Instant::now() + ttl),
+        });
+    }
+
+    /// Puts an entry back at the front of the queue in memory, keeping its expiry.
+    fn requeue(&mut self, queued: Queued) {
+        self.memory_bytes += queued.payload.len();
+        self.memory.push_front(queued);
+    }
+
+    /// Returns the number of queued payloads, including expired ones not yet dropped.
+    pub fn len(&self) -> usize {
+        self.memory.len() + self.spilled()
+    }
+
+    /// Returns whether the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of payload bytes held in memory.
+    pub fn memory_bytes(&self) -> usize {
+        self.memory_bytes
+    }
+
+    /// Returns the number of payloads currently spilled to disk.
+    pub fn spilled(&self) -> usize {
+        self.spill.as_ref().map_or(0, |spill| spill.records)
+    }
+
+    /// Sends every queued payload over `ws_stream` as binary messages, oldest first.
+    ///
+    /// Each payload is removed only after it was sent, so a failure leaves it, with its
+    /// original expiry, and everything after it queued for the next connection.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A newly (re)connected WebSocket stream.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the number of payloads sent, or an error message if a send
+    /// fails.
+    pub async fn replay<S>(&mut self, ws_stream: &mut WebSocketStream<S>) -> Result<usize, String>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut sent = 0;
+        while let Some(queued) = self.pop_queued()? {
+            if let Err(e) = ws_stream.send(Message::Binary(queued.payload.clone())).await {
+                self.requeue(queued);
+                return Err(format!("Failed to replay offline message: {}", e));
+            }
+            sent += 1;
+        }
+        debug!("Replayed {} offline messages", sent);
+        Ok(sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::WebSocketClient;
+    use crate::testing::MockServer;
+
+    /// Tests that messages beyond the memory limit are rejected without a spill directory.
+    #[test]
+    fn test_rejects_when_full_without_spill() {
+        let mut queue = OfflineQueue::new(OfflineConfig {
+            memory_limit: 4,
+            ..OfflineConfig::default()
+        });
+        queue.push(vec![1, 2, 3]).unwrap();
+        assert!(queue.push(vec![4, 5]).is_err());
+        assert_eq!(queue.len(), 1);
+    }
+
+    /// Tests that spilled messages come back in order and the spill file is removed on drop.
+    #[test]
+    fn test_spill_keeps_order_and_cleans_up() {
+        let mut queue = OfflineQueue::new(OfflineConfig::spill_to_temp(4));
+        for i in 0..5u8 {
+            queue.push(vec![i; 3]).unwrap();
+        }
+        assert_eq!(queue.memory_bytes(), 3);
+        assert_eq!(queue.spilled(), 4);
+        let path = queue.spill.as_ref().unwrap().path.clone();
+        assert!(path.exists());
+
+        assert_eq!(queue.pop().unwrap(), Some(vec![0; 3]));
+        queue.push(vec![9]).unwrap();
+        let rest: Vec<Vec<u8>> = std::iter::from_fn(|| queue.pop().unwrap()).collect();
+        assert_eq!(rest, vec![vec![1; 3], vec![2; 3], vec![3; 3], vec![4; 3], vec![9]]);
+        assert!(queue.is_empty());
+
+        drop(queue);
+        assert!(!path.exists(), "Expected the spill file to be deleted");
+    }
+
+    /// Tests that expired messages, in memory and spilled, go to the dead-letter hook.
+    #[test]
+    fn test_expired_messages_are_dead_lettered() {
+        let dead_letters = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut queue = OfflineQueue::new(OfflineConfig::spill_to_temp(4));
+        let sink = dead_letters.clone();
+        queue.set_dead_letter_hook(move |payload, reason| sink.lock().unwrap().push((payload, reason)));
+
+        queue.push_with_ttl(vec![1], Duration::ZERO).unwrap();
+        queue.push(vec![2]).unwrap();
+        queue.push_with_ttl(vec![3; 8], Duration::ZERO).unwrap();
+        queue.push_with_ttl(vec![4; 8], Duration::from_secs(60)).unwrap();
+        assert_eq!(queue.spilled(), 2);
+
+        assert_eq!(queue.purge_expired(), 1);
+        assert_eq!(queue.pop().unwrap(), Some(vec![2]));
+        assert_eq!(queue.pop().unwrap(), Some(vec![4; 8]));
+        assert_eq!(queue.pop().unwrap(), None);
+        assert_eq!(
+            *dead_letters.lock().unwrap(),
+            vec![(vec![1], DeadLetterReason::Expired), (vec![3; 8], DeadLetterReason::Expired)]
+        );
+    }
+
+    /// Tests replaying memory and spilled messages to a new connection.
+    #[tokio::test]
+    async fn test_replay_streams_everything_in_order() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let mut queue = OfflineQueue::new(OfflineConfig::spill_to_temp(6));
+        for message in [&b"one"[..], b"two", b"three"] {
+            queue.push(message.to_vec()).unwrap();
+        }
+        assert_eq!(queue.spilled(), 1);
+
+        let mut ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let mut connection = server.accept().await;
+        assert_eq!(queue.replay(&mut ws_stream).await, Ok(3));
+        for message in [&b"one"[..], b"two", b"three"] {
+            connection.assert_next_message_eq(Message::Binary(message.to_vec())).await;
+        }
+    }
+
+    /// Tests that a message whose replay failed is requeued with its original expiry.
+    #[tokio::test]
+    async fn test_failed_replay_keeps_expiry() {
+        let server = MockServer::start().await.expect("Failed to start mock server");
+        let mut queue = OfflineQueue::new(OfflineConfig::default());
+        queue.push_with_ttl(b"quote".to_vec(), Duration::from_secs(60)).unwrap();
+        let expires_at = queue.memory[0].expires_at;
+
+        let mut ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        ws_stream.close(None).await.unwrap();
+        assert!(queue.replay(&mut ws_stream).await.is_err());
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.memory[0].expires_at, expires_at);
+    }
+}
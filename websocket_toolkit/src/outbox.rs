@@ -0,0 +1,439 @@
+//! # `outbox.rs`: Durable outbox for messages that must not be lost
+//!
+//! Messages sent with `Outbox::send_durable` are written to a `Storage` backend and flushed
+//! before they go on the wire, and stay there until the peer acknowledges them. After a
+//! crash or a reconnect, `Outbox::resend_pending` sends every unacknowledged message again, so
+//! telemetry from an IoT gateway survives both process restarts and dropped connections.
+//!
+//! Durable messages travel as `Envelope`s whose `id` is the outbox sequence number. Sequence
+//! numbers start at 1 and have no gaps, even across crashes, so the receiving side can restore
+//! the original order with an `ordering::Resequencer`. The peer
+//! confirms receipt by replying with `ack_envelope(id)`; pass incoming envelopes to
+//! `Outbox::handle_ack` to remove acknowledged messages. Delivery is at-least-once: a message
+//! whose ack was lost is sent again. A peer that journals its progress can instead send a
+//! `resume` envelope after reconnecting (see the `journal` module), which
+//! `Outbox::handle_resume` answers by dropping everything up to its cursor and resending the rest.
+//!
+//! `Outbox::open` keeps messages in a sled database; `Outbox::with_storage` accepts any
+//! other backend. Enabled by the `outbox` feature.
+
+use crate::journal::RESUME_KIND;
+use crate::messages::{Envelope, MessageFormat};
+use crate::storage::{MemoryStorage, SledStorage, Storage};
+use futures_util::SinkExt;
+use log::{debug, info, warn};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/// The namespace of pending messages, keyed by big-endian sequence number.
+const MESSAGES_NAMESPACE: &str = "outbox";
+
+/// The namespace of the outbox's metadata.
+const META_NAMESPACE: &str = "outbox_meta";
+
+/// The key of the next sequence number in the metadata namespace.
+const NEXT_SEQUENCE_KEY: &[u8] = b"next_sequence";
+
+/// The envelope kind of acknowledgements.
+pub const ACK_KIND: &str = "ack";
+
+/// Builds the acknowledgement a receiver sends back for the durable message `id`.
+///
+/// # Arguments
+///
+/// * `id` - The `id` of the received envelope.
+///
+/// # Returns
+///
+/// An empty `Envelope` of kind `ACK_KIND` carrying `id`.
+pub fn ack_envelope(id: impl Into<String>) -> Envelope {
+    Envelope::new(ACK_KIND, Vec::new()).with_id(id)
+}
+
+/// A crash-safe store of sent but unacknowledged messages.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::messages::{Envelope, MessageFormat};
+/// use websocket_toolkit::outbox::{ack_envelope, Outbox};
+///
+/// let outbox = Outbox::temporary().unwrap();
+/// let id = outbox.store(Envelope::new("telemetry", b"21.5".to_vec()), MessageFormat::Cbor).unwrap();
+/// assert_eq!(outbox.len(), 1);
+///
+/// assert!(outbox.handle_ack(&ack_envelope(id.to_string())).unwrap());
+/// assert!(outbox.is_empty());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Outbox {
+    storage: Arc<dyn Storage>,
+    /// The next sequence number; held while storing so concurrent stores stay contiguous.
+    next: Arc<Mutex<u64>>,
+}
+
+impl Outbox {
+    /// Opens (or creates) the outbox database at `path`, keeping any unacknowledged messages.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory of the sled database.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the outbox, or an error message if the database cannot be opened.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let storage = SledStorage::open(path).map_err(|e| format!("Failed to open outbox: {}", e))?;
+        Self::with_storage(Arc::new(storage))
+    }
+
+    /// Creates an outbox that keeps messages in memory only, for tests.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the outbox, or an error message on failure.
+    pub fn temporary() -> Result<Self, String> {
+        Self::with_storage(Arc::new(MemoryStorage::new()))
+    }
+
+    /// Creates an outbox on `storage`, keeping any unacknowledged messages already stored there.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - The persistence backend; it may be shared with other components.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the outbox, or an error message if the backend cannot be read.
+    pub fn with_storage(storage: Arc<dyn Storage>) -> Result<Self, String> {
+        let pending = storage.iterate(MESSAGES_NAMESPACE)?;
+        if !pending.is_empty() {
+            info!("Outbox holds {} unacknowledged messages", pending.len());
+        }
+        // A crash between storing a message and advancing the counter leaves the counter
+        // behind the newest message, so resume after whichever is higher.
+        let stored_next = storage
+            .get(META_NAMESPACE, NEXT_SEQUENCE_KEY)?
+            .as_deref()
+            .and_then(decode_sequence)
+            .unwrap_or(1);
+        let after_pending = pending
+            .last()
+            .and_then(|(key, _)| decode_sequence(key))
+            .map_or(1, |last| last + 1);
+        Ok(Outbox {
+            storage,
+            next: Arc::new(Mutex::new(stored_next.max(after_pending))),
+        })
+    }
+
+    /// Assigns `envelope` the next sequence number as its id and persists it encoded in `format`.
+    ///
+    /// Returns only once the message has been flushed.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - The message; any id it carries is replaced.
+    /// * `format` - The wire format the message is stored and sent in.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the message's sequence number, or an error message on failure.
+    pub fn store(&self, envelope: Envelope, format: MessageFormat) -> Result<u64, String> {
+        self.store_encoded(envelope, format).map(|(id, _)| id)
+    }
+
+    /// Persists `envelope` like `store` and returns the id with the encoded frame.
+    fn store_encoded(&self, envelope: Envelope, format: MessageFormat) -> Result<(u64, Vec<u8>), String> {
+        let mut next = self.next.lock().unwrap();
+        let id = *next;
+        let encoded = envelope.with_id(id.to_string()).encode(format)?;
+        self.storage
+            .put(MESSAGES_NAMESPACE, &id.to_be_bytes(), &encoded)
+            .map_err(|e| format!("Failed to store outbox message: {}", e))?;
+        *next = id + 1;
+        self.storage
+            .put(META_NAMESPACE, NEXT_SEQUENCE_KEY, &next.to_be_bytes())
+            .map_err(|e| format!("Failed to store outbox message: {}", e))?;
+        self.storage.flush().map_err(|e| format!("Failed to flush outbox: {}", e))?;
+        Ok((id, encoded))
+    }
+
+    /// Persists `envelope`, then sends it as a binary message.
+    ///
+    /// If the send fails the message stays in the outbox and is sent again by `resend_pending`.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    /// * `envelope` - The message; its id is replaced by the outbox sequence number.
+    /// * `format` - The wire format.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the message's sequence number, or an error message if storing or
+    /// sending fails.
+    pub async fn send_durable<S>(
+        &self,
+        ws_stream: &mut WebSocketStream<S>,
+        envelope: Envelope,
+        format: MessageFormat,
+    ) -> Result<u64, String>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (id, encoded) = self.store_encoded(envelope, format)?;
+        ws_stream
+            .send(Message::Binary(encoded))
+            .await
+            .map_err(|e| format!("Failed to send durable message {}: {}", id, e))?;
+        Ok(id)
+    }
+
+    /// Sends every unacknowledged message again, oldest first.
+    ///
+    /// Call this after connecting, including on startup after a crash.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the number of messages sent, or an error message on failure.
+    pub async fn resend_pending<S>(&self, ws_stream: &mut WebSocketStream<S>) -> Result<usize, String>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let pending = self
+            .storage
+            .iterate(MESSAGES_NAMESPACE)
+            .map_err(|e| format!("Failed to read outbox: {}", e))?;
+        let mut sent = 0;
+        for (_, encoded) in pending {
+            ws_stream
+                .send(Message::Binary(encoded))
+                .await
+                .map_err(|e| format!("Failed to resend durable message: {}", e))?;
+            sent += 1;
+        }
+        debug!("Resent {} unacknowledged messages", sent);
+        Ok(sent)
+    }
+
+    /// Removes the message with the given sequence number.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The sequence number returned by `store` or `send_durable`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing whether the message was still pending, or an error message on failure.
+    pub fn ack(&self, id: u64) -> Result<bool, String> {
+        let removed = self
+            .storage
+            .delete(MESSAGES_NAMESPACE, &id.to_be_bytes())
+            .map_err(|e| format!("Failed to remove outbox message: {}", e))?;
+        self.storage.flush().map_err(|e| format!("Failed to flush outbox: {}", e))?;
+        Ok(removed)
+    }
+
+    /// Removes the acknowledged message if `envelope` is an acknowledgement.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - A received envelope.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `true` if `envelope` acknowledged a pending message, `false` for
+    /// other envelopes and duplicate acks, or an error message on failure.
+    pub fn handle_ack(&self, envelope: &Envelope) -> Result<bool, String> {
+        if envelope.kind != ACK_KIND {
+            return Ok(false);
+        }
+        match envelope.id.as_deref().map(str::parse::<u64>) {
+            Some(Ok(id)) => self.ack(id),
+            _ => {
+                warn!("Ignoring acknowledgement without a valid id: {:?}", envelope.id);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Answers a resume request from a peer that journals its progress.
+    ///
+    /// Every message up to the request's cursor counts as acknowledged and is removed; the
+    /// remaining messages are sent again, oldest first. A request without an id resends everything.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the WebSocket stream.
+    /// * `envelope` - A received envelope.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the number of messages resent, `None` if `envelope` is not a resume
+    /// request, or an error message on failure.
+    pub async fn handle_resume<S>(
+        &self,
+        ws_stream: &mut WebSocketStream<S>,
+        envelope: &Envelope,
+    ) -> Result<Option<usize>, String>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        if envelope.kind != RESUME_KIND {
+            return Ok(None);
+        }
+        match envelope.id.as_deref().map(str::parse::<u64>) {
+            Some(Ok(cursor)) => {
+                let pending = self
+                    .storage
+                    .iterate(MESSAGES_NAMESPACE)
+                    .map_err(|e| format!("Failed to read outbox: {}", e))?;
+                for (key, _) in pending {
+                    if decode_sequence(&key).is_some_and(|id| id <= cursor) {
+                        self.storage
+                            .delete(MESSAGES_NAMESPACE, &key)
+                            .map_err(|e| format!("Failed to remove outbox message: {}", e))?;
+                    }
+                }
+                self.storage.flush().map_err(|e| format!("Failed to flush outbox: {}", e))?;
+            }
+            Some(Err(_)) => warn!("Resume request with an invalid cursor: {:?}", envelope.id),
+            None => {}
+        }
+        self.resend_pending(ws_stream).await.map(Some)
+    }
+
+    /// Returns the number of unacknowledged messages, or 0 if the backend cannot be read.
+    pub fn len(&self) -> usize {
+        self.storage.len(MESSAGES_NAMESPACE).unwrap_or(0)
+    }
+
+    /// Returns whether every message has been acknowledged.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Decodes a big-endian sequence number stored as a key or value.
+fn decode_sequence(bytes: &[u8]) -> Option<u64> {
+    <[u8; 8]>::try_from(bytes).ok().map(u64::from_be_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::WebSocketClient;
+    use crate::testing::MockServer;
+
+    /// Tests that unacknowledged messages survive reopening the database.
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn test_pending_messages_survive_restart() {
+        let dir = std::env::temp_dir().join(format!("wstk-outbox-test-{}", std::process::id()));
+        let first = {
+            let outbox = Outbox::open(&dir).unwrap();
+            let first = outbox.store(Envelope::new("reading", vec![1]), MessageFormat::Cbor).unwrap();
+            let second = outbox.store(Envelope::new("reading", vec![2]), MessageFormat::Cbor).unwrap();
+            assert!(outbox.ack(second).unwrap());
+            first
+        };
+
+        let outbox = Outbox::open(&dir).unwrap();
+        assert_eq!(outbox.len(), 1);
+        assert!(outbox.ack(first).unwrap());
+        drop(outbox);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Tests that sequence numbers start at 1 and stay contiguous across reopening.
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn test_sequence_numbers_are_contiguous() {
+        let dir = std::env::temp_dir().join(format!("wstk-outbox-sequence-test-{}", std::process::id()));
+        {
+            let outbox = Outbox::open(&dir).unwrap();
+            assert_eq!(outbox.store(Envelope::new("reading", vec![1]), MessageFormat::Cbor), Ok(1));
+            assert_eq!(outbox.store(Envelope::new("reading", vec![2]), MessageFormat::Cbor), Ok(2));
+        }
+        let outbox = Outbox::open(&dir).unwrap();
+        assert_eq!(outbox.store(Envelope::new("reading", vec![3]), MessageFormat::Cbor), Ok(3));
+        drop(outbox);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Tests that the sequence resumes after the newest message even if the counter lags behind.
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn test_sequence_recovers_from_stale_counter() {
+        let dir = std::env::temp_dir().join(format!("wstk-outbox-file-test-{}", std::process::id()));
+        let storage: Arc<dyn Storage> = Arc::new(crate::storage::FileStorage::open(&dir).unwrap());
+        let outbox = Outbox::with_storage(storage.clone()).unwrap();
+        assert_eq!(outbox.store(Envelope::new("reading", vec![1]), MessageFormat::Cbor), Ok(1));
+        assert_eq!(outbox.store(Envelope::new("reading", vec![2]), MessageFormat::Cbor), Ok(2));
+        storage.put(META_NAMESPACE, NEXT_SEQUENCE_KEY, &2u64.to_be_bytes()).unwrap();
+
+        let outbox = Outbox::with_storage(storage).unwrap();
+        assert_eq!(outbox.len(), 2);
+        assert_eq!(outbox.store(Envelope::new("reading", vec![3]), MessageFormat::Cbor), Ok(3));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Tests that a resume request drops processed messages and resends the rest.
+    #[cfg(feature = "cbor")]
+    #[tokio::test]
+    async fn test_handle_resume() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let mut ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let mut connection = server.accept().await;
+        let outbox = Outbox::temporary().unwrap();
+        let journal = crate::journal::InboundJournal::temporary().unwrap();
+
+        let ids: Vec<u64> = (0..3u8)
+            .map(|i| outbox.store(Envelope::new("reading", vec![i]), MessageFormat::Cbor).unwrap())
+            .collect();
+        journal.commit(ids[1]).unwrap();
+
+        let resume = journal.resume_envelope().unwrap();
+        assert_eq!(outbox.handle_resume(&mut ws_stream, &resume).await, Ok(Some(1)));
+        assert_eq!(outbox.len(), 1);
+        let expected = Envelope::new("reading", vec![2]).with_id(ids[2].to_string());
+        connection
+            .assert_next_message_eq(Message::Binary(expected.encode(MessageFormat::Cbor).unwrap()))
+            .await;
+        assert_eq!(outbox.handle_resume(&mut ws_stream, &ack_envelope("1")).await, Ok(None));
+    }
+
+    /// Tests sending, resending and acknowledging durable messages.
+    #[cfg(feature = "cbor")]
+    #[tokio::test]
+    async fn test_send_resend_and_ack() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let mut ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let mut connection = server.accept().await;
+        let outbox = Outbox::temporary().unwrap();
+
+        let id = outbox
+            .send_durable(&mut ws_stream, Envelope::new("reading", vec![7]), MessageFormat::Cbor)
+            .await
+            .unwrap();
+        let expected = Envelope::new("reading", vec![7]).with_id(id.to_string());
+        let frame = Message::Binary(expected.encode(MessageFormat::Cbor).unwrap());
+        connection.assert_next_message_eq(frame.clone()).await;
+
+        assert_eq!(outbox.resend_pending(&mut ws_stream).await, Ok(1));
+        connection.assert_next_message_eq(frame).await;
+
+        assert!(!outbox.handle_ack(&Envelope::new("reading", Vec::new())).unwrap());
+        assert!(outbox.handle_ack(&ack_envelope(id.to_string())).unwrap());
+        assert!(!outbox.handle_ack(&ack_envelope(id.to_string())).unwrap(), "Duplicate ack");
+        assert_eq!(outbox.resend_pending(&mut ws_stream).await, Ok(0));
+    }
+}
@@ -0,0 +1,1276 @@
+//! # `pipeline.rs`: Reader/writer tasks for high-throughput streaming
+//!
+//! Sharing a connection as `Arc<Mutex<WebSocketStream>>` lets only one task read or write
+//! at a time, which caps throughput at roughly one in-flight operation. `pipeline::spawn`
+//! instead splits the stream between a dedicated reader task and a dedicated writer task:
+//!
+//! - `PipelineSender` (cloneable) queues outbound messages on a bounded MPSC channel. The
+//!   writer task feeds them to the socket and flushes according to the pipeline's
+//!   `FlushPolicy`; by default (`FlushPolicy::WhenIdle`) it flushes once the queue is empty,
+//!   so bursts are written with one flush instead of one per message.
+//!   `acquire_send_permit` reserves a queue slot before the message is built.
+//! - `PipelineReceiver` receives inbound messages from the reader task over a bounded
+//!   channel, so a slow consumer applies backpressure instead of buffering without limit.
+//!   With `InboundPolicy::DropOldest` the reader instead discards the oldest buffered message
+//!   and keeps reading, so the consumer always sees the freshest data.
+//!
+//! Neither side waits on the other: senders never block readers and vice versa. Keep-alive
+//! pings go through the writer task too (`PipelineSender::ping`), so they never lock the
+//! stream, and their payload is the empty `PING_PAYLOAD`, so a ping never allocates. With
+//! `PipelineConfig::track_rtt`, each ping is timed against its pong (see the `rtt` module).
+//! `PipelineSender::probe` pings and waits for the pong; a connection that does not answer
+//! in time is half-open and is closed, so the receiver sees it end. With
+//! `PipelineConfig::link_quality`, pings, pongs and writes also feed a `LinkMonitor` (see the
+//! `quality` module), watched with `PipelineSender::watch_link_quality`.
+//!
+//! `PipelineSender::drain` (and `ConnectionHandle::shutdown_with_grace`) closes a pipeline
+//! by priority instead of in queue order: messages sent with `Delivery::MustDeliver` go out
+//! first, `Delivery::Normal` messages follow while the grace period lasts, and
+//! `Delivery::BestEffort` messages are discarded. The returned `DrainReport` counts what
+//! each class delivered and discarded.
+//!
+//! With `PipelineConfig::fragmentation`, the writer sends messages above the threshold in
+//! fragments, so a giant message does not go out as one giant frame.
+//!
+//! Pipelines opened by a controller with `Limits` hold a connection slot while their tasks
+//! run, and reserve the bytes of every queued message in the limits' byte budgets until the
+//! writer has sent it or the receiver has taken it.
+
+use crate::flush::{FlushPolicy, FlushState};
+use crate::fragment::{Fragmentation, FragmentingStream};
+use crate::limits::{BufferReservation, ConnectionLimits};
+use crate::quality::{LinkMonitor, LinkQuality, QualityThresholds};
+use crate::rtt::{RttStats, RttTracker};
+use crate::tasks::spawn_named;
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, Weak};
+use tokio::sync::Notify;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc::{self, error::SendError, error::TrySendError};
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant, MissedTickBehavior};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/// The payload of keep-alive pings. An empty `Vec` owns no heap memory, so building a ping
+/// frame from it does not allocate.
+pub(crate) const PING_PAYLOAD: Vec<u8> = Vec::new();
+
+/// What the reader task does when the inbound buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InboundPolicy {
+    /// Stop reading until the receiver makes room, so the server is slowed down by TCP flow
+    /// control.
+    #[default]
+    Backpressure,
+    /// Discard the oldest buffered message to make room. The socket is always drained and a
+    /// slow consumer skips stale messages instead of falling behind; suited to market data
+    /// and other feeds where only the latest value matters.
+    DropOldest,
+}
+
+/// How important a queued message is when the pipeline is drained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Delivery {
+    /// Sent before any other class, e.g. an order cancel.
+    MustDeliver,
+    /// Sent after must-deliver messages while the grace period lasts.
+    #[default]
+    Normal,
+    /// Discarded when draining, e.g. telemetry.
+    BestEffort,
+}
+
+/// What one delivery class sent and discarded while draining.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClassReport {
+    /// The messages sent.
+    pub delivered: usize,
+    /// The messages discarded, because of their class or because the grace period ran out.
+    pub discarded: usize,
+}
+
+/// What a drain delivered and discarded, per delivery class.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DrainReport {
+    /// `Delivery::MustDeliver` messages.
+    pub must_deliver: ClassReport,
+    /// `Delivery::Normal` messages.
+    pub normal: ClassReport,
+    /// `Delivery::BestEffort` messages.
+    pub best_effort: ClassReport,
+    /// Whether the close frame was sent within the grace period.
+    pub closed: bool,
+}
+
+impl DrainReport {
+    /// Returns the report of one class.
+    pub fn class(&self, delivery: Delivery) -> ClassReport {
+        match delivery {
+            Delivery::MustDeliver => self.must_deliver,
+            Delivery::Normal => self.normal,
+            Delivery::BestEffort => self.best_effort,
+        }
+    }
+
+    /// Returns the mutable report of one class.
+    fn class_mut(&mut self, delivery: Delivery) -> &mut ClassReport {
+        match delivery {
+            Delivery::MustDeliver => &mut self.must_deliver,
+            Delivery::Normal => &mut self.normal,
+            Delivery::BestEffort => &mut self.best_effort,
+        }
+    }
+}
+
+/// A request to drain the writer's queue by priority within `grace`.
+#[derive(Debug)]
+struct DrainRequest {
+    grace: Duration,
+    report: oneshot::Sender<DrainReport>,
+}
+
+/// Channel sizes and flush policy for a pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineConfig {
+    /// The number of outbound messages that can be queued before `send` waits.
+    pub outbound_capacity: usize,
+    /// The number of inbound messages buffered before the reader stops reading.
+    pub inbound_capacity: usize,
+    /// When the writer task flushes. With `FlushPolicy::Manual`, flushes happen only on
+    /// `PipelineSender::flush` and when the connection closes.
+    pub flush_policy: FlushPolicy,
+    /// Closes the connection once no text or binary message has been received for this long.
+    /// Pings and pongs do not count as activity. `None` keeps idle connections open.
+    pub idle_timeout: Option<Duration>,
+    /// What the reader does when `inbound_capacity` messages are waiting.
+    pub inbound_policy: InboundPolicy,
+    /// Times each ping against its pong; read the results with `PipelineSender::rtt`.
+    pub track_rtt: bool,
+    /// Sends messages above a size in fragments; see the `fragment` module. `None` sends
+    /// every message as a single frame.
+    pub fragmentation: Option<Fragmentation>,
+    /// Estimates the link's quality from pings, pongs and writes; read it with
+    /// `PipelineSender::link_quality`. Implies `track_rtt`. `None` does not estimate it.
+    pub link_quality: Option<QualityThresholds>,
+}
+
+impl Default for PipelineConfig {
+    /// Buffers up to 1024 messages in each direction with backpressure, flushes whenever the
+    /// writer's queue runs empty, never closes idle connections, does not track RTT or link
+    /// quality and does not fragment.
+    fn default() -> Self {
+        PipelineConfig {
+            outbound_capacity: 1024,
+            inbound_capacity: 1024,
+            flush_policy: FlushPolicy::WhenIdle,
+            idle_timeout: None,
+            inbound_policy: InboundPolicy::Backpressure,
+            track_rtt: false,
+            fragmentation: None,
+            link_quality: None,
+        }
+    }
+}
+
+/// An item on the writer task's queue.
+#[derive(Debug)]
+enum Outbound {
+    /// A frame to send, its delivery class and the bytes it holds in the limits' budgets.
+    Message(Message, Delivery, Option<BufferReservation>),
+    /// An explicit flush request.
+    Flush,
+    /// A keep-alive ping, sent and flushed ahead of the flush policy.
+    Ping,
+}
+
+/// A slot in a pipeline's outbound queue, from `PipelineSender::acquire_send_permit`.
+///
+/// Dropping the permit unused returns the slot. Permits are `'static`, so they can be moved
+/// to the task that builds the message.
+#[derive(Debug)]
+pub struct SendPermit {
+    permit: mpsc::OwnedPermit<Outbound>,
+    limits: Option<Weak<ConnectionLimits>>,
+}
+
+impl SendPermit {
+    /// Queues `message` in the reserved slot.
+    ///
+    /// With `Limits`, this still waits while the byte budget has no room for the message. If
+    /// the writer task stopped after the permit was taken, the message is discarded like any
+    /// other message still queued when a connection ends.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The frame to send.
+    pub async fn send(self, message: Message) {
+        self.send_with(message, Delivery::Normal).await
+    }
+
+    /// Queues `message` with a delivery class in the reserved slot; see `send`.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The frame to send.
+    /// * `delivery` - The message's delivery class.
+    pub async fn send_with(self, message: Message, delivery: Delivery) {
+        let reservation = match self.limits.as_ref().and_then(Weak::upgrade) {
+            Some(limits) => Some(limits.reserve(message.len()).await),
+            None => None,
+        };
+        self.permit.send(Outbound::Message(message, delivery, reservation));
+    }
+
+    /// Queues a binary message in the reserved slot; see `send`.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The message payload.
+    pub async fn send_binary(self, payload: Vec<u8>) {
+        self.send(Message::Binary(payload)).await
+    }
+}
+
+/// The sending half of a pipeline. Clones share the same writer task.
+#[derive(Debug, Clone)]
+pub struct PipelineSender {
+    outbound: mpsc::Sender<Outbound>,
+    rtt: Option<Arc<RttTracker>>,
+    quality: Option<Arc<LinkMonitor>>,
+    /// The connection's limits, while its tasks run.
+    limits: Option<Weak<ConnectionLimits>>,
+    /// Asks the writer to drain; taken by the first `drain`.
+    drain: Arc<Mutex<Option<oneshot::Sender<DrainRequest>>>>,
+    liveness: Arc<Liveness>,
+}
+
+/// Pongs seen by the reader, and the signal that stops it when a probe went unanswered.
+#[derive(Debug, Default)]
+struct Liveness {
+    pongs: std::sync::atomic::AtomicU64,
+    pong: Notify,
+    stale: Notify,
+}
+
+impl Liveness {
+    fn record_pong(&self) {
+        self.pongs.fetch_add(1, std::sync::atomic::Ordering::AcqRel);
+        self.pong.notify_waiters();
+    }
+
+    fn pongs(&self) -> u64 {
+        self.pongs.load(std::sync::atomic::Ordering::Acquire)
+    }
+}
+
+impl PipelineSender {
+    /// Queues a message for the writer task, waiting while the queue is full or, with
+    /// `Limits`, while the byte budget has no room for it.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The frame to send.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or the message back if the writer task has stopped.
+    pub async fn send(&self, message: Message) -> Result<(), SendError<Message>> {
+        self.send_with(message, Delivery::Normal).await
+    }
+
+    /// Queues a message with a delivery class, which decides its fate if the pipeline is
+    /// drained while it is still queued.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The frame to send.
+    /// * `delivery` - The message's delivery class.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or the message back if the writer task has stopped.
+    pub async fn send_with(&self, message: Message, delivery: Delivery) -> Result<(), SendError<Message>> {
+        let reservation = match self.limits.as_ref().and_then(Weak::upgrade) {
+            Some(limits) => Some(limits.reserve(message.len()).await),
+            None => None,
+        };
+        self.outbound.send(Outbound::Message(message, delivery, reservation)).await.map_err(|SendError(item)| match item {
+            Outbound::Message(message, _, _) => SendError(message),
+            Outbound::Flush | Outbound::Ping => unreachable!("send only queues messages"),
+        })
+    }
+
+    /// Waits for room for one message, before the message exists.
+    ///
+    /// Producers sharing a connection can acquire a permit first and only then serialize a
+    /// payload, so the work is not wasted on a message that would have to wait or be
+    /// dropped. The permit holds a slot in the outbound queue until it is used or dropped.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the permit, or an error if the writer task has stopped.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit::connection::WebSocketClient;
+    /// use websocket_toolkit::pipeline::{self, PipelineConfig};
+    /// use websocket_toolkit::testing::EchoServer;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let server = EchoServer::start().await.unwrap();
+    /// let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+    /// let (sender, _receiver, _tasks) = pipeline::spawn(ws_stream, PipelineConfig::default());
+    ///
+    /// let permit = sender.acquire_send_permit().await.unwrap();
+    /// let report = vec![0u8; 1024]; // Expensive to build; only built once there is room.
+    /// permit.send_binary(report).await;
+    /// # }
+    /// ```
+    pub async fn acquire_send_permit(&self) -> Result<SendPermit, SendError<()>> {
+        let permit = self.outbound.clone().reserve_owned().await.map_err(|_| SendError(()))?;
+        Ok(SendPermit { permit, limits: self.limits.clone() })
+    }
+
+    /// Takes a permit if the outbound queue has room right now; see `acquire_send_permit`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the permit, or `TrySendError::Full` if the queue is full, or
+    /// `TrySendError::Closed` if the writer task has stopped.
+    pub fn try_acquire_send_permit(&self) -> Result<SendPermit, TrySendError<()>> {
+        let permit = self.outbound.clone().try_reserve_owned().map_err(|e| match e {
+            TrySendError::Full(_) => TrySendError::Full(()),
+            TrySendError::Closed(_) => TrySendError::Closed(()),
+        })?;
+        Ok(SendPermit { permit, limits: self.limits.clone() })
+    }
+
+    /// Queues a binary message for the writer task.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The message payload.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or the message back if the writer task has stopped.
+    pub async fn send_binary(&self, payload: Vec<u8>) -> Result<(), SendError<Message>> {
+        self.send(Message::Binary(payload)).await
+    }
+
+    /// Asks the writer task to flush everything queued before this call.
+    ///
+    /// Only needed with `FlushPolicy::Manual`, `EveryN` or `Interval`; other policies flush
+    /// on their own.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error if the writer task has stopped.
+    pub async fn flush(&self) -> Result<(), SendError<()>> {
+        self.outbound.send(Outbound::Flush).await.map_err(|_| SendError(()))
+    }
+
+    /// Asks the writer task to send a keep-alive ping.
+    ///
+    /// The ping carries an empty payload and is flushed straight away, together with any
+    /// messages queued before it.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error if the writer task has stopped.
+    pub async fn ping(&self) -> Result<(), SendError<()>> {
+        self.outbound.send(Outbound::Ping).await.map_err(|_| SendError(()))
+    }
+
+    /// Verifies that the peer still answers: sends a ping and waits for a pong.
+    ///
+    /// Use it before trusting a connection that may have died unnoticed, e.g. after the
+    /// machine resumed from sleep: the kernel still reports such a socket as open while the
+    /// server has long dropped it. If no pong arrives within `timeout`, the connection is
+    /// closed, so the receiver sees it end and reconnection can start.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - How long to wait for the pong.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the time until the pong arrived, or an error message if none
+    /// arrived in time or the writer task has stopped.
+    pub async fn probe(&self, timeout: Duration) -> Result<Duration, String> {
+        let started = Instant::now();
+        let before = self.liveness.pongs();
+        self.ping().await.map_err(|_| "Failed to probe connection: writer stopped".to_string())?;
+        let answered = tokio::time::timeout(timeout, async {
+            loop {
+                // Registered before the check, so a pong between the two is not missed.
+                let pong = self.liveness.pong.notified();
+                if self.liveness.pongs() > before {
+                    return;
+                }
+                pong.await;
+            }
+        })
+        .await;
+        match answered {
+            Ok(()) => Ok(started.elapsed()),
+            Err(_) => {
+                self.liveness.stale.notify_one();
+                Err(format!("Failed to probe connection: no pong within {:?}", timeout))
+            }
+        }
+    }
+
+    /// Asks the writer task to send what is queued by priority and close the connection.
+    ///
+    /// Must-deliver messages are sent first, then normal messages while `grace` lasts;
+    /// best-effort messages, and whatever does not fit in `grace`, are discarded. The close
+    /// frame follows. Messages sent after the drain started are not delivered.
+    ///
+    /// # Arguments
+    ///
+    /// * `grace` - How long sending the queued messages and the close frame may take.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the per-class report, or an error message if the pipeline
+    /// was already drained or the writer task has stopped.
+    pub async fn drain(&self, grace: Duration) -> Result<DrainReport, String> {
+        let drain = self.drain.lock().unwrap().take().ok_or("Failed to drain: pipeline already drained")?;
+        let (report, receiver) = oneshot::channel();
+        drain
+            .send(DrainRequest { grace, report })
+            .map_err(|_| "Failed to drain: writer stopped".to_string())?;
+        receiver.await.map_err(|_| "Failed to drain: writer stopped".to_string())
+    }
+
+    /// Returns whether the writer task has stopped.
+    pub fn is_closed(&self) -> bool {
+        self.outbound.is_closed()
+    }
+
+    /// Returns the number of messages waiting for the writer task.
+    pub fn queued(&self) -> usize {
+        self.outbound.max_capacity() - self.outbound.capacity()
+    }
+
+    /// Returns whether `other` feeds the same writer task, i.e. is a clone of this sender.
+    pub fn same_channel(&self, other: &PipelineSender) -> bool {
+        self.outbound.same_channel(&other.outbound)
+    }
+
+    /// Returns the round-trip times measured from pings.
+    ///
+    /// # Returns
+    ///
+    /// The `RttStats`, or `None` if `PipelineConfig::track_rtt` is off or no ping has been
+    /// answered yet.
+    pub fn rtt(&self) -> Option<RttStats> {
+        self.rtt.as_ref()?.stats()
+    }
+
+    /// Returns how long the last ping has gone unanswered.
+    ///
+    /// # Returns
+    ///
+    /// The time since the unanswered ping was sent, or `None` if `PipelineConfig::track_rtt`
+    /// is off or every ping was answered.
+    pub fn ping_outstanding(&self) -> Option<Duration> {
+        self.rtt.as_ref()?.outstanding()
+    }
+
+    /// Returns the estimated link quality.
+    ///
+    /// # Returns
+    ///
+    /// The current `LinkQuality`, or `None` if `PipelineConfig::link_quality` is off.
+    pub fn link_quality(&self) -> Option<LinkQuality> {
+        Some(self.quality.as_ref()?.quality())
+    }
+
+    /// Returns a receiver that sees every change of the estimated link quality, e.g. to lower
+    /// the update rate while the link is degraded.
+    ///
+    /// # Returns
+    ///
+    /// A watch receiver of the `LinkQuality`, or `None` if `PipelineConfig::link_quality` is
+    /// off.
+    pub fn watch_link_quality(&self) -> Option<watch::Receiver<LinkQuality>> {
+        Some(self.quality.as_ref()?.subscribe())
+    }
+}
+
+/// The receiving half of a pipeline.
+#[derive(Debug)]
+pub struct PipelineReceiver {
+    inbound: InboundReceiver,
+}
+
+impl PipelineReceiver {
+    /// Waits for the next inbound message.
+    ///
+    /// # Returns
+    ///
+    /// The next text, binary or close message, or `None` once the connection has ended.
+    pub async fn recv(&mut self) -> Option<Message> {
+        match &mut self.inbound {
+            InboundReceiver::Channel(inbound) => inbound.recv().await.map(|(message, _)| message),
+            InboundReceiver::Latest(queue) => queue.recv().await,
+        }
+    }
+
+    /// Returns the number of messages discarded under `InboundPolicy::DropOldest`.
+    pub fn dropped(&self) -> u64 {
+        match &self.inbound {
+            InboundReceiver::Channel(_) => 0,
+            InboundReceiver::Latest(queue) => queue.state.lock().unwrap().dropped,
+        }
+    }
+}
+
+/// A buffered inbound message, with the bytes it holds in the limits' budgets.
+type Buffered = (Message, Option<BufferReservation>);
+
+/// The reader task's end of the inbound buffer.
+#[derive(Debug)]
+enum InboundSender {
+    /// A bounded channel; sending waits while it is full.
+    Channel(mpsc::Sender<Buffered>),
+    /// A queue that discards its oldest message when full.
+    Latest(Arc<LatestQueue>),
+}
+
+/// The `PipelineReceiver`'s end of the inbound buffer.
+#[derive(Debug)]
+enum InboundReceiver {
+    Channel(mpsc::Receiver<Buffered>),
+    Latest(Arc<LatestQueue>),
+}
+
+impl InboundSender {
+    /// Buffers `message` for the receiver.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error if the receiver has been dropped.
+    async fn send(&self, message: Buffered) -> Result<(), ()> {
+        match self {
+            InboundSender::Channel(inbound) => inbound.send(message).await.map_err(|_| ()),
+            // The reader and the receiver each hold one reference.
+            InboundSender::Latest(queue) if Arc::strong_count(queue) < 2 => Err(()),
+            InboundSender::Latest(queue) => {
+                queue.push(message);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Drop for InboundSender {
+    /// Lets the receiver see the end of the connection once the buffered messages are taken.
+    fn drop(&mut self) {
+        if let InboundSender::Latest(queue) = self {
+            queue.state.lock().unwrap().closed = true;
+            queue.ready.notify_one();
+        }
+    }
+}
+
+/// A bounded inbound queue that discards its oldest message when full.
+#[derive(Debug)]
+struct LatestQueue {
+    state: std::sync::Mutex<LatestState>,
+    /// Signalled when a message is pushed or the reader stops.
+    ready: Notify,
+    capacity: usize,
+}
+
+/// The contents of a `LatestQueue`.
+#[derive(Debug, Default)]
+struct LatestState {
+    messages: VecDeque<Buffered>,
+    /// Whether the reader has stopped.
+    closed: bool,
+    /// The number of messages discarded so far.
+    dropped: u64,
+}
+
+impl LatestQueue {
+    /// Creates an empty queue holding up to `capacity` messages.
+    fn new(capacity: usize) -> Self {
+        LatestQueue {
+            state: std::sync::Mutex::new(LatestState {
+                messages: VecDeque::with_capacity(capacity),
+                ..LatestState::default()
+            }),
+            ready: Notify::new(),
+            capacity,
+        }
+    }
+
+    /// Appends `message`, discarding the oldest message if the queue is full.
+    fn push(&self, message: Buffered) {
+        let mut state = self.state.lock().unwrap();
+        if state.messages.len() >= self.capacity && state.messages.pop_front().is_some() {
+            state.dropped += 1;
+            if state.dropped.is_power_of_two() {
+                warn!("Inbound buffer full; {} messages dropped so far", state.dropped);
+            }
+        }
+        state.messages.push_back(message);
+        drop(state);
+        self.ready.notify_one();
+    }
+
+    /// Waits for the oldest buffered message, or `None` once the reader has stopped and the
+    /// queue is empty.
+    async fn recv(&self) -> Option<Message> {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some((message, _)) = state.messages.pop_front() {
+                    return Some(message);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            self.ready.notified().await;
+        }
+    }
+}
+
+/// The reader and writer tasks of a pipeline.
+///
+/// The writer stops once every `PipelineSender` is dropped, after sending a close frame; the
+/// reader stops when the connection ends or the `PipelineReceiver` is dropped.
+#[derive(Debug)]
+pub struct PipelineTasks {
+    /// The reader task, named `websocket_toolkit::reader`.
+    pub reader: JoinHandle<()>,
+    /// The writer task, named `websocket_toolkit::writer`.
+    pub writer: JoinHandle<()>,
+}
+
+impl PipelineTasks {
+    /// Aborts both tasks, dropping the connection without a close handshake.
+    pub fn abort(&self) {
+        self.reader.abort();
+        self.writer.abort();
+    }
+}
+
+/// Splits `ws_stream` into dedicated reader and writer tasks.
+///
+/// Must be called within a tokio runtime.
+///
+/// # Arguments
+///
+/// * `ws_stream` - An open WebSocket stream.
+/// * `config` - The channel sizes and flush policy.
+///
+/// # Returns
+///
+/// The sender, the receiver and the handles of the two tasks.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::pipeline::{self, PipelineConfig};
+/// use websocket_toolkit::testing::EchoServer;
+/// use websocket_toolkit::connection::WebSocketClient;
+/// use tokio_tungstenite::tungstenite::Message;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let server = EchoServer::start().await.unwrap();
+/// let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+/// let (sender, mut receiver, _tasks) = pipeline::spawn(ws_stream, PipelineConfig::default());
+///
+/// sender.send_binary(b"hello".to_vec()).await.unwrap();
+/// assert_eq!(receiver.recv().await, Some(Message::Binary(b"hello".to_vec())));
+/// # }
+/// ```
+pub fn spawn<S>(ws_stream: WebSocketStream<S>, config: PipelineConfig) -> (PipelineSender, PipelineReceiver, PipelineTasks)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    spawn_limited(ws_stream, config, None)
+}
+
+/// Like `spawn`, holding the connection's slot in `limits` until both tasks stop and
+/// reserving queued messages in its byte budgets.
+pub(crate) fn spawn_limited<S>(
+    ws_stream: WebSocketStream<S>,
+    config: PipelineConfig,
+    limits: Option<Arc<ConnectionLimits>>,
+) -> (PipelineSender, PipelineReceiver, PipelineTasks)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (sink, stream) = FragmentingStream::new(ws_stream, config.fragmentation).split();
+    let (outbound, outbound_rx) = mpsc::channel(config.outbound_capacity.max(1));
+    let (inbound_tx, inbound) = match config.inbound_policy {
+        InboundPolicy::Backpressure => {
+            let (inbound_tx, inbound) = mpsc::channel(config.inbound_capacity.max(1));
+            (InboundSender::Channel(inbound_tx), InboundReceiver::Channel(inbound))
+        }
+        InboundPolicy::DropOldest => {
+            let queue = Arc::new(LatestQueue::new(config.inbound_capacity.max(1)));
+            (InboundSender::Latest(queue.clone()), InboundReceiver::Latest(queue))
+        }
+    };
+    let rtt = (config.track_rtt || config.link_quality.is_some()).then(|| Arc::new(RttTracker::new()));
+    let quality = config.link_quality.map(|thresholds| Arc::new(LinkMonitor::new(thresholds)));
+    let liveness = Arc::new(Liveness::default());
+
+    let idle = Arc::new(Notify::new());
+    let (drain, drain_rx) = oneshot::channel();
+    let writer = spawn_named(
+        "websocket_toolkit::writer",
+        run_writer(sink, outbound_rx, drain_rx, config.flush_policy, idle.clone(), rtt.clone(), quality.clone(), limits.clone()),
+    );
+    let reader = spawn_named(
+        "websocket_toolkit::reader",
+        run_reader(stream, inbound_tx, config.idle_timeout, idle, rtt.clone(), quality.clone(), liveness.clone(), limits.clone()),
+    );
+
+    (
+        PipelineSender {
+            outbound,
+            rtt,
+            quality,
+            limits: limits.as_ref().map(Arc::downgrade),
+            drain: Arc::new(Mutex::new(Some(drain))),
+            liveness,
+        },
+        PipelineReceiver { inbound },
+        PipelineTasks { reader, writer },
+    )
+}
+
+/// Writes queued messages, flushing as `policy` dictates, until every sender is dropped,
+/// the reader reports the connection idle or a drain is requested. Pings and the time each
+/// write waits for the socket feed `quality` when it is set.
+#[allow(clippy::too_many_arguments)]
+async fn run_writer<S>(
+    mut sink: SplitSink<FragmentingStream<S>, Message>,
+    mut outbound: mpsc::Receiver<Outbound>,
+    mut drain: oneshot::Receiver<DrainRequest>,
+    policy: FlushPolicy,
+    idle: Arc<Notify>,
+    rtt: Option<Arc<RttTracker>>,
+    quality: Option<Arc<LinkMonitor>>,
+    _limits: Option<Arc<ConnectionLimits>>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut state = FlushState::new();
+    let tick = match policy {
+        FlushPolicy::Interval(interval) => interval.max(Duration::from_millis(1)),
+        _ => Duration::from_secs(3600),
+    };
+    let mut ticker = tokio::time::interval(tick);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut drain_open = true;
+
+    loop {
+        let item = tokio::select! {
+            // A drain must see the queue before the loop sends it in order.
+            biased;
+            request = &mut drain, if drain_open => match request {
+                Ok(request) => return drain_by_priority(sink, outbound, request).await,
+                // Every sender is gone; the queue closing ends the loop below.
+                Err(_) => {
+                    drain_open = false;
+                    continue;
+                }
+            },
+            item = outbound.recv() => item,
+            _ = ticker.tick(), if matches!(policy, FlushPolicy::Interval(_)) => Some(Outbound::Flush).filter(|_| state.is_due(policy)),
+            _ = idle.notified() => break,
+        };
+        let flush = match item {
+            None if outbound.is_closed() && outbound.is_empty() => {
+                info!("All senders dropped; closing the connection");
+                break;
+            }
+            None => false,
+            Some(Outbound::Flush) => state.pending() > 0,
+            Some(Outbound::Ping) => {
+                if let Some(rtt) = &rtt {
+                    rtt.record_ping();
+                }
+                if let Some(quality) = &quality {
+                    quality.record_ping();
+                }
+                let started = Instant::now();
+                if let Err(e) = sink.send(Message::Ping(PING_PAYLOAD)).await {
+                    error!("Writer failed to send ping: {}", e);
+                    return;
+                }
+                if let Some(quality) = &quality {
+                    quality.record_write(started.elapsed());
+                }
+                state.record_flush();
+                false
+            }
+            Some(Outbound::Message(message, _, _reservation)) => {
+                let started = Instant::now();
+                if let Err(e) = sink.feed(message).await {
+                    error!("Writer failed to send: {}", e);
+                    return;
+                }
+                if let Some(quality) = &quality {
+                    quality.record_write(started.elapsed());
+                }
+                state.record_feed();
+                match policy {
+                    FlushPolicy::WhenIdle => outbound.is_empty(),
+                    _ => state.is_due(policy),
+                }
+            }
+        };
+        if flush {
+            let started = Instant::now();
+            if let Err(e) = sink.flush().await {
+                error!("Writer failed to flush: {}", e);
+                return;
+            }
+            if let Some(quality) = &quality {
+                quality.record_write(started.elapsed());
+            }
+            debug!("Writer flushed {} messages", state.pending());
+            state.record_flush();
+        }
+    }
+    let _ = sink.close().await;
+}
+
+/// Sends the queued messages by delivery class within the request's grace period, closes
+/// the connection and reports what was delivered.
+async fn drain_by_priority<S>(
+    mut sink: SplitSink<FragmentingStream<S>, Message>,
+    mut outbound: mpsc::Receiver<Outbound>,
+    request: DrainRequest,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let deadline = Instant::now() + request.grace;
+    // Nothing sent from now on is delivered.
+    outbound.close();
+    let mut queued = Vec::new();
+    while let Some(item) = outbound.recv().await {
+        if let Outbound::Message(message, delivery, reservation) = item {
+            queued.push((message, delivery, reservation));
+        }
+    }
+    // Stable, so each class keeps its queue order.
+    queued.sort_by_key(|(_, delivery, _)| *delivery);
+
+    let mut report = DrainReport::default();
+    let mut failed = false;
+    for (message, delivery, _reservation) in queued {
+        let sent = !failed
+            && delivery != Delivery::BestEffort
+            && matches!(tokio::time::timeout_at(deadline, sink.feed(message)).await, Ok(Ok(())));
+        if sent {
+            report.class_mut(delivery).delivered += 1;
+        } else {
+            failed |= delivery != Delivery::BestEffort;
+            report.class_mut(delivery).discarded += 1;
+        }
+    }
+    report.closed = !failed && matches!(tokio::time::timeout_at(deadline, sink.close()).await, Ok(Ok(())));
+    if report.must_deliver.discarded > 0 {
+        warn!("Drain discarded {} must-deliver messages", report.must_deliver.discarded);
+    }
+    info!("Drained pipeline: {:?}", report);
+    let _ = request.report.send(report);
+}
+
+/// Forwards inbound text, binary and close messages until the connection ends, or until no
+/// data message has arrived for `idle_timeout`, in which case it asks the writer to close.
+/// Pongs complete RTT samples when `rtt` is set and feed `quality` when it is set, and
+/// messages wait for room in the byte budgets when `limits` is set. An unanswered probe
+/// closes the connection like an idle timeout.
+#[allow(clippy::too_many_arguments)]
+async fn run_reader<S>(
+    mut stream: SplitStream<FragmentingStream<S>>,
+    inbound: InboundSender,
+    idle_timeout: Option<Duration>,
+    idle: Arc<Notify>,
+    rtt: Option<Arc<RttTracker>>,
+    quality: Option<Arc<LinkMonitor>>,
+    liveness: Arc<Liveness>,
+    limits: Option<Arc<ConnectionLimits>>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut deadline = idle_timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+    loop {
+        let next = async {
+            match deadline {
+                Some(at) => tokio::time::timeout_at(at, stream.next()).await.ok(),
+                None => Some(stream.next().await),
+            }
+        };
+        let message = tokio::select! {
+            _ = liveness.stale.notified() => {
+                warn!("No pong to a liveness probe; closing the half-open connection");
+                idle.notify_one();
+                break;
+            }
+            next = next => match next {
+                Some(message) => message,
+                None => {
+                    info!("No messages for {:?}; closing the idle connection", idle_timeout.unwrap_or_default());
+                    idle.notify_one();
+                    break;
+                }
+            },
+        };
+        let message = match message {
+            Some(message) => message,
+            None => break,
+        };
+        if matches!(message, Ok(Message::Text(_)) | Ok(Message::Binary(_))) {
+            deadline = idle_timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+        }
+        match message {
+            Ok(Message::Pong(_)) => {
+                liveness.record_pong();
+                if let Some(sample) = rtt.as_ref().and_then(|rtt| rtt.record_pong()) {
+                    debug!("Round trip took {:?}", sample);
+                }
+                if let Some(quality) = &quality {
+                    quality.record_pong(rtt.as_ref().and_then(|rtt| rtt.stats()).map(|stats| stats.jitter));
+                }
+            }
+            Ok(Message::Ping(_)) => continue,
+            Ok(message) => {
+                let closing = message.is_close();
+                let reservation = match &limits {
+                    Some(limits) => Some(limits.reserve(message.len()).await),
+                    None => None,
+                };
+                if inbound.send((message, reservation)).await.is_err() || closing {
+                    break;
+                }
+            }
+            Err(e) => {
+                error!("Reader failed to receive: {}", e);
+                break;
+            }
+        }
+    }
+    debug!("Reader finished");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::WebSocketClient;
+    use crate::testing::{EchoServer, MockServer};
+
+    /// Tests that messages from concurrent senders are all echoed back.
+    #[tokio::test]
+    async fn test_pipeline_concurrent_senders() {
+        let server = EchoServer::start().await.expect("Failed to start echo server");
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let (sender, mut receiver, _tasks) = spawn(ws_stream, PipelineConfig::default());
+
+        let mut senders = Vec::new();
+        for task in 0..4u8 {
+            let sender = sender.clone();
+            senders.push(tokio::spawn(async move {
+                for i in 0..25u8 {
+                    sender.send_binary(vec![task, i]).await.unwrap();
+                }
+            }));
+        }
+        for handle in senders {
+            handle.await.unwrap();
+        }
+
+        let mut received = Vec::new();
+        while received.len() < 100 {
+            match receiver.recv().await {
+                Some(Message::Binary(data)) => received.push(data),
+                other => panic!("Unexpected message: {:?}", other),
+            }
+        }
+        received.sort();
+        let mut expected: Vec<Vec<u8>> = (0..4u8).flat_map(|t| (0..25u8).map(move |i| vec![t, i])).collect();
+        expected.sort();
+        assert_eq!(received, expected);
+    }
+
+    /// Tests that send permits hold queue slots until used or dropped.
+    #[tokio::test]
+    async fn test_pipeline_send_permits() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let mut connection = server.accept().await;
+        let config = PipelineConfig {
+            outbound_capacity: 1,
+            ..PipelineConfig::default()
+        };
+        let (sender, _receiver, tasks) = spawn(ws_stream, config);
+
+        let permit = sender.acquire_send_permit().await.unwrap();
+        assert_eq!(sender.queued(), 1);
+        assert!(matches!(sender.try_acquire_send_permit(), Err(TrySendError::Full(()))));
+        drop(permit);
+        let permit = sender.try_acquire_send_permit().unwrap();
+        tokio::spawn(permit.send_binary(vec![7])).await.unwrap();
+        connection.assert_next_message_eq(Message::Binary(vec![7])).await;
+
+        tasks.abort();
+        while !sender.is_closed() {
+            tokio::task::yield_now().await;
+        }
+        assert!(sender.acquire_send_permit().await.is_err());
+    }
+
+    /// Tests that a manual-flush pipeline still delivers everything once flushed.
+    #[tokio::test]
+    async fn test_pipeline_manual_flush() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let mut connection = server.accept().await;
+        let config = PipelineConfig {
+            flush_policy: FlushPolicy::Manual,
+            ..PipelineConfig::default()
+        };
+        let (sender, _receiver, _tasks) = spawn(ws_stream, config);
+
+        for i in 0..3u8 {
+            sender.send_binary(vec![i]).await.unwrap();
+        }
+        sender.flush().await.unwrap();
+        for i in 0..3u8 {
+            connection.assert_next_message_eq(Message::Binary(vec![i])).await;
+        }
+    }
+
+    /// Tests that every flush policy delivers a burst through the echo server.
+    #[tokio::test]
+    async fn test_pipeline_flush_policies() {
+        let server = EchoServer::start().await.expect("Failed to start echo server");
+        for policy in [
+            FlushPolicy::Immediate,
+            FlushPolicy::EveryN(4),
+            FlushPolicy::Interval(Duration::from_millis(5)),
+            FlushPolicy::WhenIdle,
+        ] {
+            let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+            let config = PipelineConfig {
+                flush_policy: policy,
+                ..PipelineConfig::default()
+            };
+            let (sender, mut receiver, _tasks) = spawn(ws_stream, config);
+            for i in 0..8u8 {
+                sender.send_binary(vec![i]).await.unwrap();
+            }
+            for i in 0..8u8 {
+                assert_eq!(receiver.recv().await, Some(Message::Binary(vec![i])), "{:?}", policy);
+            }
+        }
+    }
+
+    /// Tests that an idle connection is closed while an active one stays open.
+    #[tokio::test]
+    async fn test_pipeline_idle_timeout() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let mut connection = server.accept().await;
+        let config = PipelineConfig {
+            idle_timeout: Some(Duration::from_millis(100)),
+            ..PipelineConfig::default()
+        };
+        let (_sender, mut receiver, _tasks) = spawn(ws_stream, config);
+
+        for i in 0..3u8 {
+            tokio::time::sleep(Duration::from_millis(60)).await;
+            connection.send(Message::Binary(vec![i])).await;
+            assert_eq!(receiver.recv().await, Some(Message::Binary(vec![i])));
+        }
+        assert_eq!(receiver.recv().await, None);
+        assert!(connection.next_message().await.is_some_and(|message| message.is_close()));
+    }
+
+    /// Tests that pings are written with an empty payload and flush queued messages.
+    #[tokio::test]
+    async fn test_pipeline_ping() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let mut connection = server.accept().await;
+        let config = PipelineConfig {
+            flush_policy: FlushPolicy::Manual,
+            ..PipelineConfig::default()
+        };
+        let (sender, _receiver, _tasks) = spawn(ws_stream, config);
+
+        sender.send_binary(b"queued".to_vec()).await.unwrap();
+        sender.ping().await.unwrap();
+        assert_eq!(connection.next_frame().await, Some(Message::Binary(b"queued".to_vec())));
+        assert_eq!(connection.next_frame().await, Some(Message::Ping(Vec::new())));
+    }
+
+    /// Tests that dropping every sender closes the connection with a close frame.
+    #[tokio::test]
+    async fn test_pipeline_closes_when_senders_dropped() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let mut connection = server.accept().await;
+        let (sender, _receiver, tasks) = spawn(ws_stream, PipelineConfig::default());
+
+        sender.send(Message::Text("bye".into())).await.unwrap();
+        drop(sender);
+        tasks.writer.await.unwrap();
+        connection.assert_next_message_eq(Message::Text("bye".into())).await;
+        assert!(matches!(connection.next_frame().await, Some(Message::Close(_))));
+    }
+
+    /// Tests that a full drop-oldest buffer keeps the newest messages and counts the rest.
+    #[tokio::test]
+    async fn test_pipeline_drop_oldest() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let mut connection = server.accept().await;
+        let config = PipelineConfig {
+            inbound_capacity: 3,
+            inbound_policy: InboundPolicy::DropOldest,
+            ..PipelineConfig::default()
+        };
+        let (_sender, mut receiver, _tasks) = spawn(ws_stream, config);
+
+        for i in 0..10u8 {
+            connection.send(Message::Binary(vec![i])).await;
+        }
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while receiver.dropped() < 7 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("Reader did not drain the socket");
+        for i in 7..10u8 {
+            assert_eq!(receiver.recv().await, Some(Message::Binary(vec![i])));
+        }
+        assert_eq!(receiver.dropped(), 7);
+    }
+
+    /// Tests that draining sends must-deliver messages first and discards best-effort ones.
+    #[tokio::test]
+    async fn test_pipeline_drain_by_priority() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let mut connection = server.accept().await;
+        let (sender, _receiver, _tasks) = spawn(ws_stream, PipelineConfig::default());
+
+        // Queued without yielding, so the writer sees them all at once.
+        sender.send_with(Message::Text("telemetry".into()), Delivery::BestEffort).await.unwrap();
+        sender.send(Message::Text("quote".into())).await.unwrap();
+        sender.send_with(Message::Text("cancel 1".into()), Delivery::MustDeliver).await.unwrap();
+        sender.send_with(Message::Text("cancel 2".into()), Delivery::MustDeliver).await.unwrap();
+        let report = sender.drain(Duration::from_secs(5)).await.unwrap();
+
+        for expected in ["cancel 1", "cancel 2", "quote"] {
+            connection.assert_next_message_eq(Message::Text(expected.into())).await;
+        }
+        connection.assert_next_message_eq(Message::Close(None)).await;
+        assert_eq!(report.class(Delivery::MustDeliver), ClassReport { delivered: 2, discarded: 0 });
+        assert_eq!(report.normal, ClassReport { delivered: 1, discarded: 0 });
+        assert_eq!(report.best_effort, ClassReport { delivered: 0, discarded: 1 });
+        assert!(report.closed);
+        assert!(sender.drain(Duration::from_secs(1)).await.is_err());
+        assert!(sender.send(Message::Text("late".into())).await.is_err());
+    }
+
+    /// Tests that pings are timed against their pongs when RTT tracking is on.
+    #[tokio::test]
+    async fn test_pipeline_tracks_rtt() {
+        let server = EchoServer::start().await.expect("Failed to start echo server");
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let config = PipelineConfig {
+            track_rtt: true,
+            ..PipelineConfig::default()
+        };
+        let (sender, _receiver, _tasks) = spawn(ws_stream, config);
+        assert_eq!(sender.rtt(), None);
+
+        sender.ping().await.unwrap();
+        let stats = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(stats) = sender.rtt() {
+                    return stats;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("No pong received");
+        assert_eq!(stats.samples, 1);
+        assert_eq!(stats.min, stats.latest);
+    }
+
+    /// Tests that unanswered pings degrade the watched link quality.
+    #[tokio::test]
+    async fn test_pipeline_link_quality() {
+        let server = EchoServer::start().await.expect("Failed to start echo server");
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let (sender, _receiver, _tasks) = spawn(ws_stream, PipelineConfig::default());
+        assert_eq!(sender.link_quality(), None);
+        assert!(sender.watch_link_quality().is_none());
+
+        // The mock connection is never read, so no ping is ever answered.
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let _connection = server.accept().await;
+        let config = PipelineConfig { link_quality: Some(QualityThresholds::default()), ..PipelineConfig::default() };
+        let (sender, _receiver, _tasks) = spawn(ws_stream, config);
+        let mut quality = sender.watch_link_quality().unwrap();
+        assert_eq!(*quality.borrow(), LinkQuality::Good);
+
+        // The second ping misses the first one's pong, the third misses two.
+        sender.ping().await.unwrap();
+        for expected in [LinkQuality::Degraded, LinkQuality::Poor] {
+            sender.ping().await.unwrap();
+            tokio::time::timeout(Duration::from_secs(5), quality.changed()).await.unwrap().unwrap();
+            assert_eq!(*quality.borrow_and_update(), expected);
+        }
+        assert_eq!(sender.link_quality(), Some(LinkQuality::Poor));
+    }
+
+    /// Tests that an answered probe returns its round trip and an unanswered one closes the
+    /// connection.
+    #[tokio::test]
+    async fn test_pipeline_probe() {
+        let server = EchoServer::start().await.expect("Failed to start echo server");
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let (sender, _receiver, _tasks) = spawn(ws_stream, PipelineConfig::default());
+        assert!(sender.probe(Duration::from_secs(5)).await.unwrap() < Duration::from_secs(5));
+
+        // The mock connection is never read, so it never answers the ping: a half-open peer.
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let ws_stream = WebSocketClient::new(server.url(), 0).connect().await.unwrap();
+        let _connection = server.accept().await;
+        let (sender, mut receiver, _tasks) = spawn(ws_stream, PipelineConfig::default());
+        let error = sender.probe(Duration::from_millis(100)).await.unwrap_err();
+        assert!(error.contains("no pong"), "{}", error);
+        let ended = tokio::time::timeout(Duration::from_secs(5), receiver.recv()).await;
+        assert_eq!(ended.expect("Expected the connection to close"), None);
+    }
+}
@@ -0,0 +1,293 @@
+//! # `pool.rs`: Reusable payload buffers
+//!
+//! At high message rates, allocating a fresh `Vec<u8>` for every serialized or received
+//! payload makes the allocator the hottest code path. `BufferPool` keeps a bounded free list
+//! of `BytesMut` buffers: `get` hands out a cleared buffer, `serialize` encodes a message
+//! straight into one, and received payloads can be adopted with `recycle`. Every
+//! `PooledBuffer` returns its allocation to the pool when dropped.
+
+use crate::messages::MessageFormat;
+use bytes::{BufMut, Bytes, BytesMut};
+use log::{debug, error};
+use serde::Serialize;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+/// A bounded, thread-safe pool of reusable `BytesMut` buffers.
+///
+/// Cloning a `BufferPool` is cheap and shares the same free list.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::pool::BufferPool;
+///
+/// let pool = BufferPool::new(4096, 64);
+/// let mut buffer = pool.get();
+/// buffer.extend_from_slice(b"payload");
+/// drop(buffer);
+/// assert_eq!(pool.available(), 1);
+/// ```
+#[derive(Clone, Debug)]
+pub struct BufferPool {
+    /// Buffers ready for reuse.
+    free: Arc<Mutex<Vec<BytesMut>>>,
+    /// The capacity of newly allocated buffers.
+    buffer_capacity: usize,
+    /// The maximum number of buffers kept in the free list.
+    max_pooled: usize,
+}
+
+impl BufferPool {
+    /// Creates an empty pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer_capacity` - The capacity of buffers allocated when the pool is empty.
+    /// * `max_pooled` - The maximum number of idle buffers kept; extra buffers are freed.
+    ///
+    /// # Returns
+    ///
+    /// A new `BufferPool`.
+    pub fn new(buffer_capacity: usize, max_pooled: usize) -> Self {
+        BufferPool {
+            free: Arc::new(Mutex::new(Vec::with_capacity(max_pooled))),
+            buffer_capacity,
+            max_pooled,
+        }
+    }
+
+    /// Creates a pool whose free list is filled up front, so the first `max_pooled` buffers
+    /// are handed out without touching the allocator on the hot path.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer_capacity` - The capacity of each buffer.
+    /// * `max_pooled` - The number of buffers allocated now, and the most kept idle later.
+    ///
+    /// # Returns
+    ///
+    /// A new `BufferPool` with `max_pooled` idle buffers.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit::pool::BufferPool;
+    ///
+    /// let pool = BufferPool::preallocated(4096, 16);
+    /// assert_eq!(pool.available(), 16);
+    /// assert!(pool.get().capacity() >= 4096);
+    /// ```
+    pub fn preallocated(buffer_capacity: usize, max_pooled: usize) -> Self {
+        let pool = BufferPool::new(buffer_capacity, max_pooled);
+        pool.free
+            .lock()
+            .unwrap()
+            .extend((0..max_pooled).map(|_| BytesMut::with_capacity(buffer_capacity)));
+        pool
+    }
+
+    /// Takes an empty buffer from the pool, allocating one if none is idle.
+    ///
+    /// # Returns
+    ///
+    /// A cleared `PooledBuffer` that returns to this pool when dropped.
+    pub fn get(&self) -> PooledBuffer {
+        let buffer = self
+            .free
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| BytesMut::with_capacity(self.buffer_capacity));
+        PooledBuffer {
+            buffer: Some(buffer),
+            pool: self.clone(),
+        }
+    }
+
+    /// Adopts a payload allocated elsewhere, such as a received message, without copying it.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The payload; its allocation joins the pool once the returned buffer is dropped.
+    ///
+    /// # Returns
+    ///
+    /// A `PooledBuffer` holding `data`.
+    pub fn recycle(&self, data: Vec<u8>) -> PooledBuffer {
+        PooledBuffer {
+            buffer: Some(BytesMut::from(Bytes::from(data))),
+            pool: self.clone(),
+        }
+    }
+
+    /// Serializes `data` directly into a pooled buffer.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The data to serialize.
+    /// * `format` - The wire format.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the encoded `PooledBuffer`, or an error message on failure.
+    pub fn serialize<T: Serialize>(&self, data: &T, format: MessageFormat) -> Result<PooledBuffer, String> {
+        let mut buffer = self.get();
+        let result: Result<(), String> = match format {
+            MessageFormat::Json => {
+                #[cfg(feature = "json")]
+                {
+                    serde_json::to_writer(BufMut::writer(&mut *buffer), data).map_err(|e| {
+                        error!("Failed to serialize JSON: {}", e);
+                        format!("Failed to serialize JSON: {}", e)
+                    })
+                }
+                #[cfg(not(feature = "json"))]
+                {
+                    let _ = (data, &mut buffer);
+                    Err("JSON support is disabled; enable the `json` feature".to_string())
+                }
+            }
+            MessageFormat::Cbor => {
+                #[cfg(feature = "cbor")]
+                {
+                    serde_cbor::to_writer(BufMut::writer(&mut *buffer), data).map_err(|e| {
+                        error!("Failed to serialize CBOR: {}", e);
+                        format!("Failed to serialize CBOR: {}", e)
+                    })
+                }
+                #[cfg(not(feature = "cbor"))]
+                {
+                    let _ = (data, &mut buffer);
+                    Err("CBOR support is disabled; enable the `cbor` feature".to_string())
+                }
+            }
+        };
+        result.map(|_| buffer)
+    }
+
+    /// Returns the number of idle buffers in the pool.
+    pub fn available(&self) -> usize {
+        self.free.lock().unwrap().len()
+    }
+
+    /// Puts a buffer back on the free list, unless the pool is full.
+    fn release(&self, mut buffer: BytesMut) {
+        buffer.clear();
+        let mut free = self.free.lock().unwrap();
+        if free.len() < self.max_pooled {
+            free.push(buffer);
+        } else {
+            debug!("Buffer pool full; freeing a {} byte buffer", buffer.capacity());
+        }
+    }
+}
+
+impl Default for BufferPool {
+    /// Creates a pool of up to 64 buffers of 4 KiB each.
+    fn default() -> Self {
+        BufferPool::new(4096, 64)
+    }
+}
+
+/// A buffer borrowed from a `BufferPool`.
+///
+/// Dereferences to `BytesMut`, and is returned to its pool when dropped.
+#[derive(Debug)]
+pub struct PooledBuffer {
+    /// The buffer; only `None` after `into_vec` has taken it.
+    buffer: Option<BytesMut>,
+    /// The pool the buffer returns to.
+    pool: BufferPool,
+}
+
+impl PooledBuffer {
+    /// Detaches the buffer from the pool and converts it into a `Vec<u8>` without copying.
+    ///
+    /// Use this to hand the payload to tungstenite, which takes ownership of a `Vec<u8>`.
+    pub fn into_vec(mut self) -> Vec<u8> {
+        self.buffer.take().map(Vec::from).unwrap_or_default()
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = BytesMut;
+
+    fn deref(&self) -> &BytesMut {
+        self.buffer.as_ref().expect("PooledBuffer used after into_vec")
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut BytesMut {
+        self.buffer.as_mut().expect("PooledBuffer used after into_vec")
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.pool.release(buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::MessageHandler;
+
+    /// Tests that dropped buffers are reused instead of reallocated.
+    #[test]
+    fn test_buffers_are_reused() {
+        let pool = BufferPool::new(128, 2);
+        let mut buffer = pool.get();
+        buffer.extend_from_slice(b"hello");
+        let pointer = buffer.as_ptr();
+        drop(buffer);
+
+        let buffer = pool.get();
+        assert!(buffer.is_empty(), "Expected a cleared buffer");
+        assert_eq!(buffer.as_ptr(), pointer, "Expected the same allocation");
+    }
+
+    /// Tests that the pool never holds more than its maximum number of idle buffers.
+    #[test]
+    fn test_pool_is_bounded() {
+        let pool = BufferPool::new(16, 1);
+        let buffers = vec![pool.get(), pool.get(), pool.recycle(vec![1, 2, 3])];
+        drop(buffers);
+        assert_eq!(pool.available(), 1);
+    }
+
+    /// Tests that pooled serialization matches `MessageHandler::serialize`.
+    #[cfg(all(feature = "json", feature = "cbor"))]
+    #[test]
+    fn test_pooled_serialize_matches_handler() {
+        let pool = BufferPool::default();
+        for format in [MessageFormat::Json, MessageFormat::Cbor] {
+            let pooled = pool.serialize(&("hello", 7), format).unwrap();
+            assert_eq!(&pooled[..], &MessageHandler::serialize(&("hello", 7), format).unwrap()[..]);
+        }
+        assert_eq!(pool.available(), 1, "Expected the first buffer to be reused");
+    }
+
+    /// Tests that recycling a received payload keeps its allocation.
+    #[test]
+    fn test_recycle_is_zero_copy() {
+        let pool = BufferPool::default();
+        let data = b"received".to_vec();
+        let pointer = data.as_ptr();
+        drop(pool.recycle(data));
+        assert_eq!(pool.get().as_ptr(), pointer);
+    }
+
+    /// Tests that `into_vec` detaches the buffer from the pool.
+    #[test]
+    fn test_into_vec_detaches() {
+        let pool = BufferPool::default();
+        let vec = pool.recycle(b"data".to_vec()).into_vec();
+        assert_eq!(vec, b"data");
+        assert_eq!(pool.available(), 0);
+    }
+}
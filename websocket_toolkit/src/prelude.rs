@@ -0,0 +1,48 @@
+//! # `prelude.rs`: The types most applications need
+//!
+//! A single glob import brings the controller, its configuration, the message types and the
+//! tungstenite types that appear in the controller's signatures into scope, so basic usage
+//! needs neither a list of module paths nor a direct dependency on `tokio-tungstenite`:
+//!
+//! ```rust
+//! use websocket_toolkit::prelude::*;
+//!
+//! async fn greet(controller: &mut WebSocketController) -> Result<(), BoxError> {
+//!     let mut ws_stream = controller.connect().await?;
+//!     let hello = Envelope::new("hello", Vec::new()).encode(MessageFormat::Json)?;
+//!     controller.send_message(&mut ws_stream, &hello).await?;
+//!     if let Some(reply) = controller.receive_inbound(&mut ws_stream).await? {
+//!         println!("{:?}", Message::from(reply));
+//!     }
+//!     Ok(())
+//! }
+//! ```
+
+pub use crate::connection::WebSocketClient;
+#[cfg(any(feature = "tokio", target_arch = "wasm32"))]
+pub use crate::controller::WebSocketController;
+pub use crate::messages::{Envelope, FrameKind, InboundMessage, MessageFormat, MessageHandler, TextMode};
+pub use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+pub use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+pub use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub use crate::config::Config;
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub use crate::events::EventDriver;
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::flush::FlushPolicy;
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub use crate::handle::ConnectionHandle;
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub use crate::pipeline::{InboundPolicy, PipelineConfig, PipelineReceiver, PipelineSender};
+#[cfg(not(target_arch = "wasm32"))]
+pub use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+#[cfg(all(feature = "keep-alive", not(target_arch = "wasm32")))]
+pub use crate::keep_alive::KeepAlive;
+#[cfg(feature = "reconnection")]
+pub use crate::reconnection::{BackoffStrategy, ReconnectError, ReconnectStrategy};
+
+/// The error type returned by the controller's methods.
+pub type BoxError = Box<dyn std::error::Error>;
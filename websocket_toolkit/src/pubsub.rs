@@ -0,0 +1,413 @@
+//! # `pubsub.rs`: Topic-based publish/subscribe
+//!
+//! `PubSubClient` opens its own connection with a controller and turns it into topics:
+//! `subscribe("prices")` returns a `TopicStream` of the envelopes published to that topic,
+//! `publish("prices", payload)` sends one, and a background reader routes every received
+//! envelope to the subscribers of its topic, so consumers of different topics never see each
+//! other's messages.
+//!
+//! The topic of an envelope is read from the field chosen with `TopicField`: its `type` by
+//! default, or a header for servers that keep the message type separate from the topic.
+//! The first stream of a topic sends `typed_stream::subscribe_envelope(topic)` and dropping
+//! the last one sends `unsubscribe_envelope(topic)`, the same requests typed streams use.
+//! Received envelopes of topics with no subscriber, and messages that are not envelopes, are
+//! dropped.
+//!
+//! Each stream buffers up to the pipeline's `inbound_capacity` envelopes; while one is full,
+//! the reader waits, holding up the other topics too, so keep consumers draining. The streams
+//! end when the connection does; the client does not reconnect.
+
+use crate::controller::WebSocketController;
+use crate::handle::ConnectionHandle;
+use crate::messages::Envelope;
+use crate::pipeline::PipelineConfig;
+use crate::tasks::spawn_named;
+use crate::typed_stream::{subscribe_envelope, unsubscribe_envelope};
+use futures_util::Stream;
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+/// The envelope kind of publications when the topic is carried in a header.
+pub const PUBLISH_KIND: &str = "publish";
+
+/// Which envelope field names the topic.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum TopicField {
+    /// The envelope's `type`.
+    #[default]
+    Kind,
+    /// The header with this name. Publications are sent with the type `PUBLISH_KIND`.
+    Header(String),
+}
+
+impl TopicField {
+    /// Returns the topic of `envelope`, if it has one.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - A received envelope.
+    pub fn topic<'a>(&self, envelope: &'a Envelope) -> Option<&'a str> {
+        match self {
+            TopicField::Kind => Some(envelope.kind.as_str()),
+            TopicField::Header(name) => envelope.headers.get(name).map(String::as_str),
+        }
+    }
+
+    /// Builds the envelope publishing `payload` to `topic`.
+    ///
+    /// # Arguments
+    ///
+    /// * `topic` - The topic.
+    /// * `payload` - The encoded message body.
+    pub fn envelope(&self, topic: &str, payload: Vec<u8>) -> Envelope {
+        match self {
+            TopicField::Kind => Envelope::new(topic, payload),
+            TopicField::Header(name) => {
+                let mut envelope = Envelope::new(PUBLISH_KIND, payload);
+                envelope.headers.insert(name.clone(), topic.to_string());
+                envelope
+            }
+        }
+    }
+}
+
+/// Publishes to and subscribes to topics over one connection.
+///
+/// Clones share the connection and the subscriptions.
+///
+/// # Examples
+///
+/// ```rust
+/// use futures_util::StreamExt;
+/// use std::sync::Arc;
+/// use websocket_toolkit::controller::WebSocketController;
+/// use websocket_toolkit::pipeline::PipelineConfig;
+/// use websocket_toolkit::pubsub::{PubSubClient, TopicField};
+/// use websocket_toolkit::testing::EchoServer;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let server = EchoServer::start().await.unwrap();
+/// let controller = Arc::new(WebSocketController::new(server.url(), 0, None));
+/// let client = PubSubClient::connect(controller, PipelineConfig::default(), TopicField::Kind).await.unwrap();
+///
+/// let mut prices = client.subscribe("prices");
+/// // The echo server publishes everything back.
+/// client.publish("prices", b"12.5".to_vec()).await.unwrap();
+/// assert_eq!(prices.next().await.unwrap().payload, b"12.5");
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct PubSubClient {
+    shared: Arc<Shared>,
+}
+
+/// The open streams of each topic, by stream id.
+type Subscribers = Mutex<HashMap<String, Vec<(u64, mpsc::Sender<Envelope>)>>>;
+
+/// State shared with the reader task and the streams.
+struct Shared {
+    controller: Arc<WebSocketController>,
+    handle: ConnectionHandle,
+    field: TopicField,
+    capacity: usize,
+    subscribers: Subscribers,
+    next_id: AtomicU64,
+}
+
+impl Shared {
+    /// Sends `envelope` with the controller's codec.
+    async fn send(&self, envelope: &Envelope) -> Result<(), String> {
+        let payload = self.controller.encode_envelope(envelope)?;
+        self.handle.send(self.controller.frame_kind().frame(payload)?).await
+    }
+}
+
+impl PubSubClient {
+    /// Opens a connection with `controller` and starts routing envelopes on it.
+    ///
+    /// # Arguments
+    ///
+    /// * `controller` - Connects, and encodes and decodes envelopes.
+    /// * `config` - The pipeline settings of the connection; `inbound_capacity` also bounds
+    ///   each stream's buffer.
+    /// * `field` - Which envelope field names the topic.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the client, or a boxed error if the connection fails.
+    pub async fn connect(
+        controller: Arc<WebSocketController>,
+        config: PipelineConfig,
+        field: TopicField,
+    ) -> Result<Self, Box<dyn StdError>> {
+        let handle = controller.connect_handle(config).await?;
+        Ok(Self::new(controller, handle, config.inbound_capacity, field))
+    }
+
+    /// Starts routing envelopes on an open connection. The client takes every message
+    /// received on `handle` from now on.
+    ///
+    /// Must be called within a tokio runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `controller` - Encodes and decodes envelopes.
+    /// * `handle` - The connection to publish and subscribe on.
+    /// * `capacity` - How many envelopes each stream buffers before the reader waits.
+    /// * `field` - Which envelope field names the topic.
+    ///
+    /// # Returns
+    ///
+    /// A new `PubSubClient`.
+    pub fn new(controller: Arc<WebSocketController>, handle: ConnectionHandle, capacity: usize, field: TopicField) -> Self {
+        let shared = Arc::new(Shared {
+            controller,
+            handle: handle.clone(),
+            field,
+            capacity: capacity.max(1),
+            subscribers: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+        });
+        spawn_named("websocket_toolkit::pubsub_reader", route(handle, Arc::downgrade(&shared)));
+        PubSubClient { shared }
+    }
+
+    /// Returns a stream of the envelopes published to `topic`, subscribing if it is the
+    /// topic's first stream.
+    ///
+    /// Must be called within a tokio runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `topic` - The topic.
+    ///
+    /// # Returns
+    ///
+    /// A `TopicStream` that ends when the connection does.
+    pub fn subscribe(&self, topic: &str) -> TopicStream {
+        let (sender, receiver) = mpsc::channel(self.shared.capacity);
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        let first = {
+            let mut subscribers = self.shared.subscribers.lock().unwrap();
+            let streams = subscribers.entry(topic.to_string()).or_default();
+            streams.push((id, sender));
+            streams.len() == 1
+        };
+        if first {
+            announce(&self.shared, subscribe_envelope(topic));
+        }
+        TopicStream { receiver, topic: topic.to_string(), id, shared: self.shared.clone() }
+    }
+
+    /// Publishes `payload` to `topic`.
+    ///
+    /// # Arguments
+    ///
+    /// * `topic` - The topic.
+    /// * `payload` - The encoded message body.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the envelope was queued, or an error message if it could not be
+    /// encoded or the connection is closed.
+    pub async fn publish(&self, topic: &str, payload: Vec<u8>) -> Result<(), String> {
+        self.shared.send(&self.shared.field.envelope(topic, payload)).await
+    }
+
+    /// Returns the topics with at least one open stream.
+    pub fn topics(&self) -> Vec<String> {
+        let mut topics: Vec<String> = self.shared.subscribers.lock().unwrap().keys().cloned().collect();
+        topics.sort();
+        topics
+    }
+
+    /// Returns the connection the client publishes and subscribes on.
+    pub fn handle(&self) -> &ConnectionHandle {
+        &self.shared.handle
+    }
+}
+
+impl fmt::Debug for PubSubClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PubSubClient").field("field", &self.shared.field).field("topics", &self.topics()).finish()
+    }
+}
+
+/// Sends a subscription change in the background.
+fn announce(shared: &Arc<Shared>, envelope: Envelope) {
+    let shared = shared.clone();
+    spawn_named("websocket_toolkit::pubsub_announce", async move {
+        if let Err(e) = shared.send(&envelope).await {
+            debug!("Failed to send {} of {:?}: {}", envelope.kind, String::from_utf8_lossy(&envelope.payload), e);
+        }
+    });
+}
+
+/// Routes received envelopes to the streams of their topic until the connection ends, which
+/// ends every stream.
+async fn route(handle: ConnectionHandle, shared: Weak<Shared>) {
+    while let Some(message) = handle.recv_inbound().await {
+        let Some(shared) = shared.upgrade() else { return };
+        let envelope = match shared.controller.decode_envelope(message.as_bytes()) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                debug!("Dropping a message that is not an envelope: {}", e);
+                continue;
+            }
+        };
+        let streams: Vec<mpsc::Sender<Envelope>> = match shared.field.topic(&envelope) {
+            Some(topic) => shared
+                .subscribers
+                .lock()
+                .unwrap()
+                .get(topic)
+                .map(|streams| streams.iter().map(|(_, sender)| sender.clone()).collect())
+                .unwrap_or_default(),
+            None => Vec::new(),
+        };
+        drop(shared);
+        if streams.is_empty() {
+            debug!("Dropping an envelope of type {} with no subscriber", envelope.kind);
+        }
+        for stream in streams {
+            // A stream dropped meanwhile has unsubscribed itself.
+            let _ = stream.send(envelope.clone()).await;
+        }
+    }
+    if let Some(shared) = shared.upgrade() {
+        shared.subscribers.lock().unwrap().clear();
+    }
+}
+
+/// The envelopes published to one topic, from `PubSubClient::subscribe`.
+///
+/// Dropping the last stream of a topic unsubscribes from it.
+pub struct TopicStream {
+    receiver: mpsc::Receiver<Envelope>,
+    topic: String,
+    id: u64,
+    shared: Arc<Shared>,
+}
+
+impl TopicStream {
+    /// Returns the topic of the stream.
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+}
+
+impl Stream for TopicStream {
+    type Item = Envelope;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Envelope>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for TopicStream {
+    fn drop(&mut self) {
+        let last = {
+            let mut subscribers = self.shared.subscribers.lock().unwrap();
+            match subscribers.get_mut(&self.topic) {
+                Some(streams) => {
+                    streams.retain(|(id, _)| *id != self.id);
+                    let last = streams.is_empty();
+                    if last {
+                        subscribers.remove(&self.topic);
+                    }
+                    last
+                }
+                // The connection ended.
+                None => false,
+            }
+        };
+        if last {
+            if self.shared.handle.sender().is_closed() {
+                return;
+            }
+            match tokio::runtime::Handle::try_current() {
+                Ok(_) => announce(&self.shared, unsubscribe_envelope(&self.topic)),
+                Err(_) => warn!("Dropped the last stream of {} outside a runtime; not unsubscribing", self.topic),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for TopicStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TopicStream").field("topic", &self.topic).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::EchoServer;
+    use crate::typed_stream::{SUBSCRIBE_KIND, UNSUBSCRIBE_KIND};
+    use futures_util::StreamExt;
+    use std::time::Duration;
+
+    /// Tests routing by type, subscription changes, and that streams end with the
+    /// connection.
+    #[tokio::test]
+    async fn test_pubsub() {
+        let server = EchoServer::start().await.unwrap();
+        let controller = Arc::new(WebSocketController::new(server.url(), 0, None));
+        let client = PubSubClient::connect(controller, PipelineConfig::default(), TopicField::Kind).await.unwrap();
+        // The echo server reflects subscription changes, which are routed like publications.
+        let mut subscribes = client.subscribe(SUBSCRIBE_KIND);
+        let mut unsubscribes = client.subscribe(UNSUBSCRIBE_KIND);
+
+        let mut prices = client.subscribe("prices");
+        let mut prices_too = client.subscribe("prices");
+        let news = client.subscribe("news");
+        assert_eq!(client.topics(), vec!["news", "prices", SUBSCRIBE_KIND, UNSUBSCRIBE_KIND]);
+        let mut subscribed = Vec::new();
+        for _ in 0..4 {
+            subscribed.push(String::from_utf8(subscribes.next().await.unwrap().payload).unwrap());
+        }
+        subscribed.sort();
+        assert_eq!(subscribed, vec!["news", "prices", SUBSCRIBE_KIND, UNSUBSCRIBE_KIND]);
+
+        client.publish("prices", b"12.5".to_vec()).await.unwrap();
+        client.publish("weather", b"rain".to_vec()).await.unwrap();
+        assert_eq!(prices.next().await.unwrap().payload, b"12.5");
+        assert_eq!(prices_too.next().await.unwrap().payload, b"12.5");
+        assert_eq!(prices.topic(), "prices");
+
+        drop(news);
+        assert_eq!(unsubscribes.next().await.unwrap().payload, b"news");
+        drop(prices_too);
+        client.publish("prices", b"13".to_vec()).await.unwrap();
+        assert_eq!(prices.next().await.unwrap().payload, b"13");
+        assert_eq!(client.topics(), vec!["prices", SUBSCRIBE_KIND, UNSUBSCRIBE_KIND]);
+
+        client.handle().shutdown().await.unwrap();
+        let ended = tokio::time::timeout(Duration::from_secs(5), prices.next()).await.unwrap();
+        assert!(ended.is_none());
+    }
+
+    /// Tests routing by a header.
+    #[tokio::test]
+    async fn test_pubsub_header_topics() {
+        let field = TopicField::Header("topic".to_string());
+        let envelope = field.envelope("alerts", b"fire".to_vec());
+        assert_eq!((envelope.kind.as_str(), field.topic(&envelope)), (PUBLISH_KIND, Some("alerts")));
+        assert_eq!(field.topic(&Envelope::new("alerts", Vec::new())), None);
+
+        let server = EchoServer::start().await.unwrap();
+        let controller = Arc::new(WebSocketController::new(server.url(), 0, None));
+        let client = PubSubClient::connect(controller, PipelineConfig::default(), field).await.unwrap();
+        let mut alerts = client.subscribe("alerts");
+        client.publish("alerts", b"fire".to_vec()).await.unwrap();
+        assert_eq!(alerts.next().await.unwrap().payload, b"fire");
+    }
+}
@@ -0,0 +1,250 @@
+//! # `quality.rs`: Link quality estimation
+//!
+//! A connection usually degrades before it dies: round trips start to wobble, pongs go
+//! missing and writes back up behind a congested link. `LinkMonitor` turns those signals into
+//! a coarse `LinkQuality` (good, degraded or poor) that applications can watch and adapt to,
+//! e.g. by lowering their update rate, before the keep-alive gives up on the connection.
+//!
+//! Pipelines created with `PipelineConfig::link_quality` feed a monitor from their keep-alive
+//! pings, pongs and writes; `PipelineSender::watch_link_quality` returns a watch channel that
+//! changes whenever the quality does. The quality is the worst of three signals:
+//!
+//! - the round-trip jitter (the smoothed variation between samples, see the `rtt` module);
+//! - the number of consecutive pings that went unanswered before the next one was sent;
+//! - the number of writes that took longer than `stall` within the last `stall_window`.
+//!
+//! The quality is only re-assessed when a ping, pong or write happens, so stalls age out of
+//! the window on the next keep-alive ping.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// A coarse estimate of how well a connection is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkQuality {
+    /// Round trips are steady, pongs arrive and writes go through.
+    #[default]
+    Good,
+    /// The link is struggling; consider sending less.
+    Degraded,
+    /// The link is close to failing.
+    Poor,
+}
+
+/// When each signal counts as degraded or poor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QualityThresholds {
+    /// Round-trip jitter from which the link is degraded.
+    pub degraded_jitter: Duration,
+    /// Round-trip jitter from which the link is poor.
+    pub poor_jitter: Duration,
+    /// Consecutive unanswered pings from which the link is degraded.
+    pub degraded_missed_pongs: u32,
+    /// Consecutive unanswered pings from which the link is poor.
+    pub poor_missed_pongs: u32,
+    /// How long a write may take before it counts as a stall.
+    pub stall: Duration,
+    /// How long a stall counts towards the quality.
+    pub stall_window: Duration,
+    /// Stalls within the window from which the link is degraded.
+    pub degraded_stalls: u32,
+    /// Stalls within the window from which the link is poor.
+    pub poor_stalls: u32,
+}
+
+impl Default for QualityThresholds {
+    /// Degraded from 50 ms of jitter, one missed pong or one stall of a second within a
+    /// minute; poor from 250 ms of jitter, two missed pongs or three stalls.
+    fn default() -> Self {
+        QualityThresholds {
+            degraded_jitter: Duration::from_millis(50),
+            poor_jitter: Duration::from_millis(250),
+            degraded_missed_pongs: 1,
+            poor_missed_pongs: 2,
+            stall: Duration::from_secs(1),
+            stall_window: Duration::from_secs(60),
+            degraded_stalls: 1,
+            poor_stalls: 3,
+        }
+    }
+}
+
+impl QualityThresholds {
+    /// Returns the quality of a signal given its two thresholds.
+    fn level<T: PartialOrd>(value: T, degraded: T, poor: T) -> LinkQuality {
+        if value >= poor {
+            LinkQuality::Poor
+        } else if value >= degraded {
+            LinkQuality::Degraded
+        } else {
+            LinkQuality::Good
+        }
+    }
+}
+
+/// Estimates a connection's quality from its pings, pongs and writes.
+#[derive(Debug)]
+pub struct LinkMonitor {
+    thresholds: QualityThresholds,
+    state: Mutex<MonitorState>,
+    quality: watch::Sender<LinkQuality>,
+}
+
+/// The monitor's mutable state.
+#[derive(Debug, Default)]
+struct MonitorState {
+    /// Whether the last ping is still waiting for its pong.
+    ping_outstanding: bool,
+    /// Pings superseded without a pong since the last pong.
+    missed_pongs: u32,
+    /// The latest smoothed round-trip jitter.
+    jitter: Duration,
+    /// When the stalls within the window happened, oldest first.
+    stalls: VecDeque<Instant>,
+}
+
+impl LinkMonitor {
+    /// Creates a monitor that reports `LinkQuality::Good` until a signal says otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `thresholds` - When each signal counts as degraded or poor.
+    ///
+    /// # Returns
+    ///
+    /// A new `LinkMonitor`.
+    pub fn new(thresholds: QualityThresholds) -> Self {
+        LinkMonitor {
+            thresholds,
+            state: Mutex::new(MonitorState::default()),
+            quality: watch::channel(LinkQuality::Good).0,
+        }
+    }
+
+    /// Records that a ping is being sent now; a ping still waiting for its pong counts as
+    /// missed.
+    pub fn record_ping(&self) {
+        self.update(|state| {
+            if state.ping_outstanding {
+                state.missed_pongs += 1;
+            }
+            state.ping_outstanding = true;
+        });
+    }
+
+    /// Records that a pong arrived now.
+    ///
+    /// # Arguments
+    ///
+    /// * `jitter` - The smoothed round-trip jitter including this pong's sample, if known.
+    pub fn record_pong(&self, jitter: Option<Duration>) {
+        self.update(|state| {
+            state.ping_outstanding = false;
+            state.missed_pongs = 0;
+            if let Some(jitter) = jitter {
+                state.jitter = jitter;
+            }
+        });
+    }
+
+    /// Records that a write to the socket took `elapsed`.
+    ///
+    /// # Arguments
+    ///
+    /// * `elapsed` - How long the write waited for the socket.
+    pub fn record_write(&self, elapsed: Duration) {
+        let stall = self.thresholds.stall;
+        self.update(|state| {
+            if elapsed >= stall {
+                state.stalls.push_back(Instant::now());
+            }
+        });
+    }
+
+    /// Returns the current quality.
+    pub fn quality(&self) -> LinkQuality {
+        *self.quality.borrow()
+    }
+
+    /// Returns a receiver that sees every change of the quality.
+    pub fn subscribe(&self) -> watch::Receiver<LinkQuality> {
+        self.quality.subscribe()
+    }
+
+    /// Applies `record` to the state and publishes the re-assessed quality if it changed.
+    fn update(&self, record: impl FnOnce(&mut MonitorState)) {
+        let mut state = self.state.lock().unwrap();
+        record(&mut state);
+        let thresholds = &self.thresholds;
+        let window_start = Instant::now().checked_sub(thresholds.stall_window);
+        while matches!((state.stalls.front(), window_start), (Some(at), Some(start)) if *at < start) {
+            state.stalls.pop_front();
+        }
+        let quality = QualityThresholds::level(state.jitter, thresholds.degraded_jitter, thresholds.poor_jitter)
+            .max(QualityThresholds::level(
+                state.missed_pongs,
+                thresholds.degraded_missed_pongs,
+                thresholds.poor_missed_pongs,
+            ))
+            .max(QualityThresholds::level(
+                state.stalls.len() as u32,
+                thresholds.degraded_stalls,
+                thresholds.poor_stalls,
+            ));
+        drop(state);
+        if *self.quality.borrow() != quality {
+            self.quality.send_replace(quality);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that each signal degrades the quality and that the worst one wins.
+    #[test]
+    fn test_link_monitor() {
+        let thresholds = QualityThresholds {
+            stall: Duration::from_millis(100),
+            stall_window: Duration::from_millis(200),
+            degraded_stalls: 1,
+            poor_stalls: 2,
+            ..QualityThresholds::default()
+        };
+        let monitor = LinkMonitor::new(thresholds);
+        let mut changes = monitor.subscribe();
+        assert_eq!(monitor.quality(), LinkQuality::Good);
+
+        monitor.record_ping();
+        monitor.record_pong(Some(Duration::from_millis(10)));
+        monitor.record_write(Duration::from_millis(5));
+        assert_eq!(monitor.quality(), LinkQuality::Good);
+        assert!(!changes.has_changed().unwrap());
+
+        // Two pings in a row without a pong miss one, a third misses two.
+        monitor.record_ping();
+        monitor.record_ping();
+        assert_eq!(*changes.borrow_and_update(), LinkQuality::Degraded);
+        monitor.record_ping();
+        assert_eq!(monitor.quality(), LinkQuality::Poor);
+        monitor.record_pong(Some(Duration::from_millis(60)));
+        assert_eq!(monitor.quality(), LinkQuality::Degraded);
+        monitor.record_pong(Some(Duration::from_millis(300)));
+        assert_eq!(monitor.quality(), LinkQuality::Poor);
+        monitor.record_pong(Some(Duration::ZERO));
+        assert_eq!(*changes.borrow_and_update(), LinkQuality::Good);
+
+        monitor.record_write(Duration::from_millis(150));
+        assert_eq!(monitor.quality(), LinkQuality::Degraded);
+        monitor.record_write(Duration::from_millis(150));
+        assert_eq!(monitor.quality(), LinkQuality::Poor);
+        std::thread::sleep(Duration::from_millis(250));
+        monitor.record_ping();
+        assert_eq!(monitor.quality(), LinkQuality::Good);
+    }
+}
@@ -0,0 +1,431 @@
+//! # `ratelimit.rs`: Inbound rate limiting
+//!
+//! A misbehaving peer can flood a connection with messages faster than its handler can
+//! process them. `InboundRateLimiter` enforces a message rate and a byte rate per connection
+//! with token buckets, and `recv_limited` applies it while reading from a `WebSocketStream`,
+//! with one of three responses once the peer exceeds its rate:
+//!
+//! - `RateLimitAction::Delay` stops reading until the peer is back within its rate, so TCP
+//!   flow control slows it down.
+//! - `RateLimitAction::Warn` logs the violation and carries on.
+//! - `RateLimitAction::Close` closes the connection with code 1008 (Policy Violation).
+//!
+//! The toolkit has no server module of its own; the limiter works on any stream, including
+//! server-side streams accepted with `tokio_tungstenite::accept_async`, and can equally guard
+//! a client against a misbehaving server.
+//!
+//! `set_limit` changes the rates of a running limiter without resetting what the peer has
+//! used, and `follow` applies the updates published on a `watch` channel, such as the one of
+//! `WebSocketController::watch_inbound_rate_limit`, so limits can be tuned without
+//! reconnecting.
+
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, warn};
+use std::borrow::Cow;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::watch;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/// What happens when a peer exceeds its rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RateLimitAction {
+    /// Stop reading until the peer is back within its rate.
+    #[default]
+    Delay,
+    /// Log a warning and keep going.
+    Warn,
+    /// Close the connection with 1008 (Policy Violation).
+    Close,
+}
+
+/// The rates a peer may send at; `None` leaves a rate unlimited.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InboundRateLimit {
+    /// The messages per second a peer may send on average.
+    pub messages_per_sec: Option<f64>,
+    /// The payload bytes per second a peer may send on average.
+    pub bytes_per_sec: Option<f64>,
+    /// How long the peer may send above its rates after a quiet period; the buckets hold
+    /// this many seconds' worth of messages and bytes.
+    pub burst: Duration,
+    /// The response to a peer above its rate.
+    pub action: RateLimitAction,
+}
+
+impl Default for InboundRateLimit {
+    /// 100 messages and 1 MiB per second with bursts of 1 second, delaying reads above that.
+    fn default() -> Self {
+        InboundRateLimit {
+            messages_per_sec: Some(100.0),
+            bytes_per_sec: Some(1024.0 * 1024.0),
+            burst: Duration::from_secs(1),
+            action: RateLimitAction::Delay,
+        }
+    }
+}
+
+/// The limiter's decision for one received message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateDecision {
+    /// The message is within the rates.
+    Allow,
+    /// The message exceeds the rates; wait this long before reading on.
+    Delay(Duration),
+    /// The message exceeds the rates; log it.
+    Warn,
+    /// The message exceeds the rates; close the connection.
+    Close,
+}
+
+/// A token bucket refilled at `rate` tokens per second up to `capacity`.
+#[derive(Debug, Clone)]
+struct Bucket {
+    rate: f64,
+    capacity: f64,
+    /// The tokens left; negative while a delayed peer is in debt.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: f64, burst: Duration, now: Instant) -> Self {
+        let capacity = (rate * burst.as_secs_f64()).max(1.0);
+        Bucket { rate, capacity, tokens: capacity, updated: now }
+    }
+
+    /// Refills the bucket and takes `amount` tokens, returning how long the debt takes to
+    /// repay if the bucket went negative.
+    fn take(&mut self, amount: f64, now: Instant) -> Option<Duration> {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+        self.tokens -= amount;
+        (self.tokens < 0.0).then(|| Duration::from_secs_f64(-self.tokens / self.rate))
+    }
+}
+
+/// Enforces an `InboundRateLimit` on one connection.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Instant;
+/// use websocket_toolkit::ratelimit::{InboundRateLimit, InboundRateLimiter, RateDecision, RateLimitAction};
+///
+/// let mut limiter = InboundRateLimiter::new(InboundRateLimit {
+///     messages_per_sec: Some(2.0),
+///     action: RateLimitAction::Warn,
+///     ..InboundRateLimit::default()
+/// });
+/// let now = Instant::now();
+/// assert_eq!(limiter.check_at(10, now), RateDecision::Allow);
+/// assert_eq!(limiter.check_at(10, now), RateDecision::Allow);
+/// assert_eq!(limiter.check_at(10, now), RateDecision::Warn);
+/// assert_eq!(limiter.violations(), 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct InboundRateLimiter {
+    limit: InboundRateLimit,
+    messages: Option<Bucket>,
+    bytes: Option<Bucket>,
+    violations: u64,
+}
+
+impl InboundRateLimiter {
+    /// Creates a limiter with full buckets.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The rates and the response to exceeding them.
+    ///
+    /// # Returns
+    ///
+    /// A new `InboundRateLimiter`.
+    pub fn new(limit: InboundRateLimit) -> Self {
+        let now = Instant::now();
+        InboundRateLimiter {
+            limit,
+            messages: limit.messages_per_sec.filter(|rate| *rate > 0.0).map(|rate| Bucket::new(rate, limit.burst, now)),
+            bytes: limit.bytes_per_sec.filter(|rate| *rate > 0.0).map(|rate| Bucket::new(rate, limit.burst, now)),
+            violations: 0,
+        }
+    }
+
+    /// Returns the limits enforced.
+    pub fn limit(&self) -> InboundRateLimit {
+        self.limit
+    }
+
+    /// Replaces the limits, keeping the tokens the peer has left (up to the new burst) and
+    /// the violation count.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The new rates and response.
+    pub fn set_limit(&mut self, limit: InboundRateLimit) {
+        let now = Instant::now();
+        let carry = |old: Option<Bucket>, rate: Option<f64>| {
+            rate.filter(|rate| *rate > 0.0).map(|rate| {
+                let mut bucket = Bucket::new(rate, limit.burst, now);
+                if let Some(old) = old {
+                    bucket.tokens = old.tokens.min(bucket.capacity);
+                    bucket.updated = old.updated;
+                }
+                bucket
+            })
+        };
+        self.messages = carry(self.messages.take(), limit.messages_per_sec);
+        self.bytes = carry(self.bytes.take(), limit.bytes_per_sec);
+        self.limit = limit;
+    }
+
+    /// Applies the latest limits published on `updates`, if they changed since the last call.
+    /// `None` lifts both rates.
+    ///
+    /// # Arguments
+    ///
+    /// * `updates` - The receiving half of the channel the limits are published on.
+    ///
+    /// # Returns
+    ///
+    /// Whether the limits changed.
+    pub fn follow(&mut self, updates: &mut watch::Receiver<Option<InboundRateLimit>>) -> bool {
+        if !updates.has_changed().unwrap_or(false) {
+            return false;
+        }
+        let limit = *updates.borrow_and_update();
+        let unlimited = InboundRateLimit { messages_per_sec: None, bytes_per_sec: None, ..self.limit };
+        self.set_limit(limit.unwrap_or(unlimited));
+        true
+    }
+
+    /// Returns the number of messages that exceeded the rates.
+    pub fn violations(&self) -> u64 {
+        self.violations
+    }
+
+    /// Accounts for a received message of `bytes` bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The message's payload size.
+    ///
+    /// # Returns
+    ///
+    /// The `RateDecision` for the message.
+    pub fn check(&mut self, bytes: usize) -> RateDecision {
+        self.check_at(bytes, Instant::now())
+    }
+
+    /// Like `check`, for a message received at `now`.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The message's payload size.
+    /// * `now` - When the message was received.
+    ///
+    /// # Returns
+    ///
+    /// The `RateDecision` for the message.
+    pub fn check_at(&mut self, bytes: usize, now: Instant) -> RateDecision {
+        let saved = (self.messages.clone(), self.bytes.clone());
+        let waits = [
+            self.messages.as_mut().and_then(|bucket| bucket.take(1.0, now)),
+            self.bytes.as_mut().and_then(|bucket| bucket.take(bytes as f64, now)),
+        ];
+        let wait = match waits.into_iter().flatten().max() {
+            Some(wait) => wait,
+            None => return RateDecision::Allow,
+        };
+        self.violations += 1;
+        match self.limit.action {
+            // The delayed peer pays off its debt by waiting.
+            RateLimitAction::Delay => RateDecision::Delay(wait),
+            action => {
+                // Rejected messages are not charged, so the buckets keep measuring the rate.
+                (self.messages, self.bytes) = saved;
+                if action == RateLimitAction::Warn {
+                    RateDecision::Warn
+                } else {
+                    RateDecision::Close
+                }
+            }
+        }
+    }
+}
+
+/// Receives the next message from `ws_stream`, enforcing `limiter`'s rates.
+///
+/// Every message but the closing one counts against the rates, control frames included.
+///
+/// # Arguments
+///
+/// * `ws_stream` - The connection to read from.
+/// * `limiter` - The connection's limiter.
+///
+/// # Returns
+///
+/// A `Result` containing the next message, or `None` once the connection has ended, or an
+/// error message if receiving failed or the connection was closed for exceeding its rate.
+///
+/// # Examples
+///
+/// ```rust
+/// use futures_util::SinkExt;
+/// use tokio_tungstenite::tungstenite::Message;
+/// use websocket_toolkit::ratelimit::{recv_limited, InboundRateLimit, InboundRateLimiter, RateLimitAction};
+/// use websocket_toolkit::testing::memory_pair;
+///
+/// let runtime = tokio::runtime::Runtime::new().unwrap();
+/// runtime.block_on(async {
+///     let (mut client, mut server) = memory_pair(None).await;
+///     let mut limiter = InboundRateLimiter::new(InboundRateLimit {
+///         messages_per_sec: Some(1.0),
+///         action: RateLimitAction::Close,
+///         ..InboundRateLimit::default()
+///     });
+///     client.send(Message::Text("one".into())).await.unwrap();
+///     client.send(Message::Text("two".into())).await.unwrap();
+///     assert_eq!(recv_limited(&mut server, &mut limiter).await.unwrap(), Some(Message::Text("one".into())));
+///     assert!(recv_limited(&mut server, &mut limiter).await.is_err());
+/// });
+/// ```
+pub async fn recv_limited<S>(
+    ws_stream: &mut WebSocketStream<S>,
+    limiter: &mut InboundRateLimiter,
+) -> Result<Option<Message>, String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let message = match ws_stream.next().await {
+        Some(Ok(message)) => message,
+        Some(Err(e)) => return Err(format!("Failed to receive message: {}", e)),
+        None => return Ok(None),
+    };
+    if message.is_close() {
+        return Ok(Some(message));
+    }
+    match limiter.check(message.len()) {
+        RateDecision::Allow => {}
+        RateDecision::Delay(wait) => {
+            debug!("Peer exceeded its inbound rate; pausing reads for {:?}", wait);
+            tokio::time::sleep(wait).await;
+        }
+        RateDecision::Warn => warn!("Peer exceeded its inbound rate ({} violations)", limiter.violations()),
+        RateDecision::Close => {
+            warn!("Peer exceeded its inbound rate; closing the connection");
+            let frame = CloseFrame {
+                code: CloseCode::Policy,
+                reason: Cow::Borrowed("Rate limit exceeded"),
+            };
+            if let Err(e) = ws_stream.close(Some(frame)).await {
+                debug!("Failed to send close frame: {}", e);
+            }
+            return Err("Closed connection: inbound rate limit exceeded".to_string());
+        }
+    }
+    Ok(Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::memory_pair;
+
+    /// Tests that followed updates change the rates without refilling the buckets.
+    #[test]
+    fn test_follow_updates() {
+        let limit = InboundRateLimit { messages_per_sec: Some(2.0), bytes_per_sec: None, ..InboundRateLimit::default() };
+        let (updates, mut receiver) = watch::channel(Some(limit));
+        let mut limiter = InboundRateLimiter::new(limit);
+        assert!(!limiter.follow(&mut receiver));
+        let now = Instant::now();
+        assert_eq!(limiter.check_at(1, now), RateDecision::Allow);
+        assert_eq!(limiter.check_at(1, now), RateDecision::Allow);
+
+        updates.send_replace(Some(InboundRateLimit { messages_per_sec: Some(10.0), ..limit }));
+        assert!(limiter.follow(&mut receiver));
+        assert_eq!(limiter.limit().messages_per_sec, Some(10.0));
+        assert!(matches!(limiter.check_at(1, now), RateDecision::Delay(_)), "Expected the used tokens to carry over");
+
+        updates.send_replace(None);
+        assert!(limiter.follow(&mut receiver));
+        assert_eq!(limiter.check_at(1, now), RateDecision::Allow);
+        assert_eq!(limiter.violations(), 1);
+    }
+
+    /// Tests delays, refills and the byte rate.
+    #[test]
+    fn test_rate_decisions() {
+        let start = Instant::now();
+        let mut limiter = InboundRateLimiter::new(InboundRateLimit {
+            messages_per_sec: Some(2.0),
+            bytes_per_sec: Some(1000.0),
+            burst: Duration::from_secs(1),
+            action: RateLimitAction::Delay,
+        });
+        assert_eq!(limiter.check_at(10, start), RateDecision::Allow);
+        assert_eq!(limiter.check_at(10, start), RateDecision::Allow);
+        assert_eq!(limiter.check_at(10, start), RateDecision::Delay(Duration::from_millis(500)));
+        // The delayed message is charged; after the delay and another half second, one fits.
+        assert_eq!(limiter.check_at(10, start + Duration::from_secs(1)), RateDecision::Allow);
+        assert_eq!(limiter.check_at(1500, start + Duration::from_secs(2)), RateDecision::Delay(Duration::from_millis(500)));
+        assert_eq!(limiter.violations(), 2);
+
+        let mut unlimited = InboundRateLimiter::new(InboundRateLimit {
+            messages_per_sec: None,
+            bytes_per_sec: None,
+            ..InboundRateLimit::default()
+        });
+        assert!((0..1000).all(|_| unlimited.check_at(1 << 20, start) == RateDecision::Allow));
+    }
+
+    /// Tests that warnings don't charge the buckets, so the peer recovers at its rate.
+    #[test]
+    fn test_warn_does_not_charge() {
+        let start = Instant::now();
+        let mut limiter = InboundRateLimiter::new(InboundRateLimit {
+            messages_per_sec: Some(1.0),
+            bytes_per_sec: None,
+            action: RateLimitAction::Warn,
+            ..InboundRateLimit::default()
+        });
+        assert_eq!(limiter.check_at(1, start), RateDecision::Allow);
+        for _ in 0..10 {
+            assert_eq!(limiter.check_at(1, start), RateDecision::Warn);
+        }
+        assert_eq!(limiter.check_at(1, start + Duration::from_secs(1)), RateDecision::Allow);
+    }
+
+    /// Tests that a flooding peer is closed with 1008.
+    #[tokio::test]
+    async fn test_close_on_flood() {
+        let (mut client, mut server) = memory_pair(None).await;
+        let mut limiter = InboundRateLimiter::new(InboundRateLimit {
+            messages_per_sec: Some(5.0),
+            action: RateLimitAction::Close,
+            ..InboundRateLimit::default()
+        });
+        for i in 0..10u8 {
+            client.send(Message::Binary(vec![i])).await.unwrap();
+        }
+        let mut received = Vec::new();
+        let error = loop {
+            match recv_limited(&mut server, &mut limiter).await {
+                Ok(Some(message)) => received.push(message),
+                Ok(None) => panic!("Connection ended without a close"),
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(received.len(), 5);
+        assert!(error.contains("rate limit exceeded"));
+        match client.next().await {
+            Some(Ok(Message::Close(Some(frame)))) => assert_eq!(frame.code, CloseCode::Policy),
+            other => panic!("Expected a 1008 close, got {:?}", other),
+        }
+    }
+}
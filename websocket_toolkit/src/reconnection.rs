@@ -1,10 +1,24 @@
 #![allow(unused_imports)]
 use crate::connection::WebSocketClient;
 use log::{warn, error, info};
-use tokio::time::{sleep, Duration};
+#[cfg(target_arch = "wasm32")]
+use gloo_timers::future::sleep;
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::runtime::Runtime;
 use tokio_tungstenite::tungstenite::Error;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use async_trait::async_trait;
+use crate::jitter::{unit_random, SeededRandom};
+use futures_util::future::{select, Either};
+use futures_util::pin_mut;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Waits on the default runtime's timer, so no tokio timer is needed without the `tokio` feature.
+#[cfg(not(target_arch = "wasm32"))]
+fn sleep(duration: Duration) -> futures::future::BoxFuture<'static, ()> {
+    crate::runtime::DefaultRuntime::sleep(duration)
+}
 
 /// A trait that defines the connection behavior for WebSocket clients.
 ///
@@ -36,31 +50,222 @@ impl Connectable for WebSocketClient {
     async fn connect(&self) -> Result<(), Error> {
         let client = self.clone(); // Clone the client for async tasks
 
-        tokio::spawn(async move {
+        let task = async move {
             match client.connect().await {
                 Ok(_) => info!("Successfully connected"),
                 Err(e) => error!("Failed to connect: {}", e),
             }
-        });
+        };
+        #[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+        crate::tasks::spawn_named("websocket_toolkit::connect", task);
+        #[cfg(all(not(feature = "tokio"), not(target_arch = "wasm32")))]
+        crate::runtime::DefaultRuntime::spawn(task);
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(task);
 
         Ok(())
     }
 }
 
+/// A trait that decides how long to wait between reconnection attempts.
+///
+/// Implement it to customize retry timing, or use one of the built-in strategies:
+/// `Linear`, `Exponential`, `Fibonacci`, `DecorrelatedJitter` and `Constant`. Closures of the
+/// form `Fn(u32, Duration) -> Duration` implement it too.
+pub trait BackoffStrategy: Send + Sync {
+    /// Returns the delay before the next attempt.
+    ///
+    /// # Arguments
+    ///
+    /// * `attempt` - The number of failed attempts so far, starting at 1.
+    /// * `previous` - The delay returned for the previous attempt, or zero for the first.
+    ///
+    /// # Returns
+    ///
+    /// How long to wait before attempting again.
+    fn delay(&self, attempt: u32, previous: Duration) -> Duration;
+}
+
+impl<F> BackoffStrategy for F
+where
+    F: Fn(u32, Duration) -> Duration + Send + Sync,
+{
+    fn delay(&self, attempt: u32, previous: Duration) -> Duration {
+        self(attempt, previous)
+    }
+}
+
+/// Waits `base * attempt`: the strategy used by `ReconnectStrategy::new`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Linear {
+    /// The delay after the first failed attempt.
+    pub base: Duration,
+}
+
+impl BackoffStrategy for Linear {
+    fn delay(&self, attempt: u32, _previous: Duration) -> Duration {
+        self.base.saturating_mul(attempt)
+    }
+}
+
+/// Doubles the delay after every failed attempt, from `base` up to `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Exponential {
+    /// The delay after the first failed attempt.
+    pub base: Duration,
+    /// The longest delay.
+    pub max: Duration,
+}
+
+impl BackoffStrategy for Exponential {
+    fn delay(&self, attempt: u32, _previous: Duration) -> Duration {
+        self.base.saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1))).min(self.max)
+    }
+}
+
+/// Grows the delay along the Fibonacci sequence (`base`, `base`, `2 * base`, `3 * base`,
+/// `5 * base`, ...) up to `max`, more gently than `Exponential`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fibonacci {
+    /// The delay after the first and second failed attempts.
+    pub base: Duration,
+    /// The longest delay.
+    pub max: Duration,
+}
+
+impl BackoffStrategy for Fibonacci {
+    fn delay(&self, attempt: u32, _previous: Duration) -> Duration {
+        let (mut current, mut next) = (1_u32, 1_u32);
+        for _ in 1..attempt {
+            (current, next) = (next, current.saturating_add(next));
+        }
+        self.base.saturating_mul(current).min(self.max)
+    }
+}
+
+/// Picks a random delay between `base` and three times the previous one, up to `max`.
+///
+/// This is the "decorrelated jitter" strategy: clients that failed together spread out
+/// instead of retrying in lockstep, while the delay still tends to grow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecorrelatedJitter {
+    /// The shortest delay.
+    pub base: Duration,
+    /// The longest delay.
+    pub max: Duration,
+}
+
+impl BackoffStrategy for DecorrelatedJitter {
+    fn delay(&self, _attempt: u32, previous: Duration) -> Duration {
+        let upper = previous.max(self.base).saturating_mul(3);
+        let spread = upper.saturating_sub(self.base);
+        (self.base + spread.mul_f64(unit_random())).min(self.max)
+    }
+}
+
+/// Always waits the same time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Constant(pub Duration);
+
+impl BackoffStrategy for Constant {
+    fn delay(&self, _attempt: u32, _previous: Duration) -> Duration {
+        self.0
+    }
+}
+
+/// How the delays of a `ReconnectStrategy` are randomized.
+///
+/// Clients that lose their connection together, e.g. when a gateway restarts, would otherwise
+/// all retry at the same moments and knock it over again. With `d` the delay computed by the
+/// backoff strategy:
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Jitter {
+    /// Wait exactly `d`.
+    #[default]
+    None,
+    /// Wait a random time between zero and `d`; spreads retries the most.
+    Full,
+    /// Wait `d / 2` plus a random time up to `d / 2`, so the backoff still grows.
+    Equal,
+    /// Wait a random time between the first delay and three times the previous one, but no
+    /// longer than `d`.
+    Decorrelated,
+}
+
 /// A struct that defines a strategy for reconnecting to a WebSocket server with retries and backoff.
 ///
 /// This struct encapsulates the reconnection logic, allowing a WebSocket client to retry
-/// connections with exponential backoff up to a maximum number of attempts.
+/// connections with a `BackoffStrategy` between attempts up to a maximum number of attempts.
 ///
 /// # Fields
 ///
 /// * `retries` - The maximum number of reconnection attempts.
-/// * `base_delay` - The base delay (in seconds) between reconnection attempts.
+/// * `backoff` - How long to wait between reconnection attempts.
+/// * `jitter` - How the delays are randomized.
+/// * `seeded` - The random sequence used instead of fresh randomness, for tests.
+/// * `max_duration` - The wall-clock budget of all attempts and delays together.
+/// * `attempt_timeout` - How long a single attempt may take.
 pub struct ReconnectStrategy {
     retries: u32,
-    base_delay: Duration,
+    backoff: Box<dyn BackoffStrategy>,
+    jitter: Jitter,
+    seeded: Option<Mutex<SeededRandom>>,
+    max_duration: Option<Duration>,
+    attempt_timeout: Option<Duration>,
+}
+
+/// Why `ReconnectStrategy::try_reconnect` gave up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReconnectError {
+    /// Every allowed attempt failed.
+    RetriesExhausted {
+        /// The number of attempts made.
+        attempts: u32,
+    },
+    /// The total time budget ran out before an attempt succeeded.
+    DeadlineExceeded {
+        /// The number of attempts started.
+        attempts: u32,
+        /// The budget that ran out.
+        max_duration: Duration,
+    },
+}
+
+impl std::fmt::Display for ReconnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReconnectError::RetriesExhausted { attempts } => {
+                write!(f, "Failed to reconnect: all {} attempts failed", attempts)
+            }
+            ReconnectError::DeadlineExceeded { attempts, max_duration } => {
+                write!(f, "Failed to reconnect within {:?} ({} attempts)", max_duration, attempts)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReconnectError {}
+
+/// Why `ReconnectStrategy::retry` gave up.
+#[derive(Debug)]
+pub enum RetryError<E> {
+    /// An attempt failed with an error that is not retried, such as rejected credentials.
+    Fatal(E),
+    /// The retries or the time budget ran out.
+    GaveUp(ReconnectError),
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for RetryError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RetryError::Fatal(e) => write!(f, "Failed to reconnect: {}", e),
+            RetryError::GaveUp(e) => e.fmt(f),
+        }
+    }
 }
 
+impl<E: std::fmt::Debug + std::fmt::Display> std::error::Error for RetryError<E> {}
+
 impl ReconnectStrategy {
     /// Creates a new `ReconnectStrategy` with the specified number of retries and base delay.
     ///
@@ -96,12 +301,143 @@ impl ReconnectStrategy {
     /// assert!(result.is_some(), "Expected successful reconnection");
     /// ```
     pub fn new(retries: u32, base_delay_secs: u64) -> Self {
+        Self::new_with_backoff(retries, Linear { base: Duration::from_secs(base_delay_secs) })
+    }
+
+    /// Creates a new `ReconnectStrategy` that waits between attempts as `backoff` decides.
+    ///
+    /// # Arguments
+    ///
+    /// * `retries` - The maximum number of reconnection attempts.
+    /// * `backoff` - The strategy computing each delay.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `ReconnectStrategy`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use websocket_toolkit::reconnection::{DecorrelatedJitter, Exponential, ReconnectStrategy};
+    ///
+    /// let strategy = ReconnectStrategy::new_with_backoff(
+    ///     5,
+    ///     Exponential { base: Duration::from_millis(500), max: Duration::from_secs(30) },
+    /// );
+    /// assert_eq!(strategy.delay(3, Duration::ZERO), Duration::from_secs(2));
+    ///
+    /// let jittered = ReconnectStrategy::new_with_backoff(
+    ///     5,
+    ///     DecorrelatedJitter { base: Duration::from_millis(500), max: Duration::from_secs(30) },
+    /// );
+    /// // Custom timing needs no new type.
+    /// let custom = ReconnectStrategy::new_with_backoff(5, |attempt: u32, _previous: Duration| {
+    ///     Duration::from_millis(100 * u64::from(attempt * attempt))
+    /// });
+    /// # let _ = (jittered, custom);
+    /// ```
+    pub fn new_with_backoff(retries: u32, backoff: impl BackoffStrategy + 'static) -> Self {
         ReconnectStrategy {
             retries,
-            base_delay: Duration::from_secs(base_delay_secs),
+            backoff: Box::new(backoff),
+            jitter: Jitter::None,
+            seeded: None,
+            max_duration: None,
+            attempt_timeout: None,
         }
     }
 
+    /// Randomizes the computed delays with `jitter`.
+    ///
+    /// # Arguments
+    ///
+    /// * `jitter` - How the delays are randomized.
+    ///
+    /// # Returns
+    ///
+    /// The updated `ReconnectStrategy`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use websocket_toolkit::reconnection::{Exponential, Jitter, ReconnectStrategy};
+    ///
+    /// let strategy = ReconnectStrategy::new_with_backoff(
+    ///     5,
+    ///     Exponential { base: Duration::from_secs(1), max: Duration::from_secs(30) },
+    /// )
+    /// .with_jitter(Jitter::Equal)
+    /// .with_seed(42);
+    /// let delay = strategy.delay(3, Duration::ZERO);
+    /// assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_secs(4));
+    /// ```
+    pub fn with_jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Draws the jitter from a deterministic sequence, so tests see the same delays every run.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The seed of the sequence.
+    ///
+    /// # Returns
+    ///
+    /// The updated `ReconnectStrategy`.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seeded = Some(Mutex::new(SeededRandom::new(seed)));
+        self
+    }
+
+    /// Gives up once `max_duration` has passed since reconnection started, whatever retries
+    /// are left; an attempt or delay in progress is cut short.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_duration` - The budget of all attempts and delays together.
+    ///
+    /// # Returns
+    ///
+    /// The updated `ReconnectStrategy`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use websocket_toolkit::reconnection::{Exponential, ReconnectStrategy};
+    ///
+    /// // Up to 100 attempts, but never longer than 5 minutes, 10 seconds each.
+    /// let strategy = ReconnectStrategy::new_with_backoff(
+    ///     100,
+    ///     Exponential { base: Duration::from_secs(1), max: Duration::from_secs(30) },
+    /// )
+    /// .with_max_duration(Duration::from_secs(300))
+    /// .with_attempt_timeout(Duration::from_secs(10));
+    /// # let _ = strategy;
+    /// ```
+    pub fn with_max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = Some(max_duration);
+        self
+    }
+
+    /// Fails an attempt that has not connected within `timeout`, so one hanging handshake
+    /// does not use up the budget; the attempt counts as failed and the next one follows.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - How long a single attempt may take.
+    ///
+    /// # Returns
+    ///
+    /// The updated `ReconnectStrategy`.
+    pub fn with_attempt_timeout(mut self, timeout: Duration) -> Self {
+        self.attempt_timeout = Some(timeout);
+        self
+    }
+
     /// Retrieves the number of retries for the strategy.
     ///
     /// # Returns
@@ -111,7 +447,36 @@ impl ReconnectStrategy {
         self.retries
     }
 
-    /// Attempts to reconnect with exponential backoff up to the maximum retries.
+    /// Returns the delay before the next attempt, as decided by the backoff strategy and
+    /// randomized by the jitter.
+    ///
+    /// # Arguments
+    ///
+    /// * `attempt` - The number of failed attempts so far, starting at 1.
+    /// * `previous` - The delay before the previous attempt, or zero for the first.
+    pub fn delay(&self, attempt: u32, previous: Duration) -> Duration {
+        let delay = self.backoff.delay(attempt, previous);
+        match self.jitter {
+            Jitter::None => delay,
+            Jitter::Full => delay.mul_f64(self.unit_random()),
+            Jitter::Equal => delay / 2 + (delay / 2).mul_f64(self.unit_random()),
+            Jitter::Decorrelated => {
+                let first = self.backoff.delay(1, Duration::ZERO).min(delay);
+                let upper = previous.max(first).saturating_mul(3).min(delay);
+                first + upper.saturating_sub(first).mul_f64(self.unit_random())
+            }
+        }
+    }
+
+    /// Returns a random number in `[0, 1]`, from the seeded sequence if there is one.
+    fn unit_random(&self) -> f64 {
+        match &self.seeded {
+            Some(seeded) => seeded.lock().unwrap().next_unit(),
+            None => unit_random(),
+        }
+    }
+
+    /// Attempts to reconnect with backoff up to the maximum retries.
     ///
     /// # Arguments
     ///
@@ -122,24 +487,471 @@ impl ReconnectStrategy {
     /// * `Some(())` - If reconnection was successful.
     /// * `None` - If all attempts failed.
     pub async fn reconnect(&self, client: Arc<dyn Connectable>) -> Option<()> {
+        self.try_reconnect(client).await.ok()
+    }
+
+    /// Like `reconnect`, but reports why reconnection gave up.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The client wrapped in an `Arc` to handle reconnection.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if reconnection was successful, or a `ReconnectError` telling whether the
+    /// retries or the time budget ran out.
+    pub async fn try_reconnect(&self, client: Arc<dyn Connectable>) -> Result<(), ReconnectError> {
+        self.reconnect_with_sleep(client, sleep).await
+    }
+
+    /// Runs the reconnection loop with `connect` making each attempt, so callers that get a
+    /// connection back (a stream or a handle) can use the strategy's retries, backoff, time
+    /// budget and attempt timeout.
+    ///
+    /// # Arguments
+    ///
+    /// * `connect` - Makes one attempt.
+    /// * `is_fatal` - Returns whether an attempt's error ends reconnection at once.
+    ///
+    /// # Returns
+    ///
+    /// The first successful attempt's value, or a `RetryError` with the fatal error or the
+    /// reason reconnection gave up.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use websocket_toolkit::reconnection::{Constant, ReconnectStrategy};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let strategy = ReconnectStrategy::new_with_backoff(3, Constant(Duration::from_millis(1)));
+    /// let mut attempts = 0;
+    /// let connected = strategy
+    ///     .retry(
+    ///         || {
+    ///             attempts += 1;
+    ///             let attempt = attempts;
+    ///             async move { if attempt < 3 { Err("refused") } else { Ok(attempt) } }
+    ///         },
+    ///         |_| false,
+    ///     )
+    ///     .await;
+    /// assert_eq!(connected.unwrap(), 3);
+    /// # }
+    /// ```
+    pub async fn retry<T, E, F, Fut>(&self, mut connect: F, is_fatal: impl Fn(&E) -> bool) -> Result<T, RetryError<E>>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T, E>>,
+        E: std::fmt::Display,
+    {
+        self.retry_with_sleep(&mut connect, &is_fatal, &sleep, &mut |_, delay| sleep(delay), false).await
+    }
+
+    /// Runs the reconnection loop after an attempt made outside it failed: `wait` is called
+    /// before each of the retries with the number of the attempt about to be made and the
+    /// strategy's delay, and should wait that long, so the caller can announce or cut short
+    /// every wait. The time budget and the attempt timeout apply as in `retry`.
+    pub(crate) async fn retry_after_failure<T, E, C, CFut, P, W, WFut>(
+        &self,
+        mut connect: C,
+        is_fatal: P,
+        mut wait: W,
+    ) -> Result<T, RetryError<E>>
+    where
+        C: FnMut() -> CFut,
+        CFut: std::future::Future<Output = Result<T, E>>,
+        E: std::fmt::Display,
+        P: Fn(&E) -> bool,
+        W: FnMut(u32, Duration) -> WFut,
+        WFut: std::future::Future<Output = ()>,
+    {
+        self.retry_with_sleep(&mut connect, &is_fatal, &sleep, &mut wait, true).await
+    }
+
+    /// Attempts to reconnect with backoff, waiting between attempts on runtime `R`.
+    ///
+    /// This lets callers on async-std or smol use the reconnection logic without a tokio timer.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The client wrapped in an `Arc` to handle reconnection.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(())` - If reconnection was successful.
+    /// * `None` - If all attempts failed.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn reconnect_on<R: Runtime>(&self, client: Arc<dyn Connectable>) -> Option<()> {
+        self.reconnect_with_sleep(client, R::sleep).await.ok()
+    }
+
+    /// Runs the reconnection loop of a `Connectable`, using `sleep` to wait.
+    async fn reconnect_with_sleep<F, Fut>(&self, client: Arc<dyn Connectable>, sleep: F) -> Result<(), ReconnectError>
+    where
+        F: Fn(Duration) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+    {
+        let client = client.as_ref();
+        let wait = &mut |_, delay| sleep(delay);
+        match self.retry_with_sleep(&mut || client.connect(), &|_: &Error| false, &sleep, wait, false).await {
+            Ok(()) => Ok(()),
+            Err(RetryError::GaveUp(e)) => Err(e),
+            Err(RetryError::Fatal(_)) => unreachable!("no connection error is fatal"),
+        }
+    }
+
+    /// Runs the reconnection loop within the time budget, using `sleep` to time the budget
+    /// and attempts and `wait` to wait between attempts; with `after_failure`, also before
+    /// the first.
+    async fn retry_with_sleep<T, E, C, CFut, P, F, Fut, W, WFut>(
+        &self,
+        connect: &mut C,
+        is_fatal: &P,
+        sleep: &F,
+        wait: &mut W,
+        after_failure: bool,
+    ) -> Result<T, RetryError<E>>
+    where
+        C: FnMut() -> CFut,
+        CFut: std::future::Future<Output = Result<T, E>>,
+        E: std::fmt::Display,
+        P: Fn(&E) -> bool,
+        F: Fn(Duration) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+        W: FnMut(u32, Duration) -> WFut,
+        WFut: std::future::Future<Output = ()>,
+    {
+        let attempts = AtomicU32::new(0);
+        let attempts_loop = self.attempt_loop(connect, is_fatal, sleep, wait, after_failure, &attempts);
+        let Some(max_duration) = self.max_duration else {
+            return attempts_loop.await;
+        };
+        let deadline = sleep(max_duration);
+        pin_mut!(attempts_loop, deadline);
+        match select(attempts_loop, deadline).await {
+            Either::Left((result, _)) => result,
+            Either::Right(((), _)) => {
+                error!("Exceeded the reconnection budget of {:?}", max_duration);
+                let attempts = attempts.load(Ordering::Relaxed);
+                Err(RetryError::GaveUp(ReconnectError::DeadlineExceeded { attempts, max_duration }))
+            }
+        }
+    }
+
+    /// Attempts to connect up to the maximum retries, counting the attempts in `attempts`.
+    /// With `after_failure`, an earlier attempt counts as failed and is waited for too.
+    async fn attempt_loop<T, E, C, CFut, P, F, Fut, W, WFut>(
+        &self,
+        connect: &mut C,
+        is_fatal: &P,
+        sleep: &F,
+        wait: &mut W,
+        after_failure: bool,
+        attempts: &AtomicU32,
+    ) -> Result<T, RetryError<E>>
+    where
+        C: FnMut() -> CFut,
+        CFut: std::future::Future<Output = Result<T, E>>,
+        E: std::fmt::Display,
+        P: Fn(&E) -> bool,
+        F: Fn(Duration) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+        W: FnMut(u32, Duration) -> WFut,
+        WFut: std::future::Future<Output = ()>,
+    {
+        let mut delay = Duration::ZERO;
         for attempt in 1..=self.retries {
+            let failed = if after_failure { attempt } else { attempt - 1 };
+            if failed > 0 {
+                delay = self.delay(failed, delay);
+                warn!("Waiting for {:?} before next reconnection attempt", delay);
+                wait(attempt, delay).await;
+            }
             warn!("Reconnection attempt {} of {}", attempt, self.retries);
+            attempts.store(attempt, Ordering::Relaxed);
 
-            match client.connect().await {
-                Ok(()) => {
+            match self.attempt(connect(), sleep).await {
+                Ok(connected) => {
                     info!("Reconnected successfully on attempt {}", attempt);
-                    return Some(()); // Successful reconnection
+                    return Ok(connected); // Successful reconnection
+                }
+                Err(Some(e)) if is_fatal(&e) => {
+                    error!("Reconnection stopped: {}", e);
+                    return Err(RetryError::Fatal(e));
                 }
-                Err(e) => error!("Reconnection attempt {} failed: {}", attempt, e),
+                Err(Some(e)) => error!("Reconnection attempt {} failed: {}", attempt, e),
+                Err(None) => error!("Reconnection attempt {} timed out", attempt),
             }
-
-            let delay = self.base_delay * attempt;
-            warn!("Waiting for {:?} before next reconnection attempt", delay);
-            sleep(delay).await;
         }
 
         error!("Exceeded maximum reconnection attempts");
-        None
+        Err(RetryError::GaveUp(ReconnectError::RetriesExhausted { attempts: self.retries }))
+    }
+
+    /// Makes one attempt; `Err(None)` means it ran past the attempt timeout.
+    async fn attempt<T, E, CFut, F, Fut>(&self, connecting: CFut, sleep: &F) -> Result<T, Option<E>>
+    where
+        CFut: std::future::Future<Output = Result<T, E>>,
+        F: Fn(Duration) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+    {
+        let Some(timeout) = self.attempt_timeout else {
+            return connecting.await.map_err(Some);
+        };
+        let expired = sleep(timeout);
+        pin_mut!(connecting, expired);
+        match select(connecting, expired).await {
+            Either::Left((result, _)) => result.map_err(Some),
+            Either::Right(((), _)) => Err(None),
+        }
+    }
+}
+
+/// The state of the circuit for one URL.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Attempts are allowed; `failures` consecutive attempts have failed so far.
+    Closed {
+        /// The consecutive failures since the last success.
+        failures: u32,
+    },
+    /// Attempts are refused until the cooldown ends.
+    Open {
+        /// The time left until a probe is allowed.
+        retry_in: Duration,
+    },
+    /// The cooldown ended: the next attempt is let through as a probe, and further attempts
+    /// are refused until it succeeds or fails.
+    HalfOpen,
+}
+
+/// The error returned for an attempt refused by an open circuit.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircuitOpen {
+    /// The URL whose circuit is open.
+    pub url: String,
+    /// The time left until a probe is allowed, or zero while a probe is in flight.
+    pub retry_in: Duration,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl std::fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Circuit open for {}: retry in {:?}", self.url, self.retry_in)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl std::error::Error for CircuitOpen {}
+
+/// Returns whether `error` is a `CircuitOpen`, i.e. no attempt was made.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn is_circuit_open(error: &(dyn std::error::Error + 'static)) -> bool {
+    error.downcast_ref::<CircuitOpen>().is_some()
+}
+
+/// The failure count and cooldown of one URL.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+#[derive(Debug, Default)]
+struct Circuit {
+    failures: u32,
+    /// When the circuit opened, if it is open or half-open.
+    opened_at: Option<tokio::time::Instant>,
+    /// Whether the half-open probe is in flight.
+    probing: bool,
+}
+
+/// Stops connecting to endpoints that keep failing.
+///
+/// Every URL has its own circuit. After `failure_threshold` consecutive failed attempts the
+/// circuit opens and `acquire` refuses attempts with `CircuitOpen` for `cooldown`, sparing
+/// both the client and the struggling server. Once the cooldown ends the circuit is
+/// half-open: one probe attempt is let through, and its outcome closes the circuit or opens
+/// it for another cooldown. Requires the `tokio` feature.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use websocket_toolkit::reconnection::{CircuitBreaker, CircuitState};
+///
+/// let breaker = CircuitBreaker::new(2, Duration::from_secs(30));
+/// let url = "ws://example.com";
+/// for _ in 0..2 {
+///     breaker.acquire(url).unwrap().failed();
+/// }
+/// assert!(matches!(breaker.state(url), CircuitState::Open { .. }));
+/// assert!(breaker.acquire(url).is_err());
+/// ```
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    circuits: Mutex<std::collections::HashMap<String, Circuit>>,
+}
+
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+impl CircuitBreaker {
+    /// Creates a breaker with every circuit closed.
+    ///
+    /// # Arguments
+    ///
+    /// * `failure_threshold` - The consecutive failures that open a circuit; at least 1.
+    /// * `cooldown` - How long an open circuit refuses attempts.
+    ///
+    /// # Returns
+    ///
+    /// A new `CircuitBreaker`.
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            circuits: Mutex::new(Default::default()),
+        }
+    }
+
+    /// Returns the state of the circuit for `url`.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The endpoint.
+    pub fn state(&self, url: &str) -> CircuitState {
+        let circuits = self.circuits.lock().unwrap();
+        match circuits.get(url) {
+            Some(circuit) => self.state_of(circuit),
+            None => CircuitState::Closed { failures: 0 },
+        }
+    }
+
+    /// Asks to attempt a connection to `url`.
+    ///
+    /// Report the attempt's outcome through the returned `CircuitAttempt`. An attempt dropped
+    /// without an outcome, e.g. because its connect future timed out or was cancelled, counts
+    /// for nothing; if it was the half-open probe, the next attempt probes instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The endpoint.
+    ///
+    /// # Returns
+    ///
+    /// A `CircuitAttempt` if the attempt may go ahead, as a probe if the circuit is
+    /// half-open, or a `CircuitOpen` error if it must not.
+    pub fn acquire(&self, url: &str) -> Result<CircuitAttempt<'_>, CircuitOpen> {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(url.to_string()).or_default();
+        let attempt = |probe| CircuitAttempt { breaker: self, url: url.to_string(), probe, finished: false };
+        match self.state_of(circuit) {
+            CircuitState::Closed { .. } => Ok(attempt(false)),
+            CircuitState::HalfOpen if !circuit.probing => {
+                info!("Probing {} after the circuit cooldown", url);
+                circuit.probing = true;
+                Ok(attempt(true))
+            }
+            CircuitState::HalfOpen => Err(CircuitOpen { url: url.to_string(), retry_in: Duration::ZERO }),
+            CircuitState::Open { retry_in } => Err(CircuitOpen { url: url.to_string(), retry_in }),
+        }
+    }
+
+    /// Records a successful attempt, closing the circuit for `url`.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The endpoint.
+    pub fn record_success(&self, url: &str) {
+        if let Some(circuit) = self.circuits.lock().unwrap().remove(url) {
+            if circuit.opened_at.is_some() {
+                info!("Circuit for {} closed", url);
+            }
+        }
+    }
+
+    /// Records a failed attempt, opening the circuit for `url` at the threshold or after a
+    /// failed probe.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The endpoint.
+    pub fn record_failure(&self, url: &str) {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(url.to_string()).or_default();
+        circuit.failures = circuit.failures.saturating_add(1);
+        if circuit.probing || (circuit.opened_at.is_none() && circuit.failures >= self.failure_threshold) {
+            warn!("Circuit for {} open for {:?} after {} failures", url, self.cooldown, circuit.failures);
+            circuit.opened_at = Some(tokio::time::Instant::now());
+            circuit.probing = false;
+        }
+    }
+
+    /// Ends an abandoned probe, so the next attempt on `url` probes instead.
+    fn abandon_probe(&self, url: &str) {
+        if let Some(circuit) = self.circuits.lock().unwrap().get_mut(url) {
+            if circuit.probing {
+                info!("Probe of {} abandoned", url);
+                circuit.probing = false;
+            }
+        }
+    }
+
+    fn state_of(&self, circuit: &Circuit) -> CircuitState {
+        match circuit.opened_at {
+            None => CircuitState::Closed { failures: circuit.failures },
+            Some(opened_at) => match self.cooldown.checked_sub(opened_at.elapsed()) {
+                Some(retry_in) if !retry_in.is_zero() => CircuitState::Open { retry_in },
+                _ => CircuitState::HalfOpen,
+            },
+        }
+    }
+}
+
+/// A connection attempt let through by `CircuitBreaker::acquire`.
+///
+/// Report its outcome with `succeeded` or `failed`. Dropping it without one, e.g. when the
+/// connect future it guards times out or is cancelled, records nothing, but frees the
+/// half-open probe slot if it held it, so the circuit cannot stay stuck half-open.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+#[derive(Debug)]
+#[must_use = "report the attempt's outcome with `succeeded` or `failed`"]
+pub struct CircuitAttempt<'a> {
+    breaker: &'a CircuitBreaker,
+    url: String,
+    probe: bool,
+    finished: bool,
+}
+
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+impl CircuitAttempt<'_> {
+    /// Returns whether this attempt is the probe of a half-open circuit.
+    pub fn is_probe(&self) -> bool {
+        self.probe
+    }
+
+    /// Records that the attempt succeeded, closing the circuit.
+    pub fn succeeded(mut self) {
+        self.finished = true;
+        self.breaker.record_success(&self.url);
+    }
+
+    /// Records that the attempt failed, opening the circuit at the threshold or after a
+    /// failed probe.
+    pub fn failed(mut self) {
+        self.finished = true;
+        self.breaker.record_failure(&self.url);
+    }
+}
+
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+impl Drop for CircuitAttempt<'_> {
+    fn drop(&mut self) {
+        if self.probe && !self.finished {
+            self.breaker.abandon_probe(&self.url);
+        }
     }
 }
 
@@ -165,7 +977,103 @@ mod tests {
     async fn test_reconnect_strategy_creation() {
         let reconnect_strategy = ReconnectStrategy::new(3, 2);
         assert_eq!(reconnect_strategy.retries, 3);
-        assert_eq!(reconnect_strategy.base_delay, Duration::from_secs(2));
+        assert_eq!(reconnect_strategy.delay(1, Duration::ZERO), Duration::from_secs(2));
+        assert_eq!(reconnect_strategy.delay(3, Duration::from_secs(4)), Duration::from_secs(6));
+    }
+
+    /// Tests the delays of the built-in backoff strategies.
+    #[test]
+    fn test_backoff_strategies() {
+        let second = Duration::from_secs(1);
+        let max = Duration::from_secs(10);
+        let delays = |backoff: &dyn BackoffStrategy| -> Vec<u64> {
+            let mut previous = Duration::ZERO;
+            (1..=6)
+                .map(|attempt| {
+                    previous = backoff.delay(attempt, previous);
+                    previous.as_secs()
+                })
+                .collect()
+        };
+        assert_eq!(delays(&Exponential { base: second, max }), vec![1, 2, 4, 8, 10, 10]);
+        assert_eq!(delays(&Fibonacci { base: second, max }), vec![1, 1, 2, 3, 5, 8]);
+        assert_eq!(delays(&Constant(second)), vec![1; 6]);
+        assert_eq!(delays(&Linear { base: second }), vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(delays(&|attempt: u32, _: Duration| Duration::from_secs(u64::from(attempt % 2))), vec![1, 0, 1, 0, 1, 0]);
+
+        let jitter = DecorrelatedJitter { base: second, max };
+        let mut previous = Duration::ZERO;
+        for attempt in 1..=20 {
+            let delay = jitter.delay(attempt, previous);
+            assert!(delay >= second && delay <= max && delay <= previous.max(second) * 3);
+            previous = delay;
+        }
+    }
+
+    /// Tests that a circuit opens at the threshold, refuses attempts during the cooldown and
+    /// lets exactly one probe through afterwards.
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(10));
+        let url = "ws://example.com";
+        for failures in 0..3 {
+            assert_eq!(breaker.state(url), CircuitState::Closed { failures });
+            breaker.acquire(url).unwrap().failed();
+        }
+        let refused = breaker.acquire(url).unwrap_err();
+        assert_eq!(refused.retry_in, Duration::from_secs(10));
+        assert!(is_circuit_open(&refused));
+        assert_eq!(breaker.state("ws://other.example.com"), CircuitState::Closed { failures: 0 });
+
+        // A failed probe opens the circuit again.
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(breaker.state(url), CircuitState::HalfOpen);
+        let probe = breaker.acquire(url).unwrap();
+        assert!(probe.is_probe());
+        assert!(breaker.acquire(url).is_err(), "Expected a single probe");
+        probe.failed();
+        assert!(matches!(breaker.state(url), CircuitState::Open { .. }));
+
+        // An abandoned probe lets the next attempt probe.
+        tokio::time::advance(Duration::from_secs(10)).await;
+        drop(breaker.acquire(url).unwrap());
+        assert_eq!(breaker.state(url), CircuitState::HalfOpen);
+
+        // A successful probe closes it.
+        let probe = breaker.acquire(url).unwrap();
+        assert!(probe.is_probe());
+        probe.succeeded();
+        assert_eq!(breaker.state(url), CircuitState::Closed { failures: 0 });
+    }
+
+    /// Tests that every jitter mode stays within its bounds and seeded strategies repeat.
+    #[test]
+    fn test_jitter_modes() {
+        let backoff = Exponential { base: Duration::from_secs(1), max: Duration::from_secs(60) };
+        let delays = |jitter, seed| {
+            let strategy = ReconnectStrategy::new_with_backoff(10, backoff).with_jitter(jitter).with_seed(seed);
+            let mut previous = Duration::ZERO;
+            (1..=6)
+                .map(|attempt| {
+                    previous = strategy.delay(attempt, previous);
+                    (backoff.delay(attempt, Duration::ZERO), previous)
+                })
+                .collect::<Vec<_>>()
+        };
+        for (nominal, delay) in delays(Jitter::None, 1) {
+            assert_eq!(delay, nominal);
+        }
+        for (nominal, delay) in delays(Jitter::Full, 1) {
+            assert!(delay <= nominal);
+        }
+        for (nominal, delay) in delays(Jitter::Equal, 1) {
+            assert!(delay >= nominal / 2 && delay <= nominal);
+        }
+        for (nominal, delay) in delays(Jitter::Decorrelated, 1) {
+            assert!(delay >= Duration::from_secs(1) && delay <= nominal);
+        }
+        assert_eq!(delays(Jitter::Full, 7), delays(Jitter::Full, 7));
+        assert_ne!(delays(Jitter::Full, 7), delays(Jitter::Full, 8));
     }
 
     /// Tests the behavior of `ReconnectStrategy` with exponential backoff when all reconnection attempts fail.
@@ -197,4 +1105,51 @@ mod tests {
         let reconnection_result = reconnect_strategy.reconnect(client).await;
         assert!(reconnection_result.is_some(), "Expected successful reconnection");
     }
+
+    /// Tests that the time budget and the attempt timeout end reconnection with the
+    /// matching error.
+    #[tokio::test(start_paused = true)]
+    async fn test_reconnect_deadlines() {
+        struct HangingClient;
+
+        #[async_trait]
+        impl Connectable for HangingClient {
+            async fn connect(&self) -> Result<(), Error> {
+                std::future::pending().await
+            }
+        }
+
+        let second = Duration::from_secs(1);
+        let strategy = ReconnectStrategy::new_with_backoff(100, Constant(second)).with_max_duration(second * 11 / 2);
+        assert_eq!(
+            strategy.try_reconnect(Arc::new(MockWebSocketClient)).await,
+            Err(ReconnectError::DeadlineExceeded { attempts: 6, max_duration: second * 11 / 2 })
+        );
+
+        let strategy = ReconnectStrategy::new_with_backoff(3, Constant(second)).with_attempt_timeout(second * 2);
+        let started = tokio::time::Instant::now();
+        assert_eq!(
+            strategy.try_reconnect(Arc::new(HangingClient)).await,
+            Err(ReconnectError::RetriesExhausted { attempts: 3 })
+        );
+        assert_eq!(started.elapsed(), second * 8);
+
+        let strategy = strategy.with_max_duration(second * 4);
+        assert!(matches!(
+            strategy.try_reconnect(Arc::new(HangingClient)).await,
+            Err(ReconnectError::DeadlineExceeded { attempts: 2, .. })
+        ));
+    }
+
+    /// Tests that `reconnect_on` drives the backoff with the given runtime's timer.
+    #[tokio::test]
+    async fn test_reconnect_on_tokio_runtime() {
+        let reconnect_strategy = ReconnectStrategy::new(2, 0);
+        let client = Arc::new(MockWebSocketClient);
+
+        let reconnection_result = reconnect_strategy
+            .reconnect_on::<crate::runtime::TokioRuntime>(client)
+            .await;
+        assert!(reconnection_result.is_none(), "Expected all reconnection attempts to fail");
+    }
 }
@@ -0,0 +1,323 @@
+//! # `replay.rs`: Replay protection for command channels
+//!
+//! A signature proves who sent a command, not that it is being received for the first time:
+//! an attacker who captured a signed `unlock` envelope can send it again later. A
+//! `ReplayGuard` closes that gap. The sender stamps every envelope with a timestamp and a
+//! nonce (`ReplayGuard::stamp`), both covered by whatever signature the application applies,
+//! and the receiver accepts an envelope only if
+//!
+//! - its timestamp lies within the acceptance window around the local clock, widened by the
+//!   allowed clock skew, and
+//! - its nonce has not been seen within that window.
+//!
+//! Nonces are only remembered for as long as their timestamps are acceptable, so memory stays
+//! bounded by the message rate and the window. Timestamps issued by `stamp` are monotonic:
+//! they never repeat or go backwards, even if the sender's wall clock is stepped back. With
+//! `ReplayPolicy::require_monotonic`, the receiver also rejects any envelope not newer than
+//! the last one it accepted, for channels that must not be reordered either.
+
+use crate::messages::Envelope;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long envelopes are accepted and how far clocks may disagree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayPolicy {
+    /// How old an envelope may be when it arrives.
+    pub window: Duration,
+    /// How far the sender's clock may be ahead of or behind the local one.
+    pub max_skew: Duration,
+    /// Rejects envelopes not newer than the last accepted one.
+    pub require_monotonic: bool,
+    /// The most nonces remembered; beyond it, the oldest are forgotten and envelopes no newer
+    /// than them are rejected.
+    pub max_tracked: usize,
+}
+
+impl Default for ReplayPolicy {
+    /// Accepts envelopes up to 30 seconds old with 5 seconds of skew, in any order, and
+    /// remembers up to 100,000 nonces.
+    fn default() -> Self {
+        ReplayPolicy {
+            window: Duration::from_secs(30),
+            max_skew: Duration::from_secs(5),
+            require_monotonic: false,
+            max_tracked: 100_000,
+        }
+    }
+}
+
+/// Why an envelope was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The envelope has no timestamp or no nonce.
+    Unstamped,
+    /// The envelope is older than the window allows.
+    Stale {
+        /// How old the envelope is, in milliseconds.
+        age_ms: u64,
+    },
+    /// The envelope is further in the future than the skew allows.
+    Future {
+        /// How far ahead of the local clock the envelope is, in milliseconds.
+        ahead_ms: u64,
+    },
+    /// The nonce was already accepted.
+    Duplicate {
+        /// The repeated nonce.
+        nonce: u64,
+    },
+    /// The envelope is not newer than the last accepted one, or than the oldest forgotten
+    /// nonce.
+    OutOfOrder {
+        /// The envelope's timestamp.
+        timestamp: u64,
+        /// The timestamp it had to exceed.
+        last: u64,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Unstamped => write!(f, "envelope has no timestamp or nonce"),
+            ReplayError::Stale { age_ms } => write!(f, "envelope is {} ms old", age_ms),
+            ReplayError::Future { ahead_ms } => write!(f, "envelope is {} ms in the future", ahead_ms),
+            ReplayError::Duplicate { nonce } => write!(f, "nonce {} was already used", nonce),
+            ReplayError::OutOfOrder { timestamp, last } => {
+                write!(f, "timestamp {} is not after {}", timestamp, last)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// The nonces accepted within the window and the stamps issued.
+#[derive(Debug, Default)]
+struct State {
+    /// Accepted nonces, for lookup.
+    seen: HashSet<u64>,
+    /// Accepted `(timestamp, nonce)` pairs, oldest first, for expiry.
+    expiry: BTreeSet<(u64, u64)>,
+    /// Envelopes with a timestamp at or below this are rejected as out of order.
+    floor: Option<u64>,
+    /// The last timestamp issued by `stamp`.
+    last_issued: u64,
+    /// The number of nonces issued by `stamp`.
+    issued: u64,
+}
+
+/// Stamps outgoing envelopes and rejects replayed incoming ones.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::messages::Envelope;
+/// use websocket_toolkit::replay::{ReplayError, ReplayGuard, ReplayPolicy};
+///
+/// let sender = ReplayGuard::new(ReplayPolicy::default());
+/// let receiver = ReplayGuard::new(ReplayPolicy::default());
+///
+/// let command = sender.stamp(Envelope::new("unlock", Vec::new()));
+/// assert!(receiver.verify(&command).is_ok());
+/// assert!(matches!(receiver.verify(&command), Err(ReplayError::Duplicate { .. })));
+/// ```
+#[derive(Debug)]
+pub struct ReplayGuard {
+    policy: ReplayPolicy,
+    /// The random start of this guard's nonces, so restarts don't reuse them.
+    nonce_base: u64,
+    state: Mutex<State>,
+}
+
+impl ReplayGuard {
+    /// Creates a guard.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The acceptance window and skew tolerance.
+    ///
+    /// # Returns
+    ///
+    /// A new `ReplayGuard`.
+    pub fn new(policy: ReplayPolicy) -> Self {
+        ReplayGuard {
+            policy,
+            nonce_base: RandomState::new().build_hasher().finish(),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Returns the guard's policy.
+    pub fn policy(&self) -> ReplayPolicy {
+        self.policy
+    }
+
+    /// Sets a fresh nonce and a timestamp later than any this guard issued before.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - The envelope to send.
+    ///
+    /// # Returns
+    ///
+    /// The stamped envelope.
+    pub fn stamp(&self, envelope: Envelope) -> Envelope {
+        let mut state = self.state.lock().unwrap();
+        let timestamp = now_millis().max(state.last_issued + 1);
+        state.last_issued = timestamp;
+        let nonce = self.nonce_base.wrapping_add(state.issued);
+        state.issued += 1;
+        envelope.with_stamp(timestamp, nonce)
+    }
+
+    /// Accepts `envelope` if it is stamped, fresh and not seen before, and remembers its
+    /// nonce.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - The received envelope.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating acceptance, or why the envelope was rejected.
+    pub fn verify(&self, envelope: &Envelope) -> Result<(), ReplayError> {
+        self.verify_at(envelope, now_millis())
+    }
+
+    /// Like `verify`, against the given local time instead of the system clock.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - The received envelope.
+    /// * `now` - The local time in milliseconds since the Unix epoch.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating acceptance, or why the envelope was rejected.
+    pub fn verify_at(&self, envelope: &Envelope, now: u64) -> Result<(), ReplayError> {
+        let (timestamp, nonce) = match (envelope.timestamp, envelope.nonce) {
+            (Some(timestamp), Some(nonce)) => (timestamp, nonce),
+            _ => return Err(ReplayError::Unstamped),
+        };
+        let skew = millis(self.policy.max_skew);
+        let oldest = now.saturating_sub(millis(self.policy.window).saturating_add(skew));
+        if timestamp < oldest {
+            return Err(ReplayError::Stale { age_ms: now - timestamp });
+        }
+        if timestamp > now.saturating_add(skew) {
+            return Err(ReplayError::Future { ahead_ms: timestamp - now });
+        }
+
+        let mut state = self.state.lock().unwrap();
+        while let Some(&expired) = state.expiry.first().filter(|(expired, _)| *expired < oldest) {
+            state.expiry.remove(&expired);
+            state.seen.remove(&expired.1);
+        }
+        if let Some(last) = state.floor.filter(|last| timestamp <= *last) {
+            return Err(ReplayError::OutOfOrder { timestamp, last });
+        }
+        if state.seen.contains(&nonce) {
+            return Err(ReplayError::Duplicate { nonce });
+        }
+
+        state.seen.insert(nonce);
+        state.expiry.insert((timestamp, nonce));
+        if self.policy.require_monotonic {
+            state.floor = Some(timestamp);
+        }
+        while state.expiry.len() > self.policy.max_tracked {
+            if let Some((forgotten, forgotten_nonce)) = state.expiry.pop_first() {
+                state.seen.remove(&forgotten_nonce);
+                state.floor = Some(state.floor.map_or(forgotten, |floor| floor.max(forgotten)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the current time in milliseconds since the Unix epoch.
+fn now_millis() -> u64 {
+    millis(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default())
+}
+
+/// Returns `duration` in whole milliseconds, saturating.
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamped(timestamp: u64, nonce: u64) -> Envelope {
+        Envelope::new("command", Vec::new()).with_stamp(timestamp, nonce)
+    }
+
+    /// Tests the window, skew, duplicate and expiry rules.
+    #[test]
+    fn test_verify_window() {
+        let guard = ReplayGuard::new(ReplayPolicy {
+            window: Duration::from_secs(10),
+            max_skew: Duration::from_secs(1),
+            ..ReplayPolicy::default()
+        });
+        let now = 1_000_000;
+
+        assert_eq!(guard.verify_at(&Envelope::new("command", Vec::new()), now), Err(ReplayError::Unstamped));
+        assert_eq!(guard.verify_at(&stamped(now - 11_001, 1), now), Err(ReplayError::Stale { age_ms: 11_001 }));
+        assert_eq!(guard.verify_at(&stamped(now + 1_001, 1), now), Err(ReplayError::Future { ahead_ms: 1_001 }));
+
+        assert!(guard.verify_at(&stamped(now - 11_000, 1), now).is_ok());
+        assert!(guard.verify_at(&stamped(now + 1_000, 2), now).is_ok());
+        // Out of order is fine without `require_monotonic`.
+        assert!(guard.verify_at(&stamped(now, 3), now).is_ok());
+        assert_eq!(guard.verify_at(&stamped(now, 3), now), Err(ReplayError::Duplicate { nonce: 3 }));
+
+        // Nonce 1 expires with its timestamp; a replay of it is then stale instead.
+        assert!(guard.verify_at(&stamped(now + 5_000, 4), now + 5_000).is_ok());
+        assert!(!guard.state.lock().unwrap().seen.contains(&1));
+        assert!(matches!(guard.verify_at(&stamped(now - 11_000, 1), now + 5_000), Err(ReplayError::Stale { .. })));
+    }
+
+    /// Tests monotonic acceptance and the bound on remembered nonces.
+    #[test]
+    fn test_monotonic_and_bounded() {
+        let guard = ReplayGuard::new(ReplayPolicy {
+            require_monotonic: true,
+            ..ReplayPolicy::default()
+        });
+        let now = 1_000_000;
+        assert!(guard.verify_at(&stamped(now, 1), now).is_ok());
+        assert_eq!(
+            guard.verify_at(&stamped(now - 1, 2), now),
+            Err(ReplayError::OutOfOrder { timestamp: now - 1, last: now })
+        );
+
+        let bounded = ReplayGuard::new(ReplayPolicy { max_tracked: 2, ..ReplayPolicy::default() });
+        for nonce in 0..3 {
+            assert!(bounded.verify_at(&stamped(now + nonce, nonce), now).is_ok());
+        }
+        assert_eq!(bounded.state.lock().unwrap().seen.len(), 2);
+        assert!(matches!(bounded.verify_at(&stamped(now, 0), now), Err(ReplayError::OutOfOrder { .. })));
+        assert!(bounded.verify_at(&stamped(now + 1, 7), now).is_ok());
+    }
+
+    /// Tests that stamps are unique and strictly increasing.
+    #[test]
+    fn test_stamp_monotonic() {
+        let guard = ReplayGuard::new(ReplayPolicy::default());
+        let stamps: Vec<Envelope> = (0..100).map(|_| guard.stamp(Envelope::new("command", Vec::new()))).collect();
+        for pair in stamps.windows(2) {
+            assert!(pair[1].timestamp > pair[0].timestamp);
+            assert_ne!(pair[1].nonce, pair[0].nonce);
+        }
+        let receiver = ReplayGuard::new(ReplayPolicy { require_monotonic: true, ..ReplayPolicy::default() });
+        assert!(stamps.iter().all(|envelope| receiver.verify(envelope).is_ok()));
+    }
+}
@@ -0,0 +1,355 @@
+//! # `request.rs`: Request/response over a connection
+//!
+//! Many WebSocket APIs answer a request with a reply that carries the request's id, while
+//! unrelated pushes arrive on the same connection in between. `Requester` does the
+//! bookkeeping: `request` stamps each envelope with a fresh correlation id, sends it through
+//! the controller's codec, and waits for the envelope with the same id, failing with
+//! `RequestError::Timeout` if none arrives in time. Any number of requests may be in flight
+//! at once, from any number of tasks.
+//!
+//! A requester takes over the connection's inbound messages: its background reader takes
+//! every message off the connection, so nothing else may read from the handle. Replies are
+//! routed to their waiting request; everything else, including replies that arrive after
+//! their request timed out, is passed on to `recv_inbound`. No message is dropped: once
+//! `recv_inbound`'s buffer is full the reader waits for it to make room, which holds up
+//! replies too, so callers expecting unsolicited messages must keep reading them. Dropping
+//! the last clone of a requester stops the reader.
+
+use crate::controller::WebSocketController;
+use crate::handle::ConnectionHandle;
+use crate::jitter::random_u64;
+use crate::messages::{Envelope, InboundMessage};
+use crate::pipeline::PipelineConfig;
+use crate::tasks::spawn_named;
+use log::debug;
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+/// Why a request got no reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestError {
+    /// No reply arrived within the timeout.
+    Timeout {
+        /// The correlation id of the request.
+        id: String,
+        /// The timeout that elapsed.
+        timeout: Duration,
+    },
+    /// The connection closed before the reply arrived.
+    Closed,
+    /// The request could not be encoded or sent.
+    Send(String),
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::Timeout { id, timeout } => write!(f, "request {} timed out after {:?}", id, timeout),
+            RequestError::Closed => write!(f, "connection closed before the reply arrived"),
+            RequestError::Send(e) => write!(f, "failed to send request: {}", e),
+        }
+    }
+}
+
+impl StdError for RequestError {}
+
+/// Sends envelopes and awaits the replies that carry their correlation id.
+///
+/// Clones share the connection and the table of requests in flight.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use websocket_toolkit::controller::WebSocketController;
+/// use websocket_toolkit::messages::Envelope;
+/// use websocket_toolkit::pipeline::PipelineConfig;
+/// use websocket_toolkit::request::Requester;
+/// use websocket_toolkit::testing::EchoServer;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let server = EchoServer::start().await.unwrap();
+/// let controller = Arc::new(WebSocketController::new(server.url(), 0, None));
+/// let requester = Requester::connect(controller, PipelineConfig::default()).await.unwrap();
+///
+/// // The echo server replies with the request itself, id included.
+/// let reply = requester.request(Envelope::new("quote", b"ACME".to_vec()), Duration::from_secs(5)).await.unwrap();
+/// assert_eq!(reply.payload, b"ACME");
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Requester {
+    controller: Arc<WebSocketController>,
+    handle: ConnectionHandle,
+    shared: Arc<Shared>,
+}
+
+/// State shared with the reader task.
+struct Shared {
+    /// The requests awaiting a reply, by correlation id.
+    pending: Mutex<HashMap<String, oneshot::Sender<Envelope>>>,
+    /// Set, under the `pending` lock, once the connection has ended.
+    closed: AtomicBool,
+    /// Makes ids unique across requesters and processes.
+    id_prefix: u64,
+    next_id: AtomicU64,
+    unmatched: tokio::sync::Mutex<mpsc::Receiver<InboundMessage>>,
+    /// The reader task, aborted when the last requester is dropped.
+    reader: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        if let Some(reader) = self.reader.get_mut().unwrap().take() {
+            reader.abort();
+        }
+    }
+}
+
+impl Requester {
+    /// Opens a connection with `controller` and starts matching replies on it.
+    ///
+    /// # Arguments
+    ///
+    /// * `controller` - Connects, and encodes requests and decodes replies.
+    /// * `config` - The pipeline settings of the connection.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the requester, or a boxed error if the connection fails.
+    pub async fn connect(controller: Arc<WebSocketController>, config: PipelineConfig) -> Result<Self, Box<dyn StdError>> {
+        let handle = controller.connect_handle(config).await?;
+        Ok(Self::new(controller, handle, config.inbound_capacity))
+    }
+
+    /// Starts matching replies on an open connection. The requester takes every message
+    /// received on `handle` from now on, so nothing else may read from it; read the unmatched
+    /// ones with `recv_inbound`.
+    ///
+    /// Must be called within a tokio runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `controller` - Encodes requests and decodes replies.
+    /// * `handle` - The connection to send requests on.
+    /// * `capacity` - How many unmatched messages are buffered; beyond that, the reader
+    ///   waits for `recv_inbound` to catch up, and replies wait with it.
+    ///
+    /// # Returns
+    ///
+    /// A new `Requester`.
+    pub fn new(controller: Arc<WebSocketController>, handle: ConnectionHandle, capacity: usize) -> Self {
+        let (unmatched_tx, unmatched) = mpsc::channel(capacity.max(1));
+        let shared = Arc::new(Shared {
+            pending: Mutex::new(HashMap::new()),
+            closed: AtomicBool::new(false),
+            id_prefix: random_u64(),
+            next_id: AtomicU64::new(1),
+            unmatched: tokio::sync::Mutex::new(unmatched),
+            reader: Mutex::new(None),
+        });
+        let reader = spawn_named(
+            "websocket_toolkit::request_reader",
+            read_replies(controller.clone(), handle.clone(), Arc::downgrade(&shared), unmatched_tx),
+        );
+        *shared.reader.lock().unwrap() = Some(reader);
+        Requester { controller, handle, shared }
+    }
+
+    /// Sends `envelope` with a fresh correlation id and waits for the reply carrying it.
+    ///
+    /// Any id already set on `envelope` is replaced. Cancelling the returned future forgets
+    /// the request, so a late reply goes to `recv_inbound`.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - The request.
+    /// * `timeout` - How long to wait for the reply, including the time to queue the request.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the reply, or why none arrived.
+    pub async fn request(&self, envelope: Envelope, timeout: Duration) -> Result<Envelope, RequestError> {
+        let id = format!("{:016x}-{}", self.shared.id_prefix, self.shared.next_id.fetch_add(1, Ordering::Relaxed));
+        let (reply_tx, reply) = oneshot::channel();
+        {
+            let mut pending = self.shared.pending.lock().unwrap();
+            if self.shared.closed.load(Ordering::Relaxed) {
+                return Err(RequestError::Closed);
+            }
+            pending.insert(id.clone(), reply_tx);
+        }
+        let _pending = PendingGuard { shared: &self.shared, id: &id };
+
+        let exchange = async {
+            let payload = self.controller.encode_envelope(&envelope.with_id(id.clone())).map_err(RequestError::Send)?;
+            let message = self.controller.frame_kind().frame(payload).map_err(RequestError::Send)?;
+            self.handle.send(message).await.map_err(|_| RequestError::Closed)?;
+            reply.await.map_err(|_| RequestError::Closed)
+        };
+        match tokio::time::timeout(timeout, exchange).await {
+            Ok(result) => result,
+            Err(_) => Err(RequestError::Timeout { id: id.clone(), timeout }),
+        }
+    }
+
+    /// Waits for the next received message that is not a reply to a request in flight.
+    ///
+    /// While the buffer of unmatched messages is full, no replies are matched either, so keep
+    /// calling this if the server sends anything besides replies.
+    ///
+    /// # Returns
+    ///
+    /// The next unmatched text or binary message, or `None` once the connection has ended.
+    pub async fn recv_inbound(&self) -> Option<InboundMessage> {
+        self.shared.unmatched.lock().await.recv().await
+    }
+
+    /// Returns the number of requests awaiting a reply.
+    pub fn in_flight(&self) -> usize {
+        self.shared.pending.lock().unwrap().len()
+    }
+
+    /// Returns the connection requests are sent on.
+    pub fn handle(&self) -> &ConnectionHandle {
+        &self.handle
+    }
+}
+
+impl fmt::Debug for Requester {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Requester").field("in_flight", &self.in_flight()).finish()
+    }
+}
+
+/// Removes a request from the pending table when it completes, fails or is cancelled.
+struct PendingGuard<'a> {
+    shared: &'a Shared,
+    id: &'a str,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.shared.pending.lock().unwrap().remove(self.id);
+    }
+}
+
+/// Routes replies to their requests and everything else to the unmatched channel until the
+/// connection ends, which fails the requests still in flight.
+async fn read_replies(
+    controller: Arc<WebSocketController>,
+    handle: ConnectionHandle,
+    shared: std::sync::Weak<Shared>,
+    unmatched: mpsc::Sender<InboundMessage>,
+) {
+    while let Some(message) = handle.recv_inbound().await {
+        let Some(shared) = shared.upgrade() else { return };
+        let reply = match controller.decode_envelope(message.as_bytes()) {
+            Ok(envelope) => envelope
+                .id
+                .as_ref()
+                .and_then(|id| shared.pending.lock().unwrap().remove(id))
+                .map(|waiter| (waiter, envelope)),
+            Err(e) => {
+                debug!("Passing on a message that is not an envelope: {}", e);
+                None
+            }
+        };
+        drop(shared);
+        match reply {
+            // A request cancelled since the lookup drops the reply.
+            Some((waiter, envelope)) => {
+                let _ = waiter.send(envelope);
+            }
+            None => {
+                if unmatched.send(message).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+    if let Some(shared) = shared.upgrade() {
+        let mut pending = shared.pending.lock().unwrap();
+        shared.closed.store(true, Ordering::Relaxed);
+        pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::MessageFormat;
+    use crate::testing::{EchoConfig, EchoServer};
+    use tokio_tungstenite::tungstenite::Message;
+
+    /// Tests concurrent requests, passing on unrelated messages, and timeouts that leave no
+    /// request behind.
+    #[tokio::test]
+    async fn test_requests() {
+        // The latency keeps echoes behind a zero timeout.
+        let server = EchoServer::start_with(EchoConfig { latency: Duration::from_millis(20), ..Default::default() })
+            .await
+            .unwrap();
+        let controller = Arc::new(WebSocketController::new(server.url(), 0, None));
+        let requester = Requester::connect(controller, PipelineConfig::default()).await.unwrap();
+
+        let requests = (0..20).map(|n| {
+            let requester = requester.clone();
+            tokio::spawn(async move {
+                let reply = requester
+                    .request(Envelope::new("echo", vec![n]), Duration::from_secs(5))
+                    .await
+                    .unwrap();
+                assert_eq!(reply.payload, vec![n]);
+            })
+        });
+        futures_util::future::join_all(requests).await.into_iter().for_each(|joined| joined.unwrap());
+        assert_eq!(requester.in_flight(), 0);
+
+        requester.handle().send(Message::Text("push".to_string())).await.unwrap();
+        assert_eq!(requester.recv_inbound().await.unwrap().as_bytes(), b"push");
+
+        let error = requester.request(Envelope::new("echo", Vec::new()), Duration::ZERO).await.unwrap_err();
+        assert!(matches!(error, RequestError::Timeout { .. }), "Unexpected error: {}", error);
+        assert_eq!(requester.in_flight(), 0);
+        // The late echo of the timed-out request is passed on.
+        let late = requester.recv_inbound().await.unwrap();
+        assert_eq!(Envelope::decode(late.as_bytes(), MessageFormat::Json).unwrap().kind, "echo");
+
+        requester.handle().shutdown().await.unwrap();
+        let closed = requester.request(Envelope::new("echo", Vec::new()), Duration::from_secs(5)).await;
+        assert_eq!(closed.unwrap_err(), RequestError::Closed);
+    }
+
+    /// Tests that unmatched messages beyond the buffer wait for `recv_inbound` instead of
+    /// being dropped.
+    #[tokio::test]
+    async fn test_unread_messages_are_kept() {
+        let server = EchoServer::start().await.unwrap();
+        let controller = Arc::new(WebSocketController::new(server.url(), 0, None));
+        let handle = controller.connect_handle(PipelineConfig::default()).await.unwrap();
+        let requester = Requester::new(controller, handle, 1);
+
+        for push in ["first", "second", "third"] {
+            requester.handle().send(Message::Text(push.to_string())).await.unwrap();
+        }
+        // The reader holds "second" until there is room for it, so the reply waits behind it.
+        let error = requester.request(Envelope::new("echo", vec![1]), Duration::from_millis(200)).await.unwrap_err();
+        assert!(matches!(error, RequestError::Timeout { .. }), "Unexpected error: {}", error);
+        for push in ["first", "second", "third"] {
+            assert_eq!(requester.recv_inbound().await.unwrap().as_bytes(), push.as_bytes());
+        }
+        let late = requester.recv_inbound().await.unwrap();
+        assert_eq!(Envelope::decode(late.as_bytes(), MessageFormat::Json).unwrap().payload, vec![1]);
+        let reply = requester.request(Envelope::new("echo", vec![2]), Duration::from_secs(5)).await.unwrap();
+        assert_eq!(reply.payload, vec![2]);
+    }
+}
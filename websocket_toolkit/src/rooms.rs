@@ -0,0 +1,320 @@
+//! # `rooms.rs`: Persistent room logs replayed on join
+//!
+//! A client joining a chat room or a live feed wants what it missed: the last few messages
+//! when it joins for the first time, everything since the last message it processed when it
+//! rejoins after a disconnect or a restart. `RoomLog` keeps the messages posted to each room
+//! in a `Storage` backend, so the backlog survives restarts of the hub as well, and answers
+//! join requests from it.
+//!
+//! Every posted message is an `Envelope` whose id becomes its sequence number within the
+//! room, starting at 1, so a client can track its position with an `InboundJournal` per room
+//! (see the `journal` module) exactly as it does for durable messages. It joins by sending
+//! `join_envelope(room, cursor)`; `RoomLog::handle_join` then replays the messages after the
+//! cursor, or the last `join_replay` messages if the client has none. Only the newest
+//! `capacity` messages of a room are kept: a client whose cursor is older than that misses
+//! the messages in between, which is logged.
+//!
+//! The toolkit is a client library with no hub of its own; a server built on it (for
+//! example on tokio-tungstenite's `accept_async`) calls `post` for every message it
+//! broadcasts to a room and `handle_join` for every envelope a client sends.
+
+use crate::messages::{Envelope, MessageFormat};
+use crate::storage::{MemoryStorage, Storage};
+use futures_util::SinkExt;
+use log::{debug, warn};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/// The envelope kind of join requests. The payload is the room name and the id, if any, is
+/// the last sequence number the client processed in that room.
+pub const JOIN_KIND: &str = "join";
+
+/// The namespace of the rooms' next sequence numbers, keyed by room name.
+const META_NAMESPACE: &str = "rooms_meta";
+
+/// Which messages of a room to replay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replay {
+    /// The last `n` messages.
+    Last(usize),
+    /// Every message after the given sequence number.
+    Since(u64),
+}
+
+/// Builds the join request a client sends to enter `room`.
+///
+/// # Arguments
+///
+/// * `room` - The room to join.
+/// * `cursor` - The last sequence number processed in the room, e.g. from
+///   `InboundJournal::cursor`, or `None` on the first join.
+///
+/// # Returns
+///
+/// An `Envelope` of kind `JOIN_KIND`.
+pub fn join_envelope(room: &str, cursor: Option<u64>) -> Envelope {
+    let envelope = Envelope::new(JOIN_KIND, room.as_bytes().to_vec());
+    match cursor {
+        Some(cursor) => envelope.with_id(cursor.to_string()),
+        None => envelope,
+    }
+}
+
+/// A persistent, bounded log of the messages posted to each room.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::messages::{Envelope, MessageFormat};
+/// use websocket_toolkit::rooms::{Replay, RoomLog};
+///
+/// let log = RoomLog::temporary(MessageFormat::Cbor, 100);
+/// for text in ["hi", "hello", "bye"] {
+///     log.post("lobby", Envelope::new("chat", text.as_bytes().to_vec())).unwrap();
+/// }
+///
+/// let missed = log.replay("lobby", Replay::Since(1)).unwrap();
+/// assert_eq!(missed.iter().map(|m| m.id.as_deref().unwrap()).collect::<Vec<_>>(), ["2", "3"]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RoomLog {
+    storage: Arc<dyn Storage>,
+    format: MessageFormat,
+    capacity: usize,
+    join_replay: usize,
+    /// Held while posting so sequence numbers stay contiguous.
+    post_lock: Arc<Mutex<()>>,
+}
+
+impl RoomLog {
+    /// Creates a room log on `storage`, keeping the messages already stored there.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - The persistence backend; it may be shared with other components.
+    /// * `format` - The format messages are stored and replayed in.
+    /// * `capacity` - The number of most recent messages kept per room.
+    ///
+    /// # Returns
+    ///
+    /// A new `RoomLog` that replays the last 50 messages to clients joining without a cursor.
+    pub fn with_storage(storage: Arc<dyn Storage>, format: MessageFormat, capacity: usize) -> Self {
+        RoomLog {
+            storage,
+            format,
+            capacity: capacity.max(1),
+            join_replay: 50,
+            post_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Creates a room log that keeps messages in memory only, for tests.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format messages are stored and replayed in.
+    /// * `capacity` - The number of most recent messages kept per room.
+    ///
+    /// # Returns
+    ///
+    /// A new `RoomLog`.
+    pub fn temporary(format: MessageFormat, capacity: usize) -> Self {
+        Self::with_storage(Arc::new(MemoryStorage::new()), format, capacity)
+    }
+
+    /// Sets how many messages a client joining without a cursor receives.
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - The number of most recent messages replayed.
+    ///
+    /// # Returns
+    ///
+    /// The updated `RoomLog`.
+    pub fn with_join_replay(mut self, n: usize) -> Self {
+        self.join_replay = n;
+        self
+    }
+
+    /// Assigns `envelope` the room's next sequence number as its id and persists it.
+    ///
+    /// Returns only once the message has been flushed; the oldest messages beyond the
+    /// capacity are removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `room` - The room the message is posted to.
+    /// * `envelope` - The message; its id is replaced by the sequence number.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the message as stored, to broadcast to the room's members, or an
+    /// error message on failure.
+    pub fn post(&self, room: &str, envelope: Envelope) -> Result<Envelope, String> {
+        let storage_error = |e: String| format!("Failed to store room message: {}", e);
+        let namespace = room_namespace(room);
+        let _guard = self.post_lock.lock().unwrap();
+        let sequence = self
+            .storage
+            .get(META_NAMESPACE, room.as_bytes())
+            .map_err(storage_error)?
+            .as_deref()
+            .and_then(decode_sequence)
+            .unwrap_or(1);
+        let envelope = envelope.with_id(sequence.to_string());
+        self.storage
+            .put(&namespace, &sequence.to_be_bytes(), &envelope.encode(self.format)?)
+            .map_err(storage_error)?;
+        self.storage
+            .put(META_NAMESPACE, room.as_bytes(), &(sequence + 1).to_be_bytes())
+            .map_err(storage_error)?;
+        let stored = self.storage.iterate(&namespace).map_err(storage_error)?;
+        for (key, _) in stored.iter().take(stored.len().saturating_sub(self.capacity)) {
+            self.storage.delete(&namespace, key).map_err(storage_error)?;
+        }
+        self.storage.flush().map_err(storage_error)?;
+        Ok(envelope)
+    }
+
+    /// Returns the messages of `room` selected by `replay`, oldest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `room` - The room.
+    /// * `replay` - Which messages to return.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the messages, or an error message on failure.
+    pub fn replay(&self, room: &str, replay: Replay) -> Result<Vec<Envelope>, String> {
+        let stored = self
+            .storage
+            .iterate(&room_namespace(room))
+            .map_err(|e| format!("Failed to read room log: {}", e))?;
+        let selected: Vec<&Vec<u8>> = match replay {
+            Replay::Last(n) => stored.iter().skip(stored.len().saturating_sub(n)).map(|(_, encoded)| encoded).collect(),
+            Replay::Since(cursor) => {
+                let oldest = stored.first().and_then(|(key, _)| decode_sequence(key));
+                if oldest.is_some_and(|oldest| oldest > cursor.saturating_add(1)) {
+                    warn!("Room {} no longer holds the messages after {}", room, cursor);
+                }
+                stored
+                    .iter()
+                    .filter(|(key, _)| decode_sequence(key).is_some_and(|sequence| sequence > cursor))
+                    .map(|(_, encoded)| encoded)
+                    .collect()
+            }
+        };
+        selected.into_iter().map(|encoded| Envelope::decode(encoded, self.format)).collect()
+    }
+
+    /// Replays the room's backlog to the client if `envelope` is a join request.
+    ///
+    /// A request with a cursor receives every message after it, one without the last
+    /// `join_replay` messages.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the client's WebSocket stream.
+    /// * `envelope` - An envelope received from the client.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the joined room and the number of messages replayed, `None` if
+    /// `envelope` is not a join request, or an error message on failure.
+    pub async fn handle_join<S>(
+        &self,
+        ws_stream: &mut WebSocketStream<S>,
+        envelope: &Envelope,
+    ) -> Result<Option<(String, usize)>, String>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        if envelope.kind != JOIN_KIND {
+            return Ok(None);
+        }
+        let room = String::from_utf8(envelope.payload.clone()).map_err(|_| "Join request with an invalid room name".to_string())?;
+        let replay = match envelope.id.as_deref().map(str::parse::<u64>) {
+            Some(Ok(cursor)) => Replay::Since(cursor),
+            Some(Err(_)) => {
+                warn!("Join request with an invalid cursor: {:?}", envelope.id);
+                Replay::Last(self.join_replay)
+            }
+            None => Replay::Last(self.join_replay),
+        };
+        let backlog = self.replay(&room, replay)?;
+        for message in &backlog {
+            ws_stream
+                .send(Message::Binary(message.encode(self.format)?))
+                .await
+                .map_err(|e| format!("Failed to replay room message: {}", e))?;
+        }
+        debug!("Replayed {} messages of room {}", backlog.len(), room);
+        Ok(Some((room, backlog.len())))
+    }
+}
+
+/// Returns the storage namespace of a room's messages.
+fn room_namespace(room: &str) -> String {
+    format!("room:{}", room)
+}
+
+/// Decodes a big-endian sequence number stored as a key or value.
+fn decode_sequence(bytes: &[u8]) -> Option<u64> {
+    <[u8; 8]>::try_from(bytes).ok().map(u64::from_be_bytes)
+}
+
+#[cfg(all(test, feature = "cbor"))]
+mod tests {
+    use super::*;
+    use crate::testing::memory_pair;
+    use futures_util::StreamExt;
+
+    fn ids(messages: &[Envelope]) -> Vec<&str> {
+        messages.iter().map(|message| message.id.as_deref().unwrap()).collect()
+    }
+
+    /// Tests that rooms are numbered separately, trimmed to capacity and survive reopening.
+    #[test]
+    fn test_post_and_replay() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let log = RoomLog::with_storage(storage.clone(), MessageFormat::Cbor, 3);
+        for _ in 0..5 {
+            log.post("a", Envelope::new("chat", Vec::new())).unwrap();
+        }
+        assert_eq!(log.post("b", Envelope::new("chat", Vec::new())).unwrap().id.as_deref(), Some("1"));
+
+        let log = RoomLog::with_storage(storage, MessageFormat::Cbor, 3);
+        assert_eq!(log.post("a", Envelope::new("chat", Vec::new())).unwrap().id.as_deref(), Some("6"));
+        assert_eq!(ids(&log.replay("a", Replay::Last(10)).unwrap()), ["4", "5", "6"]);
+        assert_eq!(ids(&log.replay("a", Replay::Last(1)).unwrap()), ["6"]);
+        assert_eq!(ids(&log.replay("a", Replay::Since(4)).unwrap()), ["5", "6"]);
+        assert_eq!(ids(&log.replay("a", Replay::Since(1)).unwrap()), ["4", "5", "6"]);
+        assert!(log.replay("c", Replay::Last(10)).unwrap().is_empty());
+    }
+
+    /// Tests that a join request replays the backlog after the client's cursor.
+    #[tokio::test]
+    async fn test_handle_join() {
+        let log = RoomLog::temporary(MessageFormat::Cbor, 10).with_join_replay(2);
+        for _ in 0..3 {
+            log.post("lobby", Envelope::new("chat", Vec::new())).unwrap();
+        }
+        let (mut client, mut server) = memory_pair(None).await;
+        assert_eq!(log.handle_join(&mut server, &Envelope::new("chat", Vec::new())).await.unwrap(), None);
+
+        let joined = log.handle_join(&mut server, &join_envelope("lobby", None)).await.unwrap();
+        assert_eq!(joined, Some(("lobby".to_string(), 2)));
+        let joined = log.handle_join(&mut server, &join_envelope("lobby", Some(2))).await.unwrap();
+        assert_eq!(joined, Some(("lobby".to_string(), 1)));
+
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            let frame = client.next().await.unwrap().unwrap();
+            received.push(Envelope::decode(&frame.into_data(), MessageFormat::Cbor).unwrap());
+        }
+        assert_eq!(ids(&received), ["2", "3", "3"]);
+    }
+}
@@ -0,0 +1,150 @@
+//! # `rtt.rs`: Round-trip time tracking
+//!
+//! Latency-sensitive applications want to know how long the network takes to answer, not
+//! just whether the connection is alive. `RttTracker` times each keep-alive ping against the
+//! pong that answers it and keeps the latest, smoothed, minimum and maximum round-trip
+//! times. Pipelines created with `PipelineConfig::track_rtt` record every ping sent through
+//! `PipelineSender::ping` (and therefore every `KeepAlive` ping); read the results with
+//! `PipelineSender::rtt`.
+//!
+//! Pings carry no payload, so a pong is matched to the most recent ping. A ping that is never
+//! answered is superseded by the next one instead of inflating later samples; `outstanding`
+//! reports how long the current one has gone unanswered, the earliest sign of a dead link.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Round-trip time statistics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RttStats {
+    /// The most recent sample.
+    pub latest: Duration,
+    /// The exponentially weighted moving average of the samples, as TCP computes it
+    /// (RFC 6298, with a gain of 1/8).
+    pub smoothed: Duration,
+    /// The smallest sample.
+    pub min: Duration,
+    /// The largest sample.
+    pub max: Duration,
+    /// The smoothed variation between consecutive samples, as RTP computes interarrival
+    /// jitter (RFC 3550, with a gain of 1/16).
+    pub jitter: Duration,
+    /// The number of samples.
+    pub samples: u64,
+}
+
+/// Times pings against their pongs.
+#[derive(Debug, Default)]
+pub struct RttTracker {
+    state: Mutex<RttState>,
+}
+
+/// The tracker's mutable state.
+#[derive(Debug, Default)]
+struct RttState {
+    /// When the unanswered ping was sent.
+    ping_sent: Option<Instant>,
+    /// The statistics, once a pong has been timed.
+    stats: Option<RttStats>,
+}
+
+impl RttTracker {
+    /// Creates a tracker with no samples.
+    ///
+    /// # Returns
+    ///
+    /// A new `RttTracker`.
+    pub fn new() -> Self {
+        RttTracker::default()
+    }
+
+    /// Records that a ping is being sent now.
+    pub fn record_ping(&self) {
+        self.state.lock().unwrap().ping_sent = Some(Instant::now());
+    }
+
+    /// Records that a pong arrived now, completing a sample if a ping is outstanding.
+    ///
+    /// # Returns
+    ///
+    /// The round-trip time of the answered ping, or `None` for an unsolicited pong.
+    pub fn record_pong(&self) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        let sample = state.ping_sent.take()?.elapsed();
+        state.stats = Some(match state.stats {
+            None => RttStats {
+                latest: sample,
+                smoothed: sample,
+                min: sample,
+                max: sample,
+                jitter: Duration::ZERO,
+                samples: 1,
+            },
+            Some(stats) => RttStats {
+                latest: sample,
+                smoothed: stats.smoothed * 7 / 8 + sample / 8,
+                min: stats.min.min(sample),
+                max: stats.max.max(sample),
+                jitter: stats.jitter * 15 / 16 + abs_diff(sample, stats.latest) / 16,
+                samples: stats.samples + 1,
+            },
+        });
+        Some(sample)
+    }
+
+    /// Returns the statistics so far.
+    ///
+    /// # Returns
+    ///
+    /// The `RttStats`, or `None` until the first pong has been timed.
+    pub fn stats(&self) -> Option<RttStats> {
+        self.state.lock().unwrap().stats
+    }
+
+    /// Returns how long the most recent ping has been waiting for its pong.
+    ///
+    /// # Returns
+    ///
+    /// The time since the unanswered ping was sent, or `None` if every ping was answered.
+    pub fn outstanding(&self) -> Option<Duration> {
+        self.state.lock().unwrap().ping_sent.map(|sent| sent.elapsed())
+    }
+}
+
+/// Returns the absolute difference between two durations.
+fn abs_diff(a: Duration, b: Duration) -> Duration {
+    a.checked_sub(b).unwrap_or_else(|| b - a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that samples are only taken for answered pings and update every statistic.
+    #[test]
+    fn test_rtt_tracker() {
+        let tracker = RttTracker::new();
+        assert_eq!(tracker.record_pong(), None);
+        assert_eq!(tracker.stats(), None);
+
+        tracker.record_ping();
+        std::thread::sleep(Duration::from_millis(5));
+        let first = tracker.record_pong().expect("Expected a sample");
+        assert!(first >= Duration::from_millis(5));
+        assert_eq!(tracker.record_pong(), None);
+
+        tracker.record_ping();
+        let second = tracker.record_pong().expect("Expected a sample");
+        let stats = tracker.stats().unwrap();
+        assert_eq!(stats.samples, 2);
+        assert_eq!(stats.latest, second);
+        assert_eq!(stats.min, second.min(first));
+        assert_eq!(stats.max, first.max(second));
+        assert!(stats.smoothed <= first && stats.smoothed >= second.min(first));
+        assert_eq!(stats.jitter, abs_diff(first, second) / 16);
+
+        assert_eq!(tracker.outstanding(), None);
+        tracker.record_ping();
+        assert!(tracker.outstanding().is_some());
+    }
+}
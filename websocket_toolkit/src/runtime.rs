@@ -0,0 +1,253 @@
+//! # `runtime.rs`: async runtime abstraction
+//!
+//! This module abstracts the timer, spawn and TCP primitives the toolkit needs behind the
+//! [`Runtime`] trait, with feature-gated implementations for tokio (`tokio`, enabled by
+//! default), async-std (`runtime-async-std`) and smol (`runtime-smol`). Code that is generic
+//! over `Runtime` never starts a second executor: async-std and smol sockets are adapted to
+//! the I/O traits tungstenite expects through `tokio-util`'s compatibility layer, which does
+//! not require the tokio runtime. `runtime-async-std` and `runtime-smol` build without the
+//! default features, enabling only tokio's `net` and `io-util` features; the `tokio` feature
+//! selects `TokioRuntime` and the full tokio runtime. The controller and the modules built on
+//! its pipeline spawn tokio tasks and use tokio's timers, so they are only compiled with the
+//! `tokio` feature; without it the crate provides the client, this module, `reconnection`
+//! and the message, codec and bookkeeping types.
+
+use futures::future::BoxFuture;
+use log::info;
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::error::UrlError;
+use tokio_tungstenite::tungstenite::Error;
+use tokio_tungstenite::{client_async, WebSocketStream};
+use url::Url;
+
+/// The primitives an async runtime provides to the toolkit.
+///
+/// Implementations are zero-sized marker types; every primitive is an associated function,
+/// so code selects a runtime with a type parameter such as `connect_async::<SmolRuntime>(..)`.
+pub trait Runtime: Send + Sync + 'static {
+    /// The TCP stream type produced by [`Runtime::connect_tcp`].
+    type TcpStream: AsyncRead + AsyncWrite + Send + Unpin + 'static;
+
+    /// Spawns a detached background task on the runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `future` - The task to run.
+    fn spawn<F>(future: F)
+    where
+        F: Future<Output = ()> + Send + 'static;
+
+    /// Returns a future that completes after `duration` has elapsed.
+    ///
+    /// # Arguments
+    ///
+    /// * `duration` - How long to sleep.
+    fn sleep(duration: Duration) -> BoxFuture<'static, ()>;
+
+    /// Opens a TCP connection to `addr`.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The `host:port` to connect to.
+    ///
+    /// # Returns
+    ///
+    /// A future resolving to the connected stream or an I/O error.
+    fn connect_tcp(addr: String) -> BoxFuture<'static, io::Result<Self::TcpStream>>;
+}
+
+/// The tokio runtime.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioRuntime;
+
+#[cfg(feature = "tokio")]
+impl Runtime for TokioRuntime {
+    type TcpStream = tokio::net::TcpStream;
+
+    fn spawn<F>(future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        tokio::spawn(future);
+    }
+
+    fn sleep(duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+
+    fn connect_tcp(addr: String) -> BoxFuture<'static, io::Result<Self::TcpStream>> {
+        Box::pin(tokio::net::TcpStream::connect(addr))
+    }
+}
+
+/// The async-std runtime, enabled by the `runtime-async-std` feature.
+#[cfg(feature = "runtime-async-std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncStdRuntime;
+
+#[cfg(feature = "runtime-async-std")]
+impl Runtime for AsyncStdRuntime {
+    type TcpStream = tokio_util::compat::Compat<async_std::net::TcpStream>;
+
+    fn spawn<F>(future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        async_std::task::spawn(future);
+    }
+
+    fn sleep(duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(async_std::task::sleep(duration))
+    }
+
+    fn connect_tcp(addr: String) -> BoxFuture<'static, io::Result<Self::TcpStream>> {
+        use tokio_util::compat::FuturesAsyncReadCompatExt;
+        Box::pin(async move { Ok(async_std::net::TcpStream::connect(addr).await?.compat()) })
+    }
+}
+
+/// The smol runtime, enabled by the `runtime-smol` feature.
+#[cfg(feature = "runtime-smol")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SmolRuntime;
+
+#[cfg(feature = "runtime-smol")]
+impl Runtime for SmolRuntime {
+    type TcpStream = tokio_util::compat::Compat<smol::net::TcpStream>;
+
+    fn spawn<F>(future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        smol::spawn(future).detach();
+    }
+
+    fn sleep(duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(async move {
+            smol::Timer::after(duration).await;
+        })
+    }
+
+    fn connect_tcp(addr: String) -> BoxFuture<'static, io::Result<Self::TcpStream>> {
+        use tokio_util::compat::FuturesAsyncReadCompatExt;
+        Box::pin(async move { Ok(smol::net::TcpStream::connect(addr).await?.compat()) })
+    }
+}
+
+/// The runtime used by the toolkit's non-generic APIs.
+///
+/// This is tokio when the `tokio` feature is enabled, otherwise async-std or smol.
+#[cfg(feature = "tokio")]
+pub type DefaultRuntime = TokioRuntime;
+
+/// The runtime used by the toolkit's non-generic APIs.
+#[cfg(all(not(feature = "tokio"), feature = "runtime-async-std"))]
+pub type DefaultRuntime = AsyncStdRuntime;
+
+/// The runtime used by the toolkit's non-generic APIs.
+#[cfg(all(not(feature = "tokio"), not(feature = "runtime-async-std"), feature = "runtime-smol"))]
+pub type DefaultRuntime = SmolRuntime;
+
+/// Connects to a `ws://` WebSocket server using the TCP primitive of runtime `R`.
+///
+/// TLS (`wss://`) is only available through the tokio-based `WebSocketClient::connect`.
+///
+/// # Arguments
+///
+/// * `url` - The WebSocket server URL.
+///
+/// # Returns
+///
+/// A `Result` containing the WebSocket stream on success, or an `Error` on failure.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use websocket_toolkit::runtime::{connect_async, TokioRuntime};
+///
+/// # async fn run() -> Result<(), tokio_tungstenite::tungstenite::Error> {
+/// let ws_stream = connect_async::<TokioRuntime>("ws://127.0.0.1:9001").await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn connect_async<R: Runtime>(url: &str) -> Result<WebSocketStream<R::TcpStream>, Error> {
+    let parsed = Url::parse(url).map_err(|_| Error::Url(UrlError::NoHostName))?;
+    if parsed.scheme() != "ws" {
+        return Err(Error::Url(UrlError::UnsupportedUrlScheme));
+    }
+    let host = parsed.host_str().ok_or(Error::Url(UrlError::NoHostName))?;
+    let port = parsed.port_or_known_default().unwrap_or(80);
+
+    info!("Attempting to connect to WebSocket server at {}", url);
+    let tcp = R::connect_tcp(format!("{}:{}", host, port)).await?;
+    let (ws_stream, _) = client_async(url, tcp).await?;
+    info!("Connected to WebSocket server at {}", url);
+    Ok(ws_stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::EchoServer;
+    use futures_util::{SinkExt, StreamExt};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use tokio_tungstenite::tungstenite::Message;
+
+    /// Round-trips one message through the echo server using runtime `R`.
+    async fn echo_round_trip<R: Runtime>(url: &str) {
+        let mut ws_stream = connect_async::<R>(url).await.expect("Failed to connect");
+        ws_stream.send(Message::Text("hello".into())).await.expect("Failed to send");
+        let reply = ws_stream.next().await.expect("Stream ended").expect("Failed to receive");
+        assert_eq!(reply, Message::Text("hello".into()));
+    }
+
+    /// Tests that the tokio runtime's sleep and spawn primitives run tasks to completion.
+    #[tokio::test]
+    async fn test_tokio_spawn_and_sleep() {
+        let done = Arc::new(AtomicBool::new(false));
+        let flag = done.clone();
+        TokioRuntime::spawn(async move {
+            flag.store(true, Ordering::SeqCst);
+        });
+        TokioRuntime::sleep(Duration::from_millis(50)).await;
+        assert!(done.load(Ordering::SeqCst), "Expected the spawned task to have run");
+    }
+
+    /// Tests connecting through the tokio runtime's TCP primitive.
+    #[tokio::test]
+    async fn test_connect_async_with_tokio_runtime() {
+        let server = EchoServer::start().await.expect("Failed to start echo server");
+        echo_round_trip::<TokioRuntime>(server.url()).await;
+    }
+
+    /// Tests that non-`ws` schemes are rejected.
+    #[tokio::test]
+    async fn test_connect_async_rejects_tls_urls() {
+        let result = connect_async::<TokioRuntime>("wss://example.com").await;
+        assert!(matches!(result, Err(Error::Url(_))), "Expected a URL error for wss://");
+    }
+
+    /// Tests connecting through the async-std runtime's TCP primitive.
+    #[cfg(feature = "runtime-async-std")]
+    #[tokio::test]
+    async fn test_connect_async_with_async_std_runtime() {
+        let server = EchoServer::start().await.expect("Failed to start echo server");
+        let url = server.url().to_string();
+        async_std::task::spawn(async move { echo_round_trip::<AsyncStdRuntime>(&url).await })
+            .await;
+    }
+
+    /// Tests connecting through the smol runtime's TCP primitive.
+    #[cfg(feature = "runtime-smol")]
+    #[tokio::test]
+    async fn test_connect_async_with_smol_runtime() {
+        let server = EchoServer::start().await.expect("Failed to start echo server");
+        let url = server.url().to_string();
+        smol::spawn(async move { echo_round_trip::<SmolRuntime>(&url).await }).await;
+    }
+}
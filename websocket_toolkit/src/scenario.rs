@@ -0,0 +1,454 @@
+//! # `scenario.rs`: Scripted integration-test scenarios
+//!
+//! This module provides a small DSL for describing client/server interactions as a list of
+//! steps (connect, send, expect a message, drop the connection, expect a reconnect within a
+//! deadline, ...) and a runner that executes them against a `MockServer`. Scenarios can be
+//! built in code or, with the `toml` feature, loaded from a TOML document.
+//!
+//! The client is a `ManagedConnection`, so reconnects are the library's own: the runner
+//! keeps receiving in the background, as an application would, and `ExpectReconnect` only
+//! watches for the reconnect that follows.
+
+use crate::controller::WebSocketController;
+use crate::managed::ManagedConnection;
+use crate::pipeline::PipelineConfig;
+use crate::testing::{MockServer, TestHandle};
+use futures_util::SinkExt;
+use log::info;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout, Instant};
+use tokio_tungstenite::tungstenite::Message;
+
+/// The default time an expectation step waits before failing.
+const DEFAULT_WITHIN_MS: u64 = 2000;
+
+/// The payload of a message in a scenario step.
+///
+/// In TOML, a payload is written as either `text = "..."` or `binary = [1, 2, 3]`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    /// A UTF-8 text frame.
+    Text(String),
+    /// A binary frame.
+    Binary(Vec<u8>),
+}
+
+impl Payload {
+    /// Returns the raw bytes of the payload.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Payload::Text(text) => text.as_bytes(),
+            Payload::Binary(data) => data,
+        }
+    }
+
+    /// Converts the payload into the WebSocket frame that carries it.
+    pub fn to_message(&self) -> Message {
+        match self {
+            Payload::Text(text) => Message::Text(text.clone()),
+            Payload::Binary(data) => Message::Binary(data.clone()),
+        }
+    }
+}
+
+/// A single step of a scenario.
+///
+/// "Client" steps act through a `WebSocketController`; "server" steps act on the mock
+/// server's side of the current connection.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum Step {
+    /// The client connects and the mock server accepts the connection.
+    Connect,
+    /// The client sends a message.
+    Send {
+        /// The message to send.
+        #[serde(flatten)]
+        payload: Payload,
+    },
+    /// The server sends a message to the client.
+    ServerSend {
+        /// The message to send.
+        #[serde(flatten)]
+        payload: Payload,
+    },
+    /// The client must receive the given message, with the same frame type, within
+    /// `within_ms` milliseconds.
+    ExpectMessage {
+        /// The expected message.
+        #[serde(flatten)]
+        payload: Payload,
+        /// The deadline in milliseconds (defaults to two seconds).
+        within_ms: Option<u64>,
+    },
+    /// The server must receive the given message within `within_ms` milliseconds.
+    ExpectServerReceives {
+        /// The expected message.
+        #[serde(flatten)]
+        payload: Payload,
+        /// The deadline in milliseconds (defaults to two seconds).
+        within_ms: Option<u64>,
+    },
+    /// The server drops the connection, with a close frame if `code` is given or abruptly otherwise.
+    DropConnection {
+        /// The close code to send before dropping.
+        code: Option<u16>,
+    },
+    /// The client must notice the dropped connection and reconnect on its own within
+    /// `within_ms` milliseconds.
+    ExpectReconnect {
+        /// The deadline in milliseconds.
+        within_ms: u64,
+    },
+    /// Waits for the given number of milliseconds.
+    Sleep {
+        /// The time to wait in milliseconds.
+        ms: u64,
+    },
+}
+
+impl Step {
+    /// Returns the snake_case name of the step used in failure messages.
+    fn name(&self) -> &'static str {
+        match self {
+            Step::Connect => "connect",
+            Step::Send { .. } => "send",
+            Step::ServerSend { .. } => "server_send",
+            Step::ExpectMessage { .. } => "expect_message",
+            Step::ExpectServerReceives { .. } => "expect_server_receives",
+            Step::DropConnection { .. } => "drop_connection",
+            Step::ExpectReconnect { .. } => "expect_reconnect",
+            Step::Sleep { .. } => "sleep",
+        }
+    }
+}
+
+/// A named, ordered list of steps.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::scenario::Scenario;
+/// use std::time::Duration;
+///
+/// let runtime = tokio::runtime::Runtime::new().unwrap();
+/// runtime.block_on(async {
+///     let result = Scenario::new("greeting")
+///         .connect()
+///         .send_text("hello")
+///         .expect_server_receives_text("hello")
+///         .server_send_text("welcome")
+///         .expect_text("welcome")
+///         .drop_connection(Some(1012))
+///         .expect_reconnect_within(Duration::from_secs(2))
+///         .send_text("again")
+///         .expect_server_receives_text("again")
+///         .run()
+///         .await;
+///     assert!(result.is_ok(), "{:?}", result);
+/// });
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Scenario {
+    /// The name of the scenario, used in failure messages.
+    pub name: String,
+    /// The steps executed in order.
+    #[serde(default)]
+    pub steps: Vec<Step>,
+}
+
+impl Scenario {
+    /// Creates an empty scenario with the given name.
+    pub fn new(name: &str) -> Self {
+        Scenario {
+            name: name.to_string(),
+            steps: Vec::new(),
+        }
+    }
+
+    /// Parses a scenario from a TOML document.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit::scenario::{Scenario, Step};
+    ///
+    /// let scenario = Scenario::from_toml(r#"
+    ///     name = "echo"
+    ///
+    ///     [[steps]]
+    ///     step = "connect"
+    ///
+    ///     [[steps]]
+    ///     step = "send"
+    ///     text = "ping"
+    ///
+    ///     [[steps]]
+    ///     step = "expect_reconnect"
+    ///     within_ms = 500
+    /// "#).unwrap();
+    /// assert_eq!(scenario.steps.len(), 3);
+    /// assert_eq!(scenario.steps[0], Step::Connect);
+    /// ```
+    #[cfg(feature = "toml")]
+    pub fn from_toml(document: &str) -> Result<Self, String> {
+        toml::from_str(document).map_err(|e| format!("Failed to parse scenario: {}", e))
+    }
+
+    /// Appends an arbitrary step.
+    pub fn step(mut self, step: Step) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    /// Appends a `Connect` step.
+    pub fn connect(self) -> Self {
+        self.step(Step::Connect)
+    }
+
+    /// Appends a `Send` step with a text payload.
+    pub fn send_text(self, text: &str) -> Self {
+        self.step(Step::Send { payload: Payload::Text(text.to_string()) })
+    }
+
+    /// Appends a `Send` step with a binary payload.
+    pub fn send_binary(self, data: &[u8]) -> Self {
+        self.step(Step::Send { payload: Payload::Binary(data.to_vec()) })
+    }
+
+    /// Appends a `ServerSend` step with a text payload.
+    pub fn server_send_text(self, text: &str) -> Self {
+        self.step(Step::ServerSend { payload: Payload::Text(text.to_string()) })
+    }
+
+    /// Appends an `ExpectMessage` step for a text payload with the default deadline.
+    pub fn expect_text(self, text: &str) -> Self {
+        self.step(Step::ExpectMessage {
+            payload: Payload::Text(text.to_string()),
+            within_ms: None,
+        })
+    }
+
+    /// Appends an `ExpectServerReceives` step for a text payload with the default deadline.
+    pub fn expect_server_receives_text(self, text: &str) -> Self {
+        self.step(Step::ExpectServerReceives {
+            payload: Payload::Text(text.to_string()),
+            within_ms: None,
+        })
+    }
+
+    /// Appends a `DropConnection` step.
+    pub fn drop_connection(self, code: Option<u16>) -> Self {
+        self.step(Step::DropConnection { code })
+    }
+
+    /// Appends an `ExpectReconnect` step.
+    pub fn expect_reconnect_within(self, within: Duration) -> Self {
+        self.step(Step::ExpectReconnect { within_ms: within.as_millis() as u64 })
+    }
+
+    /// Appends a `Sleep` step.
+    pub fn sleep(self, duration: Duration) -> Self {
+        self.step(Step::Sleep { ms: duration.as_millis() as u64 })
+    }
+
+    /// Runs the scenario against a freshly started `MockServer`.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if every step succeeded, or an error naming the scenario, the failing step
+    /// and the reason.
+    pub async fn run(&self) -> Result<(), String> {
+        let server = MockServer::start()
+            .await
+            .map_err(|e| format!("Scenario '{}': failed to start mock server: {}", self.name, e))?;
+        let mut runner = Runner {
+            controller: Arc::new(WebSocketController::new(server.url(), 3, None)),
+            server,
+            client: None,
+            connection: None,
+        };
+
+        for (index, step) in self.steps.iter().enumerate() {
+            info!("Scenario '{}': step {} ({})", self.name, index + 1, step.name());
+            runner.execute(step).await.map_err(|reason| {
+                format!("Scenario '{}' failed at step {} ({}): {}", self.name, index + 1, step.name(), reason)
+            })?;
+        }
+        runner.disconnect();
+        Ok(())
+    }
+}
+
+/// The client side of a running scenario.
+struct Client {
+    connection: ManagedConnection,
+    /// Messages received by the background task, which also drives reconnects.
+    messages: UnboundedReceiver<Message>,
+    receiver: JoinHandle<()>,
+    /// The reconnects already expected by `ExpectReconnect` steps.
+    reconnects: u64,
+}
+
+/// The mutable state of a running scenario.
+struct Runner {
+    controller: Arc<WebSocketController>,
+    server: MockServer,
+    client: Option<Client>,
+    connection: Option<TestHandle<TcpStream>>,
+}
+
+impl Runner {
+    /// Executes a single step.
+    async fn execute(&mut self, step: &Step) -> Result<(), String> {
+        match step {
+            Step::Connect => {
+                let connecting = ManagedConnection::connect(self.controller.clone(), PipelineConfig::default());
+                let connection = connecting.await.map_err(|e| e.to_string())?;
+                let accepted = self
+                    .server
+                    .accept_within(Duration::from_millis(DEFAULT_WITHIN_MS))
+                    .await
+                    .ok_or("the mock server did not accept the connection")?;
+                let (sender, messages) = unbounded_channel();
+                let receiver = connection.clone();
+                let receiver = tokio::spawn(async move {
+                    while let Some(message) = receiver.recv().await {
+                        if sender.send(message).is_err() {
+                            break;
+                        }
+                    }
+                });
+                self.disconnect();
+                self.client = Some(Client { connection, messages, receiver, reconnects: 0 });
+                self.connection = Some(accepted);
+            }
+            Step::Send { payload } => {
+                let client = self.client.as_ref().ok_or("the client is not connected")?;
+                client.connection.send(payload.to_message()).await?;
+            }
+            Step::ServerSend { payload } => {
+                let connection = self.connection.as_mut().ok_or("the server has no connection")?;
+                connection
+                    .stream()
+                    .send(payload.to_message())
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            Step::ExpectMessage { payload, within_ms } => {
+                let client = self.client.as_mut().ok_or("the client is not connected")?;
+                let within = Duration::from_millis(within_ms.unwrap_or(DEFAULT_WITHIN_MS));
+                match timeout(within, client.messages.recv()).await {
+                    Ok(Some(received)) if received == payload.to_message() => {}
+                    Ok(Some(received)) => return Err(format!("expected {:?}, received {:?}", payload, received)),
+                    Ok(None) => return Err(format!("expected {:?}, but the connection ended", payload)),
+                    Err(_) => return Err(format!("expected {:?} within {:?}", payload, within)),
+                }
+            }
+            Step::ExpectServerReceives { payload, within_ms } => {
+                let connection = self.connection.as_mut().ok_or("the server has no connection")?;
+                let within = Duration::from_millis(within_ms.unwrap_or(DEFAULT_WITHIN_MS));
+                connection.set_timeout(within);
+                match connection.next_message().await {
+                    Some(message) if message == payload.to_message() => {}
+                    Some(message) => return Err(format!("expected {:?}, server received {:?}", payload, message)),
+                    None => return Err(format!("expected {:?} on the server within {:?}", payload, within)),
+                }
+            }
+            Step::DropConnection { code } => {
+                let mut connection = self.connection.take().ok_or("the server has no connection")?;
+                if let Some(code) = code {
+                    connection.close(*code, "scenario drop").await;
+                }
+            }
+            Step::ExpectReconnect { within_ms } => {
+                let within = Duration::from_millis(*within_ms);
+                let deadline = Instant::now() + within;
+                let client = self.client.as_mut().ok_or("the client was never connected")?;
+                let connection = self
+                    .server
+                    .accept_within(within)
+                    .await
+                    .ok_or_else(|| format!("the client did not reconnect within {:?}", within))?;
+                // The server accepts before the client finishes its side of the handshake.
+                while client.connection.reconnects() == client.reconnects {
+                    if Instant::now() >= deadline || client.receiver.is_finished() {
+                        return Err(format!("the client did not reconnect within {:?}", within));
+                    }
+                    sleep(Duration::from_millis(10)).await;
+                }
+                client.reconnects = client.connection.reconnects();
+                self.connection = Some(connection);
+            }
+            Step::Sleep { ms } => sleep(Duration::from_millis(*ms)).await,
+        }
+        Ok(())
+    }
+
+    /// Stops the client's background receiver, with any reconnect it is making, and aborts
+    /// its connection.
+    fn disconnect(&mut self) {
+        if let Some(client) = self.client.take() {
+            client.receiver.abort();
+            client.connection.handle().abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests a scenario covering traffic in both directions and a reconnect.
+    #[tokio::test]
+    async fn test_scenario_with_reconnect() {
+        let result = Scenario::new("reconnect")
+            .connect()
+            .send_binary(b"\x01\x02")
+            .step(Step::ExpectServerReceives {
+                payload: Payload::Binary(vec![1, 2]),
+                within_ms: None,
+            })
+            .server_send_text("update")
+            .expect_text("update")
+            .drop_connection(None)
+            .expect_reconnect_within(Duration::from_secs(2))
+            .send_text("resumed")
+            .expect_server_receives_text("resumed")
+            .run()
+            .await;
+        assert!(result.is_ok(), "Scenario failed: {:?}", result);
+    }
+
+    /// Tests that a failing expectation reports the step that failed.
+    #[tokio::test]
+    async fn test_scenario_reports_failing_step() {
+        let result = Scenario::new("mismatch")
+            .connect()
+            .server_send_text("actual")
+            .expect_text("expected")
+            .run()
+            .await;
+        let error = result.expect_err("Expected the scenario to fail");
+        assert!(error.contains("step 3 (expect_message)"), "Unexpected error: {}", error);
+    }
+
+    /// Tests that a message with the expected bytes but the other frame type does not match.
+    #[tokio::test]
+    async fn test_scenario_compares_frame_types() {
+        let result = Scenario::new("frame type")
+            .connect()
+            .step(Step::ServerSend { payload: Payload::Binary(b"update".to_vec()) })
+            .expect_text("update")
+            .run()
+            .await;
+        let error = result.expect_err("Expected the scenario to fail");
+        assert!(error.contains("step 3 (expect_message)"), "Unexpected error: {}", error);
+    }
+}
@@ -0,0 +1,257 @@
+//! # `schema.rs`: Envelope schema versions and migrations
+//!
+//! Clients and servers are rarely deployed at the same moment. While a rollout is under way,
+//! a new client talks to old servers and an old client to new ones, and a payload whose
+//! shape changed fails to deserialize on whichever side did not expect it. `SchemaMigrations`
+//! keeps both sides working: envelopes carry the schema version of their payload
+//! (`Envelope::version`), and the application registers migration functions per message
+//! kind.
+//!
+//! - Inbound envelopes from an older peer are upgraded one version at a time to the local
+//!   version before they are decoded.
+//! - Outbound envelopes are downgraded to the peer's version when the peer is older, so an
+//!   old peer receives payloads it understands.
+//!
+//! The peer's version is learned from the envelopes it sends, or set explicitly with
+//! `set_peer_version`, e.g. from a hello message. Unversioned envelopes count as version 0,
+//! and a kind with no migration for a step passes through that step unchanged, so only
+//! message kinds whose shape actually changed need migrations.
+
+use crate::messages::Envelope;
+use log::debug;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Migrates an envelope by one schema version; may change the payload, the kind or both.
+pub type Migration = Arc<dyn Fn(Envelope) -> Result<Envelope, String> + Send + Sync>;
+
+/// The local schema version and the migrations between versions.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::messages::Envelope;
+/// use websocket_toolkit::schema::SchemaMigrations;
+///
+/// // Version 2 renamed the `temp` field of `reading` payloads to `celsius`.
+/// let migrations = SchemaMigrations::new(2)
+///     .with_upgrade("reading", 1, |mut envelope| {
+///         envelope.payload = String::from_utf8_lossy(&envelope.payload).replace("temp", "celsius").into_bytes();
+///         Ok(envelope)
+///     })
+///     .with_downgrade("reading", 2, |mut envelope| {
+///         envelope.payload = String::from_utf8_lossy(&envelope.payload).replace("celsius", "temp").into_bytes();
+///         Ok(envelope)
+///     });
+///
+/// let old = Envelope::new("reading", br#"{"temp":21}"#.to_vec()).with_version(1);
+/// let upgraded = migrations.upgrade(old).unwrap();
+/// assert_eq!(upgraded.payload, br#"{"celsius":21}"#.to_vec());
+///
+/// // The peer sent version 1, so replies are downgraded for it.
+/// let reply = migrations.prepare_outgoing(Envelope::new("reading", br#"{"celsius":22}"#.to_vec())).unwrap();
+/// assert_eq!((reply.version, reply.payload), (Some(1), br#"{"temp":22}"#.to_vec()));
+/// ```
+pub struct SchemaMigrations {
+    current: u32,
+    /// Migrations from version `n` to `n + 1`, by kind and `n`.
+    upgrades: HashMap<(String, u32), Migration>,
+    /// Migrations from version `n` to `n - 1`, by kind and `n`.
+    downgrades: HashMap<(String, u32), Migration>,
+    /// The peer's version, once known.
+    peer_version: Mutex<Option<u32>>,
+}
+
+impl fmt::Debug for SchemaMigrations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SchemaMigrations")
+            .field("current", &self.current)
+            .field("upgrades", &self.upgrades.keys().collect::<Vec<_>>())
+            .field("downgrades", &self.downgrades.keys().collect::<Vec<_>>())
+            .field("peer_version", &self.peer_version())
+            .finish()
+    }
+}
+
+impl SchemaMigrations {
+    /// Creates a registry without migrations.
+    ///
+    /// # Arguments
+    ///
+    /// * `current` - The schema version this side produces and expects.
+    ///
+    /// # Returns
+    ///
+    /// A new `SchemaMigrations`.
+    pub fn new(current: u32) -> Self {
+        SchemaMigrations {
+            current,
+            upgrades: HashMap::new(),
+            downgrades: HashMap::new(),
+            peer_version: Mutex::new(None),
+        }
+    }
+
+    /// Registers the migration of `kind` envelopes from version `from` to `from + 1`.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The message kind, as it is named at version `from`.
+    /// * `from` - The version migrated from.
+    /// * `migration` - The migration.
+    ///
+    /// # Returns
+    ///
+    /// The registry with the migration added.
+    pub fn with_upgrade<F>(mut self, kind: impl Into<String>, from: u32, migration: F) -> Self
+    where
+        F: Fn(Envelope) -> Result<Envelope, String> + Send + Sync + 'static,
+    {
+        self.upgrades.insert((kind.into(), from), Arc::new(migration));
+        self
+    }
+
+    /// Registers the migration of `kind` envelopes from version `from` to `from - 1`.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The message kind, as it is named at version `from`.
+    /// * `from` - The version migrated from; at least 1.
+    /// * `migration` - The migration.
+    ///
+    /// # Returns
+    ///
+    /// The registry with the migration added.
+    pub fn with_downgrade<F>(mut self, kind: impl Into<String>, from: u32, migration: F) -> Self
+    where
+        F: Fn(Envelope) -> Result<Envelope, String> + Send + Sync + 'static,
+    {
+        self.downgrades.insert((kind.into(), from), Arc::new(migration));
+        self
+    }
+
+    /// Returns the local schema version.
+    pub fn current(&self) -> u32 {
+        self.current
+    }
+
+    /// Returns the peer's schema version, once known.
+    pub fn peer_version(&self) -> Option<u32> {
+        *self.peer_version.lock().unwrap()
+    }
+
+    /// Sets the peer's schema version, e.g. from a handshake or hello message.
+    ///
+    /// # Arguments
+    ///
+    /// * `version` - The peer's version.
+    pub fn set_peer_version(&self, version: u32) {
+        *self.peer_version.lock().unwrap() = Some(version);
+    }
+
+    /// Records the version of a received envelope as the peer's and migrates the envelope to
+    /// the local version.
+    ///
+    /// Envelopes from an older peer are upgraded. Envelopes from a newer peer are downgraded
+    /// where downgrades are registered, which is rarely the case for an old release; they
+    /// are otherwise passed on unchanged.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - The received envelope.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the envelope at the local version, or the error of a migration.
+    pub fn upgrade(&self, envelope: Envelope) -> Result<Envelope, String> {
+        let version = envelope.version.unwrap_or(0);
+        self.set_peer_version(version);
+        self.migrate(envelope, version, self.current)
+    }
+
+    /// Stamps an outgoing envelope with the local version and, if the peer is known to be
+    /// older, downgrades it to the peer's version.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - The envelope to send, at the local version.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the envelope to send, or the error of a migration.
+    pub fn prepare_outgoing(&self, envelope: Envelope) -> Result<Envelope, String> {
+        let target = self.peer_version().map_or(self.current, |peer| peer.min(self.current));
+        self.migrate(envelope, self.current, target)
+    }
+
+    /// Migrates `envelope` from version `from` to version `to`, one version at a time.
+    fn migrate(&self, mut envelope: Envelope, from: u32, to: u32) -> Result<Envelope, String> {
+        let mut version = from;
+        while version != to {
+            let (migrations, next) = if version < to {
+                (&self.upgrades, version + 1)
+            } else {
+                (&self.downgrades, version - 1)
+            };
+            if let Some(migration) = migrations.get(&(envelope.kind.clone(), version)) {
+                debug!("Migrating {} envelope from version {} to {}", envelope.kind, version, next);
+                let kind = envelope.kind.clone();
+                envelope = migration(envelope)
+                    .map_err(|e| format!("Failed to migrate {} envelope from version {} to {}: {}", kind, version, next, e))?;
+            }
+            version = next;
+        }
+        envelope.version = Some(to);
+        Ok(envelope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Appends `suffix` to the payload.
+    fn append(suffix: &'static str) -> impl Fn(Envelope) -> Result<Envelope, String> {
+        move |mut envelope| {
+            envelope.payload.extend_from_slice(suffix.as_bytes());
+            Ok(envelope)
+        }
+    }
+
+    /// Tests chained upgrades across a kind rename, downgrades for an older peer, and steps
+    /// without migrations.
+    #[test]
+    fn test_migrations() {
+        let migrations = SchemaMigrations::new(3)
+            .with_upgrade("temp", 0, |mut envelope| {
+                envelope.kind = "reading".to_string();
+                Ok(envelope)
+            })
+            .with_upgrade("reading", 2, append("+v3"))
+            .with_downgrade("reading", 3, |mut envelope| {
+                envelope.payload.truncate(envelope.payload.len() - 3);
+                Ok(envelope)
+            })
+            .with_upgrade("broken", 1, |_| Err("bad payload".to_string()));
+
+        let unversioned = migrations.upgrade(Envelope::new("temp", b"21".to_vec())).unwrap();
+        assert_eq!(unversioned, Envelope::new("reading", b"21+v3".to_vec()).with_version(3));
+        assert_eq!(migrations.peer_version(), Some(0));
+
+        migrations.set_peer_version(2);
+        let outgoing = migrations.prepare_outgoing(Envelope::new("reading", b"22+v3".to_vec())).unwrap();
+        assert_eq!(outgoing, Envelope::new("reading", b"22".to_vec()).with_version(2));
+        let untouched = migrations.prepare_outgoing(Envelope::new("chat", b"hi".to_vec())).unwrap();
+        assert_eq!(untouched.version, Some(2));
+
+        // A newer peer's envelopes pass through when no downgrade is registered.
+        let newer = migrations.upgrade(Envelope::new("chat", b"hi".to_vec()).with_version(5)).unwrap();
+        assert_eq!(newer, Envelope::new("chat", b"hi".to_vec()).with_version(3));
+        let outgoing = migrations.prepare_outgoing(Envelope::new("chat", b"hi".to_vec())).unwrap();
+        assert_eq!(outgoing.version, Some(3));
+
+        let error = migrations.upgrade(Envelope::new("broken", Vec::new()).with_version(1)).unwrap_err();
+        assert!(error.contains("from version 1 to 2: bad payload"), "Unexpected error: {}", error);
+    }
+}
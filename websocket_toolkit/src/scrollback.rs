@@ -0,0 +1,273 @@
+//! # `scrollback.rs`: Bounded per-topic history of inbound messages
+//!
+//! A subscriber of a `Fanout` sees only what is published after it subscribes. A chat view
+//! opened mid-conversation or a dashboard that joins late wants the recent past too, and
+//! fetching it from the server is a round trip the client already paid for. `Scrollback`
+//! keeps the most recent inbound messages per topic, bounded by count and by payload bytes,
+//! and answers `history(topic, n)` from memory.
+//!
+//! A message's topic is whatever the extractor given to `Scrollback::new` returns; the
+//! envelope `type` (`Scrollback::by_kind`) suits most protocols. Messages without a topic are
+//! not kept. The number of topics is bounded as well: when a new topic would exceed
+//! `max_topics`, the topic updated least recently is dropped.
+//!
+//! Set on a fan-out with `Fanout::set_scrollback`, every published message is recorded, and
+//! `Fanout::subscribe_with_history` returns the history together with a subscription that
+//! continues exactly after it, without gaps or duplicates.
+
+use crate::messages::{Envelope, InboundMessage, MessageFormat};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Returns the topic a message is kept under, or `None` to not keep it.
+pub type TopicFn = Arc<dyn Fn(&InboundMessage) -> Option<String> + Send + Sync>;
+
+/// How much a `Scrollback` keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScrollbackLimits {
+    /// The most messages kept per topic, or `None` for no count limit.
+    pub max_messages: Option<usize>,
+    /// The most payload bytes kept per topic, or `None` for no byte limit.
+    pub max_bytes: Option<usize>,
+    /// The most topics kept, or `None` for no limit.
+    pub max_topics: Option<usize>,
+}
+
+impl Default for ScrollbackLimits {
+    /// Keeps up to 100 messages and 1 MiB per topic, for up to 1024 topics.
+    fn default() -> Self {
+        ScrollbackLimits {
+            max_messages: Some(100),
+            max_bytes: Some(1024 * 1024),
+            max_topics: Some(1024),
+        }
+    }
+}
+
+/// The retained messages of one topic.
+#[derive(Debug, Default)]
+struct Topic {
+    messages: VecDeque<InboundMessage>,
+    /// The payload bytes of `messages`.
+    bytes: usize,
+    /// The recording sequence number of the last update, for evicting stale topics.
+    updated: u64,
+}
+
+/// The topics, behind the scrollback's lock.
+#[derive(Debug, Default)]
+struct Topics {
+    topics: HashMap<String, Topic>,
+    sequence: u64,
+}
+
+/// Recent inbound messages per topic, bounded in count and bytes.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::messages::InboundMessage;
+/// use websocket_toolkit::scrollback::{Scrollback, ScrollbackLimits};
+///
+/// let limits = ScrollbackLimits { max_messages: Some(2), ..Default::default() };
+/// let scrollback = Scrollback::new(limits, |message| {
+///     let text = std::str::from_utf8(message.as_bytes()).ok()?;
+///     text.split_once(':').map(|(room, _)| room.to_string())
+/// });
+/// for line in ["lobby:hi", "lobby:hello", "lobby:bye", "ops:deploy"] {
+///     scrollback.record(&InboundMessage::Text(line.into()));
+/// }
+/// assert_eq!(
+///     scrollback.history("lobby", 10),
+///     vec![InboundMessage::Text("lobby:hello".into()), InboundMessage::Text("lobby:bye".into())]
+/// );
+/// ```
+pub struct Scrollback {
+    limits: ScrollbackLimits,
+    topic: TopicFn,
+    topics: Mutex<Topics>,
+}
+
+impl Scrollback {
+    /// Creates an empty scrollback.
+    ///
+    /// # Arguments
+    ///
+    /// * `limits` - How much is kept.
+    /// * `topic` - Returns the topic of a message, or `None` to not keep it.
+    ///
+    /// # Returns
+    ///
+    /// A new `Scrollback`.
+    pub fn new<F>(limits: ScrollbackLimits, topic: F) -> Self
+    where
+        F: Fn(&InboundMessage) -> Option<String> + Send + Sync + 'static,
+    {
+        Scrollback {
+            limits,
+            topic: Arc::new(topic),
+            topics: Mutex::new(Topics::default()),
+        }
+    }
+
+    /// Creates an empty scrollback keeping envelopes under their `type`.
+    ///
+    /// # Arguments
+    ///
+    /// * `limits` - How much is kept.
+    /// * `format` - The format envelopes are serialized in; other messages are not kept.
+    ///
+    /// # Returns
+    ///
+    /// A new `Scrollback`.
+    pub fn by_kind(limits: ScrollbackLimits, format: MessageFormat) -> Self {
+        Scrollback::new(limits, move |message| message.decode::<Envelope>(format).ok().map(|envelope| envelope.kind))
+    }
+
+    /// Keeps `message` under its topic, evicting the oldest messages beyond the limits.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The inbound message.
+    ///
+    /// # Returns
+    ///
+    /// Whether the message was kept; it is not if it has no topic or alone exceeds
+    /// `max_bytes`.
+    pub fn record(&self, message: &InboundMessage) -> bool {
+        let mut topics = self.topics.lock().unwrap();
+        self.record_locked(&mut topics, message)
+    }
+
+    /// Returns the last `n` messages of `topic`, oldest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `topic` - The topic.
+    /// * `n` - The most messages returned.
+    pub fn history(&self, topic: &str, n: usize) -> Vec<InboundMessage> {
+        Self::history_locked(&self.topics.lock().unwrap(), topic, n)
+    }
+
+    /// Returns the topics with kept messages.
+    pub fn topics(&self) -> Vec<String> {
+        self.topics.lock().unwrap().topics.keys().cloned().collect()
+    }
+
+    /// Forgets the messages of `topic`.
+    ///
+    /// # Arguments
+    ///
+    /// * `topic` - The topic.
+    pub fn clear(&self, topic: &str) {
+        self.topics.lock().unwrap().topics.remove(topic);
+    }
+
+    /// Records `message` and runs `publish` under the lock, so a concurrent
+    /// `snapshot_then` sees the message either in the history or on its subscription.
+    pub(crate) fn record_then<R>(&self, message: &InboundMessage, publish: impl FnOnce() -> R) -> R {
+        let mut topics = self.topics.lock().unwrap();
+        self.record_locked(&mut topics, message);
+        publish()
+    }
+
+    /// Returns the last `n` messages of `topic` and runs `subscribe` under the lock.
+    pub(crate) fn snapshot_then<R>(&self, topic: &str, n: usize, subscribe: impl FnOnce() -> R) -> (Vec<InboundMessage>, R) {
+        let topics = self.topics.lock().unwrap();
+        (Self::history_locked(&topics, topic, n), subscribe())
+    }
+
+    fn record_locked(&self, topics: &mut Topics, message: &InboundMessage) -> bool {
+        let len = message.as_bytes().len();
+        if self.limits.max_bytes.is_some_and(|max| len > max) || self.limits.max_messages == Some(0) {
+            return false;
+        }
+        let Some(name) = (self.topic)(message) else {
+            return false;
+        };
+        topics.sequence += 1;
+        let sequence = topics.sequence;
+        if !topics.topics.contains_key(&name) && self.limits.max_topics.is_some_and(|max| topics.topics.len() >= max) {
+            let stale = topics.topics.iter().min_by_key(|(_, topic)| topic.updated).map(|(name, _)| name.clone());
+            match stale {
+                Some(stale) => {
+                    topics.topics.remove(&stale);
+                }
+                // `max_topics` is 0.
+                None => return false,
+            }
+        }
+        let topic = topics.topics.entry(name).or_default();
+        topic.updated = sequence;
+        topic.bytes += len;
+        topic.messages.push_back(message.clone());
+        while self.limits.max_messages.is_some_and(|max| topic.messages.len() > max)
+            || self.limits.max_bytes.is_some_and(|max| topic.bytes > max)
+        {
+            if let Some(evicted) = topic.messages.pop_front() {
+                topic.bytes -= evicted.as_bytes().len();
+            }
+        }
+        true
+    }
+
+    fn history_locked(topics: &Topics, topic: &str, n: usize) -> Vec<InboundMessage> {
+        match topics.topics.get(topic) {
+            Some(topic) => topic.messages.iter().skip(topic.messages.len().saturating_sub(n)).cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl fmt::Debug for Scrollback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scrollback")
+            .field("limits", &self.limits)
+            .field("topics", &self.topics.lock().unwrap().topics.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room(message: &InboundMessage) -> Option<String> {
+        let text = std::str::from_utf8(message.as_bytes()).ok()?;
+        text.split_once(':').map(|(room, _)| room.to_string())
+    }
+
+    /// Tests the count, byte and topic limits.
+    #[test]
+    fn test_scrollback_limits() {
+        let limits = ScrollbackLimits {
+            max_messages: Some(3),
+            max_bytes: Some(12),
+            max_topics: Some(2),
+        };
+        let scrollback = Scrollback::new(limits, room);
+        let text = |s: &str| InboundMessage::Text(s.to_string());
+
+        assert!(scrollback.record(&text("a:1")));
+        assert!(scrollback.record(&text("a:2")));
+        assert!(scrollback.record(&text("a:3")));
+        assert!(scrollback.record(&text("a:4")));
+        assert_eq!(scrollback.history("a", 10), vec![text("a:2"), text("a:3"), text("a:4")]);
+        assert_eq!(scrollback.history("a", 1), vec![text("a:4")]);
+
+        // 9 + 7 bytes exceed 12, so only the newest message stays.
+        assert!(scrollback.record(&text("b:1234567")));
+        assert!(scrollback.record(&text("b:12345")));
+        assert_eq!(scrollback.history("b", 10), vec![text("b:12345")]);
+        assert!(!scrollback.record(&text("b:too long for it")));
+        assert!(!scrollback.record(&text("no topic")));
+
+        // A third topic evicts `a`, updated least recently.
+        assert!(scrollback.record(&text("c:1")));
+        assert!(scrollback.history("a", 10).is_empty());
+        let mut topics = scrollback.topics();
+        topics.sort();
+        assert_eq!(topics, vec!["b", "c"]);
+    }
+}
@@ -0,0 +1,303 @@
+//! # `session.rs`: Encrypted session persistence
+//!
+//! Long-lived desktop clients should come back after a restart the way a browser does: still
+//! logged in, with the server's cookies, and able to pick up where they left off. `Session`
+//! holds that state: the last URL connected to, an auth token sent as
+//! `Authorization: Bearer`, cookies collected from `Set-Cookie` handshake headers, and
+//! application-defined resume state such as a journal cursor or a list of subscriptions.
+//!
+//! `SessionStore` keeps a `Session` in a single file at a path chosen by the application,
+//! encrypted and authenticated with ChaCha20-Poly1305 under a 32-byte key the application
+//! supplies (from the OS keychain, for example), so tokens are never written in the clear and
+//! a tampered file is rejected instead of loaded. Every change is written atomically.
+//!
+//! Attach a store with `WebSocketController::set_session`: the controller restores the
+//! session's URL, sends its token and cookies with every handshake and records the cookies
+//! and URL of every successful connection. Enabled by the `session` feature.
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The header identifying session files; also authenticated with the contents.
+const MAGIC: &[u8] = b"WSTKSESS1";
+/// The length of a ChaCha20-Poly1305 nonce.
+const NONCE_LEN: usize = 12;
+
+/// The state restored on startup.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    /// The URL of the last successful connection.
+    pub url: Option<String>,
+    /// Sent as `Authorization: Bearer <token>` with every handshake.
+    pub auth_token: Option<String>,
+    /// Cookies by name, sent in a `Cookie` header with every handshake.
+    pub cookies: BTreeMap<String, String>,
+    /// Application-defined resume state, such as the last processed sequence number.
+    pub resume: BTreeMap<String, String>,
+}
+
+impl Session {
+    /// Records a `Set-Cookie` header value, removing the cookie if it is empty or expired
+    /// (`Max-Age` of zero or less). Other attributes are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - The header value, such as `sid=abc; Path=/; HttpOnly`.
+    pub fn apply_set_cookie(&mut self, header: &str) {
+        let mut attributes = header.split(';').map(str::trim);
+        let (name, value) = match attributes.next().and_then(|pair| pair.split_once('=')) {
+            Some((name, value)) if !name.trim().is_empty() => (name.trim(), value.trim()),
+            _ => return,
+        };
+        let expired = attributes.any(|attribute| {
+            attribute
+                .split_once('=')
+                .filter(|(key, _)| key.trim().eq_ignore_ascii_case("max-age"))
+                .is_some_and(|(_, age)| age.trim().parse::<i64>().is_ok_and(|age| age <= 0))
+        });
+        if value.is_empty() || expired {
+            self.cookies.remove(name);
+        } else {
+            self.cookies.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    /// Returns the headers to send with a handshake: `Authorization` and `Cookie`, when set.
+    pub fn headers(&self) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        if let Some(token) = &self.auth_token {
+            headers.push(("Authorization".to_string(), format!("Bearer {}", token)));
+        }
+        if !self.cookies.is_empty() {
+            let cookies: Vec<String> = self.cookies.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+            headers.push(("Cookie".to_string(), cookies.join("; ")));
+        }
+        headers
+    }
+}
+
+/// A 256-bit key for encrypting session files.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SessionKey([u8; 32]);
+
+impl SessionKey {
+    /// Wraps raw key bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The key, which should come from a secure source such as the OS keychain.
+    ///
+    /// # Returns
+    ///
+    /// A new `SessionKey`.
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        SessionKey(bytes)
+    }
+
+    /// Generates a random key.
+    ///
+    /// # Returns
+    ///
+    /// A new `SessionKey` from the operating system's random number generator.
+    pub fn generate() -> Self {
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&ChaCha20Poly1305::generate_key(&mut OsRng));
+        SessionKey(bytes)
+    }
+
+    /// Returns the raw key bytes, for storing the key.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for SessionKey {
+    /// Never prints the key itself.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SessionKey(..)")
+    }
+}
+
+/// A `Session` persisted in an encrypted file.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::session::{SessionKey, SessionStore};
+///
+/// let path = std::env::temp_dir().join(format!("wstk-session-doc-{}", std::process::id()));
+/// let key = SessionKey::generate();
+///
+/// let store = SessionStore::open(&path, key.clone()).unwrap();
+/// store.update(|session| session.auth_token = Some("secret".to_string())).unwrap();
+///
+/// // After a restart:
+/// let restored = SessionStore::open(&path, key).unwrap();
+/// assert_eq!(restored.session().auth_token.as_deref(), Some("secret"));
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct SessionStore {
+    path: PathBuf,
+    key: SessionKey,
+    session: Mutex<Session>,
+}
+
+impl SessionStore {
+    /// Opens the session file at `path`, or starts an empty session if it does not exist yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The session file.
+    /// * `key` - The key the file is encrypted with.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the store, or an error message if the file cannot be read, was
+    /// encrypted with a different key or has been tampered with.
+    pub fn open(path: impl AsRef<Path>, key: SessionKey) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let session = match fs::read(&path) {
+            Ok(file) => decrypt(&key, &file)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Session::default(),
+            Err(e) => return Err(format!("Failed to read session {}: {}", path.display(), e)),
+        };
+        Ok(SessionStore {
+            path,
+            key,
+            session: Mutex::new(session),
+        })
+    }
+
+    /// Returns a copy of the current session.
+    pub fn session(&self) -> Session {
+        self.session.lock().unwrap().clone()
+    }
+
+    /// Changes the session and saves it.
+    ///
+    /// # Arguments
+    ///
+    /// * `change` - Applied to the session before it is saved.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if the file cannot be written.
+    pub fn update(&self, change: impl FnOnce(&mut Session)) -> Result<(), String> {
+        let mut session = self.session.lock().unwrap();
+        change(&mut session);
+        self.write(&session)
+    }
+
+    /// Forgets the session, e.g. on logout, and deletes its file.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if the file cannot be deleted.
+    pub fn clear(&self) -> Result<(), String> {
+        *self.session.lock().unwrap() = Session::default();
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("Failed to delete session {}: {}", self.path.display(), e)),
+        }
+    }
+
+    /// Encrypts `session` and replaces the file atomically.
+    fn write(&self, session: &Session) -> Result<(), String> {
+        let write_error = |e: std::io::Error| format!("Failed to write session {}: {}", self.path.display(), e);
+        let encrypted = encrypt(&self.key, session)?;
+        let temp_path = self.path.with_extension("tmp");
+        let mut file = fs::File::create(&temp_path).map_err(write_error)?;
+        file.write_all(&encrypted).map_err(write_error)?;
+        file.sync_all().map_err(write_error)?;
+        fs::rename(&temp_path, &self.path).map_err(write_error)
+    }
+}
+
+/// Serializes and encrypts `session` as `MAGIC`, a random nonce and the ciphertext.
+fn encrypt(key: &SessionKey, session: &Session) -> Result<Vec<u8>, String> {
+    let plaintext = serde_json::to_vec(session).map_err(|e| format!("Failed to serialize session: {}", e))?;
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key.0))
+        .encrypt(&nonce, Payload { msg: &plaintext, aad: MAGIC })
+        .map_err(|_| "Failed to encrypt session".to_string())?;
+    let mut file = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+    file.extend_from_slice(MAGIC);
+    file.extend_from_slice(&nonce);
+    file.extend_from_slice(&ciphertext);
+    Ok(file)
+}
+
+/// Decrypts and deserializes a file written by `encrypt`.
+fn decrypt(key: &SessionKey, file: &[u8]) -> Result<Session, String> {
+    let body = file
+        .strip_prefix(MAGIC)
+        .filter(|body| body.len() >= NONCE_LEN)
+        .ok_or("Failed to load session: not a session file")?;
+    let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+    let plaintext = ChaCha20Poly1305::new(Key::from_slice(&key.0))
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: MAGIC })
+        .map_err(|_| "Failed to load session: wrong key or corrupted file".to_string())?;
+    serde_json::from_slice(&plaintext).map_err(|e| format!("Failed to load session: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that a session survives reopening, is unreadable without its key and is not
+    /// stored in the clear.
+    #[test]
+    fn test_session_round_trip() {
+        let path = std::env::temp_dir().join(format!("wstk-session-test-{}", std::process::id()));
+        let key = SessionKey::generate();
+        let store = SessionStore::open(&path, key.clone()).unwrap();
+        assert_eq!(store.session(), Session::default());
+        store
+            .update(|session| {
+                session.auth_token = Some("token-123".to_string());
+                session.apply_set_cookie("sid=abc; Path=/; HttpOnly");
+                session.resume.insert("cursor".to_string(), "42".to_string());
+            })
+            .unwrap();
+
+        let restored = SessionStore::open(&path, key).unwrap().session();
+        assert_eq!(restored, store.session());
+        assert!(!String::from_utf8_lossy(&fs::read(&path).unwrap()).contains("token-123"));
+        let error = SessionStore::open(&path, SessionKey::generate()).unwrap_err();
+        assert!(error.contains("wrong key"), "Unexpected error: {}", error);
+
+        store.clear().unwrap();
+        assert!(!path.exists());
+    }
+
+    /// Tests cookie updates, removal and the resulting handshake headers.
+    #[test]
+    fn test_session_headers() {
+        let mut session = Session {
+            auth_token: Some("t".to_string()),
+            ..Session::default()
+        };
+        session.apply_set_cookie("a=1");
+        session.apply_set_cookie("b=2; Max-Age=3600");
+        session.apply_set_cookie("a=; Path=/");
+        session.apply_set_cookie("c=3");
+        session.apply_set_cookie("c=3; max-age=0");
+        assert_eq!(
+            session.headers(),
+            vec![
+                ("Authorization".to_string(), "Bearer t".to_string()),
+                ("Cookie".to_string(), "b=2".to_string()),
+            ]
+        );
+    }
+}
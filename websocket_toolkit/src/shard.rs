@@ -0,0 +1,260 @@
+//! # `shard.rs`: Keyed sharding across a pool of connections
+//!
+//! A single connection serializes every message through one writer, which caps throughput
+//! for publishers of keyed data (per-symbol quotes, per-user events). Spreading messages over
+//! several connections at random would raise throughput but reorder messages for the same
+//! key. `ShardedPool` instead hashes each message's key to one of its connections, so all
+//! messages for a key travel the same connection, in order, while different keys proceed in
+//! parallel.
+//!
+//! Keys are hashed with 64-bit FNV-1a and mapped to a shard with jump consistent hashing
+//! (Lamping and Veach, 2014). Both are deterministic across processes and releases, so
+//! several publishers agree on the shard for a key, and growing the pool from `n` to `n + 1`
+//! connections moves only about `1 / (n + 1)` of the keys.
+
+use crate::controller::WebSocketController;
+use crate::handle::ConnectionHandle;
+use crate::messages::{Envelope, MessageFormat};
+use crate::pipeline::PipelineConfig;
+use log::info;
+use std::error::Error as StdError;
+use tokio_tungstenite::tungstenite::Message;
+
+/// A pool of connections that routes each message by key.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::pipeline::PipelineConfig;
+/// use websocket_toolkit::shard::ShardedPool;
+/// use websocket_toolkit::testing::EchoServer;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let server = EchoServer::start().await.unwrap();
+/// let pool = ShardedPool::connect(&[server.url()], 4, PipelineConfig::default()).await.unwrap();
+///
+/// pool.send("AAPL", "bid=189.1".into()).await.unwrap();
+/// pool.send("AAPL", "bid=189.2".into()).await.unwrap();
+/// let shard = pool.shard("AAPL");
+/// assert_eq!(pool.handle(shard).recv_inbound().await.unwrap().as_bytes(), b"bid=189.1");
+/// pool.shutdown().await.unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ShardedPool {
+    handles: Vec<ConnectionHandle>,
+}
+
+impl ShardedPool {
+    /// Builds a pool from connections opened elsewhere.
+    ///
+    /// # Arguments
+    ///
+    /// * `handles` - The connections, in shard order. The order must be the same wherever
+    ///   keys need to map to the same shard.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the pool, or an error message if `handles` is empty.
+    pub fn new(handles: Vec<ConnectionHandle>) -> Result<Self, String> {
+        if handles.is_empty() {
+            return Err("Failed to create sharded pool: no connections".to_string());
+        }
+        Ok(ShardedPool { handles })
+    }
+
+    /// Opens `connections` pipelined connections, assigning endpoints round-robin.
+    ///
+    /// # Arguments
+    ///
+    /// * `urls` - The endpoints; shard `i` connects to `urls[i % urls.len()]`.
+    /// * `connections` - The number of shards.
+    /// * `config` - The pipeline settings of every connection.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the pool, or an error if there are no endpoints or shards, or a
+    /// connection fails. Connections already opened are shut down on failure.
+    pub async fn connect(
+        urls: &[&str],
+        connections: usize,
+        config: PipelineConfig,
+    ) -> Result<Self, Box<dyn StdError>> {
+        if urls.is_empty() || connections == 0 {
+            return Err("Failed to create sharded pool: no endpoints or shards".into());
+        }
+        let mut handles = Vec::with_capacity(connections);
+        for shard in 0..connections {
+            let url = urls[shard % urls.len()];
+            match WebSocketController::new(url, 0, None).connect_handle(config).await {
+                Ok(handle) => handles.push(handle),
+                Err(e) => {
+                    for handle in &handles {
+                        let _ = handle.shutdown().await;
+                    }
+                    return Err(format!("Failed to connect shard {} to {}: {}", shard, url, e).into());
+                }
+            }
+        }
+        info!("Opened {} shards across {} endpoints", connections, urls.len());
+        Ok(ShardedPool { handles })
+    }
+
+    /// Returns the number of shards.
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// Returns whether the pool has no shards; always `false` for a constructed pool.
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    /// Returns the shard that messages for `key` are sent on.
+    pub fn shard(&self, key: impl AsRef<[u8]>) -> usize {
+        shard_for(key.as_ref(), self.handles.len())
+    }
+
+    /// Returns the connection of shard `index`, for receiving or sending unkeyed messages.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than `len()`.
+    pub fn handle(&self, index: usize) -> &ConnectionHandle {
+        &self.handles[index]
+    }
+
+    /// Returns every connection, in shard order.
+    pub fn handles(&self) -> &[ConnectionHandle] {
+        &self.handles
+    }
+
+    /// Queues `message` on the shard of `key`. Messages sent with the same key are delivered
+    /// in the order they were sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The routing key, such as a symbol or user id.
+    /// * `message` - The frame to send.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if that shard's connection has closed.
+    pub async fn send(&self, key: impl AsRef<[u8]>, message: Message) -> Result<(), String> {
+        self.handles[self.shard(key)].send(message).await
+    }
+
+    /// Encodes `envelope` and queues it on the shard of `key`.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The routing key.
+    /// * `envelope` - The message to send.
+    /// * `format` - The wire format.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message if encoding fails or the shard's
+    /// connection has closed.
+    pub async fn send_envelope(&self, key: impl AsRef<[u8]>, envelope: &Envelope, format: MessageFormat) -> Result<(), String> {
+        self.handles[self.shard(key)].send_envelope(envelope, format).await
+    }
+
+    /// Shuts down every connection.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or the first shutdown error; every shard is shut down
+    /// regardless.
+    pub async fn shutdown(&self) -> Result<(), String> {
+        let mut result = Ok(());
+        for handle in &self.handles {
+            let shutdown = handle.shutdown().await;
+            if result.is_ok() {
+                result = shutdown;
+            }
+        }
+        result
+    }
+}
+
+/// Maps `key` to one of `shards` buckets.
+///
+/// # Arguments
+///
+/// * `key` - The routing key.
+/// * `shards` - The number of buckets; at least 1.
+///
+/// # Returns
+///
+/// The bucket index, stable across processes for the same key and bucket count.
+pub fn shard_for(key: &[u8], shards: usize) -> usize {
+    jump_hash(fnv1a(key), shards.max(1) as i64) as usize
+}
+
+/// Hashes `bytes` with 64-bit FNV-1a.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// Jump consistent hash: maps `key` to a bucket in `0..buckets`.
+fn jump_hash(mut key: u64, buckets: i64) -> i64 {
+    let (mut bucket, mut next) = (-1i64, 0i64);
+    while next < buckets {
+        bucket = next;
+        key = key.wrapping_mul(2_862_933_555_777_941_757).wrapping_add(1);
+        next = ((bucket + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    bucket
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::EchoServer;
+
+    /// Tests that growing the pool only moves keys to the new shard.
+    #[test]
+    fn test_shard_for_is_consistent() {
+        let keys: Vec<String> = (0..1000).map(|i| format!("user-{}", i)).collect();
+        let mut moved = 0;
+        for key in &keys {
+            let before = shard_for(key.as_bytes(), 8);
+            let after = shard_for(key.as_bytes(), 9);
+            assert!(before < 8 && after < 9);
+            if before != after {
+                assert_eq!(after, 8, "Key {} moved between existing shards", key);
+                moved += 1;
+            }
+        }
+        assert!(moved > 50 && moved < 200, "Unexpected number of moved keys: {}", moved);
+        assert_eq!(shard_for(b"anything", 1), 0);
+    }
+
+    /// Tests that each key's messages arrive in order on its own shard.
+    #[tokio::test]
+    async fn test_sharded_pool_preserves_key_order() {
+        let server = EchoServer::start().await.expect("Failed to start echo server");
+        let pool = ShardedPool::connect(&[server.url()], 3, PipelineConfig::default()).await.unwrap();
+        let keys = ["AAPL", "MSFT", "GOOG", "AMZN", "TSLA"];
+        for i in 0..20u8 {
+            for key in keys {
+                pool.send(key, Message::Binary([key.as_bytes(), &[i]].concat())).await.unwrap();
+            }
+        }
+
+        for (shard, handle) in pool.handles().iter().enumerate() {
+            let shard_keys: Vec<&str> = keys.iter().copied().filter(|key| pool.shard(key) == shard).collect();
+            let mut next = vec![0u8; shard_keys.len()];
+            for _ in 0..shard_keys.len() * 20 {
+                let payload = handle.recv_inbound().await.unwrap().into_bytes();
+                let (key, i) = payload.split_at(4);
+                let position = shard_keys.iter().position(|k| k.as_bytes() == key).expect("Key on the wrong shard");
+                assert_eq!(i, [next[position]]);
+                next[position] += 1;
+            }
+        }
+        assert_eq!(pool.shutdown().await, Ok(()));
+    }
+}
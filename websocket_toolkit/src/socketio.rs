@@ -0,0 +1,940 @@
+//! # `socketio.rs`: Socket.IO client
+//!
+//! Many Node.js servers only speak Socket.IO, which layers its own protocol over WebSocket
+//! frames. `SocketIoClient` implements Socket.IO v5 over Engine.IO v4 with the `websocket`
+//! transport only (no HTTP long-polling):
+//!
+//! - `engine_io_url` turns a server URL into the Engine.IO endpoint
+//!   (`/socket.io/?EIO=4&transport=websocket`); build the `WebSocketController` with it.
+//! - `connect` opens a connection with the controller, waits for the Engine.IO `open` packet
+//!   and connects the default namespace `/`. `of` connects further namespaces, each with its
+//!   own optional `auth` payload.
+//! - `SocketIoNamespace::emit` sends an event; `emit_with_ack` also waits for the server's
+//!   acknowledgement. `on` returns a `Stream` of the events of one name, whose `ack` answers
+//!   an event the server expects acknowledged.
+//! - The server's Engine.IO pings are answered with pongs. When no ping arrives within
+//!   `pingInterval + pingTimeout`, or the connection drops, the client reconnects with the
+//!   controller's reconnection strategy (`set_reconnect_strategy`, or its retries and
+//!   backoff) and connects every namespace again. Acknowledgements pending at the drop fail
+//!   with `SocketIoError::Disconnected`. Reconnecting requires the `reconnection` feature.
+//!
+//! Binary events and acknowledgements (packet types 5 and 6) are not supported; they are
+//! logged and dropped.
+
+use crate::controller::WebSocketController;
+use crate::handle::ConnectionHandle;
+use crate::pipeline::PipelineConfig;
+#[cfg(feature = "reconnection")]
+use crate::reconnection::RetryError;
+use crate::tasks::spawn_named;
+use futures_util::stream::Stream;
+use log::{debug, info, warn};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite::Message;
+use url::Url;
+
+/// The default namespace.
+pub const DEFAULT_NAMESPACE: &str = "/";
+
+/// How long `connect` and `of` wait for the server's answer.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many events of one listener are buffered before new ones are dropped.
+const EVENT_CAPACITY: usize = 64;
+
+/// Builds the Engine.IO WebSocket endpoint of a Socket.IO server.
+///
+/// # Arguments
+///
+/// * `server` - The server URL, with an `http`, `https`, `ws` or `wss` scheme. A path other
+///   than `/` replaces the default `/socket.io/`.
+///
+/// # Returns
+///
+/// A `Result` containing the `ws` or `wss` URL, or an error message if `server` is not a
+/// valid URL.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::socketio::engine_io_url;
+///
+/// assert_eq!(
+///     engine_io_url("https://chat.example.com").unwrap(),
+///     "wss://chat.example.com/socket.io/?EIO=4&transport=websocket"
+/// );
+/// ```
+pub fn engine_io_url(server: &str) -> Result<String, String> {
+    let mut url = Url::parse(server).map_err(|e| format!("Invalid Socket.IO URL {}: {}", server, e))?;
+    let scheme = match url.scheme() {
+        "http" | "ws" => "ws",
+        "https" | "wss" => "wss",
+        other => return Err(format!("Invalid Socket.IO URL {}: unsupported scheme {}", server, other)),
+    };
+    url.set_scheme(scheme).map_err(|_| format!("Invalid Socket.IO URL {}", server))?;
+    if url.path() == "/" || url.path().is_empty() {
+        url.set_path("/socket.io/");
+    }
+    url.query_pairs_mut().append_pair("EIO", "4").append_pair("transport", "websocket");
+    Ok(url.to_string())
+}
+
+/// The type of a Socket.IO packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    /// Connects a namespace, or confirms the connection.
+    Connect,
+    /// Disconnects a namespace.
+    Disconnect,
+    /// An event: an array of the event name and its arguments.
+    Event,
+    /// The acknowledgement of an event: an array of arguments.
+    Ack,
+    /// The server refused to connect a namespace.
+    ConnectError,
+    /// An event with binary attachments.
+    BinaryEvent,
+    /// An acknowledgement with binary attachments.
+    BinaryAck,
+}
+
+impl PacketType {
+    /// Returns the type's digit on the wire.
+    fn code(self) -> char {
+        match self {
+            PacketType::Connect => '0',
+            PacketType::Disconnect => '1',
+            PacketType::Event => '2',
+            PacketType::Ack => '3',
+            PacketType::ConnectError => '4',
+            PacketType::BinaryEvent => '5',
+            PacketType::BinaryAck => '6',
+        }
+    }
+
+    /// Parses the type's digit.
+    fn from_code(code: char) -> Option<Self> {
+        Some(match code {
+            '0' => PacketType::Connect,
+            '1' => PacketType::Disconnect,
+            '2' => PacketType::Event,
+            '3' => PacketType::Ack,
+            '4' => PacketType::ConnectError,
+            '5' => PacketType::BinaryEvent,
+            '6' => PacketType::BinaryAck,
+            _ => return None,
+        })
+    }
+}
+
+/// A Socket.IO packet, as carried in an Engine.IO `message`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    /// The packet type.
+    pub kind: PacketType,
+    /// The namespace, `/` by default.
+    pub namespace: String,
+    /// The acknowledgement id, for events that expect one and their acknowledgements.
+    pub id: Option<u64>,
+    /// The JSON data, if any.
+    pub data: Option<Value>,
+}
+
+impl Packet {
+    /// Creates a packet without acknowledgement id.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The packet type.
+    /// * `namespace` - The namespace.
+    /// * `data` - The JSON data, if any.
+    ///
+    /// # Returns
+    ///
+    /// A new `Packet`.
+    pub fn new(kind: PacketType, namespace: impl Into<String>, data: Option<Value>) -> Self {
+        Packet { kind, namespace: namespace.into(), id: None, data }
+    }
+
+    /// Encodes the packet in the Socket.IO text format, without the Engine.IO prefix.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use serde_json::json;
+    /// use websocket_toolkit::socketio::{Packet, PacketType};
+    ///
+    /// let mut packet = Packet::new(PacketType::Event, "/chat", Some(json!(["message", "hi"])));
+    /// packet.id = Some(7);
+    /// assert_eq!(packet.encode(), r#"2/chat,7["message","hi"]"#);
+    /// assert_eq!(Packet::decode(&packet.encode()).unwrap(), packet);
+    /// ```
+    pub fn encode(&self) -> String {
+        let mut text = String::new();
+        text.push(self.kind.code());
+        if self.namespace != DEFAULT_NAMESPACE {
+            text.push_str(&self.namespace);
+            text.push(',');
+        }
+        if let Some(id) = self.id {
+            text.push_str(&id.to_string());
+        }
+        if let Some(data) = &self.data {
+            text.push_str(&data.to_string());
+        }
+        text
+    }
+
+    /// Decodes a packet from the Socket.IO text format.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The packet, without the Engine.IO prefix.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the packet, or an error message if `text` is malformed. The
+    /// attachment count of binary packets is skipped.
+    pub fn decode(text: &str) -> Result<Self, String> {
+        let mut chars = text.chars();
+        let kind = chars
+            .next()
+            .and_then(PacketType::from_code)
+            .ok_or_else(|| format!("Invalid Socket.IO packet type in {:?}", text))?;
+        let mut rest = chars.as_str();
+        if matches!(kind, PacketType::BinaryEvent | PacketType::BinaryAck) {
+            let dash = rest.find('-').ok_or_else(|| format!("Missing attachment count in {:?}", text))?;
+            rest = &rest[dash + 1..];
+        }
+        let mut namespace = DEFAULT_NAMESPACE.to_string();
+        if rest.starts_with('/') {
+            let end = rest.find(',').unwrap_or(rest.len());
+            namespace = rest[..end].to_string();
+            rest = rest.get(end + 1..).unwrap_or("");
+        }
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let id = match digits {
+            0 => None,
+            _ => Some(rest[..digits].parse().map_err(|e| format!("Invalid acknowledgement id in {:?}: {}", text, e))?),
+        };
+        rest = &rest[digits..];
+        let data = match rest {
+            "" => None,
+            json => Some(serde_json::from_str(json).map_err(|e| format!("Invalid Socket.IO data in {:?}: {}", text, e))?),
+        };
+        Ok(Packet { kind, namespace, id, data })
+    }
+
+    /// Frames the packet as an Engine.IO `message`.
+    fn to_frame(&self) -> Message {
+        Message::Text(format!("4{}", self.encode()))
+    }
+}
+
+/// The Engine.IO `open` packet.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OpenPacket {
+    sid: String,
+    ping_interval: u64,
+    ping_timeout: u64,
+}
+
+/// Why a Socket.IO operation failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SocketIoError {
+    /// The connection could not be opened.
+    Connect(String),
+    /// The Engine.IO handshake failed.
+    Handshake(String),
+    /// The server refused to connect the namespace, with the data of its `CONNECT_ERROR`.
+    Rejected(Value),
+    /// No answer arrived in time.
+    Timeout(Duration),
+    /// The connection or the namespace is not connected.
+    Disconnected,
+    /// Event arguments could not be encoded or decoded.
+    Serialization(String),
+}
+
+impl fmt::Display for SocketIoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SocketIoError::Connect(e) => write!(f, "failed to connect: {}", e),
+            SocketIoError::Handshake(e) => write!(f, "Engine.IO handshake failed: {}", e),
+            SocketIoError::Rejected(data) => write!(f, "namespace connection refused: {}", data),
+            SocketIoError::Timeout(timeout) => write!(f, "no answer within {:?}", timeout),
+            SocketIoError::Disconnected => write!(f, "disconnected"),
+            SocketIoError::Serialization(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl StdError for SocketIoError {}
+
+/// A received event, from `SocketIoNamespace::on`.
+#[derive(Debug)]
+pub struct SocketIoEvent {
+    /// The event arguments.
+    pub args: Vec<Value>,
+    ack: Option<(u64, String, Weak<Shared>)>,
+}
+
+impl SocketIoEvent {
+    /// Returns whether the server expects an acknowledgement.
+    pub fn wants_ack(&self) -> bool {
+        self.ack.is_some()
+    }
+
+    /// Acknowledges the event.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - The arguments of the acknowledgement.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the acknowledgement was queued, or why not. Acknowledging an
+    /// event the server does not expect an acknowledgement for does nothing.
+    pub async fn ack(mut self, args: Vec<Value>) -> Result<(), SocketIoError> {
+        let Some((id, namespace, shared)) = self.ack.take() else { return Ok(()) };
+        let shared = shared.upgrade().ok_or(SocketIoError::Disconnected)?;
+        let packet = Packet { kind: PacketType::Ack, namespace, id: Some(id), data: Some(Value::Array(args)) };
+        shared.send(&packet).await
+    }
+}
+
+/// The events of one name in one namespace, from `SocketIoNamespace::on`.
+///
+/// Ends when the namespace is disconnected by the server or the client gives up
+/// reconnecting.
+#[derive(Debug)]
+pub struct SocketIoEvents {
+    events: mpsc::Receiver<SocketIoEvent>,
+}
+
+impl Stream for SocketIoEvents {
+    type Item = SocketIoEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+/// A connected namespace.
+#[derive(Default)]
+struct NamespaceState {
+    /// The `auth` payload sent with every `CONNECT`.
+    auth: Option<Value>,
+    connected: bool,
+    /// The waiter of the pending `of` call.
+    waiter: Option<oneshot::Sender<Result<(), SocketIoError>>>,
+    /// The listeners by event name.
+    listeners: HashMap<String, Vec<mpsc::Sender<SocketIoEvent>>>,
+}
+
+/// State shared with the session task, namespaces and events.
+struct Shared {
+    handle: Mutex<Option<ConnectionHandle>>,
+    namespaces: Mutex<HashMap<String, NamespaceState>>,
+    acks: Mutex<HashMap<u64, oneshot::Sender<Vec<Value>>>>,
+    next_id: AtomicU64,
+    /// The Engine.IO session id of the current connection.
+    sid: Mutex<Option<String>>,
+    connect_timeout: Duration,
+}
+
+impl Shared {
+    /// Queues `packet` on the current connection.
+    async fn send(&self, packet: &Packet) -> Result<(), SocketIoError> {
+        let handle = self.handle.lock().unwrap().clone().ok_or(SocketIoError::Disconnected)?;
+        handle.send(packet.to_frame()).await.map_err(|_| SocketIoError::Disconnected)
+    }
+
+    /// Sends a `CONNECT` for `namespace` with its auth payload.
+    async fn connect_namespace(&self, namespace: &str) -> Result<(), SocketIoError> {
+        let auth = self.namespaces.lock().unwrap().get(namespace).and_then(|state| state.auth.clone());
+        self.send(&Packet::new(PacketType::Connect, namespace, auth)).await
+    }
+
+    /// Marks every namespace disconnected and fails the pending acknowledgements.
+    fn disconnected(&self) {
+        self.handle.lock().unwrap().take();
+        self.acks.lock().unwrap().clear();
+        for state in self.namespaces.lock().unwrap().values_mut() {
+            state.connected = false;
+        }
+    }
+}
+
+impl fmt::Debug for Shared {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shared").field("sid", &self.sid.lock().unwrap()).finish()
+    }
+}
+
+/// A Socket.IO session. Clones share the connection and namespaces.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use futures_util::StreamExt;
+/// use serde_json::json;
+/// use std::sync::Arc;
+/// use websocket_toolkit::controller::WebSocketController;
+/// use websocket_toolkit::pipeline::PipelineConfig;
+/// use websocket_toolkit::socketio::{engine_io_url, SocketIoClient};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let url = engine_io_url("https://chat.example.com")?;
+/// let controller = Arc::new(WebSocketController::new(&url, 5, None));
+/// let client = SocketIoClient::connect(controller, PipelineConfig::default(), None).await?;
+///
+/// let chat = client.of("/chat", Some(json!({ "token": "secret" }))).await?;
+/// let mut messages = chat.on("message");
+/// let reply = chat.emit_with_ack("join", vec![json!("lobby")]).await?;
+/// println!("joined: {:?}", reply);
+/// while let Some(message) = messages.next().await {
+///     println!("{:?}", message.args);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct SocketIoClient {
+    shared: Arc<Shared>,
+}
+
+impl SocketIoClient {
+    /// Opens a connection with `controller`, completes the Engine.IO handshake and connects
+    /// the default namespace. The connection is reopened with the controller's reconnection
+    /// strategy when it drops.
+    ///
+    /// # Arguments
+    ///
+    /// * `controller` - Opens the connections; its URL must be an `engine_io_url`.
+    /// * `config` - The pipeline settings of each connection.
+    /// * `auth` - The `auth` payload of the default namespace, if the server expects one.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the client, or why the connection or namespace failed.
+    pub async fn connect(controller: Arc<WebSocketController>, config: PipelineConfig, auth: Option<Value>) -> Result<Self, SocketIoError> {
+        let (handle, open) = open_session(&controller, &config).await?;
+        let shared = Arc::new(Shared {
+            handle: Mutex::new(Some(handle.clone())),
+            namespaces: Mutex::new(HashMap::new()),
+            acks: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            sid: Mutex::new(Some(open.sid.clone())),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+        });
+        spawn_named(
+            "websocket_toolkit::socketio_session",
+            run_session(controller, config, handle, open, Arc::downgrade(&shared)),
+        );
+        let client = SocketIoClient { shared };
+        if let Err(e) = client.of(DEFAULT_NAMESPACE, auth).await {
+            let _ = client.close().await;
+            return Err(e);
+        }
+        Ok(client)
+    }
+
+    /// Connects a namespace, or returns it if already connected.
+    ///
+    /// # Arguments
+    ///
+    /// * `namespace` - The namespace, such as `/chat`.
+    /// * `auth` - The `auth` payload sent with the `CONNECT`, now and after reconnects.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the namespace, or why the server did not connect it.
+    pub async fn of(&self, namespace: &str, auth: Option<Value>) -> Result<SocketIoNamespace, SocketIoError> {
+        let (waiter, answer) = oneshot::channel();
+        {
+            let mut namespaces = self.shared.namespaces.lock().unwrap();
+            let state = namespaces.entry(namespace.to_string()).or_default();
+            if state.connected {
+                return Ok(self.namespace(namespace));
+            }
+            state.auth = auth;
+            state.waiter = Some(waiter);
+        }
+        let connected = async {
+            self.shared.connect_namespace(namespace).await?;
+            match tokio::time::timeout(self.shared.connect_timeout, answer).await {
+                Ok(Ok(result)) => result,
+                Ok(Err(_)) => Err(SocketIoError::Disconnected),
+                Err(_) => Err(SocketIoError::Timeout(self.shared.connect_timeout)),
+            }
+        };
+        match connected.await {
+            Ok(()) => Ok(self.namespace(namespace)),
+            Err(e) => {
+                self.shared.namespaces.lock().unwrap().remove(namespace);
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the default namespace, connected by `connect`.
+    pub fn socket(&self) -> SocketIoNamespace {
+        self.namespace(DEFAULT_NAMESPACE)
+    }
+
+    /// Returns the Engine.IO session id of the current connection, if connected.
+    pub fn sid(&self) -> Option<String> {
+        self.shared.sid.lock().unwrap().clone()
+    }
+
+    /// Returns whether a connection is open.
+    pub fn is_connected(&self) -> bool {
+        self.shared.handle.lock().unwrap().as_ref().is_some_and(|handle| !handle.is_closed())
+    }
+
+    /// Disconnects every namespace and closes the connection; the client does not reconnect.
+    pub async fn close(&self) -> Result<(), SocketIoError> {
+        let handle = self.shared.handle.lock().unwrap().take();
+        self.shared.namespaces.lock().unwrap().clear();
+        match handle {
+            Some(handle) => handle.close().await.map_err(|_| SocketIoError::Disconnected),
+            None => Ok(()),
+        }
+    }
+
+    fn namespace(&self, namespace: &str) -> SocketIoNamespace {
+        SocketIoNamespace { name: namespace.to_string(), shared: self.shared.clone() }
+    }
+}
+
+/// A namespace of a `SocketIoClient`.
+#[derive(Clone, Debug)]
+pub struct SocketIoNamespace {
+    name: String,
+    shared: Arc<Shared>,
+}
+
+impl SocketIoNamespace {
+    /// Returns the namespace's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns whether the namespace is connected.
+    pub fn is_connected(&self) -> bool {
+        self.shared.namespaces.lock().unwrap().get(&self.name).is_some_and(|state| state.connected)
+    }
+
+    /// Sends an event.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The event name.
+    /// * `args` - The event arguments.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the event was queued, or `SocketIoError::Disconnected`.
+    pub async fn emit(&self, event: &str, args: Vec<Value>) -> Result<(), SocketIoError> {
+        self.shared.send(&self.event_packet(event, args, None)).await
+    }
+
+    /// Sends an event and waits for the server's acknowledgement.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The event name.
+    /// * `args` - The event arguments.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the arguments of the acknowledgement, or why none arrived within
+    /// `DEFAULT_CONNECT_TIMEOUT`.
+    pub async fn emit_with_ack(&self, event: &str, args: Vec<Value>) -> Result<Vec<Value>, SocketIoError> {
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        let (ack_tx, ack) = oneshot::channel();
+        self.shared.acks.lock().unwrap().insert(id, ack_tx);
+        if let Err(e) = self.shared.send(&self.event_packet(event, args, Some(id))).await {
+            self.shared.acks.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+        let timeout = self.shared.connect_timeout;
+        match tokio::time::timeout(timeout, ack).await {
+            Ok(Ok(args)) => Ok(args),
+            Ok(Err(_)) => Err(SocketIoError::Disconnected),
+            Err(_) => {
+                self.shared.acks.lock().unwrap().remove(&id);
+                Err(SocketIoError::Timeout(timeout))
+            }
+        }
+    }
+
+    /// Returns the events of the name `event` sent to this namespace from now on.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The event name.
+    ///
+    /// # Returns
+    ///
+    /// A `Stream` of the events. When it falls 64 events behind, newer ones are dropped.
+    pub fn on(&self, event: &str) -> SocketIoEvents {
+        let (sender, events) = mpsc::channel(EVENT_CAPACITY);
+        let mut namespaces = self.shared.namespaces.lock().unwrap();
+        let state = namespaces.entry(self.name.clone()).or_default();
+        state.listeners.entry(event.to_string()).or_default().push(sender);
+        SocketIoEvents { events }
+    }
+
+    /// Disconnects the namespace; it is not connected again on reconnect.
+    pub async fn disconnect(&self) -> Result<(), SocketIoError> {
+        self.shared.namespaces.lock().unwrap().remove(&self.name);
+        self.shared.send(&Packet::new(PacketType::Disconnect, self.name.clone(), None)).await
+    }
+
+    fn event_packet(&self, event: &str, mut args: Vec<Value>, id: Option<u64>) -> Packet {
+        args.insert(0, Value::String(event.to_string()));
+        Packet { kind: PacketType::Event, namespace: self.name.clone(), id, data: Some(Value::Array(args)) }
+    }
+}
+
+/// Opens a connection and waits for the Engine.IO `open` packet.
+async fn open_session(controller: &WebSocketController, config: &PipelineConfig) -> Result<(ConnectionHandle, OpenPacket), SocketIoError> {
+    let handle = controller
+        .connect_handle(*config)
+        .await
+        .map_err(|e| SocketIoError::Connect(e.to_string()))?;
+    let open = tokio::time::timeout(DEFAULT_CONNECT_TIMEOUT, handle.recv())
+        .await
+        .map_err(|_| SocketIoError::Timeout(DEFAULT_CONNECT_TIMEOUT))?;
+    let open = match open {
+        Some(Message::Text(text)) if text.starts_with('0') => serde_json::from_str::<OpenPacket>(&text[1..])
+            .map_err(|e| SocketIoError::Handshake(format!("invalid open packet: {}", e)))?,
+        Some(other) => return Err(SocketIoError::Handshake(format!("expected an open packet, got {:?}", other))),
+        None => return Err(SocketIoError::Disconnected),
+    };
+    debug!("Engine.IO session {} opened", open.sid);
+    Ok((handle, open))
+}
+
+/// Reads the session's connections, reconnecting until the client is dropped or the
+/// controller's reconnection strategy gives up.
+async fn run_session(
+    controller: Arc<WebSocketController>,
+    config: PipelineConfig,
+    mut handle: ConnectionHandle,
+    mut open: OpenPacket,
+    shared: Weak<Shared>,
+) {
+    loop {
+        read_packets(&handle, &open, &shared).await;
+        handle.abort();
+        let Some(live) = shared.upgrade() else { return };
+        live.disconnected();
+        if live.namespaces.lock().unwrap().is_empty() {
+            // Closed by the client.
+            return;
+        }
+        drop(live);
+
+        let reopened = reopen(&controller, &config, &shared).await;
+        let Some(live) = shared.upgrade() else { return };
+        let Some((reopened_handle, reopened)) = reopened else {
+            // Dropping the listeners ends the event streams.
+            live.namespaces.lock().unwrap().clear();
+            return;
+        };
+        info!("Socket.IO session {} reopened", reopened.sid);
+        (handle, open) = (reopened_handle, reopened);
+        *live.handle.lock().unwrap() = Some(handle.clone());
+        *live.sid.lock().unwrap() = Some(open.sid.clone());
+        let namespaces: Vec<String> = live.namespaces.lock().unwrap().keys().cloned().collect();
+        for namespace in namespaces {
+            if let Err(e) = live.connect_namespace(&namespace).await {
+                warn!("Failed to reconnect Socket.IO namespace {}: {}", namespace, e);
+            }
+        }
+    }
+}
+
+/// Reopens the session with the controller's reconnection strategy, stopping if the client
+/// is dropped.
+#[cfg(feature = "reconnection")]
+async fn reopen(controller: &WebSocketController, config: &PipelineConfig, shared: &Weak<Shared>) -> Option<(ConnectionHandle, OpenPacket)> {
+    let fallback;
+    let strategy = match controller.reconnect_strategy() {
+        Some(strategy) => strategy,
+        None => {
+            fallback = controller.default_reconnect_strategy();
+            &fallback
+        }
+    };
+    let attempt = || async {
+        if shared.strong_count() == 0 {
+            return Err(AttemptError::Dropped);
+        }
+        open_session(controller, config).await.map_err(|e| {
+            warn!("Socket.IO reconnection attempt failed: {}", e);
+            AttemptError::Failed(e.to_string())
+        })
+    };
+    let is_fatal = |e: &AttemptError| matches!(e, AttemptError::Dropped);
+    match strategy.retry(attempt, is_fatal).await {
+        Ok(session) => Some(session),
+        Err(RetryError::Fatal(_)) => None,
+        Err(RetryError::GaveUp(e)) => {
+            warn!("Socket.IO reconnection gave up: {}", e);
+            None
+        }
+    }
+}
+
+/// Without the `reconnection` feature the session is not reopened.
+#[cfg(not(feature = "reconnection"))]
+async fn reopen(_controller: &WebSocketController, _config: &PipelineConfig, _shared: &Weak<Shared>) -> Option<(ConnectionHandle, OpenPacket)> {
+    warn!("Socket.IO connection lost; reconnecting requires the `reconnection` feature");
+    None
+}
+
+/// Why an attempt to reopen the session failed.
+#[cfg(feature = "reconnection")]
+#[derive(Debug)]
+enum AttemptError {
+    /// The client was dropped.
+    Dropped,
+    /// The connection or the handshake failed.
+    Failed(String),
+}
+
+#[cfg(feature = "reconnection")]
+impl fmt::Display for AttemptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttemptError::Dropped => write!(f, "The client was dropped"),
+            AttemptError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Answers pings and dispatches packets until the connection ends or the server stops
+/// pinging.
+async fn read_packets(handle: &ConnectionHandle, open: &OpenPacket, shared: &Weak<Shared>) {
+    let ping_deadline = Duration::from_millis(open.ping_interval + open.ping_timeout);
+    loop {
+        let message = match tokio::time::timeout(ping_deadline, handle.recv()).await {
+            Ok(Some(message)) => message,
+            Ok(None) => return,
+            Err(_) => {
+                warn!("No Engine.IO ping within {:?}; reconnecting", ping_deadline);
+                return;
+            }
+        };
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Binary(_) => {
+                warn!("Dropping a binary Socket.IO attachment, which is not supported");
+                continue;
+            }
+            _ => continue,
+        };
+        match text.chars().next() {
+            Some('2') => {
+                if handle.send(Message::Text(format!("3{}", &text[1..]))).await.is_err() {
+                    return;
+                }
+            }
+            Some('4') => {
+                let Some(shared) = shared.upgrade() else { return };
+                match Packet::decode(&text[1..]) {
+                    Ok(packet) => dispatch(&shared, packet),
+                    Err(e) => warn!("Ignoring a malformed Socket.IO packet: {}", e),
+                }
+            }
+            Some('1') => return,
+            _ => debug!("Ignoring Engine.IO packet {:?}", text),
+        }
+    }
+}
+
+/// Routes a received packet.
+fn dispatch(shared: &Arc<Shared>, packet: Packet) {
+    match packet.kind {
+        PacketType::Connect => {
+            let mut namespaces = shared.namespaces.lock().unwrap();
+            if let Some(state) = namespaces.get_mut(&packet.namespace) {
+                state.connected = true;
+                if let Some(waiter) = state.waiter.take() {
+                    let _ = waiter.send(Ok(()));
+                }
+            }
+        }
+        PacketType::ConnectError => {
+            let mut namespaces = shared.namespaces.lock().unwrap();
+            if let Some(mut state) = namespaces.remove(&packet.namespace) {
+                let rejected = SocketIoError::Rejected(packet.data.unwrap_or(Value::Null));
+                match state.waiter.take() {
+                    Some(waiter) => {
+                        let _ = waiter.send(Err(rejected));
+                    }
+                    None => warn!("Socket.IO namespace {} not reconnected: {}", packet.namespace, rejected),
+                }
+            }
+        }
+        PacketType::Disconnect => {
+            info!("Server disconnected Socket.IO namespace {}", packet.namespace);
+            shared.namespaces.lock().unwrap().remove(&packet.namespace);
+        }
+        PacketType::Event => {
+            let mut args = match packet.data {
+                Some(Value::Array(args)) if matches!(args.first(), Some(Value::String(_))) => args,
+                other => {
+                    warn!("Ignoring a Socket.IO event without a name: {:?}", other);
+                    return;
+                }
+            };
+            let Value::String(event) = args.remove(0) else { return };
+            let mut namespaces = shared.namespaces.lock().unwrap();
+            let listeners = namespaces
+                .get_mut(&packet.namespace)
+                .and_then(|state| state.listeners.get_mut(&event))
+                .filter(|listeners| !listeners.is_empty());
+            let Some(listeners) = listeners else {
+                debug!("No listener for Socket.IO event {} on {}", event, packet.namespace);
+                return;
+            };
+            listeners.retain(|listener| !listener.is_closed());
+            for listener in listeners.iter() {
+                let ack = packet.id.map(|id| (id, packet.namespace.clone(), Arc::downgrade(shared)));
+                if listener.try_send(SocketIoEvent { args: args.clone(), ack }).is_err() {
+                    warn!("Dropping Socket.IO event {}: listener is {} events behind", event, EVENT_CAPACITY);
+                }
+            }
+        }
+        PacketType::Ack => {
+            let Some(id) = packet.id else { return };
+            let args = match packet.data {
+                Some(Value::Array(args)) => args,
+                other => other.into_iter().collect(),
+            };
+            if let Some(ack) = shared.acks.lock().unwrap().remove(&id) {
+                let _ = ack.send(args);
+            }
+        }
+        PacketType::BinaryEvent | PacketType::BinaryAck => {
+            warn!("Dropping a binary Socket.IO packet, which is not supported");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::testing::{MockServer, TestHandle};
+    use futures_util::StreamExt;
+    use serde_json::json;
+    use tokio::net::TcpStream;
+
+    /// Sends an Engine.IO `open` packet from the server.
+    async fn open(connection: &mut TestHandle<TcpStream>, sid: &str) {
+        let open = json!({ "sid": sid, "upgrades": [], "pingInterval": 25000, "pingTimeout": 20000, "maxPayload": 1000000 });
+        connection.send(Message::Text(format!("0{}", open))).await;
+    }
+
+    /// Reads the next text frame the server received.
+    async fn next_text(connection: &mut TestHandle<TcpStream>) -> String {
+        connection.next_message().await.expect("No message received").into_text().unwrap()
+    }
+
+    /// Tests packet encoding and decoding, including namespaces, ids and binary headers.
+    #[test]
+    fn test_packets() {
+        let packet = Packet::decode(r#"2["news",{"x":1}]"#).unwrap();
+        assert_eq!((packet.kind, packet.namespace.as_str(), packet.id), (PacketType::Event, "/", None));
+        assert_eq!(packet.data, Some(json!(["news", { "x": 1 }])));
+        assert_eq!(Packet::decode("0").unwrap(), Packet::new(PacketType::Connect, "/", None));
+        assert_eq!(Packet::decode("1/admin,").unwrap(), Packet::new(PacketType::Disconnect, "/admin", None));
+        let ack = Packet::decode("3/admin,12[\"ok\"]").unwrap();
+        assert_eq!((ack.namespace.as_str(), ack.id), ("/admin", Some(12)));
+        assert_eq!(Packet::decode(r#"51-["upload",{"_placeholder":true,"num":0}]"#).unwrap().kind, PacketType::BinaryEvent);
+        assert!(Packet::decode("9").is_err());
+        assert!(Packet::decode("2[not json").is_err());
+        assert_eq!(engine_io_url("ws://localhost:3000/io").unwrap(), "ws://localhost:3000/io?EIO=4&transport=websocket");
+        assert!(engine_io_url("ftp://example.com").is_err());
+    }
+
+    /// Tests the handshake, namespaces, events and acknowledgements both ways, pings, and
+    /// reconnecting namespaces after the connection drops.
+    #[tokio::test]
+    async fn test_socketio() {
+        let mut server = MockServer::start().await.unwrap();
+        let config = Config { urls: vec![server.url().to_string()], retries: 2, backoff_secs: 0, ..Config::default() };
+        let controller = Arc::new(WebSocketController::from_config(&config).unwrap());
+        let (client, mut connection) = tokio::join!(
+            SocketIoClient::connect(controller, PipelineConfig::default(), Some(json!({ "token": "secret" }))),
+            async {
+                let mut connection = server.accept().await;
+                open(&mut connection, "first").await;
+                assert_eq!(next_text(&mut connection).await, r#"40{"token":"secret"}"#);
+                connection.send(Message::Text(r#"40{"sid":"a"}"#.into())).await;
+                connection
+            }
+        );
+        let client = client.unwrap();
+        assert_eq!(client.sid().as_deref(), Some("first"));
+
+        let (chat, _) = tokio::join!(client.of("/chat", None), async {
+            assert_eq!(next_text(&mut connection).await, "40/chat,");
+            connection.send(Message::Text(r#"40/chat,{"sid":"b"}"#.into())).await;
+        });
+        let chat = chat.unwrap();
+        let (admin, _) = tokio::join!(client.of("/admin", None), async {
+            assert_eq!(next_text(&mut connection).await, "40/admin,");
+            connection.send(Message::Text(r#"44/admin,{"message":"Not authorized"}"#.into())).await;
+        });
+        assert_eq!(admin.unwrap_err(), SocketIoError::Rejected(json!({ "message": "Not authorized" })));
+
+        let (reply, _) = tokio::join!(chat.emit_with_ack("join", vec![json!("lobby")]), async {
+            assert_eq!(next_text(&mut connection).await, r#"42/chat,0["join","lobby"]"#);
+            connection.send(Message::Text(r#"43/chat,0["welcome"]"#.into())).await;
+        });
+        assert_eq!(reply.unwrap(), vec![json!("welcome")]);
+
+        let mut messages = chat.on("message");
+        connection.send(Message::Text(r#"42/chat,5["message","hi"]"#.into())).await;
+        let message = messages.next().await.unwrap();
+        assert_eq!(message.args, vec![json!("hi")]);
+        assert!(message.wants_ack());
+        message.ack(vec![json!(true)]).await.unwrap();
+        assert_eq!(next_text(&mut connection).await, "43/chat,5[true]");
+
+        connection.send(Message::Text("2".into())).await;
+        assert_eq!(next_text(&mut connection).await, "3");
+
+        // The client reconnects and connects its namespaces again.
+        drop(connection);
+        let mut connection = server.accept().await;
+        open(&mut connection, "second").await;
+        let mut reconnects = vec![next_text(&mut connection).await, next_text(&mut connection).await];
+        reconnects.sort();
+        assert_eq!(reconnects, [r#"40/chat,"#, r#"40{"token":"secret"}"#]);
+        connection.send(Message::Text(r#"40/chat,{"sid":"c"}"#.into())).await;
+        connection.send(Message::Text(r#"42/chat,["message","again"]"#.into())).await;
+        assert_eq!(messages.next().await.unwrap().args, vec![json!("again")]);
+        assert_eq!(client.sid().as_deref(), Some("second"));
+        assert!(chat.is_connected());
+    }
+}
@@ -0,0 +1,415 @@
+//! # `storage.rs`: Pluggable persistence backends
+//!
+//! The durable outbox, the dedupe window and the inbound journal keep their state through the
+//! `Storage` trait, a small namespaced key-value interface, so an application can keep that
+//! state wherever it already keeps its own. Namespaces let several components share one
+//! backend; keys and values are raw bytes and namespaces iterate in ascending key order.
+//!
+//! Three backends are provided:
+//!
+//! - `MemoryStorage` keeps everything in memory, for tests and for processes that only need to
+//!   survive reconnects, not restarts.
+//! - `FileStorage` keeps one file per key in one directory per namespace. It has no
+//!   dependencies and suits small amounts of state, such as an IoT gateway's outbox.
+//! - `SledStorage` keeps everything in a sled database. Enabled by the `outbox` feature.
+//!
+//! Other stores, such as Redis or RocksDB, plug in by implementing `Storage`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The key-value pairs of a namespace, in ascending key order.
+pub type Entries = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// The entries of each namespace of a `MemoryStorage`.
+type Namespaces = Mutex<HashMap<String, BTreeMap<Vec<u8>, Vec<u8>>>>;
+
+/// A namespaced key-value store.
+///
+/// Implementations must be safe to share between threads. Writes may be buffered until
+/// `flush`; components call `flush` before reporting a write as durable.
+pub trait Storage: Send + Sync + fmt::Debug {
+    /// Returns the value stored under `key` in `namespace`.
+    ///
+    /// # Arguments
+    ///
+    /// * `namespace` - The namespace to read from.
+    /// * `key` - The key to look up.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the value, `None` if the key is absent, or an error message on failure.
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, String>;
+
+    /// Stores `value` under `key` in `namespace`, replacing any previous value.
+    ///
+    /// # Arguments
+    ///
+    /// * `namespace` - The namespace to write to.
+    /// * `key` - The key to write.
+    /// * `value` - The value to store.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message on failure.
+    fn put(&self, namespace: &str, key: &[u8], value: &[u8]) -> Result<(), String>;
+
+    /// Removes `key` from `namespace`.
+    ///
+    /// # Arguments
+    ///
+    /// * `namespace` - The namespace to remove from.
+    /// * `key` - The key to remove.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing whether the key was present, or an error message on failure.
+    fn delete(&self, namespace: &str, key: &[u8]) -> Result<bool, String>;
+
+    /// Returns every entry in `namespace`, in ascending key order.
+    ///
+    /// # Arguments
+    ///
+    /// * `namespace` - The namespace to read.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the key-value pairs, or an error message on failure.
+    fn iterate(&self, namespace: &str) -> Result<Entries, String>;
+
+    /// Returns the number of entries in `namespace`.
+    ///
+    /// # Arguments
+    ///
+    /// * `namespace` - The namespace to count.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the number of entries, or an error message on failure.
+    fn len(&self, namespace: &str) -> Result<usize, String> {
+        self.iterate(namespace).map(|entries| entries.len())
+    }
+
+    /// Makes every completed write durable.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success, or an error message on failure.
+    fn flush(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// A `Storage` backend that keeps everything in memory.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    namespaces: Namespaces,
+}
+
+impl MemoryStorage {
+    /// Creates an empty in-memory store.
+    ///
+    /// # Returns
+    ///
+    /// A new `MemoryStorage`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        let namespaces = self.namespaces.lock().unwrap();
+        Ok(namespaces.get(namespace).and_then(|entries| entries.get(key)).cloned())
+    }
+
+    fn put(&self, namespace: &str, key: &[u8], value: &[u8]) -> Result<(), String> {
+        let mut namespaces = self.namespaces.lock().unwrap();
+        namespaces
+            .entry(namespace.to_string())
+            .or_default()
+            .insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, key: &[u8]) -> Result<bool, String> {
+        let mut namespaces = self.namespaces.lock().unwrap();
+        Ok(namespaces
+            .get_mut(namespace)
+            .is_some_and(|entries| entries.remove(key).is_some()))
+    }
+
+    fn iterate(&self, namespace: &str) -> Result<Entries, String> {
+        let namespaces = self.namespaces.lock().unwrap();
+        Ok(namespaces
+            .get(namespace)
+            .map(|entries| entries.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+            .unwrap_or_default())
+    }
+
+    fn len(&self, namespace: &str) -> Result<usize, String> {
+        let namespaces = self.namespaces.lock().unwrap();
+        Ok(namespaces.get(namespace).map_or(0, BTreeMap::len))
+    }
+}
+
+/// A `Storage` backend that keeps one file per key under a root directory.
+///
+/// Namespaces and keys are hex-encoded into directory and file names. Every `put` writes a
+/// temporary file, syncs it and renames it over the old value, so a crash leaves either the
+/// old or the new value and `put` is durable once it returns.
+#[derive(Debug)]
+pub struct FileStorage {
+    root: PathBuf,
+    /// Serializes writes so concurrent puts of one key do not share a temporary file.
+    write_lock: Mutex<()>,
+}
+
+impl FileStorage {
+    /// Opens (or creates) the store rooted at `root`, keeping any existing entries.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The directory holding the namespaces.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the store, or an error message if the directory cannot be created.
+    pub fn open(root: impl AsRef<Path>) -> Result<Self, String> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(&root).map_err(|e| format!("Failed to open storage directory: {}", e))?;
+        Ok(FileStorage {
+            root,
+            write_lock: Mutex::new(()),
+        })
+    }
+
+    /// Returns the directory of `namespace`.
+    fn namespace_dir(&self, namespace: &str) -> PathBuf {
+        self.root.join(encode_hex(namespace.as_bytes()))
+    }
+}
+
+impl Storage for FileStorage {
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        match fs::read(self.namespace_dir(namespace).join(encode_hex(key))) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read storage entry: {}", e)),
+        }
+    }
+
+    fn put(&self, namespace: &str, key: &[u8], value: &[u8]) -> Result<(), String> {
+        let storage_error = |e: std::io::Error| format!("Failed to write storage entry: {}", e);
+        let _guard = self.write_lock.lock().unwrap();
+        let dir = self.namespace_dir(namespace);
+        fs::create_dir_all(&dir).map_err(storage_error)?;
+        let name = encode_hex(key);
+        let temp_path = dir.join(format!("{}.tmp", name));
+        let mut file = fs::File::create(&temp_path).map_err(storage_error)?;
+        file.write_all(value).map_err(storage_error)?;
+        file.sync_all().map_err(storage_error)?;
+        fs::rename(&temp_path, dir.join(name)).map_err(storage_error)
+    }
+
+    fn delete(&self, namespace: &str, key: &[u8]) -> Result<bool, String> {
+        match fs::remove_file(self.namespace_dir(namespace).join(encode_hex(key))) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(format!("Failed to delete storage entry: {}", e)),
+        }
+    }
+
+    fn iterate(&self, namespace: &str) -> Result<Entries, String> {
+        let storage_error = |e: std::io::Error| format!("Failed to read storage directory: {}", e);
+        let dir = match fs::read_dir(self.namespace_dir(namespace)) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(storage_error(e)),
+        };
+        let mut entries = Vec::new();
+        for entry in dir {
+            let entry = entry.map_err(storage_error)?;
+            // Leftover temporary files from an interrupted put are not valid hex and are skipped.
+            let key = match entry.file_name().to_str().and_then(decode_hex) {
+                Some(key) => key,
+                None => continue,
+            };
+            match fs::read(entry.path()) {
+                Ok(value) => entries.push((key, value)),
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(format!("Failed to read storage entry: {}", e)),
+            }
+        }
+        entries.sort();
+        Ok(entries)
+    }
+}
+
+/// Encodes `bytes` as lowercase hex, which sorts in the same order as the bytes.
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes lowercase hex produced by `encode_hex`.
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// A `Storage` backend that keeps each namespace in a tree of a sled database.
+///
+/// Enabled by the `outbox` feature.
+#[cfg(feature = "outbox")]
+#[derive(Debug, Clone)]
+pub struct SledStorage {
+    db: sled::Db,
+}
+
+#[cfg(feature = "outbox")]
+impl SledStorage {
+    /// Opens (or creates) the sled database at `path`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory of the sled database.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the store, or an error message if the database cannot be opened.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let db = sled::open(path.as_ref()).map_err(|e| format!("Failed to open sled database: {}", e))?;
+        Ok(SledStorage { db })
+    }
+
+    /// Wraps an already open sled database.
+    ///
+    /// # Arguments
+    ///
+    /// * `db` - The database.
+    ///
+    /// # Returns
+    ///
+    /// A new `SledStorage`.
+    pub fn from_db(db: sled::Db) -> Self {
+        SledStorage { db }
+    }
+
+    /// Opens the tree holding `namespace`.
+    fn tree(&self, namespace: &str) -> Result<sled::Tree, String> {
+        self.db
+            .open_tree(namespace)
+            .map_err(|e| format!("Failed to open sled tree {}: {}", namespace, e))
+    }
+}
+
+#[cfg(feature = "outbox")]
+impl Storage for SledStorage {
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        let value = self
+            .tree(namespace)?
+            .get(key)
+            .map_err(|e| format!("Failed to read sled entry: {}", e))?;
+        Ok(value.map(|value| value.to_vec()))
+    }
+
+    fn put(&self, namespace: &str, key: &[u8], value: &[u8]) -> Result<(), String> {
+        self.tree(namespace)?
+            .insert(key, value)
+            .map_err(|e| format!("Failed to write sled entry: {}", e))?;
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, key: &[u8]) -> Result<bool, String> {
+        let removed = self
+            .tree(namespace)?
+            .remove(key)
+            .map_err(|e| format!("Failed to delete sled entry: {}", e))?;
+        Ok(removed.is_some())
+    }
+
+    fn iterate(&self, namespace: &str) -> Result<Entries, String> {
+        self.tree(namespace)?
+            .iter()
+            .map(|entry| {
+                entry
+                    .map(|(key, value)| (key.to_vec(), value.to_vec()))
+                    .map_err(|e| format!("Failed to read sled entry: {}", e))
+            })
+            .collect()
+    }
+
+    fn len(&self, namespace: &str) -> Result<usize, String> {
+        self.tree(namespace).map(|tree| tree.len())
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        self.db
+            .flush()
+            .map(|_| ())
+            .map_err(|e| format!("Failed to flush sled database: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Exercises the `Storage` contract against `storage`.
+    fn check_contract(storage: &dyn Storage) {
+        assert_eq!(storage.get("a", b"k"), Ok(None));
+        storage.put("a", &[2], b"two").unwrap();
+        storage.put("a", &[1, 0], b"one").unwrap();
+        storage.put("a", &[1], b"first").unwrap();
+        storage.put("a", &[1], b"one-ish").unwrap();
+        storage.put("b", &[1], b"other").unwrap();
+        assert_eq!(storage.get("a", &[1]), Ok(Some(b"one-ish".to_vec())));
+        assert_eq!(
+            storage.iterate("a").unwrap(),
+            vec![
+                (vec![1], b"one-ish".to_vec()),
+                (vec![1, 0], b"one".to_vec()),
+                (vec![2], b"two".to_vec())
+            ]
+        );
+        assert_eq!(storage.len("a"), Ok(3));
+        assert_eq!(storage.delete("a", &[2]), Ok(true));
+        assert_eq!(storage.delete("a", &[2]), Ok(false));
+        assert_eq!(storage.len("b"), Ok(1));
+        assert_eq!(storage.iterate("missing"), Ok(Vec::new()));
+        storage.flush().unwrap();
+    }
+
+    /// Tests the in-memory backend.
+    #[test]
+    fn test_memory_storage() {
+        check_contract(&MemoryStorage::new());
+    }
+
+    /// Tests the file backend, including that entries survive reopening.
+    #[test]
+    fn test_file_storage() {
+        let dir = std::env::temp_dir().join(format!("wstk-storage-test-{}", std::process::id()));
+        check_contract(&FileStorage::open(&dir).unwrap());
+        let reopened = FileStorage::open(&dir).unwrap();
+        assert_eq!(reopened.get("a", &[1, 0]), Ok(Some(b"one".to_vec())));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Tests the sled backend.
+    #[cfg(feature = "outbox")]
+    #[test]
+    fn test_sled_storage() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        check_contract(&SledStorage::from_db(db));
+    }
+}
@@ -0,0 +1,596 @@
+//! # `streaming.rs`: Streaming inbound binary messages
+//!
+//! tungstenite reassembles every message in memory before handing it out, so a
+//! multi-hundred-megabyte snapshot needs a buffer of the same size. `MessageStream` reads
+//! inbound frames itself instead: text messages are still collected into a `String`, but each
+//! binary message, fragmented or not, is surfaced as a `BinaryReader`, an `AsyncRead` over its
+//! payload that can be copied into a file or fed to a streaming parser while it arrives.
+//! Memory use stays at one read buffer no matter the message size. Text messages are capped
+//! at `max_text_size`, tungstenite's default `max_message_size` of 64 MiB unless changed with
+//! `MessageStream::with_max_text_size`; a longer one fails with `Error::Capacity` and is
+//! skipped by the next `next`.
+//!
+//! The handshake and everything sent go through tokio-tungstenite; only frame headers are
+//! read directly, with tungstenite's `FrameHeader::parse`. To keep tungstenite from reading
+//! past the handshake response, `client_async` hands it the stream one byte at a time until
+//! the response ends, so the frames the server sends right behind it are left for the
+//! `MessageStream`. `connect` does the same over TCP for `ws://` URLs, and
+//! `MessageStream::from_raw_socket` takes a stream whose handshake was completed elsewhere
+//! and nothing read past it.
+//!
+//! Pings that arrive while a binary message is being read are answered once the message has
+//! been read or dropped, on the next `next` or `send`. A reader dropped before its end is
+//! skipped over by the next `next`. Extensions such as `permessage-deflate` are not
+//! negotiated.
+
+use futures_util::sink::SinkExt;
+use futures_util::future::poll_fn;
+use std::borrow::Cow;
+use std::io::{self, Cursor};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::error::{CapacityError, UrlError};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::{CloseCode, Control, Data, OpCode};
+use tokio_tungstenite::tungstenite::protocol::frame::FrameHeader;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Role, WebSocketConfig};
+use tokio_tungstenite::tungstenite::{Error, Message};
+use tokio_tungstenite::WebSocketStream;
+
+/// The most bytes read from the socket at once.
+const READ_CHUNK: usize = 16 * 1024;
+
+/// The end of an HTTP response head.
+const HEAD_END: &[u8] = b"\r\n\r\n";
+
+/// Connects to a `ws://` URL over TCP; see `client_async`.
+///
+/// # Arguments
+///
+/// * `url` - The server URL.
+///
+/// # Returns
+///
+/// A `Result` containing the connected `MessageStream`, or an error if the URL is not a
+/// `ws://` URL, the connection fails or the server refuses the handshake.
+pub async fn connect(url: &str) -> Result<MessageStream<TcpStream>, Error> {
+    let request = url.into_client_request()?;
+    if request.uri().scheme_str() != Some("ws") {
+        return Err(Error::Url(UrlError::UnsupportedUrlScheme));
+    }
+    let host = request.uri().host().ok_or(Error::Url(UrlError::NoHostName))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let stream = TcpStream::connect((host, request.uri().port_u16().unwrap_or(80))).await?;
+    client_async(request, stream).await
+}
+
+/// Performs the client handshake over `stream` and returns it as a `MessageStream`.
+///
+/// # Arguments
+///
+/// * `request` - The URL or request to send; its headers are sent along.
+/// * `stream` - A connected stream, with TLS already established for `wss://`.
+///
+/// # Returns
+///
+/// A `Result` containing the `MessageStream`, or an error if the server refuses the
+/// handshake.
+pub async fn client_async<R, S>(request: R, stream: S) -> Result<MessageStream<S>, Error>
+where
+    R: IntoClientRequest + Unpin,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (ws_stream, _) = tokio_tungstenite::client_async(request, HandshakeStream::new(stream)).await?;
+    Ok(MessageStream::with_websocket(ws_stream))
+}
+
+/// A stream that returns at most one byte per read until an HTTP response head has been read.
+///
+/// tungstenite keeps whatever it reads past the handshake response in its own buffer; with
+/// reads this small there is nothing past it.
+#[derive(Debug)]
+struct HandshakeStream<S> {
+    stream: S,
+    /// How much of `HEAD_END` was just read; `HEAD_END.len()` once the head is complete.
+    matched: usize,
+}
+
+impl<S> HandshakeStream<S> {
+    /// Wraps a stream whose handshake response has not been read yet.
+    fn new(stream: S) -> Self {
+        HandshakeStream { stream, matched: 0 }
+    }
+
+    /// Wraps a stream whose handshake has completed.
+    fn established(stream: S) -> Self {
+        HandshakeStream { stream, matched: HEAD_END.len() }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for HandshakeStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.matched == HEAD_END.len() || buf.remaining() == 0 {
+            return Pin::new(&mut this.stream).poll_read(cx, buf);
+        }
+        let mut byte = [0; 1];
+        let mut read = ReadBuf::new(&mut byte);
+        ready!(Pin::new(&mut this.stream).poll_read(cx, &mut read))?;
+        if let [byte] = read.filled() {
+            this.matched = match *byte {
+                byte if byte == HEAD_END[this.matched] => this.matched + 1,
+                b'\r' => 1,
+                _ => 0,
+            };
+            buf.put_slice(&[*byte]);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for HandshakeStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+/// The client side of a connection that streams binary messages.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use tokio::fs::File;
+/// use websocket_toolkit::streaming::{self, StreamedMessage};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut stream = streaming::connect("ws://example.com/snapshots").await?;
+/// while let Some(message) = stream.next().await? {
+///     match message {
+///         StreamedMessage::Binary(mut reader) => {
+///             let mut file = File::create("snapshot.bin").await?;
+///             tokio::io::copy(&mut reader, &mut file).await?;
+///         }
+///         StreamedMessage::Text(text) => println!("{}", text),
+///         StreamedMessage::Close(_) => break,
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MessageStream<S> {
+    ws_stream: WebSocketStream<HandshakeStream<S>>,
+    /// Received bytes; those before `start` are consumed.
+    buffer: Vec<u8>,
+    start: usize,
+    /// Whether a data message is being read, the payload bytes left in its current frame
+    /// and whether that frame is its last.
+    in_message: bool,
+    remaining: u64,
+    fin: bool,
+    /// Payloads of pings to answer.
+    pongs: Vec<Vec<u8>>,
+    close_sent: bool,
+    /// The longest text message collected, or `None` for no limit.
+    max_text_size: Option<usize>,
+}
+
+/// A message received on a `MessageStream`.
+#[derive(Debug)]
+pub enum StreamedMessage<'a, S> {
+    /// A complete text message.
+    Text(String),
+    /// A binary message, read as it arrives.
+    Binary(BinaryReader<'a, S>),
+    /// The server's close frame; it has been answered.
+    Close(Option<CloseFrame<'static>>),
+}
+
+/// The payload of a binary message, across all its fragments.
+///
+/// Reading returns end of file once the message's final fragment has been read.
+#[derive(Debug)]
+pub struct BinaryReader<'a, S> {
+    stream: &'a mut MessageStream<S>,
+}
+
+/// A frame header, with the payload of control frames.
+enum Frame {
+    Data { opcode: Data, fin: bool, len: u64 },
+    Control { opcode: Control, payload: Vec<u8> },
+}
+
+impl<S> MessageStream<S> {
+    /// Wraps a connection whose handshake response tungstenite read one byte at a time.
+    fn with_websocket(ws_stream: WebSocketStream<HandshakeStream<S>>) -> Self {
+        MessageStream {
+            ws_stream,
+            buffer: Vec::new(),
+            start: 0,
+            in_message: false,
+            remaining: 0,
+            fin: true,
+            pongs: Vec::new(),
+            close_sent: false,
+            max_text_size: WebSocketConfig::default().max_message_size,
+        }
+    }
+
+    /// Sets the longest text message `next` collects; longer ones fail with
+    /// `Error::Capacity`. Binary messages are streamed and not limited.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_size` - The limit in bytes, or `None` for no limit.
+    ///
+    /// # Returns
+    ///
+    /// The updated `MessageStream`.
+    pub fn with_max_text_size(mut self, max_size: Option<usize>) -> Self {
+        self.max_text_size = max_size;
+        self
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> MessageStream<S> {
+    /// Wraps a stream whose handshake has completed, with nothing read past the response.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The client side of a connection.
+    ///
+    /// # Returns
+    ///
+    /// A new `MessageStream`.
+    pub async fn from_raw_socket(stream: S) -> Self {
+        let ws_stream = WebSocketStream::from_raw_socket(HandshakeStream::established(stream), Role::Client, None).await;
+        Self::with_websocket(ws_stream)
+    }
+
+    /// Returns the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.ws_stream.get_ref().stream
+    }
+
+    /// Waits for the next message.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the message, or `None` once the connection has ended, or an
+    /// error if the server breaks the protocol or the connection fails.
+    pub async fn next(&mut self) -> Result<Option<StreamedMessage<'_, S>>, Error> {
+        if self.in_message {
+            // Skip what the previous message's reader left unread.
+            tokio::io::copy(&mut BinaryReader { stream: self }, &mut tokio::io::sink()).await?;
+        }
+        loop {
+            self.send_pongs().await?;
+            let frame = match poll_fn(|cx| self.poll_frame(cx)).await? {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+            match frame {
+                Frame::Data { opcode: Data::Text, fin, len } => {
+                    self.begin(fin, len);
+                    let max_size = self.max_text_size;
+                    let too_long = |size| Error::Capacity(CapacityError::MessageTooLong { size, max_size: max_size.unwrap_or_default() });
+                    if max_size.is_some_and(|max_size| len > max_size as u64) {
+                        return Err(too_long(usize::try_from(len).unwrap_or(usize::MAX)));
+                    }
+                    let mut text = Vec::new();
+                    // A fragmented message may only turn out too long in a later fragment;
+                    // read at most one byte past the limit to find out.
+                    let limit = max_size.map_or(u64::MAX, |max_size| max_size as u64 + 1);
+                    BinaryReader { stream: self }.take(limit).read_to_end(&mut text).await?;
+                    if max_size.is_some_and(|max_size| text.len() > max_size) {
+                        return Err(too_long(text.len()));
+                    }
+                    return String::from_utf8(text).map(|text| Some(StreamedMessage::Text(text))).map_err(|_| Error::Utf8);
+                }
+                Frame::Data { opcode: Data::Binary, fin, len } => {
+                    self.begin(fin, len);
+                    return Ok(Some(StreamedMessage::Binary(BinaryReader { stream: self })));
+                }
+                Frame::Data { opcode, .. } => {
+                    return Err(invalid_data(format!("Unexpected {} frame at the start of a message", OpCode::Data(opcode))).into());
+                }
+                Frame::Control { opcode: Control::Close, payload } => {
+                    let frame = parse_close(&payload)?;
+                    if !self.close_sent {
+                        self.close_sent = true;
+                        let reply = frame.as_ref().map(|frame| CloseFrame { code: frame.code, reason: Cow::Borrowed("") });
+                        self.ws_stream.send(Message::Close(reply)).await?;
+                    }
+                    return Ok(Some(StreamedMessage::Close(frame)));
+                }
+                Frame::Control { opcode: Control::Ping, payload } => self.pongs.push(payload),
+                Frame::Control { .. } => {}
+            }
+        }
+    }
+
+    /// Sends `message`, after answering any pending pings.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to send.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the message was written and flushed.
+    pub async fn send(&mut self, message: Message) -> Result<(), Error> {
+        self.send_pongs().await?;
+        if message.is_close() {
+            self.close_sent = true;
+        }
+        self.ws_stream.send(message).await
+    }
+
+    /// Answers the pings received so far, unless a close frame was sent.
+    async fn send_pongs(&mut self) -> Result<(), Error> {
+        for payload in std::mem::take(&mut self.pongs) {
+            if !self.close_sent {
+                self.ws_stream.send(Message::Pong(payload)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts reading a data message at the payload of its first frame.
+    fn begin(&mut self, fin: bool, len: u64) {
+        self.in_message = true;
+        self.fin = fin;
+        self.remaining = len;
+    }
+
+    /// Reads until at least `n` unconsumed bytes are buffered; `false` if the stream ended.
+    fn poll_fill(&mut self, cx: &mut Context<'_>, n: usize) -> Poll<io::Result<bool>> {
+        while self.buffer.len() - self.start < n {
+            if self.start > 0 {
+                self.buffer.drain(..self.start);
+                self.start = 0;
+            }
+            let mut chunk = [0; READ_CHUNK];
+            let mut read = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(self.ws_stream.get_mut()).poll_read(cx, &mut read))?;
+            if read.filled().is_empty() {
+                return Poll::Ready(Ok(false));
+            }
+            self.buffer.extend_from_slice(read.filled());
+        }
+        Poll::Ready(Ok(true))
+    }
+
+    /// Reads the next frame header, and the whole payload of a control frame.
+    fn poll_frame(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Option<Frame>>> {
+        let (header, len) = loop {
+            let mut cursor = Cursor::new(&self.buffer[self.start..]);
+            match FrameHeader::parse(&mut cursor).map_err(|e| invalid_data(e.to_string()))? {
+                Some((header, len)) => {
+                    self.start += cursor.position() as usize;
+                    break (header, len);
+                }
+                None => {
+                    let buffered = self.buffer.len() - self.start;
+                    if !ready!(self.poll_fill(cx, buffered + 1))? {
+                        return Poll::Ready(match buffered {
+                            0 => Ok(None),
+                            _ => Err(io::ErrorKind::UnexpectedEof.into()),
+                        });
+                    }
+                }
+            }
+        };
+        if header.rsv1 || header.rsv2 || header.rsv3 {
+            return Poll::Ready(Err(invalid_data("Reserved bits set; extensions are not supported".to_string())));
+        }
+        if header.mask.is_some() {
+            return Poll::Ready(Err(invalid_data("Server frames must not be masked".to_string())));
+        }
+        let opcode = match header.opcode {
+            OpCode::Data(opcode) => return Poll::Ready(Ok(Some(Frame::Data { opcode, fin: header.is_final, len }))),
+            OpCode::Control(opcode) => opcode,
+        };
+        if !header.is_final || len > 125 {
+            return Poll::Ready(Err(invalid_data("Invalid control frame".to_string())));
+        }
+        // Wait for the whole payload before consuming anything, so a retried poll starts over.
+        self.start -= header.len(len);
+        if !ready!(self.poll_fill(cx, header.len(len) + len as usize))? {
+            return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+        }
+        self.start += header.len(len);
+        let payload = self.buffer[self.start..self.start + len as usize].to_vec();
+        self.start += len as usize;
+        Poll::Ready(Ok(Some(Frame::Control { opcode, payload })))
+    }
+
+    /// Reads payload bytes of the current data message into `buf`, moving on to its
+    /// continuation frames; reads nothing once the message is complete.
+    fn poll_read_payload(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        loop {
+            if !self.in_message || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            if self.remaining > 0 {
+                if !ready!(self.poll_fill(cx, 1))? {
+                    return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                }
+                let available = &self.buffer[self.start..];
+                let remaining = usize::try_from(self.remaining).unwrap_or(usize::MAX);
+                let n = available.len().min(buf.remaining()).min(remaining);
+                buf.put_slice(&available[..n]);
+                self.start += n;
+                self.remaining -= n as u64;
+                return Poll::Ready(Ok(()));
+            }
+            if self.fin {
+                self.in_message = false;
+                return Poll::Ready(Ok(()));
+            }
+            match ready!(self.poll_frame(cx))? {
+                Some(Frame::Data { opcode: Data::Continue, fin, len }) => {
+                    self.fin = fin;
+                    self.remaining = len;
+                }
+                Some(Frame::Data { opcode, .. }) => {
+                    return Poll::Ready(Err(invalid_data(format!("Expected a continuation frame, got {}", OpCode::Data(opcode)))));
+                }
+                Some(Frame::Control { opcode: Control::Ping, payload }) => self.pongs.push(payload),
+                Some(Frame::Control { opcode: Control::Close, .. }) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "Connection closed in the middle of a message",
+                    )));
+                }
+                Some(Frame::Control { .. }) => {}
+                None => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for BinaryReader<'_, S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().stream.poll_read_payload(cx, buf)
+    }
+}
+
+/// Parses the payload of a close frame.
+fn parse_close(payload: &[u8]) -> io::Result<Option<CloseFrame<'static>>> {
+    match payload {
+        [] => Ok(None),
+        [high, low, reason @ ..] => {
+            let reason = String::from_utf8(reason.to_vec()).map_err(|_| invalid_data("Close reason is not UTF-8".to_string()))?;
+            Ok(Some(CloseFrame { code: CloseCode::from(u16::from_be_bytes([*high, *low])), reason: Cow::Owned(reason) }))
+        }
+        _ => Err(invalid_data("Close frame payload of one byte".to_string())),
+    }
+}
+
+/// Returns an `InvalidData` error with `message`.
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockServer;
+    use tokio::io::AsyncWriteExt;
+
+    const CONTINUATION: u8 = 0x0;
+    const TEXT: u8 = 0x1;
+    const BINARY: u8 = 0x2;
+    const CLOSE: u8 = 0x8;
+    const PING: u8 = 0x9;
+    const PONG: u8 = 0xA;
+
+    /// Returns an unmasked server frame.
+    fn server_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![first, payload.len() as u8];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    /// Tests the handshake, streamed and skipped binary messages, and replies.
+    #[tokio::test]
+    async fn test_message_stream() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let url = server.url().to_string();
+        let connecting = tokio::spawn(async move { connect(&url).await.map_err(|e| e.to_string()) });
+        let mut connection = server.accept().await;
+        let mut stream = connecting.await.unwrap().unwrap();
+
+        let snapshot: Vec<u8> = (0..300_000u32).map(|i| i as u8).collect();
+        connection.send(Message::Text("hello".into())).await;
+        connection.send(Message::Binary(snapshot.clone())).await;
+        connection.send(Message::Binary(vec![1, 2, 3])).await;
+        connection.send(Message::Text("after".into())).await;
+
+        let Some(StreamedMessage::Text(text)) = stream.next().await.unwrap() else { panic!("Expected text") };
+        assert_eq!(text, "hello");
+        let Some(StreamedMessage::Binary(mut reader)) = stream.next().await.unwrap() else { panic!("Expected binary") };
+        let mut received = Vec::new();
+        reader.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, snapshot);
+        let Some(StreamedMessage::Binary(mut reader)) = stream.next().await.unwrap() else { panic!("Expected binary") };
+        assert_eq!(reader.read_u8().await.unwrap(), 1);
+        let Some(StreamedMessage::Text(text)) = stream.next().await.unwrap() else { panic!("Expected text") };
+        assert_eq!(text, "after");
+
+        stream.send(Message::Text("reply".into())).await.unwrap();
+        connection.assert_next_message_eq(Message::Text("reply".into())).await;
+    }
+
+    /// Tests fragmented messages with pings between the fragments, and the close handshake.
+    #[tokio::test]
+    async fn test_message_stream_fragments() {
+        let (client, mut peer) = tokio::io::duplex(64 * 1024);
+        let mut stream = MessageStream::from_raw_socket(client).await;
+        let mut frames = Vec::new();
+        frames.extend(server_frame(BINARY, b"ab"));
+        frames.extend(server_frame(0x80 | PING, b"p"));
+        frames.extend(server_frame(CONTINUATION, b"cd"));
+        frames.extend(server_frame(0x80 | CONTINUATION, b"e"));
+        frames.extend(server_frame(TEXT, b"hel"));
+        frames.extend(server_frame(0x80 | CONTINUATION, b"lo"));
+        frames.extend(server_frame(0x80 | CLOSE, &[0x03, 0xe8]));
+        peer.write_all(&frames).await.unwrap();
+
+        let Some(StreamedMessage::Binary(mut reader)) = stream.next().await.unwrap() else { panic!("Expected binary") };
+        let mut received = Vec::new();
+        reader.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"abcde");
+        let Some(StreamedMessage::Text(text)) = stream.next().await.unwrap() else { panic!("Expected text") };
+        assert_eq!(text, "hello");
+        let Some(StreamedMessage::Close(Some(frame))) = stream.next().await.unwrap() else { panic!("Expected close") };
+        assert_eq!(frame.code, CloseCode::Normal);
+
+        // A masked pong echoing the ping, then the close reply.
+        let mut replies = [0; 2 + 4 + 1 + 2 + 4 + 2];
+        peer.read_exact(&mut replies).await.unwrap();
+        assert_eq!((replies[0], replies[1]), (0x80 | PONG, 0x81));
+        assert_eq!(replies[6] ^ replies[2], b'p');
+        assert_eq!((replies[7], replies[8]), (0x80 | CLOSE, 0x82));
+
+        drop(peer);
+        assert!(stream.next().await.unwrap().is_none());
+    }
+
+    /// Tests that text messages beyond `max_text_size` fail, whole or fragmented, and are
+    /// skipped by the next `next`.
+    #[tokio::test]
+    async fn test_message_stream_text_limit() {
+        let (client, mut peer) = tokio::io::duplex(64 * 1024);
+        let mut stream = MessageStream::from_raw_socket(client).await.with_max_text_size(Some(4));
+        let mut frames = Vec::new();
+        frames.extend(server_frame(0x80 | TEXT, b"hello"));
+        frames.extend(server_frame(TEXT, b"abc"));
+        frames.extend(server_frame(0x80 | CONTINUATION, b"de"));
+        frames.extend(server_frame(0x80 | TEXT, b"ok"));
+        frames.extend(server_frame(0x80 | BINARY, b"binary"));
+        peer.write_all(&frames).await.unwrap();
+
+        let error = stream.next().await.expect_err("Expected the single frame to be too long");
+        assert!(matches!(error, Error::Capacity(CapacityError::MessageTooLong { size: 5, max_size: 4 })), "{}", error);
+        let error = stream.next().await.expect_err("Expected the fragmented message to be too long");
+        assert!(matches!(error, Error::Capacity(CapacityError::MessageTooLong { size: 5, max_size: 4 })), "{}", error);
+        let Some(StreamedMessage::Text(text)) = stream.next().await.unwrap() else { panic!("Expected text") };
+        assert_eq!(text, "ok");
+        let Some(StreamedMessage::Binary(mut reader)) = stream.next().await.unwrap() else { panic!("Expected binary") };
+        let mut received = Vec::new();
+        reader.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"binary");
+    }
+}
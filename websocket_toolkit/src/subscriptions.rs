@@ -0,0 +1,312 @@
+//! # `subscriptions.rs`: Subscriptions replayed on every connection
+//!
+//! Servers forget a client's subscriptions when its connection drops, so after a reconnect
+//! the client receives nothing until it subscribes again. `SubscriptionManager` records the
+//! subscribe frames of the active subscriptions, in the order they were made, and the
+//! controller it is set on (`WebSocketController::set_subscription_manager`) sends them
+//! again on every connection it opens, after authentication and before the stream or
+//! pipeline is handed out: `connect`, `reconnect`, `connect_pipeline` and
+//! `connect_handle` alike.
+//!
+//! Subscribe frames often carry a token that has expired by the time of the reconnect. A
+//! refresh hook (`with_refresh`) is called with each recorded frame before it is sent again
+//! and returns the frame to send, e.g. with a fresh token; the recorded frame stays as it
+//! was, so a template with a token placeholder works on every reconnect. A hook error fails
+//! the connect, like a failed auth provider.
+//!
+//! Unlike typed streams, which subscribe with the `subscribe_envelope` format, the manager
+//! replays whatever frames the application's protocol uses.
+//!
+//! With `WebSocketController::set_subscription_sync`, the manager takes part in the same
+//! diff as typed streams: keys the server lists in its `subscriptions_envelope` as already
+//! acknowledged are not replayed, and are not unsubscribed as stale topics either. Use the
+//! topic names the server reports as keys.
+
+use futures_util::SinkExt;
+use log::debug;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::{Error, Message};
+use tokio_tungstenite::WebSocketStream;
+
+/// Called with a subscription's key and recorded frame before it is sent again; returns the
+/// frame to send.
+pub type RefreshHook = Arc<dyn Fn(&str, &Message) -> Result<Message, String> + Send + Sync>;
+
+/// Records the active subscriptions and replays them on new connections.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use tokio_tungstenite::tungstenite::Message;
+/// use websocket_toolkit::controller::WebSocketController;
+/// use websocket_toolkit::subscriptions::SubscriptionManager;
+///
+/// let manager = Arc::new(SubscriptionManager::new().with_refresh(|_, message| {
+///     let token = "fresh-token"; // e.g. from the application's token store
+///     Ok(Message::Text(message.to_text().map_err(|e| e.to_string())?.replace("{token}", token)))
+/// }));
+/// manager.add("trades", Message::Text(r#"{"op":"subscribe","channel":"trades","token":"{token}"}"#.into()));
+///
+/// let mut controller = WebSocketController::new("ws://example.com", 3, None);
+/// controller.set_subscription_manager(Some(manager.clone()));
+/// assert_eq!(manager.keys(), vec!["trades"]);
+/// ```
+#[derive(Default)]
+pub struct SubscriptionManager {
+    /// The subscribe frames by key, in subscription order.
+    active: Mutex<Vec<(String, Message)>>,
+    refresh: Option<RefreshHook>,
+}
+
+impl SubscriptionManager {
+    /// Creates a manager with no subscriptions and no refresh hook.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the hook that rewrites each frame before it is replayed.
+    ///
+    /// # Arguments
+    ///
+    /// * `refresh` - Called with the subscription's key and recorded frame; returns the frame
+    ///   to send, or an error message to fail the connect.
+    ///
+    /// # Returns
+    ///
+    /// The updated `SubscriptionManager`.
+    pub fn with_refresh<F>(mut self, refresh: F) -> Self
+    where
+        F: Fn(&str, &Message) -> Result<Message, String> + Send + Sync + 'static,
+    {
+        self.refresh = Some(Arc::new(refresh));
+        self
+    }
+
+    /// Records a subscription. A subscription with the same key is replaced but keeps its
+    /// place in the order.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Identifies the subscription, e.g. its channel.
+    /// * `message` - The frame that subscribes.
+    pub fn add(&self, key: impl Into<String>, message: Message) {
+        let key = key.into();
+        let mut active = self.active.lock().unwrap();
+        match active.iter_mut().find(|(existing, _)| *existing == key) {
+            Some((_, recorded)) => *recorded = message,
+            None => active.push((key, message)),
+        }
+    }
+
+    /// Forgets a subscription.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key the subscription was recorded with.
+    ///
+    /// # Returns
+    ///
+    /// Whether the subscription was recorded.
+    pub fn remove(&self, key: &str) -> bool {
+        let mut active = self.active.lock().unwrap();
+        let before = active.len();
+        active.retain(|(existing, _)| existing != key);
+        active.len() != before
+    }
+
+    /// Sends `message` on `ws_stream` and records it once sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - The current connection.
+    /// * `key` - Identifies the subscription.
+    /// * `message` - The frame that subscribes.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the frame was sent.
+    pub async fn subscribe<S>(&self, ws_stream: &mut WebSocketStream<S>, key: impl Into<String>, message: Message) -> Result<(), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        ws_stream.send(message.clone()).await?;
+        self.add(key, message);
+        Ok(())
+    }
+
+    /// Sends `message` on `ws_stream` and forgets the subscription `key`, even if sending
+    /// fails, so it is not replayed.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - The current connection.
+    /// * `key` - The key the subscription was recorded with.
+    /// * `message` - The frame that unsubscribes.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating the frame was sent.
+    pub async fn unsubscribe<S>(&self, ws_stream: &mut WebSocketStream<S>, key: &str, message: Message) -> Result<(), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.remove(key);
+        ws_stream.send(message).await
+    }
+
+    /// Returns the keys of the recorded subscriptions, in order.
+    pub fn keys(&self) -> Vec<String> {
+        self.active.lock().unwrap().iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    /// Returns the number of recorded subscriptions.
+    pub fn len(&self) -> usize {
+        self.active.lock().unwrap().len()
+    }
+
+    /// Returns whether no subscription is recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the frames to replay, in order, each passed through the refresh hook.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the frames, or the hook's error message for the first frame it
+    /// rejected.
+    pub fn replay(&self) -> Result<Vec<Message>, String> {
+        self.replay_missing(&BTreeSet::new())
+    }
+
+    /// Returns the frames of the subscriptions the server has not acknowledged, in order,
+    /// each passed through the refresh hook.
+    ///
+    /// # Arguments
+    ///
+    /// * `acknowledged` - The keys the server already has, from its subscription list.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the frames, or the hook's error message for the first frame it
+    /// rejected.
+    pub fn replay_missing(&self, acknowledged: &BTreeSet<String>) -> Result<Vec<Message>, String> {
+        // The hook runs without the lock, so it may add or remove subscriptions itself.
+        let mut recorded = self.active.lock().unwrap().clone();
+        recorded.retain(|(key, _)| !acknowledged.contains(key));
+        let frames = match &self.refresh {
+            Some(refresh) => recorded
+                .iter()
+                .map(|(key, message)| {
+                    refresh(key, message).map_err(|e| format!("Failed to refresh subscription {}: {}", key, e))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            None => recorded.into_iter().map(|(_, message)| message).collect(),
+        };
+        debug!("Replaying {} subscriptions", frames.len());
+        Ok(frames)
+    }
+}
+
+impl fmt::Debug for SubscriptionManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SubscriptionManager")
+            .field("keys", &self.keys())
+            .field("refresh", &self.refresh.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::WebSocketController;
+    use crate::testing::MockServer;
+    use crate::messages::MessageFormat;
+    use crate::typed_stream::subscriptions_envelope;
+    use futures_util::StreamExt;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    /// Tests that subscriptions are replayed in order on a reconnect, with refreshed tokens,
+    /// on the connection `reconnect` returns, and that removed ones are not.
+    #[cfg(feature = "reconnection")]
+    #[tokio::test]
+    async fn test_subscription_manager() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let refreshes = Arc::new(AtomicU32::new(0));
+        let counter = refreshes.clone();
+        let manager = Arc::new(SubscriptionManager::new().with_refresh(move |_, message| {
+            let token = counter.fetch_add(1, Ordering::Relaxed) + 1;
+            Ok(Message::Text(message.to_text().unwrap().replace("token=0", &format!("token={}", token))))
+        }));
+        let mut controller = WebSocketController::new(server.url(), 1, None);
+        controller.set_subscription_manager(Some(manager.clone()));
+        let controller = Arc::new(controller);
+
+        let connecting = tokio::spawn({
+            let controller = controller.clone();
+            async move { controller.connect().await.map_err(|e| e.to_string()) }
+        });
+        let mut connection = server.accept().await;
+        let mut ws_stream = connecting.await.unwrap().unwrap();
+        for key in ["trades", "books", "tickers"] {
+            let message = Message::Text(format!("sub {} token=0", key));
+            manager.subscribe(&mut ws_stream, key, message.clone()).await.unwrap();
+            connection.assert_next_message_eq(message).await;
+        }
+        manager.unsubscribe(&mut ws_stream, "books", Message::Text("unsub books".into())).await.unwrap();
+        manager.add("trades", Message::Text("sub trades token=0 depth=5".into()));
+        assert_eq!(manager.keys(), vec!["trades", "tickers"]);
+
+        drop(connection);
+        let reconnecting = tokio::spawn({
+            let controller = controller.clone();
+            async move { controller.reconnect().await.map_err(|e| e.to_string()) }
+        });
+        let mut connection = server.accept().await;
+        connection.assert_next_message_eq(Message::Text("sub trades token=1 depth=5".into())).await;
+        connection.assert_next_message_eq(Message::Text("sub tickers token=2".into())).await;
+        let mut ws_stream = reconnecting.await.unwrap().unwrap();
+        assert_eq!(refreshes.load(Ordering::Relaxed), 2);
+        ws_stream.send(Message::Text("after replay".into())).await.unwrap();
+        connection.assert_next_message_eq(Message::Text("after replay".into())).await;
+        connection.send(Message::Text("update".into())).await;
+        assert_eq!(ws_stream.next().await.unwrap().unwrap(), Message::Text("update".into()));
+
+        let failing = SubscriptionManager::new().with_refresh(|_, _| Err("token store offline".into()));
+        failing.add("trades", Message::Text("sub trades".into()));
+        assert!(failing.replay().unwrap_err().contains("token store offline"));
+    }
+
+    /// Tests that with subscription sync only the subscriptions the server has not
+    /// acknowledged are replayed, and that acknowledged ones are not unsubscribed.
+    #[tokio::test]
+    async fn test_subscription_manager_sync() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let manager = Arc::new(SubscriptionManager::new());
+        for key in ["trades", "books", "tickers"] {
+            manager.add(key, Message::Text(format!("sub {}", key)));
+        }
+        let mut controller = WebSocketController::new(server.url(), 0, None);
+        controller.set_subscription_manager(Some(manager));
+        controller.set_subscription_sync(Some(Duration::from_secs(5)));
+        let controller = Arc::new(controller);
+
+        let connecting = tokio::spawn({
+            let controller = controller.clone();
+            async move { controller.connect().await.map(|_| ()).map_err(|e| e.to_string()) }
+        });
+        let mut connection = server.accept().await;
+        connection.next_message().await.unwrap();
+        let answer = subscriptions_envelope(["books", "trades"]).encode(MessageFormat::Json).unwrap();
+        connection.send(Message::Binary(answer)).await;
+        connection.assert_next_message_eq(Message::Text("sub tickers".into())).await;
+        connecting.await.unwrap().unwrap();
+        connection.expect_silence_for(Duration::from_millis(50)).await;
+    }
+}
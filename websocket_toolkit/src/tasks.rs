@@ -0,0 +1,108 @@
+//! # `tasks.rs`: Named task spawning for tokio-console
+//!
+//! Every background task the toolkit starts (keep-alive pings, connection writers, reconnects,
+//! test servers) is spawned through `spawn_named`, so `tokio-console` lists it under a
+//! meaningful name instead of an anonymous `task`. Names are only attached when the crate is
+//! built with the `console` feature and `RUSTFLAGS="--cfg tokio_unstable"`, which is what
+//! tokio requires for task instrumentation; otherwise `spawn_named` is a plain `tokio::spawn`.
+//!
+//! The spawn functions return the task's `JoinHandle`, so callers can await or abort tasks
+//! instead of leaking them.
+
+use log::debug;
+use std::future::Future;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+
+/// Spawns `future` on the current tokio runtime as a task called `name`.
+///
+/// # Arguments
+///
+/// * `name` - The task name shown by `tokio-console`, such as `websocket_toolkit::keep_alive`.
+/// * `future` - The future to run.
+///
+/// # Returns
+///
+/// The `JoinHandle` of the spawned task.
+///
+/// # Panics
+///
+/// Panics if called outside a tokio runtime, like `tokio::spawn`.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::tasks::spawn_named;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let handle = spawn_named("example::worker", async { 1 + 1 });
+/// assert_eq!(handle.await.unwrap(), 2);
+/// # }
+/// ```
+pub fn spawn_named<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    spawn_named_on(name, future, &Handle::current())
+}
+
+/// Spawns `future` on the runtime behind `handle` as a task called `name`.
+///
+/// # Arguments
+///
+/// * `name` - The task name shown by `tokio-console`.
+/// * `future` - The future to run.
+/// * `handle` - The runtime to spawn on.
+///
+/// # Returns
+///
+/// The `JoinHandle` of the spawned task.
+pub fn spawn_named_on<F>(name: &str, future: F, handle: &Handle) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    debug!("Spawning task {}", name);
+    #[cfg(all(tokio_unstable, feature = "console"))]
+    {
+        tokio::task::Builder::new()
+            .name(name)
+            .spawn_on(future, handle)
+            .expect("Failed to spawn task")
+    }
+    #[cfg(not(all(tokio_unstable, feature = "console")))]
+    {
+        handle.spawn(future)
+    }
+}
+
+/// Installs the `console-subscriber` layer so `tokio-console` can attach to this process.
+///
+/// Call once at startup, inside or before creating the tokio runtime. Available with the
+/// `console` feature; task names additionally require `RUSTFLAGS="--cfg tokio_unstable"`.
+#[cfg(feature = "console")]
+pub fn init_console() {
+    console_subscriber::init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that named tasks run and hand their output back through the join handle.
+    #[tokio::test]
+    async fn test_spawn_named_returns_handle() {
+        let handle = spawn_named("test::answer", async { 42 });
+        assert_eq!(handle.await.unwrap(), 42);
+    }
+
+    /// Tests that aborting a named task through its handle cancels it.
+    #[tokio::test]
+    async fn test_spawn_named_abort() {
+        let handle = spawn_named("test::forever", std::future::pending::<()>());
+        handle.abort();
+        assert!(handle.await.unwrap_err().is_cancelled());
+    }
+}
@@ -0,0 +1,643 @@
+//! # `testing.rs`: Local servers and helpers for tests and benchmarks
+//!
+//! This module provides lightweight, in-process WebSocket servers that tests, benchmarks
+//! and the load-generation harness can run against instead of an external server, as well
+//! as an in-memory transport that connects a client and a server stream without any sockets.
+//! `TestHandle` wraps either end of a connection with assertion helpers for expected traffic.
+
+use crate::tasks::spawn_named;
+use log::{error, info};
+use futures_util::{SinkExt, StreamExt};
+use std::borrow::Cow;
+use std::time::Duration;
+use tokio::io::{duplex, AsyncRead, AsyncWrite, DuplexStream};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Instant};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Role, WebSocketConfig};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{accept_async, WebSocketStream};
+
+/// A WebSocket stream running over the in-memory transport.
+pub type MemoryStream = WebSocketStream<DuplexStream>;
+
+/// The buffer size of each direction of the in-memory transport.
+const MEMORY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Creates a connected pair of WebSocket streams backed by an in-memory pipe.
+///
+/// The first stream plays the client role and the second the server role. No handshake
+/// is performed, which makes the pair suitable for unit tests and fuzz targets that feed
+/// frames straight into the controller's receive path.
+///
+/// # Arguments
+///
+/// * `config` - Optional protocol configuration applied to both ends (e.g. message size limits).
+///
+/// # Returns
+///
+/// A `(client, server)` tuple of connected `MemoryStream`s.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::testing::memory_pair;
+/// use futures_util::{SinkExt, StreamExt};
+/// use tokio_tungstenite::tungstenite::Message;
+///
+/// let runtime = tokio::runtime::Runtime::new().unwrap();
+/// runtime.block_on(async {
+///     let (mut client, mut server) = memory_pair(None).await;
+///     server.send(Message::Text("hi".into())).await.unwrap();
+///     assert_eq!(client.next().await.unwrap().unwrap(), Message::Text("hi".into()));
+/// });
+/// ```
+pub async fn memory_pair(config: Option<WebSocketConfig>) -> (MemoryStream, MemoryStream) {
+    let (client_io, server_io) = duplex(MEMORY_BUFFER_SIZE);
+    let client = WebSocketStream::from_raw_socket(client_io, Role::Client, config).await;
+    let server = WebSocketStream::from_raw_socket(server_io, Role::Server, config).await;
+    (client, server)
+}
+
+/// Creates a client WebSocket stream whose peer is a raw, unframed byte pipe.
+///
+/// Bytes written to the returned `DuplexStream` are parsed by the client as incoming
+/// WebSocket frames, which allows tests and fuzz targets to inject malformed frames.
+///
+/// # Arguments
+///
+/// * `config` - Optional protocol configuration for the client stream.
+///
+/// # Returns
+///
+/// A `(client, raw_peer)` tuple.
+pub async fn raw_memory_pair(config: Option<WebSocketConfig>) -> (MemoryStream, DuplexStream) {
+    let (client_io, raw_peer) = duplex(MEMORY_BUFFER_SIZE);
+    let client = WebSocketStream::from_raw_socket(client_io, Role::Client, config).await;
+    (client, raw_peer)
+}
+
+/// The frame type an `EchoServer` replies in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EchoFrames {
+    /// Replies in the frame type received.
+    Mirror,
+    /// Replies in text frames; binary payloads are converted lossily.
+    Text,
+    /// Replies in binary frames.
+    Binary,
+}
+
+/// How an `EchoServer` answers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EchoConfig {
+    /// The frame type of replies.
+    pub frames: EchoFrames,
+    /// How long the server waits before echoing each message. Messages are echoed one at a
+    /// time, in order, so a burst queues behind the latency like on a slow server.
+    pub latency: Duration,
+    /// How long the server waits before answering a ping, or `None` to answer at once.
+    pub pong_delay: Option<Duration>,
+}
+
+impl Default for EchoConfig {
+    /// Mirrored frame types, no latency and immediate pongs.
+    fn default() -> Self {
+        EchoConfig { frames: EchoFrames::Mirror, latency: Duration::ZERO, pong_delay: None }
+    }
+}
+
+impl EchoConfig {
+    /// Returns the reply to a text or binary `message`.
+    fn reply(&self, message: Message) -> Message {
+        match (self.frames, message) {
+            (EchoFrames::Text, Message::Binary(data)) => Message::Text(String::from_utf8_lossy(&data).into_owned()),
+            (EchoFrames::Binary, Message::Text(text)) => Message::Binary(text.into_bytes()),
+            (_, message) => message,
+        }
+    }
+}
+
+/// A local WebSocket server that echoes every text and binary frame back to the sender.
+///
+/// The server binds to an ephemeral port on `127.0.0.1` and accepts any number of
+/// concurrent connections. It is stopped when the `EchoServer` is dropped. `start_with`
+/// controls the frame type of replies, their latency and how late pings are answered, so
+/// RTT measurement and liveness detection can be tested against known timings.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::testing::EchoServer;
+///
+/// let runtime = tokio::runtime::Runtime::new().unwrap();
+/// runtime.block_on(async {
+///     let server = EchoServer::start().await.unwrap();
+///     assert!(server.url().starts_with("ws://127.0.0.1:"));
+/// });
+/// ```
+pub struct EchoServer {
+    url: String,
+    handle: JoinHandle<()>,
+}
+
+impl EchoServer {
+    /// Starts a new echo server on an ephemeral local port.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the running `EchoServer`, or an I/O error if binding fails.
+    pub async fn start() -> Result<Self, std::io::Error> {
+        EchoServer::start_with(EchoConfig::default()).await
+    }
+
+    /// Starts a new echo server that answers as `config` describes.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The frame type, latency and pong delay of replies.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the running `EchoServer`, or an I/O error if binding fails.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use websocket_toolkit::testing::{EchoConfig, EchoFrames, EchoServer};
+    ///
+    /// let runtime = tokio::runtime::Runtime::new().unwrap();
+    /// runtime.block_on(async {
+    ///     let server = EchoServer::start_with(EchoConfig {
+    ///         frames: EchoFrames::Binary,
+    ///         latency: Duration::from_millis(20),
+    ///         pong_delay: Some(Duration::from_millis(100)),
+    ///     })
+    ///     .await
+    ///     .unwrap();
+    ///     assert!(server.url().starts_with("ws://127.0.0.1:"));
+    /// });
+    /// ```
+    pub async fn start_with(config: EchoConfig) -> Result<Self, std::io::Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("ws://{}", listener.local_addr()?);
+        info!("Echo server listening on {}", url);
+
+        let handle = spawn_named("websocket_toolkit::echo_server", async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let _ = stream.set_nodelay(true);
+                spawn_named("websocket_toolkit::echo_connection", async move {
+                    let mut ws_stream = match accept_async(stream).await {
+                        Ok(ws_stream) => ws_stream,
+                        Err(e) => {
+                            error!("Echo server handshake failed: {}", e);
+                            return;
+                        }
+                    };
+
+                    while let Some(Ok(message)) = ws_stream.next().await {
+                        let reply = match message {
+                            Message::Text(_) | Message::Binary(_) => config.reply(message),
+                            Message::Ping(_) => {
+                                // tungstenite queues the pong and writes it on the next read
+                                // or flush, so waiting before either delays it.
+                                if let Some(delay) = config.pong_delay {
+                                    tokio::time::sleep(delay).await;
+                                    if ws_stream.flush().await.is_err() {
+                                        break;
+                                    }
+                                }
+                                continue;
+                            }
+                            Message::Close(_) => break,
+                            _ => continue,
+                        };
+                        if !config.latency.is_zero() {
+                            tokio::time::sleep(config.latency).await;
+                        }
+                        if ws_stream.send(reply).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        Ok(EchoServer { url, handle })
+    }
+
+    /// Returns the `ws://` URL clients should connect to.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+impl Drop for EchoServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// The default time a `TestHandle` waits for an expected frame before failing.
+const DEFAULT_EXPECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One end of a WebSocket connection wrapped with assertion helpers for tests.
+///
+/// Every `assert_*`/`expect_*` method panics with a descriptive message when the expected
+/// traffic does not arrive within the handle's timeout, which replaces the hand-rolled
+/// `StreamExt::next` loops tests would otherwise repeat. Ping and pong frames are skipped
+/// by all helpers except `next_frame`.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::testing::{memory_pair, TestHandle};
+/// use tokio_tungstenite::tungstenite::Message;
+///
+/// let runtime = tokio::runtime::Runtime::new().unwrap();
+/// runtime.block_on(async {
+///     let (client, server) = memory_pair(None).await;
+///     let mut client = TestHandle::new(client);
+///     let mut server = TestHandle::new(server);
+///
+///     client.send(Message::Text("hello".into())).await;
+///     server.assert_next_message_eq(Message::Text("hello".into())).await;
+/// });
+/// ```
+pub struct TestHandle<S> {
+    ws_stream: WebSocketStream<S>,
+    expect_timeout: Duration,
+}
+
+impl<S> TestHandle<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Wraps a WebSocket stream with the default expectation timeout of five seconds.
+    pub fn new(ws_stream: WebSocketStream<S>) -> Self {
+        TestHandle {
+            ws_stream,
+            expect_timeout: DEFAULT_EXPECT_TIMEOUT,
+        }
+    }
+
+    /// Sets how long the assertion helpers wait for expected traffic.
+    pub fn with_timeout(mut self, expect_timeout: Duration) -> Self {
+        self.expect_timeout = expect_timeout;
+        self
+    }
+
+    /// Changes how long the assertion helpers wait for expected traffic.
+    pub fn set_timeout(&mut self, expect_timeout: Duration) {
+        self.expect_timeout = expect_timeout;
+    }
+
+    /// Returns the wrapped stream for operations the helpers do not cover.
+    pub fn stream(&mut self) -> &mut WebSocketStream<S> {
+        &mut self.ws_stream
+    }
+
+    /// Consumes the handle and returns the wrapped stream.
+    pub fn into_inner(self) -> WebSocketStream<S> {
+        self.ws_stream
+    }
+
+    /// Sends a frame, panicking if the send fails.
+    pub async fn send(&mut self, message: Message) {
+        if let Err(e) = self.ws_stream.send(message).await {
+            panic!("Failed to send test message: {}", e);
+        }
+    }
+
+    /// Reads the next frame of any type, including control frames.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the stream ended, errored, or nothing arrived within the timeout.
+    pub async fn next_frame(&mut self) -> Option<Message> {
+        match timeout(self.expect_timeout, self.ws_stream.next()).await {
+            Ok(Some(Ok(message))) => Some(message),
+            _ => None,
+        }
+    }
+
+    /// Reads the next text, binary or close frame, skipping pings and pongs.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the stream ended, errored, or nothing arrived within the timeout.
+    pub async fn next_message(&mut self) -> Option<Message> {
+        let deadline = Instant::now() + self.expect_timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match timeout(remaining, self.ws_stream.next()).await {
+                Ok(Some(Ok(Message::Ping(_)))) | Ok(Some(Ok(Message::Pong(_)))) => continue,
+                Ok(Some(Ok(message))) => return Some(message),
+                _ => return None,
+            }
+        }
+    }
+
+    /// Asserts that the next non-control frame equals `expected`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a different frame arrives or nothing arrives within the timeout.
+    pub async fn assert_next_message_eq(&mut self, expected: Message) {
+        match self.next_message().await {
+            Some(message) => assert_eq!(message, expected, "Unexpected WebSocket message"),
+            None => panic!(
+                "Expected message {:?}, but nothing arrived within {:?}",
+                expected, self.expect_timeout
+            ),
+        }
+    }
+
+    /// Asserts that the connection is closed with the given status code.
+    ///
+    /// Data frames received before the close frame are skipped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the close frame carries a different code or none arrives within the timeout.
+    pub async fn expect_close_code(&mut self, code: u16) {
+        let deadline = Instant::now() + self.expect_timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match timeout(remaining, self.ws_stream.next()).await {
+                Ok(Some(Ok(Message::Close(Some(frame))))) => {
+                    assert_eq!(u16::from(frame.code), code, "Unexpected close code ({})", frame.reason);
+                    return;
+                }
+                Ok(Some(Ok(Message::Close(None)))) => {
+                    panic!("Expected close code {}, but the close frame carried no code", code)
+                }
+                Ok(Some(Ok(_))) => continue,
+                Ok(Some(Err(e))) => panic!("Expected close code {}, but the stream failed: {}", code, e),
+                Ok(None) => panic!("Expected close code {}, but the stream ended without a close frame", code),
+                Err(_) => panic!("Expected close code {}, but nothing arrived within {:?}", code, self.expect_timeout),
+            }
+        }
+    }
+
+    /// Collects every non-control frame received during `duration`.
+    ///
+    /// Collection stops early if the stream ends or errors.
+    ///
+    /// # Returns
+    ///
+    /// The received frames in arrival order.
+    pub async fn collect_messages_for(&mut self, duration: Duration) -> Vec<Message> {
+        let deadline = Instant::now() + duration;
+        let mut messages = Vec::new();
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match timeout(remaining, self.ws_stream.next()).await {
+                Ok(Some(Ok(Message::Ping(_)))) | Ok(Some(Ok(Message::Pong(_)))) => continue,
+                Ok(Some(Ok(message))) => messages.push(message),
+                _ => return messages,
+            }
+        }
+    }
+
+    /// Asserts that no text, binary or close frame arrives during `duration`.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the first frame received during the window.
+    pub async fn expect_silence_for(&mut self, duration: Duration) {
+        let messages = self.collect_messages_for(duration).await;
+        assert!(messages.is_empty(), "Expected no messages, but received {:?}", messages);
+    }
+
+    /// Closes the connection with the given status code and reason.
+    pub async fn close(&mut self, code: u16, reason: &str) {
+        let frame = CloseFrame {
+            code: CloseCode::from(code),
+            reason: Cow::Owned(reason.to_string()),
+        };
+        if let Err(e) = self.ws_stream.close(Some(frame)).await {
+            error!("Failed to close test connection: {}", e);
+        }
+    }
+}
+
+/// A local WebSocket server whose accepted connections are handed to the test as `TestHandle`s.
+///
+/// Unlike `EchoServer`, the mock server does not respond on its own; the test drives the
+/// server side of every connection explicitly.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::testing::MockServer;
+/// use websocket_toolkit::controller::WebSocketController;
+/// use tokio_tungstenite::tungstenite::Message;
+///
+/// let runtime = tokio::runtime::Runtime::new().unwrap();
+/// runtime.block_on(async {
+///     let mut server = MockServer::start().await.unwrap();
+///     let mut controller = WebSocketController::new(server.url(), 1, None);
+///     let mut ws_stream = controller.connect().await.unwrap();
+///
+///     let mut connection = server.accept().await;
+///     controller.send_message(&mut ws_stream, b"hello").await.unwrap();
+///     connection.assert_next_message_eq(Message::Binary(b"hello".to_vec())).await;
+/// });
+/// ```
+pub struct MockServer {
+    url: String,
+    accepted: mpsc::UnboundedReceiver<WebSocketStream<TcpStream>>,
+    handle: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Starts a new mock server on an ephemeral local port.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the running `MockServer`, or an I/O error if binding fails.
+    pub async fn start() -> Result<Self, std::io::Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("ws://{}", listener.local_addr()?);
+        let (sender, accepted) = mpsc::unbounded_channel();
+
+        let handle = spawn_named("websocket_toolkit::mock_server", async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let _ = stream.set_nodelay(true);
+                match accept_async(stream).await {
+                    Ok(ws_stream) => {
+                        if sender.send(ws_stream).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => error!("Mock server handshake failed: {}", e),
+                }
+            }
+        });
+
+        Ok(MockServer { url, accepted, handle })
+    }
+
+    /// Returns the `ws://` URL clients should connect to.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Waits for the next client connection.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no client connects within the default expectation timeout.
+    pub async fn accept(&mut self) -> TestHandle<TcpStream> {
+        self.accept_within(DEFAULT_EXPECT_TIMEOUT)
+            .await
+            .unwrap_or_else(|| panic!("No client connected within {:?}", DEFAULT_EXPECT_TIMEOUT))
+    }
+
+    /// Waits up to `duration` for the next client connection.
+    ///
+    /// # Returns
+    ///
+    /// `None` if no client connected in time.
+    pub async fn accept_within(&mut self, duration: Duration) -> Option<TestHandle<TcpStream>> {
+        match timeout(duration, self.accepted.recv()).await {
+            Ok(Some(ws_stream)) => Some(TestHandle::new(ws_stream)),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::WebSocketClient;
+
+    /// Tests that the echo server returns binary frames unchanged.
+    #[tokio::test]
+    async fn test_echo_server_round_trip() {
+        let server = EchoServer::start().await.expect("Failed to start echo server");
+        let client = WebSocketClient::new(server.url(), 1);
+        let mut ws_stream = client.connect().await.expect("Failed to connect to echo server");
+
+        ws_stream.send(Message::Binary(b"echo".to_vec())).await.unwrap();
+        let reply = ws_stream.next().await.unwrap().unwrap();
+        assert_eq!(reply, Message::Binary(b"echo".to_vec()));
+    }
+
+    /// Tests that the echo server converts frame types and delays replies and pongs.
+    #[tokio::test]
+    async fn test_echo_server_config() {
+        let server = EchoServer::start_with(EchoConfig {
+            frames: EchoFrames::Text,
+            latency: Duration::from_millis(50),
+            pong_delay: Some(Duration::from_millis(150)),
+        })
+        .await
+        .expect("Failed to start echo server");
+        let client = WebSocketClient::new(server.url(), 1);
+        let mut ws_stream = client.connect().await.expect("Failed to connect to echo server");
+
+        let started = Instant::now();
+        ws_stream.send(Message::Binary(b"echo".to_vec())).await.unwrap();
+        assert_eq!(ws_stream.next().await.unwrap().unwrap(), Message::Text("echo".into()));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        let started = Instant::now();
+        ws_stream.send(Message::Ping(b"rtt".to_vec())).await.unwrap();
+        assert_eq!(ws_stream.next().await.unwrap().unwrap(), Message::Pong(b"rtt".to_vec()));
+        assert!(started.elapsed() >= Duration::from_millis(150));
+
+        let server = EchoServer::start_with(EchoConfig { frames: EchoFrames::Binary, ..EchoConfig::default() })
+            .await
+            .expect("Failed to start echo server");
+        let mut ws_stream = WebSocketClient::new(server.url(), 1).connect().await.unwrap();
+        ws_stream.send(Message::Text("echo".into())).await.unwrap();
+        assert_eq!(ws_stream.next().await.unwrap().unwrap(), Message::Binary(b"echo".to_vec()));
+    }
+
+    /// Tests that frames written on one end of the in-memory pair arrive on the other.
+    #[tokio::test]
+    async fn test_memory_pair_delivers_frames() {
+        let (mut client, mut server) = memory_pair(None).await;
+
+        client.send(Message::Binary(vec![1, 2, 3])).await.unwrap();
+        assert_eq!(server.next().await.unwrap().unwrap(), Message::Binary(vec![1, 2, 3]));
+
+        server.send(Message::Text("pong".into())).await.unwrap();
+        assert_eq!(client.next().await.unwrap().unwrap(), Message::Text("pong".into()));
+    }
+
+    /// Tests that raw bytes written to the peer are parsed as frames by the client.
+    #[tokio::test]
+    async fn test_raw_memory_pair_parses_frames() {
+        use tokio::io::AsyncWriteExt;
+
+        let (mut client, mut raw_peer) = raw_memory_pair(None).await;
+        // FIN + text opcode, unmasked, 2-byte payload.
+        raw_peer.write_all(&[0x81, 0x02, b'o', b'k']).await.unwrap();
+        assert_eq!(client.next().await.unwrap().unwrap(), Message::Text("ok".into()));
+    }
+
+    /// Tests the message assertion and collection helpers of `TestHandle`.
+    #[tokio::test]
+    async fn test_handle_message_helpers() {
+        let (client, server) = memory_pair(None).await;
+        let mut client = TestHandle::new(client);
+        let mut server = TestHandle::new(server).with_timeout(Duration::from_millis(200));
+
+        client.send(Message::Text("first".into())).await;
+        client.send(Message::Ping(vec![])).await;
+        client.send(Message::Binary(vec![2])).await;
+        client.send(Message::Text("third".into())).await;
+
+        server.assert_next_message_eq(Message::Text("first".into())).await;
+        let rest = server.collect_messages_for(Duration::from_millis(100)).await;
+        assert_eq!(rest, vec![Message::Binary(vec![2]), Message::Text("third".into())]);
+        server.expect_silence_for(Duration::from_millis(50)).await;
+    }
+
+    /// Tests that `expect_close_code` observes the peer's close code.
+    #[tokio::test]
+    async fn test_handle_expect_close_code() {
+        let (client, server) = memory_pair(None).await;
+        let mut client = TestHandle::new(client);
+        let mut server = TestHandle::new(server);
+
+        server.close(1008, "policy").await;
+        client.expect_close_code(1008).await;
+    }
+
+    /// Tests that a mismatching message fails the assertion.
+    #[tokio::test]
+    #[should_panic(expected = "Unexpected WebSocket message")]
+    async fn test_handle_assert_mismatch_panics() {
+        let (client, server) = memory_pair(None).await;
+        let mut client = TestHandle::new(client);
+        let mut server = TestHandle::new(server);
+
+        client.send(Message::Text("actual".into())).await;
+        server.assert_next_message_eq(Message::Text("expected".into())).await;
+    }
+
+    /// Tests that the mock server hands accepted connections to the test.
+    #[tokio::test]
+    async fn test_mock_server_accept() {
+        let mut server = MockServer::start().await.expect("Failed to start mock server");
+        let client = WebSocketClient::new(server.url(), 1);
+        let mut ws_stream = client.connect().await.expect("Failed to connect to mock server");
+
+        let mut connection = server.accept().await;
+        connection.send(Message::Text("welcome".into())).await;
+        assert_eq!(ws_stream.next().await.unwrap().unwrap(), Message::Text("welcome".into()));
+    }
+}
@@ -0,0 +1,350 @@
+//! # `timesync.rs`: Estimating the server's clock over the connection
+//!
+//! Clients that timestamp events against each other, such as market data consumers, need a
+//! common clock, and local clocks are neither set exactly nor running at exactly the same
+//! rate. `TimeSync` estimates the server's clock the way NTP does, over the existing
+//! connection instead of a separate protocol:
+//!
+//! 1. The client sends a `time_sync` envelope stamped with its send time `t1` (`request`).
+//! 2. The server stamps when it received the request (`t2`) and sent the reply (`t3`)
+//!    (`respond`, for servers built with this toolkit).
+//! 3. The client records the reply with its receive time `t4` (`record_response`). The
+//!    sample's offset is `((t2 - t1) + (t3 - t4)) / 2` and its round-trip delay
+//!    `(t4 - t1) - (t3 - t2)`.
+//!
+//! Asymmetric queueing makes individual samples noisy, so the offset is taken from the
+//! sample with the smallest delay among the last `window` samples, as NTP's clock filter
+//! does. The drift, how fast the offset changes, is the least-squares slope of the offsets
+//! of the low-delay samples (at most twice the smallest delay) among the last `history`.
+//! `estimate` and `server_now_micros` project the offset forward with the drift, so
+//! estimates stay accurate between samples.
+
+use crate::messages::{Envelope, MessageFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The envelope kind of time sync requests and replies.
+pub const TIME_SYNC_KIND: &str = "time_sync";
+
+/// How many samples the estimator keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSyncConfig {
+    /// The number of recent samples the offset is chosen from.
+    pub window: usize,
+    /// The number of samples the drift is fitted to.
+    pub history: usize,
+}
+
+impl Default for TimeSyncConfig {
+    /// The offset from the last 8 samples and the drift from the last 64.
+    fn default() -> Self {
+        TimeSyncConfig { window: 8, history: 64 }
+    }
+}
+
+/// The payload of `time_sync` envelopes; times are microseconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeSyncPayload {
+    /// When the client sent the request, by the client's clock.
+    pub t1: u64,
+    /// When the server received the request, by the server's clock.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub t2: Option<u64>,
+    /// When the server sent the reply, by the server's clock.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub t3: Option<u64>,
+}
+
+/// One request/reply exchange.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeSample {
+    /// When the reply arrived, by the local clock, in microseconds since the Unix epoch.
+    pub received_at: u64,
+    /// How far the server's clock is ahead of the local one, in microseconds.
+    pub offset_micros: f64,
+    /// The round-trip time without the server's processing time, in microseconds.
+    pub delay_micros: f64,
+}
+
+impl TimeSample {
+    /// Computes a sample from the four timestamps of an exchange.
+    ///
+    /// # Arguments
+    ///
+    /// * `t1` - When the request was sent, by the local clock.
+    /// * `t2` - When the server received it, by the server's clock.
+    /// * `t3` - When the server replied, by the server's clock.
+    /// * `t4` - When the reply arrived, by the local clock.
+    ///
+    /// # Returns
+    ///
+    /// The `TimeSample`.
+    pub fn from_timestamps(t1: u64, t2: u64, t3: u64, t4: u64) -> Self {
+        let (t1, t2, t3, t4) = (t1 as f64, t2 as f64, t3 as f64, t4 as f64);
+        TimeSample {
+            received_at: t4 as u64,
+            offset_micros: ((t2 - t1) + (t3 - t4)) / 2.0,
+            delay_micros: ((t4 - t1) - (t3 - t2)).max(0.0),
+        }
+    }
+}
+
+/// The estimated relation between the server's clock and the local one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockEstimate {
+    /// How far the server's clock is ahead of the local one, in microseconds, now.
+    pub offset_micros: f64,
+    /// How fast the offset grows, in microseconds per second (parts per million).
+    pub drift_ppm: f64,
+    /// The round-trip delay of the sample the offset is based on; the offset is accurate to
+    /// within half of it.
+    pub delay_micros: f64,
+    /// The number of samples recorded.
+    pub samples: u64,
+}
+
+/// Estimates the server's clock offset and drift from time sync exchanges.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::messages::MessageFormat;
+/// use websocket_toolkit::timesync::TimeSync;
+///
+/// let sync = TimeSync::default();
+/// let request = sync.request(MessageFormat::Json).unwrap();
+/// // Sent to the server, which answers with `TimeSync::respond`.
+/// let reply = TimeSync::respond(&request, MessageFormat::Json).unwrap();
+/// let sample = sync.record_response(&reply, MessageFormat::Json).unwrap();
+/// assert!(sample.delay_micros >= 0.0);
+/// assert_eq!(sync.estimate().unwrap().samples, 1);
+/// ```
+#[derive(Debug, Default)]
+pub struct TimeSync {
+    config: TimeSyncConfig,
+    state: Mutex<SyncState>,
+}
+
+/// The estimator's mutable state.
+#[derive(Debug, Default)]
+struct SyncState {
+    /// The most recent samples, oldest first.
+    samples: VecDeque<TimeSample>,
+    /// The number of samples ever recorded.
+    recorded: u64,
+}
+
+impl TimeSync {
+    /// Creates an estimator with no samples.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - How many samples to keep.
+    ///
+    /// # Returns
+    ///
+    /// A new `TimeSync`.
+    pub fn new(config: TimeSyncConfig) -> Self {
+        TimeSync { config, state: Mutex::new(SyncState::default()) }
+    }
+
+    /// Creates a request stamped with the current time.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format the envelope's payload is encoded in.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `time_sync` envelope to send, or an error message if
+    /// encoding fails.
+    pub fn request(&self, format: MessageFormat) -> Result<Envelope, String> {
+        let payload = TimeSyncPayload { t1: now_micros(), t2: None, t3: None };
+        Envelope::from_value(TIME_SYNC_KIND, &payload, format)
+    }
+
+    /// Answers a request on the server side, stamping its receive and send times.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The received `time_sync` envelope.
+    /// * `format` - The format the envelope's payload is encoded in.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the reply, or an error message if the request is malformed.
+    pub fn respond(request: &Envelope, format: MessageFormat) -> Result<Envelope, String> {
+        let received = now_micros();
+        let payload: TimeSyncPayload = read_payload(request, format)?;
+        let reply = TimeSyncPayload { t1: payload.t1, t2: Some(received), t3: Some(now_micros()) };
+        let mut envelope = Envelope::from_value(TIME_SYNC_KIND, &reply, format)?;
+        envelope.id = request.id.clone();
+        Ok(envelope)
+    }
+
+    /// Records a reply received now.
+    ///
+    /// # Arguments
+    ///
+    /// * `reply` - The server's `time_sync` reply.
+    /// * `format` - The format the envelope's payload is encoded in.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the new sample, or an error message if the reply is malformed.
+    pub fn record_response(&self, reply: &Envelope, format: MessageFormat) -> Result<TimeSample, String> {
+        let received = now_micros();
+        let payload = read_payload(reply, format)?;
+        match (payload.t2, payload.t3) {
+            (Some(t2), Some(t3)) => Ok(self.record(TimeSample::from_timestamps(payload.t1, t2, t3, received))),
+            _ => Err("Failed to read time sync reply: missing server timestamps".to_string()),
+        }
+    }
+
+    /// Records a sample computed elsewhere, e.g. from a server's own time sync protocol.
+    ///
+    /// # Arguments
+    ///
+    /// * `sample` - The sample.
+    ///
+    /// # Returns
+    ///
+    /// The sample.
+    pub fn record(&self, sample: TimeSample) -> TimeSample {
+        let mut state = self.state.lock().unwrap();
+        state.samples.push_back(sample);
+        while state.samples.len() > self.config.history.max(self.config.window).max(1) {
+            state.samples.pop_front();
+        }
+        state.recorded += 1;
+        sample
+    }
+
+    /// Returns the current estimate.
+    ///
+    /// # Returns
+    ///
+    /// The `ClockEstimate`, or `None` until a sample has been recorded.
+    pub fn estimate(&self) -> Option<ClockEstimate> {
+        self.estimate_at(now_micros())
+    }
+
+    /// Returns the estimate as of `now`, in local microseconds since the Unix epoch.
+    pub fn estimate_at(&self, now: u64) -> Option<ClockEstimate> {
+        let state = self.state.lock().unwrap();
+        let window = self.config.window.max(1);
+        let best = state
+            .samples
+            .iter()
+            .rev()
+            .take(window)
+            .min_by(|a, b| a.delay_micros.total_cmp(&b.delay_micros))?;
+        let history: Vec<&TimeSample> = state
+            .samples
+            .iter()
+            .skip(state.samples.len().saturating_sub(self.config.history.max(1)))
+            .collect();
+        let min_delay = history.iter().map(|sample| sample.delay_micros).fold(f64::INFINITY, f64::min);
+        let drift_ppm = drift(history.into_iter().filter(|sample| sample.delay_micros <= min_delay * 2.0));
+        let elapsed_secs = (now as f64 - best.received_at as f64) / 1_000_000.0;
+        Some(ClockEstimate {
+            offset_micros: best.offset_micros + drift_ppm * elapsed_secs,
+            drift_ppm,
+            delay_micros: best.delay_micros,
+            samples: state.recorded,
+        })
+    }
+
+    /// Returns the server's current time, in microseconds since the Unix epoch, or `None`
+    /// until a sample has been recorded.
+    pub fn server_now_micros(&self) -> Option<u64> {
+        let now = now_micros();
+        let estimate = self.estimate_at(now)?;
+        Some((now as f64 + estimate.offset_micros).max(0.0) as u64)
+    }
+}
+
+/// Fits a line to the samples' offsets over their receive times, returning its slope in
+/// microseconds per second, or 0 with fewer than two distinct receive times.
+fn drift<'a>(samples: impl Iterator<Item = &'a TimeSample>) -> f64 {
+    let points: Vec<(f64, f64)> = samples
+        .map(|sample| (sample.received_at as f64 / 1_000_000.0, sample.offset_micros))
+        .collect();
+    if points.len() < 2 {
+        return 0.0;
+    }
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let covariance: f64 = points.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+    let variance: f64 = points.iter().map(|(x, _)| (x - mean_x) * (x - mean_x)).sum();
+    if variance == 0.0 {
+        0.0
+    } else {
+        covariance / variance
+    }
+}
+
+/// Decodes the payload of a `time_sync` envelope.
+fn read_payload(envelope: &Envelope, format: MessageFormat) -> Result<TimeSyncPayload, String> {
+    if envelope.kind != TIME_SYNC_KIND {
+        return Err(format!("Failed to read time sync message: unexpected kind {}", envelope.kind));
+    }
+    envelope.payload_as(format)
+}
+
+/// Returns the current time in microseconds since the Unix epoch.
+fn now_micros() -> u64 {
+    u64::try_from(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests the offset and delay of a single exchange.
+    #[test]
+    fn test_sample_from_timestamps() {
+        // The server is 500µs ahead; 100µs each way and 20µs of processing.
+        let sample = TimeSample::from_timestamps(1_000, 1_600, 1_620, 1_220);
+        assert_eq!((sample.offset_micros, sample.delay_micros), (500.0, 200.0));
+    }
+
+    /// Tests that the least-delayed sample sets the offset and the drift is projected.
+    #[test]
+    fn test_estimate_filters_and_drifts() {
+        let sync = TimeSync::new(TimeSyncConfig { window: 4, history: 16 });
+        assert_eq!(sync.estimate_at(0), None);
+        // The offset grows by 10µs per second (10 ppm); odd samples are delayed and skewed.
+        for second in 0..10_u64 {
+            let skew = if second % 2 == 1 { 300.0 } else { 0.0 };
+            sync.record(TimeSample {
+                received_at: second * 1_000_000,
+                offset_micros: 1_000.0 + 10.0 * second as f64 + skew,
+                delay_micros: 100.0 + skew * 2.0,
+            });
+        }
+        let estimate = sync.estimate_at(8_000_000).unwrap();
+        assert_eq!(estimate.delay_micros, 100.0);
+        assert_eq!(estimate.offset_micros, 1_080.0);
+        assert!((estimate.drift_ppm - 10.0).abs() < 1e-6, "Drift was {}", estimate.drift_ppm);
+        let later = sync.estimate_at(18_000_000).unwrap();
+        assert!((later.offset_micros - 1_180.0).abs() < 1e-3);
+        assert_eq!(later.samples, 10);
+    }
+
+    /// Tests a round trip through `request`, `respond` and `record_response`.
+    #[test]
+    fn test_round_trip() {
+        let sync = TimeSync::default();
+        let request = sync.request(MessageFormat::Json).unwrap().with_id("7");
+        let reply = TimeSync::respond(&request, MessageFormat::Json).unwrap();
+        assert_eq!(reply.id.as_deref(), Some("7"));
+        let sample = sync.record_response(&reply, MessageFormat::Json).unwrap();
+        assert!(sample.offset_micros.abs() < 1_000_000.0);
+        assert!(sync.server_now_micros().is_some());
+        assert!(sync.record_response(&request, MessageFormat::Json).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! # `tls.rs`: Trusted roots for `wss://` connections
+//!
+//! With the `rustls-tls` feature, `wss://` connections can use rustls. `client_config`
+//! builds its configuration from a `TlsConfig`: servers are verified against the Mozilla
+//! roots bundled with `webpki-roots`, or, with `TrustRoots::Native` and the `native-roots`
+//! feature, against the operating system's certificate store (the Windows certificate store,
+//! the macOS keychain, or the OpenSSL certificate directories on Linux). The latter lets
+//! certificates issued by an enterprise CA that IT installed on the machine verify without
+//! shipping that CA with the application. `ca_cert` adds roots on top of either set.
+//!
+//! Pass the result to `WebSocketClient::with_tls_config`; `WebSocketController::from_config`
+//! does so for its client.
+
+use crate::config::{TlsConfig, TrustRoots};
+use log::{debug, warn};
+use rustls::{ClientConfig, RootCertStore};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+/// Loads the trusted root certificates.
+///
+/// # Arguments
+///
+/// * `roots` - Where the roots come from.
+///
+/// # Returns
+///
+/// The root store, or an error message if the system store could not be read or holds no
+/// certificates. Certificates of the system store that fail to parse are skipped with a
+/// warning.
+pub fn root_store(roots: TrustRoots) -> Result<RootCertStore, String> {
+    let store = match roots {
+        TrustRoots::Bundled => {
+            let mut store = RootCertStore::empty();
+            store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+            store
+        }
+        #[cfg(feature = "native-roots")]
+        TrustRoots::Native => match rustls_native_certs::load_native_certs() {
+            Ok(store) => store,
+            Err((Some(store), e)) => {
+                warn!("Skipped unreadable certificates in the system certificate store: {}", e);
+                store
+            }
+            Err((None, e)) => return Err(format!("Failed to load the system certificate store: {}", e)),
+        },
+        #[cfg(not(feature = "native-roots"))]
+        TrustRoots::Native => {
+            return Err("Trusting the system certificate store requires the native-roots feature".to_string())
+        }
+    };
+    if store.is_empty() {
+        return Err(format!("No trusted root certificates found in the {:?} store", roots));
+    }
+    debug!("Loaded {} trusted root certificates from the {:?} store", store.len(), roots);
+    Ok(store)
+}
+
+/// Builds the rustls configuration for `wss://` connections.
+///
+/// # Arguments
+///
+/// * `tls` - The trusted roots and the optional `ca_cert` file of additional roots.
+///
+/// # Returns
+///
+/// The configuration, or an error message if the roots could not be loaded or `ca_cert` is
+/// unreadable or holds no certificate.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use websocket_toolkit::config::{TlsConfig, TrustRoots};
+/// use websocket_toolkit::connection::WebSocketClient;
+/// use websocket_toolkit::tls;
+///
+/// let tls = TlsConfig { roots: TrustRoots::Native, ..TlsConfig::default() };
+/// let client = WebSocketClient::new("wss://intranet.example.com/ws", 3)
+///     .with_tls_config(tls::client_config(&tls).unwrap());
+/// ```
+pub fn client_config(tls: &TlsConfig) -> Result<Arc<ClientConfig>, String> {
+    let mut config = ClientConfig::new();
+    config.root_store = root_store(tls.roots)?;
+    if let Some(path) = &tls.ca_cert {
+        let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let (added, _) = config
+            .root_store
+            .add_pem_file(&mut BufReader::new(file))
+            .map_err(|()| format!("Invalid PEM in {}", path.display()))?;
+        if added == 0 {
+            return Err(format!("No certificate in {}", path.display()));
+        }
+    }
+    Ok(Arc::new(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Tests loading the bundled roots, and the errors for unusable roots.
+    #[test]
+    fn test_client_config() {
+        let config = client_config(&TlsConfig::default()).unwrap();
+        assert_eq!(config.root_store.len(), webpki_roots::TLS_SERVER_ROOTS.0.len());
+
+        let path = std::env::temp_dir().join(format!("wstk-tls-test-{}.pem", std::process::id()));
+        std::fs::write(&path, "not a certificate\n").unwrap();
+        let tls = TlsConfig { ca_cert: Some(path.clone()), ..TlsConfig::default() };
+        let error = client_config(&tls).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.contains("No certificate"), "{}", error);
+        let tls = TlsConfig { ca_cert: Some(PathBuf::from("/nonexistent/ca.pem")), ..TlsConfig::default() };
+        assert!(client_config(&tls).err().unwrap().contains("Failed to open"));
+
+        let native = root_store(TrustRoots::Native);
+        if !cfg!(feature = "native-roots") {
+            assert!(native.unwrap_err().contains("native-roots"));
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! # `topics.rs`: Compile-time topic to payload type mapping
+//!
+//! Code that routes envelopes by their `type` usually repeats each topic name twice, once
+//! where it subscribes and once where it picks the payload type to decode, and the two drift
+//! apart. `ws_topics!` declares the mapping once:
+//!
+//! ```rust
+//! use serde::{Deserialize, Serialize};
+//! use websocket_toolkit::messages::{Envelope, MessageFormat};
+//! use websocket_toolkit::topics::{self, Topic};
+//! use websocket_toolkit::ws_topics;
+//!
+//! #[derive(Serialize, Deserialize, Debug, PartialEq)]
+//! struct Trade { price: f64 }
+//! #[derive(Serialize, Deserialize, Debug, PartialEq)]
+//! struct Order { id: u64 }
+//!
+//! ws_topics! {
+//!     #[derive(Debug, PartialEq)]
+//!     pub enum Market {
+//!         "trades" => Trade,
+//!         "orders" => Order,
+//!     }
+//! }
+//!
+//! assert_eq!(Trade::TOPIC, "trades");
+//! assert_eq!(Market::TOPICS, ["trades", "orders"]);
+//!
+//! let envelope = topics::envelope(&Order { id: 7 }, MessageFormat::Json).unwrap();
+//! assert_eq!(envelope.kind, "orders");
+//! let decoded = Market::decode(&envelope, MessageFormat::Json).unwrap();
+//! assert_eq!(decoded, Some(Market::Order(Order { id: 7 })));
+//! assert_eq!(decoded.unwrap().topic(), "orders");
+//! assert_eq!(Market::decode(&Envelope::new("news", Vec::new()), MessageFormat::Json), Ok(None));
+//! ```
+//!
+//! The macro implements `Topic` for every payload type, so `T::TOPIC` names its topic and
+//! `WebSocketController::topic_stream::<T>()` opens a typed stream without a string, and
+//! generates an enum with one variant per type, named after it, with:
+//!
+//! - `TOPICS`, every topic in declaration order, e.g. to subscribe to all of them;
+//! - `decode(envelope, format)`, which picks the payload type from the envelope's `type` and
+//!   returns `Ok(None)` for topics not in the mapping;
+//! - `topic()`, the topic of a decoded message.
+//!
+//! Mapping the same topic twice fails to compile, and so does mapping one type to two topics.
+//! Without an enum declaration (`ws_topics! { "trades" => Trade, "orders" => Order }`), the
+//! enum is `pub enum TopicMessage`. Payload types must be plain type names in scope.
+
+use crate::messages::{Envelope, MessageFormat};
+use serde::Serialize;
+
+/// A payload type bound to a topic by `ws_topics!`.
+pub trait Topic {
+    /// The topic, matched against the envelope `type`.
+    const TOPIC: &'static str;
+}
+
+/// Wraps `value` in an envelope of its topic.
+///
+/// # Arguments
+///
+/// * `value` - The payload.
+/// * `format` - The format the payload is serialized in.
+///
+/// # Returns
+///
+/// A `Result` containing the envelope, or an error message if serialization fails.
+pub fn envelope<T: Topic + Serialize>(value: &T, format: MessageFormat) -> Result<Envelope, String> {
+    Envelope::from_value(T::TOPIC, value, format)
+}
+
+/// Returns whether a topic appears twice in `topics`; used by `ws_topics!` at compile time.
+#[doc(hidden)]
+pub const fn has_duplicates(topics: &[&str]) -> bool {
+    let mut i = 0;
+    while i < topics.len() {
+        let mut j = i + 1;
+        while j < topics.len() {
+            if str_eq(topics[i], topics[j]) {
+                return true;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Compares two strings in a const context.
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Maps topics to payload types; see the `topics` module.
+#[macro_export]
+macro_rules! ws_topics {
+    ($(#[$meta:meta])* $vis:vis enum $name:ident { $($topic:literal => $ty:ident),+ $(,)? }) => {
+        $(#[$meta])*
+        $vis enum $name {
+            $(
+                #[doc = concat!("A `", $topic, "` message.")]
+                $ty($ty),
+            )+
+        }
+
+        $(
+            impl $crate::topics::Topic for $ty {
+                const TOPIC: &'static str = $topic;
+            }
+        )+
+
+        const _: () = assert!(!$crate::topics::has_duplicates(&[$($topic),+]), "ws_topics!: a topic is mapped twice");
+
+        impl $name {
+            /// Every mapped topic, in declaration order.
+            pub const TOPICS: &'static [&'static str] = &[$($topic),+];
+
+            /// Returns the topic of the message.
+            pub fn topic(&self) -> &'static str {
+                match self {
+                    $($name::$ty(_) => $topic,)+
+                }
+            }
+
+            /// Decodes `envelope` as the payload type of its topic.
+            ///
+            /// Returns `Ok(None)` for a topic that is not mapped, or an error message if the
+            /// payload is not a valid value of the topic's type.
+            pub fn decode(
+                envelope: &$crate::messages::Envelope,
+                format: $crate::messages::MessageFormat,
+            ) -> ::std::result::Result<::std::option::Option<Self>, ::std::string::String> {
+                match envelope.kind.as_str() {
+                    $(
+                        $topic => envelope
+                            .payload_as::<$ty>(format)
+                            .map(|payload| ::std::option::Option::Some($name::$ty(payload)))
+                            .map_err(|e| ::std::format!("Failed to decode {} payload: {}", $topic, e)),
+                    )+
+                    _ => ::std::result::Result::Ok(::std::option::Option::None),
+                }
+            }
+        }
+    };
+    ($($topic:literal => $ty:ident),+ $(,)?) => {
+        $crate::ws_topics! {
+            pub enum TopicMessage { $($topic => $ty),+ }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    pub struct Trade {
+        price: f64,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    pub struct Order {
+        id: u64,
+    }
+
+    ws_topics! { "trades" => Trade, "orders" => Order }
+
+    /// Tests the generated constants and decode dispatch, and the duplicate check.
+    #[test]
+    fn test_ws_topics() {
+        assert_eq!((Trade::TOPIC, Order::TOPIC), ("trades", "orders"));
+        assert_eq!(TopicMessage::TOPICS, ["trades", "orders"]);
+
+        let envelope = envelope(&Trade { price: 12.5 }, MessageFormat::Cbor).unwrap();
+        match TopicMessage::decode(&envelope, MessageFormat::Cbor).unwrap() {
+            Some(message @ TopicMessage::Trade(_)) => assert_eq!(message.topic(), "trades"),
+            other => panic!("Unexpected message: {:?}", other.map(|message| message.topic())),
+        }
+        let invalid = Envelope::new("orders", b"not cbor".to_vec());
+        assert!(TopicMessage::decode(&invalid, MessageFormat::Cbor).err().unwrap().contains("orders"));
+        assert!(TopicMessage::decode(&Envelope::new("news", Vec::new()), MessageFormat::Cbor).unwrap().is_none());
+
+        assert!(has_duplicates(&["trades", "orders", "trades"]));
+        assert!(!has_duplicates(&["trades", "trade"]));
+    }
+}
@@ -0,0 +1,363 @@
+//! # `trace_context.rs`: Distributed tracing across WebSocket hops
+//!
+//! HTTP services pass trace context in the W3C `traceparent` header, so a trace follows a
+//! request from service to service. WebSocket messages have no per-message headers, so a
+//! trace usually ends at the socket. `TracePropagation` carries the context in an envelope
+//! header instead (`traceparent` by default, or any field a peer already uses):
+//!
+//! - Outbound, `inject` adds a child of the context in scope (see `TraceContext::scope`), or
+//!   starts a new trace, to every envelope that does not carry one yet.
+//! - Inbound, `inbound` extracts the sender's context so the handler can continue its trace,
+//!   and with the `tracing` feature `span` opens the handling span with its ids recorded.
+//!
+//! Tracing every message of a busy stream is expensive, so messages are sampled: received
+//! context keeps the sender's decision (the `sampled` flag), and traces started here are
+//! sampled with probability `sample_rate`.
+
+use crate::jitter::{random_u64, unit_random};
+use crate::messages::Envelope;
+use log::debug;
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+
+/// The W3C Trace Context header name, the default field for `TracePropagation`.
+pub const TRACEPARENT: &str = "traceparent";
+
+/// The trace-flags bit marking a trace as sampled.
+const SAMPLED: u8 = 0x01;
+
+tokio::task_local! {
+    static CURRENT: TraceContext;
+}
+
+/// A W3C trace context: the trace, the span that sent the message and the trace flags.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::trace_context::TraceContext;
+///
+/// let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+/// let context: TraceContext = header.parse().unwrap();
+/// assert!(context.sampled());
+/// assert_eq!(context.to_string(), header);
+/// assert_eq!(context.child().trace_id, context.trace_id);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceContext {
+    /// The id of the whole trace.
+    pub trace_id: [u8; 16],
+    /// The id of the span that sent the message.
+    pub parent_id: [u8; 8],
+    /// The trace flags; bit 0 is the sampled flag.
+    pub flags: u8,
+}
+
+impl TraceContext {
+    /// Starts a new trace with random ids.
+    ///
+    /// # Arguments
+    ///
+    /// * `sampled` - Whether the trace is recorded.
+    ///
+    /// # Returns
+    ///
+    /// A new root `TraceContext`.
+    pub fn new_root(sampled: bool) -> Self {
+        let mut trace_id = [0; 16];
+        trace_id[..8].copy_from_slice(&nonzero_random().to_be_bytes());
+        trace_id[8..].copy_from_slice(&random_u64().to_be_bytes());
+        TraceContext {
+            trace_id,
+            parent_id: nonzero_random().to_be_bytes(),
+            flags: if sampled { SAMPLED } else { 0 },
+        }
+    }
+
+    /// Parses a `traceparent` header value.
+    ///
+    /// Versions above `00` are accepted as long as they start with the `00` fields, as the
+    /// specification requires.
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - The header value.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the context, or an error message if the value is malformed or
+    /// has an all-zero id.
+    pub fn parse(header: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid traceparent: {}", header);
+        let fields: Vec<&str> = header.trim().split('-').collect();
+        if fields.len() < 4 || fields[0] == "ff" || (fields[0] == "00" && fields.len() != 4) {
+            return Err(invalid());
+        }
+        decode_hex::<1>(fields[0]).ok_or_else(invalid)?;
+        let trace_id: [u8; 16] = decode_hex(fields[1]).ok_or_else(invalid)?;
+        let parent_id: [u8; 8] = decode_hex(fields[2]).ok_or_else(invalid)?;
+        let [flags] = decode_hex(fields[3]).ok_or_else(invalid)?;
+        if trace_id == [0; 16] || parent_id == [0; 8] {
+            return Err(invalid());
+        }
+        Ok(TraceContext { trace_id, parent_id, flags })
+    }
+
+    /// Returns whether the sender records this trace.
+    pub fn sampled(&self) -> bool {
+        self.flags & SAMPLED != 0
+    }
+
+    /// Returns the context for a message sent from within this span: same trace and flags,
+    /// new random span id.
+    pub fn child(&self) -> Self {
+        TraceContext {
+            parent_id: nonzero_random().to_be_bytes(),
+            ..*self
+        }
+    }
+
+    /// Returns the trace id as 32 lowercase hex digits.
+    pub fn trace_id_hex(&self) -> String {
+        encode_hex(&self.trace_id)
+    }
+
+    /// Returns the parent span id as 16 lowercase hex digits.
+    pub fn parent_id_hex(&self) -> String {
+        encode_hex(&self.parent_id)
+    }
+
+    /// Returns the context in scope for the current task, if any.
+    pub fn current() -> Option<TraceContext> {
+        CURRENT.try_with(|context| *context).ok()
+    }
+
+    /// Runs `future` with this context in scope, so envelopes it sends through a controller
+    /// with trace propagation continue this trace.
+    ///
+    /// # Arguments
+    ///
+    /// * `future` - The handler to run.
+    ///
+    /// # Returns
+    ///
+    /// The output of `future`.
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        CURRENT.scope(self, future).await
+    }
+}
+
+impl fmt::Display for TraceContext {
+    /// Formats the context as a version `00` `traceparent` value.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "00-{}-{}-{:02x}", self.trace_id_hex(), self.parent_id_hex(), self.flags)
+    }
+}
+
+impl FromStr for TraceContext {
+    type Err = String;
+
+    fn from_str(header: &str) -> Result<Self, Self::Err> {
+        TraceContext::parse(header)
+    }
+}
+
+/// Where trace context travels in an envelope and how new traces are sampled.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::messages::Envelope;
+/// use websocket_toolkit::trace_context::{TracePropagation, TRACEPARENT};
+///
+/// let propagation = TracePropagation::default();
+/// let envelope = propagation.inject(Envelope::new("order", Vec::new()));
+/// let context = propagation.extract(&envelope).unwrap();
+/// assert_eq!(envelope.header(TRACEPARENT), Some(context.to_string().as_str()));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TracePropagation {
+    /// The envelope header holding the `traceparent` value.
+    pub field: String,
+    /// The fraction of traces started here that are sampled, from `0.0` to `1.0`.
+    pub sample_rate: f64,
+}
+
+impl Default for TracePropagation {
+    /// Uses the `traceparent` header and samples every trace.
+    fn default() -> Self {
+        TracePropagation {
+            field: TRACEPARENT.to_string(),
+            sample_rate: 1.0,
+        }
+    }
+}
+
+impl TracePropagation {
+    /// Reads the trace context of `envelope`.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - The received envelope.
+    ///
+    /// # Returns
+    ///
+    /// The sender's context, or `None` if the header is missing or malformed.
+    pub fn extract(&self, envelope: &Envelope) -> Option<TraceContext> {
+        let header = envelope.header(&self.field)?;
+        TraceContext::parse(header)
+            .map_err(|e| debug!("Ignoring trace context of {} envelope: {}", envelope.kind, e))
+            .ok()
+    }
+
+    /// Returns the context to handle `envelope` in: the sender's, or a new trace sampled with
+    /// probability `sample_rate` if the envelope carries none.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - The received envelope.
+    ///
+    /// # Returns
+    ///
+    /// The `TraceContext` to run the handler in, e.g. with `TraceContext::scope`.
+    pub fn inbound(&self, envelope: &Envelope) -> TraceContext {
+        self.extract(envelope).unwrap_or_else(|| self.new_root())
+    }
+
+    /// Adds trace context to `envelope` unless it already carries some: a child of the
+    /// context in scope, or a new trace sampled with probability `sample_rate`.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - The envelope to send.
+    ///
+    /// # Returns
+    ///
+    /// The envelope with the trace header set.
+    pub fn inject(&self, envelope: Envelope) -> Envelope {
+        if envelope.headers.contains_key(&self.field) {
+            return envelope;
+        }
+        let context = match TraceContext::current() {
+            Some(current) => current.child(),
+            None => self.new_root(),
+        };
+        envelope.with_header(self.field.clone(), context.to_string())
+    }
+
+    /// Opens the span for handling `envelope`, recording its type and trace ids; a disabled
+    /// span if the trace is not sampled.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - The received envelope.
+    ///
+    /// # Returns
+    ///
+    /// The span and the context to run the handler in.
+    #[cfg(feature = "tracing")]
+    pub fn span(&self, envelope: &Envelope) -> (tracing::Span, TraceContext) {
+        let context = self.inbound(envelope);
+        let span = if context.sampled() {
+            tracing::info_span!(
+                "websocket_message",
+                kind = %envelope.kind,
+                trace_id = %context.trace_id_hex(),
+                parent_id = %context.parent_id_hex(),
+            )
+        } else {
+            tracing::Span::none()
+        };
+        (span, context)
+    }
+
+    /// Starts a new trace, sampled with probability `sample_rate`.
+    fn new_root(&self) -> TraceContext {
+        TraceContext::new_root(self.sample_rate >= 1.0 || unit_random() < self.sample_rate)
+    }
+}
+
+/// Returns a random non-zero `u64`; all-zero ids are invalid.
+fn nonzero_random() -> u64 {
+    random_u64().max(1)
+}
+
+/// Decodes exactly `N` bytes from lowercase hex digits.
+fn decode_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    let digits = hex.as_bytes();
+    if digits.len() != 2 * N {
+        return None;
+    }
+    let digit = |c: u8| match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        _ => None,
+    };
+    let mut bytes = [0; N];
+    for (byte, pair) in bytes.iter_mut().zip(digits.chunks(2)) {
+        *byte = (digit(pair[0])? << 4) | digit(pair[1])?;
+    }
+    Some(bytes)
+}
+
+/// Encodes bytes as lowercase hex digits.
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests parsing and formatting against the W3C rules.
+    #[test]
+    fn test_parse_traceparent() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let context = TraceContext::parse(header).unwrap();
+        assert_eq!(context.parent_id, [0x00, 0xf0, 0x67, 0xaa, 0x0b, 0xa9, 0x02, 0xb7]);
+        assert_eq!(context.to_string(), header);
+        assert!(TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00").is_ok_and(|c| !c.sampled()));
+        // Later versions may append fields.
+        assert!(TraceContext::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra").is_ok());
+
+        for invalid in [
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "not a traceparent",
+        ] {
+            assert!(TraceContext::parse(invalid).is_err(), "Expected {} to be rejected", invalid);
+        }
+    }
+
+    /// Tests that outbound envelopes continue the trace in scope, keep existing context and
+    /// start sampled or unsampled traces at the configured rate.
+    #[tokio::test]
+    async fn test_propagation() {
+        let propagation = TracePropagation {
+            field: "x-trace".to_string(),
+            sample_rate: 0.0,
+        };
+        let received = Envelope::new("order", Vec::new())
+            .with_header("x-trace", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+        let context = propagation.inbound(&received);
+        assert!(context.sampled());
+
+        let reply = context.scope(async { propagation.inject(Envelope::new("ack", Vec::new())) }).await;
+        let sent = propagation.extract(&reply).unwrap();
+        assert_eq!((sent.trace_id, sent.flags), (context.trace_id, context.flags));
+        assert_ne!(sent.parent_id, context.parent_id);
+        assert_eq!(propagation.inject(received.clone()), received);
+
+        let unrelated = propagation.inject(Envelope::new("tick", Vec::new()));
+        let root = propagation.extract(&unrelated).unwrap();
+        assert!(!root.sampled());
+        assert_ne!(root.trace_id, context.trace_id);
+        assert!(!propagation.inbound(&Envelope::new("tick", Vec::new())).sampled());
+        assert!(TracePropagation::default().inbound(&Envelope::new("tick", Vec::new())).sampled());
+    }
+}
@@ -0,0 +1,227 @@
+//! # `transform.rs`: Rewriting messages on their way in and out
+//!
+//! Some rewrites belong to every message rather than to the code that sends it: injecting a
+//! trace id, stamping a tenant, or redacting PII before a payload leaves the process.
+//! `MessageMap` is one such rewrite, registered on the controller with
+//! `WebSocketController::map_outbound` or `map_inbound`. A map works at one of two levels:
+//!
+//! - `MessageMap::envelope` rewrites the typed `Envelope`: outbound before schema migration
+//!   and encoding, inbound after decoding and migration, so it sees the local schema.
+//! - `MessageMap::payload` rewrites the serialized bytes: outbound after encoding and before
+//!   compression, inbound after decompression and before decoding.
+//!
+//! Maps run in the order they were registered. An error from a map fails the send or
+//! receive, so a redaction that cannot be applied never lets the message through. Without
+//! compression, payloads are sent untagged and receivers unwrap any that start with an
+//! encoding tag (see `compression::Encoding`), so a payload map whose output can start with
+//! `i`, `p` or `d`, such as encryption, needs compression configured on both ends.
+
+use crate::messages::Envelope;
+use std::fmt;
+use std::sync::Arc;
+
+/// Rewrites a typed envelope.
+pub type EnvelopeMap = Arc<dyn Fn(Envelope) -> Result<Envelope, String> + Send + Sync>;
+
+/// Rewrites a serialized envelope.
+pub type PayloadMap = Arc<dyn Fn(Vec<u8>) -> Result<Vec<u8>, String> + Send + Sync>;
+
+/// A rewrite applied to every envelope sent or received.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::controller::WebSocketController;
+/// use websocket_toolkit::transform::MessageMap;
+///
+/// let mut controller = WebSocketController::new("ws://example.com", 3, None);
+/// controller.map_outbound(MessageMap::envelope(|envelope| Ok(envelope.with_id("trace-42"))));
+/// controller.map_inbound(MessageMap::payload(|payload| Ok(payload)));
+/// ```
+#[derive(Clone)]
+pub enum MessageMap {
+    /// Rewrites the typed envelope.
+    Envelope(EnvelopeMap),
+    /// Rewrites the serialized bytes.
+    Payload(PayloadMap),
+}
+
+impl MessageMap {
+    /// Creates a map over typed envelopes.
+    ///
+    /// # Arguments
+    ///
+    /// * `map` - Returns the rewritten envelope, or an error to fail the message.
+    pub fn envelope<F>(map: F) -> Self
+    where
+        F: Fn(Envelope) -> Result<Envelope, String> + Send + Sync + 'static,
+    {
+        MessageMap::Envelope(Arc::new(map))
+    }
+
+    /// Creates a map over serialized envelopes.
+    ///
+    /// # Arguments
+    ///
+    /// * `map` - Returns the rewritten bytes, or an error to fail the message.
+    pub fn payload<F>(map: F) -> Self
+    where
+        F: Fn(Vec<u8>) -> Result<Vec<u8>, String> + Send + Sync + 'static,
+    {
+        MessageMap::Payload(Arc::new(map))
+    }
+
+    /// Creates an envelope map replacing the given fields of JSON payloads with
+    /// `"[REDACTED]"`, at any depth. Payloads that are not JSON objects or arrays pass
+    /// through unchanged.
+    ///
+    /// Available with the `json` feature.
+    ///
+    /// # Arguments
+    ///
+    /// * `fields` - The names of the fields to redact.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use websocket_toolkit::messages::Envelope;
+    /// use websocket_toolkit::transform::{apply_envelope_maps, MessageMap};
+    ///
+    /// let redact = MessageMap::redact_json_fields(&["ssn"]);
+    /// let envelope = Envelope::new("signup", br#"{"name":"Ada","ssn":"123-45-6789"}"#.to_vec());
+    /// let redacted = apply_envelope_maps(&[redact], envelope).unwrap();
+    /// assert_eq!(redacted.payload, br#"{"name":"Ada","ssn":"[REDACTED]"}"#.to_vec());
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn redact_json_fields(fields: &[&str]) -> Self {
+        let fields: Vec<String> = fields.iter().map(|field| field.to_string()).collect();
+        MessageMap::envelope(move |mut envelope| {
+            let mut value: serde_json::Value = match serde_json::from_slice::<serde_json::Value>(&envelope.payload) {
+                Ok(value) if value.is_object() || value.is_array() => value,
+                _ => return Ok(envelope),
+            };
+            if redact(&mut value, &fields) {
+                envelope.payload = serde_json::to_vec(&value).map_err(|e| format!("Failed to encode redacted payload: {}", e))?;
+            }
+            Ok(envelope)
+        })
+    }
+}
+
+/// Replaces the named fields in `value`, returning whether any was found.
+#[cfg(feature = "json")]
+fn redact(value: &mut serde_json::Value, fields: &[String]) -> bool {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut found = false;
+            for (key, field) in map.iter_mut() {
+                if fields.iter().any(|name| name == key) {
+                    *field = serde_json::Value::String("[REDACTED]".to_string());
+                    found = true;
+                } else {
+                    found |= redact(field, fields);
+                }
+            }
+            found
+        }
+        serde_json::Value::Array(items) => items.iter_mut().fold(false, |found, item| redact(item, fields) | found),
+        _ => false,
+    }
+}
+
+impl fmt::Debug for MessageMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageMap::Envelope(_) => f.write_str("MessageMap::Envelope"),
+            MessageMap::Payload(_) => f.write_str("MessageMap::Payload"),
+        }
+    }
+}
+
+/// Applies the envelope maps in `maps` to `envelope`, in order.
+///
+/// # Arguments
+///
+/// * `maps` - The registered maps; payload maps are skipped.
+/// * `envelope` - The envelope to rewrite.
+///
+/// # Returns
+///
+/// A `Result` containing the rewritten envelope, or the first map's error.
+pub fn apply_envelope_maps(maps: &[MessageMap], envelope: Envelope) -> Result<Envelope, String> {
+    maps.iter().try_fold(envelope, |envelope, map| match map {
+        MessageMap::Envelope(map) => map(envelope),
+        MessageMap::Payload(_) => Ok(envelope),
+    })
+}
+
+/// Applies the payload maps in `maps` to `payload`, in order.
+///
+/// # Arguments
+///
+/// * `maps` - The registered maps; envelope maps are skipped.
+/// * `payload` - The serialized envelope to rewrite.
+///
+/// # Returns
+///
+/// A `Result` containing the rewritten bytes, or the first map's error.
+pub fn apply_payload_maps(maps: &[MessageMap], payload: Vec<u8>) -> Result<Vec<u8>, String> {
+    maps.iter().try_fold(payload, |payload, map| match map {
+        MessageMap::Payload(map) => map(payload),
+        MessageMap::Envelope(_) => Ok(payload),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that maps run in order at their own level and errors stop the chain.
+    #[test]
+    fn test_apply_maps() {
+        let maps = vec![
+            MessageMap::envelope(|envelope| Ok(envelope.with_id("trace-1"))),
+            MessageMap::payload(|mut payload| {
+                payload.push(b'!');
+                Ok(payload)
+            }),
+            MessageMap::envelope(|mut envelope| {
+                envelope.kind.push_str(".v2");
+                Ok(envelope)
+            }),
+        ];
+        let envelope = apply_envelope_maps(&maps, Envelope::new("ping", Vec::new())).unwrap();
+        assert_eq!((envelope.kind.as_str(), envelope.id.as_deref()), ("ping.v2", Some("trace-1")));
+        assert_eq!(apply_payload_maps(&maps, b"hi".to_vec()).unwrap(), b"hi!".to_vec());
+
+        let failing = vec![MessageMap::payload(|_| Err("blocked".to_string())), MessageMap::payload(|_| Ok(Vec::new()))];
+        assert_eq!(apply_payload_maps(&failing, b"hi".to_vec()).unwrap_err(), "blocked");
+    }
+
+    /// Tests that redaction reaches nested fields and leaves other payloads alone.
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_redact_json_fields() {
+        let maps = vec![MessageMap::redact_json_fields(&["password", "card"])];
+        let envelope = Envelope::new("login", br#"{"user":"ada","password":"x","orders":[{"card":"4111"}]}"#.to_vec());
+        let redacted = apply_envelope_maps(&maps, envelope).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&redacted.payload).unwrap();
+        assert_eq!(value["password"], "[REDACTED]");
+        assert_eq!(value["orders"][0]["card"], "[REDACTED]");
+        assert_eq!(value["user"], "ada");
+
+        let binary = Envelope::new("blob", vec![0xff, 0x00]);
+        assert_eq!(apply_envelope_maps(&maps, binary.clone()).unwrap(), binary);
+    }
+
+    /// Tests that JSON scalars and payloads without the fields pass through unchanged.
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_redact_json_fields_untouched() {
+        let maps = vec![MessageMap::redact_json_fields(&["ssn"])];
+        for payload in [&b"42"[..], br#""ssn""#, br#"{"name":"Ada"}"#] {
+            let envelope = Envelope::new("signup", payload.to_vec());
+            assert_eq!(apply_envelope_maps(&maps, envelope.clone()).unwrap(), envelope);
+        }
+    }
+}
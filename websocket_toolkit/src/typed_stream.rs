@@ -0,0 +1,472 @@
+//! # `typed_stream.rs`: Typed per-topic streams
+//!
+//! Feeds such as tickers or order books arrive as envelopes whose `type` names the topic.
+//! `WebSocketController::typed_stream::<T>(topic)` returns a `TypedStream` that yields only
+//! the envelopes of that topic, with their payload decoded as `T` in the controller's format,
+//! so consumers never touch raw frames.
+//!
+//! Servers usually only publish topics a client subscribed to, and forget the subscriptions
+//! when the connection drops. While a stream of a topic is open, the controller therefore
+//! sends a `subscribe_envelope(topic)` as soon as the first stream is created (on its current
+//! pipeline, if any) and again on every connection it opens afterwards, so subscriptions
+//! survive reconnects. Once the last stream of a topic is dropped, it sends an
+//! `unsubscribe_envelope(topic)`.
+//!
+//! Replaying every subscription on every connection can trip a server's rate limits after a
+//! failover, when the backup endpoint has resumed the session and already knows most of
+//! them. With `WebSocketController::set_subscription_sync`, the controller instead sends a
+//! `subscriptions_query()` right after connecting (and authenticating) and reads the
+//! server's first reply: a `subscriptions_envelope` listing the topics it has acknowledged.
+//! It then sends only the `SubscriptionDiff`: subscriptions for the open topics the server
+//! lacks and unsubscriptions for the ones no stream wants any more. Subscriptions recorded in
+//! a `SubscriptionManager` are diffed the same way, by key. If the first reply is
+//! anything else, or none arrives in time, it replays every subscription as before; the reply
+//! is consumed either way.
+//!
+//! Streams read the controller's fan-out, so the messages must reach it: through
+//! `receive_inbound`, or by forwarding a pipeline with `controller.fanout().forward(receiver)`.
+//! Each stream decodes the messages it sees with `decode_envelope`; replay protection rejects
+//! a message decoded twice, so do not combine it with several consumers of the same message.
+
+use crate::controller::WebSocketController;
+use crate::fanout::LagPolicy;
+use crate::messages::Envelope;
+use crate::pipeline::PipelineSender;
+use crate::tasks::spawn_named;
+use futures_util::stream::{self, BoxStream, Stream, StreamExt};
+use futures_util::SinkExt;
+use log::{debug, warn};
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error as StdError;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/// The envelope kind of subscription requests. The payload is the topic.
+pub const SUBSCRIBE_KIND: &str = "subscribe";
+
+/// The envelope kind of unsubscription requests. The payload is the topic.
+pub const UNSUBSCRIBE_KIND: &str = "unsubscribe";
+
+/// Builds the request to subscribe to `topic`.
+///
+/// # Arguments
+///
+/// * `topic` - The topic, matched against the `type` of published envelopes.
+///
+/// # Returns
+///
+/// An `Envelope` of kind `SUBSCRIBE_KIND`.
+pub fn subscribe_envelope(topic: &str) -> Envelope {
+    Envelope::new(SUBSCRIBE_KIND, topic.as_bytes().to_vec())
+}
+
+/// Builds the request to unsubscribe from `topic`.
+///
+/// # Arguments
+///
+/// * `topic` - The topic.
+///
+/// # Returns
+///
+/// An `Envelope` of kind `UNSUBSCRIBE_KIND`.
+pub fn unsubscribe_envelope(topic: &str) -> Envelope {
+    Envelope::new(UNSUBSCRIBE_KIND, topic.as_bytes().to_vec())
+}
+
+/// The envelope kind of the subscription list query and the server's answer. The answer's
+/// payload is the acknowledged topics, one per line.
+pub const SUBSCRIPTIONS_KIND: &str = "subscriptions";
+
+/// Builds the query for the topics the server has acknowledged.
+///
+/// # Returns
+///
+/// An `Envelope` of kind `SUBSCRIPTIONS_KIND` with an empty payload.
+pub fn subscriptions_query() -> Envelope {
+    Envelope::new(SUBSCRIPTIONS_KIND, Vec::new())
+}
+
+/// Builds the server's answer to `subscriptions_query`.
+///
+/// # Arguments
+///
+/// * `topics` - The topics the server has acknowledged for the connection.
+///
+/// # Returns
+///
+/// An `Envelope` of kind `SUBSCRIPTIONS_KIND`.
+pub fn subscriptions_envelope<'a>(topics: impl IntoIterator<Item = &'a str>) -> Envelope {
+    Envelope::new(SUBSCRIPTIONS_KIND, topics.into_iter().collect::<Vec<_>>().join("\n").into_bytes())
+}
+
+/// The subscription changes that bring a server from the topics it acknowledged to the
+/// topics the client wants.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubscriptionDiff {
+    /// Topics to subscribe to, in order.
+    pub subscribe: Vec<String>,
+    /// Topics to unsubscribe from, in order.
+    pub unsubscribe: Vec<String>,
+}
+
+impl SubscriptionDiff {
+    /// Computes the changes from `acknowledged` to `desired`.
+    ///
+    /// # Arguments
+    ///
+    /// * `desired` - The topics the client wants.
+    /// * `acknowledged` - The topics the server already has.
+    ///
+    /// # Returns
+    ///
+    /// A new `SubscriptionDiff`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::BTreeSet;
+    /// use websocket_toolkit::typed_stream::SubscriptionDiff;
+    ///
+    /// let desired: BTreeSet<String> = ["books", "tickers"].map(String::from).into();
+    /// let acknowledged: BTreeSet<String> = ["tickers", "trades"].map(String::from).into();
+    /// let diff = SubscriptionDiff::new(&desired, &acknowledged);
+    /// assert_eq!(diff.subscribe, vec!["books"]);
+    /// assert_eq!(diff.unsubscribe, vec!["trades"]);
+    /// ```
+    pub fn new(desired: &BTreeSet<String>, acknowledged: &BTreeSet<String>) -> Self {
+        SubscriptionDiff {
+            subscribe: desired.difference(acknowledged).cloned().collect(),
+            unsubscribe: acknowledged.difference(desired).cloned().collect(),
+        }
+    }
+
+    /// Returns whether the server already has exactly the desired topics.
+    pub fn is_empty(&self) -> bool {
+        self.subscribe.is_empty() && self.unsubscribe.is_empty()
+    }
+
+    /// Returns the requests making the changes: unsubscriptions first, then subscriptions.
+    pub fn envelopes(&self) -> Vec<Envelope> {
+        let unsubscribe = self.unsubscribe.iter().map(|topic| unsubscribe_envelope(topic));
+        unsubscribe.chain(self.subscribe.iter().map(|topic| subscribe_envelope(topic))).collect()
+    }
+}
+
+/// Asks the server for the topics it has acknowledged, for `set_subscription_sync`.
+///
+/// # Returns
+///
+/// A `Result` containing the topics, `None` if the server did not answer with a
+/// `SUBSCRIPTIONS_KIND` envelope within `timeout`, or an error if the connection failed.
+pub(crate) async fn acknowledged_topics<S>(
+    controller: &WebSocketController,
+    ws_stream: &mut WebSocketStream<S>,
+    timeout: Duration,
+) -> Result<Option<BTreeSet<String>>, Box<dyn StdError>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    ws_stream.send(controller.subscription_frame(&subscriptions_query())?).await?;
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let reply = match tokio::time::timeout_at(deadline, ws_stream.next()).await {
+            Ok(reply) => reply,
+            Err(_) => {
+                warn!("No subscription list within {:?}; replaying every subscription", timeout);
+                return Ok(None);
+            }
+        };
+        let payload = match reply {
+            Some(Ok(Message::Text(text))) => text.into_bytes(),
+            Some(Ok(Message::Binary(data))) => data,
+            Some(Ok(Message::Close(_))) | None => return Err("Connection closed before the subscription list".into()),
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => return Err(format!("Failed to read the subscription list: {}", e).into()),
+        };
+        return Ok(match controller.decode_envelope(&payload) {
+            Ok(envelope) if envelope.kind == SUBSCRIPTIONS_KIND => Some(
+                String::from_utf8_lossy(&envelope.payload)
+                    .lines()
+                    .filter(|topic| !topic.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            ),
+            _ => {
+                warn!("The server did not answer with its subscriptions; replaying every subscription");
+                None
+            }
+        });
+    }
+}
+
+/// The topics with open streams, and the pipeline to announce changes on.
+#[derive(Debug, Default)]
+pub(crate) struct Topics {
+    /// The number of open streams per topic.
+    open: Mutex<BTreeMap<String, usize>>,
+    /// The sender of the controller's most recent pipeline.
+    live: Mutex<Option<PipelineSender>>,
+}
+
+impl Topics {
+    /// Counts a new stream of `topic`; returns whether it is the first.
+    fn open(&self, topic: &str) -> bool {
+        let mut open = self.open.lock().unwrap();
+        let count = open.entry(topic.to_string()).or_insert(0);
+        *count += 1;
+        *count == 1
+    }
+
+    /// Counts a dropped stream of `topic`; returns whether it was the last.
+    fn close(&self, topic: &str) -> bool {
+        let mut open = self.open.lock().unwrap();
+        match open.get_mut(topic) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                false
+            }
+            Some(_) => {
+                open.remove(topic);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the topics with at least one open stream.
+    pub(crate) fn topics(&self) -> Vec<String> {
+        self.open.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Remembers the pipeline subscriptions are announced on from now on.
+    pub(crate) fn set_live(&self, sender: PipelineSender) {
+        *self.live.lock().unwrap() = Some(sender);
+    }
+
+    /// Queues `frame` on the live pipeline, if one is open; later connections subscribe on
+    /// their own.
+    fn announce(&self, frame: Message) {
+        let live = self.live.lock().unwrap().clone().filter(|sender| !sender.is_closed());
+        if let Some(sender) = live {
+            spawn_named("websocket_toolkit::typed_stream_announce", async move {
+                if sender.send(frame).await.is_err() {
+                    debug!("Pipeline closed before a subscription change was sent");
+                }
+            });
+        }
+    }
+}
+
+/// A stream of the decoded payloads of one topic, from
+/// `WebSocketController::typed_stream`.
+///
+/// Yields `Err` for envelopes of the topic whose payload is not a valid `T`, and ends when
+/// the controller's fan-out has no publisher left.
+///
+/// # Examples
+///
+/// ```rust
+/// use futures_util::StreamExt;
+/// use serde::Deserialize;
+/// use std::sync::Arc;
+/// use websocket_toolkit::controller::WebSocketController;
+/// use websocket_toolkit::messages::{Envelope, InboundMessage, MessageFormat};
+///
+/// #[derive(Deserialize, Debug, PartialEq)]
+/// struct Ticker {
+///     symbol: String,
+///     price: f64,
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let controller = Arc::new(WebSocketController::new("ws://example.com", 0, None));
+/// let mut tickers = controller.typed_stream::<Ticker>("tickers");
+///
+/// let payload = br#"{"symbol":"ACME","price":12.5}"#.to_vec();
+/// let encoded = Envelope::new("tickers", payload).encode(MessageFormat::Json).unwrap();
+/// controller.fanout().publish(InboundMessage::Text(String::from_utf8(encoded).unwrap()));
+///
+/// let ticker = tickers.next().await.unwrap().unwrap();
+/// assert_eq!(ticker, Ticker { symbol: "ACME".into(), price: 12.5 });
+/// # }
+/// ```
+pub struct TypedStream<T> {
+    inner: BoxStream<'static, Result<T, String>>,
+    topic: String,
+    controller: Arc<WebSocketController>,
+}
+
+impl<T: DeserializeOwned + Send + 'static> TypedStream<T> {
+    /// Opens a stream of `topic` on `controller`, subscribing if it is the topic's first.
+    pub(crate) fn new(controller: Arc<WebSocketController>, topic: &str) -> Self {
+        // Subscribe to the fan-out first, so nothing published after the server subscription
+        // is missed.
+        let subscription = controller.fanout().subscribe_with(LagPolicy::Skip);
+        if controller.topics().open(topic) {
+            match controller.subscription_frame(&subscribe_envelope(topic)) {
+                Ok(frame) => controller.topics().announce(frame),
+                Err(e) => warn!("Failed to encode subscription to {}: {}", topic, e),
+            }
+        }
+        let state = (subscription, controller.clone(), topic.to_string());
+        let inner = stream::unfold(state, |(mut subscription, controller, topic)| async move {
+            loop {
+                let message = subscription.recv().await?;
+                let envelope = match controller.decode_envelope(message.as_bytes()) {
+                    Ok(envelope) if envelope.kind == topic => envelope,
+                    _ => continue,
+                };
+                let item = envelope
+                    .payload_as::<T>(controller.format())
+                    .map_err(|e| format!("Failed to decode {} payload: {}", topic, e));
+                return Some((item, (subscription, controller, topic)));
+            }
+        });
+        TypedStream { inner: Box::pin(inner), topic: topic.to_string(), controller }
+    }
+}
+
+impl<T> TypedStream<T> {
+    /// Returns the topic of the stream.
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+}
+
+impl<T> Stream for TypedStream<T> {
+    type Item = Result<T, String>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+impl<T> Drop for TypedStream<T> {
+    fn drop(&mut self) {
+        if self.controller.topics().close(&self.topic) {
+            match self.controller.subscription_frame(&unsubscribe_envelope(&self.topic)) {
+                Ok(frame) => self.controller.topics().announce(frame),
+                Err(e) => warn!("Failed to encode unsubscription from {}: {}", self.topic, e),
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for TypedStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedStream").field("topic", &self.topic).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::MessageFormat;
+    use crate::pipeline::PipelineConfig;
+    use crate::testing::EchoServer;
+    use futures_util::StreamExt;
+    use serde::{Deserialize, Serialize};
+    use std::time::Duration;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Ticker {
+        symbol: String,
+        price: f64,
+    }
+
+    /// Waits for the next subscription change the echo server reflected.
+    async fn next_change(changes: &mut crate::fanout::Subscription) -> (String, String) {
+        loop {
+            let message = changes.recv().await.unwrap();
+            let envelope = Envelope::decode(message.as_bytes(), MessageFormat::Json).unwrap();
+            if envelope.kind == SUBSCRIBE_KIND || envelope.kind == UNSUBSCRIBE_KIND {
+                return (envelope.kind, String::from_utf8(envelope.payload).unwrap());
+            }
+        }
+    }
+
+    /// Tests that a synced connection sends only the missing subscription changes, and that
+    /// an unexpected reply falls back to replaying them all.
+    #[tokio::test]
+    async fn test_subscription_sync() {
+        let mut server = crate::testing::MockServer::start().await.unwrap();
+        let mut controller = WebSocketController::new(server.url(), 0, None);
+        controller.set_subscription_sync(Some(Duration::from_secs(5)));
+        let controller = Arc::new(controller);
+        let _books = controller.typed_stream::<String>("books");
+        let _tickers = controller.typed_stream::<String>("tickers");
+        let kinds = |message: Message| {
+            let envelope = Envelope::decode(&message.into_data(), MessageFormat::Json).unwrap();
+            (envelope.kind, String::from_utf8(envelope.payload).unwrap())
+        };
+
+        let connecting = tokio::spawn({
+            let controller = controller.clone();
+            async move { controller.connect().await.map(|_| ()).map_err(|e| e.to_string()) }
+        });
+        let mut connection = server.accept().await;
+        assert_eq!(kinds(connection.next_message().await.unwrap()), (SUBSCRIPTIONS_KIND.to_string(), String::new()));
+        let answer = subscriptions_envelope(["tickers", "trades"]).encode(MessageFormat::Json).unwrap();
+        connection.send(Message::Binary(answer)).await;
+        assert_eq!(kinds(connection.next_message().await.unwrap()), (UNSUBSCRIBE_KIND.to_string(), "trades".to_string()));
+        assert_eq!(kinds(connection.next_message().await.unwrap()), (SUBSCRIBE_KIND.to_string(), "books".to_string()));
+        connecting.await.unwrap().unwrap();
+        connection.expect_silence_for(Duration::from_millis(50)).await;
+
+        let connecting = tokio::spawn({
+            let controller = controller.clone();
+            async move { controller.connect().await.map(|_| ()).map_err(|e| e.to_string()) }
+        });
+        let mut connection = server.accept().await;
+        connection.next_message().await.unwrap();
+        connection.send(Message::Text("hello".to_string())).await;
+        assert_eq!(kinds(connection.next_message().await.unwrap()), (SUBSCRIBE_KIND.to_string(), "books".to_string()));
+        assert_eq!(kinds(connection.next_message().await.unwrap()), (SUBSCRIBE_KIND.to_string(), "tickers".to_string()));
+        connecting.await.unwrap().unwrap();
+    }
+
+    /// Tests filtering and decoding, and that subscriptions are sent when streams open and
+    /// close and again on every new connection.
+    #[tokio::test]
+    async fn test_typed_streams() {
+        let server = EchoServer::start().await.unwrap();
+        let controller = Arc::new(WebSocketController::new(server.url(), 0, None));
+        let mut changes = controller.fanout().subscribe_with(LagPolicy::Skip);
+        let mut tickers = controller.typed_stream::<Ticker>("tickers");
+
+        let (sender, receiver, _tasks) = controller.connect_pipeline(PipelineConfig::default()).await.unwrap();
+        controller.fanout().forward(receiver);
+        assert_eq!(next_change(&mut changes).await, (SUBSCRIBE_KIND.to_string(), "tickers".to_string()));
+
+        let ticker = Ticker { symbol: "ACME".into(), price: 12.5 };
+        for envelope in [
+            Envelope::from_value("quotes", &"ignored", MessageFormat::Json).unwrap(),
+            Envelope::new("tickers", b"not json".to_vec()),
+            Envelope::from_value("tickers", &ticker, MessageFormat::Json).unwrap(),
+        ] {
+            sender.send(controller.subscription_frame(&envelope).unwrap()).await.unwrap();
+        }
+        assert!(tickers.next().await.unwrap().is_err());
+        assert_eq!(tickers.next().await.unwrap().unwrap(), ticker);
+
+        let quotes = controller.typed_stream::<String>("quotes");
+        assert_eq!(next_change(&mut changes).await, (SUBSCRIBE_KIND.to_string(), "quotes".to_string()));
+        drop(quotes);
+        assert_eq!(next_change(&mut changes).await, (UNSUBSCRIBE_KIND.to_string(), "quotes".to_string()));
+
+        // A new connection subscribes again.
+        let (_sender, receiver, _tasks) = controller.connect_pipeline(PipelineConfig::default()).await.unwrap();
+        controller.fanout().forward(receiver);
+        let resubscribed = tokio::time::timeout(Duration::from_secs(5), next_change(&mut changes)).await.unwrap();
+        assert_eq!(resubscribed, (SUBSCRIBE_KIND.to_string(), "tickers".to_string()));
+        assert_eq!(tickers.topic(), "tickers");
+    }
+}
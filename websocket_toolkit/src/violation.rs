@@ -0,0 +1,193 @@
+//! # `violation.rs`: Structured protocol violation reports
+//!
+//! tungstenite rejects frames that break RFC 6455 (unknown opcodes, oversized or fragmented
+//! control frames, invalid UTF-8 in text messages, ...) with an error whose text is meant for
+//! humans. `ProtocolViolation` turns such errors into a structured report: a stable rule id,
+//! the direction of the offending traffic, and an excerpt of the offending bytes where they
+//! are known. Reports serialize with serde, so a fleet can ship them to one place and count
+//! violations per rule.
+//!
+//! `WebSocketController::set_violation_hook` receives a report for every violation the
+//! controller's receive and send methods run into, including UTF-8 checks made by the
+//! controller itself (`TextMode::Validated` and text framing).
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use tokio_tungstenite::tungstenite::error::{CapacityError, Error, ProtocolError};
+
+/// The most bytes kept in a report's excerpt.
+pub const EXCERPT_LEN: usize = 16;
+
+/// Which way the offending traffic was going.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Received from the peer.
+    Inbound,
+    /// Being sent to the peer.
+    Outbound,
+}
+
+/// The protocol rule that was broken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ViolationRule {
+    /// A frame used an unknown or reserved opcode.
+    InvalidOpcode,
+    /// A control frame carried more than 125 bytes.
+    OversizedControlFrame,
+    /// A control frame was fragmented.
+    FragmentedControlFrame,
+    /// A text message was not valid UTF-8.
+    InvalidUtf8,
+    /// A frame set reserved bits no extension was negotiated for.
+    ReservedBits,
+    /// A frame was masked or unmasked against its direction.
+    Masking,
+    /// A continuation frame arrived without a message to continue, or a new message
+    /// started before the previous one was finished.
+    Fragmentation,
+    /// A message exceeded the configured maximum size.
+    MessageTooLarge,
+    /// Any other protocol error.
+    Other,
+}
+
+impl ViolationRule {
+    /// Returns the rule's stable id, e.g. `invalid-opcode`, for aggregation.
+    pub fn id(self) -> &'static str {
+        match self {
+            ViolationRule::InvalidOpcode => "invalid-opcode",
+            ViolationRule::OversizedControlFrame => "oversized-control-frame",
+            ViolationRule::FragmentedControlFrame => "fragmented-control-frame",
+            ViolationRule::InvalidUtf8 => "invalid-utf8",
+            ViolationRule::ReservedBits => "reserved-bits",
+            ViolationRule::Masking => "masking",
+            ViolationRule::Fragmentation => "fragmentation",
+            ViolationRule::MessageTooLarge => "message-too-large",
+            ViolationRule::Other => "other",
+        }
+    }
+}
+
+/// A structured report of one protocol violation.
+///
+/// # Examples
+///
+/// ```rust
+/// use websocket_toolkit::violation::{Direction, ProtocolViolation, ViolationRule};
+///
+/// let report = ProtocolViolation::invalid_utf8(b"caf\xe9 au lait", 3, Direction::Inbound);
+/// assert_eq!(report.rule.id(), "invalid-utf8");
+/// assert_eq!(report.offset, Some(3));
+/// assert_eq!(report.excerpt, b"\xe9 au lait".to_vec());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolViolation {
+    /// The rule broken.
+    pub rule: ViolationRule,
+    /// Which way the offending traffic was going.
+    pub direction: Direction,
+    /// The position of the offending byte in the payload, where known.
+    pub offset: Option<usize>,
+    /// Up to `EXCERPT_LEN` offending bytes, starting at `offset`; the opcode for opcode
+    /// violations; empty where the bytes are unknown.
+    pub excerpt: Vec<u8>,
+    /// The underlying error message.
+    pub detail: String,
+}
+
+impl ProtocolViolation {
+    /// Classifies a tungstenite error.
+    ///
+    /// # Arguments
+    ///
+    /// * `error` - The error returned by a receive or send.
+    /// * `direction` - Whether the error came from receiving or sending.
+    ///
+    /// # Returns
+    ///
+    /// The report, or `None` if the error is not a protocol violation (e.g. an I/O error or
+    /// a closed connection).
+    pub fn from_error(error: &Error, direction: Direction) -> Option<Self> {
+        let (rule, excerpt) = match error {
+            Error::Protocol(protocol) => match protocol {
+                ProtocolError::InvalidOpcode(opcode)
+                | ProtocolError::UnknownControlFrameType(opcode)
+                | ProtocolError::UnknownDataFrameType(opcode) => (ViolationRule::InvalidOpcode, vec![*opcode]),
+                ProtocolError::ControlFrameTooBig => (ViolationRule::OversizedControlFrame, Vec::new()),
+                ProtocolError::FragmentedControlFrame => (ViolationRule::FragmentedControlFrame, Vec::new()),
+                ProtocolError::NonZeroReservedBits => (ViolationRule::ReservedBits, Vec::new()),
+                ProtocolError::MaskedFrameFromServer | ProtocolError::UnmaskedFrameFromClient => {
+                    (ViolationRule::Masking, Vec::new())
+                }
+                ProtocolError::UnexpectedContinueFrame | ProtocolError::ExpectedFragment(_) => {
+                    (ViolationRule::Fragmentation, Vec::new())
+                }
+                _ => (ViolationRule::Other, Vec::new()),
+            },
+            Error::Utf8 => (ViolationRule::InvalidUtf8, Vec::new()),
+            Error::Capacity(CapacityError::MessageTooLong { .. }) => (ViolationRule::MessageTooLarge, Vec::new()),
+            _ => return None,
+        };
+        Some(ProtocolViolation {
+            rule,
+            direction,
+            offset: None,
+            excerpt,
+            detail: error.to_string(),
+        })
+    }
+
+    /// Reports a payload that is not valid UTF-8.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The payload.
+    /// * `valid_up_to` - The length of its valid prefix, from `Utf8Error::valid_up_to`.
+    /// * `direction` - Which way the payload was going.
+    ///
+    /// # Returns
+    ///
+    /// The report, with an excerpt starting at the first invalid byte.
+    pub fn invalid_utf8(payload: &[u8], valid_up_to: usize, direction: Direction) -> Self {
+        let start = valid_up_to.min(payload.len());
+        let end = (start + EXCERPT_LEN).min(payload.len());
+        ProtocolViolation {
+            rule: ViolationRule::InvalidUtf8,
+            direction,
+            offset: Some(start),
+            excerpt: payload[start..end].to_vec(),
+            detail: format!("invalid UTF-8 after {} valid bytes", valid_up_to),
+        }
+    }
+}
+
+impl fmt::Display for ProtocolViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} protocol violation {}: {}", self.direction, self.rule.id(), self.detail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::Data;
+
+    /// Tests the classification of tungstenite errors.
+    #[test]
+    fn test_from_error() {
+        let opcode = ProtocolViolation::from_error(&Error::Protocol(ProtocolError::UnknownDataFrameType(3)), Direction::Inbound).unwrap();
+        assert_eq!((opcode.rule, opcode.excerpt), (ViolationRule::InvalidOpcode, vec![3]));
+        let fragment = ProtocolViolation::from_error(&Error::Protocol(ProtocolError::ExpectedFragment(Data::Text)), Direction::Inbound).unwrap();
+        assert_eq!(fragment.rule, ViolationRule::Fragmentation);
+        let too_big = ProtocolViolation::from_error(&Error::Protocol(ProtocolError::ControlFrameTooBig), Direction::Outbound).unwrap();
+        assert_eq!((too_big.rule.id(), too_big.direction), ("oversized-control-frame", Direction::Outbound));
+        assert_eq!(ProtocolViolation::from_error(&Error::ConnectionClosed, Direction::Inbound), None);
+
+        let utf8 = ProtocolViolation::invalid_utf8(&[b'a'; 40], 40, Direction::Outbound);
+        assert_eq!((utf8.offset, utf8.excerpt.len()), (Some(40), 0));
+        let utf8 = ProtocolViolation::invalid_utf8(&[0xff; 40], 0, Direction::Outbound);
+        assert_eq!(utf8.excerpt.len(), EXCERPT_LEN);
+    }
+}
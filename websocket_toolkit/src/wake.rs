@@ -0,0 +1,112 @@
+//! # `wake.rs`: Detecting resume from sleep
+//!
+//! A laptop that sleeps for an hour wakes with sockets the kernel still considers open,
+//! while the server dropped them long ago. Nothing fails until the next write goes
+//! unanswered, which with a long keep-alive interval can take minutes, and meanwhile the
+//! application believes it is connected.
+//!
+//! There is no portable wake notification, but sleep leaves a trace: timers stop. On Linux
+//! and macOS the monotonic clock stops too while the wall clock keeps running, and on other
+//! platforms a short timer fires far later than it was due. `WakeProbe` watches for either
+//! gap with a cheap periodic check. Set with `WebSocketController::set_wake_probe`, the
+//! keep-alive task of every pipelined connection (`maintain_pipeline`) probes the connection
+//! as soon as a wake is detected: it pings and closes the connection if no pong arrives
+//! within `probe_timeout` (see `ConnectionHandle::probe`), so reconnection starts at once.
+
+use std::time::{Duration, SystemTime};
+use tokio::time::{Instant, MissedTickBehavior};
+
+/// How a resume from sleep is detected and verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WakeProbe {
+    /// How often the clocks are compared.
+    pub check_interval: Duration,
+    /// How much time must go missing between two checks to count as sleep.
+    pub min_gap: Duration,
+    /// How long the probe after a wake waits for its pong.
+    pub probe_timeout: Duration,
+}
+
+impl Default for WakeProbe {
+    /// Checks every second, treats 5 missing seconds as sleep and waits 5 seconds for the
+    /// probe's pong.
+    fn default() -> Self {
+        WakeProbe {
+            check_interval: Duration::from_secs(1),
+            min_gap: Duration::from_secs(5),
+            probe_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl WakeProbe {
+    /// Waits until the machine resumes from sleep.
+    ///
+    /// # Returns
+    ///
+    /// About how long the machine slept.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # async fn example(handle: websocket_toolkit::handle::ConnectionHandle) {
+    /// use websocket_toolkit::wake::WakeProbe;
+    ///
+    /// let wake = WakeProbe::default();
+    /// loop {
+    ///     let slept = wake.wait_for_wake().await;
+    ///     if handle.probe(wake.probe_timeout).await.is_err() {
+    ///         eprintln!("Connection died during {:?} of sleep", slept);
+    ///         break;
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub async fn wait_for_wake(&self) -> Duration {
+        let mut ticker = tokio::time::interval(self.check_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        ticker.tick().await;
+        let mut last = (Instant::now(), SystemTime::now());
+        loop {
+            ticker.tick().await;
+            let now = (Instant::now(), SystemTime::now());
+            let monotonic = now.0.duration_since(last.0);
+            // A wall clock set backwards is not sleep.
+            let wall = now.1.duration_since(last.1).unwrap_or_default();
+            last = now;
+            if let Some(gap) = self.sleep_gap(monotonic, wall) {
+                return gap;
+            }
+        }
+    }
+
+    /// Returns how long the machine slept between two checks, if it did.
+    ///
+    /// # Arguments
+    ///
+    /// * `monotonic` - The time between the checks on the monotonic clock.
+    /// * `wall` - The time between the checks on the wall clock.
+    fn sleep_gap(&self, monotonic: Duration, wall: Duration) -> Option<Duration> {
+        // The monotonic clock stopped while the wall clock ran on, or the check fired late.
+        let gap = wall.saturating_sub(monotonic).max(monotonic.saturating_sub(self.check_interval));
+        (gap >= self.min_gap).then_some(gap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that missing time on either clock counts as sleep, and ordinary delays do not.
+    #[test]
+    fn test_sleep_gap() {
+        let probe = WakeProbe::default();
+        let second = Duration::from_secs(1);
+        assert_eq!(probe.sleep_gap(second, second), None);
+        assert_eq!(probe.sleep_gap(second, Duration::from_millis(1200)), None);
+        // Linux and macOS: the monotonic clock stopped.
+        assert_eq!(probe.sleep_gap(second, Duration::from_secs(3601)), Some(Duration::from_secs(3600)));
+        // Elsewhere: the check fired an hour late.
+        assert_eq!(probe.sleep_gap(Duration::from_secs(3601), Duration::from_secs(3601)), Some(Duration::from_secs(3600)));
+    }
+}
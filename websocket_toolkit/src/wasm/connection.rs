@@ -0,0 +1,340 @@
+//! # `wasm/connection.rs`: browser WebSocket connection handling module
+//!
+//! This module is compiled in place of `connection.rs` when building for `wasm32` targets with
+//! the `wasm` feature. It exposes the same `WebSocketClient` API, backed by the browser's
+//! `WebSocket` object instead of tokio and tungstenite, so shared client logic compiles for both
+//! native and browser frontends.
+
+#![allow(unused_imports)]
+use log::{info, error, debug};
+use tokio_tungstenite::tungstenite::{Error, Message};
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use futures_channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures_util::{Sink, Stream, sink::SinkExt, StreamExt};
+use gloo_timers::future::sleep;
+use js_sys::{ArrayBuffer, Uint8Array};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{BinaryType, CloseEvent, Event, MessageEvent, WebSocket};
+use crate::messages::{MessageHandler, MessageFormat};
+use bytes::Bytes;
+
+/// An event raised by the browser `WebSocket` callbacks.
+enum BrowserEvent {
+    /// The connection was opened.
+    Open,
+    /// A text or binary message was received.
+    Message(Message),
+    /// The browser reported an error on the connection.
+    Error(String),
+    /// The connection was closed, optionally with the close frame sent by the server.
+    Closed(Option<CloseFrame<'static>>),
+}
+
+/// Converts a JavaScript error value into a tungstenite `Error`.
+fn js_error(value: JsValue) -> Error {
+    Error::Io(std::io::Error::other(format!("{:?}", value)))
+}
+
+/// A WebSocket connection backed by the browser's `WebSocket` object.
+///
+/// `BrowserStream` implements `Stream<Item = Result<Message, Error>>` and `Sink<Message>`,
+/// so code written against the native `WebSocketStream` with `StreamExt::next` and
+/// `SinkExt::send` works unchanged. Ping and pong frames are handled by the browser itself;
+/// sending them through the sink is a no-op.
+pub struct BrowserStream {
+    socket: WebSocket,
+    events: UnboundedReceiver<BrowserEvent>,
+    closed: bool,
+    _on_open: Closure<dyn FnMut(Event)>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_error: Closure<dyn FnMut(Event)>,
+    _on_close: Closure<dyn FnMut(CloseEvent)>,
+}
+
+impl BrowserStream {
+    /// Opens a browser WebSocket to `url` and waits until it is open.
+    ///
+    /// # Arguments
+    /// - `url` - The WebSocket server URL.
+    ///
+    /// # Returns
+    /// A `Result` containing the open stream, or an `Error` if the browser refused the URL or
+    /// the connection failed before opening.
+    async fn open(url: &str) -> Result<Self, Error> {
+        let socket = WebSocket::new(url).map_err(js_error)?;
+        socket.set_binary_type(BinaryType::Arraybuffer);
+        let (sender, events) = unbounded();
+
+        let on_open = {
+            let sender = sender.clone();
+            Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+                let _ = sender.unbounded_send(BrowserEvent::Open);
+            })
+        };
+        let on_message = {
+            let sender = sender.clone();
+            Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+                let data = event.data();
+                let message = if let Some(text) = data.as_string() {
+                    Message::Text(text)
+                } else if let Ok(buffer) = data.dyn_into::<ArrayBuffer>() {
+                    Message::Binary(Uint8Array::new(&buffer).to_vec())
+                } else {
+                    debug!("Ignoring browser message of unsupported type");
+                    return;
+                };
+                let _ = sender.unbounded_send(BrowserEvent::Message(message));
+            })
+        };
+        let on_error = {
+            let sender = sender.clone();
+            Closure::<dyn FnMut(Event)>::new(move |event: Event| {
+                let _ = sender.unbounded_send(BrowserEvent::Error(event.type_()));
+            })
+        };
+        let on_close = Closure::<dyn FnMut(CloseEvent)>::new(move |event: CloseEvent| {
+            let frame = CloseFrame {
+                code: event.code().into(),
+                reason: event.reason().into(),
+            };
+            let _ = sender.unbounded_send(BrowserEvent::Closed(Some(frame)));
+        });
+
+        socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+        socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        socket.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+        socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+        let mut stream = BrowserStream {
+            socket,
+            events,
+            closed: false,
+            _on_open: on_open,
+            _on_message: on_message,
+            _on_error: on_error,
+            _on_close: on_close,
+        };
+
+        match stream.events.next().await {
+            Some(BrowserEvent::Open) => Ok(stream),
+            Some(BrowserEvent::Error(reason)) => Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::ConnectionRefused,
+                format!("WebSocket error before open: {}", reason),
+            ))),
+            _ => Err(Error::ConnectionClosed),
+        }
+    }
+}
+
+impl Stream for BrowserStream {
+    type Item = Result<Message, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.closed {
+            return Poll::Ready(None);
+        }
+        loop {
+            return match Pin::new(&mut self.events).poll_next(cx) {
+                Poll::Ready(Some(BrowserEvent::Open)) => continue,
+                Poll::Ready(Some(BrowserEvent::Message(message))) => Poll::Ready(Some(Ok(message))),
+                Poll::Ready(Some(BrowserEvent::Error(reason))) => Poll::Ready(Some(Err(Error::Io(
+                    std::io::Error::other(reason),
+                )))),
+                Poll::Ready(Some(BrowserEvent::Closed(frame))) => {
+                    self.closed = true;
+                    Poll::Ready(Some(Ok(Message::Close(frame))))
+                }
+                Poll::Ready(None) => {
+                    self.closed = true;
+                    Poll::Ready(None)
+                }
+                Poll::Pending => Poll::Pending,
+            };
+        }
+    }
+}
+
+impl Sink<Message> for BrowserStream {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        if self.closed {
+            Poll::Ready(Err(Error::AlreadyClosed))
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Message) -> Result<(), Error> {
+        match item {
+            Message::Text(text) => self.socket.send_with_str(&text).map_err(js_error),
+            Message::Binary(data) => self.socket.send_with_u8_array(&data).map_err(js_error),
+            Message::Ping(_) | Message::Pong(_) => {
+                debug!("Ping/Pong frames are handled by the browser; skipping");
+                Ok(())
+            }
+            Message::Close(Some(frame)) => self
+                .socket
+                .close_with_code_and_reason(frame.code.into(), &frame.reason)
+                .map_err(js_error),
+            Message::Close(None) => self.socket.close().map_err(js_error),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(self.socket.close().map_err(js_error))
+    }
+}
+
+impl Drop for BrowserStream {
+    /// Detaches the callbacks and closes the browser socket.
+    fn drop(&mut self) {
+        self.socket.set_onopen(None);
+        self.socket.set_onmessage(None);
+        self.socket.set_onerror(None);
+        self.socket.set_onclose(None);
+        let _ = self.socket.close();
+    }
+}
+
+/// `WebSocketClient` is responsible for managing WebSocket connections, including connection setup,
+/// message sending, and reconnection logic. This is the browser implementation; it mirrors the
+/// native client's API.
+///
+/// # Fields
+/// - `url` - The URL of the WebSocket server.
+/// - `retries` - The number of reconnection attempts allowed.
+#[derive(Clone)]
+pub struct WebSocketClient {
+    /// The URL of the WebSocket server.
+    pub url: String,
+    /// Number of retries allowed for reconnection attempts.
+    retries: u32,
+}
+
+impl WebSocketClient {
+    /// Creates a new `WebSocketClient` with a specified URL and retry limit.
+    ///
+    /// # Arguments
+    /// - `url` - The WebSocket server URL as a string.
+    /// - `retries` - The number of reconnection attempts allowed.
+    ///
+    /// # Returns
+    /// A new instance of `WebSocketClient`.
+    pub fn new(url: &str, retries: u32) -> Self {
+        WebSocketClient {
+            url: url.to_string(),
+            retries,
+        }
+    }
+
+    /// Receives a message from the WebSocket server.
+    ///
+    /// # Returns
+    /// An `Option` containing the message as a `Vec<u8>` if successful, or `None` otherwise.
+    pub async fn receive_message(&self) -> Option<Vec<u8>> {
+        let mut ws_stream = self.connect().await.ok()?;
+
+        match ws_stream.next().await {
+            Some(Ok(Message::Text(text))) => Some(text.into_bytes()),
+            Some(Ok(Message::Binary(data))) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Establishes a browser WebSocket connection.
+    ///
+    /// # Returns
+    /// A `Result` containing the `BrowserStream` on success, or an `Error` on failure.
+    pub async fn connect(&self) -> Result<BrowserStream, Error> {
+        info!("Attempting to connect to WebSocket server at {}", self.url);
+        let ws_stream = BrowserStream::open(&self.url).await?;
+        info!("Connected to WebSocket server at {}", self.url);
+        Ok(ws_stream)
+    }
+
+    /// Sends a message over an active WebSocket connection. The message is serialized using JSON format by default.
+    ///
+    /// # Arguments
+    /// - `ws_stream` - The WebSocket stream to send the message over.
+    /// - `message` - The message to send as a string.
+    pub async fn send_message(&self, ws_stream: &mut BrowserStream, message: &str) {
+        match MessageHandler::serialize(&message, MessageFormat::Json) {
+            Ok(serialized_data) => match ws_stream.send(Message::Binary(serialized_data)).await {
+                Ok(_) => info!("Sent message: {}", message),
+                Err(e) => error!("Failed to send message: {}", e),
+            },
+            Err(e) => error!("Failed to serialize message: {}", e),
+        }
+    }
+
+    /// Sends already-serialized text as a text message, without re-serializing or copying it.
+    ///
+    /// # Arguments
+    /// - `ws_stream` - The WebSocket stream to send the message over.
+    /// - `text` - The text payload, such as a JSON document.
+    ///
+    /// # Returns
+    /// A `Result` indicating success, or the `Error` from the stream.
+    pub async fn send_text(&self, ws_stream: &mut BrowserStream, text: impl Into<String>) -> Result<(), Error> {
+        ws_stream.send(Message::Text(text.into())).await
+    }
+
+    /// Sends an owned payload as a binary message without copying it on the Rust side.
+    ///
+    /// # Arguments
+    /// - `ws_stream` - The WebSocket stream to send the message over.
+    /// - `payload` - The already-serialized payload.
+    ///
+    /// # Returns
+    /// A `Result` indicating success, or the `Error` from the stream.
+    pub async fn send_bytes(&self, ws_stream: &mut BrowserStream, payload: impl Into<Bytes>) -> Result<(), Error> {
+        ws_stream.send(Message::Binary(Vec::from(payload.into()))).await
+    }
+
+    /// Disconnects the WebSocket connection gracefully.
+    ///
+    /// Browser sockets are closed when their `BrowserStream` is dropped.
+    pub fn disconnect(&self) {
+        info!("Disconnected from WebSocket server at {}", self.url);
+    }
+
+    /// Returns the retry count for reconnection logic.
+    ///
+    /// # Returns
+    /// The number of retries allowed for reconnection attempts.
+    pub fn get_retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// Attempts to reconnect to the WebSocket server if the connection fails.
+    ///
+    /// # Returns
+    /// A `Result` containing the stream on successful reconnection, or an `Error` if all retries fail.
+    #[cfg(feature = "reconnection")]
+    pub async fn reconnect(&self) -> Result<BrowserStream, Error> {
+        let mut retries_left = self.retries;
+        while retries_left > 0 {
+            match self.connect().await {
+                Ok(ws_stream) => {
+                    info!("Reconnection successful.");
+                    return Ok(ws_stream);
+                }
+                Err(e) => {
+                    error!("Failed to reconnect: {}", e);
+                    retries_left -= 1;
+                    sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+        Err(Error::Io(std::io::Error::new(std::io::ErrorKind::TimedOut, "Reconnection failed")))
+    }
+}
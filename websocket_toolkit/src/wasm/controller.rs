@@ -0,0 +1,335 @@
+#![allow(unused_imports)]
+#![allow(unused_variables)]
+#![allow(dead_code)]
+
+//! Module for WebSocket controller logic in the browser.
+//!
+//! This module is compiled in place of `controller.rs` when building for `wasm32` targets with
+//! the `wasm` feature. The `WebSocketController` keeps the native method names and signatures,
+//! operating on a `BrowserStream` instead of a tokio `WebSocketStream`.
+
+use crate::connection::{BrowserStream, WebSocketClient};
+use crate::messages::{InboundMessage, MessageHandler, MessageFormat};
+use crate::pool::{BufferPool, PooledBuffer};
+use bytes::Bytes;
+#[cfg(feature = "reconnection")]
+use crate::reconnection::ReconnectStrategy;
+use log::{info, error, debug, warn};
+use tokio_tungstenite::tungstenite::Message;
+use futures::lock::Mutex;
+use futures_util::{sink::SinkExt, StreamExt};
+use gloo_timers::future::sleep;
+use std::sync::Arc;
+use std::time::Duration;
+use std::error::Error as StdError;
+
+/// The `WebSocketController` struct is responsible for managing WebSocket connections,
+/// handling reconnections, and sending/receiving messages from a browser.
+pub struct WebSocketController {
+    client: Arc<WebSocketClient>,
+    #[cfg(feature = "reconnection")]
+    reconnect_strategy: Option<ReconnectStrategy>,
+    ping_interval: Duration,
+    retries: u32,
+}
+
+impl WebSocketController {
+    /// Creates a new instance of `WebSocketController`.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The WebSocket server URL.
+    /// * `retries` - The maximum number of reconnection attempts.
+    /// * `ping_interval` - Optional ping interval in seconds, kept for API parity; browsers
+    ///   manage keep-alive pings themselves.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `WebSocketController`.
+    pub fn new(url: &str, retries: u32, ping_interval: Option<u64>) -> Self {
+        Self {
+            client: Arc::new(WebSocketClient::new(url, retries)),
+            #[cfg(feature = "reconnection")]
+            reconnect_strategy: Some(ReconnectStrategy::new(retries, 2)),
+            ping_interval: Duration::from_secs(ping_interval.unwrap_or(5)),
+            retries,
+        }
+    }
+
+    /// Establishes a WebSocket connection.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a `BrowserStream` if the connection is successful,
+    /// or a boxed error if the connection fails.
+    pub async fn connect(&self) -> Result<BrowserStream, Box<dyn StdError>> {
+        self.client
+            .connect()
+            .await
+            .map_err(|e| Box::new(e) as Box<dyn StdError>)
+    }
+
+    /// Connects to the WebSocket server and sends a message.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to send as a byte slice.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn connect_and_send_message(
+        &mut self,
+        message: &[u8],
+    ) -> Result<(), Box<dyn StdError>> {
+        let mut ws_stream = self.connect().await?;
+        self.send_message(&mut ws_stream, message).await?;
+        Ok(())
+    }
+
+    /// Disconnects from the WebSocket server gracefully.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn disconnect(&self) -> Result<(), Box<dyn StdError>> {
+        self.client.disconnect();
+        Ok(())
+    }
+
+    /// Receives a message from the WebSocket server.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the browser stream.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the received message as a `Vec<u8>` or an error.
+    pub async fn receive_message(
+        &mut self,
+        ws_stream: &mut BrowserStream,
+    ) -> Result<Option<Vec<u8>>, Box<dyn StdError>> {
+        Ok(self.receive_inbound(ws_stream).await?.map(InboundMessage::into_bytes))
+    }
+
+    /// Receives a data message from the WebSocket server, keeping its text or binary kind.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the browser stream.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the received `InboundMessage`, `None` for a ping or pong, or an
+    /// error if the server closed the connection.
+    pub async fn receive_inbound(
+        &mut self,
+        ws_stream: &mut BrowserStream,
+    ) -> Result<Option<InboundMessage>, Box<dyn StdError>> {
+        match self.receive_raw(ws_stream).await? {
+            Some(msg) => match InboundMessage::try_from(msg) {
+                Ok(inbound) => Ok(Some(inbound)),
+                Err(Message::Close(_)) => {
+                    info!("Received Close message");
+                    Err("Connection closed by server".into())
+                }
+                Err(_) => {
+                    info!("Received control message: Ping/Pong");
+                    Ok(None)
+                }
+            },
+            None => Err("No message received".into()),
+        }
+    }
+
+    /// Receives the next frame from the WebSocket server as a raw tungstenite `Message`.
+    ///
+    /// Browsers never surface ping or pong frames, but close frames are returned as-is.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the browser stream.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the next frame, or `None` if the stream has ended.
+    pub async fn receive_raw(
+        &mut self,
+        ws_stream: &mut BrowserStream,
+    ) -> Result<Option<Message>, Box<dyn StdError>> {
+        match ws_stream.next().await {
+            Some(msg) => Ok(Some(msg?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Receives a data message into a buffer from `pool`.
+    ///
+    /// The payload's allocation is adopted by the pool, so once the returned buffer is dropped
+    /// it is reused for later pooled serialization instead of being freed.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the browser stream.
+    /// * `pool` - The pool the payload buffer returns to.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the payload, `None` for a ping or pong, or an error.
+    pub async fn receive_pooled(
+        &mut self,
+        ws_stream: &mut BrowserStream,
+        pool: &BufferPool,
+    ) -> Result<Option<PooledBuffer>, Box<dyn StdError>> {
+        Ok(self.receive_message(ws_stream).await?.map(|data| pool.recycle(data)))
+    }
+
+    /// Sends a message to the WebSocket server.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the browser stream.
+    /// * `message` - The message to send as a byte slice.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn send_message(
+        &mut self,
+        ws_stream: &mut BrowserStream,
+        message: &[u8],
+    ) -> Result<(), Box<dyn StdError>> {
+        ws_stream.send(Message::Binary(message.to_vec())).await?;
+        Ok(())
+    }
+
+    /// Sends an owned payload as a binary message without copying it on the Rust side.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the browser stream.
+    /// * `payload` - The already-serialized payload.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn send_bytes(
+        &mut self,
+        ws_stream: &mut BrowserStream,
+        payload: impl Into<Bytes>,
+    ) -> Result<(), Box<dyn StdError>> {
+        ws_stream.send(Message::Binary(Vec::from(payload.into()))).await?;
+        Ok(())
+    }
+
+    /// Sends a pooled buffer, such as one filled by `BufferPool::serialize`, as a binary message.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the browser stream.
+    /// * `buffer` - The payload; it is handed to the stream without copying.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn send_pooled(
+        &mut self,
+        ws_stream: &mut BrowserStream,
+        buffer: PooledBuffer,
+    ) -> Result<(), Box<dyn StdError>> {
+        ws_stream.send(Message::Binary(buffer.into_vec())).await?;
+        Ok(())
+    }
+
+    /// Sends a raw tungstenite `Message`, bypassing the controller's framing.
+    ///
+    /// Ping and pong frames are dropped, as the browser does not let scripts send them.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the browser stream.
+    /// * `message` - The frame to send.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn send_raw(
+        &mut self,
+        ws_stream: &mut BrowserStream,
+        message: Message,
+    ) -> Result<(), Box<dyn StdError>> {
+        ws_stream.send(message).await?;
+        Ok(())
+    }
+
+    /// Maintains the WebSocket connection.
+    ///
+    /// Browsers answer server pings and keep connections alive on their own, so this only
+    /// logs and returns; it exists so shared code can call it on every target.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - An `Arc`-wrapped `Mutex` protecting the browser stream.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    #[cfg(feature = "keep-alive")]
+    pub async fn maintain_connection(
+        &self,
+        ws_stream: Arc<Mutex<BrowserStream>>,
+    ) -> Result<(), Box<dyn StdError>> {
+        debug!("Keep-alive is managed by the browser; ignoring ping interval {:?}", self.ping_interval);
+        Ok(())
+    }
+
+    /// Attempts to reconnect to the WebSocket server using exponential backoff.
+    ///
+    /// The new connection is dropped; use `reconnect` to keep it.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    #[cfg(feature = "reconnection")]
+    pub async fn reconnect_if_needed(&self) -> Result<(), Box<dyn StdError>> {
+        self.reconnect().await.map(|_| ())
+    }
+
+    /// Reconnects like `reconnect_if_needed`, returning the new connection.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the new `BrowserStream`, which replaces the dropped one, or a
+    /// boxed error if every attempt failed.
+    #[cfg(feature = "reconnection")]
+    pub async fn reconnect(&self) -> Result<BrowserStream, Box<dyn StdError>> {
+        let mut attempts = 0;
+        while attempts < self.retries {
+            match self.connect().await {
+                Ok(ws_stream) => return Ok(ws_stream),
+                Err(e) => {
+                    error!("Reconnection attempt {} failed: {}", attempts + 1, e);
+                    sleep(Duration::from_secs(2_u64.pow(attempts))).await; // Exponential backoff
+                    attempts += 1;
+                }
+            }
+        }
+        Err("All reconnection attempts failed.".into())
+    }
+
+    /// Sends a ping message to the WebSocket server.
+    ///
+    /// Browsers do not expose ping frames to scripts, so this always returns an error.
+    ///
+    /// # Arguments
+    ///
+    /// * `ws_stream` - A mutable reference to the browser stream.
+    ///
+    /// # Returns
+    ///
+    /// An error explaining that pings are unavailable in the browser.
+    pub async fn send_ping(&self, ws_stream: &mut BrowserStream) -> Result<(), Box<dyn StdError>> {
+        Err("Ping frames cannot be sent from a browser WebSocket".into())
+    }
+}
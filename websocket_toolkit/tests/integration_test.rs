@@ -12,10 +12,11 @@ use websocket_toolkit::controller::WebSocketController;
 use websocket_toolkit::messages::{MessageHandler, MessageFormat};
 use websocket_toolkit::reconnection::{ReconnectStrategy, Connectable};
 use websocket_toolkit::keep_alive::KeepAlive;
+use websocket_toolkit::testing::MockServer;
 use std::sync::Arc;
 use log::{info, error};
 use futures_util::{StreamExt, SinkExt}; // Ensure these imports are included
-use tokio_tungstenite::tungstenite::Error;
+use tokio_tungstenite::tungstenite::{Error, Message};
 use async_trait::async_trait;
 use tokio::sync::Mutex;
 
@@ -159,3 +160,33 @@ fn test_message_deserialization() {
         Err(e) => error!("Deserialization error: {:?}", e),
     }
 }
+
+/// Tests controller traffic against the mock server using the test handle assertion helpers.
+///
+/// This test verifies that messages flow in both directions and that a server-side close
+/// surfaces as an error on the controller's receive path.
+#[tokio::test]
+async fn test_controller_traffic_with_mock_server() {
+    let mut server = MockServer::start().await.expect("Failed to start mock server");
+    let mut controller = WebSocketController::new(server.url(), 1, None);
+    let mut ws_stream = controller.connect().await.expect("Failed to connect to mock server");
+    let mut connection = server.accept().await;
+
+    controller
+        .send_message(&mut ws_stream, b"Hello, server!")
+        .await
+        .expect("Failed to send message");
+    connection
+        .assert_next_message_eq(Message::Binary(b"Hello, server!".to_vec()))
+        .await;
+
+    connection.send(Message::Text("Hello, client!".to_string())).await;
+    let received = controller.receive_message(&mut ws_stream).await.expect("Failed to receive message");
+    assert_eq!(received, Some(b"Hello, client!".to_vec()));
+
+    connection.close(1001, "going away").await;
+    assert!(
+        controller.receive_message(&mut ws_stream).await.is_err(),
+        "Expected the server close to surface as an error"
+    );
+}